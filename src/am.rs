@@ -0,0 +1,469 @@
+//! Applying a series of patches from mailboxes as commits, as `git am` does: each mail gives
+//! the author, date and message of a commit, and its patch is applied to the index and the
+//! working tree.
+//!
+//! The mails are split into `.git/rebase-apply`, numbered from `0001`, along with the number of
+//! the next one to apply (`next`) and of the last (`last`). A patch that doesn't apply stops the
+//! series with its message (`msg`), author (`author-script`) and diff (`patch`) kept there, so
+//! that `--continue` can commit the user's resolution, `--skip` drop it and `--abort` go back
+//! to `ORIG_HEAD`.
+
+use std::{collections::BTreeMap, fs, io::Write, path::Path};
+
+use anyhow::Context;
+
+use crate::{
+    apply::{self, ApplyOptions, FilePatch, Target},
+    index::Index,
+    mail::{self, MailInfo},
+    merge::{
+        self,
+        content::{ConflictStyle, FileOptions, Labels},
+        octopus::index_tree,
+        MergeOptions,
+    },
+    object::{self, commit::Commit, write::write_commit_by, write::write_tree_from_paths},
+    quote, refs, revision,
+    unpack::{self, tree_blobs, UnpackOptions},
+};
+
+const STATE_DIR: &str = ".git/rebase-apply";
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct AmOptions {
+    /// Fall back to a three-way merge with the blobs the patches name
+    pub(crate) three_way: bool,
+    /// Keep the subjects as they are, `[PATCH]` prefixes and all
+    pub(crate) keep_subject: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Done,
+    /// A patch didn't apply, and the user was told how to go on
+    Stopped,
+}
+
+/// Whether `am` is in the middle of a series
+pub(crate) fn in_progress() -> bool {
+    Path::new(STATE_DIR).join("applying").exists()
+}
+
+/// Splits the mailboxes into the state directory and applies the patches in them in order
+pub(crate) fn start(mailboxes: &[Vec<u8>], opts: AmOptions) -> anyhow::Result<Outcome> {
+    anyhow::ensure!(
+        !Path::new(STATE_DIR).exists(),
+        "previous rebase directory {STATE_DIR} still exists but mbox given."
+    );
+    let mails: Vec<&[u8]> = mailboxes
+        .iter()
+        .flat_map(|mailbox| mail::split_mbox(mailbox))
+        .collect();
+    anyhow::ensure!(!mails.is_empty(), "Patch format detection failed.");
+
+    fs::create_dir_all(STATE_DIR).context("Creating the am state directory")?;
+    for (i, content) in mails.iter().enumerate() {
+        write_state(&format!("{:04}", i + 1), content)?;
+    }
+    write_state("next", b"1\n")?;
+    write_state("last", format!("{}\n", mails.len()).as_bytes())?;
+    write_state("threeway", if opts.three_way { b"t\n" } else { b"f\n" })?;
+    write_state("keep", if opts.keep_subject { b"t\n" } else { b"f\n" })?;
+    write_state("applying", b"")?;
+    match refs::head_commit()? {
+        Some(head) => {
+            refs::update_ref("ORIG_HEAD", &head, "am")?;
+            write_state(
+                "abort-safety",
+                format!("{}\n", hex::encode(head)).as_bytes(),
+            )?;
+        }
+        None => write_state("abort-safety", b"")?,
+    }
+    run()
+}
+
+/// Commits the resolution of the patch the series stopped at, then goes on with the rest
+pub(crate) fn resume() -> anyhow::Result<Outcome> {
+    check_in_progress()?;
+    let info = read_current()?;
+    println!("Applying: {}", info.subject);
+    let index = Index::read()?;
+    let Some(tree) = index_tree(&index)? else {
+        println!("You still have unmerged paths in your index.");
+        println!("You should 'git add' each file with resolved conflicts to mark them as such.");
+        println!("You might run `git rm` on a file to accept \"deleted by them\" for it.");
+        return Ok(stop_for_user());
+    };
+    let head_tree = head_tree()?;
+    if Some(tree) == head_tree {
+        println!("No changes - did you forget to use 'git add'?");
+        println!("If there is nothing left to stage, chances are that something else");
+        println!("already introduced the same changes; you might want to skip this patch.");
+        return Ok(stop_for_user());
+    }
+    commit(&tree, &info)?;
+    advance()?;
+    run()
+}
+
+/// Tries the patch the series stopped at again, as when the user has changed the tree so that
+/// it applies
+pub(crate) fn retry() -> anyhow::Result<Outcome> {
+    check_in_progress()?;
+    run()
+}
+
+/// Drops the patch the series stopped at, putting the index and working tree back to HEAD,
+/// and goes on with the rest
+pub(crate) fn skip() -> anyhow::Result<Outcome> {
+    check_in_progress()?;
+    reset_to(head_tree()?.as_ref())?;
+    advance()?;
+    run()
+}
+
+/// Gives up on the series, going back to where it started unless HEAD was moved since it
+/// stopped
+pub(crate) fn abort() -> anyhow::Result<()> {
+    check_in_progress()?;
+    let dir = Path::new(STATE_DIR);
+    if !dir.join("dirtyindex").exists() {
+        let safety = fs::read_to_string(dir.join("abort-safety")).unwrap_or_default();
+        let expected = match safety.trim() {
+            "" => None,
+            hex => Some(revision::resolve(hex)?),
+        };
+        if refs::head_commit()? != expected {
+            eprintln!("warning: You seem to have moved HEAD since the last 'am' failure.");
+            eprintln!("Not rewinding to ORIG_HEAD");
+        } else if let Some(orig_head) = refs::read_ref("ORIG_HEAD")? {
+            reset_to(Some(&Commit::read(&orig_head)?.tree))?;
+            refs::update_ref("HEAD", &orig_head, "am --abort")?;
+        }
+    }
+    fs::remove_dir_all(STATE_DIR).context("Removing the am state directory")
+}
+
+/// Shows the mail the series stopped at, or with `diff` only its patch
+pub(crate) fn show_current_patch(diff: bool) -> anyhow::Result<()> {
+    check_in_progress()?;
+    let content = if diff {
+        read_state("patch")?
+    } else {
+        read_state(&format!("{:04}", read_number("next")?))?
+    };
+    std::io::stdout().lock().write_all(&content)?;
+    Ok(())
+}
+
+fn check_in_progress() -> anyhow::Result<()> {
+    anyhow::ensure!(
+        in_progress(),
+        "Resolve operation not in progress, we are not resuming."
+    );
+    Ok(())
+}
+
+/// Applies the patches from `next` to `last`, stopping at the first that doesn't apply
+fn run() -> anyhow::Result<Outcome> {
+    let index = Index::read()?;
+    let head_tree = head_tree()?;
+    if index_tree(&index)? != head_tree {
+        write_state("dirtyindex", b"")?;
+        let dirty = dirty_paths(&index, head_tree.as_ref())?;
+        anyhow::bail!(
+            "Dirty index: cannot apply patches (dirty: {})",
+            dirty.join(" ")
+        );
+    }
+    let last = read_number("last")?;
+    loop {
+        let next = read_number("next")?;
+        if next > last {
+            break;
+        }
+        let mail = read_state(&format!("{next:04}"))?;
+        let info = mail::parse(&mail, keep_subject()?)?;
+        write_current(&info)?;
+        println!("Applying: {}", info.subject);
+        if info.patch.is_empty() {
+            println!("Patch is empty.");
+            return Ok(stop_for_user());
+        }
+        if info.author_email.is_empty() {
+            eprintln!("Patch does not have a valid e-mail address.");
+            return Ok(stop_for_user());
+        }
+        if !apply_patch(&info, next)? {
+            return Ok(Outcome::Stopped);
+        }
+        advance()?;
+    }
+    fs::remove_dir_all(STATE_DIR).context("Removing the am state directory")?;
+    Ok(Outcome::Done)
+}
+
+/// Applies and commits one patch, falling back to a three-way merge if asked to. Returns
+/// whether it was committed.
+fn apply_patch(info: &MailInfo, number: usize) -> anyhow::Result<bool> {
+    // The author has to be good before anything is staged, not only once it is committed
+    read_author(info)?;
+    let patches = apply::parse(&info.patch, 1)
+        .and_then(|patches| apply::verify_paths(&patches).map(|()| patches));
+    let patches = match patches {
+        Ok(patches) => patches,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return Ok(patch_failed(info, number));
+        }
+    };
+    let three_way = read_state("threeway")?.starts_with(b"t");
+    let mut index = Index::read()?;
+    // The three-way fallback speaks for itself
+    let opts = ApplyOptions {
+        quiet: three_way,
+        ..ApplyOptions::default()
+    };
+    let mut target = Target::Index {
+        index: &mut index,
+        cached: false,
+    };
+    let applied = apply::apply(&patches, &mut target, &opts)? == apply::Outcome::Clean;
+    if applied {
+        index.write()?;
+    } else if three_way {
+        match fall_back_three_way(&patches, info, &mut index)? {
+            Some(true) => {}
+            Some(false) => {
+                eprintln!("error: Failed to merge in the changes.");
+                return Ok(patch_failed(info, number));
+            }
+            None => return Ok(patch_failed(info, number)),
+        }
+    } else {
+        return Ok(patch_failed(info, number));
+    }
+    let tree = index_tree(&index)?.context("Your index file is unmerged.")?;
+    commit(&tree, info)?;
+    Ok(true)
+}
+
+/// Rebuilds the files the patches apply to from the blobs their `index` lines name, applies
+/// the patches to those and merges the result into HEAD. Returns whether the merge was clean,
+/// or `None` if the patches don't name blobs this repository has.
+fn fall_back_three_way(
+    patches: &[FilePatch],
+    info: &MailInfo,
+    index: &mut Index,
+) -> anyhow::Result<Option<bool>> {
+    let mut base_files = BTreeMap::new();
+    for patch in patches {
+        let Some(path) = &patch.old_path else {
+            continue;
+        };
+        let blob = patch.old_id.as_deref().and_then(apply::resolve_blob);
+        let Some(blob) = blob else {
+            eprintln!("error: sha1 information is lacking or useless ({path}).");
+            eprintln!("error: could not build fake ancestor");
+            return Ok(None);
+        };
+        let mode = patch.old_mode.unwrap_or(object::MODE_FILE);
+        base_files.insert(path.clone(), (mode, blob));
+    }
+    let base = write_tree_from_paths(&base_files)?;
+    println!("Using index info to reconstruct a base tree...");
+
+    // The paths whose base differs from HEAD, which the merge may get wrong
+    let head = refs::head_commit()?;
+    let head_tree = head_tree()?;
+    let head_files = tree_blobs(head_tree.as_ref())?;
+    let mut out = std::io::stdout().lock();
+    for (path, entry) in &base_files {
+        match head_files.get(path) {
+            None => writeln!(out, "A\t{}", quote::c_style(path, false))?,
+            Some(head_entry) if head_entry != entry => {
+                writeln!(out, "M\t{}", quote::c_style(path, false))?
+            }
+            Some(_) => {}
+        }
+    }
+    drop(out);
+
+    let mut their_files = base_files;
+    if apply::apply(
+        patches,
+        &mut Target::Tree(&mut their_files),
+        &ApplyOptions::default(),
+    )? != apply::Outcome::Clean
+    {
+        eprintln!("error: Did you hand edit your patch?");
+        eprintln!("It does not apply to blobs recorded in its index.");
+        return Ok(None);
+    }
+    let theirs = write_tree_from_paths(&their_files)?;
+    println!("Falling back to patching base and 3-way merge...");
+
+    let ours = match head_tree {
+        Some(tree) => tree,
+        None => write_tree_from_paths(&BTreeMap::new())?,
+    };
+    let opts = MergeOptions {
+        labels: Labels {
+            ours: "HEAD",
+            base: "constructed merge base",
+            theirs: &info.subject,
+        },
+        content: FileOptions {
+            style: ConflictStyle::configured()?,
+            ..FileOptions::default()
+        },
+        subtree_shift: None,
+    };
+    let result = merge::merge_tree_changes(Some(&base), &ours, Some(&theirs), opts)?;
+    merge::checkout(index, head.is_some().then_some(&ours), &result)?;
+    index.write()?;
+    let mut out = std::io::stdout().lock();
+    for line in &result.messages {
+        writeln!(out, "{line}")?;
+    }
+    Ok(Some(result.is_clean()))
+}
+
+/// Commits `tree` on HEAD with the author and message of the mail
+fn commit(tree: &[u8; 20], info: &MailInfo) -> anyhow::Result<()> {
+    let parents: Vec<[u8; 20]> = refs::head_commit()?.into_iter().collect();
+    let author = read_author(info)?;
+    let message = String::from_utf8_lossy(&read_state("msg")?).into_owned();
+    let commit = write_commit_by(tree, &parents, &author, &message)?;
+    let subject = message.lines().next().unwrap_or_default();
+    refs::update_ref("HEAD", &commit, &format!("am: {subject}"))?;
+    write_state(
+        "abort-safety",
+        format!("{}\n", hex::encode(commit)).as_bytes(),
+    )
+}
+
+/// Tells the user the patch didn't apply and how to go on
+fn patch_failed(info: &MailInfo, number: usize) -> bool {
+    eprintln!("hint: Use 'git am --show-current-patch=diff' to see the failed patch");
+    println!("Patch failed at {number:04} {}", info.subject);
+    stop_for_user();
+    false
+}
+
+/// Tells the user how to go on from a patch that needs their help
+fn stop_for_user() -> Outcome {
+    println!("When you have resolved this problem, run \"git am --continue\".");
+    println!("If you prefer to skip this patch, run \"git am --skip\" instead.");
+    println!("To restore the original branch and stop patching, run \"git am --abort\".");
+    Outcome::Stopped
+}
+
+/// Records the message, author and diff of the mail being applied
+fn write_current(info: &MailInfo) -> anyhow::Result<()> {
+    write_state("msg", info.message().as_bytes())?;
+    let mut script = format!(
+        "GIT_AUTHOR_NAME={}\nGIT_AUTHOR_EMAIL={}\n",
+        quote::shell(&info.author_name),
+        quote::shell(&info.author_email)
+    );
+    if let Some(date) = &info.date {
+        script.push_str(&format!("GIT_AUTHOR_DATE={}\n", quote::shell(date)));
+    }
+    write_state("author-script", script.as_bytes())?;
+    write_state("patch", &info.patch)
+}
+
+/// The mail the series stopped at, with the author and message recorded for it
+fn read_current() -> anyhow::Result<MailInfo> {
+    let mail = read_state(&format!("{:04}", read_number("next")?))?;
+    let mut info = mail::parse(&mail, keep_subject()?)?;
+    let message = String::from_utf8_lossy(&read_state("msg")?).into_owned();
+    info.subject = message.lines().next().unwrap_or_default().to_string();
+    Ok(info)
+}
+
+/// The author recorded for the current mail in `author-script`
+fn read_author(info: &MailInfo) -> anyhow::Result<object::commit::Signature> {
+    let mut info = info.clone();
+    info.date = None;
+    let script = String::from_utf8_lossy(&read_state("author-script")?).into_owned();
+    for line in script.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = quote::split_shell(value)
+            .and_then(|values| values.into_iter().next())
+            .with_context(|| format!("Invalid line in the am author script: {line}"))?;
+        match key {
+            "GIT_AUTHOR_NAME" => info.author_name = value,
+            "GIT_AUTHOR_EMAIL" => info.author_email = value,
+            "GIT_AUTHOR_DATE" => info.date = Some(value),
+            _ => {}
+        }
+    }
+    info.author()
+}
+
+fn keep_subject() -> anyhow::Result<bool> {
+    Ok(read_state("keep")?.starts_with(b"t"))
+}
+
+/// Moves on to the next mail
+fn advance() -> anyhow::Result<()> {
+    let next = read_number("next")? + 1;
+    write_state("next", format!("{next}\n").as_bytes())
+}
+
+/// Resets the index and working tree to `tree`, keeping local changes to other files
+fn reset_to(tree: Option<&[u8; 20]>) -> anyhow::Result<()> {
+    let mut index = Index::read()?;
+    let opts = UnpackOptions {
+        update: true,
+        overwrite_unmerged: true,
+        ..UnpackOptions::default()
+    };
+    unpack::one_way(&mut index, tree, opts)?;
+    index.write()
+}
+
+fn head_tree() -> anyhow::Result<Option<[u8; 20]>> {
+    match refs::head_commit()? {
+        Some(head) => Ok(Some(Commit::read(&head)?.tree)),
+        None => Ok(None),
+    }
+}
+
+/// The paths whose index entries differ from those of `tree`
+fn dirty_paths(index: &Index, tree: Option<&[u8; 20]>) -> anyhow::Result<Vec<String>> {
+    let mut files = tree_blobs(tree)?;
+    let mut dirty = Vec::new();
+    for entry in index.entries() {
+        match files.remove(&entry.path) {
+            Some(blob) if entry.stage == 0 && blob == (entry.mode, entry.hash) => {}
+            _ => dirty.push(entry.path.clone()),
+        }
+    }
+    dirty.extend(files.into_keys());
+    dirty.sort();
+    dirty.dedup();
+    Ok(dirty)
+}
+
+fn read_number(name: &str) -> anyhow::Result<usize> {
+    let content = read_state(name)?;
+    String::from_utf8_lossy(&content)
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid {name} in the am state"))
+}
+
+fn read_state(name: &str) -> anyhow::Result<Vec<u8>> {
+    fs::read(Path::new(STATE_DIR).join(name)).with_context(|| format!("Reading {STATE_DIR}/{name}"))
+}
+
+fn write_state(name: &str, content: &[u8]) -> anyhow::Result<()> {
+    fs::write(Path::new(STATE_DIR).join(name), content)
+        .with_context(|| format!("Writing {STATE_DIR}/{name}"))
+}
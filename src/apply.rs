@@ -0,0 +1,1400 @@
+//! Applying patches in git's diff format, or plain unified diffs, to the working tree, the
+//! index or a tree. Hunks must match exactly, though not necessarily where the patch says; the
+//! nearest place the old lines are found is taken, with less context if allowed. Nothing is
+//! changed unless every file of the patch applies, or, when rejects are allowed, the hunks
+//! that don't apply are left out and written to `.rej` files. A three-way merge with the blobs
+//! the patch names can stand in for applying it directly.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use flate2::read::ZlibDecoder;
+
+use crate::{
+    diff::patch::stat_summary,
+    index::{self, Index, IndexEntry},
+    merge::content::{self, ConflictStyle, FileOptions, Labels},
+    object::{
+        self, read::read_object_of_kind, write::hash_object, ObjectKind, MODE_FILE, MODE_GITLINK,
+    },
+    quote, revision, worktree,
+};
+
+/// The changes a patch makes to one file
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FilePatch {
+    /// The path the changes apply to, `None` for a file the patch creates
+    pub(crate) old_path: Option<String>,
+    /// The path the result goes to, `None` for a file the patch deletes
+    pub(crate) new_path: Option<String>,
+    pub(crate) old_mode: Option<u32>,
+    pub(crate) new_mode: Option<u32>,
+    pub(crate) is_rename: bool,
+    pub(crate) is_copy: bool,
+    /// The (possibly abbreviated) blob names of the `index` line
+    pub(crate) old_id: Option<String>,
+    pub(crate) new_id: Option<String>,
+    pub(crate) hunks: Vec<Hunk>,
+    /// For binary files, the data the patch gives
+    pub(crate) binary: Option<BinaryPatch>,
+}
+
+impl FilePatch {
+    /// The path the patch is reported under
+    pub(crate) fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// The path errors name, the one the changes apply to unless the file is new
+    fn error_path(&self) -> &str {
+        self.old_path
+            .as_deref()
+            .or(self.new_path.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// The quoted path, or both paths of a rename or copy, for progress messages
+    fn display_name(&self) -> String {
+        match (&self.old_path, &self.new_path) {
+            (Some(old), Some(new)) if old != new => format!(
+                "{} => {}",
+                quote::c_style(old, false),
+                quote::c_style(new, false)
+            ),
+            _ => quote::c_style(self.path(), false),
+        }
+    }
+
+    /// Turns the patch around, so that it undoes what it did
+    pub(crate) fn reverse(&mut self) {
+        std::mem::swap(&mut self.old_path, &mut self.new_path);
+        std::mem::swap(&mut self.old_mode, &mut self.new_mode);
+        std::mem::swap(&mut self.old_id, &mut self.new_id);
+        for hunk in &mut self.hunks {
+            hunk.reverse();
+        }
+        if let Some(binary) = &mut self.binary {
+            std::mem::swap(&mut binary.forward, &mut binary.reverse);
+        }
+    }
+
+    /// The number of lines the patch adds and deletes
+    fn line_counts(&self) -> (usize, usize) {
+        let count = |sign| {
+            self.hunks
+                .iter()
+                .flat_map(|hunk| &hunk.lines)
+                .filter(|line| line[0] == sign)
+                .count()
+        };
+        (count(b'+'), count(b'-'))
+    }
+}
+
+/// One `@@` section of a text patch
+#[derive(Debug, Clone)]
+pub(crate) struct Hunk {
+    pub(crate) old_start: usize,
+    pub(crate) old_count: usize,
+    pub(crate) new_start: usize,
+    pub(crate) new_count: usize,
+    /// What follows the line numbers in the `@@` line, usually the function the hunk is in
+    pub(crate) section: String,
+    /// The lines of the hunk, each starting with ` `, `-` or `+`, and ending with a newline
+    /// unless the patch said there was none
+    pub(crate) lines: Vec<Vec<u8>>,
+}
+
+impl Hunk {
+    /// The lines of the hunk with the given signs, without their signs
+    fn image(&self, sign: u8) -> Vec<&[u8]> {
+        self.lines
+            .iter()
+            .filter(|line| line[0] == b' ' || line[0] == sign)
+            .map(|line| &line[1..])
+            .collect()
+    }
+
+    /// The number of context lines before the first change and after the last
+    fn context(&self) -> (usize, usize) {
+        let is_context = |line: &&Vec<u8>| line[0] == b' ';
+        let leading = self.lines.iter().take_while(is_context).count();
+        let trailing = self.lines.iter().rev().take_while(is_context).count();
+        (leading, trailing)
+    }
+
+    fn reverse(&mut self) {
+        std::mem::swap(&mut self.old_start, &mut self.new_start);
+        std::mem::swap(&mut self.old_count, &mut self.new_count);
+        for line in &mut self.lines {
+            line[0] = match line[0] {
+                b'+' => b'-',
+                b'-' => b'+',
+                sign => sign,
+            };
+        }
+    }
+
+    /// Writes the hunk back out the way it was in the patch
+    fn write_to(&self, out: &mut Vec<u8>) {
+        let range = |start: usize, count: usize| {
+            if count == 1 {
+                start.to_string()
+            } else {
+                format!("{start},{count}")
+            }
+        };
+        out.extend_from_slice(
+            format!(
+                "@@ -{} +{} @@{}\n",
+                range(self.old_start, self.old_count),
+                range(self.new_start, self.new_count),
+                self.section
+            )
+            .as_bytes(),
+        );
+        for line in &self.lines {
+            out.extend_from_slice(line);
+            if !line.ends_with(b"\n") {
+                out.extend_from_slice(b"\n\\ No newline at end of file\n");
+            }
+        }
+    }
+}
+
+/// The data of a `GIT binary patch`: one hunk to go from the old content to the new, and
+/// usually one to go back
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BinaryPatch {
+    pub(crate) forward: Option<BinaryHunk>,
+    pub(crate) reverse: Option<BinaryHunk>,
+}
+
+/// One hunk of a binary patch
+#[derive(Debug, Clone)]
+pub(crate) enum BinaryHunk {
+    /// The whole new content
+    Literal(Vec<u8>),
+    /// A delta against the old content, in the format of packs
+    Delta(Vec<u8>),
+}
+
+/// A file as the patch finds or leaves it: mode and content
+type FileState = (u32, Vec<u8>);
+
+/// The blobs of a file a three-way merge left conflicts in: the base, unless the file is new,
+/// ours and theirs
+type Stages = [Option<[u8; 20]>; 3];
+
+/// How patches are applied
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ApplyOptions {
+    /// Fewest context lines a hunk may be matched with, as `-C` gives; without it, all of
+    /// them must match
+    pub(crate) min_context: Option<usize>,
+    /// Apply the hunks that do apply and leave the rest in `.rej` files
+    pub(crate) reject: bool,
+    /// Merge text patches into the files with the blobs their `index` lines name as the base,
+    /// falling back to applying them directly
+    pub(crate) three_way: bool,
+    /// Report nothing, not even errors
+    pub(crate) quiet: bool,
+    /// Report each file as it is checked and applied
+    pub(crate) verbose: bool,
+}
+
+impl ApplyOptions {
+    fn error(&self, message: impl std::fmt::Display) {
+        if !self.quiet {
+            eprintln!("error: {message}");
+        }
+    }
+
+    fn say(&self, message: impl std::fmt::Display) {
+        if !self.quiet {
+            eprintln!("{message}");
+        }
+    }
+}
+
+/// Where patches are applied
+pub(crate) enum Target<'a> {
+    /// The files in the working tree, whatever the index has
+    Worktree,
+    /// The index and, unless `cached`, the working tree too, which must match it
+    Index { index: &'a mut Index, cached: bool },
+    /// The files of a tree, keyed by path, as `am --3way` builds its trees
+    Tree(&'a mut BTreeMap<String, (u32, [u8; 20])>),
+}
+
+/// Parses a patch into the changes it makes to each file, taking `strip` leading components
+/// off the paths in it, as `-p` does
+pub(crate) fn parse(input: &[u8], strip: usize) -> anyhow::Result<Vec<FilePatch>> {
+    let lines: Vec<&[u8]> = input.split_inclusive(|&b| b == b'\n').collect();
+    let mut patches = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = text(lines[i]);
+        if let Some(names) = line.strip_prefix("diff --git ") {
+            let mut patch = FilePatch::default();
+            if let Some((old, new)) = git_header_names(names.trim_end_matches('\n'), strip) {
+                patch.old_path = Some(old);
+                patch.new_path = Some(new);
+            }
+            i = parse_git_header(&lines, i + 1, strip, &mut patch)?;
+            i = parse_body(&lines, i, &mut patch)?;
+            anyhow::ensure!(
+                patch.old_path.is_some() || patch.new_path.is_some(),
+                "git diff header lacks filename information (line {i})"
+            );
+            patches.push(patch);
+        } else if line.starts_with("--- ")
+            && lines
+                .get(i + 1)
+                .is_some_and(|next| next.starts_with(b"+++ "))
+            && lines
+                .get(i + 2)
+                .is_some_and(|next| next.starts_with(b"@@ -"))
+        {
+            let mut patch = FilePatch {
+                old_path: patch_name(&line[4..], strip),
+                new_path: patch_name(&text(lines[i + 1])[4..], strip),
+                ..FilePatch::default()
+            };
+            i = parse_body(&lines, i + 2, &mut patch)?;
+            // A side that is empty, rather than missing, is taken as missing only when it is
+            // also named /dev/null
+            if patch.old_path.is_none() && patch.new_path.is_none() {
+                anyhow::bail!("patch with only garbage at line {i}");
+            }
+            patches.push(patch);
+        } else {
+            i += 1;
+        }
+    }
+    Ok(patches)
+}
+
+fn text(line: &[u8]) -> std::borrow::Cow<'_, str> {
+    String::from_utf8_lossy(line)
+}
+
+/// The paths of the `diff --git` line when they can be told apart, which they always can when
+/// the file keeps its name
+fn git_header_names(names: &str, strip: usize) -> Option<(String, String)> {
+    if let Some((old, rest)) = quote::unquote_c_style(names) {
+        let rest = rest.trim_start();
+        let new = match quote::unquote_c_style(rest) {
+            Some((new, _)) => new,
+            None => rest.to_string(),
+        };
+        return Some((strip_path(&old, strip)?, strip_path(&new, strip)?));
+    }
+    if let Some(at) = names.find(" \"") {
+        let (new, _) = quote::unquote_c_style(&names[at + 1..])?;
+        return Some((strip_path(&names[..at], strip)?, strip_path(&new, strip)?));
+    }
+    names
+        .match_indices(' ')
+        .filter_map(|(at, _)| {
+            let old = strip_path(&names[..at], strip)?;
+            let new = strip_path(&names[at + 1..], strip)?;
+            (old == new).then_some((old, new))
+        })
+        .next()
+}
+
+/// Takes `strip` leading components off a path, `None` if it doesn't have that many
+fn strip_path(path: &str, strip: usize) -> Option<String> {
+    let mut rest = path;
+    for _ in 0..strip {
+        let (_, after) = rest.split_once('/')?;
+        rest = after.trim_start_matches('/');
+    }
+    (!rest.is_empty()).then(|| rest.to_string())
+}
+
+/// The path of a `---` or `+++` line, without a timestamp following a tab, `None` for
+/// `/dev/null`
+fn patch_name(value: &str, strip: usize) -> Option<String> {
+    let value = value.trim_end_matches(['\n', '\r']);
+    let name = match quote::unquote_c_style(value) {
+        Some((name, _)) => name,
+        None => value
+            .split('\t')
+            .next()
+            .unwrap_or_default()
+            .trim_end()
+            .to_string(),
+    };
+    if name == "/dev/null" {
+        return None;
+    }
+    strip_path(&name, strip)
+}
+
+/// Reads the extended header lines following `diff --git`, returning where they end
+fn parse_git_header(
+    lines: &[&[u8]],
+    mut i: usize,
+    strip: usize,
+    patch: &mut FilePatch,
+) -> anyhow::Result<usize> {
+    // `rename from` and the like name paths relative to the top, without a prefix to strip
+    let header_path = |value: &str| {
+        let value = value.trim_end_matches('\n');
+        let name = match quote::unquote_c_style(value) {
+            Some((name, _)) => name,
+            None => value.to_string(),
+        };
+        strip_path(&name, strip.saturating_sub(1))
+    };
+    while i < lines.len() {
+        let line = text(lines[i]);
+        let line = line.trim_end_matches('\n');
+        if let Some(value) = line.strip_prefix("old mode ") {
+            patch.old_mode = Some(parse_mode(value, i + 1)?);
+        } else if let Some(value) = line.strip_prefix("new mode ") {
+            patch.new_mode = Some(parse_mode(value, i + 1)?);
+        } else if let Some(value) = line.strip_prefix("deleted file mode ") {
+            patch.old_mode = Some(parse_mode(value, i + 1)?);
+            patch.new_path = None;
+        } else if let Some(value) = line.strip_prefix("new file mode ") {
+            patch.new_mode = Some(parse_mode(value, i + 1)?);
+            patch.old_path = None;
+        } else if let Some(value) = line.strip_prefix("rename from ") {
+            patch.old_path = header_path(value);
+            patch.is_rename = true;
+        } else if let Some(value) = line.strip_prefix("rename to ") {
+            patch.new_path = header_path(value);
+            patch.is_rename = true;
+        } else if let Some(value) = line.strip_prefix("copy from ") {
+            patch.old_path = header_path(value);
+            patch.is_copy = true;
+        } else if let Some(value) = line.strip_prefix("copy to ") {
+            patch.new_path = header_path(value);
+            patch.is_copy = true;
+        } else if line.starts_with("similarity index ") || line.starts_with("dissimilarity index ")
+        {
+        } else if let Some(value) = line.strip_prefix("index ") {
+            let (ids, index_mode) = match value.split_once(' ') {
+                Some((ids, index_mode)) => (ids, Some(parse_mode(index_mode, i + 1)?)),
+                None => (value, None),
+            };
+            if let Some((old, new)) = ids.split_once("..") {
+                patch.old_id = Some(old.to_string());
+                patch.new_id = Some(new.to_string());
+            }
+            // Only what the file is expected to be: its new mode is whatever it has now
+            if let Some(index_mode) = index_mode {
+                patch.old_mode.get_or_insert(index_mode);
+            }
+        } else if line.starts_with("--- ")
+            && lines
+                .get(i + 1)
+                .is_some_and(|next| next.starts_with(b"+++ "))
+        {
+            // The names here agree with the header, other than being /dev/null for files
+            // created or deleted
+            let old = patch_name(&line[4..], strip);
+            let new = patch_name(&text(lines[i + 1])[4..], strip);
+            if patch.old_path.is_none() && patch.new_path.is_none() {
+                patch.old_path = old;
+                patch.new_path = new;
+            } else if old.is_none() {
+                patch.old_path = None;
+            } else if new.is_none() {
+                patch.new_path = None;
+            }
+            i += 2;
+            break;
+        } else {
+            break;
+        }
+        i += 1;
+    }
+    Ok(i)
+}
+
+fn parse_mode(value: &str, line_number: usize) -> anyhow::Result<u32> {
+    u32::from_str_radix(value.trim(), 8)
+        .with_context(|| format!("invalid mode '{}' at line {line_number}", value.trim()))
+}
+
+/// Reads the hunks of a text patch, or the data of a binary one, returning where they end
+fn parse_body(lines: &[&[u8]], mut i: usize, patch: &mut FilePatch) -> anyhow::Result<usize> {
+    if let Some(line) = lines.get(i) {
+        if line.starts_with(b"GIT binary patch") {
+            return parse_binary(lines, i + 1, patch);
+        }
+        if line.starts_with(b"Binary files ") {
+            patch.binary = Some(BinaryPatch::default());
+            return Ok(i + 1);
+        }
+    }
+    while let Some(line) = lines.get(i) {
+        if !line.starts_with(b"@@ -") {
+            break;
+        }
+        let line_number = i + 1;
+        let mut hunk = parse_hunk_header(&text(line))
+            .with_context(|| format!("corrupt patch at line {line_number}"))?;
+        let (old_count, new_count) = (hunk.old_count, hunk.new_count);
+        let (mut old_left, mut new_left) = (old_count, new_count);
+        i += 1;
+        while old_left > 0 || new_left > 0 {
+            let Some(line) = lines.get(i) else {
+                anyhow::bail!("corrupt patch at line {}", i + 1);
+            };
+            let sign = match line.first() {
+                // An empty line stands for an empty context line, as some mailers strip the
+                // trailing space
+                Some(b'\n') => b' ',
+                Some(&sign @ (b' ' | b'-' | b'+')) => sign,
+                Some(b'\\') => {
+                    strip_newline(&mut hunk);
+                    i += 1;
+                    continue;
+                }
+                _ => anyhow::bail!("corrupt patch at line {}", i + 1),
+            };
+            match sign {
+                b' ' if old_left > 0 && new_left > 0 => {
+                    (old_left, new_left) = (old_left - 1, new_left - 1)
+                }
+                b'-' if old_left > 0 => old_left -= 1,
+                b'+' if new_left > 0 => new_left -= 1,
+                _ => anyhow::bail!("corrupt patch at line {}", i + 1),
+            }
+            let content = if line[0] == b'\n' {
+                &line[..]
+            } else {
+                &line[1..]
+            };
+            let mut hunk_line = vec![sign];
+            hunk_line.extend_from_slice(content);
+            hunk.lines.push(hunk_line);
+            i += 1;
+        }
+        if lines.get(i).is_some_and(|line| line.starts_with(b"\\")) {
+            strip_newline(&mut hunk);
+            i += 1;
+        }
+        patch.hunks.push(hunk);
+    }
+    Ok(i)
+}
+
+/// Takes the newline off the last line of a hunk, for `\ No newline at end of file`
+fn strip_newline(hunk: &mut Hunk) {
+    if let Some(last) = hunk.lines.last_mut() {
+        if last.ends_with(b"\n") {
+            last.pop();
+        }
+    }
+}
+
+/// The hunk `@@ -<start>[,<count>] +<start>[,<count>] @@<section>` starts, without its lines
+fn parse_hunk_header(line: &str) -> Option<Hunk> {
+    let rest = line.strip_prefix("@@ -")?;
+    let (ranges, section) = rest.split_once(" @@")?;
+    let (old, new) = ranges.split_once(" +")?;
+    let range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(old)?;
+    let (new_start, new_count) = range(new)?;
+    Some(Hunk {
+        old_start,
+        old_count,
+        new_start,
+        new_count,
+        section: section.trim_end_matches(['\n', '\r']).to_string(),
+        lines: Vec::new(),
+    })
+}
+
+/// Reads the data of a `GIT binary patch`: the hunk going forward, then the one going back if
+/// there is one
+fn parse_binary(lines: &[&[u8]], i: usize, patch: &mut FilePatch) -> anyhow::Result<usize> {
+    let Some((forward, mut i)) = parse_binary_hunk(lines, i)? else {
+        anyhow::bail!("unrecognized binary patch at line {}", i + 1);
+    };
+    let mut binary = BinaryPatch {
+        forward: Some(forward),
+        reverse: None,
+    };
+    if let Some((reverse, end)) = parse_binary_hunk(lines, i)? {
+        binary.reverse = Some(reverse);
+        i = end;
+    }
+    patch.binary = Some(binary);
+    Ok(i)
+}
+
+/// Reads a `literal <size>` or `delta <size>` hunk and the base85 lines of deflated data that
+/// follow, up to a blank line. Returns the hunk and where it ends, or `None` if there is no
+/// hunk at `i`.
+fn parse_binary_hunk(lines: &[&[u8]], mut i: usize) -> anyhow::Result<Option<(BinaryHunk, usize)>> {
+    let header_line = i + 1;
+    let Some(header) = lines.get(i).map(|line| text(line)) else {
+        return Ok(None);
+    };
+    let header = header.trim_end();
+    let (literal, size) = if let Some(size) = header.strip_prefix("literal ") {
+        (true, size)
+    } else if let Some(size) = header.strip_prefix("delta ") {
+        (false, size)
+    } else {
+        return Ok(None);
+    };
+    let size: usize = size
+        .parse()
+        .with_context(|| format!("unrecognized binary patch at line {header_line}"))?;
+    i += 1;
+    let mut deflated = Vec::new();
+    while let Some(line) = lines.get(i) {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        i += 1;
+        if line.is_empty() {
+            break;
+        }
+        decode85_line(line, &mut deflated)
+            .with_context(|| format!("corrupt binary patch at line {i}"))?;
+    }
+    let mut data = Vec::with_capacity(size);
+    ZlibDecoder::new(deflated.as_slice())
+        .read_to_end(&mut data)
+        .with_context(|| format!("corrupt binary patch at line {header_line}"))?;
+    anyhow::ensure!(
+        data.len() == size,
+        "corrupt binary patch at line {header_line}"
+    );
+    let hunk = if literal {
+        BinaryHunk::Literal(data)
+    } else {
+        BinaryHunk::Delta(data)
+    };
+    Ok(Some((hunk, i)))
+}
+
+/// Decodes one line of base85 data, led by a character giving the number of bytes it holds
+fn decode85_line(line: &[u8], out: &mut Vec<u8>) -> anyhow::Result<()> {
+    let len = match line[0] {
+        c @ b'A'..=b'Z' => c - b'A' + 1,
+        c @ b'a'..=b'z' => c - b'a' + 27,
+        _ => anyhow::bail!("invalid line length"),
+    } as usize;
+    let data = &line[1..];
+    anyhow::ensure!(data.len() == len.div_ceil(4) * 5, "invalid line length");
+    let mut decoded = Vec::with_capacity(data.len() / 5 * 4);
+    for group in data.chunks(5) {
+        let mut value = 0u64;
+        for &c in group {
+            let digit = crate::diff::binary::BASE85
+                .iter()
+                .position(|&d| d == c)
+                .context("invalid base85 character")?;
+            value = value * 85 + digit as u64;
+        }
+        anyhow::ensure!(value <= u64::from(u32::MAX), "invalid base85 group");
+        decoded.extend_from_slice(&(value as u32).to_be_bytes());
+    }
+    decoded.truncate(len);
+    out.extend_from_slice(&decoded);
+    Ok(())
+}
+
+/// How applying patches went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Clean,
+    /// A three-way merge left conflicts, which are recorded in the index
+    Conflicts,
+    /// Some patches or hunks didn't apply
+    Failed,
+}
+
+/// Applies the patches to the target, all of them or, reporting why on stderr, none; with
+/// `reject`, the ones that apply, leaving the hunks that don't in `.rej` files
+pub(crate) fn apply(
+    patches: &[FilePatch],
+    target: &mut Target,
+    opts: &ApplyOptions,
+) -> anyhow::Result<Outcome> {
+    match check(patches, target, opts)? {
+        Some(applied) => write_changes(applied, target, opts),
+        None => Ok(Outcome::Failed),
+    }
+}
+
+/// What applying patches leaves, as worked out by [`check`]
+#[derive(Debug, Default)]
+pub(crate) struct Applied {
+    /// The files left at their paths, `None` for those deleted, in the order the patches
+    /// first touched them
+    files: Vec<(String, Option<FileState>)>,
+    /// The paths a three-way merge left conflicts in, with their mode and blobs
+    conflicts: Vec<(String, u32, Stages)>,
+    /// The patches that applied, with the hunks left out of each
+    reports: Vec<Report>,
+    /// Whether some patches were left out
+    failed: bool,
+}
+
+/// How a patch applied
+#[derive(Debug)]
+struct Report {
+    name: String,
+    path: String,
+    hunks: usize,
+    /// The hunks that didn't apply, with their numbers counting from 1
+    rejects: Vec<(usize, Hunk)>,
+}
+
+/// What one patch leaves
+struct PatchResult {
+    changes: Vec<(String, Option<FileState>)>,
+    conflict: Option<Stages>,
+    rejects: Vec<(usize, Hunk)>,
+}
+
+/// Refuses patches to paths outside the working tree or inside `.git`, before anything is
+/// checked, let alone written
+pub(crate) fn verify_paths(patches: &[FilePatch]) -> anyhow::Result<()> {
+    for path in patches
+        .iter()
+        .flat_map(|patch| [&patch.old_path, &patch.new_path])
+        .flatten()
+    {
+        anyhow::ensure!(index::verify_path(path), "invalid path '{path}'");
+    }
+    Ok(())
+}
+
+/// Works out the files the patches leave in the target, without changing it, reporting why
+/// the patches that don't apply don't. Returns `None` if some don't, unless `reject` allows
+/// leaving them out.
+pub(crate) fn check(
+    patches: &[FilePatch],
+    target: &Target,
+    opts: &ApplyOptions,
+) -> anyhow::Result<Option<Applied>> {
+    verify_paths(patches)?;
+
+    // What the patches so far left at each path, for later patches to the same files
+    let mut results: HashMap<String, Option<FileState>> = HashMap::new();
+    let mut order: Vec<String> = Vec::new();
+    let mut applied = Applied::default();
+    for patch in patches {
+        if opts.verbose {
+            opts.say(format!("Checking patch {}...", patch.display_name()));
+        }
+        let Some(result) = apply_patch(patch, target, &results, opts)? else {
+            applied.failed = true;
+            continue;
+        };
+        for (path, state) in result.changes {
+            if !results.contains_key(&path) {
+                order.push(path.clone());
+            }
+            results.insert(path, state);
+        }
+        if let Some(stages) = result.conflict {
+            let mode = match results.get(patch.path()) {
+                Some(Some((mode, _))) => *mode,
+                _ => MODE_FILE,
+            };
+            applied
+                .conflicts
+                .push((patch.path().to_string(), mode, stages));
+        }
+        applied.reports.push(Report {
+            name: patch.display_name(),
+            path: patch.path().to_string(),
+            hunks: patch.hunks.len(),
+            rejects: result.rejects,
+        });
+    }
+    if applied.failed && !opts.reject {
+        return Ok(None);
+    }
+    applied.files = order
+        .into_iter()
+        .map(|path| {
+            let state = results.remove(&path).flatten();
+            (path, state)
+        })
+        .collect();
+    Ok(Some(applied))
+}
+
+/// Makes the changes [`check`] worked out, writing the hunks left out to `.rej` files and
+/// recording conflicts in the index
+pub(crate) fn write_changes(
+    applied: Applied,
+    target: &mut Target,
+    opts: &ApplyOptions,
+) -> anyhow::Result<Outcome> {
+    // Removals go first, so that a file can give way to a directory of the same name
+    for (path, state) in &applied.files {
+        if state.is_none() {
+            remove(target, path)?;
+        }
+    }
+    for (path, state) in &applied.files {
+        if let Some((mode, content)) = state {
+            write(target, path, *mode, content)?;
+        }
+    }
+    if let Target::Index { index, .. } = target {
+        for (path, mode, stages) in &applied.conflicts {
+            index.remove(path);
+            for (stage, hash) in (1..).zip(stages) {
+                if let Some(hash) = hash {
+                    index.add(IndexEntry::new(path.clone(), *mode, *hash, stage));
+                }
+            }
+        }
+    }
+
+    let mut clean = !applied.failed;
+    for report in &applied.reports {
+        if report.rejects.is_empty() {
+            if opts.verbose {
+                opts.say(format!("Applied patch {} cleanly.", report.name));
+            }
+            continue;
+        }
+        clean = false;
+        let count = report.rejects.len();
+        opts.say(format!(
+            "Applying patch {} with {count} {}...",
+            report.name,
+            if count == 1 { "reject" } else { "rejects" }
+        ));
+        let mut rej = format!("diff a/{0} b/{0}\t(rejected hunks)\n", report.path).into_bytes();
+        let mut rejects = report.rejects.iter().peekable();
+        for number in 1..=report.hunks {
+            match rejects.next_if(|(rejected, _)| *rejected == number) {
+                Some((_, hunk)) => {
+                    opts.say(format!("Rejected hunk #{number}."));
+                    hunk.write_to(&mut rej);
+                }
+                None => opts.say(format!("Hunk #{number} applied cleanly.")),
+            }
+        }
+        let rej_path = format!("{}.rej", report.path);
+        fs::write(&rej_path, rej).with_context(|| format!("cannot open {rej_path}"))?;
+    }
+    let mut conflicted: Vec<&str> = applied
+        .conflicts
+        .iter()
+        .map(|(path, ..)| path.as_str())
+        .collect();
+    conflicted.sort_unstable();
+    for path in &conflicted {
+        opts.say(format!("U {path}"));
+    }
+    Ok(if !clean {
+        Outcome::Failed
+    } else if !conflicted.is_empty() {
+        Outcome::Conflicts
+    } else {
+        Outcome::Clean
+    })
+}
+
+/// The files a patch leaves, or `None` if it doesn't apply, which has been reported
+fn apply_patch(
+    patch: &FilePatch,
+    target: &Target,
+    results: &HashMap<String, Option<FileState>>,
+    opts: &ApplyOptions,
+) -> anyhow::Result<Option<PatchResult>> {
+    let current = |path: &str| -> anyhow::Result<Result<Option<FileState>, String>> {
+        match results.get(path) {
+            Some(state) => Ok(Ok(state.clone())),
+            None => read(target, path),
+        }
+    };
+    let old = match &patch.old_path {
+        Some(path) => match current(path)? {
+            Ok(Some(state)) => Some(state),
+            Ok(None) => {
+                opts.error(missing(target, path));
+                return Ok(None);
+            }
+            Err(error) => {
+                opts.error(error);
+                return Ok(None);
+            }
+        },
+        None => None,
+    };
+    if let Some(path) = &patch.new_path {
+        let created = patch.old_path.as_ref() != Some(path);
+        let on_disk = || {
+            matches!(target, Target::Index { cached: false, .. })
+                && !results.contains_key(path.as_str())
+                && fs::symlink_metadata(path).is_ok()
+        };
+        if created && (!matches!(current(path)?, Ok(None)) || on_disk()) {
+            opts.error(existing(target, path, results));
+            return Ok(None);
+        }
+    }
+
+    if let (Some((mode, _)), Some(expected), Some(path)) = (&old, patch.old_mode, &patch.old_path) {
+        if *mode != expected && !opts.quiet {
+            eprintln!("warning: {path} has type {mode:o}, expected {expected:o}");
+        }
+    }
+    let path = patch.error_path();
+    let old_content = old
+        .as_ref()
+        .map_or(&[][..], |(_, content)| content.as_slice());
+    let mut conflict = None;
+    let mut rejects = Vec::new();
+    let content = match &patch.binary {
+        Some(binary) => match apply_binary(patch, old_content, binary)? {
+            Ok(content) => content,
+            Err(error) => {
+                opts.error(error);
+                opts.error(format!("{path}: patch does not apply"));
+                return Ok(None);
+            }
+        },
+        None => {
+            let merged = if opts.three_way {
+                try_three_way(patch, old.as_ref(), opts)?
+            } else {
+                None
+            };
+            match merged {
+                Some((content, stages)) => {
+                    conflict = stages;
+                    content
+                }
+                None => {
+                    if opts.three_way {
+                        opts.say("Falling back to direct application...");
+                    }
+                    let Some((content, rejected)) = apply_hunks(old_content, patch, opts) else {
+                        opts.error(format!("{path}: patch does not apply"));
+                        return Ok(None);
+                    };
+                    rejects = rejected
+                        .into_iter()
+                        .map(|i| (i + 1, patch.hunks[i].clone()))
+                        .collect();
+                    content
+                }
+            }
+        }
+    };
+
+    let mut changes = Vec::new();
+    match &patch.new_path {
+        None => {
+            if !content.is_empty() {
+                opts.error("removal patch leaves file contents");
+                opts.error(format!("{path}: patch does not apply"));
+                return Ok(None);
+            }
+        }
+        Some(new_path) => {
+            let mode = patch
+                .new_mode
+                .or(old.as_ref().map(|(mode, _)| *mode))
+                .unwrap_or(MODE_FILE);
+            changes.push((new_path.clone(), Some((mode, content))));
+        }
+    }
+    if let Some(old_path) = &patch.old_path {
+        if !patch.is_copy && patch.new_path.as_ref() != Some(old_path) {
+            changes.push((old_path.clone(), None));
+        }
+    }
+    Ok(Some(PatchResult {
+        changes,
+        conflict,
+        rejects,
+    }))
+}
+
+/// Applies the hunks in order, each where its old lines are found nearest to where it says
+/// they are. Returns `None` at the first hunk that doesn't apply, unless `reject` allows
+/// leaving such hunks out, in which case their indexes are returned with the result.
+fn apply_hunks(
+    content: &[u8],
+    patch: &FilePatch,
+    opts: &ApplyOptions,
+) -> Option<(Vec<u8>, Vec<usize>)> {
+    let mut image: Vec<&[u8]> = content.split_inclusive(|&b| b == b'\n').collect();
+    let mut rejected = Vec::new();
+    for (i, hunk) in patch.hunks.iter().enumerate() {
+        if apply_hunk(&mut image, hunk, opts) {
+            continue;
+        }
+        opts.error(format!(
+            "patch failed: {}:{}",
+            patch.error_path(),
+            hunk.old_start
+        ));
+        if !opts.reject {
+            return None;
+        }
+        rejected.push(i);
+    }
+    Some((image.concat(), rejected))
+}
+
+/// Applies a hunk to the lines of a file, returning whether it applied. When its lines aren't
+/// found and `min_context` allows, the context is cut down, from the longer end first, until
+/// they are.
+fn apply_hunk<'a>(image: &mut Vec<&'a [u8]>, hunk: &'a Hunk, opts: &ApplyOptions) -> bool {
+    let preimage = hunk.image(b'-');
+    let postimage = hunk.image(b'+');
+    let (mut pre, mut post) = (&preimage[..], &postimage[..]);
+    let full_context = hunk.context();
+    let (mut leading, mut trailing) = full_context;
+    // A hunk starting at the first line must match there, and one without trailing context at
+    // the end
+    let mut match_beginning = hunk.old_start <= 1;
+    let mut match_end = trailing == 0;
+    let mut start = hunk.new_start as isize - 1;
+    let min_context = opts.min_context.unwrap_or(usize::MAX);
+    let found = loop {
+        if let Some(at) = find_pos(image, pre, start, match_beginning, match_end) {
+            break Some(at);
+        }
+        if leading <= min_context && trailing <= min_context {
+            break None;
+        }
+        if match_beginning || match_end {
+            (match_beginning, match_end) = (false, false);
+            continue;
+        }
+        if leading >= trailing {
+            (pre, post) = (&pre[1..], &post[1..]);
+            start -= 1;
+            leading -= 1;
+        }
+        if trailing > leading {
+            (pre, post) = (&pre[..pre.len() - 1], &post[..post.len() - 1]);
+            trailing -= 1;
+        }
+    };
+    let Some(at) = found else {
+        if opts.verbose {
+            opts.error(format!(
+                "while searching for:\n{}",
+                text(&preimage.concat())
+            ));
+        }
+        return false;
+    };
+    if (leading, trailing) != full_context {
+        opts.say(format!(
+            "Context reduced to ({leading}/{trailing}) to apply fragment at {}",
+            at + 1
+        ));
+    }
+    image.splice(at..at + pre.len(), post.iter().copied());
+    true
+}
+
+/// Where `preimage` is found in `image`, looking at `start` first, then alternately after
+/// and before it, further and further away
+fn find_pos(
+    image: &[&[u8]],
+    preimage: &[&[u8]],
+    start: isize,
+    match_beginning: bool,
+    match_end: bool,
+) -> Option<usize> {
+    if preimage.len() > image.len() {
+        return None;
+    }
+    let start = if match_beginning {
+        0
+    } else if match_end {
+        image.len() - preimage.len()
+    } else {
+        usize::try_from(start).map_or(image.len(), |start| start.min(image.len()))
+    };
+    let matches = |at: usize| {
+        at + preimage.len() <= image.len()
+            && (!match_beginning || at == 0)
+            && (!match_end || at + preimage.len() == image.len())
+            && image[at..at + preimage.len()] == *preimage
+    };
+    let (mut backwards, mut forwards) = (start, start);
+    if matches(start) {
+        return Some(start);
+    }
+    loop {
+        if backwards == 0 && forwards == image.len() {
+            return None;
+        }
+        if forwards < image.len() {
+            forwards += 1;
+            if matches(forwards) {
+                return Some(forwards);
+            }
+        }
+        if backwards > 0 {
+            backwards -= 1;
+            if matches(backwards) {
+                return Some(backwards);
+            }
+        }
+    }
+}
+
+/// Merges the changes of a text patch into the current content, as `--3way` does: the blob
+/// its `index` line names is the base, and the patch applied to that is theirs. Returns the
+/// result with the blobs of the three sides if it has conflicts, or `None` if the patch has to
+/// be applied directly.
+fn try_three_way(
+    patch: &FilePatch,
+    old: Option<&FileState>,
+    opts: &ApplyOptions,
+) -> anyhow::Result<Option<(Vec<u8>, Option<Stages>)>> {
+    // Created and deleted files have nothing to merge, nor have renames without changes
+    let (Some((_, ours)), Some(new_path)) = (old, &patch.new_path) else {
+        return Ok(None);
+    };
+    if patch.old_mode == Some(MODE_GITLINK)
+        || patch.new_mode == Some(MODE_GITLINK)
+        || (patch.is_rename && patch.hunks.is_empty())
+    {
+        return Ok(None);
+    }
+    let Some(base) = patch.old_id.as_deref().and_then(resolve_blob) else {
+        opts.error("repository lacks the necessary blob to perform 3-way merge.");
+        return Ok(None);
+    };
+    let base_content = read_object_of_kind(&hex::encode(base), ObjectKind::Blob)?;
+    let direct = ApplyOptions {
+        reject: false,
+        ..*opts
+    };
+    let Some((theirs, _)) = apply_hunks(&base_content, patch, &direct) else {
+        return Ok(None);
+    };
+    let their_hash = hash_object(ObjectKind::Blob, &theirs, true)?;
+    let our_hash = hash_object(ObjectKind::Blob, ours, true)?;
+    let labels = Labels {
+        ours: "ours",
+        base: "base",
+        theirs: "theirs",
+    };
+    let file_opts = FileOptions {
+        style: ConflictStyle::configured()?,
+        ..FileOptions::default()
+    };
+    let (content, conflicts) = content::merge(&base_content, ours, &theirs, labels, &file_opts);
+    if conflicts == 0 {
+        opts.say(format!("Applied patch to '{new_path}' cleanly."));
+        return Ok(Some((content, None)));
+    }
+    opts.say(format!("Applied patch to '{new_path}' with conflicts."));
+    Ok(Some((
+        content,
+        Some([Some(base), Some(our_hash), Some(their_hash)]),
+    )))
+}
+
+/// The blob a possibly abbreviated name from an `index` line stands for, if this repository
+/// has it
+pub(crate) fn resolve_blob(id: &str) -> Option<[u8; 20]> {
+    let hex = object::resolve_hash(id).ok()?;
+    let hash = object::commit::parse_hash(&hex).ok()?;
+    revision::kind_of(&hash)
+        .is_ok_and(|kind| kind == ObjectKind::Blob)
+        .then_some(hash)
+}
+
+/// The content a binary patch leaves, checking the old content against the `index` line
+fn apply_binary(
+    patch: &FilePatch,
+    old: &[u8],
+    binary: &BinaryPatch,
+) -> anyhow::Result<Result<Vec<u8>, String>> {
+    let path = patch.error_path();
+    let full_id = |id: &Option<String>| id.as_ref().filter(|id| id.len() == 40).cloned();
+    let (Some(old_id), Some(new_id)) = (full_id(&patch.old_id), full_id(&patch.new_id)) else {
+        return Ok(Err(format!(
+            "cannot apply binary patch to '{path}' without full index line"
+        )));
+    };
+    if patch.old_path.is_some() {
+        let old_hash = hex::encode(hash_object(ObjectKind::Blob, old, false)?);
+        if old_hash != old_id {
+            return Ok(Err(format!(
+                "the patch applies to '{path}' ({old_hash}), which does not match the current contents."
+            )));
+        }
+    } else if !old.is_empty() {
+        return Ok(Err(format!(
+            "the patch applies to an empty '{path}' but it is not empty"
+        )));
+    }
+    if new_id == "0".repeat(40) {
+        return Ok(Ok(Vec::new()));
+    }
+    // The result may already be in the repository
+    if let Ok(content) = read_object_of_kind(&new_id, ObjectKind::Blob) {
+        return Ok(Ok(content));
+    }
+    let content = match &binary.forward {
+        Some(BinaryHunk::Literal(content)) => Some(content.clone()),
+        Some(BinaryHunk::Delta(delta)) => apply_delta(old, delta),
+        None => return Ok(Err(format!("missing binary patch data for '{path}'"))),
+    };
+    let Some(content) = content else {
+        return Ok(Err(format!("binary patch does not apply to '{path}'")));
+    };
+    let new_hash = hex::encode(hash_object(ObjectKind::Blob, &content, false)?);
+    if new_hash != new_id {
+        return Ok(Err(format!(
+            "binary patch to '{path}' creates incorrect result (expecting {new_id}, got {new_hash})"
+        )));
+    }
+    Ok(Ok(content))
+}
+
+/// Rebuilds content from a delta against `base`: the sizes of the base and the result, then
+/// instructions to copy ranges of the base or insert the bytes that follow. `None` if the
+/// delta doesn't fit the base.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 0;
+    let mut size = || {
+        let mut value = 0usize;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(pos)?;
+            pos += 1;
+            value |= usize::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+    };
+    let base_size = size()?;
+    let result_size = size()?;
+    if base_size != base.len() {
+        return None;
+    }
+    let mut out = Vec::with_capacity(result_size);
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            // Copy: the bits say which bytes of the offset and the size follow
+            let mut field = |bits: u8, count: usize| -> Option<usize> {
+                let mut value = 0;
+                for i in 0..count {
+                    if bits & (1 << i) != 0 {
+                        value |= usize::from(*delta.get(pos)?) << (8 * i);
+                        pos += 1;
+                    }
+                }
+                Some(value)
+            };
+            let offset = field(op, 4)?;
+            let size = match field(op >> 4, 3)? {
+                0 => 0x10000,
+                size => size,
+            };
+            out.extend_from_slice(base.get(offset..offset.checked_add(size)?)?);
+        } else if op != 0 {
+            let len = usize::from(op);
+            out.extend_from_slice(delta.get(pos..pos + len)?);
+            pos += len;
+        } else {
+            return None;
+        }
+    }
+    (out.len() == result_size).then_some(out)
+}
+
+/// The widths `apply --stat` lays its lines out for, which grow with each patch file read:
+/// names up to 50 columns wide and graphs scaled to fit in 70 with them
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct StatLayout {
+    max_len: usize,
+    max_change: usize,
+}
+
+impl StatLayout {
+    /// Makes room for the names and changes of more patches
+    pub(crate) fn add(&mut self, patches: &[FilePatch]) {
+        for patch in patches {
+            let (added, deleted) = patch.line_counts();
+            self.max_change = self.max_change.max(added + deleted);
+            for path in [&patch.old_path, &patch.new_path].into_iter().flatten() {
+                self.max_len = self
+                    .max_len
+                    .max(quote::c_style(path, false).chars().count());
+            }
+        }
+    }
+
+    /// Writes the diffstat of patches
+    pub(crate) fn write(&self, out: &mut impl Write, patches: &[FilePatch]) -> anyhow::Result<()> {
+        let max_len = self.max_len.min(50);
+        let max_change = self.max_change;
+        let graph_width = if max_len + max_change > 70 {
+            70 - max_len
+        } else {
+            max_change
+        };
+        let (mut insertions, mut deletions) = (0, 0);
+        for patch in patches {
+            let mut name = quote::c_style(patch.path(), false);
+            let len = name.chars().count();
+            if len > max_len {
+                // Keep the end of the name, from a slash if there is one
+                let skip = len + 3 - max_len;
+                let start = name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i);
+                let start = name[start..].find('/').map_or(start, |slash| start + slash);
+                name = format!("...{}", &name[start..]);
+            }
+            if patch.binary.is_some() {
+                writeln!(out, " {name:<max_len$} |  Bin")?;
+                continue;
+            }
+            let (added, deleted) = patch.line_counts();
+            insertions += added;
+            deletions += deleted;
+            // Without changes anywhere, there is nothing to scale
+            let scale = |lines: usize| (lines * graph_width + max_change / 2) / max_change.max(1);
+            let add = scale(added);
+            let del = scale(added + deleted) - add;
+            writeln!(
+                out,
+                " {name:<max_len$} |{:5} {}{}",
+                added + deleted,
+                "+".repeat(add),
+                "-".repeat(del)
+            )?;
+        }
+        writeln!(
+            out,
+            "{}",
+            stat_summary(patches.len(), insertions, deletions)
+        )?;
+        Ok(())
+    }
+}
+
+/// The file at `path` in the target, `None` if there is none, or why it can't be patched
+fn read(target: &Target, path: &str) -> anyhow::Result<Result<Option<FileState>, String>> {
+    match target {
+        Target::Worktree => match fs::symlink_metadata(path) {
+            Ok(metadata) if !metadata.is_dir() => Ok(Ok(Some((
+                index::mode_from_metadata(&metadata),
+                worktree::read_file(Path::new(path))?,
+            )))),
+            Err(error) if error.kind() == io::ErrorKind::NotADirectory => {
+                Ok(Err(format!("{path}: Not a directory")))
+            }
+            _ => Ok(Ok(None)),
+        },
+        Target::Index { index, cached } => {
+            let Some(entry) = index.get(path, 0) else {
+                return Ok(Ok(None));
+            };
+            if !cached && !index.matches_worktree(entry)? {
+                return Ok(Err(format!("{path}: does not match index")));
+            }
+            Ok(Ok(Some((entry.mode, read_blob(entry.mode, &entry.hash)?))))
+        }
+        Target::Tree(files) => match files.get(path) {
+            Some((mode, hash)) => Ok(Ok(Some((*mode, read_blob(*mode, hash)?)))),
+            None => Ok(Ok(None)),
+        },
+    }
+}
+
+fn read_blob(mode: u32, hash: &[u8; 20]) -> anyhow::Result<Vec<u8>> {
+    if mode == MODE_GITLINK {
+        return Ok(format!("Subproject commit {}\n", hex::encode(hash)).into_bytes());
+    }
+    read_object_of_kind(&hex::encode(hash), ObjectKind::Blob)
+}
+
+/// Why a file the patch changes can't be found
+fn missing(target: &Target, path: &str) -> String {
+    match target {
+        Target::Worktree => format!("{path}: No such file or directory"),
+        _ => format!("{path}: does not exist in index"),
+    }
+}
+
+/// Why a file the patch creates is in the way
+fn existing(target: &Target, path: &str, results: &HashMap<String, Option<FileState>>) -> String {
+    match target {
+        Target::Index { index, .. }
+            if results.contains_key(path) || index.get(path, 0).is_some() =>
+        {
+            format!("{path}: already exists in index")
+        }
+        Target::Tree(_) => format!("{path}: already exists in index"),
+        _ => format!("{path}: already exists in working directory"),
+    }
+}
+
+fn remove(target: &mut Target, path: &str) -> anyhow::Result<()> {
+    match target {
+        Target::Worktree => worktree::remove_file(path),
+        Target::Index { index, cached } => {
+            index.remove(path);
+            if !*cached {
+                worktree::remove_file(path)?;
+            }
+            Ok(())
+        }
+        Target::Tree(files) => {
+            files.remove(path);
+            Ok(())
+        }
+    }
+}
+
+fn write(target: &mut Target, path: &str, mode: u32, content: &[u8]) -> anyhow::Result<()> {
+    match target {
+        Target::Worktree => worktree::write_content(path, mode, content.to_vec()),
+        Target::Index { index, cached } => {
+            let hash = hash_object(ObjectKind::Blob, content, true)?;
+            let mut entry = IndexEntry::new(path.to_string(), mode, hash, 0);
+            if !*cached {
+                worktree::write_content(path, mode, content.to_vec())?;
+                entry.update_stat(&fs::symlink_metadata(path)?);
+            }
+            index.remove(path);
+            index.add(entry);
+            Ok(())
+        }
+        Target::Tree(files) => {
+            let hash = hash_object(ObjectKind::Blob, content, true)?;
+            files.insert(path.to_string(), (mode, hash));
+            Ok(())
+        }
+    }
+}
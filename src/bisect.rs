@@ -0,0 +1,1046 @@
+//! Bisecting: a binary search through the history for the commit that introduced a change, as
+//! `git bisect` does.
+//!
+//! The user marks commits with the bad term (the change is there) or the good term (it isn't),
+//! or skips those that can't be tested. Each time both a bad and a good commit are known, the
+//! commits reachable from the bad one but from no good one are weighed by how many of them each
+//! reaches, and the one closest to halving them is checked out to be tested next. The state lives
+//! in `.git/BISECT_*` files and `refs/bisect/*`, laid out as git lays it out, so that either can
+//! carry on a bisection the other started.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Write,
+    path::Path,
+    process,
+};
+
+use anyhow::Context;
+
+use crate::{
+    commands::checkout::{self, Switch},
+    date::DateMode,
+    diff::{
+        files::tree_to_tree,
+        patch::{write_stat, write_summary, PatchOptions},
+    },
+    merge_base::CommitGraph,
+    object::commit::{parse_hash, Commit},
+    pathspec::Pathspec,
+    pretty::{self, Format, PrettyOptions, ShownCommit},
+    quote,
+    refs::{self, Head},
+    revision,
+    revwalk::{RevWalk, Revisions, WalkOptions},
+    worktree,
+};
+
+/// The branch (or detached commit) the bisection started from, to go back to on reset
+const START: &str = ".git/BISECT_START";
+/// The bad term, then the good term, one per line
+const TERMS: &str = ".git/BISECT_TERMS";
+/// The paths the bisection is limited to, quoted for the shell
+const NAMES: &str = ".git/BISECT_NAMES";
+/// The commands that got the bisection where it is, with comments, as `bisect replay` takes
+const LOG: &str = ".git/BISECT_LOG";
+/// The commit last checked out to be tested
+const EXPECTED_REV: &str = ".git/BISECT_EXPECTED_REV";
+/// Present once every good commit is known to be an ancestor of the bad one
+const ANCESTORS_OK: &str = ".git/BISECT_ANCESTORS_OK";
+/// The output of the last step of `bisect run`
+const RUN: &str = ".git/BISECT_RUN";
+const REFS: &str = "refs/bisect/";
+
+/// The largest value of [`pseudo_random`]
+const PRN_MODULO: u32 = 32768;
+
+/// The names commits are marked with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Terms {
+    /// For the commits that have the change, `bad` by default
+    pub(crate) bad: String,
+    /// For the commits that don't have it yet, `good` by default
+    pub(crate) good: String,
+}
+
+impl Default for Terms {
+    fn default() -> Terms {
+        Terms::new("bad", "good")
+    }
+}
+
+impl Terms {
+    fn new(bad: &str, good: &str) -> Terms {
+        Terms {
+            bad: bad.to_string(),
+            good: good.to_string(),
+        }
+    }
+
+    /// The terms recorded for the bisection in progress
+    pub(crate) fn read() -> Option<Terms> {
+        let content = fs::read_to_string(TERMS).ok()?;
+        let mut lines = content.lines();
+        match (lines.next(), lines.next()) {
+            (Some(bad), Some(good)) if !bad.is_empty() && !good.is_empty() => {
+                Some(Terms::new(bad, good))
+            }
+            _ => None,
+        }
+    }
+
+    fn write(&self) -> anyhow::Result<()> {
+        fs::write(TERMS, format!("{}\n{}\n", self.bad, self.good)).context("Writing BISECT_TERMS")
+    }
+}
+
+/// How a bisect command left the bisection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// A commit was checked out to be tested, or more commits have to be marked first
+    Continue,
+    /// The first bad commit was found
+    Found,
+    /// A merge base of the bad and good commits was checked out to be tested first
+    MergeBase,
+    Failed,
+    /// Only skipped commits are left, so the first bad commit is one of them
+    OnlySkipped,
+    /// The bad commit is a merge base of the good ones, so it can't be bisected
+    BadMergeBase,
+    /// No commit changes the paths the bisection is limited to
+    NoTestable,
+}
+
+impl Outcome {
+    /// The exit status git gives each outcome
+    pub(crate) fn exit_code(self) -> i32 {
+        match self {
+            Outcome::Continue | Outcome::Found | Outcome::MergeBase => 0,
+            Outcome::Failed => 1,
+            Outcome::OnlySkipped => 2,
+            Outcome::BadMergeBase => 3,
+            Outcome::NoTestable => 4,
+        }
+    }
+
+    fn is_success(self) -> bool {
+        self.exit_code() == 0
+    }
+}
+
+/// The commits marked so far
+#[derive(Debug, Default)]
+struct Marks {
+    bad: Option<[u8; 20]>,
+    /// Sorted by id, as the refs are
+    good: Vec<[u8; 20]>,
+    skip: Vec<[u8; 20]>,
+}
+
+impl Marks {
+    fn read(terms: &Terms) -> anyhow::Result<Marks> {
+        let mut marks = Marks::default();
+        let good_prefix = format!("{}-", terms.good);
+        for (name, hash) in refs::list(REFS)? {
+            let name = &name[REFS.len()..];
+            if name == terms.bad {
+                marks.bad = Some(hash);
+            } else if name.starts_with(&good_prefix) {
+                marks.good.push(hash);
+            } else if name.starts_with("skip-") {
+                marks.skip.push(hash);
+            }
+        }
+        Ok(marks)
+    }
+}
+
+pub(crate) fn is_bisecting() -> bool {
+    fs::read_to_string(START).is_ok_and(|start| !start.is_empty())
+}
+
+/// `bisect start [--term-{old,good}=<term>] [--term-{new,bad}=<term>] [<bad> [<good>...]]
+/// [--] [<paths>...]`: starts over from the current branch, marking the commits given
+pub(crate) fn start(args: &[String], out: &mut impl Write) -> anyhow::Result<Outcome> {
+    let has_double_dash = args.iter().any(|arg| arg == "--");
+    let mut terms = Terms::default();
+    let mut must_write_terms = false;
+    let mut revs = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg == "--" {
+            break;
+        }
+        if let Some(option) = ["--term-good", "--term-old", "--term-bad", "--term-new"]
+            .into_iter()
+            .find(|option| arg == *option || arg.starts_with(&format!("{option}=")))
+        {
+            let value = match arg
+                .strip_prefix(option)
+                .and_then(|arg| arg.strip_prefix('='))
+            {
+                Some(value) => value.to_string(),
+                None => {
+                    i += 1;
+                    match args.get(i) {
+                        Some(value) => value.clone(),
+                        None => anyhow::bail!("'' is not a valid term"),
+                    }
+                }
+            };
+            if option == "--term-good" || option == "--term-old" {
+                terms.good = value;
+            } else {
+                terms.bad = value;
+            }
+            must_write_terms = true;
+        } else if arg.starts_with("--") {
+            anyhow::bail!("unrecognized option: '{arg}'");
+        } else if let Ok(hash) = resolve_commit(arg) {
+            revs.push(hash);
+        } else if has_double_dash {
+            anyhow::bail!("'{arg}' does not appear to be a valid revision");
+        } else {
+            break;
+        }
+        i += 1;
+    }
+    let pathspec_pos = i;
+    // Marking commits records the default terms if none were given, as they can't change after
+    must_write_terms |= !revs.is_empty();
+
+    let Some(head) = refs::head_commit()? else {
+        anyhow::bail!("bad HEAD - I need a HEAD");
+    };
+    let start_head = if is_bisecting() {
+        let start_head = fs::read_to_string(START).context("Reading BISECT_START")?;
+        let start_head = start_head.trim().to_string();
+        checkout::switch_to(Switch {
+            target: Some(start_head.clone()),
+            ..Switch::default()
+        })
+        .with_context(|| {
+            format!("checking out '{start_head}' failed. Try 'git bisect start <valid-branch>'.")
+        })?;
+        start_head
+    } else {
+        match refs::read_head()? {
+            Head::Symbolic(target) => match target.strip_prefix("refs/heads/") {
+                Some(branch) => branch.to_string(),
+                None => anyhow::bail!("bad HEAD - strange symbolic ref"),
+            },
+            Head::Detached(_) => hex::encode(head),
+        }
+    };
+
+    clean_state()?;
+    fs::write(START, format!("{start_head}\n")).context("Writing BISECT_START")?;
+    // As git does, the paths are only recorded when more than one argument is left
+    let names = match args.len().saturating_sub(1) > pathspec_pos {
+        true => quote_args(&args[pathspec_pos..]),
+        false => String::new(),
+    };
+    fs::write(NAMES, format!("{names}\n")).context("Writing BISECT_NAMES")?;
+    for (n, hash) in revs.iter().enumerate() {
+        let state = if n == 0 { &terms.bad } else { &terms.good };
+        write_mark(state, hash, &terms, false)?;
+    }
+    if must_write_terms {
+        terms.write()?;
+    }
+    append_log(&format!("git bisect start{}\n", quote_args(args)))?;
+
+    let outcome = auto_next(&terms, out)?;
+    if !outcome.is_success() {
+        clean_state()?;
+    }
+    Ok(outcome)
+}
+
+/// `bisect <state> [<rev>...]`: marks the commits (HEAD by default) as bad, good or skipped,
+/// then checks out the next one to test
+pub(crate) fn mark(state: &str, revs: &[String], out: &mut impl Write) -> anyhow::Result<Outcome> {
+    if !is_bisecting() {
+        eprintln!("You need to start by \"git bisect start\"\n");
+        return Ok(Outcome::Failed);
+    }
+    let Some(terms) = check_terms(state)? else {
+        return Ok(Outcome::Failed);
+    };
+    if state != terms.bad && state != terms.good && state != "skip" {
+        eprintln!("error: unknown command: '{state}'");
+        return Ok(Outcome::Failed);
+    }
+    if state == terms.bad && revs.len() > 1 {
+        eprintln!(
+            "error: 'git bisect {}' can take only one argument.",
+            terms.bad
+        );
+        return Ok(Outcome::Failed);
+    }
+
+    let mut hashes = Vec::new();
+    if revs.is_empty() {
+        match refs::head_commit()? {
+            Some(head) => hashes.push(head),
+            None => {
+                eprintln!("error: Bad rev input: HEAD");
+                return Ok(Outcome::Failed);
+            }
+        }
+    }
+    for rev in revs {
+        match resolve_commit(rev) {
+            Ok(hash) => hashes.push(hash),
+            Err(_) => {
+                eprintln!("error: Bad rev input: {rev}");
+                return Ok(Outcome::Failed);
+            }
+        }
+    }
+
+    let mut expected = fs::read_to_string(EXPECTED_REV)
+        .ok()
+        .and_then(|content| parse_hash(content.trim()).ok());
+    for hash in &hashes {
+        write_mark(state, hash, &terms, true)?;
+        // Marking anything but the commit that was checked out means the ancestry of the good
+        // commits has to be checked again
+        if expected.is_some_and(|expected| expected != *hash) {
+            worktree::remove_if_exists(ANCESTORS_OK)?;
+            worktree::remove_if_exists(EXPECTED_REV)?;
+            expected = None;
+        }
+    }
+    auto_next(&terms, out)
+}
+
+/// `bisect skip [<rev>|<range>...]`: marks the commits as untestable, ranges like `a..b`
+/// standing for every commit in them
+pub(crate) fn skip(revs: &[String], out: &mut impl Write) -> anyhow::Result<Outcome> {
+    let mut expanded = Vec::new();
+    for rev in revs {
+        if !rev.contains("..") {
+            expanded.push(rev.clone());
+            continue;
+        }
+        let revisions = Revisions::parse(std::slice::from_ref(rev), &[], false)?;
+        let mut walk = RevWalk::new(&revisions, WalkOptions::default())?;
+        while let Some(hash) = walk.next()? {
+            expanded.push(hex::encode(hash));
+        }
+    }
+    mark("skip", &expanded, out)
+}
+
+/// `bisect reset [<commit>]`: ends the bisection, going back to where it started or to
+/// `commit`
+pub(crate) fn reset(commit: Option<&str>) -> anyhow::Result<()> {
+    let target = match commit {
+        Some(commit) => {
+            resolve_commit(commit).with_context(|| format!("'{commit}' is not a valid commit"))?;
+            commit.to_string()
+        }
+        None => match fs::read_to_string(START) {
+            Ok(start) if !start.is_empty() => start.trim_end().to_string(),
+            _ => {
+                println!("We are not bisecting.");
+                return Ok(());
+            }
+        },
+    };
+    checkout::switch_to(Switch {
+        target: Some(target.clone()),
+        ..Switch::default()
+    })
+    .with_context(|| {
+        format!("could not check out original HEAD '{target}'. Try 'git bisect reset <commit>'.")
+    })?;
+    clean_state()
+}
+
+/// `bisect log`: the commands of the bisection so far, `None` when not bisecting
+pub(crate) fn log() -> Option<String> {
+    fs::read_to_string(LOG).ok().filter(|log| !log.is_empty())
+}
+
+/// `bisect replay <file>`: starts over, going through the commands of a bisect log
+pub(crate) fn replay(file: &str, out: &mut impl Write) -> anyhow::Result<Outcome> {
+    let content = match fs::read_to_string(file) {
+        Ok(content) if !content.is_empty() => content,
+        _ => anyhow::bail!("cannot read file '{file}' for replaying"),
+    };
+    reset(None)?;
+    for line in content.lines() {
+        let line = line.trim_start_matches([' ', '\t']);
+        let Some(rest) = line
+            .strip_prefix("git bisect")
+            .or_else(|| line.strip_prefix("git-bisect"))
+        else {
+            continue;
+        };
+        if !rest.starts_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let rest = rest.trim_start_matches([' ', '\t']);
+        let (command, arg) = match rest.split_once([' ', '\t']) {
+            Some((command, arg)) => (command, arg.trim_start_matches([' ', '\t'])),
+            None => (rest, ""),
+        };
+        let Some(terms) = check_terms(command)? else {
+            return Ok(Outcome::Failed);
+        };
+        if command == "start" {
+            let args = quote::split_shell(arg).unwrap_or_default();
+            if start(&args, out)? != Outcome::Continue {
+                return Ok(Outcome::Failed);
+            }
+        } else if command == terms.good || command == terms.bad || command == "skip" {
+            let Ok(hash) = resolve_commit(arg) else {
+                eprintln!("error: couldn't get the oid of the rev '{arg}'");
+                return Ok(Outcome::Failed);
+            };
+            write_mark(command, &hash, &terms, true)?;
+        } else if command == "terms" {
+            let args = quote::split_shell(arg).unwrap_or_default();
+            let option = match args.as_slice() {
+                [option] => Some(option.as_str()),
+                _ => None,
+            };
+            write!(out, "{}", describe_terms(option)?)?;
+        } else {
+            eprintln!("error: '{command}'?? what are you talking about?");
+            return Ok(Outcome::Failed);
+        }
+    }
+    auto_next(&Terms::read().unwrap_or_default(), out)
+}
+
+/// `bisect run <command>...`: marks each commit checked out by the exit status of `command`
+/// until the first bad commit is found: 0 for good, 125 for skip and anything else below 128
+/// for bad
+pub(crate) fn run(command: &[String]) -> anyhow::Result<Outcome> {
+    if command.is_empty() {
+        eprintln!("error: bisect run failed: no command provided.");
+        return Ok(Outcome::Failed);
+    }
+    let terms = Terms::read().unwrap_or_default();
+    let marks = Marks::read(&terms)?;
+    if marks.bad.is_none() || marks.good.is_empty() {
+        return Ok(Outcome::Failed);
+    }
+    let command = quote_args(command);
+    loop {
+        println!("running {command}");
+        let status = process::Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .status()
+            .with_context(|| format!("Running {command}"))?;
+        let code = status.code().unwrap_or(-1);
+        if !(0..128).contains(&code) {
+            eprintln!(
+                "error: bisect run failed: exit code {code} from '{command}' is < 0 or >= 128"
+            );
+            return Ok(Outcome::Failed);
+        }
+        let state = match code {
+            125 => "skip",
+            0 => terms.good.as_str(),
+            _ => terms.bad.as_str(),
+        };
+
+        let mut output = Vec::new();
+        let outcome = mark(state, &[], &mut output)?;
+        fs::write(RUN, &output).context("Writing BISECT_RUN")?;
+        std::io::stdout().write_all(&output)?;
+        match outcome {
+            Outcome::Continue => continue,
+            Outcome::OnlySkipped => eprintln!("error: bisect run cannot continue any more"),
+            Outcome::MergeBase => println!("bisect run success"),
+            Outcome::Found => println!("bisect found first bad commit"),
+            outcome => eprintln!(
+                "error: bisect run failed: 'git bisect {state}' exited with error code -{}",
+                outcome.exit_code()
+            ),
+        }
+        return Ok(outcome);
+    }
+}
+
+/// `bisect terms [--term-good|--term-bad]`: both terms in a sentence, or the one asked for
+pub(crate) fn describe_terms(option: Option<&str>) -> anyhow::Result<String> {
+    let terms = Terms::read().context("no terms defined")?;
+    match option {
+        None => Ok(format!(
+            "Your current terms are {} for the old state\nand {} for the new state.\n",
+            terms.good, terms.bad
+        )),
+        Some("--term-good" | "--term-old") => Ok(format!("{}\n", terms.good)),
+        Some("--term-bad" | "--term-new") => Ok(format!("{}\n", terms.bad)),
+        Some(option) => anyhow::bail!(
+            "invalid argument {option} for 'git bisect terms'.\nSupported options are: --term-good|--term-old and --term-bad|--term-new."
+        ),
+    }
+}
+
+/// The terms to mark commits with as `command`. Marking with an unknown term is an error once
+/// terms are recorded; before that, `bad`/`good` and `new`/`old` record themselves.
+fn check_terms(command: &str) -> anyhow::Result<Option<Terms>> {
+    let recorded = Terms::read();
+    if ["skip", "start", "terms"].contains(&command) {
+        return Ok(Some(recorded.unwrap_or_default()));
+    }
+    let terms = match recorded {
+        Some(terms) if command != terms.bad && command != terms.good => {
+            eprintln!(
+                "error: Invalid command: you're currently in a {}/{} bisect",
+                terms.bad, terms.good
+            );
+            return Ok(None);
+        }
+        Some(terms) => terms,
+        None => match command {
+            "bad" | "good" => Terms::default(),
+            "new" | "old" => Terms::new("new", "old"),
+            _ => return Ok(Some(Terms::default())),
+        },
+    };
+    if !Path::new(TERMS).exists() {
+        terms.write()?;
+    }
+    Ok(Some(terms))
+}
+
+/// Records a mark in `refs/bisect` and the log, with the command that made it unless it was
+/// made by `bisect start`
+fn write_mark(
+    state: &str,
+    hash: &[u8; 20],
+    terms: &Terms,
+    log_command: bool,
+) -> anyhow::Result<()> {
+    let hex = hex::encode(hash);
+    let name = match state == terms.bad {
+        true => format!("{REFS}{state}"),
+        false => format!("{REFS}{state}-{hex}"),
+    };
+    refs::update_ref(&name, hash, "")?;
+    let commit = Commit::read(hash)?;
+    let mut entry = format!("# {state}: [{hex}] {}\n", pretty::subject(&commit.message));
+    if log_command {
+        entry.push_str(&format!("git bisect {state} {hex}\n"));
+    }
+    append_log(&entry)
+}
+
+/// Moves on once both a bad and a good commit are known, otherwise says what's still needed
+fn auto_next(terms: &Terms, out: &mut impl Write) -> anyhow::Result<Outcome> {
+    let marks = Marks::read(terms)?;
+    let Some(bad) = marks.bad else {
+        let status = match marks.good.len() {
+            0 => String::from("status: waiting for both good and bad commits\n"),
+            1 => String::from("status: waiting for bad commit, 1 good commit known\n"),
+            n => format!("status: waiting for bad commit, {n} good commits known\n"),
+        };
+        return log_status(&status, out);
+    };
+    if marks.good.is_empty() {
+        return log_status(
+            "status: waiting for good commit(s), bad commit known\n",
+            out,
+        );
+    }
+
+    if let Some(outcome) = check_ancestors(terms, &bad, &marks, out)? {
+        return Ok(outcome);
+    }
+    let (candidates, all, reaches) =
+        find_bisection(&bad, &marks.good, &read_names()?, !marks.skip.is_empty())?;
+    let (candidate, tried) = managed_skipped(&candidates, &marks.skip, &bad);
+    let Some(candidate) = candidate else {
+        if !tried.is_empty() {
+            return only_skipped(terms, &bad, &marks.good, &tried, false, out);
+        }
+        writeln!(
+            out,
+            "{} was both {} and {}",
+            hex::encode(bad),
+            terms.good,
+            terms.bad
+        )?;
+        return Ok(Outcome::Failed);
+    };
+    if all == 0 {
+        eprintln!("No testable commit found.\nMaybe you started with bad path arguments?");
+        return Ok(Outcome::NoTestable);
+    }
+    if candidate == bad {
+        if !tried.is_empty() {
+            return only_skipped(terms, &bad, &marks.good, &tried, true, out);
+        }
+        writeln!(
+            out,
+            "{} is the first {} commit",
+            hex::encode(bad),
+            terms.bad
+        )?;
+        show_commit(&bad, out)?;
+        let subject = pretty::subject(&Commit::read(&bad)?.message);
+        append_log(&format!(
+            "# first {} commit: [{}] {subject}\n",
+            terms.bad,
+            hex::encode(bad)
+        ))?;
+        return Ok(Outcome::Found);
+    }
+
+    // Commits that don't change the limited paths can reach all of them, leaving -1
+    let left = all as i64 - reaches as i64 - 1;
+    let steps = estimate_steps(all);
+    writeln!(
+        out,
+        "Bisecting: {left} revision{} left to test after this (roughly {steps} step{})",
+        if left == 1 { "" } else { "s" },
+        if steps == 1 { "" } else { "s" }
+    )?;
+    checkout_candidate(&candidate, out)
+}
+
+fn log_status(status: &str, out: &mut impl Write) -> anyhow::Result<Outcome> {
+    write!(out, "{status}")?;
+    append_log(&format!("# {status}"))?;
+    Ok(Outcome::Continue)
+}
+
+/// Makes sure the bad commit descends from every good one. Otherwise their merge bases have to
+/// be tested first: a bad one means the change happened the other way around, and one that
+/// isn't marked yet is checked out.
+fn check_ancestors(
+    terms: &Terms,
+    bad: &[u8; 20],
+    marks: &Marks,
+    out: &mut impl Write,
+) -> anyhow::Result<Option<Outcome>> {
+    if Path::new(ANCESTORS_OK).is_file() {
+        return Ok(None);
+    }
+    let mut graph = CommitGraph::new();
+    let mut all_ancestors = true;
+    for good in &marks.good {
+        all_ancestors &= graph.is_ancestor(good, bad)?;
+    }
+    if !all_ancestors {
+        let goods = marks
+            .good
+            .iter()
+            .map(hex::encode)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let bad_hex = hex::encode(bad);
+        for base in CommitGraph::new().merge_bases(bad, &marks.good)? {
+            if base == *bad {
+                return Ok(Some(bad_merge_base(terms, &bad_hex, &goods)));
+            }
+            if marks.good.contains(&base) {
+                continue;
+            }
+            if marks.skip.contains(&base) {
+                eprintln!(
+                    "warning: the merge base between {bad_hex} and [{goods}] must be skipped.\nSo we cannot be sure the first {} commit is between {} and {bad_hex}.\nWe continue anyway.",
+                    terms.bad,
+                    hex::encode(base)
+                );
+                continue;
+            }
+            writeln!(out, "Bisecting: a merge base must be tested")?;
+            return Ok(Some(match checkout_candidate(&base, out)? {
+                Outcome::Continue => Outcome::MergeBase,
+                outcome => outcome,
+            }));
+        }
+    }
+    fs::write(ANCESTORS_OK, "").context("Writing BISECT_ANCESTORS_OK")?;
+    Ok(None)
+}
+
+/// Explains a bad commit that is a merge base of the good ones: just after checking it out,
+/// the change went the other way; otherwise the commits were probably marked the wrong way
+/// round
+fn bad_merge_base(terms: &Terms, bad: &str, goods: &str) -> Outcome {
+    let expected = fs::read_to_string(EXPECTED_REV).unwrap_or_default();
+    if expected.trim() != bad {
+        eprintln!(
+            "Some {good} revs are not ancestors of the {bad_term} rev.\ngit bisect cannot work properly in this case.\nMaybe you mistook {good} and {bad_term} revs?",
+            good = terms.good,
+            bad_term = terms.bad
+        );
+        return Outcome::Failed;
+    }
+    if *terms == Terms::default() {
+        eprintln!(
+            "The merge base {bad} is bad.\nThis means the bug has been fixed between {bad} and [{goods}]."
+        );
+    } else if *terms == Terms::new("new", "old") {
+        eprintln!(
+            "The merge base {bad} is new.\nThe property has changed between {bad} and [{goods}]."
+        );
+    } else {
+        eprintln!(
+            "The merge base {bad} is {}.\nThis means the first '{}' commit is between {bad} and [{goods}].",
+            terms.bad, terms.good
+        );
+    }
+    Outcome::BadMergeBase
+}
+
+/// The commits reachable from `bad` but not from any of `goods` (and changing `paths`), weighed
+/// by how many of them each reaches. Returns the best one to test next, or all of them best
+/// first with `find_all`, along with how many commits are left to test and how many of them the
+/// first one reaches.
+fn find_bisection(
+    bad: &[u8; 20],
+    goods: &[[u8; 20]],
+    paths: &[String],
+    find_all: bool,
+) -> anyhow::Result<(Vec<[u8; 20]>, usize, usize)> {
+    let revisions = Revisions {
+        include: vec![*bad],
+        exclude: goods.to_vec(),
+        ..Revisions::default()
+    };
+    let opts = WalkOptions {
+        paths: (!paths.is_empty()).then(|| Pathspec::new(paths)),
+        ..WalkOptions::default()
+    };
+    let walk = RevWalk::new(&revisions, opts)?;
+    // Oldest first, so parents tend to be weighed before their children
+    let mut list = walk.limited_list();
+    list.reverse();
+    let on_list: HashSet<[u8; 20]> = list.iter().copied().collect();
+    let nr = list.iter().filter(|hash| !walk.is_treesame(hash)).count() as i64;
+    let interesting_parents = |hash: &[u8; 20]| {
+        walk.parents(hash)
+            .iter()
+            .filter(|parent| on_list.contains(*parent))
+            .copied()
+            .collect::<Vec<_>>()
+    };
+    let halfway =
+        |hash: &[u8; 20], weight: i64| !walk.is_treesame(hash) && (2 * weight - nr).abs() <= 1;
+
+    // Commits without parents on the list reach only themselves. Single-parent commits reach
+    // one more than their parent, while merges have to be counted.
+    let mut weights: HashMap<[u8; 20], i64> = HashMap::new();
+    let mut counted = 0;
+    for hash in &list {
+        let weight = match interesting_parents(hash).len() {
+            0 if walk.is_treesame(hash) => 0,
+            0 => {
+                counted += 1;
+                1
+            }
+            1 => -1,
+            _ => -2,
+        };
+        weights.insert(*hash, weight);
+    }
+    let finish = |best: [u8; 20], weights: &HashMap<[u8; 20], i64>| {
+        (vec![best], nr as usize, weights[&best] as usize)
+    };
+    for hash in &list {
+        if weights[hash] == -2 {
+            let weight = count_distance(&walk, hash, &on_list);
+            weights.insert(*hash, weight);
+            if !find_all && halfway(hash, weight) {
+                return Ok(finish(*hash, &weights));
+            }
+            counted += 1;
+        }
+    }
+    while counted < nr {
+        for hash in &list {
+            if weights[hash] >= 0 {
+                continue;
+            }
+            let Some(parent) = interesting_parents(hash)
+                .into_iter()
+                .find(|parent| weights[parent] >= 0)
+            else {
+                continue;
+            };
+            let weight = if walk.is_treesame(hash) {
+                weights[&parent]
+            } else {
+                counted += 1;
+                weights[&parent] + 1
+            };
+            weights.insert(*hash, weight);
+            if !find_all && halfway(hash, weight) {
+                return Ok(finish(*hash, &weights));
+            }
+        }
+    }
+
+    let distance = |hash: &[u8; 20]| weights[hash].min(nr - weights[hash]);
+    if !find_all {
+        let mut best = list.first().copied();
+        let mut best_distance = -1;
+        for hash in list.iter().filter(|hash| !walk.is_treesame(hash)) {
+            if distance(hash) > best_distance {
+                best = Some(*hash);
+                best_distance = distance(hash);
+            }
+        }
+        return Ok(match best {
+            Some(best) => finish(best, &weights),
+            None => (Vec::new(), nr as usize, 0),
+        });
+    }
+    let mut sorted: Vec<[u8; 20]> = list
+        .iter()
+        .filter(|hash| !walk.is_treesame(hash))
+        .copied()
+        .collect();
+    sorted.sort_by(|a, b| distance(b).cmp(&distance(a)).then(a.cmp(b)));
+    let reaches = sorted.first().map_or(0, |hash| weights[hash] as usize);
+    Ok((sorted, nr as usize, reaches))
+}
+
+/// How many commits of the list that change the limited paths `hash` reaches, itself included
+fn count_distance(walk: &RevWalk, hash: &[u8; 20], on_list: &HashSet<[u8; 20]>) -> i64 {
+    let mut seen = HashSet::new();
+    let mut stack = vec![*hash];
+    let mut count = 0;
+    while let Some(hash) = stack.pop() {
+        if !on_list.contains(&hash) || !seen.insert(hash) {
+            continue;
+        }
+        if !walk.is_treesame(&hash) {
+            count += 1;
+        }
+        stack.extend(walk.parents(&hash));
+    }
+    count
+}
+
+/// Picks the commit to test among the candidates, best first, moving away from the best one
+/// when it is skipped. Also returns the skipped candidates once any had to be passed over.
+fn managed_skipped(
+    candidates: &[[u8; 20]],
+    skipped: &[[u8; 20]],
+    bad: &[u8; 20],
+) -> (Option<[u8; 20]>, Vec<[u8; 20]>) {
+    match candidates.first() {
+        Some(first) if skipped.contains(first) => {}
+        first => return (first.copied(), Vec::new()),
+    }
+    let (tried, rest): (Vec<[u8; 20]>, Vec<[u8; 20]>) =
+        candidates.iter().partition(|hash| skipped.contains(hash));
+    (skip_away(&rest, bad), tried)
+}
+
+/// Picks a candidate some way down the list, as those next to a skipped commit are likely to
+/// be untestable too. The pseudo-random choice matches git's, so both pick the same commit.
+fn skip_away(candidates: &[[u8; 20]], bad: &[u8; 20]) -> Option<[u8; 20]> {
+    let count = candidates.len() as u32;
+    let prn = pseudo_random(count);
+    let index = (count.wrapping_mul(prn) / PRN_MODULO) as i64 * sqrti(prn) / sqrti(PRN_MODULO);
+    let index = usize::try_from(index).ok()?;
+    match candidates.get(index) {
+        Some(hash) if hash != bad => Some(*hash),
+        Some(_) if index > 0 => Some(candidates[index - 1]),
+        _ => candidates.first().copied(),
+    }
+}
+
+/// The `rand()` of `man 3 rand`, seeded with `count`
+fn pseudo_random(count: u32) -> u32 {
+    (count.wrapping_mul(1103515245).wrapping_add(12345) / 65536) % PRN_MODULO
+}
+
+/// An integer square root by Newton's method in single precision, as git computes it
+fn sqrti(value: u32) -> i64 {
+    if value == 0 {
+        return 0;
+    }
+    let value = value as f32;
+    let mut x = value;
+    loop {
+        let y = (x + value / x) / 2.0;
+        let d = (y - x).abs();
+        x = y;
+        if d < 0.5 {
+            return x as i64;
+        }
+    }
+}
+
+/// Roughly how many more steps a bisection of `all` commits takes after the next one
+fn estimate_steps(all: usize) -> usize {
+    if all < 3 {
+        return 0;
+    }
+    let n = all.ilog2() as usize;
+    let e = 1 << n;
+    let x = all - e;
+    if e < 3 * x {
+        n
+    } else {
+        n - 1
+    }
+}
+
+/// Reports that only skipped commits are left, listing those the first bad commit could be, and
+/// logs every commit between the good ones and the bad one as possibly the first bad one
+fn only_skipped(
+    terms: &Terms,
+    bad: &[u8; 20],
+    goods: &[[u8; 20]],
+    tried: &[[u8; 20]],
+    bad_too: bool,
+    out: &mut impl Write,
+) -> anyhow::Result<Outcome> {
+    writeln!(
+        out,
+        "There are only 'skip'ped commits left to test.\nThe first {} commit could be any of:",
+        terms.bad
+    )?;
+    for hash in tried {
+        writeln!(out, "{}", hex::encode(hash))?;
+    }
+    if bad_too {
+        writeln!(out, "{}", hex::encode(bad))?;
+    }
+    writeln!(out, "We cannot bisect more!")?;
+
+    let mut entry = String::from("# only skipped commits left to test\n");
+    let revisions = Revisions {
+        include: vec![*bad],
+        exclude: goods.to_vec(),
+        ..Revisions::default()
+    };
+    let mut walk = RevWalk::new(&revisions, WalkOptions::default())?;
+    while let Some(hash) = walk.next()? {
+        entry.push_str(&format!(
+            "# possible first {} commit: [{}] {}\n",
+            terms.bad,
+            hex::encode(hash),
+            pretty::subject(&walk.commit(&hash).message)
+        ));
+    }
+    append_log(&entry)?;
+    Ok(Outcome::OnlySkipped)
+}
+
+/// Detaches HEAD at the next commit to test
+fn checkout_candidate(hash: &[u8; 20], out: &mut impl Write) -> anyhow::Result<Outcome> {
+    let hex = hex::encode(hash);
+    fs::write(EXPECTED_REV, format!("{hex}\n")).context("Writing BISECT_EXPECTED_REV")?;
+    let switched = checkout::switch_to(Switch {
+        target: Some(hex.clone()),
+        detach: true,
+        quiet: true,
+        ..Switch::default()
+    });
+    if let Err(err) = switched {
+        eprintln!("error: {err:#}");
+        return Ok(Outcome::Failed);
+    }
+    let subject = pretty::subject(&Commit::read(hash)?.message);
+    writeln!(out, "[{hex}] {subject}")?;
+    Ok(Outcome::Continue)
+}
+
+/// Shows the first bad commit with a diffstat against its first parent
+fn show_commit(hash: &[u8; 20], out: &mut impl Write) -> anyhow::Result<()> {
+    let commit = Commit::read(hash)?;
+    let shown = ShownCommit {
+        hash,
+        commit: &commit,
+        parents: &commit.parents,
+        decorations: &[],
+    };
+    let opts = PrettyOptions {
+        format: Format::Medium,
+        abbrev_commit: false,
+        date_mode: DateMode::default(),
+        decorate: false,
+    };
+    write!(out, "{}", pretty::format_commit(&shown, &opts)?)?;
+    let Some(parent) = commit.parents.first() else {
+        return Ok(());
+    };
+    let parent_tree = Commit::read(parent)?.tree;
+    let pairs = tree_to_tree(
+        Some(&parent_tree),
+        Some(&commit.tree),
+        &Pathspec::new(&[]),
+        false,
+    )?;
+    if pairs.is_empty() {
+        return Ok(());
+    }
+    writeln!(out)?;
+    write_stat(out, &pairs, &PatchOptions::default())?;
+    write_summary(out, &pairs)
+}
+
+/// The paths recorded by `bisect start`
+fn read_names() -> anyhow::Result<Vec<String>> {
+    let content = fs::read_to_string(NAMES).unwrap_or_default();
+    let mut paths = Vec::new();
+    for line in content.lines() {
+        let args = quote::split_shell(line).context("Badly quoted content in BISECT_NAMES")?;
+        paths.extend(args.into_iter().filter(|arg| arg != "--"));
+    }
+    Ok(paths)
+}
+
+fn resolve_commit(rev: &str) -> anyhow::Result<[u8; 20]> {
+    revision::peel_to_commit(&revision::resolve(rev)?)
+}
+
+/// Each argument quoted for the shell after a space, as the log records command lines
+fn quote_args(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| format!(" {}", quote::shell(arg)))
+        .collect()
+}
+
+fn append_log(entry: &str) -> anyhow::Result<()> {
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(LOG)
+        .context("Opening BISECT_LOG")?;
+    log.write_all(entry.as_bytes())
+        .context("Writing BISECT_LOG")
+}
+
+/// Removes the refs and files of the bisection, `BISECT_START` last so that a bisection
+/// interrupted while cleaning up still counts as one
+fn clean_state() -> anyhow::Result<()> {
+    for dir in [".git/refs/bisect", ".git/logs/refs/bisect"] {
+        if Path::new(dir).exists() {
+            fs::remove_dir_all(dir).with_context(|| format!("Removing {dir}"))?;
+        }
+    }
+    for path in [
+        EXPECTED_REV,
+        ANCESTORS_OK,
+        LOG,
+        NAMES,
+        RUN,
+        TERMS,
+        ".git/BISECT_FIRST_PARENT",
+        ".git/BISECT_HEAD",
+        START,
+    ] {
+        worktree::remove_if_exists(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,1483 @@
+//! Attribution of each line of a file to the commit that introduced it, after git's blame
+//! scoreboard.
+//!
+//! Every line starts out blamed on the commit being annotated. Taking commits newest first, each
+//! one passes the lines it shares with a parent's version of the file on to that parent and
+//! keeps those it added. The parent's version is the file at the same path or, failing that,
+//! the file it was renamed from. With `-M` the lines left are also looked for elsewhere in the
+//! parent's version, to find moved lines, and with `-C` in the parent's other files, to find
+//! copied ones. Commits given to `--ignore-rev` even pass the lines they changed on, each to
+//! the line of the parent's version that looks most like it.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+    fs,
+    io::Write,
+    rc::Rc,
+};
+
+use anyhow::Context;
+
+use crate::{
+    date::{self, DateMode},
+    diff::{
+        diff_lines,
+        files::{tree_to_index, tree_to_tree},
+        patch::{FilePair, NULL_HASH},
+        rename::{self, RenameOptions},
+        split_lines, DiffOptions,
+    },
+    index::{mode_from_metadata, Index, IndexEntry},
+    line_range,
+    object::{
+        commit::{Commit, Signature},
+        read::read_object_of_kind,
+        write::hash_object,
+        ObjectKind, MODE_GITLINK,
+    },
+    pathspec::Pathspec,
+    quote, refs, revision, revwalk, worktree,
+};
+
+/// Alphanumeric characters lines need beyond the first to be blamed as moved within a file
+pub(crate) const MOVE_SCORE: u32 = 20;
+/// Alphanumeric characters lines need beyond the first to be blamed as copied from another file
+pub(crate) const COPY_SCORE: u32 = 40;
+/// Hex digits shown of each commit name: one more than usual, to leave room for the `^` of
+/// boundary commits
+const ABBREV: usize = 8;
+
+#[derive(Debug, Clone)]
+pub(crate) struct BlameOptions {
+    /// Look for lines moved within the file (`-M`)
+    pub(crate) moves: bool,
+    /// Look for lines copied from the files the same commit changed (1, `-C`), from any file
+    /// when the commit created the file (2, `-C -C`) or from any file at all (3, `-C -C -C`)
+    pub(crate) copies: u8,
+    pub(crate) move_score: u32,
+    pub(crate) copy_score: u32,
+    pub(crate) diff: DiffOptions,
+    /// Commits whose changes are passed through to their parents
+    pub(crate) ignore: HashSet<[u8; 20]>,
+    /// Blame root commits instead of showing them as boundaries
+    pub(crate) show_root: bool,
+}
+
+impl Default for BlameOptions {
+    fn default() -> BlameOptions {
+        BlameOptions {
+            moves: false,
+            copies: 0,
+            move_score: MOVE_SCORE,
+            copy_score: COPY_SCORE,
+            diff: DiffOptions::default(),
+            ignore: HashSet::new(),
+            show_root: false,
+        }
+    }
+}
+
+/// The version of a file to annotate
+#[derive(Debug)]
+pub(crate) struct Target<'a> {
+    /// The commit with the name it was given by, or `None` for the working tree
+    pub(crate) commit: Option<([u8; 20], &'a str)>,
+    /// Commits whose history is not dug into; lines reaching them are shown as boundaries
+    pub(crate) exclude: &'a [[u8; 20]],
+    pub(crate) path: &'a str,
+    /// `-L` arguments, the whole file if empty
+    pub(crate) ranges: &'a [String],
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Format {
+    /// Commit, author, date and line number before each line
+    #[default]
+    Default,
+    /// Commit details once per commit, for tools
+    Porcelain,
+    /// Commit details on every line
+    LinePorcelain,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct OutputOptions {
+    pub(crate) format: Format,
+    /// Mark lines passed on by ignored commits with `?`
+    pub(crate) mark_ignored: bool,
+    /// Mark lines ignored commits could not pass on with `*`
+    pub(crate) mark_unblamable: bool,
+}
+
+/// A file at a path in a commit that lines may be blamed on
+#[derive(Debug)]
+struct Origin {
+    commit: [u8; 20],
+    path: String,
+    blob: [u8; 20],
+    /// Entries waiting to be passed on to this origin's parents, by line in its version
+    suspects: Vec<Entry>,
+    /// The first parent's version the file was compared with, for porcelain output
+    previous: Option<usize>,
+    /// Lines ended up blamed on this origin
+    guilty: bool,
+}
+
+/// A run of lines of the annotated file blamed on one origin
+#[derive(Debug, Clone)]
+struct Entry {
+    /// First line in the annotated file, 0-based
+    lno: usize,
+    num_lines: usize,
+    /// First line in the suspect's version of the file
+    s_lno: usize,
+    suspect: usize,
+    /// Passed on by an ignored commit to a line that looked alike
+    ignored: bool,
+    /// Changed by an ignored commit, with nothing alike in its parents
+    unblamable: bool,
+}
+
+impl Entry {
+    /// Splits the lines from `len` on off into a new entry
+    fn split_at(&mut self, len: usize) -> Entry {
+        let tail = Entry {
+            lno: self.lno + len,
+            num_lines: self.num_lines - len,
+            s_lno: self.s_lno + len,
+            ..self.clone()
+        };
+        self.num_lines = len;
+        tail
+    }
+}
+
+/// An entry split around a block of lines found in another origin: the lines before the block,
+/// the block, blamed on the other origin, and the lines after it
+type Split = [Option<Entry>; 3];
+
+/// The blame of a file, as computed by [`blame`]
+pub(crate) struct Blame {
+    path: String,
+    content: Rc<Vec<u8>>,
+    /// Where each line of `content` starts, followed by its length
+    line_starts: Vec<usize>,
+    /// Alphanumeric characters before each line, for scoring blocks of lines
+    alnums: Vec<u32>,
+    opts: BlameOptions,
+    origins: Vec<Origin>,
+    /// The origins of each commit, most recently used first
+    by_commit: HashMap<[u8; 20], Vec<usize>>,
+    commits: HashMap<[u8; 20], Commit>,
+    blobs: HashMap<[u8; 20], Rc<Vec<u8>>>,
+    /// Commits with suspects, newest first, then in the order they were queued
+    queue: BinaryHeap<(i64, Reverse<u64>, [u8; 20])>,
+    queued: u64,
+    /// Commits lines stop at: the excluded ones and their ancestors, and roots
+    boundaries: HashSet<[u8; 20]>,
+    /// The index with the working tree version of the file, as the tree of the commit standing
+    /// in for the working tree
+    index: Option<Index>,
+    /// Entries blamed for good
+    entries: Vec<Entry>,
+}
+
+/// Blames the lines of a file
+pub(crate) fn blame(target: &Target, opts: BlameOptions) -> anyhow::Result<Blame> {
+    let mut blame = Blame {
+        path: target.path.to_string(),
+        content: Rc::default(),
+        line_starts: Vec::new(),
+        alnums: Vec::new(),
+        opts,
+        origins: Vec::new(),
+        by_commit: HashMap::new(),
+        commits: HashMap::new(),
+        blobs: HashMap::new(),
+        queue: BinaryHeap::new(),
+        queued: 0,
+        boundaries: HashSet::new(),
+        index: None,
+        entries: Vec::new(),
+    };
+    let (commit, blob) = match target.commit {
+        Some((commit, name)) => {
+            let tree = blame.commit(&commit)?.tree;
+            let blob = revision::lookup_path(&tree, target.path)
+                .ok()
+                .filter(|blob| revision::kind_of(blob).ok() == Some(ObjectKind::Blob))
+                .with_context(|| format!("no such path {} in {name}", target.path))?;
+            (commit, blob)
+        }
+        None => (NULL_HASH, blame.working_tree_commit(target.path)?),
+    };
+    for tip in target.exclude {
+        blame.boundaries.extend(revwalk::ancestors(tip)?);
+    }
+
+    let origin = blame.get_origin(&commit, target.path);
+    blame.origins[origin].blob = blob;
+    blame.content = blame.blob(origin)?;
+    let lines = split_lines(&blame.content);
+    blame.line_starts = std::iter::once(0)
+        .chain(lines.iter().scan(0, |end, line| {
+            *end += line.len();
+            Some(*end)
+        }))
+        .collect();
+    blame.alnums = std::iter::once(0)
+        .chain(lines.iter().scan(0, |count, line| {
+            *count += line.iter().filter(|c| c.is_ascii_alphanumeric()).count() as u32;
+            Some(*count)
+        }))
+        .collect();
+
+    let ranges = if target.ranges.is_empty() {
+        vec![(0, lines.len())]
+    } else {
+        let mut anchor = 1;
+        let mut ranges = Vec::new();
+        for spec in target.ranges {
+            let (start, end) = line_range::resolve(spec, &lines, anchor, target.path)?;
+            ranges.push((start, end));
+            anchor = end + 1;
+        }
+        line_range::merge(ranges)
+    };
+    blame.origins[origin].suspects = ranges
+        .into_iter()
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| Entry {
+            lno: start,
+            num_lines: end - start,
+            s_lno: start,
+            suspect: origin,
+            ignored: false,
+            unblamable: false,
+        })
+        .collect();
+    blame.push(&commit)?;
+
+    blame.assign()?;
+    blame.entries.sort_by_key(|entry| entry.lno);
+    blame.coalesce();
+    Ok(blame)
+}
+
+/// The commits listed in ignore-revs files (`blame.ignoreRevsFile`, then `files`; an empty name
+/// drops the ones read so far) and the ones named in `revs`
+pub(crate) fn ignore_list(files: &[String], revs: &[String]) -> anyhow::Result<HashSet<[u8; 20]>> {
+    let config = crate::config::read_repo_config();
+    let mut ignore = HashSet::new();
+    for file in config
+        .get("blame.ignoreRevsFile")
+        .into_iter()
+        .chain(files.iter().map(String::as_str))
+    {
+        if file.is_empty() {
+            ignore.clear();
+            continue;
+        }
+        let content = fs::read_to_string(file)
+            .with_context(|| format!("could not open object name list: {file}"))?;
+        for line in content.lines() {
+            let name = line.split('#').next().unwrap_or("").trim();
+            if name.is_empty() {
+                continue;
+            }
+            let hash: [u8; 20] = hex::decode(name)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .with_context(|| format!("invalid object name: {name}"))?;
+            // Names of anything but commits (and tags of them) are skipped
+            if let Ok(commit) = revision::peel_to_commit(&hash) {
+                ignore.insert(commit);
+            }
+        }
+    }
+    for rev in revs {
+        let commit = revision::resolve(rev)
+            .and_then(|hash| revision::peel_to_commit(&hash))
+            .ok()
+            .with_context(|| format!("cannot find revision {rev} to ignore"))?;
+        ignore.insert(commit);
+    }
+    Ok(ignore)
+}
+
+/// Where a line an ignored commit changed is blamed: on a line of the parent's version that looks
+/// like it, or on the commit itself
+#[derive(Debug, Clone, Copy)]
+struct Guess {
+    /// Blame the parent's line `line` instead of the target's
+    parent: bool,
+    line: usize,
+}
+
+/// Entries being passed from a target to one parent, a run of changed lines at a time
+struct Chunking {
+    /// Entries not looked at yet, by line
+    pending: VecDeque<Entry>,
+    /// Entries the target keeps
+    kept: Vec<Entry>,
+    /// Entries passed on to the parent
+    passed: Vec<Entry>,
+}
+
+impl Chunking {
+    /// Takes the entries starting before line `end` of the target, cut off at `end`. The parts
+    /// after it go back in front of the entries left, in order.
+    fn take_before(&mut self, end: usize) -> Vec<Entry> {
+        let mut taken = Vec::new();
+        let mut tails = Vec::new();
+        while self.pending.front().is_some_and(|e| e.s_lno < end) {
+            let mut e = self.pending.pop_front().unwrap();
+            if e.s_lno + e.num_lines > end {
+                tails.push(e.split_at(end - e.s_lno));
+            }
+            taken.push(e);
+        }
+        for tail in tails.into_iter().rev() {
+            self.pending.push_front(tail);
+        }
+        taken
+    }
+
+    /// Passes the entries before line `tlno` of the target to the parent, where they are
+    /// `offset` lines further down
+    fn pass_unchanged(&mut self, tlno: usize, offset: isize, parent: usize) {
+        for mut e in self.take_before(tlno) {
+            e.suspect = parent;
+            e.s_lno = e.s_lno.wrapping_add_signed(offset);
+            self.passed.push(e);
+        }
+    }
+
+    /// Keeps the entries for the changed lines `tlno..same` with the target, or with `guesses`
+    /// passes the ones that look like parent lines on to those
+    fn keep_changed(&mut self, tlno: usize, same: usize, parent: usize, guesses: Option<&[Guess]>) {
+        for e in self.take_before(same) {
+            match guesses {
+                Some(guesses) => {
+                    let guesses = &guesses[e.s_lno - tlno..];
+                    self.ignore(e, parent, guesses)
+                }
+                None => self.kept.push(e),
+            }
+        }
+    }
+
+    /// Splits an entry of an ignored commit into runs of lines that go to consecutive parent
+    /// lines, or that stay with the commit as unblamable
+    fn ignore(&mut self, mut e: Entry, parent: usize, guesses: &[Guess]) {
+        let count = e.num_lines;
+        let mut len = 1;
+        for i in 0..count {
+            if i + 1 < count
+                && guesses[i].parent == guesses[i + 1].parent
+                && guesses[i].line + 1 == guesses[i + 1].line
+            {
+                len += 1;
+                continue;
+            }
+            let rest = (i + 1 < count).then(|| e.split_at(len));
+            if guesses[i].parent {
+                e.ignored = true;
+                e.suspect = parent;
+                e.s_lno = guesses[i + 1 - len].line;
+                self.passed.push(e);
+            } else {
+                e.unblamable = true;
+                self.kept.push(e);
+            }
+            let Some(rest) = rest else {
+                break;
+            };
+            e = rest;
+            len = 1;
+        }
+    }
+}
+
+impl Blame {
+    fn commit(&mut self, hash: &[u8; 20]) -> anyhow::Result<&Commit> {
+        if !self.commits.contains_key(hash) {
+            self.commits.insert(*hash, Commit::read(hash)?);
+        }
+        Ok(&self.commits[hash])
+    }
+
+    fn blob(&mut self, origin: usize) -> anyhow::Result<Rc<Vec<u8>>> {
+        let hash = self.origins[origin].blob;
+        if let Some(content) = self.blobs.get(&hash) {
+            return Ok(Rc::clone(content));
+        }
+        let content = Rc::new(read_object_of_kind(&hex::encode(hash), ObjectKind::Blob)?);
+        self.blobs.insert(hash, Rc::clone(&content));
+        Ok(content)
+    }
+
+    /// Sets up the commit standing in for the working tree: its parents are HEAD and any commits
+    /// being merged, its tree the index with the working tree version of `path`. Returns that
+    /// version's blob.
+    fn working_tree_commit(&mut self, path: &str) -> anyhow::Result<[u8; 20]> {
+        let mut parents: Vec<[u8; 20]> = refs::head_commit()?.into_iter().collect();
+        if let Ok(merge_heads) = fs::read_to_string(".git/MERGE_HEAD") {
+            for line in merge_heads.lines() {
+                parents.push(crate::object::commit::parse_hash(line)?);
+            }
+        }
+        let mut index = Index::read()?;
+        let mut known = index.get(path, 0).is_some() || !index.stages(path).is_empty();
+        for parent in &parents {
+            let tree = self.commit(parent)?.tree;
+            known |= revision::lookup_path(&tree, path)
+                .is_ok_and(|blob| revision::kind_of(&blob).ok() == Some(ObjectKind::Blob));
+        }
+        anyhow::ensure!(known, "no such path '{path}' in HEAD");
+
+        let metadata =
+            fs::symlink_metadata(path).with_context(|| format!("Cannot lstat '{path}'"))?;
+        let content = worktree::read_file(std::path::Path::new(path))?;
+        let blob = hash_object(ObjectKind::Blob, &content, false)?;
+        self.blobs.insert(blob, Rc::new(content));
+        index.add(IndexEntry::new(
+            path.to_string(),
+            mode_from_metadata(&metadata),
+            blob,
+            0,
+        ));
+        self.index = Some(index);
+
+        let now = chrono::Local::now();
+        let ident = Signature {
+            name: String::from("Not Committed Yet"),
+            email: String::from("not.committed.yet"),
+            time: now.timestamp(),
+            tz_offset: now.offset().local_minus_utc() / 60,
+        };
+        let commit = Commit {
+            tree: NULL_HASH,
+            parents,
+            author: ident.clone(),
+            committer: ident,
+            message: format!("Version of {path} from {path}\n"),
+        };
+        self.commits.insert(NULL_HASH, commit);
+        Ok(blob)
+    }
+
+    /// The origin for `path` in `commit`, made if there is none yet
+    fn get_origin(&mut self, commit: &[u8; 20], path: &str) -> usize {
+        let list = self.by_commit.entry(*commit).or_default();
+        if let Some(pos) = list.iter().position(|&o| self.origins[o].path == path) {
+            let origin = list.remove(pos);
+            list.insert(0, origin);
+            return origin;
+        }
+        self.origins.push(Origin {
+            commit: *commit,
+            path: path.to_string(),
+            blob: NULL_HASH,
+            suspects: Vec::new(),
+            previous: None,
+            guilty: false,
+        });
+        let origin = self.origins.len() - 1;
+        list.insert(0, origin);
+        origin
+    }
+
+    fn push(&mut self, commit: &[u8; 20]) -> anyhow::Result<()> {
+        let time = self.commit(commit)?.committer.time;
+        self.queued += 1;
+        self.queue.push((time, Reverse(self.queued), *commit));
+        Ok(())
+    }
+
+    /// Gives entries, sorted by line, to an origin, queueing its commit if nothing else of it
+    /// is waiting
+    fn queue_blames(&mut self, origin: usize, entries: Vec<Entry>) -> anyhow::Result<()> {
+        if !self.origins[origin].suspects.is_empty() {
+            let suspects = std::mem::take(&mut self.origins[origin].suspects);
+            self.origins[origin].suspects = merge(suspects, entries);
+            return Ok(());
+        }
+        let commit = self.origins[origin].commit;
+        let waiting = self.by_commit[&commit]
+            .iter()
+            .any(|&o| !self.origins[o].suspects.is_empty());
+        self.origins[origin].suspects = entries;
+        if !waiting {
+            self.push(&commit)?;
+        }
+        Ok(())
+    }
+
+    /// Takes commits newest first, passing the blame of each of their origins on to parents
+    /// and keeping what is left
+    fn assign(&mut self) -> anyhow::Result<()> {
+        while let Some((_, _, commit)) = self.queue.pop() {
+            while let Some(origin) = self.by_commit[&commit]
+                .iter()
+                .copied()
+                .find(|&o| !self.origins[o].suspects.is_empty())
+            {
+                if !self.boundaries.contains(&commit) {
+                    self.pass_blame(origin)?;
+                }
+                if self.commit(&commit)?.parents.is_empty() && !self.opts.show_root {
+                    self.boundaries.insert(commit);
+                }
+                let remaining = std::mem::take(&mut self.origins[origin].suspects);
+                if !remaining.is_empty() {
+                    self.origins[origin].guilty = true;
+                    self.entries.extend(remaining);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes from a parent to a commit; the working tree stand-in is compared by its index
+    fn tree_changes(
+        &mut self,
+        parent: &[u8; 20],
+        commit: &[u8; 20],
+        pathspec: &Pathspec,
+        unchanged: bool,
+    ) -> anyhow::Result<Vec<FilePair>> {
+        let parent_tree = self.commit(parent)?.tree;
+        if *commit == NULL_HASH {
+            let index = self
+                .index
+                .as_ref()
+                .context("No index for the working tree")?;
+            return Ok(tree_to_index(Some(&parent_tree), index, pathspec, unchanged)?.0);
+        }
+        let tree = self.commit(commit)?.tree;
+        tree_to_tree(Some(&parent_tree), Some(&tree), pathspec, unchanged)
+    }
+
+    /// The parent's version of an origin at the same path, if it has one
+    fn find_origin(&mut self, parent: &[u8; 20], origin: usize) -> anyhow::Result<Option<usize>> {
+        let path = self.origins[origin].path.clone();
+        if let Some(list) = self.by_commit.get(parent) {
+            if let Some(&found) = list.iter().find(|&&o| self.origins[o].path == path) {
+                return Ok(Some(found));
+            }
+        }
+        let commit = self.origins[origin].commit;
+        let pairs = self.tree_changes(
+            parent,
+            &commit,
+            &Pathspec::new(std::slice::from_ref(&path)),
+            false,
+        )?;
+        if pairs.is_empty() {
+            let porigin = self.get_origin(parent, &path);
+            self.origins[porigin].blob = self.origins[origin].blob;
+            return Ok(Some(porigin));
+        }
+        // The pathspec also matches files below a directory of the same name
+        let pair = pairs
+            .iter()
+            .find(|pair| {
+                pair.old
+                    .as_ref()
+                    .or(pair.new.as_ref())
+                    .is_some_and(|side| side.path == path)
+            })
+            .context("internal error in blame::find_origin")?;
+        match (pair.status(), &pair.old) {
+            ('M', Some(old)) => {
+                let blob = old.hash;
+                let porigin = self.get_origin(parent, &path);
+                self.origins[porigin].blob = blob;
+                Ok(Some(porigin))
+            }
+            ('A' | 'T', _) => Ok(None),
+            (status, _) => anyhow::bail!("internal error in blame::find_origin ({status})"),
+        }
+    }
+
+    /// The parent's version of an origin that was renamed, if rename detection finds one
+    fn find_rename(&mut self, parent: &[u8; 20], origin: usize) -> anyhow::Result<Option<usize>> {
+        let path = self.origins[origin].path.clone();
+        let commit = self.origins[origin].commit;
+        let pairs = self
+            .tree_changes(parent, &commit, &Pathspec::default(), false)?
+            .into_iter()
+            .filter(|pair| match (&pair.old, &pair.new) {
+                (Some(_), None) => true,
+                (None, Some(new)) => new.path == path,
+                _ => false,
+            })
+            .collect();
+        let limit = crate::config::read_repo_config()
+            .get("diff.renameLimit")
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(rename::DEFAULT_LIMIT);
+        let opts = RenameOptions {
+            copies: false,
+            min_score: rename::DEFAULT_SCORE,
+            limit,
+        };
+        let renamed = rename::detect(pairs, &opts)?.into_iter().find_map(|pair| {
+            match (pair.rename, pair.old, pair.new) {
+                (Some(_), Some(old), Some(new)) if new.path == path => Some(old),
+                _ => None,
+            }
+        });
+        Ok(renamed.map(|old| {
+            let porigin = self.get_origin(parent, &old.path);
+            self.origins[porigin].blob = old.hash;
+            porigin
+        }))
+    }
+
+    /// Passes the blame of an origin's entries on to the parents' versions of it
+    fn pass_blame(&mut self, origin: usize) -> anyhow::Result<()> {
+        let commit = self.origins[origin].commit;
+        let parents = self.commit(&commit)?.parents.clone();
+        let mut blamed = Vec::new();
+        let mut small = Vec::new();
+        self.pass_blame_to(origin, &parents, &mut blamed, &mut small)?;
+
+        blamed.sort_by_key(|entry| (entry.suspect, entry.s_lno));
+        let mut rest = blamed.into_iter().peekable();
+        while let Some(first) = rest.next() {
+            let suspect = first.suspect;
+            let mut group = vec![first];
+            while let Some(entry) = rest.next_if(|entry| entry.suspect == suspect) {
+                group.push(entry);
+            }
+            self.queue_blames(suspect, group)?;
+        }
+        if !small.is_empty() {
+            small.append(&mut self.origins[origin].suspects);
+            self.origins[origin].suspects = small;
+        }
+        Ok(())
+    }
+
+    /// The steps of [`Blame::pass_blame`] up to the point where nothing is left to pass on;
+    /// lines found moved or copied go to `blamed`, and blocks too small to look for to `small`
+    fn pass_blame_to(
+        &mut self,
+        origin: usize,
+        parents: &[[u8; 20]],
+        blamed: &mut Vec<Entry>,
+        small: &mut Vec<Entry>,
+    ) -> anyhow::Result<()> {
+        if parents.is_empty() {
+            return Ok(());
+        }
+        let commit = self.origins[origin].commit;
+        let mut porigins: Vec<Option<usize>> = vec![None; parents.len()];
+        // Parents keeping the path come first, renames are only looked for when none does
+        for pass in 0..2 {
+            for (i, parent) in parents.iter().enumerate() {
+                if porigins[i].is_some() {
+                    continue;
+                }
+                let found = if pass == 0 {
+                    self.find_origin(parent, origin)?
+                } else {
+                    self.find_rename(parent, origin)?
+                };
+                let Some(porigin) = found else {
+                    continue;
+                };
+                let blob = self.origins[porigin].blob;
+                if blob == self.origins[origin].blob {
+                    self.pass_whole_blame(origin, porigin)?;
+                    return Ok(());
+                }
+                let same = porigins[..i]
+                    .iter()
+                    .flatten()
+                    .any(|&other| self.origins[other].blob == blob);
+                if !same {
+                    porigins[i] = Some(porigin);
+                }
+            }
+        }
+
+        for &porigin in porigins.iter().flatten() {
+            if self.origins[origin].previous.is_none() {
+                self.origins[origin].previous = Some(porigin);
+            }
+            self.pass_to_parent(origin, porigin, false)?;
+            if self.origins[origin].suspects.is_empty() {
+                return Ok(());
+            }
+        }
+
+        if self.opts.ignore.contains(&commit) {
+            for &porigin in porigins.iter().flatten() {
+                self.pass_to_parent(origin, porigin, true)?;
+                if self.origins[origin].suspects.is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+
+        if self.opts.moves {
+            let mut suspects = std::mem::take(&mut self.origins[origin].suspects);
+            self.filter_small(small, &mut suspects, self.opts.move_score);
+            self.origins[origin].suspects = suspects;
+            for &porigin in porigins.iter().flatten() {
+                if self.origins[origin].suspects.is_empty() {
+                    break;
+                }
+                self.find_move_in_parent(blamed, small, origin, porigin)?;
+            }
+        }
+
+        if self.opts.copies > 0 {
+            let (move_score, copy_score) = (self.opts.move_score, self.opts.copy_score);
+            let mut suspects = std::mem::take(&mut self.origins[origin].suspects);
+            if copy_score > move_score {
+                self.filter_small(small, &mut suspects, copy_score);
+            } else if copy_score < move_score {
+                suspects = merge(suspects, std::mem::take(small));
+                self.filter_small(small, &mut suspects, copy_score);
+            }
+            self.origins[origin].suspects = suspects;
+            for (parent, porigin) in parents.iter().zip(&porigins) {
+                if self.origins[origin].suspects.is_empty() {
+                    break;
+                }
+                self.find_copy_in_parent(blamed, small, origin, parent, *porigin)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands all entries to a parent whose version is the same
+    fn pass_whole_blame(&mut self, origin: usize, porigin: usize) -> anyhow::Result<()> {
+        let mut entries = std::mem::take(&mut self.origins[origin].suspects);
+        for entry in &mut entries {
+            entry.suspect = porigin;
+        }
+        self.queue_blames(porigin, entries)
+    }
+
+    /// Passes the entries for lines the parent's version has too on to it. With `ignore` the
+    /// entries for changed lines are passed on as well, to the parent lines most like them.
+    fn pass_to_parent(&mut self, target: usize, parent: usize, ignore: bool) -> anyhow::Result<()> {
+        if self.origins[target].suspects.is_empty() {
+            return Ok(());
+        }
+        let parent_content = self.blob(parent)?;
+        let target_content = self.blob(target)?;
+        let parent_lines = split_lines(&parent_content);
+        let target_lines = split_lines(&target_content);
+        let mut fingerprints = ignore.then(|| {
+            let all = |lines: &[&[u8]]| -> Vec<Fingerprint> {
+                lines.iter().map(|line| fingerprint(line)).collect()
+            };
+            (all(&parent_lines), all(&target_lines))
+        });
+
+        let mut chunking = Chunking {
+            pending: std::mem::take(&mut self.origins[target].suspects).into(),
+            kept: Vec::new(),
+            passed: Vec::new(),
+        };
+        let mut offset = 0;
+        for change in diff_lines(&parent_lines, &target_lines, &self.opts.diff) {
+            let tlno = change.new_start;
+            let same = change.new_start + change.new_len;
+            chunking.pass_unchanged(tlno, offset, parent);
+            let guesses = match &mut fingerprints {
+                Some((parent_prints, target_prints))
+                    if chunking.pending.front().is_some_and(|e| e.s_lno < same) =>
+                {
+                    Some(guess_line_blames(
+                        parent_prints,
+                        target_prints,
+                        (change.old_start, change.old_len),
+                        (tlno, change.new_len),
+                    ))
+                }
+                _ => None,
+            };
+            chunking.keep_changed(tlno, same, parent, guesses.as_deref());
+            offset = (change.old_start + change.old_len) as isize - same as isize;
+        }
+        chunking.pass_unchanged(usize::MAX, offset, parent);
+
+        let Chunking {
+            pending,
+            mut kept,
+            mut passed,
+        } = chunking;
+        kept.extend(pending);
+        if ignore {
+            passed.sort_by_key(|entry| entry.s_lno);
+        }
+        self.origins[target].suspects = kept;
+        self.queue_blames(parent, passed)
+    }
+
+    /// Score of a block of lines: one more than its alphanumeric characters
+    fn score(&self, entry: &Entry) -> u32 {
+        1 + self.alnums[entry.lno + entry.num_lines] - self.alnums[entry.lno]
+    }
+
+    /// Moves the entries scoring at most `min` from `entries` to the front of `small`
+    fn filter_small(&self, small: &mut Vec<Entry>, entries: &mut Vec<Entry>, min: u32) {
+        let (low, high): (Vec<Entry>, Vec<Entry>) = std::mem::take(entries)
+            .into_iter()
+            .partition(|entry| self.score(entry) <= min);
+        *entries = high;
+        small.splice(0..0, low);
+    }
+
+    /// The lines of the annotated file an entry covers
+    fn entry_lines(&self, entry: &Entry) -> Vec<&[u8]> {
+        (entry.lno..entry.lno + entry.num_lines)
+            .map(|line| &self.content[self.line_starts[line]..self.line_starts[line + 1]])
+            .collect()
+    }
+
+    /// The best-scoring block of an entry's lines that also appears in `parent_lines`
+    fn find_copy_in_blob(&self, entry: &Entry, parent: usize, parent_lines: &[&[u8]]) -> Split {
+        let lines = self.entry_lines(entry);
+        let mut best: Split = [None, None, None];
+        let (mut plno, mut tlno) = (0, 0);
+        let consider = |tlno: usize, plno: usize, same: usize, best: &mut Split| {
+            if entry.num_lines <= tlno || tlno >= same {
+                return;
+            }
+            let potential =
+                split_overlap(entry, tlno + entry.s_lno, plno, same + entry.s_lno, parent);
+            self.copy_split_if_better(best, potential);
+        };
+        for change in diff_lines(parent_lines, &lines, &self.opts.diff) {
+            consider(tlno, plno, change.new_start, &mut best);
+            plno = change.old_start + change.old_len;
+            tlno = change.new_start + change.new_len;
+        }
+        consider(tlno, plno, entry.num_lines, &mut best);
+        best
+    }
+
+    fn copy_split_if_better(&self, best: &mut Split, potential: Split) {
+        let Some(found) = &potential[1] else {
+            return;
+        };
+        if best[1]
+            .as_ref()
+            .is_some_and(|current| self.score(found) < self.score(current))
+        {
+            return;
+        }
+        *best = potential;
+    }
+
+    /// Looks for the remaining lines elsewhere in the parent's version of the file
+    fn find_move_in_parent(
+        &mut self,
+        blamed: &mut Vec<Entry>,
+        small: &mut Vec<Entry>,
+        target: usize,
+        parent: usize,
+    ) -> anyhow::Result<()> {
+        let mut unblamed = std::mem::take(&mut self.origins[target].suspects);
+        if unblamed.is_empty() {
+            return Ok(());
+        }
+        let content = self.blob(parent)?;
+        let lines = split_lines(&content);
+        let mut leftover = Vec::new();
+        while !unblamed.is_empty() {
+            let mut rest = Vec::new();
+            for entry in unblamed {
+                let split = self.find_copy_in_blob(&entry, parent, &lines);
+                match &split[1] {
+                    Some(found) if self.opts.move_score < self.score(found) => {
+                        split_blame(blamed, &mut rest, split);
+                    }
+                    _ => leftover.push(entry),
+                }
+            }
+            unblamed = rest;
+            self.filter_small(small, &mut unblamed, self.opts.move_score);
+        }
+        self.origins[target].suspects = leftover;
+        Ok(())
+    }
+
+    /// Looks for the remaining lines in other files of the parent: the ones the commit changed,
+    /// or with `-C -C` (for new files) and `-C -C -C` all of them
+    fn find_copy_in_parent(
+        &mut self,
+        blamed: &mut Vec<Entry>,
+        small: &mut Vec<Entry>,
+        target: usize,
+        parent: &[u8; 20],
+        porigin: Option<usize>,
+    ) -> anyhow::Result<()> {
+        let mut unblamed = std::mem::take(&mut self.origins[target].suspects);
+        if unblamed.is_empty() {
+            return Ok(());
+        }
+        let porigin_path = porigin.map(|p| self.origins[p].path.clone());
+        let harder = self.opts.copies >= 3
+            || (self.opts.copies >= 2
+                && porigin_path.as_deref() != Some(self.origins[target].path.as_str()));
+        let commit = self.origins[target].commit;
+        let pairs = self.tree_changes(parent, &commit, &Pathspec::default(), harder)?;
+
+        let mut leftover = Vec::new();
+        while !unblamed.is_empty() {
+            let mut splits: Vec<Split> = vec![[None, None, None]; unblamed.len()];
+            for pair in &pairs {
+                let Some(old) = &pair.old else {
+                    continue;
+                };
+                // The parent's version of the file itself was searched for moves already
+                if old.mode == MODE_GITLINK || porigin_path.as_deref() == Some(old.path.as_str()) {
+                    continue;
+                }
+                let norigin = self.get_origin(parent, &old.path);
+                self.origins[norigin].blob = old.hash;
+                let content = self.blob(norigin)?;
+                let lines = split_lines(&content);
+                for (entry, best) in unblamed.iter().zip(&mut splits) {
+                    let potential = self.find_copy_in_blob(entry, norigin, &lines);
+                    self.copy_split_if_better(best, potential);
+                }
+            }
+            let mut rest = Vec::new();
+            for (entry, split) in unblamed.into_iter().zip(splits) {
+                match &split[1] {
+                    Some(found) if self.opts.copy_score < self.score(found) => {
+                        split_blame(blamed, &mut rest, split);
+                    }
+                    _ => leftover.push(entry),
+                }
+            }
+            unblamed = rest;
+            self.filter_small(small, &mut unblamed, self.opts.copy_score);
+        }
+        self.origins[target].suspects = leftover;
+        Ok(())
+    }
+
+    /// Joins neighbouring entries that continue each other in the same origin
+    fn coalesce(&mut self) {
+        let mut coalesced: Vec<Entry> = Vec::with_capacity(self.entries.len());
+        for entry in std::mem::take(&mut self.entries) {
+            match coalesced.last_mut() {
+                Some(last)
+                    if last.suspect == entry.suspect
+                        && last.s_lno + last.num_lines == entry.s_lno
+                        && last.lno + last.num_lines == entry.lno
+                        && last.ignored == entry.ignored
+                        && last.unblamable == entry.unblamable =>
+                {
+                    last.num_lines += entry.num_lines;
+                }
+                _ => coalesced.push(entry),
+            }
+        }
+        self.entries = coalesced;
+    }
+
+    fn line(&self, lno: usize) -> &[u8] {
+        &self.content[self.line_starts[lno]..self.line_starts[lno + 1]]
+    }
+
+    /// Writes the blame in the chosen format
+    pub(crate) fn write(&self, out: &mut impl Write, opts: &OutputOptions) -> anyhow::Result<()> {
+        match opts.format {
+            Format::Default => self.write_default(out, opts),
+            Format::Porcelain => self.write_porcelain(out, false),
+            Format::LinePorcelain => self.write_porcelain(out, true),
+        }
+    }
+
+    fn write_default(&self, out: &mut impl Write, opts: &OutputOptions) -> anyhow::Result<()> {
+        let show_name = self
+            .entries
+            .iter()
+            .any(|entry| self.origins[entry.suspect].path != self.path);
+        let width = |text: &str| text.chars().count();
+        let longest_file = self
+            .entries
+            .iter()
+            .map(|entry| width(&self.origins[entry.suspect].path))
+            .max()
+            .unwrap_or(0);
+        let longest_author = self
+            .entries
+            .iter()
+            .map(|entry| {
+                width(
+                    &self.commits[&self.origins[entry.suspect].commit]
+                        .author
+                        .name,
+                )
+            })
+            .max()
+            .unwrap_or(0);
+        let last_line = self
+            .entries
+            .iter()
+            .map(|entry| entry.lno + entry.num_lines)
+            .max()
+            .unwrap_or(0);
+        let digits = last_line.to_string().len();
+
+        for entry in &self.entries {
+            let origin = &self.origins[entry.suspect];
+            let author = &self.commits[&origin.commit].author;
+            let hex = hex::encode(origin.commit);
+            let date = date::format(author.time, author.tz_offset, DateMode::Iso);
+            for i in 0..entry.num_lines {
+                let mut length = ABBREV;
+                let mut prefix = String::new();
+                if self.boundaries.contains(&origin.commit) {
+                    prefix.push('^');
+                }
+                if opts.mark_unblamable && entry.unblamable {
+                    prefix.push('*');
+                }
+                if opts.mark_ignored && entry.ignored {
+                    prefix.push('?');
+                }
+                length -= prefix.len();
+                write!(out, "{prefix}{}", &hex[..length])?;
+                if show_name {
+                    write!(out, " {:<longest_file$}", origin.path)?;
+                }
+                let pad = longest_author - width(&author.name);
+                write!(
+                    out,
+                    " ({}{:pad$} {date} {:>digits$}) ",
+                    author.name,
+                    "",
+                    entry.lno + i + 1
+                )?;
+                out.write_all(self.line(entry.lno + i))?;
+            }
+            self.end_entry(out, entry)?;
+        }
+        Ok(())
+    }
+
+    /// Ends the last line of the file, which may lack a newline
+    fn end_entry(&self, out: &mut impl Write, entry: &Entry) -> anyhow::Result<()> {
+        let end = self.line_starts[entry.lno + entry.num_lines];
+        if end > 0 && self.content[end - 1] != b'\n' {
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn write_porcelain(&self, out: &mut impl Write, repeat: bool) -> anyhow::Result<()> {
+        // Commits with lines from several paths name the path with every group of lines
+        let mut guilty_paths: HashMap<[u8; 20], usize> = HashMap::new();
+        for origin in self.origins.iter().filter(|origin| origin.guilty) {
+            *guilty_paths.entry(origin.commit).or_default() += 1;
+        }
+        let mut shown = HashSet::new();
+        for entry in &self.entries {
+            let origin = &self.origins[entry.suspect];
+            let hex = hex::encode(origin.commit);
+            let details = |out: &mut dyn Write, shown: &mut HashSet<[u8; 20]>| {
+                let first = shown.insert(origin.commit);
+                if repeat || first {
+                    self.write_details(out, origin)?;
+                }
+                if repeat || first || guilty_paths[&origin.commit] > 1 {
+                    if let Some(previous) = origin.previous {
+                        let previous = &self.origins[previous];
+                        writeln!(
+                            out,
+                            "previous {} {}",
+                            hex::encode(previous.commit),
+                            quote::c_style(&previous.path, false)
+                        )?;
+                    }
+                    writeln!(out, "filename {}", quote::c_style(&origin.path, false))?;
+                }
+                anyhow::Ok(())
+            };
+            writeln!(
+                out,
+                "{hex} {} {} {}",
+                entry.s_lno + 1,
+                entry.lno + 1,
+                entry.num_lines
+            )?;
+            details(out, &mut shown)?;
+            for i in 0..entry.num_lines {
+                if i > 0 {
+                    writeln!(out, "{hex} {} {}", entry.s_lno + i + 1, entry.lno + i + 1)?;
+                    if repeat {
+                        details(out, &mut shown)?;
+                    }
+                }
+                out.write_all(b"\t")?;
+                out.write_all(self.line(entry.lno + i))?;
+            }
+            self.end_entry(out, entry)?;
+        }
+        Ok(())
+    }
+
+    fn write_details(&self, out: &mut dyn Write, origin: &Origin) -> anyhow::Result<()> {
+        let commit = &self.commits[&origin.commit];
+        for (role, ident) in [("author", &commit.author), ("committer", &commit.committer)] {
+            writeln!(out, "{role} {}", ident.name)?;
+            writeln!(out, "{role}-mail <{}>", ident.email)?;
+            writeln!(out, "{role}-time {}", ident.time)?;
+            writeln!(out, "{role}-tz {}", timezone(ident.tz_offset))?;
+        }
+        writeln!(out, "summary {}", commit.summary())?;
+        if self.boundaries.contains(&origin.commit) {
+            writeln!(out, "boundary")?;
+        }
+        Ok(())
+    }
+}
+
+/// A timezone offset in minutes as `+hhmm`
+fn timezone(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!("{sign}{:02}{:02}", offset.abs() / 60, offset.abs() % 60)
+}
+
+/// Merges two lists of entries sorted by line, keeping the order of equal ones with those of
+/// `first` before those of `second`
+fn merge(first: Vec<Entry>, second: Vec<Entry>) -> Vec<Entry> {
+    let mut merged = Vec::with_capacity(first.len() + second.len());
+    let mut second = second.into_iter().peekable();
+    for entry in first {
+        while let Some(other) = second.next_if(|other| other.s_lno < entry.s_lno) {
+            merged.push(other);
+        }
+        merged.push(entry);
+    }
+    merged.extend(second);
+    merged
+}
+
+/// Splits `entry` around the lines `tlno..same` of its suspect's version, which are known to
+/// match the lines from `plno` on of `parent`
+fn split_overlap(entry: &Entry, tlno: usize, plno: usize, same: usize, parent: usize) -> Split {
+    let mut split: Split = [None, None, None];
+    let (lno, s_lno) = if entry.s_lno < tlno {
+        split[0] = Some(Entry {
+            num_lines: tlno - entry.s_lno,
+            ..entry.clone()
+        });
+        (entry.lno + tlno - entry.s_lno, plno)
+    } else {
+        (entry.lno, plno + (entry.s_lno - tlno))
+    };
+    let end = if same < entry.s_lno + entry.num_lines {
+        let after = entry.lno + (same - entry.s_lno);
+        split[2] = Some(Entry {
+            lno: after,
+            s_lno: same,
+            num_lines: entry.s_lno + entry.num_lines - same,
+            ..entry.clone()
+        });
+        after
+    } else {
+        entry.lno + entry.num_lines
+    };
+    if end > lno {
+        split[1] = Some(Entry {
+            lno,
+            num_lines: end - lno,
+            s_lno,
+            suspect: parent,
+            ..entry.clone()
+        });
+    }
+    split
+}
+
+/// Blames the middle of a split on the other origin, queueing the rest to be looked at again
+fn split_blame(blamed: &mut Vec<Entry>, unblamed: &mut Vec<Entry>, split: Split) {
+    let [before, found, after] = split;
+    unblamed.extend(before);
+    unblamed.extend(after);
+    blamed.extend(found);
+}
+
+/// The lower-cased byte pairs of a line, whitespace counting as 0 and with a space added at
+/// both ends, counted; pairs of whitespace are left out. Lines sharing many pairs look alike.
+type Fingerprint = HashMap<u16, u32>;
+
+fn fingerprint(line: &[u8]) -> Fingerprint {
+    let mut pairs = HashMap::new();
+    let mut prev = 0;
+    for i in 0..=line.len() {
+        let c = match line.get(i) {
+            Some(c) if !c.is_ascii_whitespace() => u16::from(c.to_ascii_lowercase()),
+            _ => 0,
+        };
+        let pair = prev | (c << 8);
+        prev = c;
+        if pair != 0 {
+            *pairs.entry(pair).or_insert(0) += 1;
+        }
+    }
+    pairs
+}
+
+fn similarity(a: &Fingerprint, b: &Fingerprint) -> i64 {
+    b.iter()
+        .filter_map(|(pair, count)| a.get(pair).map(|other| i64::from(*count.min(other))))
+        .sum()
+}
+
+/// Takes the pairs of `b` out of `a`, so that other lines can't match them again
+fn subtract(a: &mut Fingerprint, b: &Fingerprint) {
+    for (pair, count) in b {
+        if let Some(other) = a.get_mut(pair) {
+            if *other <= *count {
+                a.remove(pair);
+            } else {
+                *other -= count;
+            }
+        }
+    }
+}
+
+/// Lines no parent line of the change looks like may still stand for a line elsewhere in the
+/// parent's version sharing at least this many byte pairs with them
+const FILE_SIMILARITY_THRESHOLD: i64 = 10;
+
+/// Decides for each changed line of an ignored commit, `target` (start and length), which line
+/// of the parent's version it stands for, if any: preferably one of the parent's side of the
+/// change, `parent`, or else the most similar line of the whole file
+fn guess_line_blames(
+    parent_prints: &mut [Fingerprint],
+    target_prints: &[Fingerprint],
+    parent: (usize, usize),
+    target: (usize, usize),
+) -> Vec<Guess> {
+    let matches = FuzzyMatcher::find(parent_prints, target_prints, parent, target);
+    (0..target.1)
+        .map(|i| {
+            if let Some(line) = matches.as_ref().and_then(|matches| matches[i]) {
+                return Guess { parent: true, line };
+            }
+            let target_line = (target.0 + i) as i64;
+            let print = &target_prints[target.0 + i];
+            let mut best: Option<(usize, i64)> = None;
+            for (line, candidate) in parent_prints.iter().enumerate() {
+                let value = similarity(candidate, print);
+                // Ties go to the line closest to the target's
+                let better = match best {
+                    None => value >= FILE_SIMILARITY_THRESHOLD,
+                    Some((best_line, best_value)) => {
+                        value > best_value
+                            || (value == best_value
+                                && (line as i64 - target_line).abs()
+                                    <= (best_line as i64 - target_line).abs())
+                    }
+                };
+                if better {
+                    best = Some((line, value));
+                }
+            }
+            match best {
+                Some((line, _)) => Guess { parent: true, line },
+                None => Guess {
+                    parent: false,
+                    line: target.0 + i,
+                },
+            }
+        })
+        .collect()
+}
+
+const CERTAINTY_NOT_CALCULATED: i64 = -1;
+const CERTAIN_NOTHING_MATCHES: i64 = -2;
+
+/// Matches lines of a target chunk (B) to lines of the parent's chunk (A) by fingerprint, taking
+/// the line matched with the most certainty first and then matching the lines before it to the
+/// lines before its match and the lines after it to those after, so that lines keep their order
+struct FuzzyMatcher<'a> {
+    prints_a: &'a mut [Fingerprint],
+    prints_b: &'a [Fingerprint],
+    /// The first line of B, where the per-line arrays start
+    first_b: i64,
+    /// The chunks as a whole, for mapping lines of B to where they would be in A
+    start_a: i64,
+    length_a: i64,
+    length_b: i64,
+    /// Lines of A further than this from where a line of B maps to are not compared with it
+    max_distance_a: i64,
+    /// How far apart lines of B can be and still be compared with the same line of A
+    max_distance_b: i64,
+    /// For each line of B, its similarities with the lines of A around where it maps to, scaled
+    /// to prefer closer lines; -1 where not calculated
+    similarities: Vec<i64>,
+    certainties: Vec<i64>,
+    /// The best and second best matching lines of A for each line of B, -1 if none
+    result: Vec<i64>,
+    second_best: Vec<i64>,
+}
+
+impl FuzzyMatcher<'_> {
+    fn find(
+        prints_a: &mut [Fingerprint],
+        prints_b: &[Fingerprint],
+        (start_a, length_a): (usize, usize),
+        (start_b, length_b): (usize, usize),
+    ) -> Option<Vec<Option<usize>>> {
+        if length_a == 0 {
+            return None;
+        }
+        let (length_a, length_b) = (length_a as i64, length_b as i64);
+        let max_distance_a = 10.min(length_a - 1);
+        let max_distance_b = ((2 * max_distance_a + 1) * length_b - 1) / length_a;
+        let row = (2 * max_distance_a + 1) as usize;
+        let mut matcher = FuzzyMatcher {
+            prints_a,
+            prints_b,
+            first_b: start_b as i64,
+            start_a: start_a as i64,
+            length_a,
+            length_b,
+            max_distance_a,
+            max_distance_b,
+            similarities: vec![-1; length_b as usize * row],
+            certainties: vec![CERTAINTY_NOT_CALCULATED; length_b as usize],
+            result: vec![-1; length_b as usize],
+            second_best: vec![-1; length_b as usize],
+        };
+        matcher.recurse(start_a as i64, start_b as i64, length_a, length_b);
+        Some(
+            matcher
+                .result
+                .iter()
+                .map(|&line| usize::try_from(line).ok())
+                .collect(),
+        )
+    }
+
+    /// Where line `line_b` of B would be in A if the chunks were stretched to the same length
+    fn map_line(&self, line_b: i64) -> i64 {
+        ((line_b - self.first_b) * 2 + 1) * self.length_a / (self.length_b * 2) + self.start_a
+    }
+
+    fn similarity_index(&self, line_a: i64, index_b: i64, closest_a: i64) -> usize {
+        (line_a - closest_a + self.max_distance_a + index_b * (2 * self.max_distance_a + 1))
+            as usize
+    }
+
+    /// Finds the best and second best lines of A (from `start_a`, `length_a` of them) for line
+    /// `local_b` of B (counted from `start_b`), and how certain the best one is
+    fn find_best(&mut self, start_a: i64, length_a: i64, start_b: i64, local_b: i64) {
+        let index_b = start_b - self.first_b + local_b;
+        if self.certainties[index_b as usize] != CERTAINTY_NOT_CALCULATED {
+            return;
+        }
+        let closest = self.map_line(local_b + start_b) - start_a;
+        let search_start = (closest - self.max_distance_a).max(0);
+        let search_end = (closest + self.max_distance_a + 1).min(length_a);
+        let (mut best, mut second) = (0, 0);
+        let (mut best_index, mut second_index) = (0, 0);
+        for i in search_start..search_end {
+            let slot = self.similarity_index(i, index_b, closest);
+            if self.similarities[slot] == -1 {
+                let b = &self.prints_b[(start_b + local_b) as usize];
+                let a = &self.prints_a[(start_a + i) as usize];
+                self.similarities[slot] = similarity(a, b) * (1000 - (i - closest).abs());
+            }
+            let value = self.similarities[slot];
+            if value > best {
+                (second, second_index) = (best, best_index);
+                (best, best_index) = (value, i);
+            } else if value > second {
+                (second, second_index) = (value, i);
+            }
+        }
+        let index_b = index_b as usize;
+        if best == 0 {
+            self.certainties[index_b] = CERTAIN_NOTHING_MATCHES;
+            self.result[index_b] = -1;
+        } else {
+            // Matching two lines well is still better than matching one line badly
+            self.certainties[index_b] = best * 2 - second;
+            self.result[index_b] = start_a + best_index;
+            self.second_best[index_b] = start_a + second_index;
+        }
+    }
+
+    fn recurse(&mut self, start_a: i64, start_b: i64, length_a: i64, length_b: i64) {
+        let base = start_b - self.first_b;
+        let mut most_certain: Option<(i64, i64)> = None;
+        for i in 0..length_b {
+            self.find_best(start_a, length_a, start_b, i);
+            let certainty = self.certainties[(base + i) as usize];
+            if certainty > most_certain.map_or(-1, |(_, best)| best) {
+                most_certain = Some((i, certainty));
+            }
+        }
+        let Some((certain_b, _)) = most_certain else {
+            return;
+        };
+        let certain_a = self.result[(base + certain_b) as usize];
+
+        subtract(
+            &mut self.prints_a[certain_a as usize],
+            &self.prints_b[(start_b + certain_b) as usize],
+        );
+
+        // Similarities with the changed line of A and matches out of order with the new one
+        // have to be worked out again
+        let invalidate_min = (certain_b - self.max_distance_b).max(0);
+        let invalidate_max = (certain_b + self.max_distance_b + 1).min(length_b);
+        for i in invalidate_min..invalidate_max {
+            let closest = self.map_line(i + start_b) - start_a;
+            if (certain_a - start_a - closest).abs() > self.max_distance_a {
+                continue;
+            }
+            let slot = self.similarity_index(certain_a - start_a, base + i, closest);
+            self.similarities[slot] = -1;
+        }
+        for i in (invalidate_min..certain_b).rev() {
+            let index = (base + i) as usize;
+            if self.certainties[index] >= 0
+                && (self.result[index] >= certain_a || self.second_best[index] >= certain_a)
+            {
+                self.certainties[index] = CERTAINTY_NOT_CALCULATED;
+            }
+        }
+        for i in certain_b + 1..invalidate_max {
+            let index = (base + i) as usize;
+            if self.certainties[index] >= 0
+                && (self.result[index] <= certain_a || self.second_best[index] <= certain_a)
+            {
+                self.certainties[index] = CERTAINTY_NOT_CALCULATED;
+            }
+        }
+
+        if certain_b > 0 {
+            self.recurse(start_a, start_b, certain_a + 1 - start_a, certain_b);
+        }
+        if certain_b + 1 < length_b {
+            let offset_b = certain_b + 1;
+            self.recurse(
+                certain_a,
+                start_b + offset_b,
+                length_a + start_a - certain_a,
+                length_b - offset_b,
+            );
+        }
+    }
+}
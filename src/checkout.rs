@@ -0,0 +1,143 @@
+//! Writing trees back to the working tree: moving between commits and checking out individual
+//! paths, as `checkout`, `switch` and `restore` do.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    index::{Index, IndexEntry},
+    pathspec::Pathspec,
+    unpack::{self, tree_blobs, Blob, UnpackOptions},
+};
+
+/// Moves the index and working tree from the `old` tree to the `new` one. Local changes are
+/// carried over unless they touch paths that differ between the two, which is an error, or
+/// discarded altogether with `force`.
+pub(crate) fn switch_trees(
+    index: &mut Index,
+    old: Option<&[u8; 20]>,
+    new: Option<&[u8; 20]>,
+    force: bool,
+) -> anyhow::Result<()> {
+    let opts = UnpackOptions {
+        update: true,
+        reset: force,
+        switching: true,
+        ..UnpackOptions::default()
+    };
+    unpack::two_way(index, old, new, opts)
+}
+
+/// Which of the index and working tree [`checkout_paths`] updates, and how
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PathOptions {
+    pub(crate) staged: bool,
+    pub(crate) worktree: bool,
+    /// Leave paths that are missing from the source alone instead of removing them
+    pub(crate) overlay: bool,
+}
+
+/// Checks out the paths matching `pathspec` from a tree, or from the index when `source` is
+/// `None`. Returns the number of working tree files that were written.
+pub(crate) fn checkout_paths(
+    index: &mut Index,
+    source: Option<&[u8; 20]>,
+    pathspec: &Pathspec,
+    opts: PathOptions,
+) -> anyhow::Result<usize> {
+    let source_blobs: BTreeMap<String, Blob> = match source {
+        Some(tree) => tree_blobs(Some(tree))?,
+        None => index
+            .entries()
+            .iter()
+            .filter(|entry| entry.stage == 0)
+            .map(|entry| (entry.path.clone(), (entry.mode, entry.hash)))
+            .collect(),
+    };
+    let unmatched = pathspec.unmatched(
+        source_blobs
+            .keys()
+            .map(String::as_str)
+            .chain(index.entries().iter().map(|entry| entry.path.as_str())),
+    );
+    if !unmatched.is_empty() {
+        let errors: Vec<String> = unmatched
+            .iter()
+            .map(|pattern| format!("pathspec '{pattern}' did not match any file(s) known to git"))
+            .collect();
+        anyhow::bail!(errors.join("\n"));
+    }
+    if source.is_none() {
+        let mut unmerged: Vec<&str> = index
+            .entries()
+            .iter()
+            .filter(|entry| entry.stage != 0 && pathspec.matches(&entry.path))
+            .map(|entry| entry.path.as_str())
+            .collect();
+        unmerged.dedup();
+        if !unmerged.is_empty() {
+            let errors: Vec<String> = unmerged
+                .iter()
+                .map(|path| format!("path '{path}' is unmerged"))
+                .collect();
+            anyhow::bail!(errors.join("\n"));
+        }
+    }
+
+    let mut targets: BTreeMap<String, Option<Blob>> = source_blobs
+        .into_iter()
+        .filter(|(path, _)| pathspec.matches(path))
+        .map(|(path, blob)| (path, Some(blob)))
+        .collect();
+    if !opts.overlay {
+        for entry in index.entries() {
+            if pathspec.matches(&entry.path) && !targets.contains_key(&entry.path) {
+                targets.insert(entry.path.clone(), None);
+            }
+        }
+    }
+
+    let mut written = 0;
+    for (path, target) in targets {
+        let Some((mode, hash)) = target else {
+            if opts.staged {
+                index.remove(&path);
+            }
+            if opts.worktree {
+                crate::worktree::remove_file(&path)?;
+            }
+            continue;
+        };
+        let same = |entry: &&IndexEntry| entry.mode == mode && entry.hash == hash;
+        let indexed = index.get(&path, 0).filter(same).is_some();
+        if opts.staged && (!indexed || index.stages(&path).len() > 1) {
+            // Entries that already match keep their cached stat information
+            let entry = match index.get(&path, 0).filter(same) {
+                Some(entry) => entry.clone(),
+                None => IndexEntry::new(path.clone(), mode, hash, 0),
+            };
+            index.add(entry);
+        }
+        if !opts.worktree {
+            continue;
+        }
+        let up_to_date = match index.get(&path, 0).filter(same) {
+            Some(entry) => index.matches_worktree(entry)?,
+            None => false,
+        };
+        if up_to_date {
+            continue;
+        }
+        // Checking out paths overwrites them, whatever is in the way
+        crate::worktree::clear_path(&path)?;
+        crate::worktree::write_file(&path, mode, &hash)?;
+        written += 1;
+        if let Some(entry) = index
+            .get_mut(&path, 0)
+            .filter(|entry| entry.mode == mode && entry.hash == hash)
+        {
+            let metadata = std::fs::symlink_metadata(&path)?;
+            entry.update_stat(&metadata);
+        }
+    }
+    Ok(written)
+}
@@ -1,12 +1,111 @@
 use clap::Subcommand;
 use std::path::PathBuf;
 
+mod am;
+mod apply;
+mod bisect;
+mod blame;
 mod cat_file;
+pub(crate) mod checkout;
+mod cherry_pick;
 mod hash_object;
 mod init;
 mod ls_tree;
 mod write_tree;
 mod commit_tree;
+mod describe;
+mod diff;
+mod diff_tree;
+mod format_patch;
+mod grep;
+mod log;
+mod merge;
+mod merge_base;
+mod name_rev;
+mod read_tree;
+mod rebase;
+mod reset;
+mod restore;
+mod revert;
+mod rev_list;
+mod show;
+pub(crate) mod status;
+mod stash;
+mod switch;
+
+/// Commands taking the diff options of [`diff::DiffFormatArgs`]
+const DIFF_COMMANDS: &[&str] = &["diff", "diff-tree", "format-patch", "log", "show"];
+
+/// Commands taking `-M[<n>]` and `-C[<n>]` for move and copy detection
+const BLAME_COMMANDS: &[&str] = &["blame"];
+
+/// Commands taking `-<n>` as a short form of `-n <n>`
+const LOG_COMMANDS: &[&str] = &["log", "rev-list"];
+
+/// Commands taking `-<n>` as a short form of `--max-count=<n>`, as `-n` means something else
+const PATCH_COMMANDS: &[&str] = &["format-patch"];
+
+/// Commands taking `-<n>` as a short form of `-C <n>`
+const GREP_COMMANDS: &[&str] = &["grep"];
+
+/// Rewrites the rename and copy detection options of diff and blame commands (`-M[<n>]`,
+/// `--find-renames[=<n>]`, `-C[<n>]` and `--find-copies[=<n>]`) into one option that keeps their
+/// order, as the last of them decides and `-C -C` means `--find-copies-harder`. Clap also only
+/// accepts optional values after `=`, while git takes them attached, as in `-M50%`.
+///
+/// Log commands get `-<n>` rewritten to `-n<n>`, format-patch to `--max-count=<n>` and grep to
+/// `-C<n>`, which clap can't express.
+pub fn normalize_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut args: Vec<String> = args.into_iter().collect();
+    let count_option = match args.get(1) {
+        Some(command) if LOG_COMMANDS.contains(&command.as_str()) => Some("-n"),
+        Some(command) if PATCH_COMMANDS.contains(&command.as_str()) => Some("--max-count="),
+        Some(command) if GREP_COMMANDS.contains(&command.as_str()) => Some("-C"),
+        _ => None,
+    };
+    if let Some(option) = count_option {
+        for arg in args.iter_mut().skip(2) {
+            if arg == "--" {
+                break;
+            }
+            if arg.len() > 1 && arg[1..].bytes().all(|b| b.is_ascii_digit()) && arg.starts_with('-') {
+                *arg = format!("{option}{}", &arg[1..]);
+            }
+        }
+    }
+    if !args
+        .get(1)
+        .is_some_and(|command| DIFF_COMMANDS.contains(&command.as_str()) || BLAME_COMMANDS.contains(&command.as_str()))
+    {
+        return args;
+    }
+    for arg in args.iter_mut().skip(2) {
+        if arg == "--" {
+            break;
+        }
+        let detection = if let Some(value) = arg.strip_prefix("-M") {
+            format!("M{value}")
+        } else if let Some(value) = arg.strip_prefix("-C") {
+            format!("C{value}")
+        } else if let Some(rest) = arg.strip_prefix("--find-renames") {
+            match rest.strip_prefix('=') {
+                Some(value) => format!("M{value}"),
+                None if rest.is_empty() => String::from("M"),
+                None => continue,
+            }
+        } else if let Some(rest) = arg.strip_prefix("--find-copies") {
+            match rest.strip_prefix('=') {
+                Some(value) => format!("C{value}"),
+                None if rest.is_empty() => String::from("C"),
+                None => continue,
+            }
+        } else {
+            continue;
+        };
+        *arg = format!("--rename-detection={detection}");
+    }
+    args
+}
 
 #[derive(Subcommand, Debug)]
 pub enum Command {
@@ -32,13 +131,79 @@ pub enum Command {
     WriteTree {},
     CommitTree {
         #[clap(short='p')]
-        parent: Option<String>,
+        parents: Vec<String>,
 
         tree_hash: String,
 
         #[clap(short='m')]
         message: String,
-    }
+    },
+    ReadTree {
+        #[clap(short = 'm')]
+        merge: bool,
+
+        #[clap(long = "reset")]
+        reset: bool,
+
+        #[clap(short = 'u')]
+        update: bool,
+
+        /// Also resolve deletions in a three-way merge
+        #[clap(long = "aggressive")]
+        aggressive: bool,
+
+        #[clap(long = "prefix")]
+        prefix: Option<String>,
+
+        #[clap(required = true, num_args = 1..)]
+        tree_ishes: Vec<String>,
+    },
+    Status {
+        #[clap(short = 's', long = "short")]
+        short: bool,
+
+        #[clap(short = 'b', long = "branch")]
+        branch: bool,
+
+        #[clap(long = "porcelain", num_args = 0..=1, require_equals = true, default_missing_value = "v1")]
+        porcelain: Option<String>,
+
+        #[clap(short = 'z')]
+        null_terminated: bool,
+
+        #[clap(
+            short = 'u',
+            long = "untracked-files",
+            num_args = 0..=1,
+            default_value = "normal",
+            default_missing_value = "all"
+        )]
+        untracked_files: String,
+    },
+    Diff(diff::DiffArgs),
+    DiffTree(diff_tree::DiffTreeArgs),
+    Checkout(checkout::CheckoutArgs),
+    Switch(switch::SwitchArgs),
+    Restore(restore::RestoreArgs),
+    Reset(reset::ResetArgs),
+    Log(Box<log::LogArgs>),
+    Merge(merge::MergeArgs),
+    CherryPick(cherry_pick::CherryPickArgs),
+    Revert(revert::RevertArgs),
+    Rebase(rebase::RebaseArgs),
+    MergeBase(merge_base::MergeBaseArgs),
+    RevList(rev_list::RevListArgs),
+    Stash(stash::StashArgs),
+    Bisect(bisect::BisectArgs),
+    Blame(blame::BlameArgs),
+    Describe(describe::DescribeArgs),
+    NameRev(name_rev::NameRevArgs),
+    Show(show::ShowArgs),
+    #[command(disable_help_flag = true)]
+    Grep(grep::GrepArgs),
+    FormatPatch(format_patch::FormatPatchArgs),
+    Am(am::AmArgs),
+    Apply(apply::ApplyArgs),
 }
 
 impl Command {
@@ -52,7 +217,45 @@ impl Command {
             Command::HashObject { file_path, write } => hash_object::invoke(&file_path, write),
             Command::LsTree { name_only , tree_hash} => ls_tree::invoke(name_only, tree_hash),
             Command::WriteTree {} => write_tree::invoke(),
-            Command::CommitTree { parent, tree_hash, message } => commit_tree::invoke(tree_hash, parent, message)
+            Command::CommitTree { parents, tree_hash, message } => commit_tree::invoke(tree_hash, parents, message),
+            Command::ReadTree {
+                merge,
+                reset,
+                update,
+                aggressive,
+                prefix,
+                tree_ishes,
+            } => read_tree::invoke(merge, reset, update, aggressive, prefix, tree_ishes),
+            Command::Status {
+                short,
+                branch,
+                porcelain,
+                null_terminated,
+                untracked_files,
+            } => status::invoke(short, branch, porcelain, null_terminated, untracked_files),
+            Command::Diff(args) => diff::invoke(args),
+            Command::DiffTree(args) => diff_tree::invoke(args),
+            Command::Checkout(args) => checkout::invoke(args),
+            Command::Switch(args) => switch::invoke(args),
+            Command::Restore(args) => restore::invoke(args),
+            Command::Reset(args) => reset::invoke(args),
+            Command::Log(args) => log::invoke(*args),
+            Command::Merge(args) => merge::invoke(args),
+            Command::CherryPick(args) => cherry_pick::invoke(args),
+            Command::Revert(args) => revert::invoke(args),
+            Command::Rebase(args) => rebase::invoke(args),
+            Command::MergeBase(args) => merge_base::invoke(args),
+            Command::RevList(args) => rev_list::invoke(args),
+            Command::Stash(args) => stash::invoke(args),
+            Command::Bisect(args) => bisect::invoke(args),
+            Command::Blame(args) => blame::invoke(args),
+            Command::Describe(args) => describe::invoke(args),
+            Command::NameRev(args) => name_rev::invoke(args),
+            Command::Show(args) => show::invoke(args),
+            Command::Grep(args) => grep::invoke(args),
+            Command::FormatPatch(args) => format_patch::invoke(args),
+            Command::Am(args) => am::invoke(args),
+            Command::Apply(args) => apply::invoke(args),
         }
     }
 }
@@ -0,0 +1,89 @@
+use std::{fs, io::Read, path::PathBuf};
+
+use anyhow::Context;
+
+use crate::am::{self, AmOptions, Outcome};
+
+#[derive(clap::Args, Debug)]
+pub struct AmArgs {
+    /// When a patch doesn't apply, merge it in using the blobs its `index` lines name
+    #[clap(short = '3', long = "3way")]
+    three_way: bool,
+
+    /// Keep the subjects as they are, rather than dropping `Re:` and `[PATCH]` prefixes
+    #[clap(short = 'k', long = "keep")]
+    keep_subject: bool,
+
+    /// Commit the resolved patch the series stopped at and go on with the rest
+    #[clap(
+        long = "continue",
+        visible_alias = "resolved",
+        short = 'r',
+        group = "resume"
+    )]
+    continue_am: bool,
+
+    /// Drop the patch the series stopped at and go on with the rest
+    #[clap(long = "skip", group = "resume")]
+    skip: bool,
+
+    /// Go back to where the series started
+    #[clap(long = "abort", group = "resume")]
+    abort: bool,
+
+    /// Show the mail the series stopped at: raw, or diff for just its patch
+    #[clap(
+        long = "show-current-patch",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "raw",
+        value_name = "part",
+        group = "resume"
+    )]
+    show_current_patch: Option<String>,
+
+    /// Mailboxes or patch files, stdin if none are given
+    #[clap(conflicts_with = "resume")]
+    mailboxes: Vec<PathBuf>,
+}
+
+/// Starts or carries on applying a series, exiting with 128 when it stops for the user
+pub(crate) fn invoke(args: AmArgs) -> anyhow::Result<()> {
+    let outcome = if args.continue_am {
+        am::resume()?
+    } else if args.skip {
+        am::skip()?
+    } else if args.abort {
+        return am::abort();
+    } else if let Some(part) = &args.show_current_patch {
+        let diff = match part.as_str() {
+            "diff" => true,
+            "raw" => false,
+            _ => anyhow::bail!("Invalid value for --show-current-patch: {part}"),
+        };
+        return am::show_current_patch(diff);
+    } else if args.mailboxes.is_empty() && am::in_progress() {
+        am::retry()?
+    } else {
+        let mut mailboxes = Vec::new();
+        for path in &args.mailboxes {
+            let content =
+                fs::read(path).with_context(|| format!("could not open '{}'", path.display()))?;
+            mailboxes.push(content);
+        }
+        if mailboxes.is_empty() {
+            let mut content = Vec::new();
+            std::io::stdin().lock().read_to_end(&mut content)?;
+            mailboxes.push(content);
+        }
+        let opts = AmOptions {
+            three_way: args.three_way,
+            keep_subject: args.keep_subject,
+        };
+        am::start(&mailboxes, opts)?
+    };
+    match outcome {
+        Outcome::Done => Ok(()),
+        Outcome::Stopped => std::process::exit(128),
+    }
+}
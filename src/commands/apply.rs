@@ -0,0 +1,152 @@
+use std::{fs, io::Read, path::PathBuf};
+
+use anyhow::Context;
+
+use crate::{
+    apply::{self, ApplyOptions, Outcome, StatLayout, Target},
+    index::Index,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ApplyArgs {
+    /// Show a diffstat of the patch instead of applying it
+    #[clap(long = "stat")]
+    stat: bool,
+
+    /// Only check that the patch applies, without applying it
+    #[clap(long = "check")]
+    check: bool,
+
+    /// Apply the patch even when --stat or --check is given
+    #[clap(long = "apply")]
+    apply: bool,
+
+    /// Apply the patch to both the index and the working tree, which must match it
+    #[clap(long = "index")]
+    index: bool,
+
+    /// Apply the patch to the index only, leaving the working tree alone
+    #[clap(long = "cached")]
+    cached: bool,
+
+    /// Merge the patch into the files with the blobs its `index` lines name as the base,
+    /// leaving conflicts in the index; implies --index unless --cached is given
+    #[clap(short = '3', long = "3way", conflicts_with = "reject")]
+    three_way: bool,
+
+    /// Apply the patch in reverse
+    #[clap(short = 'R', long = "reverse")]
+    reverse: bool,
+
+    /// Apply the hunks that apply and leave the others in `.rej` files
+    #[clap(long = "reject")]
+    reject: bool,
+
+    /// Match hunks with as few as <n> lines of context on each side if needed
+    #[clap(short = 'C', value_name = "n")]
+    min_context: Option<usize>,
+
+    /// Take <n> leading components off the paths in the patch
+    #[clap(short = 'p', value_name = "n", default_value_t = 1)]
+    strip: usize,
+
+    /// Succeed on input without patches
+    #[clap(long = "allow-empty")]
+    allow_empty: bool,
+
+    /// Report each file as it is checked and applied
+    #[clap(short = 'v', long = "verbose")]
+    verbose: bool,
+
+    /// The patch files, stdin if none are given or for `-`
+    patches: Vec<PathBuf>,
+}
+
+/// Applies, checks or shows the stats of each patch file in turn, exiting with 1 if one
+/// didn't apply cleanly
+pub(crate) fn invoke(args: ApplyArgs) -> anyhow::Result<()> {
+    let apply = args.apply || !(args.stat || args.check);
+    let opts = ApplyOptions {
+        min_context: args.min_context,
+        reject: args.reject,
+        three_way: args.three_way,
+        quiet: false,
+        // Rejects are always reported hunk by hunk
+        verbose: args.verbose || args.reject,
+    };
+    let mut index = if args.index || args.cached || args.three_way {
+        Some(Index::read()?)
+    } else {
+        None
+    };
+
+    let mut inputs = Vec::new();
+    for path in &args.patches {
+        if path.as_os_str() == "-" {
+            inputs.push(read_stdin()?);
+        } else {
+            let content =
+                fs::read(path).with_context(|| format!("can't open patch '{}'", path.display()))?;
+            inputs.push(content);
+        }
+    }
+    if inputs.is_empty() {
+        inputs.push(read_stdin()?);
+    }
+
+    // A patch that doesn't apply stops everything, leaving the index as it was, while
+    // conflicts are recorded and the next patch file applied
+    let mut clean = true;
+    let mut layout = StatLayout::default();
+    for input in &inputs {
+        let mut patches = apply::parse(input, args.strip)?;
+        anyhow::ensure!(
+            !patches.is_empty() || args.allow_empty,
+            "No valid patches in input (allow with \"--allow-empty\")"
+        );
+        // Undo the files in the opposite order, so that renames and the like come apart
+        if args.reverse {
+            patches.reverse();
+            patches.iter_mut().for_each(|patch| patch.reverse());
+        }
+        layout.add(&patches);
+        let mut target = match &mut index {
+            Some(index) => Target::Index {
+                index,
+                cached: args.cached,
+            },
+            None => Target::Worktree,
+        };
+        let outcome = if apply {
+            apply::apply(&patches, &mut target, &opts)?
+        } else if args.check && apply::check(&patches, &target, &opts)?.is_none() {
+            Outcome::Failed
+        } else {
+            Outcome::Clean
+        };
+        match outcome {
+            Outcome::Clean => {}
+            Outcome::Conflicts => clean = false,
+            Outcome::Failed => std::process::exit(1),
+        }
+        if args.stat {
+            layout.write(&mut std::io::stdout().lock(), &patches)?;
+        }
+    }
+    if let (Some(index), true) = (&mut index, apply) {
+        index.write()?;
+    }
+    if !clean {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn read_stdin() -> anyhow::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    std::io::stdin()
+        .lock()
+        .read_to_end(&mut content)
+        .context("could not read the patch from stdin")?;
+    Ok(content)
+}
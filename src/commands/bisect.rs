@@ -0,0 +1,110 @@
+use std::io::Write;
+
+use crate::bisect::{self, Outcome};
+
+#[derive(clap::Args, Debug)]
+pub struct BisectArgs {
+    #[clap(subcommand)]
+    command: BisectCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum BisectCommand {
+    /// Start bisecting from the current branch, marking a bad commit and good ones if given
+    Start {
+        /// `--term-{old,good}=<term>` and `--term-{new,bad}=<term>`, then the bad commit and
+        /// the good ones
+        #[clap(allow_hyphen_values = true)]
+        args: Vec<String>,
+
+        /// Only consider the commits changing these paths
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+    /// Mark a commit (HEAD by default) as having the change looked for
+    Bad { revs: Vec<String> },
+    /// Mark commits (HEAD by default) as not having the change yet
+    Good { revs: Vec<String> },
+    /// Mark a commit as bad, in a bisection using the terms new and old
+    New { revs: Vec<String> },
+    /// Mark commits as good, in a bisection using the terms new and old
+    Old { revs: Vec<String> },
+    /// Mark commits or ranges of them (HEAD by default) as untestable
+    Skip { revs: Vec<String> },
+    /// Stop bisecting, going back to where the bisection started or to `commit`
+    Reset { commit: Option<String> },
+    /// Show the commands of the bisection so far
+    Log,
+    /// Start over, going through the commands of a bisect log
+    Replay { file: String },
+    /// Mark each commit tested by the exit status of a command until the first bad commit is
+    /// found: 0 for good, 125 for skip, anything else below 128 for bad
+    Run {
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Show the terms commits are marked with
+    Terms {
+        /// Only show the term for good commits
+        #[clap(long = "term-good", visible_alias = "term-old")]
+        good: bool,
+
+        /// Only show the term for bad commits
+        #[clap(long = "term-bad", visible_alias = "term-new", conflicts_with = "good")]
+        bad: bool,
+    },
+    /// Mark commits with the terms given to `bisect start`
+    #[clap(external_subcommand)]
+    Mark(Vec<String>),
+}
+
+pub(crate) fn invoke(args: BisectArgs) -> anyhow::Result<()> {
+    let mut out = std::io::stdout().lock();
+    let outcome = match args.command {
+        BisectCommand::Start { mut args, paths } => {
+            if !paths.is_empty() {
+                args.push(String::from("--"));
+                args.extend(paths);
+            }
+            bisect::start(&args, &mut out)?
+        }
+        BisectCommand::Bad { revs } => bisect::mark("bad", &revs, &mut out)?,
+        BisectCommand::Good { revs } => bisect::mark("good", &revs, &mut out)?,
+        BisectCommand::New { revs } => bisect::mark("new", &revs, &mut out)?,
+        BisectCommand::Old { revs } => bisect::mark("old", &revs, &mut out)?,
+        BisectCommand::Skip { revs } => bisect::skip(&revs, &mut out)?,
+        BisectCommand::Reset { commit } => {
+            bisect::reset(commit.as_deref())?;
+            Outcome::Continue
+        }
+        BisectCommand::Log => match bisect::log() {
+            Some(log) => {
+                print!("{log}");
+                Outcome::Continue
+            }
+            None => {
+                eprintln!("error: We are not bisecting.");
+                Outcome::Failed
+            }
+        },
+        BisectCommand::Replay { file } => bisect::replay(&file, &mut out)?,
+        BisectCommand::Run { command } => bisect::run(&command)?,
+        BisectCommand::Terms { good, bad } => {
+            let option = match (good, bad) {
+                (true, _) => Some("--term-good"),
+                (_, true) => Some("--term-bad"),
+                _ => None,
+            };
+            print!("{}", bisect::describe_terms(option)?);
+            Outcome::Continue
+        }
+        BisectCommand::Mark(args) => bisect::mark(&args[0], &args[1..], &mut out)?,
+    };
+    match outcome.exit_code() {
+        0 => Ok(()),
+        code => {
+            out.flush()?;
+            std::process::exit(code)
+        }
+    }
+}
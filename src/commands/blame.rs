@@ -0,0 +1,112 @@
+use crate::{
+    blame::{self, BlameOptions, Format, OutputOptions, Target},
+    config,
+    revwalk::Revisions,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct BlameArgs {
+    /// Annotate only the lines in the range: `<start>,<end>` or `:<funcname>`, may be repeated
+    #[clap(short = 'L', value_name = "range")]
+    ranges: Vec<String>,
+
+    /// Show the commit details once per commit, in a format meant for tools
+    #[clap(short = 'p', long = "porcelain")]
+    porcelain: bool,
+
+    /// Like --porcelain, but show the commit details for every line
+    #[clap(long = "line-porcelain")]
+    line_porcelain: bool,
+
+    /// Ignore whitespace when comparing versions of the file
+    #[clap(short = 'w')]
+    ignore_all_space: bool,
+
+    /// Pass the changes of this commit on to the lines they replaced
+    #[clap(long = "ignore-rev", value_name = "rev")]
+    ignore_revs: Vec<String>,
+
+    /// Ignore the commits listed in the file, one full object name per line
+    #[clap(long = "ignore-revs-file", value_name = "file")]
+    ignore_revs_files: Vec<String>,
+
+    /// Don't treat root commits as boundaries
+    #[clap(long = "root")]
+    root: bool,
+
+    /// Move and copy detection options in command-line order, `M<n>` for `-M[<n>]` and `C<n>`
+    /// for `-C[<n>]`; see `commands::normalize_args`
+    #[clap(long = "rename-detection", hide = true, require_equals = true)]
+    detection: Vec<String>,
+
+    /// The revision to start from, if any, and the file
+    args: Vec<String>,
+
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+pub(crate) fn invoke(args: BlameArgs) -> anyhow::Result<()> {
+    let (revs, path) = match args.paths.first() {
+        Some(path) => (&args.args[..], path),
+        None => match args.args.split_last() {
+            Some((path, revs)) => (revs, path),
+            None => anyhow::bail!("No file to blame given"),
+        },
+    };
+    let revisions = Revisions::parse(revs, &[], false)?;
+    anyhow::ensure!(
+        revisions.include.len() <= 1,
+        "More than one commit to dig from"
+    );
+    let name = revs
+        .iter()
+        .find(|rev| !rev.starts_with('^'))
+        .map_or("HEAD", String::as_str);
+
+    let config = config::read_repo_config();
+    let mut opts = BlameOptions {
+        ignore: blame::ignore_list(&args.ignore_revs_files, &args.ignore_revs)?,
+        show_root: args.root || config.get_bool("blame.showRoot") == Some(true),
+        ..BlameOptions::default()
+    };
+    opts.diff.ignore_all_space = args.ignore_all_space;
+    for detection in &args.detection {
+        let (kind, value) = detection.split_at(1);
+        // Like strtoul: a missing or bad number means the default
+        let digits = value.bytes().take_while(u8::is_ascii_digit).count();
+        let score = value[..digits].parse().unwrap_or(0);
+        if kind == "C" {
+            opts.copies = (opts.copies + 1).min(3);
+            opts.moves = true;
+            if score > 0 {
+                opts.copy_score = score;
+            }
+        } else {
+            opts.moves = true;
+            if score > 0 {
+                opts.move_score = score;
+            }
+        }
+    }
+
+    let target = Target {
+        commit: revisions.include.first().map(|commit| (*commit, name)),
+        exclude: &revisions.exclude,
+        path,
+        ranges: &args.ranges,
+    };
+    let blame = blame::blame(&target, opts)?;
+    let output = OutputOptions {
+        format: if args.line_porcelain {
+            Format::LinePorcelain
+        } else if args.porcelain {
+            Format::Porcelain
+        } else {
+            Format::Default
+        },
+        mark_ignored: config.get_bool("blame.markIgnoredLines") == Some(true),
+        mark_unblamable: config.get_bool("blame.markUnblamableLines") == Some(true),
+    };
+    blame.write(&mut std::io::stdout().lock(), &output)
+}
@@ -6,7 +6,7 @@ use flate2::read::ZlibDecoder;
 pub(crate) fn invoke(pretty_print: bool, object_key: &str) -> anyhow::Result<()> {
     anyhow::ensure!(pretty_print, "Missing flag: -p");
 
-    let file = crate::object::open(&object_key)
+    let file = crate::object::open(object_key)
         .with_context(|| format!("Not a valid object name: {object_key}"))?;
 
     let zlib_decoder = ZlibDecoder::new(file);
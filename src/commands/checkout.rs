@@ -0,0 +1,440 @@
+use std::{collections::HashSet, io::Write};
+
+use anyhow::Context;
+
+use crate::{
+    checkout::{self, PathOptions},
+    commands::status,
+    diff::{files::tree_to_worktree, patch::abbrev},
+    index::Index,
+    object::commit::Commit,
+    pathspec::Pathspec,
+    quote,
+    refs::{self, Head},
+    revision, revwalk,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct CheckoutArgs {
+    /// Create a new branch at the commit and switch to it
+    #[clap(short = 'b')]
+    new_branch: Option<String>,
+
+    /// Create or reset a branch at the commit and switch to it
+    #[clap(short = 'B')]
+    force_new_branch: Option<String>,
+
+    /// Detach HEAD at the commit, even if it names a branch
+    #[clap(long = "detach")]
+    detach: bool,
+
+    /// Switch to a new branch with no commits, keeping the index and working tree
+    #[clap(long = "orphan")]
+    orphan: Option<String>,
+
+    /// Throw away local changes
+    #[clap(short = 'f', long = "force")]
+    force: bool,
+
+    /// Suppress feedback messages
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// The branch or commit to switch to, or the tree-ish followed by paths to check out
+    args: Vec<String>,
+
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+/// A move of HEAD, the index and the working tree, shared by `checkout` and `switch`
+#[derive(Debug, Default)]
+pub(crate) struct Switch {
+    /// Branch or commit to switch to, HEAD when missing
+    pub(crate) target: Option<String>,
+    /// Branch to create at the target, and whether an existing branch may be reset
+    pub(crate) new_branch: Option<(String, bool)>,
+    /// Unborn branch to switch to
+    pub(crate) orphan: Option<String>,
+    /// Whether switching to an orphan branch empties the index and working tree
+    pub(crate) orphan_empties: bool,
+    pub(crate) detach: bool,
+    /// Refuse to detach HEAD unless asked to, as `switch` does
+    pub(crate) require_branch: bool,
+    pub(crate) force: bool,
+    pub(crate) quiet: bool,
+}
+
+pub(crate) fn invoke(args: CheckoutArgs) -> anyhow::Result<()> {
+    let mut switch = Switch {
+        detach: args.detach,
+        force: args.force,
+        quiet: args.quiet,
+        ..Switch::default()
+    };
+    switch.new_branch = match (args.new_branch, args.force_new_branch) {
+        (Some(_), Some(_)) => anyhow::bail!("-b and -B are mutually exclusive"),
+        (Some(name), None) => Some((name, false)),
+        (None, Some(name)) => Some((name, true)),
+        (None, None) => None,
+    };
+    switch.orphan = args.orphan;
+
+    // `checkout <tree-ish> <paths>` and `checkout <paths>` check out files, anything else
+    // switches, where a first argument that isn't a revision starts the paths
+    let mut rest = args.args.into_iter();
+    let first = rest.next();
+    let mut paths: Vec<String> = rest.collect();
+    let explicit_paths = !args.paths.is_empty();
+    paths.extend(args.paths);
+    let (tree_ish, paths) = match first {
+        Some(first) if explicit_paths || !paths.is_empty() || is_revision(&first) => {
+            (Some(first), paths)
+        }
+        Some(first) => {
+            paths.insert(0, first);
+            (None, paths)
+        }
+        None => (None, paths),
+    };
+
+    if paths.is_empty() {
+        switch.target = tree_ish;
+        return switch_to(switch);
+    }
+    if let Some(name) = switch
+        .new_branch
+        .as_ref()
+        .map(|(name, _)| name)
+        .or(switch.orphan.as_ref())
+    {
+        anyhow::bail!("Cannot update paths and switch to branch '{name}' at the same time.");
+    }
+    anyhow::ensure!(
+        !switch.detach,
+        "git checkout: --detach does not take a path argument"
+    );
+
+    let source = match &tree_ish {
+        Some(tree_ish) => Some(
+            revision::resolve(tree_ish)
+                .and_then(|hash| revision::peel_to_tree(&hash))
+                .with_context(|| format!("invalid reference: {tree_ish}"))?,
+        ),
+        None => None,
+    };
+    let mut index = Index::read()?;
+    let opts = PathOptions {
+        staged: source.is_some(),
+        worktree: true,
+        overlay: true,
+    };
+    let written =
+        checkout::checkout_paths(&mut index, source.as_ref(), &Pathspec::new(&paths), opts)?;
+    index.write()?;
+    // Only reported when the paths were not separated with `--`, as they might have been
+    // meant as a branch
+    if !switch.quiet && !explicit_paths {
+        let paths = if written == 1 { "path" } else { "paths" };
+        match source {
+            Some(tree) => eprintln!("Updated {written} {paths} from {}", abbrev(&tree)),
+            None => eprintln!("Updated {written} {paths} from the index"),
+        }
+    }
+    Ok(())
+}
+
+fn is_revision(name: &str) -> bool {
+    expand_previous(name).is_ok_and(|name| revision::resolve(&name).is_ok())
+}
+
+/// Expands `-` and `@{-<n>}` to the branch or commit checked out before
+pub(crate) fn expand_previous(name: &str) -> anyhow::Result<String> {
+    let previous = if name == "-" { "@{-1}" } else { name };
+    let Some(n) = previous
+        .strip_prefix("@{-")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .and_then(|n| n.parse::<usize>().ok())
+    else {
+        return Ok(name.to_string());
+    };
+    refs::previous_checkout(n)?.with_context(|| format!("invalid reference: {name}"))
+}
+
+/// Switches HEAD, the index and the working tree to a branch or commit, creating the branch
+/// first if asked to
+pub(crate) fn switch_to(switch: Switch) -> anyhow::Result<()> {
+    let old_head = refs::read_head()?;
+    let old_commit = refs::head_commit()?;
+    let old_branch = match &old_head {
+        Head::Symbolic(target) => Some(refs::shorten(target).to_string()),
+        Head::Detached(_) => None,
+    };
+
+    let name = expand_previous(switch.target.as_deref().unwrap_or("HEAD"))?;
+    let creates_branch = switch.new_branch.is_some() || switch.orphan.is_some();
+    let existing_branch = format!("refs/heads/{name}");
+    let branch = if !creates_branch && !switch.detach && refs::read_ref(&existing_branch)?.is_some()
+    {
+        Some(existing_branch)
+    } else {
+        None
+    };
+    // An unborn HEAD has no commit, and new branches made from it are unborn too
+    let commit = match (&switch.orphan, switch.orphan_empties) {
+        (Some(_), true) => None,
+        _ if name == "HEAD" && old_commit.is_none() => None,
+        _ => Some(
+            revision::resolve(&name)
+                .and_then(|hash| revision::peel_to_commit(&hash))
+                .with_context(|| format!("invalid reference: {name}"))?,
+        ),
+    };
+    if switch.require_branch && branch.is_none() && !creates_branch && !switch.detach {
+        let kind = match refs::expand_ref(&name)? {
+            Some(full) if full.starts_with("refs/tags/") => "tag",
+            Some(full) if full.starts_with("refs/remotes/") => "remote branch",
+            _ => "commit",
+        };
+        anyhow::bail!(
+            "a branch is expected, got {kind} '{name}'\nhint: If you want to detach HEAD at the commit, try again with the --detach option."
+        );
+    }
+
+    if let Some((new_name, reset)) = &switch.new_branch {
+        refs::check_branch_name(new_name)?;
+        let exists = refs::read_ref(&format!("refs/heads/{new_name}"))?.is_some();
+        anyhow::ensure!(
+            !exists || *reset,
+            "a branch named '{new_name}' already exists"
+        );
+    }
+    if let Some(orphan) = &switch.orphan {
+        refs::check_branch_name(orphan)?;
+        anyhow::ensure!(
+            refs::read_ref(&format!("refs/heads/{orphan}"))?.is_none(),
+            "a branch named '{orphan}' already exists"
+        );
+    }
+
+    let old_tree = match old_commit {
+        Some(commit) => Some(Commit::read(&commit)?.tree),
+        None => None,
+    };
+    let new_tree = match commit {
+        Some(commit) => Some(Commit::read(&commit)?.tree),
+        None => None,
+    };
+    let mut index = Index::read()?;
+    if index.has_conflicts() && !switch.force {
+        let mut out = std::io::stdout().lock();
+        let mut last = None;
+        for entry in index.entries().iter().filter(|entry| entry.stage != 0) {
+            if last.replace(&entry.path) != Some(&entry.path) {
+                writeln!(out, "{}: needs merge", entry.path)?;
+            }
+        }
+        anyhow::bail!("you need to resolve your current index first");
+    }
+    checkout::switch_trees(
+        &mut index,
+        old_tree.as_ref(),
+        new_tree.as_ref(),
+        switch.force,
+    )?;
+    index.write()?;
+    if let (Some(tree), false, false) = (new_tree, switch.force, switch.quiet) {
+        show_local_changes(&tree, &index)?;
+    }
+
+    if let (None, Some(old)) = (&old_branch, old_commit) {
+        if commit != Some(old) && !switch.quiet {
+            orphaned_commit_warning(&old, commit.as_ref())?;
+        }
+    }
+
+    let old_desc = match (&old_branch, old_commit) {
+        (Some(branch), _) => branch.clone(),
+        (None, Some(old)) => hex::encode(old),
+        (None, None) => String::from("(invalid)"),
+    };
+    let new_desc = match (&switch.new_branch, &switch.orphan) {
+        (Some((new_name, _)), _) => new_name.clone(),
+        (None, Some(orphan)) => orphan.clone(),
+        (None, None) => name.clone(),
+    };
+    let message = format!("checkout: moving from {old_desc} to {new_desc}");
+
+    if let Some((new_name, _)) = &switch.new_branch {
+        let full = format!("refs/heads/{new_name}");
+        let existed = refs::read_ref(&full)?.is_some();
+        let log = if existed {
+            format!("branch: Reset to {name}")
+        } else {
+            format!("branch: Created from {name}")
+        };
+        if let Some(commit) = &commit {
+            refs::update_ref(&full, commit, &log)?;
+        }
+        refs::set_head_branch(&full, &message)?;
+        if !switch.quiet {
+            if old_branch.as_deref() == Some(new_name.as_str()) {
+                eprintln!("Reset branch '{new_name}'");
+            } else if existed {
+                eprintln!("Switched to and reset branch '{new_name}'");
+            } else {
+                eprintln!("Switched to a new branch '{new_name}'");
+            }
+        }
+    } else if let Some(orphan) = &switch.orphan {
+        refs::set_head_branch(&format!("refs/heads/{orphan}"), &message)?;
+        if !switch.quiet {
+            eprintln!("Switched to a new branch '{orphan}'");
+        }
+    } else if let Some(branch) = &branch {
+        refs::set_head_branch(branch, &message)?;
+        if !switch.quiet {
+            if old_branch.as_deref() == Some(name.as_str()) {
+                eprintln!("Already on '{name}'");
+            } else {
+                eprintln!("Switched to branch '{name}'");
+            }
+        }
+    } else if name != "HEAD" || switch.detach {
+        let commit = commit.context("You are on a branch yet to be born")?;
+        refs::detach_head(&commit, &message)?;
+        if !switch.quiet {
+            if old_branch.is_some() && !switch.detach && advice_enabled("advice.detachedHead") {
+                eprint!("{}", detach_advice(&name));
+            }
+            eprintln!("HEAD is now at {}", describe(&commit)?);
+        }
+    }
+
+    let on_branch = switch.orphan.is_some()
+        || switch.new_branch.is_some()
+        || branch.is_some()
+        || (name == "HEAD" && !switch.detach);
+    if on_branch && !switch.quiet {
+        if let Some(upstream) = status::branch_info()?.upstream {
+            let mut out = std::io::stdout().lock();
+            status::print_tracking(&mut out, &upstream)?;
+        }
+    }
+    Ok(())
+}
+
+fn advice_enabled(key: &str) -> bool {
+    let config = crate::config::read_repo_config();
+    !matches!(config.get(key), Some("false" | "no" | "off" | "0"))
+}
+
+/// `<abbreviated id> <subject>` of a commit
+pub(crate) fn describe(commit: &[u8; 20]) -> anyhow::Result<String> {
+    Ok(format!(
+        "{} {}",
+        abbrev(commit),
+        Commit::read(commit)?.summary()
+    ))
+}
+
+fn detach_advice(name: &str) -> String {
+    format!(
+        "Note: switching to '{name}'.
+
+You are in 'detached HEAD' state. You can look around, make experimental
+changes and commit them, and you can discard any commits you make in this
+state without impacting any branches by switching back to a branch.
+
+If you want to create a new branch to retain commits you create, you may
+do so (now or later) by using -c with the switch command. Example:
+
+  git switch -c <new-branch-name>
+
+Or undo this operation with:
+
+  git switch -
+
+Turn off this advice by setting config variable advice.detachedHead to false
+
+"
+    )
+}
+
+/// Lists the paths whose working tree content differs from the tree switched to
+fn show_local_changes(tree: &[u8; 20], index: &Index) -> anyhow::Result<()> {
+    let pairs = tree_to_worktree(Some(tree), index, &Pathspec::default(), false)?;
+    let mut out = std::io::stdout().lock();
+    for pair in pairs {
+        writeln!(
+            out,
+            "{}\t{}",
+            pair.status(),
+            quote::c_style(pair.path(), false)
+        )?;
+    }
+    Ok(())
+}
+
+/// Warns when leaving a detached HEAD makes commits unreachable from any ref, or else says
+/// where HEAD was
+fn orphaned_commit_warning(old: &[u8; 20], new: Option<&[u8; 20]>) -> anyhow::Result<()> {
+    let mut reachable = HashSet::new();
+    for (_, hash) in refs::list("refs/")? {
+        if let Ok(commit) = revision::peel_to_commit(&hash) {
+            reachable.extend(revwalk::ancestors(&commit)?);
+        }
+    }
+    if let Some(new) = new {
+        reachable.extend(revwalk::ancestors(new)?);
+    }
+    let lost: Vec<[u8; 20]> = revwalk::ancestors(old)?
+        .into_iter()
+        .filter(|commit| !reachable.contains(commit))
+        .collect();
+    if lost.is_empty() {
+        eprintln!("Previous HEAD position was {}", describe(old)?);
+        return Ok(());
+    }
+
+    // Newest first, following the first-parent chain from the old HEAD where possible
+    let mut ordered = Vec::new();
+    let mut pending = vec![*old];
+    let mut seen = HashSet::new();
+    while let Some(commit) = pending.pop() {
+        if !lost.contains(&commit) || !seen.insert(commit) {
+            continue;
+        }
+        ordered.push(commit);
+        pending.extend(Commit::read(&commit)?.parents.into_iter().rev());
+    }
+
+    const CUTOFF: usize = 4;
+    let count = ordered.len();
+    let mut text = if count == 1 {
+        String::from(
+            "Warning: you are leaving 1 commit behind, not connected to\nany of your branches:\n\n",
+        )
+    } else {
+        format!(
+            "Warning: you are leaving {count} commits behind, not connected to\nany of your branches:\n\n"
+        )
+    };
+    for commit in ordered.iter().take(CUTOFF) {
+        text.push_str(&format!("  {}\n", describe(commit)?));
+    }
+    match count.saturating_sub(CUTOFF) {
+        0 => {}
+        1 => text.push_str(&format!("  {}\n", describe(&ordered[CUTOFF])?)),
+        more => text.push_str(&format!(" ... and {more} more.\n")),
+    }
+    eprintln!("{text}");
+    if advice_enabled("advice.detachedHead") {
+        let them = if count == 1 { "it" } else { "them" };
+        eprint!(
+            "If you want to keep {them} by creating a new branch, this may be a good time\nto do so with:\n\n git branch <new-branch-name> {}\n\n",
+            abbrev(old)
+        );
+    }
+    Ok(())
+}
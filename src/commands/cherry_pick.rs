@@ -0,0 +1,130 @@
+use crate::{
+    commands::{checkout::expand_previous, status},
+    revision,
+    revwalk::{RevWalk, Revisions, WalkOptions},
+    sequencer::{self, Action, Outcome, ReplayOptions},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct CherryPickArgs {
+    /// Append a line saying which commit was picked to the message
+    #[clap(short = 'x')]
+    record_origin: bool,
+
+    #[clap(flatten)]
+    replay: ReplayArgs,
+}
+
+/// The options `cherry-pick` and `revert` share
+#[derive(clap::Args, Debug)]
+pub struct ReplayArgs {
+    /// For merges, the parent (counting from 1) to take the changes against
+    #[clap(short = 'm', long = "mainline")]
+    mainline: Option<usize>,
+
+    /// Apply the changes to the index and working tree without committing them
+    #[clap(short = 'n', long = "no-commit")]
+    no_commit: bool,
+
+    /// Edit the message of each commit before committing it
+    #[clap(short = 'e', long = "edit", overrides_with = "no_edit")]
+    edit: bool,
+
+    /// Commit with the message as it is, the default
+    #[clap(long = "no-edit", overrides_with = "edit")]
+    no_edit: bool,
+
+    /// Commit the resolved commit the sequence stopped at and go on with the rest
+    #[clap(long = "continue", group = "sequencer")]
+    continue_replay: bool,
+
+    /// Drop the commit the sequence stopped at and go on with the rest
+    #[clap(long = "skip", group = "sequencer")]
+    skip: bool,
+
+    /// Go back to the state from before the sequence
+    #[clap(long = "abort", group = "sequencer")]
+    abort: bool,
+
+    /// Forget the sequence in progress, keeping what it has done so far
+    #[clap(long = "quit", group = "sequencer")]
+    quit: bool,
+
+    /// The commits to replay, or ranges of them
+    #[clap(required_unless_present = "sequencer", conflicts_with = "sequencer")]
+    commits: Vec<String>,
+}
+
+pub(crate) fn invoke(args: CherryPickArgs) -> anyhow::Result<()> {
+    replay(Action::Pick, args.replay, args.record_origin)
+}
+
+/// Starts, continues or stops replaying commits, exiting with 1 when the replay stops for the
+/// user. A commit that turns out empty is shown with the status, as `git commit` would.
+pub(crate) fn replay(action: Action, args: ReplayArgs, record_origin: bool) -> anyhow::Result<()> {
+    let outcome = if args.continue_replay {
+        sequencer::resume(action)?
+    } else if args.skip {
+        sequencer::skip(action)?
+    } else if args.abort {
+        return sequencer::abort(action);
+    } else if args.quit {
+        return sequencer::quit();
+    } else {
+        anyhow::ensure!(
+            args.mainline != Some(0),
+            "option 'mainline' expects a number greater than zero"
+        );
+        let (commits, single) = resolve_commits(&args.commits)?;
+        if commits.is_empty() {
+            eprintln!("error: empty commit set passed");
+            anyhow::bail!("{} failed", action.command());
+        }
+        let opts = ReplayOptions {
+            mainline: args.mainline,
+            record_origin,
+            no_commit: args.no_commit,
+            edit: args.edit,
+        };
+        sequencer::start(action, &commits, single, opts)?
+    };
+    match outcome {
+        Outcome::Done => Ok(()),
+        Outcome::Conflicts => std::process::exit(1),
+        Outcome::Empty => {
+            status::invoke(false, false, None, false, String::from("normal"))?;
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The commits named on the command line, and whether a single commit was named on its own.
+/// Ranges are walked oldest first; commits named without any are taken in the order given.
+fn resolve_commits(args: &[String]) -> anyhow::Result<(Vec<[u8; 20]>, bool)> {
+    let mut revs = Vec::new();
+    for arg in args {
+        let rev = expand_previous(arg)?;
+        if !rev.contains("..") {
+            let name = rev.strip_prefix('^').unwrap_or(&rev);
+            anyhow::ensure!(revision::resolve(name).is_ok(), "bad revision '{arg}'");
+        }
+        revs.push(rev);
+    }
+    let revisions = Revisions::parse(&revs, &[], false)?;
+    if revisions.exclude.is_empty() {
+        let mut commits = Vec::new();
+        for commit in revisions.include {
+            if !commits.contains(&commit) {
+                commits.push(commit);
+            }
+        }
+        return Ok((commits, args.len() == 1));
+    }
+    let mut walk = RevWalk::new(&revisions, WalkOptions::default())?;
+    let mut commits = Vec::new();
+    while let Some(commit) = walk.next()? {
+        commits.push(commit);
+    }
+    commits.reverse();
+    Ok((commits, false))
+}
@@ -1,9 +1,20 @@
-pub(crate) fn invoke(
-    tree_hash: String,
-    parent: Option<String>,
-    message: String,
-) -> anyhow::Result<()> {
-    let commit_hash = crate::object::write::write_commit(tree_hash, parent, message)?;
+use anyhow::Context;
+
+use crate::revision;
+
+pub(crate) fn invoke(tree: String, parents: Vec<String>, message: String) -> anyhow::Result<()> {
+    let tree = revision::resolve(&tree)
+        .and_then(|hash| revision::peel_to_tree(&hash))
+        .with_context(|| format!("not a valid tree object: {tree}"))?;
+    let parents = parents
+        .iter()
+        .map(|parent| {
+            revision::resolve(parent)
+                .and_then(|hash| revision::peel_to_commit(&hash))
+                .with_context(|| format!("not a valid commit: {parent}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let commit_hash = crate::object::write::write_commit(&tree, &parents, &format!("{message}\n"))?;
     let commit_hash = hex::encode(commit_hash);
     println!("{commit_hash}");
     Ok(())
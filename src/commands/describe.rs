@@ -0,0 +1,162 @@
+use crate::{
+    describe::{self, DescribeOptions, Describer, MAX_CANDIDATES},
+    diff::patch::abbrev,
+    name_rev::{NameRevOptions, Names, CUTOFF_DATE_SLOP},
+    object::commit::Commit,
+    revision,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct DescribeArgs {
+    /// Find the first tag that contains the commit instead, naming the commit relative to it
+    #[clap(long = "contains")]
+    contains: bool,
+
+    /// Use any ref, not just tags
+    #[clap(long = "all")]
+    all: bool,
+
+    /// Use lightweight tags too, not just annotated ones
+    #[clap(long = "tags")]
+    tags: bool,
+
+    /// Always show the number of commits since the tag and the abbreviated commit
+    #[clap(long = "long")]
+    long: bool,
+
+    /// Show this many hex digits of the commit, 0 to show only the tag
+    #[clap(long = "abbrev", num_args = 0..=1, require_equals = true, default_missing_value = "7")]
+    abbrev: Option<usize>,
+
+    /// How many of the nearest tags to consider
+    #[clap(long = "candidates", value_name = "n")]
+    candidates: Option<usize>,
+
+    /// Only describe commits a tag points at
+    #[clap(long = "exact-match")]
+    exact_match: bool,
+
+    /// Only follow the first parent of merges
+    #[clap(long = "first-parent")]
+    first_parent: bool,
+
+    /// Show the abbreviated commit when no tag describes it
+    #[clap(long = "always")]
+    always: bool,
+
+    /// Only use tags matching the pattern, may be repeated
+    #[clap(long = "match", value_name = "pattern")]
+    patterns: Vec<String>,
+
+    /// Don't use tags matching the pattern, may be repeated
+    #[clap(long = "exclude", value_name = "pattern")]
+    excludes: Vec<String>,
+
+    /// Describe HEAD, adding the mark (`-dirty` by default) if tracked files have changes
+    #[clap(long = "dirty", value_name = "mark", num_args = 0..=1, require_equals = true, default_missing_value = "-dirty")]
+    dirty: Option<String>,
+
+    /// The commits to describe, HEAD by default
+    commits: Vec<String>,
+}
+
+pub(crate) fn invoke(args: DescribeArgs) -> anyhow::Result<()> {
+    let abbrev = match args.abbrev.unwrap_or(7) {
+        0 => 0,
+        abbrev => abbrev.clamp(4, 40),
+    };
+    anyhow::ensure!(
+        !args.long || abbrev > 0,
+        "options '--long' and '--abbrev=0' cannot be used together"
+    );
+    anyhow::ensure!(
+        !args.contains || args.dirty.is_none(),
+        "options '--contains' and '--dirty' cannot be used together"
+    );
+    anyhow::ensure!(
+        args.commits.is_empty() || args.dirty.is_none(),
+        "option '--dirty' and commit-ishes cannot be used together"
+    );
+    let commits = match args.commits.is_empty() {
+        true => vec![String::from("HEAD")],
+        false => args.commits,
+    };
+
+    if args.contains {
+        return contains(
+            &commits,
+            args.all,
+            args.always,
+            &args.patterns,
+            &args.excludes,
+        );
+    }
+
+    let suffix = match args.dirty {
+        Some(mark) if describe::is_dirty()? => mark,
+        _ => String::new(),
+    };
+    let mut describer = Describer::new(DescribeOptions {
+        all: args.all,
+        tags: args.tags,
+        long: args.long,
+        abbrev,
+        candidates: match args.exact_match {
+            true => 0,
+            false => args.candidates.unwrap_or(10).min(MAX_CANDIDATES),
+        },
+        first_parent: args.first_parent,
+        always: args.always,
+        patterns: args.patterns,
+        excludes: args.excludes,
+    })?;
+    for name in &commits {
+        let hash = revision::resolve(name)
+            .map_err(|_| anyhow::anyhow!("Not a valid object name {name}"))?;
+        let commit = revision::peel_to_commit(&hash)
+            .map_err(|_| anyhow::anyhow!("{name} is neither a commit nor blob"))?;
+        println!("{}{suffix}", describer.describe(&commit)?);
+    }
+    Ok(())
+}
+
+/// `--contains`: names each commit relative to the oldest tag that contains it, as
+/// `name-rev --tags --name-only --no-undefined` does
+fn contains(
+    commits: &[String],
+    all: bool,
+    always: bool,
+    patterns: &[String],
+    excludes: &[String],
+) -> anyhow::Result<()> {
+    let mut opts = NameRevOptions {
+        tags_only: !all,
+        shorten: !all,
+        ..NameRevOptions::default()
+    };
+    if !all {
+        opts.refs = patterns.iter().map(|p| format!("refs/tags/{p}")).collect();
+        opts.excludes = excludes.iter().map(|p| format!("refs/tags/{p}")).collect();
+    }
+    let mut hashes = Vec::new();
+    let mut cutoff = i64::MAX;
+    for name in commits {
+        let Ok(hash) = revision::resolve(name).and_then(|hash| revision::peel_to_commit(&hash))
+        else {
+            eprintln!("Could not get sha1 for {name}. Skipping.");
+            continue;
+        };
+        cutoff = cutoff.min(Commit::read(&hash)?.committer.time);
+        hashes.push(hash);
+    }
+    opts.cutoff = Some(cutoff.saturating_sub(CUTOFF_DATE_SLOP));
+    let names = Names::collect(&opts)?;
+    for hash in hashes {
+        match names.name(&hash)? {
+            Some(name) => println!("{name}"),
+            None if always => println!("{}", abbrev(&hash)),
+            None => anyhow::bail!("cannot describe '{}'", hex::encode(hash)),
+        }
+    }
+    Ok(())
+}
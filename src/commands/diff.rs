@@ -0,0 +1,742 @@
+use std::io::Write;
+
+use anyhow::Context;
+use regex::bytes::Regex;
+
+use crate::{
+    config,
+    diff::{
+        color::want_color,
+        combined::{self, CombinedPath},
+        files,
+        moved::ColorMoved,
+        patch::{self, FilePair, FileSide, PatchOptions, PatchWriter},
+        rename::{self, RenameOptions},
+        words::{WordDiff, WordDiffMode},
+        Algorithm, DiffOptions,
+    },
+    index::Index,
+    object::commit::Commit,
+    pathspec::Pathspec,
+    pretty::Format,
+    quote, revision,
+};
+
+/// Columns the diffstat of a patch sent by mail may take up
+const MAIL_STAT_WIDTH: usize = 72;
+
+/// Output format options shared by the commands that print diffs
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct DiffFormatArgs {
+    /// Generate a patch (the default unless another format is given)
+    #[clap(short = 'p', long = "patch")]
+    patch: bool,
+
+    /// Lines of context around each change
+    #[clap(short = 'U', long = "unified")]
+    unified: Option<usize>,
+
+    /// Show modes, object names and status letters, one line per file
+    #[clap(long = "raw")]
+    raw: bool,
+
+    /// Raw output gives full object names, as plumbing commands do
+    #[clap(skip)]
+    full_raw_hashes: bool,
+
+    #[clap(long = "stat")]
+    stat: bool,
+
+    #[clap(long = "numstat")]
+    numstat: bool,
+
+    /// Show created, deleted and renamed files and mode changes
+    #[clap(long = "summary")]
+    summary: bool,
+
+    /// Whether the diffstat has to fit in a mail
+    #[clap(skip)]
+    mail_stat: bool,
+
+    #[clap(long = "name-only")]
+    name_only: bool,
+
+    #[clap(long = "name-status")]
+    name_status: bool,
+
+    /// Spend extra time to make sure the smallest possible diff is produced
+    #[clap(long = "minimal")]
+    minimal: bool,
+
+    #[clap(long = "no-indent-heuristic")]
+    no_indent_heuristic: bool,
+
+    /// One of myers, minimal, patience or histogram
+    #[clap(long = "diff-algorithm", value_name = "algorithm")]
+    diff_algorithm: Option<String>,
+
+    #[clap(long = "patience")]
+    patience: bool,
+
+    #[clap(long = "histogram")]
+    histogram: bool,
+
+    /// Color the output: always, never or auto
+    #[clap(
+        long = "color",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "always",
+        value_name = "when"
+    )]
+    color: Option<String>,
+
+    #[clap(long = "no-color")]
+    no_color: bool,
+
+    /// Write binary files as patches that `apply` can use
+    #[clap(long = "binary")]
+    binary: bool,
+
+    /// Show changed words instead of lines: plain, color, porcelain or none
+    #[clap(
+        long = "word-diff",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "plain",
+        value_name = "mode"
+    )]
+    word_diff: Option<String>,
+
+    /// Regex matching a word, implies --word-diff
+    #[clap(long = "word-diff-regex", value_name = "regex")]
+    word_diff_regex: Option<String>,
+
+    /// Same as --word-diff=color with an optional word regex
+    #[clap(
+        long = "color-words",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        value_name = "regex"
+    )]
+    color_words: Option<String>,
+
+    /// Color moved lines differently: plain, blocks, zebra or dimmed-zebra
+    #[clap(
+        long = "color-moved",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "default",
+        value_name = "mode"
+    )]
+    color_moved: Option<String>,
+
+    #[clap(long = "no-color-moved")]
+    no_color_moved: bool,
+
+    /// Rename and copy detection options in command-line order, `M<n>` for
+    /// `-M[<n>]`/`--find-renames[=<n>]` and `C<n>` for `-C[<n>]`/`--find-copies[=<n>]`; see
+    /// `commands::normalize_args`
+    #[clap(long = "rename-detection", hide = true, require_equals = true)]
+    rename_detection: Vec<String>,
+
+    /// Consider unmodified files as copy sources too, implies -C
+    #[clap(long = "find-copies-harder")]
+    find_copies_harder: bool,
+
+    #[clap(long = "no-renames")]
+    no_renames: bool,
+
+    /// Skip inexact rename detection when sources times destinations exceed <num> squared
+    #[clap(short = 'l', value_name = "num")]
+    rename_limit: Option<usize>,
+}
+
+impl DiffFormatArgs {
+    pub(crate) fn patch_options(&self) -> anyhow::Result<PatchOptions> {
+        let config = config::read_repo_config();
+
+        let parse_algorithm = |name: &str| {
+            Algorithm::parse(name).with_context(|| format!("Invalid diff algorithm '{name}'"))
+        };
+        let (algorithm, minimal) = if let Some(name) = &self.diff_algorithm {
+            parse_algorithm(name)?
+        } else if self.patience {
+            (Algorithm::Patience, false)
+        } else if self.histogram {
+            (Algorithm::Histogram, false)
+        } else {
+            config
+                .get("diff.algorithm")
+                .map_or(Ok((Algorithm::Myers, false)), parse_algorithm)?
+        };
+
+        let mode = match (&self.word_diff, &self.color_words, &self.word_diff_regex) {
+            (Some(mode), _, _) if mode == "none" => None,
+            (Some(mode), _, _) => Some(
+                WordDiffMode::parse(mode)
+                    .with_context(|| format!("Invalid --word-diff mode '{mode}'"))?,
+            ),
+            (None, Some(_), _) => Some(WordDiffMode::Color),
+            (None, None, Some(_)) => Some(WordDiffMode::Plain),
+            (None, None, None) => None,
+        };
+        let word_diff = match mode {
+            Some(mode) => {
+                let regex = self
+                    .color_words
+                    .as_deref()
+                    .filter(|regex| !regex.is_empty())
+                    .or(self.word_diff_regex.as_deref())
+                    .or(config.get("diff.wordRegex"));
+                let regex = regex
+                    .map(|regex| {
+                        Regex::new(&format!("(?m){regex}"))
+                            .with_context(|| format!("Invalid word regex '{regex}'"))
+                    })
+                    .transpose()?;
+                Some(WordDiff { mode, regex })
+            }
+            None => None,
+        };
+
+        let color = if self.no_color {
+            false
+        } else if let Some(when) = &self.color {
+            want_color(when)?
+        } else {
+            let setting = config.get("color.diff").or(config.get("color.ui"));
+            want_color(setting.unwrap_or("auto"))?
+        };
+        let color = color || mode == Some(WordDiffMode::Color);
+
+        let color_moved = match self
+            .color_moved
+            .as_deref()
+            .or(config.get("diff.colorMoved"))
+        {
+            _ if self.no_color_moved || !color => ColorMoved::No,
+            Some(name) => ColorMoved::parse(name)
+                .with_context(|| format!("Invalid --color-moved mode '{name}'"))?,
+            None => ColorMoved::No,
+        };
+
+        Ok(PatchOptions {
+            context: self.unified.unwrap_or(3),
+            diff: DiffOptions {
+                algorithm,
+                minimal: minimal || self.minimal,
+                indent_heuristic: !self.no_indent_heuristic,
+                ignore_space_change: false,
+                ignore_all_space: false,
+            },
+            color,
+            word_diff,
+            color_moved,
+            binary: self.binary,
+            stat_width: if self.mail_stat {
+                MAIL_STAT_WIDTH
+            } else {
+                PatchOptions::default().stat_width
+            },
+        })
+    }
+
+    /// Whether renames (`Some(false)`) or copies too (`Some(true)`) are detected, the minimum
+    /// score given, and whether unmodified files are copy sources. Porcelain commands start
+    /// from `diff.renames`, which defaults to renames; plumbing only detects when asked to.
+    fn rename_detection(&self, porcelain: bool) -> anyhow::Result<(Option<bool>, u32, bool)> {
+        let mut copies = None;
+        if porcelain {
+            let config = config::read_repo_config();
+            copies = match config
+                .get("diff.renames")
+                .map(str::to_ascii_lowercase)
+                .as_deref()
+            {
+                Some("copies" | "copy") => Some(true),
+                Some("false" | "no" | "off" | "0") => None,
+                _ => Some(false),
+            };
+        }
+        let mut score = 0;
+        let mut harder = self.find_copies_harder;
+        for detection in &self.rename_detection {
+            let (kind, value) = detection.split_at(1);
+            score = rename::parse_score(value)?;
+            if kind == "C" {
+                harder |= copies == Some(true);
+                copies = Some(true);
+            } else {
+                copies = Some(false);
+            }
+        }
+        if harder {
+            copies = Some(true);
+        }
+        if self.no_renames {
+            return Ok((None, score, false));
+        }
+        Ok((copies, score, harder))
+    }
+
+    /// Rename detection settings, if renames are to be detected
+    pub(crate) fn rename_options(&self, porcelain: bool) -> anyhow::Result<Option<RenameOptions>> {
+        let (Some(copies), score, _) = self.rename_detection(porcelain)? else {
+            return Ok(None);
+        };
+        let limit = match self.rename_limit {
+            Some(limit) => limit,
+            None => config::read_repo_config()
+                .get("diff.renameLimit")
+                .map(|limit| {
+                    limit
+                        .parse()
+                        .with_context(|| format!("Invalid diff.renameLimit '{limit}'"))
+                })
+                .transpose()?
+                .unwrap_or(rename::DEFAULT_LIMIT),
+        };
+        Ok(Some(RenameOptions {
+            copies,
+            min_score: if score == 0 {
+                rename::DEFAULT_SCORE
+            } else {
+                score
+            },
+            limit,
+        }))
+    }
+
+    /// Whether unmodified files should be collected as copy sources
+    pub(crate) fn find_copies_harder(&self, porcelain: bool) -> anyhow::Result<bool> {
+        Ok(self.rename_detection(porcelain)?.2)
+    }
+
+    /// Whether a patch is printed; it is the default when no other format is requested
+    fn shows_patch(&self) -> bool {
+        self.patch
+            || !(self.raw
+                || self.stat
+                || self.numstat
+                || self.summary
+                || self.name_only
+                || self.name_status)
+    }
+
+    /// Whether any format was asked for, for commands that show no diff by default
+    pub(crate) fn is_requested(&self) -> bool {
+        self.patch
+            || self.raw
+            || self.stat
+            || self.numstat
+            || self.summary
+            || self.name_only
+            || self.name_status
+    }
+
+    /// Whether no format but the patch was asked for
+    pub(crate) fn is_patch_only(&self) -> bool {
+        !(self.raw
+            || self.stat
+            || self.numstat
+            || self.summary
+            || self.name_only
+            || self.name_status)
+    }
+
+    /// Whether a diffstat or numstat is printed
+    pub(crate) fn shows_stat(&self) -> bool {
+        self.stat || self.numstat
+    }
+
+    /// Whether a diffstat comes before the patch, which sets both apart from a commit message
+    /// with a `---` line
+    pub(crate) fn shows_stat_and_patch(&self) -> bool {
+        self.stat && self.shows_patch()
+    }
+
+    /// Switches to the defaults of plumbing commands: raw output unless another format is
+    /// requested, with full object names
+    pub(crate) fn use_plumbing_defaults(&mut self) {
+        if !(self.patch
+            || self.stat
+            || self.numstat
+            || self.summary
+            || self.name_only
+            || self.name_status)
+        {
+            self.raw = true;
+        }
+        self.full_raw_hashes = true;
+    }
+
+    /// Switches to the defaults of patches sent by mail: a diffstat and summary before the
+    /// patch unless another format is requested, binary files included in the patch, and a
+    /// diffstat fitting in a mail
+    pub(crate) fn use_mail_defaults(&mut self) {
+        if !self.is_requested() {
+            self.stat = true;
+            self.summary = true;
+            self.patch = true;
+        }
+        self.binary = true;
+        self.mail_stat = true;
+    }
+
+    /// Shows a diffstat unless another format is requested, as `stash show` does
+    pub(crate) fn use_stat_default(&mut self) {
+        if !(self.patch || self.raw || self.numstat || self.name_only || self.name_status) {
+            self.stat = true;
+        }
+    }
+
+    /// Formats other than raw and name lists need every changed file, not subtrees
+    pub(crate) fn needs_files(&self) -> bool {
+        self.shows_patch() || self.stat || self.numstat
+    }
+
+    /// Writes the diff of a commit after its message as `log` and `show` do: set apart by a
+    /// blank line, or a `---` line before a diffstat and patch, unless on one line
+    pub(crate) fn write_after_message(
+        &self,
+        out: &mut impl Write,
+        format: &Format,
+        pairs: &[FilePair],
+    ) -> anyhow::Result<()> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        if *format != Format::Oneline && !format.is_empty() {
+            if self.shows_stat_and_patch() {
+                write!(out, "---")?;
+            }
+            writeln!(out)?;
+        }
+        self.write(out, pairs).context("Writing diff")
+    }
+
+    fn write_raw(&self, out: &mut impl Write, pairs: &[FilePair]) -> std::io::Result<()> {
+        let hash = |side: &Option<FileSide>| {
+            let hash = match side {
+                Some(side) if !side.worktree => hex::encode(side.hash),
+                _ => hex::encode(patch::NULL_HASH),
+            };
+            if self.full_raw_hashes {
+                hash
+            } else {
+                hash[..7].to_string()
+            }
+        };
+        let mode = |side: &Option<FileSide>| side.as_ref().map_or(0, |side| side.mode);
+        for pair in pairs {
+            write!(
+                out,
+                ":{:06o} {:06o} {} {} {}\t",
+                mode(&pair.old),
+                mode(&pair.new),
+                hash(&pair.old),
+                hash(&pair.new),
+                pair.status_with_score()
+            )?;
+            if let (Some(old), Some(_)) = (&pair.old, pair.rename) {
+                write!(out, "{}\t", quote::c_style(&old.path, false))?;
+            }
+            writeln!(out, "{}", quote::c_style(pair.path(), false))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the pairs in the requested formats, stats before the patch
+    pub(crate) fn write(&self, out: &mut impl Write, pairs: &[FilePair]) -> anyhow::Result<()> {
+        let opts = self.patch_options()?;
+        if self.raw {
+            self.write_raw(out, pairs)?;
+        }
+        if self.name_only {
+            for pair in pairs {
+                writeln!(out, "{}", quote::c_style(pair.path(), false))?;
+            }
+        } else if self.name_status {
+            for pair in pairs {
+                write!(out, "{}\t", pair.status_with_score())?;
+                if let (Some(old), Some(_)) = (&pair.old, pair.rename) {
+                    write!(out, "{}\t", quote::c_style(&old.path, false))?;
+                }
+                writeln!(out, "{}", quote::c_style(pair.path(), false))?;
+            }
+        }
+        if self.numstat {
+            patch::write_numstat(out, pairs, &opts.diff)?;
+        }
+        if self.stat && !pairs.is_empty() {
+            patch::write_stat(out, pairs, &opts)?;
+        }
+        if self.summary {
+            patch::write_summary(out, pairs)?;
+        }
+        if self.shows_patch() {
+            if (self.raw || self.stat || self.numstat || self.summary) && !pairs.is_empty() {
+                writeln!(out)?;
+            }
+            let mut writer = PatchWriter::new(out, &opts);
+            for pair in pairs {
+                writer.write(pair)?;
+            }
+            writer.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Writes just the diffstat and summary of the pairs, as a cover letter sums up a series
+    pub(crate) fn write_stat_and_summary(
+        &self,
+        out: &mut impl Write,
+        pairs: &[FilePair],
+    ) -> anyhow::Result<()> {
+        patch::write_stat(out, pairs, &self.patch_options()?)?;
+        patch::write_summary(out, pairs)
+    }
+
+    /// Writes the diff of a merge after its message, set apart from it by a blank line even
+    /// when there's nothing to show
+    pub(crate) fn write_merge_after_message(
+        &self,
+        out: &mut impl Write,
+        format: &Format,
+        commit: &Commit,
+        pathspec: &Pathspec,
+        dense: bool,
+    ) -> anyhow::Result<()> {
+        let mut parents = Vec::new();
+        for parent in &commit.parents {
+            parents.push(Commit::read(parent)?.tree);
+        }
+        let unchanged = self.find_copies_harder(true)?;
+        let renames = self.rename_options(true)?;
+        let paths = combined::changed_paths(
+            &parents,
+            &commit.tree,
+            pathspec,
+            renames.as_ref(),
+            unchanged,
+        )?;
+        if !format.is_empty() {
+            writeln!(out)?;
+        }
+        let mut first_parent = Vec::new();
+        if self.shows_stat() {
+            first_parent =
+                files::tree_to_tree(parents.first(), Some(&commit.tree), pathspec, unchanged)?;
+            if let Some(opts) = renames {
+                first_parent = rename::detect(first_parent, &opts)?;
+            }
+        }
+        self.write_combined(out, &first_parent, &paths, dense)
+            .context("Writing diff")
+    }
+
+    /// Writes the diff of a merge: stats against its first parent, then raw lines, names and a
+    /// combined patch for the paths it changes relative to every parent
+    pub(crate) fn write_combined(
+        &self,
+        out: &mut impl Write,
+        first_parent: &[FilePair],
+        paths: &[CombinedPath],
+        dense: bool,
+    ) -> anyhow::Result<()> {
+        let opts = self.patch_options()?;
+        if self.numstat {
+            patch::write_numstat(out, first_parent, &opts.diff)?;
+        }
+        if self.stat && !first_parent.is_empty() {
+            patch::write_stat(out, first_parent, &opts)?;
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let hash = |side: &Option<FileSide>| {
+            let hash = side.as_ref().map_or(patch::NULL_HASH, |side| side.hash);
+            if self.full_raw_hashes {
+                hex::encode(hash)
+            } else {
+                patch::abbrev(&hash)
+            }
+        };
+        let mode = |side: &Option<FileSide>| side.as_ref().map_or(0, |side| side.mode);
+        for path in paths {
+            let statuses: String = path.statuses.iter().collect();
+            if self.raw {
+                let modes: Vec<String> = path
+                    .parents
+                    .iter()
+                    .chain([&path.result])
+                    .map(|side| format!("{:06o}", mode(side)))
+                    .collect();
+                let hashes: Vec<String> = path
+                    .parents
+                    .iter()
+                    .chain([&path.result])
+                    .map(hash)
+                    .collect();
+                write!(
+                    out,
+                    "{} {} {statuses}\t",
+                    ":".repeat(path.parents.len()) + &modes.join(" "),
+                    hashes.join(" ")
+                )?;
+            } else if self.name_status {
+                write!(out, "{statuses}\t")?;
+            } else if !self.name_only {
+                continue;
+            }
+            writeln!(out, "{}", quote::c_style(&path.path, false))?;
+        }
+        if self.shows_patch() {
+            if self.raw || self.name_only || self.name_status || self.stat || self.numstat {
+                writeln!(out)?;
+            }
+            combined::write_patches(out, paths, &opts, dense)?;
+        }
+        Ok(())
+    }
+}
+
+/// How `log` and `show` diff merges
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct CombinedArgs {
+    /// Show merges with a combined diff against all parents, leaving out the hunks that take
+    /// one parent's side
+    #[clap(long = "cc", overrides_with = "combined")]
+    dense: bool,
+
+    /// Show merges with a combined diff against all parents, with every hunk
+    #[clap(short = 'c', overrides_with = "dense")]
+    combined: bool,
+}
+
+impl CombinedArgs {
+    /// Whether a combined diff was asked for, which `log` otherwise doesn't show
+    pub(crate) fn is_requested(&self) -> bool {
+        self.dense || self.combined
+    }
+
+    /// Whether combined patches are dense, as they are unless `-c` comes last
+    pub(crate) fn is_dense(&self) -> bool {
+        !self.combined
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// Compare the index with HEAD (or the given commit) instead of the working tree
+    #[clap(long = "cached", visible_alias = "staged")]
+    cached: bool,
+
+    #[clap(flatten)]
+    format: DiffFormatArgs,
+
+    /// Exit with status 1 if there are differences
+    #[clap(long = "exit-code")]
+    exit_code: bool,
+
+    /// Print nothing, implies --exit-code
+    #[clap(long = "quiet")]
+    quiet: bool,
+
+    /// Revisions to compare, optionally followed by paths
+    args: Vec<String>,
+
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+pub(crate) fn invoke(args: DiffArgs) -> anyhow::Result<()> {
+    // Leading arguments that name revisions are revisions, the rest are paths
+    let mut revisions = Vec::new();
+    let mut paths = args.paths.clone();
+    for (i, arg) in args.args.iter().enumerate() {
+        if let Some((old, new)) = arg.split_once("..").filter(|_| !arg.contains("...")) {
+            let old = if old.is_empty() { "HEAD" } else { old };
+            let new = if new.is_empty() { "HEAD" } else { new };
+            revisions.push(tree_of(old)?);
+            revisions.push(tree_of(new)?);
+            continue;
+        }
+        anyhow::ensure!(
+            !arg.contains("..."),
+            "Symmetric difference ranges are not supported"
+        );
+        match revision::resolve(arg) {
+            Ok(_) if std::path::Path::new(arg).exists() => anyhow::bail!(
+                "Ambiguous argument '{arg}': both revision and filename, use '--' to separate paths from revisions"
+            ),
+            Ok(_) => revisions.push(tree_of(arg)?),
+            Err(_) => {
+                if let Some(arg) = args.args[i..].iter().find(|arg| !std::path::Path::new(arg).exists()) {
+                    anyhow::bail!(
+                        "Ambiguous argument '{arg}': unknown revision or path not in the working tree"
+                    );
+                }
+                paths.splice(0..0, args.args[i..].iter().cloned());
+                break;
+            }
+        }
+    }
+    let pathspec = Pathspec::new(&paths);
+
+    let index = Index::read()?;
+    let mut unmerged = Vec::new();
+    let unchanged = args.format.find_copies_harder(true)?;
+    let pairs = match (args.cached, revisions.as_slice()) {
+        (true, []) => {
+            let head = match crate::refs::head_commit()? {
+                Some(head) => Some(Commit::read(&head)?.tree),
+                None => None,
+            };
+            let (pairs, conflicts) =
+                files::tree_to_index(head.as_ref(), &index, &pathspec, unchanged)?;
+            unmerged = conflicts;
+            pairs
+        }
+        (true, [tree]) => {
+            let (pairs, conflicts) =
+                files::tree_to_index(Some(tree), &index, &pathspec, unchanged)?;
+            unmerged = conflicts;
+            pairs
+        }
+        (false, []) => {
+            let (pairs, conflicts) = files::index_to_worktree(&index, &pathspec, unchanged)?;
+            unmerged = conflicts;
+            pairs
+        }
+        (false, [tree]) => files::tree_to_worktree(Some(tree), &index, &pathspec, unchanged)?,
+        (false, [old, new]) => files::tree_to_tree(Some(old), Some(new), &pathspec, unchanged)?,
+        _ => anyhow::bail!("Too many revisions"),
+    };
+    let pairs = match args.format.rename_options(true)? {
+        Some(opts) => rename::detect(pairs, &opts)?,
+        None => pairs,
+    };
+
+    if !args.quiet {
+        let mut out = std::io::stdout().lock();
+        for path in &unmerged {
+            writeln!(out, "* Unmerged path {}", quote::c_style(path, false))?;
+        }
+        args.format
+            .write(&mut out, &pairs)
+            .context("Writing diff")?;
+        out.flush()?;
+    }
+    if (args.exit_code || args.quiet) && !pairs.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn tree_of(rev: &str) -> anyhow::Result<[u8; 20]> {
+    let hash = revision::resolve(rev)?;
+    revision::peel_to_tree(&hash).with_context(|| format!("'{rev}' is not a tree-ish"))
+}
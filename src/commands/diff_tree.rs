@@ -0,0 +1,104 @@
+use std::io::Write;
+
+use anyhow::Context;
+
+use crate::{
+    commands::diff::DiffFormatArgs,
+    diff::{
+        rename,
+        tree::{diff_trees, TreeDiffOptions},
+    },
+    object::commit::Commit,
+    pathspec::Pathspec,
+    revision,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct DiffTreeArgs {
+    /// Recurse into subtrees
+    #[clap(short = 'r')]
+    recursive: bool,
+
+    /// Show tree entries themselves as well when recursing, implies -r
+    #[clap(short = 't')]
+    show_trees: bool,
+
+    /// Show a root commit as adding all its files
+    #[clap(long = "root")]
+    root: bool,
+
+    /// Do not print the commit id before the changes of a commit
+    #[clap(long = "no-commit-id")]
+    no_commit_id: bool,
+
+    #[clap(flatten)]
+    format: DiffFormatArgs,
+
+    /// Two tree-ishes to compare, or one commit to compare with its parent, followed by paths
+    #[clap(required = true)]
+    args: Vec<String>,
+
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+pub(crate) fn invoke(mut args: DiffTreeArgs) -> anyhow::Result<()> {
+    args.format.use_plumbing_defaults();
+    let first = revision::resolve(&args.args[0])?;
+    let second = match args.args.get(1) {
+        Some(arg) => revision::resolve(arg).ok(),
+        None => None,
+    };
+    let revisions = if second.is_some() { 2 } else { 1 };
+    let mut paths = args.args[revisions..].to_vec();
+    paths.extend(args.paths.iter().cloned());
+    let pathspec = Pathspec::new(&paths);
+
+    // With a single commit, its changes relative to its parent are shown under its id
+    let (old, new, commit) = match second {
+        Some(second) => (
+            Some(revision::peel_to_tree(&first)?),
+            Some(revision::peel_to_tree(&second)?),
+            None,
+        ),
+        None => {
+            let commit = revision::peel_to_commit(&first)?;
+            let parsed = Commit::read(&commit)?;
+            match parsed.parents.as_slice() {
+                [] if !args.root => return Ok(()),
+                [] => (None, Some(parsed.tree), Some(commit)),
+                [parent] => (
+                    Some(Commit::read(parent)?.tree),
+                    Some(parsed.tree),
+                    Some(commit),
+                ),
+                // Merges need a combined diff, which is not shown by default
+                _ => return Ok(()),
+            }
+        }
+    };
+
+    let renames = args.format.rename_options(false)?;
+    let opts = TreeDiffOptions {
+        recursive: args.recursive || args.show_trees || args.format.needs_files(),
+        show_trees: args.show_trees,
+        unchanged: args.format.find_copies_harder(false)?,
+    };
+    let mut pairs = diff_trees(old.as_ref(), new.as_ref(), &pathspec, opts)?;
+    if let Some(renames) = renames {
+        pairs = rename::detect(pairs, &renames)?;
+    }
+    if pairs.is_empty() {
+        return Ok(());
+    }
+
+    let mut out = std::io::stdout().lock();
+    if let (Some(commit), false) = (commit, args.no_commit_id) {
+        writeln!(out, "{}", hex::encode(commit))?;
+    }
+    args.format
+        .write(&mut out, &pairs)
+        .context("Writing diff")?;
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,294 @@
+use std::{collections::BTreeMap, fs, io::Write, path::PathBuf};
+
+use anyhow::Context;
+
+use crate::{
+    commands::diff::DiffFormatArgs,
+    diff::{files, patch::FilePair, rename},
+    mail,
+    object::commit::{Commit, Signature},
+    pathspec::Pathspec,
+    pretty,
+    revwalk::{RevWalk, Revisions, WalkOptions},
+};
+
+/// Columns the shortlog of a cover letter is wrapped at
+const SHORTLOG_WIDTH: usize = 72;
+
+/// Longest patch file name, including the number but not `.patch`
+const NAME_MAX: usize = 57;
+
+#[derive(clap::Args, Debug)]
+pub struct FormatPatchArgs {
+    /// Number the patches in the subjects, as in `[PATCH n/m]`, even when there is just one
+    #[clap(short = 'n', long = "numbered")]
+    numbered: bool,
+
+    /// Don't number the patches in the subjects, even when there are several
+    #[clap(short = 'N', long = "no-numbered", conflicts_with = "numbered")]
+    no_numbered: bool,
+
+    /// Keep the subjects as they are, without a `[PATCH]` prefix
+    #[clap(short = 'k', long = "keep-subject")]
+    keep_subject: bool,
+
+    /// The prefix of the subjects in place of `PATCH`
+    #[clap(long = "subject-prefix", default_value = "PATCH")]
+    subject_prefix: String,
+
+    /// Number the patches from <n>
+    #[clap(long = "start-number", value_name = "n", default_value_t = 1)]
+    start_number: usize,
+
+    /// Write a patch 0 with a summary of the series, to fill in before sending
+    #[clap(long = "cover-letter")]
+    cover_letter: bool,
+
+    /// Write all patches to stdout as one mailbox instead of a file each
+    #[clap(long = "stdout")]
+    stdout: bool,
+
+    /// Write the patch files to <dir> instead of the current directory
+    #[clap(
+        short = 'o',
+        long = "output-directory",
+        value_name = "dir",
+        conflicts_with = "stdout"
+    )]
+    output_directory: Option<PathBuf>,
+
+    /// Write patches for the last <n> commits; `-<n>` is short for it
+    #[clap(long = "max-count", value_name = "n")]
+    max_count: Option<usize>,
+
+    #[clap(flatten)]
+    diff: DiffFormatArgs,
+
+    /// The commits: a range, or a revision whose descendants up to HEAD are written
+    revisions: Vec<String>,
+}
+
+pub(crate) fn invoke(mut args: FormatPatchArgs) -> anyhow::Result<()> {
+    args.diff.use_mail_defaults();
+    let commits = commits(&args)?;
+    if commits.is_empty() {
+        return Ok(());
+    }
+
+    let total = args.start_number + commits.len() - 1;
+    let numbered = !args.no_numbered && (args.numbered || commits.len() > 1 || args.cover_letter);
+    let prefix = |number: usize| {
+        if args.keep_subject {
+            String::new()
+        } else if numbered {
+            format!("[{} {number}/{total}]", args.subject_prefix)
+        } else {
+            format!("[{}]", args.subject_prefix)
+        }
+    };
+    if let Some(dir) = &args.output_directory {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("could not create directory '{}'", dir.display()))?;
+    }
+
+    if args.cover_letter {
+        let mut out = Vec::new();
+        write_cover_letter(
+            &mut out,
+            &commits,
+            &args.diff,
+            &prefix(args.start_number - 1),
+        )?;
+        emit(&args, args.start_number - 1, "cover-letter", &out, false)?;
+    }
+    for (i, (hash, commit)) in commits.iter().enumerate() {
+        let number = args.start_number + i;
+        let mut out = Vec::new();
+        let mut headers = String::new();
+        mail::write_headers(
+            &mut headers,
+            hash,
+            &commit.author,
+            &prefix(number),
+            &pretty::subject(&commit.message),
+            !commit.message.is_ascii(),
+        );
+        out.extend_from_slice(headers.as_bytes());
+        let body = pretty::body(&commit.message);
+        if !body.is_empty() {
+            out.extend_from_slice(body.as_bytes());
+            if !body.ends_with('\n') {
+                out.push(b'\n');
+            }
+        }
+        writeln!(out, "---")?;
+        let pairs = changes(commit, &args.diff)?;
+        args.diff.write(&mut out, &pairs)?;
+        write_signature(&mut out)?;
+        let subject = pretty::sanitized_subject(&commit.message);
+        emit(&args, number, &subject, &out, i > 0)?;
+    }
+    Ok(())
+}
+
+/// The commits to write, oldest first, leaving out merges
+fn commits(args: &FormatPatchArgs) -> anyhow::Result<Vec<([u8; 20], Commit)>> {
+    let mut revs = args.revisions.clone();
+    // A single revision stands for the commits since it, unless a count is given
+    if args.max_count.is_none()
+        && revs.len() == 1
+        && !revs[0].contains("..")
+        && !revs[0].starts_with('^')
+    {
+        revs = vec![format!("^{}", revs[0]), String::from("HEAD")];
+    }
+    anyhow::ensure!(
+        !revs.is_empty() || args.max_count.is_some(),
+        "usage: git format-patch [<options>] [<since> | <revision-range>]"
+    );
+    let revisions = Revisions::parse(&revs, &[], true)?;
+    let opts = WalkOptions {
+        no_merges: true,
+        max_count: args.max_count,
+        ..WalkOptions::default()
+    };
+    let mut walk = RevWalk::new(&revisions, opts)?;
+    let mut commits = Vec::new();
+    while let Some(hash) = walk.next()? {
+        commits.push((hash, Commit::read(&hash)?));
+    }
+    commits.reverse();
+    Ok(commits)
+}
+
+/// What a commit changed relative to its first parent
+fn changes(commit: &Commit, diff: &DiffFormatArgs) -> anyhow::Result<Vec<FilePair>> {
+    let parent = match commit.parents.first() {
+        Some(parent) => Some(Commit::read(parent)?.tree),
+        None => None,
+    };
+    tree_changes(parent.as_ref(), &commit.tree, diff)
+}
+
+fn tree_changes(
+    old: Option<&[u8; 20]>,
+    new: &[u8; 20],
+    diff: &DiffFormatArgs,
+) -> anyhow::Result<Vec<FilePair>> {
+    let unchanged = diff.find_copies_harder(true)?;
+    let pairs = files::tree_to_tree(old, Some(new), &Pathspec::new(&[]), unchanged)?;
+    Ok(match diff.rename_options(true)? {
+        Some(opts) => rename::detect(pairs, &opts)?,
+        None => pairs,
+    })
+}
+
+/// Writes the cover letter of a series: from the committer, with placeholders for the subject
+/// and the blurb, then the subjects of the patches by author and what the series changes
+fn write_cover_letter(
+    out: &mut Vec<u8>,
+    commits: &[([u8; 20], Commit)],
+    diff: &DiffFormatArgs,
+    prefix: &str,
+) -> anyhow::Result<()> {
+    let (tip, last) = commits.last().context("no commits for the cover letter")?;
+    let committer = Signature::current("COMMITTER")?;
+    // Mark the letter as 8-bit if any of the patches might need it
+    let eight_bit = commits
+        .iter()
+        .any(|(_, commit)| !commit.message.is_ascii() || !commit.author.name.is_ascii());
+    let mut headers = String::new();
+    mail::write_headers(
+        &mut headers,
+        tip,
+        &committer,
+        prefix,
+        "*** SUBJECT HERE ***",
+        eight_bit,
+    );
+    out.extend_from_slice(headers.as_bytes());
+    writeln!(out, "*** BLURB HERE ***\n")?;
+
+    let mut shortlog: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (_, commit) in commits {
+        shortlog
+            .entry(&commit.author.name)
+            .or_default()
+            .push(pretty::subject(&commit.message));
+    }
+    for (author, subjects) in &shortlog {
+        writeln!(out, "{author} ({}):", subjects.len())?;
+        for subject in subjects {
+            writeln!(out, "{}", wrap(subject, 2, 4, SHORTLOG_WIDTH))?;
+        }
+        writeln!(out)?;
+    }
+
+    let first = &commits[0].1;
+    let base = match first.parents.first() {
+        Some(parent) => Some(Commit::read(parent)?.tree),
+        None => None,
+    };
+    let pairs = tree_changes(base.as_ref(), &last.tree, diff)?;
+    diff.write_stat_and_summary(out, &pairs)?;
+    writeln!(out)?;
+    Ok(write_signature(out)?)
+}
+
+/// Wraps text at spaces so that no line but one with a single long word goes past `width`,
+/// indenting the first line by `first_indent` and the rest by `indent`
+fn wrap(text: &str, first_indent: usize, indent: usize, width: usize) -> String {
+    let mut out = " ".repeat(first_indent);
+    let mut column = first_indent;
+    for (i, word) in text.split(' ').filter(|word| !word.is_empty()).enumerate() {
+        let word_width = word.chars().count();
+        if i > 0 {
+            if column + 1 + word_width <= width {
+                out.push(' ');
+                column += 1;
+            } else {
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+                column = indent;
+            }
+        }
+        out.push_str(word);
+        column += word_width;
+    }
+    out
+}
+
+/// Ends a patch with the version that wrote it, as a mail signature
+fn write_signature(out: &mut Vec<u8>) -> std::io::Result<()> {
+    writeln!(out, "-- \n{}\n", env!("CARGO_PKG_VERSION"))
+}
+
+/// Writes a patch to stdout, set apart from the one before by a blank line if it `follows`
+/// one, or to a file named after its number and subject, printing the file's name
+fn emit(
+    args: &FormatPatchArgs,
+    number: usize,
+    name: &str,
+    content: &[u8],
+    follows: bool,
+) -> anyhow::Result<()> {
+    if args.stdout {
+        let mut out = std::io::stdout().lock();
+        if follows {
+            writeln!(out)?;
+        }
+        out.write_all(content)?;
+        return Ok(());
+    }
+    let mut file_name = format!("{number:04}-{name}");
+    file_name.truncate(NAME_MAX);
+    file_name.push_str(".patch");
+    let path = match &args.output_directory {
+        Some(dir) => dir.join(file_name),
+        None => PathBuf::from(file_name),
+    };
+    fs::write(&path, content)
+        .with_context(|| format!("cannot open patch file {}", path.display()))?;
+    println!("{}", path.display());
+    Ok(())
+}
@@ -0,0 +1,400 @@
+use std::{fs, io::Write, ops::ControlFlow};
+
+use anyhow::Context;
+
+use crate::{
+    grep::{BinaryFiles, Matcher, PatternType, Report, SearchOptions, Token},
+    index::Index,
+    object::{
+        read::{flatten_tree, read_object_of_kind},
+        ObjectKind, MODE_EXECUTABLE, MODE_FILE,
+    },
+    parallel,
+    pathspec::Pathspec,
+    quote, revision,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct GrepArgs {
+    /// Search the blobs staged in the index instead of the working tree files
+    #[clap(long = "cached")]
+    cached: bool,
+
+    /// Take patterns as POSIX basic regular expressions (the default)
+    #[clap(short = 'G', long = "basic-regexp", overrides_with_all = ["extended_regexp", "fixed_strings", "perl_regexp"])]
+    basic_regexp: bool,
+
+    /// Take patterns as POSIX extended regular expressions
+    #[clap(short = 'E', long = "extended-regexp", overrides_with_all = ["basic_regexp", "fixed_strings", "perl_regexp"])]
+    extended_regexp: bool,
+
+    /// Take patterns as fixed strings
+    #[clap(short = 'F', long = "fixed-strings", overrides_with_all = ["basic_regexp", "extended_regexp", "perl_regexp"])]
+    fixed_strings: bool,
+
+    /// Take patterns as Perl-compatible regular expressions
+    #[clap(short = 'P', long = "perl-regexp", overrides_with_all = ["basic_regexp", "extended_regexp", "fixed_strings"])]
+    perl_regexp: bool,
+
+    /// Ignore case differences between patterns and lines
+    #[clap(short = 'i', long = "ignore-case")]
+    ignore_case: bool,
+
+    /// Only match patterns at word boundaries
+    #[clap(short = 'w', long = "word-regexp")]
+    word_regexp: bool,
+
+    /// Select the lines that don't match
+    #[clap(short = 'v', long = "invert-match")]
+    invert_match: bool,
+
+    /// Prefix lines with their line number
+    #[clap(short = 'n', long = "line-number")]
+    line_number: bool,
+
+    /// Don't prefix lines with the name of their file
+    #[clap(short = 'h', overrides_with = "with_filename")]
+    no_filename: bool,
+
+    /// Prefix lines with the name of their file (the default)
+    #[clap(short = 'H', overrides_with = "no_filename")]
+    with_filename: bool,
+
+    /// Only show the names of files with matching lines
+    #[clap(short = 'l', long = "files-with-matches", visible_alias = "name-only")]
+    files_with_matches: bool,
+
+    /// Only show the names of files without matching lines
+    #[clap(short = 'L', long = "files-without-match")]
+    files_without_match: bool,
+
+    /// Show the number of matching lines of each file instead of the lines
+    #[clap(short = 'c', long = "count")]
+    count: bool,
+
+    /// Show nothing; the exit status tells whether anything matched
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Show lines of trailing context after matching lines
+    #[clap(short = 'A', long = "after-context")]
+    after_context: Option<usize>,
+
+    /// Show lines of leading context before matching lines
+    #[clap(short = 'B', long = "before-context")]
+    before_context: Option<usize>,
+
+    /// Show lines of context around matching lines
+    #[clap(short = 'C', long = "context")]
+    context: Option<usize>,
+
+    /// Search binary files as if they were text
+    #[clap(short = 'a', long = "text")]
+    text: bool,
+
+    /// Don't search binary files
+    #[clap(short = 'I')]
+    skip_binary: bool,
+
+    /// Only show files where each pattern or-ed at the top matches some line
+    #[clap(long = "all-match")]
+    all_match: bool,
+
+    /// The number of files searched at once; 0 for one per core
+    #[clap(long = "threads", default_value_t = 0)]
+    threads: usize,
+
+    /// Print help, as `-h` means no file names
+    #[clap(long = "help", action = clap::ArgAction::Help)]
+    help: Option<bool>,
+
+    #[clap(flatten)]
+    operands: Operands,
+}
+
+#[derive(clap::Args, Debug)]
+struct RawOperands {
+    /// Pattern to search for; several are or-ed, or combined with --and, --or, --not and ( )
+    #[clap(short = 'e', long = "regexp", allow_hyphen_values = true)]
+    patterns: Vec<String>,
+
+    /// Only match lines that both the expressions around it match
+    #[clap(long = "and", num_args = 0, default_missing_value = "--and", action = clap::ArgAction::Append)]
+    and: Vec<String>,
+
+    /// Match lines that either of the expressions around it matches
+    #[clap(long = "or", num_args = 0, default_missing_value = "--or", action = clap::ArgAction::Append)]
+    or: Vec<String>,
+
+    /// Match lines that the expression after it doesn't match
+    #[clap(long = "not", num_args = 0, default_missing_value = "--not", action = clap::ArgAction::Append)]
+    not: Vec<String>,
+
+    /// The pattern unless one is given with -e, then revisions to search instead of the
+    /// working tree, then paths to limit the search to
+    args: Vec<String>,
+
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+/// Patterns and the operators between them, which apply where they appear, so they're kept in
+/// order. `(` and `)` are taken among the positional arguments.
+#[derive(Debug)]
+struct Operands {
+    tokens: Vec<Token>,
+    args: Vec<String>,
+    paths: Vec<String>,
+}
+
+impl clap::FromArgMatches for Operands {
+    fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, clap::Error> {
+        let raw = RawOperands::from_arg_matches(matches)?;
+        let mut tokens = Vec::new();
+        let mut args = Vec::new();
+        for (id, values) in [
+            ("patterns", raw.patterns),
+            ("and", raw.and),
+            ("or", raw.or),
+            ("not", raw.not),
+            ("args", raw.args),
+        ] {
+            let Some(indices) = matches.indices_of(id) else {
+                continue;
+            };
+            for (index, value) in indices.zip(values) {
+                let token = match (id, value.as_str()) {
+                    ("patterns", _) => Token::Pattern(value),
+                    ("and", _) => Token::And,
+                    ("or", _) => Token::Or,
+                    ("not", _) => Token::Not,
+                    (_, "(") => Token::Open,
+                    (_, ")") => Token::Close,
+                    _ => {
+                        args.push((index, value));
+                        continue;
+                    }
+                };
+                tokens.push((index, token));
+            }
+        }
+        tokens.sort_by_key(|(index, _)| *index);
+        args.sort();
+        Ok(Operands {
+            tokens: tokens.into_iter().map(|(_, token)| token).collect(),
+            args: args.into_iter().map(|(_, arg)| arg).collect(),
+            paths: raw.paths,
+        })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &clap::ArgMatches) -> Result<(), clap::Error> {
+        *self = Operands::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl clap::Args for Operands {
+    fn augment_args(cmd: clap::Command) -> clap::Command {
+        RawOperands::augment_args(cmd)
+    }
+
+    fn augment_args_for_update(cmd: clap::Command) -> clap::Command {
+        RawOperands::augment_args_for_update(cmd)
+    }
+}
+
+/// A file to search, under the name its lines are shown with
+struct Target {
+    name: String,
+    source: Source,
+}
+
+enum Source {
+    /// A tracked file of the working tree
+    Worktree(String),
+    Blob([u8; 20]),
+}
+
+pub(crate) fn invoke(args: GrepArgs) -> anyhow::Result<()> {
+    let Operands {
+        mut tokens,
+        args: mut rest,
+        paths,
+    } = args.operands;
+    // Without -e, the first argument is the pattern
+    if !tokens
+        .iter()
+        .any(|token| matches!(token, Token::Pattern(_)))
+    {
+        anyhow::ensure!(!rest.is_empty(), "no pattern given");
+        tokens.push(Token::Pattern(rest.remove(0)));
+    }
+    let kind = if args.extended_regexp {
+        PatternType::Extended
+    } else if args.fixed_strings {
+        PatternType::Fixed
+    } else if args.perl_regexp {
+        PatternType::Perl
+    } else {
+        PatternType::Basic
+    };
+    let options = SearchOptions {
+        matcher: Matcher::new(&tokens, kind, args.ignore_case, args.word_regexp)?,
+        invert: args.invert_match,
+        all_match: args.all_match,
+        report: if args.quiet {
+            Report::Quiet
+        } else if args.files_with_matches {
+            Report::FilesWithMatches
+        } else if args.files_without_match {
+            Report::FilesWithoutMatch
+        } else if args.count {
+            Report::Count
+        } else {
+            Report::Lines
+        },
+        binary: if args.text {
+            BinaryFiles::Text
+        } else if args.skip_binary {
+            BinaryFiles::Skip
+        } else {
+            BinaryFiles::Matches
+        },
+        line_number: args.line_number,
+        with_filename: !args.no_filename,
+        before: args.before_context.or(args.context).unwrap_or(0),
+        after: args.after_context.or(args.context).unwrap_or(0),
+    };
+
+    // Without `--`, revisions come first and the first argument that isn't one starts the paths
+    let mut revisions = Vec::new();
+    let mut paths_given = Vec::new();
+    for arg in rest {
+        if paths_given.is_empty() {
+            if let Ok(hash) = revision::resolve(&arg) {
+                revisions.push((arg, hash));
+                continue;
+            }
+        }
+        anyhow::ensure!(
+            fs::symlink_metadata(&arg).is_ok(),
+            "ambiguous argument '{arg}': unknown revision or path not in the working tree.\nUse '--' to separate paths from revisions, like this:\n'git <command> [<revision>...] -- [<file>...]'"
+        );
+        paths_given.push(arg);
+    }
+    paths_given.extend(paths);
+    let pathspec = Pathspec::new(&paths_given);
+    anyhow::ensure!(
+        !args.cached || revisions.is_empty(),
+        "both --cached and trees are given"
+    );
+
+    let targets = if revisions.is_empty() {
+        index_targets(&pathspec, args.cached)?
+    } else {
+        let mut targets = Vec::new();
+        for (name, hash) in &revisions {
+            targets.extend(tree_targets(name, hash, &pathspec)?);
+        }
+        targets
+    };
+
+    let threads = match args.threads {
+        0 => parallel::default_threads(),
+        threads => threads,
+    };
+    let mut out = std::io::stdout().lock();
+    let mut hit = false;
+    let mut shown_lines = false;
+    parallel::for_each_ordered(
+        &targets,
+        threads,
+        |target| -> anyhow::Result<_> {
+            let content = match &target.source {
+                Source::Worktree(path) => match fs::symlink_metadata(path) {
+                    // Files missing or no longer regular are passed over
+                    Ok(metadata) if metadata.is_file() => {
+                        fs::read(path).with_context(|| format!("Reading {path}"))?
+                    }
+                    _ => return Ok(None),
+                },
+                Source::Blob(hash) => read_object_of_kind(&hex::encode(hash), ObjectKind::Blob)?,
+            };
+            Ok(Some(options.search(&target.name, &content)))
+        },
+        |result| {
+            let Some(matches) = result? else {
+                return Ok(ControlFlow::Continue(()));
+            };
+            if matches.shows_lines && shown_lines && options.shows_context() {
+                out.write_all(b"--\n")?;
+            }
+            shown_lines |= matches.shows_lines;
+            out.write_all(&matches.output)?;
+            hit |= matches.hit;
+            Ok(if hit && options.report == Report::Quiet {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            })
+        },
+    )?;
+    out.flush()?;
+    if !hit {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// The regular files of the index, read from the working tree or, with `cached`, from their
+/// staged blobs. Unmerged paths are searched once in the working tree and not at all in the
+/// index.
+fn index_targets(pathspec: &Pathspec, cached: bool) -> anyhow::Result<Vec<Target>> {
+    let index = Index::read()?;
+    let mut targets = Vec::new();
+    let mut last_path = None;
+    for entry in index.entries() {
+        if last_path == Some(&entry.path) {
+            continue;
+        }
+        last_path = Some(&entry.path);
+        if !is_regular(entry.mode) || !pathspec.matches(&entry.path) {
+            continue;
+        }
+        let source = if !cached {
+            Source::Worktree(entry.path.clone())
+        } else if entry.stage == 0 {
+            Source::Blob(entry.hash)
+        } else {
+            continue;
+        };
+        targets.push(Target {
+            name: quote::c_style(&entry.path, false),
+            source,
+        });
+    }
+    Ok(targets)
+}
+
+/// The regular files of a tree-ish, named after it, or the blob it names
+fn tree_targets(name: &str, hash: &[u8; 20], pathspec: &Pathspec) -> anyhow::Result<Vec<Target>> {
+    if revision::kind_of(hash)? == ObjectKind::Blob {
+        return Ok(vec![Target {
+            name: name.to_string(),
+            source: Source::Blob(*hash),
+        }]);
+    }
+    let tree = revision::peel_to_tree(hash)?;
+    Ok(flatten_tree(&tree, "")?
+        .into_iter()
+        .filter(|item| is_regular(item.mode) && pathspec.matches(&item.name))
+        .map(|item| Target {
+            name: format!("{name}:{}", quote::c_style(&item.name, false)),
+            source: Source::Blob(item.hash),
+        })
+        .collect())
+}
+
+fn is_regular(mode: u32) -> bool {
+    mode == MODE_FILE || mode == MODE_EXECUTABLE
+}
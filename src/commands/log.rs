@@ -0,0 +1,496 @@
+use std::io::{IsTerminal, Write};
+
+use crate::{
+    commands::diff::{CombinedArgs, DiffFormatArgs},
+    config,
+    date::{self, DateMode},
+    decorate::Decorations,
+    diff::{files, patch::FilePair, pickaxe::Pickaxe, rename},
+    graph::Graph,
+    grep::{self, PatternType},
+    line_log::LineLog,
+    object::commit::Commit,
+    pathspec::Pathspec,
+    pretty::{self, Format, PrettyOptions, ShownCommit},
+    revwalk::{CommitFilter, RevWalk, Revisions, Sort, WalkOptions},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct LogArgs {
+    /// Show at most this many commits
+    #[clap(short = 'n', long = "max-count")]
+    max_count: Option<usize>,
+
+    /// Show commits more recent than a date
+    #[clap(long = "since", visible_alias = "after")]
+    since: Option<String>,
+
+    /// Show commits older than a date
+    #[clap(long = "until", visible_alias = "before")]
+    until: Option<String>,
+
+    /// Show commits whose author matches a pattern
+    #[clap(long = "author")]
+    author: Vec<String>,
+
+    /// Show commits whose committer matches a pattern
+    #[clap(long = "committer")]
+    committer: Vec<String>,
+
+    /// Show commits whose message matches a pattern
+    #[clap(long = "grep")]
+    grep: Vec<String>,
+
+    /// Require all --grep patterns to match instead of any
+    #[clap(long = "all-match")]
+    all_match: bool,
+
+    /// Match patterns regardless of case
+    #[clap(short = 'i', long = "regexp-ignore-case")]
+    ignore_case: bool,
+
+    /// Take patterns as extended regular expressions
+    #[clap(short = 'E', long = "extended-regexp")]
+    extended: bool,
+
+    /// Take patterns as fixed strings
+    #[clap(short = 'F', long = "fixed-strings")]
+    fixed: bool,
+
+    /// Follow only the first parent of merge commits
+    #[clap(long = "first-parent")]
+    first_parent: bool,
+
+    /// Do not show merge commits
+    #[clap(long = "no-merges")]
+    no_merges: bool,
+
+    /// Show no parents before all of their children, otherwise by commit date
+    #[clap(long = "date-order")]
+    date_order: bool,
+
+    /// Show no parents before all of their children, otherwise by author date
+    #[clap(long = "author-date-order")]
+    author_date_order: bool,
+
+    /// Show no parents before all of their children, keeping lines of history together
+    #[clap(long = "topo-order")]
+    topo_order: bool,
+
+    /// Show each commit on one line with its abbreviated id and subject
+    #[clap(long = "oneline")]
+    oneline: bool,
+
+    /// Format of the commits: oneline, short, medium, full, fuller, raw or format:<template>
+    #[clap(long = "pretty", num_args = 0..=1, require_equals = true, default_missing_value = "medium")]
+    pretty: Option<String>,
+
+    /// Template for the commits, like --pretty=tformat:<template>
+    #[clap(long = "format")]
+    format: Option<String>,
+
+    /// Abbreviate commit ids
+    #[clap(long = "abbrev-commit")]
+    abbrev_commit: bool,
+
+    /// Format of dates: default, iso, iso-strict, rfc, short, raw, unix or relative
+    #[clap(long = "date")]
+    date: Option<String>,
+
+    /// Draw the history as a graph beside the commits
+    #[clap(long = "graph")]
+    graph: bool,
+
+    /// Show the names of refs pointing at commits: short, full, auto or no
+    #[clap(long = "decorate", num_args = 0..=1, require_equals = true, default_missing_value = "short")]
+    decorate: Option<String>,
+
+    /// Don't show the names of refs pointing at commits
+    #[clap(long = "no-decorate")]
+    no_decorate: bool,
+
+    /// Only show commits refs point at, and the merges and roots that connect them
+    #[clap(long = "simplify-by-decoration")]
+    simplify_by_decoration: bool,
+
+    /// Show commits changing the number of occurrences of a string
+    #[clap(short = 'S', value_name = "string")]
+    pickaxe_count: Option<String>,
+
+    /// Show commits with an added or removed line matching a regex
+    #[clap(short = 'G', value_name = "regex")]
+    pickaxe_grep: Option<String>,
+
+    /// Show every file of a commit found by -S or -G, not only those matching
+    #[clap(long = "pickaxe-all")]
+    pickaxe_all: bool,
+
+    /// Take the -S string as an extended regular expression
+    #[clap(long = "pickaxe-regex")]
+    pickaxe_regex: bool,
+
+    /// Follow the history of a line range, `<start>,<end>:<file>` or `:<funcname>:<file>`
+    #[clap(short = 'L', value_name = "range:file")]
+    line_ranges: Vec<String>,
+
+    /// Show commits without their diff
+    #[clap(short = 's', long = "no-patch")]
+    no_patch: bool,
+
+    #[clap(flatten)]
+    diff: DiffFormatArgs,
+
+    #[clap(flatten)]
+    combined: CombinedArgs,
+
+    #[clap(flatten)]
+    revisions: RevisionArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct RawRevisionArgs {
+    /// Exclude the revisions that follow, or include them again after another --not
+    #[clap(long = "not", num_args = 0, default_missing_value = "--not", action = clap::ArgAction::Append)]
+    not: Vec<String>,
+
+    /// Take every ref and HEAD as a revision
+    #[clap(long = "all", num_args = 0, default_missing_value = "--all", action = clap::ArgAction::Append)]
+    all: Vec<String>,
+
+    /// Revisions to walk from, followed by paths to limit the history to
+    args: Vec<String>,
+
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+/// Revisions and paths as `log` and `rev-list` take them. `--not` and `--all` apply where they
+/// appear among the revisions, so they're kept in order with them.
+#[derive(Debug)]
+pub struct RevisionArgs {
+    /// Revisions, `--not` and `--all` in the order given
+    pub(crate) args: Vec<String>,
+    pub(crate) paths: Vec<String>,
+}
+
+impl clap::FromArgMatches for RevisionArgs {
+    fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, clap::Error> {
+        let raw = RawRevisionArgs::from_arg_matches(matches)?;
+        let mut args = Vec::new();
+        for (id, values) in [("not", raw.not), ("all", raw.all), ("args", raw.args)] {
+            if let Some(indices) = matches.indices_of(id) {
+                args.extend(indices.zip(values));
+            }
+        }
+        args.sort();
+        Ok(RevisionArgs {
+            args: args.into_iter().map(|(_, arg)| arg).collect(),
+            paths: raw.paths,
+        })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &clap::ArgMatches) -> Result<(), clap::Error> {
+        *self = RevisionArgs::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl clap::Args for RevisionArgs {
+    fn augment_args(cmd: clap::Command) -> clap::Command {
+        RawRevisionArgs::augment_args(cmd)
+    }
+
+    fn augment_args_for_update(cmd: clap::Command) -> clap::Command {
+        RawRevisionArgs::augment_args_for_update(cmd)
+    }
+}
+
+pub(crate) fn invoke(args: LogArgs) -> anyhow::Result<()> {
+    let revisions = Revisions::parse(&args.revisions.args, &args.revisions.paths, true)?;
+
+    let kind = match (args.fixed, args.extended) {
+        (true, _) => PatternType::Fixed,
+        (_, true) => PatternType::Extended,
+        _ => PatternType::Basic,
+    };
+    let compile = |patterns: &[String]| {
+        patterns
+            .iter()
+            .map(|pattern| grep::compile(pattern, kind, args.ignore_case))
+            .collect::<anyhow::Result<Vec<_>>>()
+    };
+    let filter = CommitFilter {
+        authors: compile(&args.author)?,
+        committers: compile(&args.committer)?,
+        messages: compile(&args.grep)?,
+        all_match: args.all_match,
+    };
+    let sort = if args.author_date_order {
+        Sort::AuthorDate
+    } else if args.date_order {
+        Sort::Date
+    } else if args.topo_order
+        || args.graph
+        || args.simplify_by_decoration
+        || !args.line_ranges.is_empty()
+    {
+        Sort::Topo
+    } else {
+        Sort::Default
+    };
+
+    let format = match (&args.format, &args.pretty) {
+        (Some(format), _) => Format::parse(format)?,
+        (None, Some(pretty)) => Format::parse(pretty)?,
+        (None, None) if args.oneline => Format::Oneline,
+        (None, None) => Format::Medium,
+    };
+    let decorate = Decorate::from_args(args.decorate.as_deref(), args.no_decorate)?;
+    // `%d` shows decorations even without `--decorate`
+    let decorations = if decorate != Decorate::No
+        || args.simplify_by_decoration
+        || matches!(format, Format::Template { .. })
+    {
+        Some(Decorations::load(decorate == Decorate::Full)?)
+    } else {
+        None
+    };
+
+    let pickaxe = match (&args.pickaxe_count, &args.pickaxe_grep) {
+        (Some(needle), _) => Some(Pickaxe::count(
+            needle,
+            args.pickaxe_regex,
+            args.ignore_case,
+            args.pickaxe_all,
+        )?),
+        (None, Some(regex)) => Some(Pickaxe::grep(regex, args.ignore_case, args.pickaxe_all)?),
+        (None, None) => None,
+    };
+    let line_log = if args.line_ranges.is_empty() {
+        None
+    } else {
+        anyhow::ensure!(
+            revisions.paths.is_empty(),
+            "-L<range>:<file> cannot be used with pathspec"
+        );
+        anyhow::ensure!(
+            args.diff.is_patch_only(),
+            "-L does not yet support diff formats besides -p and -s"
+        );
+        let start = match revisions.include[..] {
+            [start] => start,
+            [] => anyhow::bail!("No commit specified?"),
+            _ => anyhow::bail!("More than one commit to dig from"),
+        };
+        let renames = args.diff.rename_options(true)?;
+        Some(LineLog::new(&start, &args.line_ranges, renames)?)
+    };
+
+    let opts = WalkOptions {
+        sort,
+        first_parent: args.first_parent,
+        no_merges: args.no_merges,
+        // Commits left out by the pickaxe don't count
+        max_count: args.max_count.filter(|_| pickaxe.is_none()),
+        since: args.since.as_deref().map(date::approxidate).transpose()?,
+        until: args.until.as_deref().map(date::approxidate).transpose()?,
+        paths: (!revisions.paths.is_empty()).then(|| Pathspec::new(&revisions.paths)),
+        decorated: match &decorations {
+            Some(decorations) if args.simplify_by_decoration => {
+                Some(decorations.objects().copied().collect())
+            }
+            _ => None,
+        },
+        rewrite_parents: args.graph || args.simplify_by_decoration,
+        simplify_merges: args.simplify_by_decoration,
+        line_log,
+        filter,
+        ..WalkOptions::default()
+    };
+
+    let pretty = PrettyOptions {
+        format,
+        abbrev_commit: args.abbrev_commit || args.oneline,
+        date_mode: match &args.date {
+            Some(mode) => DateMode::parse(mode)?,
+            None => DateMode::Default,
+        },
+        decorate: decorate != Decorate::No,
+    };
+    let terminator = pretty.format.uses_terminator();
+    let pathspec = Pathspec::new(&revisions.paths);
+    // Line-range history shows its own diff
+    let shows_diff = (args.diff.is_requested() || args.combined.is_requested())
+        && !args.no_patch
+        && args.line_ranges.is_empty();
+    let color = args.diff.patch_options()?.color;
+    let mut max_count = args.max_count;
+
+    let mut walk = RevWalk::new(&revisions, opts)?;
+    let mut graph = args.graph.then(Graph::new);
+    let mut out = std::io::stdout().lock();
+    let mut shown_one = false;
+    // Whether the last entry didn't end in a newline, so there's no line to continue the graph on
+    let mut missing_newline = false;
+    while let Some(hash) = walk.next()? {
+        if max_count == Some(0) {
+            break;
+        }
+        let parents = walk.parents(&hash);
+        if let Some(graph) = &mut graph {
+            let limit = if args.first_parent { 1 } else { parents.len() };
+            let shown_parents = parents
+                .iter()
+                .take(limit)
+                .filter(|parent| walk.is_interesting(parent))
+                .copied()
+                .collect();
+            graph.update(hash, shown_parents);
+        }
+
+        let commit = walk.commit(&hash);
+        let pairs = if (pickaxe.is_some() || shows_diff) && commit.parents.len() <= 1 {
+            let pairs = first_parent_pairs(commit, &args.diff, &pathspec)?;
+            match &pickaxe {
+                Some(pickaxe) => pickaxe.filter(pairs)?,
+                None => pairs,
+            }
+        } else {
+            Vec::new()
+        };
+        // The graph still moves past commits the pickaxe leaves out, marking the gap
+        if pickaxe.is_some() && pairs.is_empty() {
+            continue;
+        }
+        if pickaxe.is_some() {
+            max_count = max_count.map(|count| count - 1);
+        }
+
+        let mut entry = String::new();
+        if shown_one && !terminator {
+            if let (Some(graph), false) = (&mut graph, missing_newline) {
+                graph.show_padding(&mut entry);
+            }
+            entry.push('\n');
+        }
+        shown_one = true;
+        if let Some(graph) = &mut graph {
+            graph.show_commit(&mut entry);
+        }
+        let shown = ShownCommit {
+            hash: &hash,
+            commit: walk.commit(&hash),
+            parents,
+            decorations: &decorations
+                .as_ref()
+                .map(|decorations| decorations.of(&hash))
+                .unwrap_or_default(),
+        };
+        let text = pretty::format_commit(&shown, &pretty)?;
+        missing_newline = !text.ends_with('\n');
+        match &mut graph {
+            Some(graph) => graph.show_commit_msg(&mut entry, &text),
+            None => entry.push_str(&text),
+        }
+        if terminator && !pretty.format.is_empty() {
+            if let (Some(graph), false) = (&mut graph, missing_newline) {
+                graph.show_padding(&mut entry);
+            }
+            entry.push('\n');
+        }
+        write!(out, "{entry}")?;
+
+        let mut diff = Vec::new();
+        if shows_diff && commit.parents.len() <= 1 {
+            args.diff
+                .write_after_message(&mut diff, &pretty.format, &pairs)?;
+        } else if shows_diff && args.combined.is_requested() {
+            args.diff.write_merge_after_message(
+                &mut diff,
+                &pretty.format,
+                commit,
+                &pathspec,
+                args.combined.is_dense(),
+            )?;
+        }
+        if let (Some(line_log), false) = (walk.line_log(), args.no_patch) {
+            writeln!(diff)?;
+            line_log.write_diff(&mut diff, &hash, color)?;
+        }
+        match &mut graph {
+            // The graph goes on beside each line of the diff
+            Some(graph) => {
+                for line in diff.split_inclusive(|&b| b == b'\n') {
+                    let mut padding = String::new();
+                    graph.show_padding(&mut padding);
+                    out.write_all(padding.as_bytes())?;
+                    out.write_all(line)?;
+                }
+            }
+            None => out.write_all(&diff)?,
+        }
+    }
+    Ok(())
+}
+
+/// The changes a commit made to the paths relative to its first parent, or to the empty tree
+/// for a root commit
+fn first_parent_pairs(
+    commit: &Commit,
+    diff: &DiffFormatArgs,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Vec<FilePair>> {
+    let parent = match commit.parents.first() {
+        Some(parent) => Some(Commit::read(parent)?.tree),
+        None => None,
+    };
+    let unchanged = diff.find_copies_harder(true)?;
+    let pairs = files::tree_to_tree(parent.as_ref(), Some(&commit.tree), pathspec, unchanged)?;
+    Ok(match diff.rename_options(true)? {
+        Some(opts) => rename::detect(pairs, &opts)?,
+        None => pairs,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Decorate {
+    Short,
+    Full,
+    Auto,
+    No,
+}
+
+impl Decorate {
+    /// Parses the value of `--decorate` or `log.decorate`
+    fn parse(value: &str) -> Option<Decorate> {
+        match value {
+            "short" | "true" | "yes" | "on" | "1" => Some(Decorate::Short),
+            "full" => Some(Decorate::Full),
+            "auto" => Some(Decorate::Auto),
+            "no" | "false" | "off" | "0" => Some(Decorate::No),
+            _ => None,
+        }
+    }
+
+    /// Resolves `--decorate[=<value>]` and `--no-decorate`, falling back to `log.decorate`;
+    /// `auto` decorates only when writing to a terminal
+    pub(crate) fn from_args(value: Option<&str>, no_decorate: bool) -> anyhow::Result<Decorate> {
+        let decorate = if no_decorate {
+            Decorate::No
+        } else {
+            match value {
+                Some(value) => Decorate::parse(value)
+                    .ok_or_else(|| anyhow::anyhow!("invalid --decorate option: {value}"))?,
+                None => config::read_repo_config()
+                    .get("log.decorate")
+                    .and_then(Decorate::parse)
+                    .unwrap_or(Decorate::Auto),
+            }
+        };
+        Ok(match decorate {
+            Decorate::Auto if std::io::stdout().is_terminal() => Decorate::Short,
+            Decorate::Auto => Decorate::No,
+            decorate => decorate,
+        })
+    }
+}
@@ -0,0 +1,767 @@
+use std::{fs, io::Write, path::Path};
+
+use anyhow::Context;
+
+use crate::{
+    diff::{
+        files::tree_to_tree,
+        patch::{abbrev, write_stat, write_summary, PatchOptions},
+        rename::{self, RenameOptions},
+    },
+    index::Index,
+    merge::{
+        self,
+        content::{ConflictStyle, Favor, FileOptions, Labels},
+        octopus::{self, Outcome},
+        MergeOptions,
+    },
+    merge_base::{self, CommitGraph},
+    object::{
+        commit::{parse_hash, Commit, Tag},
+        read::read_object,
+        write::{write_commit, write_tree_from_paths},
+        ObjectKind,
+    },
+    pathspec::Pathspec,
+    pretty::{self, Format, PrettyOptions, ShownCommit},
+    refs::{self, Head},
+    revision,
+    revwalk::{RevWalk, Revisions, WalkOptions},
+    unpack::{self, tree_blobs, UnpackOptions},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct MergeArgs {
+    /// Create a merge commit even when the branch could be fast-forwarded
+    #[clap(long = "no-ff", conflicts_with = "ff_only")]
+    no_ff: bool,
+
+    /// Refuse to merge unless the branch can be fast-forwarded
+    #[clap(long = "ff-only")]
+    ff_only: bool,
+
+    /// Merge into the index and working tree without committing or recording a merge
+    #[clap(long = "squash")]
+    squash: bool,
+
+    /// Message for the merge commit
+    #[clap(short = 'm', long = "message")]
+    message: Option<String>,
+
+    /// Merge strategy: `ort`, or `recursive` which does the same, `octopus` for more than two
+    /// heads, `ours` to keep the current tree as it is, or `subtree` to merge a project kept in
+    /// a subdirectory of the other
+    #[clap(short = 's', long = "strategy")]
+    strategy: Option<String>,
+
+    /// Option for the strategy: `ours` or `theirs` to resolve conflicting hunks to that side,
+    /// `ignore-space-change` to ignore changes in the amount of whitespace, `subtree[=<path>]`
+    /// to shift their tree under or out of a directory before merging
+    #[clap(short = 'X', long = "strategy-option")]
+    strategy_options: Vec<String>,
+
+    /// Abandon the merge in progress, restoring the state from before it
+    #[clap(long = "abort", conflicts_with = "continue_merge")]
+    abort: bool,
+
+    /// Commit the merge in progress once its conflicts are resolved
+    #[clap(long = "continue")]
+    continue_merge: bool,
+
+    /// Merge histories that don't share a commit
+    #[clap(long = "allow-unrelated-histories")]
+    allow_unrelated_histories: bool,
+
+    /// The commits to merge into the current branch, all at once when there are several
+    commits: Vec<String>,
+}
+
+const MERGE_HEAD: &str = ".git/MERGE_HEAD";
+
+/// Strategies `git merge -s` knows, for the error naming them
+const STRATEGIES: &str = "octopus ort ours recursive subtree";
+
+const UNMERGED_HINT: &str = "hint: Fix them up in the work tree, and then use 'git add/rm <file>'
+hint: as appropriate to mark resolution and make a commit.
+fatal: Exiting because of an unresolved conflict.";
+
+/// What is being merged, as named on the command line
+struct Remote {
+    name: String,
+    commit: [u8; 20],
+    /// The annotated tag it was named through, whose message goes into the merge commit
+    tag: Option<Tag>,
+}
+
+pub(crate) fn invoke(args: MergeArgs) -> anyhow::Result<()> {
+    if args.abort {
+        return abort();
+    }
+    if args.continue_merge {
+        return conclude();
+    }
+    if let Some(strategy) = &args.strategy {
+        anyhow::ensure!(
+            matches!(
+                strategy.as_str(),
+                "ort" | "recursive" | "octopus" | "ours" | "subtree"
+            ),
+            "Could not find merge strategy '{strategy}'.\nAvailable strategies are: {STRATEGIES}."
+        );
+    }
+    anyhow::ensure!(
+        !(args.squash && args.no_ff),
+        "options '--squash' and '--no-ff.' cannot be used together"
+    );
+    let mut index = Index::read()?;
+    anyhow::ensure!(
+        !index.has_conflicts(),
+        "Merging is not possible because you have unmerged files.\n{UNMERGED_HINT}"
+    );
+    anyhow::ensure!(
+        !Path::new(MERGE_HEAD).exists(),
+        "You have not concluded your merge (MERGE_HEAD exists).\nPlease, commit your changes before you merge."
+    );
+    let remotes = match args.commits.as_slice() {
+        [] => vec![resolve_remote(&upstream()?)?],
+        names => names
+            .iter()
+            .map(|name| resolve_remote(name))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+    };
+    let head = refs::head_commit()?;
+
+    // Only the commits not reachable from HEAD or another one being merged need merging, and
+    // HEAD itself only counts as a parent when none of them contains it
+    let mut graph = CommitGraph::new();
+    let mut tips: Vec<[u8; 20]> = head.into_iter().collect();
+    tips.extend(remotes.iter().map(|remote| remote.commit));
+    let independent = graph.independent(&tips)?;
+    let head_subsumed = head.is_some_and(|head| !independent.contains(&head));
+    let mut seen = Vec::new();
+    let remotes: Vec<Remote> = remotes
+        .into_iter()
+        .filter(|remote| {
+            let first = !seen.contains(&remote.commit);
+            seen.push(remote.commit);
+            first && Some(remote.commit) != head && independent.contains(&remote.commit)
+        })
+        .collect();
+
+    let Some(head) = head else {
+        let [remote] = remotes.as_slice() else {
+            anyhow::bail!("Can merge only exactly one commit into empty head");
+        };
+        // Merging into an unborn branch just checks the commit out
+        let their_tree = Commit::read(&remote.commit)?.tree;
+        let opts = UnpackOptions {
+            update: true,
+            merging: true,
+            ..UnpackOptions::default()
+        };
+        unpack::two_way(&mut index, None, Some(&their_tree), opts)?;
+        index.write()?;
+        return refs::update_ref("HEAD", &remote.commit, "initial pull");
+    };
+    if remotes.is_empty() {
+        println!("Already up to date.");
+        return Ok(());
+    }
+    let names: Vec<&str> = remotes.iter().map(|remote| remote.name.as_str()).collect();
+    let action = format!("merge {}", names.join(" "));
+    let default_strategy = if remotes.len() > 1 { "octopus" } else { "ort" };
+    let strategy = args.strategy.as_deref().unwrap_or(default_strategy);
+    refs::update_ref("ORIG_HEAD", &head, "updating ORIG_HEAD")?;
+
+    let merge = Merge {
+        args: &args,
+        action,
+        strategy,
+        // Subtree merges shift trees about and `ours` records the merge whatever it is, so
+        // neither just takes theirs
+        no_ff: args.no_ff || matches!(strategy, "subtree" | "ours"),
+        head,
+        head_tree: Commit::read(&head)?.tree,
+        head_subsumed,
+        remotes,
+    };
+    if strategy == "ours" {
+        merge.ours(&index)
+    } else if merge.remotes.len() == 1 {
+        merge.two_heads(&mut index)
+    } else {
+        merge.octopus(&mut index)
+    }
+}
+
+/// A merge that has to do more than say HEAD is up to date
+struct Merge<'a> {
+    args: &'a MergeArgs,
+    /// What the reflog entries say is being done
+    action: String,
+    strategy: &'a str,
+    /// Always make a merge commit
+    no_ff: bool,
+    head: [u8; 20],
+    head_tree: [u8; 20],
+    /// HEAD is reachable from one of the remotes, so isn't recorded as a parent unless asked to
+    head_subsumed: bool,
+    /// The commits being merged, reduced to those with something to merge
+    remotes: Vec<Remote>,
+}
+
+impl Merge<'_> {
+    /// Merges the one remote left: a fast-forward if possible, a trivial merge for strategies
+    /// that allow one, or a merge of the two heads by the strategy
+    fn two_heads(&self, index: &mut Index) -> anyhow::Result<()> {
+        let (args, strategy, head) = (self.args, self.strategy, self.head);
+        let remote = &self.remotes[0];
+        let their_tree = Commit::read(&remote.commit)?.tree;
+        let bases = merge_base::merge_bases(&head, &remote.commit)?;
+        anyhow::ensure!(
+            !bases.is_empty() || args.allow_unrelated_histories,
+            "refusing to merge unrelated histories"
+        );
+        if bases == [head] && !self.no_ff {
+            println!("Updating {}..{}", abbrev(&head), abbrev(&remote.commit));
+            let opts = UnpackOptions {
+                update: true,
+                merging: true,
+                ..UnpackOptions::default()
+            };
+            unpack::two_way(index, Some(&self.head_tree), Some(&their_tree), opts)?;
+            index.write()?;
+            println!("Fast-forward");
+            if args.squash {
+                squash(&head, &self.remotes)?;
+            } else {
+                let message = format!("{}: Fast-forward", self.action);
+                refs::update_ref("HEAD", &remote.commit, &message)?;
+            }
+            return print_diffstat(&self.head_tree, &their_tree);
+        }
+        anyhow::ensure!(!args.ff_only, "Not possible to fast-forward, aborting.");
+
+        if strategy == "octopus" {
+            // The octopus strategy itself needs more than one head, so all there is to try is
+            // a merge the index can do by itself
+            if let [base] = bases.as_slice() {
+                let staged = staged_paths(index, &self.head_tree)?;
+                anyhow::ensure!(
+                    staged.is_empty(),
+                    "Your local changes to the following files would be overwritten by merge:\n  {}",
+                    staged.join(" ")
+                );
+                println!("Trying really trivial in-index merge...");
+                if self.trivial_merge(index, base, &their_tree)? {
+                    return Ok(());
+                }
+            }
+            anyhow::bail!("Merge with strategy {strategy} failed.");
+        }
+
+        let (content, subtree_shift) = strategy_options(&args.strategy_options)?;
+        let staged = staged_paths(index, &self.head_tree)?;
+        if !staged.is_empty() {
+            refs::update_ref("HEAD", &head, &format!("{}: updating HEAD", self.action))?;
+            anyhow::bail!(
+                "Your local changes to the following files would be overwritten by merge:\n  {}\nMerge with strategy {strategy} failed.",
+                staged.join(" ")
+            );
+        }
+        let opts = MergeOptions {
+            labels: Labels {
+                ours: "HEAD",
+                base: "",
+                theirs: &remote.name,
+            },
+            content,
+            subtree_shift: match strategy {
+                "subtree" => Some(subtree_shift.unwrap_or("")),
+                _ => subtree_shift,
+            },
+        };
+        let result = merge::merge_commits(&head, &remote.commit, &bases, opts)?;
+        merge::checkout(index, Some(&self.head_tree), &result)
+            .map_err(|err| anyhow::anyhow!("{err}\nMerge with strategy {strategy} failed."))?;
+        index.write()?;
+        fs::write(".git/AUTO_MERGE", format!("{}\n", hex::encode(result.tree)))
+            .context("Writing AUTO_MERGE")?;
+
+        let mut out = std::io::stdout().lock();
+        for message in &result.messages {
+            writeln!(out, "{message}")?;
+        }
+        drop(out);
+        if result.is_clean() {
+            fs::remove_file(".git/AUTO_MERGE").context("Removing AUTO_MERGE")?;
+            return self.conclude(&result.tree);
+        }
+        let conflicts: Vec<&str> = result.conflicts.keys().map(String::as_str).collect();
+        self.stop_for_conflicts(&conflicts)
+    }
+
+    /// Merges in the index alone when no file needs its contents merged
+    fn trivial_merge(
+        &self,
+        index: &mut Index,
+        base: &[u8; 20],
+        their_tree: &[u8; 20],
+    ) -> anyhow::Result<bool> {
+        let base_tree = Commit::read(base)?.tree;
+        let [o, a, b] =
+            [&base_tree, &self.head_tree, their_tree].map(|tree| tree_blobs(Some(tree)));
+        let (o, a, b) = (o?, a?, b?);
+        // Deletions aren't trivial either, even when both sides made them
+        let needs_merging = o.keys().chain(a.keys()).chain(b.keys()).any(|path| {
+            let [o, a, b] = [&o, &a, &b].map(|side| side.get(path));
+            (o.is_some() && (a.is_none() || b.is_none())) || (a != b && o != a && o != b)
+        });
+        if needs_merging {
+            eprintln!("error: Merge requires file-level merging");
+            println!("Nope.");
+            return Ok(false);
+        }
+        let opts = UnpackOptions {
+            update: true,
+            merging: true,
+            aggressive: true,
+            ..UnpackOptions::default()
+        };
+        unpack::three_way(
+            index,
+            Some(&base_tree),
+            Some(&self.head_tree),
+            Some(their_tree),
+            opts,
+        )?;
+        index.write()?;
+        println!("Wonderful.");
+        let tree = octopus::index_tree(index)?.context("Merge requires file-level merging")?;
+        let message = self.message()?;
+        let commit = write_commit(&tree, &[self.head, self.remotes[0].commit], &message)?;
+        refs::update_ref("HEAD", &commit, &format!("{}: In-index merge", self.action))?;
+        println!("In-index merge");
+        print_diffstat(&self.head_tree, &tree)?;
+        Ok(true)
+    }
+
+    /// Refuses remotes that share no history with HEAD, unless allowed
+    fn ensure_related(&self) -> anyhow::Result<()> {
+        if self.args.allow_unrelated_histories {
+            return Ok(());
+        }
+        for remote in &self.remotes {
+            anyhow::ensure!(
+                !merge_base::merge_bases(&self.head, &remote.commit)?.is_empty(),
+                "refusing to merge unrelated histories"
+            );
+        }
+        Ok(())
+    }
+
+    /// Records a merge of the remotes that keeps the tree of HEAD, ignoring their changes
+    fn ours(&self, index: &Index) -> anyhow::Result<()> {
+        self.ensure_related()?;
+        if !staged_paths(index, &self.head_tree)?.is_empty() {
+            anyhow::bail!("Merge with strategy ours failed.");
+        }
+        self.conclude(&self.head_tree)
+    }
+
+    /// Merges all the remotes with the octopus strategy
+    fn octopus(&self, index: &mut Index) -> anyhow::Result<()> {
+        let strategy = self.strategy;
+        self.ensure_related()?;
+        anyhow::ensure!(
+            !self.args.ff_only,
+            "Not possible to fast-forward, aborting."
+        );
+        if strategy != "octopus" {
+            eprintln!("error: Not handling anything other than two heads merge.");
+            anyhow::bail!("Merge with strategy {strategy} failed.");
+        }
+        let (content, _) = strategy_options(&self.args.strategy_options)?;
+        let staged = staged_paths(index, &self.head_tree)?;
+        if !staged.is_empty() {
+            let message = format!("{}: updating HEAD", self.action);
+            refs::update_ref("HEAD", &self.head, &message)?;
+            println!(
+                "Error: Your local changes to the following files would be overwritten by merge"
+            );
+            for path in staged {
+                println!("    {path}");
+            }
+            anyhow::bail!("Merge with strategy {strategy} failed.");
+        }
+        let heads: Vec<(&str, [u8; 20])> = self
+            .remotes
+            .iter()
+            .map(|remote| (remote.name.as_str(), remote.commit))
+            .collect();
+        let outcome = octopus::merge_octopus(index, &self.head, &heads, &content)?;
+        index.write()?;
+        match outcome {
+            Outcome::Merged(tree) => self.conclude(&tree),
+            Outcome::Conflicts => {
+                let mut conflicts: Vec<&str> = index
+                    .entries()
+                    .iter()
+                    .filter(|entry| entry.stage != 0)
+                    .map(|entry| entry.path.as_str())
+                    .collect();
+                conflicts.dedup();
+                self.stop_for_conflicts(&conflicts)
+            }
+            Outcome::Failed => {
+                // Nothing of the attempt is kept
+                let opts = UnpackOptions {
+                    update: true,
+                    reset: true,
+                    ..UnpackOptions::default()
+                };
+                unpack::one_way(index, Some(&self.head_tree), opts)?;
+                index.write()?;
+                anyhow::bail!("Merge with strategy {strategy} failed.");
+            }
+        }
+    }
+
+    /// Commits the cleanly merged `tree`, or stops short of it for `--squash`
+    fn conclude(&self, tree: &[u8; 20]) -> anyhow::Result<()> {
+        if self.args.squash {
+            eprintln!("Automatic merge went well; stopped before committing as requested");
+            return squash(&self.head, &self.remotes);
+        }
+        let mut parents = Vec::new();
+        if !self.head_subsumed || self.no_ff {
+            parents.push(self.head);
+        }
+        parents.extend(self.remotes.iter().map(|remote| remote.commit));
+        let commit = write_commit(tree, &parents, &self.message()?)?;
+        let made_by = format!("Merge made by the '{}' strategy.", self.strategy);
+        refs::update_ref("HEAD", &commit, &format!("{}: {made_by}", self.action))?;
+        println!("{made_by}");
+        print_diffstat(&self.head_tree, tree)
+    }
+
+    /// Leaves the conflicted merge for the user to finish, recording what is being merged
+    fn stop_for_conflicts(&self, conflicts: &[&str]) -> anyhow::Result<()> {
+        let conflicts: String = conflicts
+            .iter()
+            .map(|path| format!("#\t{path}\n"))
+            .collect();
+        if self.args.squash {
+            squash(&self.head, &self.remotes)?;
+            fs::write(".git/MERGE_MSG", format!("\n# Conflicts:\n{conflicts}"))
+                .context("Writing MERGE_MSG")?;
+        } else {
+            let merge_heads: String = self
+                .remotes
+                .iter()
+                .map(|remote| format!("{}\n", hex::encode(remote.commit)))
+                .collect();
+            fs::write(MERGE_HEAD, merge_heads).context("Writing MERGE_HEAD")?;
+            let mode = if self.no_ff { "no-ff" } else { "" };
+            fs::write(".git/MERGE_MODE", mode).context("Writing MERGE_MODE")?;
+            fs::write(
+                ".git/MERGE_MSG",
+                format!("{}\n# Conflicts:\n{conflicts}", self.message()?),
+            )
+            .context("Writing MERGE_MSG")?;
+        }
+        println!("Automatic merge failed; fix conflicts and then commit the result.");
+        std::process::exit(1);
+    }
+
+    fn message(&self) -> anyhow::Result<String> {
+        match &self.args.message {
+            Some(message) => Ok(format!("{message}\n")),
+            None => merge_message(&self.remotes),
+        }
+    }
+}
+
+/// Resolves the commit to merge, remembering the annotated tag it came through
+fn resolve_remote(name: &str) -> anyhow::Result<Remote> {
+    let not_mergeable = || format!("merge: {name} - not something we can merge");
+    let hash = revision::resolve(name).map_err(|_| anyhow::anyhow!(not_mergeable()))?;
+    let tag = match revision::kind_of(&hash)? {
+        ObjectKind::Tag => {
+            let (_, content) = read_object(&hex::encode(hash))?;
+            Some(Tag::parse(&content)?)
+        }
+        _ => None,
+    };
+    let commit = revision::peel_to_commit(&hash).map_err(|_| anyhow::anyhow!(not_mergeable()))?;
+    Ok(Remote {
+        name: name.to_string(),
+        commit,
+        tag,
+    })
+}
+
+/// The upstream of the current branch, merged when no commit is given
+fn upstream() -> anyhow::Result<String> {
+    let Head::Symbolic(target) = refs::read_head()? else {
+        anyhow::bail!("No current branch.");
+    };
+    let upstream = refs::upstream_of(refs::shorten(&target));
+    upstream.context("No remote for the current branch.")
+}
+
+/// How files are merged, from the `-X` options and `merge.conflictStyle`, and the prefix of
+/// `-X subtree`, empty when none is given
+fn strategy_options(strategy_options: &[String]) -> anyhow::Result<(FileOptions, Option<&str>)> {
+    let mut opts = FileOptions {
+        style: ConflictStyle::configured()?,
+        ..FileOptions::default()
+    };
+    let mut subtree_shift = None;
+    for option in strategy_options {
+        match option.as_str() {
+            "ours" => opts.favor = Favor::Ours,
+            "theirs" => opts.favor = Favor::Theirs,
+            "ignore-space-change" => opts.ignore_space_change = true,
+            "subtree" => subtree_shift = Some(""),
+            _ => match option.strip_prefix("subtree=") {
+                Some(prefix) => subtree_shift = Some(prefix),
+                None => anyhow::bail!("unknown strategy option: -X{option}"),
+            },
+        }
+    }
+    Ok((opts, subtree_shift))
+}
+
+/// The default merge commit message: `Merge branch 'topic'` and the like, saying which branch
+/// it goes into unless that is `main` or `master`. Branches, remote-tracking branches and tags
+/// are listed together by kind, commits named any other way each on their own.
+fn merge_message(remotes: &[Remote]) -> anyhow::Result<String> {
+    let mut kinds: [(&str, &str, Vec<String>); 3] = [
+        ("branch", "branches", Vec::new()),
+        (
+            "remote-tracking branch",
+            "remote-tracking branches",
+            Vec::new(),
+        ),
+        ("tag", "tags", Vec::new()),
+    ];
+    // The refs all go in one place in the list, where the first of them was named
+    let mut sources = Vec::new();
+    let mut refs_at = None;
+    for remote in remotes {
+        match describe_remote(&remote.name)? {
+            Some((kind, name)) => {
+                kinds[kind].2.push(name);
+                if refs_at.is_none() {
+                    refs_at = Some(sources.len());
+                    sources.push(String::new());
+                }
+            }
+            None => sources.push(format!("commit '{}'", remote.name)),
+        }
+    }
+    if let Some(at) = refs_at {
+        sources[at] = kinds
+            .iter()
+            .filter(|(_, _, names)| !names.is_empty())
+            .map(|(one, many, names)| {
+                let kind = if names.len() == 1 { one } else { many };
+                format!("{kind} {}", join_names(names))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+    }
+    let mut message = format!("Merge {}", sources.join("; "));
+    if let Head::Symbolic(target) = refs::read_head()? {
+        let branch = refs::shorten(&target);
+        if branch != "main" && branch != "master" {
+            message.push_str(&format!(" into {branch}"));
+        }
+    }
+    message.push('\n');
+    for tag in remotes.iter().filter_map(|remote| remote.tag.as_ref()) {
+        message.push('\n');
+        message.push_str(&tag.message);
+    }
+    Ok(message)
+}
+
+/// Which kind of ref a merged name is, as an index into the kinds of `merge_message`, and how
+/// the message quotes it. An ancestor of a branch written as `topic~2` or `topic^` is the
+/// early part of that branch.
+fn describe_remote(name: &str) -> anyhow::Result<Option<(usize, String)>> {
+    let full = refs::expand_ref(name)?.unwrap_or_default();
+    let prefixes = ["refs/heads/", "refs/remotes/", "refs/tags/"];
+    for (kind, prefix) in prefixes.iter().enumerate() {
+        if let Some(short) = full.strip_prefix(prefix) {
+            return Ok(Some((kind, format!("'{short}'"))));
+        }
+    }
+    let carets = name.len() - name.trim_end_matches('^').len();
+    let (branch, early) = if carets > 0 {
+        (&name[..name.len() - carets], true)
+    } else {
+        let Some((branch, number)) = name.rsplit_once('~') else {
+            return Ok(None);
+        };
+        if !number.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(None);
+        }
+        (
+            branch,
+            number.is_empty() || number.bytes().any(|b| b != b'0'),
+        )
+    };
+    if refs::read_ref(&format!("refs/heads/{branch}"))?.is_none() {
+        return Ok(None);
+    }
+    let early = if early { " (early part)" } else { "" };
+    Ok(Some((0, format!("'{branch}'{early}"))))
+}
+
+/// Names joined as a list: `a`, `a and b`, `a, b and c`
+fn join_names(names: &[String]) -> String {
+    match names.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {last}", rest.join(", ")),
+        _ => names.concat(),
+    }
+}
+
+/// Paths whose index entries differ from HEAD, which a merge would lose
+fn staged_paths(index: &Index, head_tree: &[u8; 20]) -> anyhow::Result<Vec<String>> {
+    let head = unpack::tree_blobs(Some(head_tree))?;
+    let mut paths: Vec<String> = index
+        .entries()
+        .iter()
+        .filter(|entry| head.get(&entry.path) != Some(&(entry.mode, entry.hash)))
+        .map(|entry| entry.path.clone())
+        .collect();
+    paths.extend(
+        head.keys()
+            .filter(|path| index.get(path, 0).is_none())
+            .cloned(),
+    );
+    paths.sort();
+    Ok(paths)
+}
+
+/// Leaves HEAD alone, writing a message describing the merged commits to `SQUASH_MSG` for the
+/// commit that will take their place
+fn squash(head: &[u8; 20], remotes: &[Remote]) -> anyhow::Result<()> {
+    println!("Squash commit -- not updating HEAD");
+    let revisions = Revisions {
+        include: remotes.iter().map(|remote| remote.commit).collect(),
+        exclude: vec![*head],
+        ..Revisions::default()
+    };
+    let mut walk = RevWalk::new(&revisions, WalkOptions::default())?;
+    let opts = PrettyOptions {
+        format: Format::Medium,
+        abbrev_commit: false,
+        date_mode: Default::default(),
+        decorate: false,
+    };
+    let mut message = String::from("Squashed commit of the following:\n");
+    while let Some(hash) = walk.next()? {
+        let commit = Commit::read(&hash)?;
+        let shown = ShownCommit {
+            hash: &hash,
+            commit: &commit,
+            parents: &commit.parents,
+            decorations: &[],
+        };
+        message.push('\n');
+        message.push_str(&pretty::format_commit(&shown, &opts)?);
+    }
+    fs::write(".git/SQUASH_MSG", message).context("Writing SQUASH_MSG")
+}
+
+/// Shows what the merge brought in: a diffstat and summary of the changes to HEAD's tree
+fn print_diffstat(old: &[u8; 20], new: &[u8; 20]) -> anyhow::Result<()> {
+    let pairs = tree_to_tree(Some(old), Some(new), &Pathspec::new(&[]), false)?;
+    let opts = RenameOptions {
+        copies: false,
+        min_score: rename::DEFAULT_SCORE,
+        limit: rename::DEFAULT_LIMIT,
+    };
+    let pairs = rename::detect(pairs, &opts)?;
+    if pairs.is_empty() {
+        return Ok(());
+    }
+    let mut out = std::io::stdout().lock();
+    write_stat(&mut out, &pairs, &PatchOptions::default())?;
+    write_summary(&mut out, &pairs)
+}
+
+/// `merge --abort`: resets the index and the files the merge touched back to HEAD, keeping
+/// local changes made before the merge
+fn abort() -> anyhow::Result<()> {
+    anyhow::ensure!(
+        Path::new(MERGE_HEAD).exists(),
+        "There is no merge to abort (MERGE_HEAD missing)."
+    );
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let head_tree = Commit::read(&head)?.tree;
+    let mut index = Index::read()?;
+    let opts = UnpackOptions {
+        update: true,
+        overwrite_unmerged: true,
+        ..UnpackOptions::default()
+    };
+    unpack::one_way(&mut index, Some(&head_tree), opts)
+        .context("Could not reset index file to revision 'HEAD'.")?;
+    index.write()?;
+    refs::update_ref("ORIG_HEAD", &head, "updating ORIG_HEAD")?;
+    refs::update_ref("HEAD", &head, "reset: moving to HEAD")?;
+    refs::clear_merge_state()
+}
+
+/// `merge --continue`: commits the resolved merge with the prepared message
+fn conclude() -> anyhow::Result<()> {
+    let merge_heads = fs::read_to_string(MERGE_HEAD)
+        .map_err(|_| anyhow::anyhow!("There is no merge in progress (MERGE_HEAD missing)."))?;
+    let index = Index::read()?;
+    if index.has_conflicts() {
+        let mut unmerged: Vec<&str> = index
+            .entries()
+            .iter()
+            .filter(|entry| entry.stage != 0)
+            .map(|entry| entry.path.as_str())
+            .collect();
+        unmerged.dedup();
+        for path in unmerged {
+            println!("U\t{path}");
+        }
+        anyhow::bail!(
+            "Committing is not possible because you have unmerged files.\n{UNMERGED_HINT}"
+        );
+    }
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let mut parents = vec![head];
+    for line in merge_heads.lines() {
+        parents.push(parse_hash(line.trim())?);
+    }
+    let files = index
+        .entries()
+        .iter()
+        .map(|entry| (entry.path.clone(), (entry.mode, entry.hash)))
+        .collect();
+    let tree = write_tree_from_paths(&files)?;
+    let message = fs::read_to_string(".git/MERGE_MSG").unwrap_or_default();
+    let message = pretty::cleanup_message(&message);
+    anyhow::ensure!(
+        !message.is_empty(),
+        "Aborting commit due to empty commit message."
+    );
+    let commit = write_commit(&tree, &parents, &message)?;
+    let subject = pretty::subject(&message);
+    refs::update_ref("HEAD", &commit, &format!("commit (merge): {subject}"))?;
+    refs::clear_merge_state()?;
+    let branch = match refs::read_head()? {
+        Head::Symbolic(target) => refs::shorten(&target).to_string(),
+        Head::Detached(_) => String::from("detached HEAD"),
+    };
+    println!("[{branch} {}] {subject}", abbrev(&commit));
+    Ok(())
+}
@@ -0,0 +1,131 @@
+use std::io::Write;
+
+use crate::{merge_base::CommitGraph, object::ObjectKind, refs, revision};
+
+const USAGE: &str = "usage: git merge-base [-a | --all] <commit> <commit>...
+   or: git merge-base [-a | --all] --octopus <commit>...
+   or: git merge-base --is-ancestor <commit> <commit>
+   or: git merge-base --independent <commit>...
+   or: git merge-base --fork-point <ref> [<commit>]";
+
+#[derive(clap::Args, Debug)]
+pub struct MergeBaseArgs {
+    /// Show all best common ancestors instead of one
+    #[clap(short = 'a', long = "all")]
+    all: bool,
+
+    /// Find the best common ancestors for merging all the commits at once
+    #[clap(long = "octopus", group = "mode")]
+    octopus: bool,
+
+    /// Show the commits that aren't reachable from any of the others
+    #[clap(long = "independent", group = "mode", conflicts_with = "all")]
+    independent: bool,
+
+    /// Exit with 0 if the first commit is an ancestor of the second, 1 otherwise
+    #[clap(long = "is-ancestor", group = "mode", conflicts_with = "all")]
+    is_ancestor: bool,
+
+    /// Find where the commit (HEAD by default) forked from the history of a ref, going by its
+    /// reflog
+    #[clap(long = "fork-point", group = "mode")]
+    fork_point: bool,
+
+    commits: Vec<String>,
+}
+
+/// Resolves an argument to the commit it names
+fn commit_reference(arg: &str) -> anyhow::Result<[u8; 20]> {
+    let hash =
+        revision::resolve(arg).map_err(|_| anyhow::anyhow!("Not a valid object name {arg}"))?;
+    revision::peel_to_commit(&hash).map_err(|_| anyhow::anyhow!("Not a valid commit name {arg}"))
+}
+
+pub(crate) fn invoke(args: MergeBaseArgs) -> anyhow::Result<()> {
+    let mut graph = CommitGraph::new();
+    let found = if args.is_ancestor {
+        anyhow::ensure!(args.commits.len() >= 2, "{USAGE}");
+        anyhow::ensure!(
+            args.commits.len() == 2,
+            "--is-ancestor takes exactly two commits"
+        );
+        let commit = commit_reference(&args.commits[0])?;
+        let reference = commit_reference(&args.commits[1])?;
+        graph.is_ancestor(&commit, &reference)?
+    } else if args.fork_point {
+        anyhow::ensure!((1..=2).contains(&args.commits.len()), "{USAGE}");
+        let commit = commit_reference(args.commits.get(1).map_or("HEAD", String::as_str))?;
+        match fork_point(&mut graph, &args.commits[0], &commit)? {
+            Some(fork_point) => print(&[fork_point], true)?,
+            None => false,
+        }
+    } else {
+        let min = if args.octopus || args.independent {
+            1
+        } else {
+            2
+        };
+        anyhow::ensure!(args.commits.len() >= min, "{USAGE}");
+        let commits = args
+            .commits
+            .iter()
+            .map(|arg| commit_reference(arg))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let bases = if args.independent {
+            graph.independent(&commits)?
+        } else if args.octopus {
+            graph.octopus_bases(&commits)?
+        } else {
+            graph.merge_bases(&commits[0], &commits[1..])?
+        };
+        print(&bases, args.all || args.independent)?
+    };
+    if !found {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Prints the first commit or all of them, returning whether there were any
+fn print(commits: &[[u8; 20]], all: bool) -> anyhow::Result<bool> {
+    let mut out = std::io::stdout().lock();
+    let shown = if all { commits.len() } else { 1 };
+    for hash in commits.iter().take(shown) {
+        writeln!(out, "{}", hex::encode(hash))?;
+    }
+    Ok(!commits.is_empty())
+}
+
+/// Where `commit` forked from a ref: the best common ancestor of `commit` and every value the ref
+/// has had according to its reflog, if that is a single commit and one of those values. That
+/// finds the fork even after the ref was rewritten, which plain merge bases don't.
+fn fork_point(
+    graph: &mut CommitGraph,
+    name: &str,
+    commit: &[u8; 20],
+) -> anyhow::Result<Option<[u8; 20]>> {
+    let full = refs::expand_ref(name)?.ok_or_else(|| anyhow::anyhow!("No such ref: '{name}'"))?;
+    let current = refs::read_ref(&full)?.unwrap_or_default();
+
+    let log = refs::read_reflog(&full)?;
+    let values = log
+        .first()
+        .map(|entry| entry.old)
+        .into_iter()
+        .chain(log.iter().map(|entry| entry.new));
+    let mut candidates = Vec::new();
+    for hash in values {
+        let is_commit = revision::kind_of(&hash).ok() == Some(ObjectKind::Commit);
+        if hash != [0; 20] && is_commit && !candidates.contains(&hash) {
+            candidates.push(hash);
+        }
+    }
+    if candidates.is_empty() {
+        candidates.push(current);
+    }
+
+    match graph.merge_bases(commit, &candidates)?.as_slice() {
+        [base] if candidates.contains(base) => Ok(Some(*base)),
+        _ => Ok(None),
+    }
+}
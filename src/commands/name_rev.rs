@@ -0,0 +1,117 @@
+use std::io::{BufRead, Write};
+
+use crate::{
+    name_rev::{NameRevOptions, Names, CUTOFF_DATE_SLOP},
+    object::{commit::Commit, ObjectKind},
+    revision,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct NameRevArgs {
+    /// Print only the names, not the objects they name
+    #[clap(long = "name-only")]
+    name_only: bool,
+
+    /// Only use tags to name the commits
+    #[clap(long = "tags")]
+    tags: bool,
+
+    /// Only use refs matching the pattern, may be repeated
+    #[clap(long = "refs", value_name = "pattern")]
+    refs: Vec<String>,
+
+    /// Don't use refs matching the pattern, may be repeated
+    #[clap(long = "exclude", value_name = "pattern")]
+    excludes: Vec<String>,
+
+    /// Name every commit reachable from a ref
+    #[clap(long = "all", conflicts_with = "revs")]
+    all: bool,
+
+    /// Copy the standard input to the output, naming each full object name found in it
+    #[clap(long = "annotate-stdin", visible_alias = "stdin", conflicts_with_all = ["revs", "all"])]
+    annotate_stdin: bool,
+
+    /// Die instead of printing `undefined` for an object without a name
+    #[clap(long = "no-undefined")]
+    no_undefined: bool,
+
+    /// Show the abbreviated object name for an object without a name
+    #[clap(long = "always")]
+    always: bool,
+
+    /// Name the commits tags point at rather than the tags themselves
+    #[clap(long = "peel-tag")]
+    peel_tag: bool,
+
+    revs: Vec<String>,
+}
+
+pub(crate) fn invoke(args: NameRevArgs) -> anyhow::Result<()> {
+    let mut objects = Vec::new();
+    let mut cutoff = i64::MAX;
+    for rev in &args.revs {
+        let Ok(mut hash) = revision::resolve(rev) else {
+            eprintln!("Could not get sha1 for {rev}. Skipping.");
+            continue;
+        };
+        let peeled = revision::peel(&hash, None)?;
+        let commit = match revision::kind_of(&peeled)? {
+            ObjectKind::Commit => Some(peeled),
+            _ => None,
+        };
+        if let Some(commit) = commit {
+            cutoff = cutoff.min(Commit::read(&commit)?.committer.time);
+        }
+        if args.peel_tag {
+            let Some(commit) = commit else {
+                eprintln!("Could not get commit for {rev}. Skipping.");
+                continue;
+            };
+            hash = commit;
+        }
+        objects.push((hash, rev.as_str()));
+    }
+
+    let opts = NameRevOptions {
+        tags_only: args.tags,
+        shorten: args.tags && args.name_only,
+        refs: args.refs,
+        excludes: args.excludes,
+        cutoff: match args.all || args.annotate_stdin {
+            true => None,
+            false => Some(cutoff.saturating_sub(CUTOFF_DATE_SLOP)),
+        },
+    };
+    let names = Names::collect(&opts)?;
+
+    let mut out = std::io::stdout().lock();
+    if args.annotate_stdin {
+        for line in std::io::stdin().lock().lines() {
+            writeln!(out, "{}", names.annotate(&line?, args.name_only))?;
+        }
+        return Ok(());
+    }
+    if args.all {
+        let mut commits: Vec<_> = names.commits().collect();
+        commits.sort_unstable();
+        objects = commits.into_iter().map(|commit| (*commit, "")).collect();
+    }
+    for (hash, rev) in objects {
+        let hex = hex::encode(hash);
+        if !args.name_only {
+            let caller = if rev.is_empty() { &hex } else { rev };
+            write!(out, "{caller} ")?;
+        }
+        match names.name(&hash)? {
+            Some(name) => writeln!(out, "{name}")?,
+            None if !args.no_undefined => writeln!(out, "undefined")?,
+            None if args.always => writeln!(out, "{}", crate::diff::patch::abbrev(&hash))?,
+            None => {
+                out.flush()?;
+                anyhow::bail!("cannot describe '{hex}'");
+            }
+        }
+    }
+    Ok(())
+}
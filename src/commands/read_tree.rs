@@ -0,0 +1,99 @@
+use anyhow::Context;
+
+use crate::{
+    index::{Index, IndexEntry},
+    object::read::flatten_tree,
+    revision,
+    unpack::{self, UnpackOptions},
+};
+
+pub(crate) fn invoke(
+    merge: bool,
+    reset: bool,
+    update: bool,
+    aggressive: bool,
+    prefix: Option<String>,
+    tree_ishes: Vec<String>,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(!(merge && reset), "-m and --reset are mutually exclusive");
+    anyhow::ensure!(
+        !update || merge || reset || prefix.is_some(),
+        "-u is meaningless without -m, --reset or --prefix"
+    );
+    anyhow::ensure!(
+        prefix.is_none() || (!merge && !reset && tree_ishes.len() == 1),
+        "--prefix takes exactly one tree and can't be combined with -m or --reset"
+    );
+
+    let trees = tree_ishes
+        .iter()
+        .map(|tree_ish| {
+            revision::resolve(tree_ish)
+                .and_then(|hash| revision::peel_to_tree(&hash))
+                .with_context(|| format!("Not a valid tree-ish: {tree_ish}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut index = Index::read()?;
+    let opts = UnpackOptions {
+        update,
+        reset,
+        aggressive,
+        ..UnpackOptions::default()
+    };
+
+    if let Some(prefix) = prefix {
+        read_with_prefix(&mut index, &trees[0], &prefix, update)?;
+    } else if merge || reset {
+        match trees.as_slice() {
+            [tree] => unpack::one_way(&mut index, Some(tree), opts)?,
+            [head, merge] => unpack::two_way(&mut index, Some(head), Some(merge), opts)?,
+            [base, ours, theirs] => {
+                unpack::three_way(&mut index, Some(base), Some(ours), Some(theirs), opts)?
+            }
+            _ => anyhow::bail!("-m and --reset take one, two or three trees"),
+        }
+    } else {
+        anyhow::ensure!(!trees.is_empty(), "No tree given");
+        // Without a merge, later trees are overlaid on earlier ones and stat info is discarded
+        index.clear();
+        for tree in &trees {
+            for item in flatten_tree(tree, "")? {
+                index.add(IndexEntry::new(item.name, item.mode, item.hash, 0));
+            }
+        }
+    }
+
+    index.write()
+}
+
+/// Grafts a tree into the index under `prefix`, which must not already be in use
+fn read_with_prefix(
+    index: &mut Index,
+    tree: &[u8; 20],
+    prefix: &str,
+    update: bool,
+) -> anyhow::Result<()> {
+    let prefix = prefix.trim_end_matches('/');
+    let is_taken = index.entries().iter().any(|entry| {
+        entry.path == prefix
+            || prefix.is_empty()
+            || entry
+                .path
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.starts_with('/'))
+    });
+    anyhow::ensure!(
+        !is_taken,
+        "Subdirectory '{prefix}' already exists in the index"
+    );
+
+    for item in flatten_tree(tree, prefix)? {
+        let mut entry = IndexEntry::new(item.name, item.mode, item.hash, 0);
+        if update {
+            crate::worktree::checkout_entry(&mut entry)?;
+        }
+        index.add(entry);
+    }
+    Ok(())
+}
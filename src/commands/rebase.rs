@@ -0,0 +1,261 @@
+use anyhow::Context;
+
+use crate::{
+    checkout,
+    commands::checkout::expand_previous,
+    config,
+    index::Index,
+    merge_base,
+    object::commit::Commit,
+    rebase::{self, Options, Outcome},
+    refs::{self, Head},
+    revision,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct RebaseArgs {
+    /// Replay the commits onto this commit instead of the upstream
+    #[clap(long = "onto")]
+    onto: Option<String>,
+
+    /// Let the user edit the list of commits to replay before replaying them
+    #[clap(short = 'i', long = "interactive")]
+    interactive: bool,
+
+    /// Move `fixup!`, `amend!` and `squash!` commits after the commits they name
+    #[clap(long = "autosquash", overrides_with = "no_autosquash")]
+    autosquash: bool,
+
+    #[clap(long = "no-autosquash")]
+    no_autosquash: bool,
+
+    /// Recreate merges instead of flattening the history
+    #[clap(short = 'r', long = "rebase-merges")]
+    rebase_merges: bool,
+
+    /// Move other branches pointing into the rebased commits along with them
+    #[clap(long = "update-refs", overrides_with = "no_update_refs")]
+    update_refs: bool,
+
+    #[clap(long = "no-update-refs")]
+    no_update_refs: bool,
+
+    /// Commit the resolved commit the rebase stopped at and go on with the rest
+    #[clap(long = "continue", group = "action")]
+    continue_rebase: bool,
+
+    /// Drop the commit the rebase stopped at and go on with the rest
+    #[clap(long = "skip", group = "action")]
+    skip: bool,
+
+    /// Go back to the branch as it was before the rebase
+    #[clap(long = "abort", group = "action")]
+    abort: bool,
+
+    /// Forget the rebase in progress, keeping what it has done so far
+    #[clap(long = "quit", group = "action")]
+    quit: bool,
+
+    /// Edit the rest of the todo list of the rebase in progress
+    #[clap(long = "edit-todo", group = "action")]
+    edit_todo: bool,
+
+    /// The commits reachable from it aren't replayed, the configured upstream when missing
+    #[clap(conflicts_with = "action")]
+    upstream: Option<String>,
+
+    /// The branch to rebase, switched to first
+    #[clap(conflicts_with = "action")]
+    branch: Option<String>,
+}
+
+const IN_PROGRESS: &str = "It seems that there is already a rebase-merge directory, and
+I wonder if you are in the middle of another rebase.  If that is the
+case, please try
+\tgit rebase (--continue | --abort | --skip)
+If that is not the case, please
+\trm -fr \".git/rebase-merge\"
+and run me again.  I am stopping in case you still have something
+valuable there.
+";
+
+pub(crate) fn invoke(args: RebaseArgs) -> anyhow::Result<()> {
+    let outcome = if args.continue_rebase {
+        rebase::resume()?
+    } else if args.skip {
+        rebase::skip()?
+    } else if args.abort {
+        return rebase::abort();
+    } else if args.quit {
+        return rebase::quit();
+    } else if args.edit_todo {
+        rebase::edit_todo()?
+    } else {
+        anyhow::ensure!(!rebase::in_progress(), "{IN_PROGRESS}");
+        match start(&args)? {
+            Some(outcome) => outcome,
+            None => return Ok(()),
+        }
+    };
+    match outcome {
+        Outcome::Done | Outcome::Stopped => Ok(()),
+        Outcome::Failed => std::process::exit(1),
+    }
+}
+
+/// Works out what to rebase onto what, and starts the rebase unless there is nothing to do
+fn start(args: &RebaseArgs) -> anyhow::Result<Option<Outcome>> {
+    let config = config::read_repo_config();
+    let autosquash = match (args.autosquash, args.no_autosquash) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => args.interactive && config.get_bool("rebase.autoSquash").unwrap_or(false),
+    };
+    let update_refs = match (args.update_refs, args.no_update_refs) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => config.get_bool("rebase.updateRefs").unwrap_or(false),
+    };
+
+    // The branch to rebase, and the commit it is at
+    let (head_name, orig_head) = match &args.branch {
+        Some(branch) => {
+            let full = format!("refs/heads/{branch}");
+            match refs::read_ref(&full)? {
+                Some(commit) => (Some(full), commit),
+                None => {
+                    let Ok(commit) =
+                        revision::resolve(branch).and_then(|hash| revision::peel_to_commit(&hash))
+                    else {
+                        anyhow::bail!("no such branch/commit '{branch}'");
+                    };
+                    (None, commit)
+                }
+            }
+        }
+        None => {
+            let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+            match refs::read_head()? {
+                Head::Symbolic(target) => (Some(target), head),
+                Head::Detached(_) => (None, head),
+            }
+        }
+    };
+
+    let (upstream_name, upstream) = match &args.upstream {
+        Some(name) => {
+            let name = expand_previous(name)?;
+            let Ok(commit) =
+                revision::resolve(&name).and_then(|hash| revision::peel_to_commit(&hash))
+            else {
+                anyhow::bail!("invalid upstream '{name}'");
+            };
+            (name, commit)
+        }
+        None => {
+            let configured = head_name
+                .as_deref()
+                .and_then(|name| refs::upstream_of(refs::shorten(name)));
+            let Some(name) = configured else {
+                print_no_upstream(head_name.as_deref());
+                std::process::exit(1);
+            };
+            let commit =
+                refs::read_ref(&name)?.with_context(|| format!("invalid upstream '{name}'"))?;
+            (name, commit)
+        }
+    };
+    let (onto_name, onto) = match &args.onto {
+        Some(name) => (name.clone(), resolve_onto(name)?),
+        None => (upstream_name, upstream),
+    };
+
+    check_clean()?;
+
+    let branch_name = match &head_name {
+        Some(name) => refs::shorten(name).to_string(),
+        None => String::from("HEAD"),
+    };
+    if !args.interactive && !args.rebase_merges && can_fast_forward(&onto, &upstream, &orig_head)? {
+        if let Some(branch) = args.branch.as_deref().filter(|_| head_name.is_some()) {
+            switch_branch(branch)?;
+        }
+        println!("Current branch {branch_name} is up to date.");
+        return Ok(None);
+    }
+
+    let opts = Options {
+        upstream,
+        onto,
+        onto_name,
+        head_name,
+        orig_head,
+        interactive: args.interactive,
+        autosquash,
+        rebase_merges: args.rebase_merges,
+        update_refs,
+    };
+    rebase::start(&opts).map(Some)
+}
+
+/// Resolves `--onto`, where `A...B` stands for the merge base of A and B
+fn resolve_onto(name: &str) -> anyhow::Result<[u8; 20]> {
+    let resolve = |name: &str| -> anyhow::Result<[u8; 20]> {
+        let name = match name {
+            "" => "HEAD",
+            name => name,
+        };
+        revision::resolve(name).and_then(|hash| revision::peel_to_commit(&hash))
+    };
+    if let Some((left, right)) = name.split_once("...") {
+        let (Ok(left), Ok(right)) = (resolve(left), resolve(right)) else {
+            anyhow::bail!("Does not point to a valid commit '{name}'");
+        };
+        let bases = merge_base::merge_bases(&left, &right)?;
+        let [base] = bases.as_slice() else {
+            anyhow::bail!("'{name}': need exactly one merge base");
+        };
+        return Ok(*base);
+    }
+    let Ok(onto) = resolve(name) else {
+        anyhow::bail!("Does not point to a valid commit '{name}'");
+    };
+    Ok(onto)
+}
+
+/// Whether the branch already sits on top of `onto` with nothing from `upstream` missing
+fn can_fast_forward(onto: &[u8; 20], upstream: &[u8; 20], head: &[u8; 20]) -> anyhow::Result<bool> {
+    Ok(merge_base::merge_bases(onto, head)? == [*onto]
+        && merge_base::merge_bases(upstream, head)? == [*onto])
+}
+
+/// Switches to the branch named on the command line before reporting it up to date
+fn switch_branch(branch: &str) -> anyhow::Result<()> {
+    let full = format!("refs/heads/{branch}");
+    let target = refs::read_ref(&full)?.with_context(|| format!("invalid reference: {branch}"))?;
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let mut index = Index::read()?;
+    let old_tree = Commit::read(&head)?.tree;
+    let new_tree = Commit::read(&target)?.tree;
+    checkout::switch_trees(&mut index, Some(&old_tree), Some(&new_tree), false)?;
+    index.write()?;
+    refs::set_head_branch(&full, &format!("rebase: checkout {branch}"))
+}
+
+/// Refuses to rebase over local changes to tracked files, which replaying would clobber
+fn check_clean() -> anyhow::Result<()> {
+    if rebase::report_local_changes()? {
+        eprintln!("error: Please commit or stash them.");
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Explains how to name the upstream when the branch has none configured
+fn print_no_upstream(head_name: Option<&str>) {
+    let Some(name) = head_name else {
+        print!("You are not currently on a branch.\nPlease specify which branch you want to rebase against.\nSee git-rebase(1) for details.\n\n    git rebase '<branch>'\n\n");
+        return;
+    };
+    print!("There is no tracking information for the current branch.\nPlease specify which branch you want to rebase against.\nSee git-rebase(1) for details.\n\n    git rebase '<branch>'\n\nIf you wish to set tracking information for this branch you can do so with:\n\n    git branch --set-upstream-to=<remote>/<branch> {}\n\n", refs::shorten(name));
+}
@@ -0,0 +1,259 @@
+use std::io::Write;
+
+use anyhow::Context;
+
+use crate::{
+    commands::checkout::describe,
+    index::{Index, IndexEntry},
+    object::commit::Commit,
+    pathspec::Pathspec,
+    refs, revision,
+    status::{self, UntrackedMode},
+    unpack::{self, tree_blobs, UnpackOptions},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ResetArgs {
+    /// Only move HEAD, keeping the index and working tree
+    #[clap(long = "soft", group = "mode")]
+    soft: bool,
+
+    /// Reset the index but not the working tree (the default)
+    #[clap(long = "mixed", group = "mode")]
+    mixed: bool,
+
+    /// Reset the index and working tree, discarding local changes
+    #[clap(long = "hard", group = "mode")]
+    hard: bool,
+
+    /// Reset the index and the files that differ between HEAD and the commit, keeping local
+    /// changes that are not staged
+    #[clap(long = "merge", group = "mode")]
+    merge: bool,
+
+    /// Reset the index and the files that differ between HEAD and the commit, refusing to
+    /// touch files with local changes
+    #[clap(long = "keep", group = "mode")]
+    keep: bool,
+
+    /// Suppress feedback messages
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// The commit to reset to, or the tree-ish followed by paths to unstage
+    args: Vec<String>,
+
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Soft,
+    Mixed,
+    Hard,
+    Merge,
+    Keep,
+}
+
+impl Mode {
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Soft => "soft",
+            Mode::Mixed => "mixed",
+            Mode::Hard => "hard",
+            Mode::Merge => "merge",
+            Mode::Keep => "keep",
+        }
+    }
+}
+
+pub(crate) fn invoke(args: ResetArgs) -> anyhow::Result<()> {
+    let mode = match (args.soft, args.hard, args.merge, args.keep) {
+        (true, ..) => Mode::Soft,
+        (_, true, ..) => Mode::Hard,
+        (_, _, true, _) => Mode::Merge,
+        (.., true) => Mode::Keep,
+        _ => Mode::Mixed,
+    };
+
+    // Without `--`, a first argument that isn't a revision starts the paths
+    let mut rest = args.args.into_iter();
+    let first = rest.next();
+    let mut paths: Vec<String> = rest.collect();
+    let explicit_paths = !args.paths.is_empty();
+    paths.extend(args.paths);
+    let rev = match first {
+        Some(first) if explicit_paths || revision::resolve(&first).is_ok() => Some(first),
+        Some(first) => {
+            anyhow::ensure!(
+                std::fs::symlink_metadata(&first).is_ok(),
+                "ambiguous argument '{first}': unknown revision or path not in the working tree.\nUse '--' to separate paths from revisions, like this:\n'git <command> [<revision>...] -- [<file>...]'"
+            );
+            paths.insert(0, first);
+            None
+        }
+        None => None,
+    };
+    if !paths.is_empty() {
+        anyhow::ensure!(
+            mode == Mode::Mixed,
+            "Cannot do {} reset with paths.",
+            mode.name()
+        );
+        if args.mixed {
+            eprintln!(
+                "warning: --mixed with paths is deprecated; use 'git reset -- <paths>' instead."
+            );
+        }
+        return reset_paths(rev.as_deref(), &Pathspec::new(&paths), args.quiet);
+    }
+
+    let rev = rev.unwrap_or_else(|| String::from("HEAD"));
+    let old_head = refs::head_commit()?;
+    // Resetting an unborn branch to itself empties the index
+    let target = match (rev.as_str(), old_head) {
+        ("HEAD", None) => None,
+        _ => Some(
+            revision::resolve(&rev)
+                .and_then(|hash| revision::peel_to_commit(&hash))
+                .with_context(|| format!("Failed to resolve '{rev}' as a valid revision."))?,
+        ),
+    };
+    let target_tree = match target {
+        Some(commit) => Some(Commit::read(&commit)?.tree),
+        None => None,
+    };
+
+    let mut index = Index::read()?;
+    match mode {
+        Mode::Soft => {
+            anyhow::ensure!(
+                !index.has_conflicts() && !std::path::Path::new(".git/MERGE_HEAD").exists(),
+                "Cannot do a soft reset in the middle of a merge."
+            );
+        }
+        Mode::Mixed | Mode::Hard => {
+            let opts = UnpackOptions {
+                update: mode == Mode::Hard,
+                reset: true,
+                ..UnpackOptions::default()
+            };
+            unpack::one_way(&mut index, target_tree.as_ref(), opts)?;
+        }
+        Mode::Merge => {
+            let opts = UnpackOptions {
+                update: true,
+                overwrite_unmerged: true,
+                ..UnpackOptions::default()
+            };
+            unpack::one_way(&mut index, target_tree.as_ref(), opts)
+                .with_context(|| format!("Could not reset index file to revision '{rev}'."))?;
+        }
+        Mode::Keep => {
+            let head = old_head.context("You do not have a valid HEAD.")?;
+            let head_tree = Commit::read(&head)?.tree;
+            let opts = UnpackOptions {
+                update: true,
+                ..UnpackOptions::default()
+            };
+            unpack::two_way(&mut index, Some(&head_tree), target_tree.as_ref(), opts)
+                .with_context(|| format!("Could not reset index file to revision '{rev}'."))?;
+            // Files left alone keep their local changes, but staged ones are unstaged
+            let opts = UnpackOptions {
+                reset: true,
+                ..UnpackOptions::default()
+            };
+            unpack::one_way(&mut index, target_tree.as_ref(), opts)?;
+        }
+    }
+    if mode == Mode::Mixed {
+        refresh(&mut index, args.quiet)?;
+    }
+    if mode != Mode::Soft {
+        index.write()?;
+    }
+
+    if let Some(target) = target {
+        if let Some(old) = old_head {
+            refs::update_ref("ORIG_HEAD", &old, "updating ORIG_HEAD")?;
+        }
+        refs::update_ref("HEAD", &target, &format!("reset: moving to {rev}"))?;
+    }
+    refs::clear_merge_state()?;
+
+    if let (Mode::Hard, Some(target), false) = (mode, target, args.quiet) {
+        println!("HEAD is now at {}", describe(&target)?);
+    }
+    Ok(())
+}
+
+/// Resets the index entries of the matching paths to their state in `rev`, leaving HEAD and the
+/// working tree alone
+fn reset_paths(rev: Option<&str>, pathspec: &Pathspec, quiet: bool) -> anyhow::Result<()> {
+    let tree = match rev {
+        Some(rev) => Some(
+            revision::resolve(rev)
+                .and_then(|hash| revision::peel_to_tree(&hash))
+                .with_context(|| format!("Failed to resolve '{rev}' as a valid tree."))?,
+        ),
+        None => match refs::head_commit()? {
+            Some(head) => Some(Commit::read(&head)?.tree),
+            None => None,
+        },
+    };
+    let target = tree_blobs(tree.as_ref())?;
+
+    let mut index = Index::read()?;
+    let mut paths: Vec<String> = index
+        .entries()
+        .iter()
+        .map(|entry| entry.path.clone())
+        .chain(target.keys().cloned())
+        .filter(|path| pathspec.matches(path))
+        .collect();
+    paths.sort();
+    paths.dedup();
+    for path in paths {
+        match target.get(&path) {
+            Some(&(mode, hash)) => {
+                let current = index.stages(&path);
+                let unchanged = matches!(current, [entry] if entry.stage == 0 && entry.mode == mode && entry.hash == hash);
+                if !unchanged {
+                    index.add(IndexEntry::new(path, mode, hash, 0));
+                }
+            }
+            None => {
+                index.remove(&path);
+            }
+        }
+    }
+    refresh(&mut index, quiet)?;
+    index.write()
+}
+
+/// Refreshes cached stat information and lists the paths that still differ from the index
+fn refresh(index: &mut Index, quiet: bool) -> anyhow::Result<()> {
+    let report = status::compute(index, None, UntrackedMode::No)?;
+    let mut unstaged: Vec<(char, &str)> = report
+        .changes
+        .iter()
+        .filter_map(|change| Some((change.unstaged?.letter(), change.path.as_str())))
+        .chain(
+            report
+                .unmerged
+                .iter()
+                .map(|unmerged| ('U', unmerged.path.as_str())),
+        )
+        .collect();
+    if quiet || unstaged.is_empty() {
+        return Ok(());
+    }
+    unstaged.sort_by_key(|&(_, path)| path);
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "Unstaged changes after reset:")?;
+    for (letter, path) in unstaged {
+        writeln!(out, "{letter}\t{path}")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use anyhow::Context;
+
+use crate::{
+    checkout::{self, PathOptions},
+    index::Index,
+    pathspec::Pathspec,
+    revision,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct RestoreArgs {
+    /// Tree-ish to restore from, instead of the index (or HEAD with --staged)
+    #[clap(short = 's', long = "source")]
+    source: Option<String>,
+
+    /// Restore the index
+    #[clap(short = 'S', long = "staged")]
+    staged: bool,
+
+    /// Restore the working tree, the default unless --staged is given
+    #[clap(short = 'W', long = "worktree")]
+    worktree: bool,
+
+    /// Paths to restore
+    paths: Vec<String>,
+
+    #[clap(last = true)]
+    more_paths: Vec<String>,
+}
+
+pub(crate) fn invoke(args: RestoreArgs) -> anyhow::Result<()> {
+    let mut paths = args.paths;
+    paths.extend(args.more_paths);
+    anyhow::ensure!(!paths.is_empty(), "you must specify path(s) to restore");
+
+    let worktree = args.worktree || !args.staged;
+    let source = match args.source.as_deref() {
+        Some(source) => Some(source),
+        None if args.staged => Some("HEAD"),
+        None => None,
+    };
+    let source = match source {
+        Some(source) => Some(
+            revision::resolve(source)
+                .and_then(|hash| revision::peel_to_tree(&hash))
+                .with_context(|| format!("could not resolve {source}"))?,
+        ),
+        None => None,
+    };
+
+    let mut index = Index::read()?;
+    let opts = PathOptions {
+        staged: args.staged,
+        worktree,
+        overlay: false,
+    };
+    checkout::checkout_paths(&mut index, source.as_ref(), &Pathspec::new(&paths), opts)?;
+    // Checking out from the index refreshes stat information worth keeping
+    if args.staged || source.is_none() {
+        index.write()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use std::io::Write;
+
+use crate::{
+    commands::log::RevisionArgs,
+    pathspec::Pathspec,
+    revwalk::{RevWalk, Revisions, Sort, WalkOptions},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct RevListArgs {
+    /// List at most this many commits
+    #[clap(short = 'n', long = "max-count")]
+    max_count: Option<usize>,
+
+    /// Skip this many commits before listing any
+    #[clap(long = "skip", default_value_t = 0)]
+    skip: usize,
+
+    /// List the commits oldest first
+    #[clap(long = "reverse")]
+    reverse: bool,
+
+    /// Show the parents of each commit after its id
+    #[clap(long = "parents")]
+    parents: bool,
+
+    /// Also list the excluded commits the listed ones have as parents, marked with `-`
+    #[clap(long = "boundary")]
+    boundary: bool,
+
+    /// Print the number of commits instead of listing them
+    #[clap(long = "count")]
+    count: bool,
+
+    /// Mark which side of `a...b` each commit is reachable from with `<` or `>`
+    #[clap(long = "left-right")]
+    left_right: bool,
+
+    /// Leave out commits of `a...b` whose change is also on the other side
+    #[clap(long = "cherry-pick")]
+    cherry_pick: bool,
+
+    /// Also list the trees and blobs the commits need, with their paths
+    #[clap(long = "objects")]
+    objects: bool,
+
+    /// Like --objects, also listing the excluded commits the walk stops at, marked with `-`
+    #[clap(long = "objects-edge")]
+    objects_edge: bool,
+
+    /// Follow only the first parent of merge commits
+    #[clap(long = "first-parent")]
+    first_parent: bool,
+
+    /// Do not list merge commits
+    #[clap(long = "no-merges")]
+    no_merges: bool,
+
+    /// List no parents before all of their children, otherwise by commit date
+    #[clap(long = "date-order")]
+    date_order: bool,
+
+    /// List no parents before all of their children, keeping lines of history together
+    #[clap(long = "topo-order")]
+    topo_order: bool,
+
+    #[clap(flatten)]
+    revisions: RevisionArgs,
+}
+
+pub(crate) fn invoke(args: RevListArgs) -> anyhow::Result<()> {
+    let revisions = Revisions::parse(&args.revisions.args, &args.revisions.paths, false)?;
+    if revisions.include.is_empty() && revisions.exclude.is_empty() {
+        anyhow::bail!("usage: git rev-list [<options>] <commit>... [--] [<path>...]");
+    }
+    let sort = if args.date_order {
+        Sort::Date
+    } else if args.topo_order {
+        Sort::Topo
+    } else {
+        Sort::Default
+    };
+    let opts = WalkOptions {
+        sort,
+        first_parent: args.first_parent,
+        no_merges: args.no_merges,
+        max_count: args.max_count,
+        skip: args.skip,
+        paths: (!revisions.paths.is_empty()).then(|| Pathspec::new(&revisions.paths)),
+        rewrite_parents: args.parents,
+        boundary: args.boundary,
+        cherry_pick: args.cherry_pick,
+        ..WalkOptions::default()
+    };
+    let mut walk = RevWalk::new(&revisions, opts)?;
+    let mut out = std::io::stdout().lock();
+
+    let objects = args.objects || args.objects_edge;
+    if args.objects_edge {
+        for edge in walk.edges() {
+            writeln!(out, "-{}", hex::encode(edge))?;
+        }
+    }
+    let mut commits = Vec::new();
+    while let Some(hash) = walk.next()? {
+        commits.push(hash);
+    }
+    if args.reverse {
+        commits.reverse();
+    }
+
+    if args.count {
+        let left = commits.iter().filter(|hash| walk.is_left(hash)).count();
+        if args.left_right {
+            writeln!(out, "{left}\t{}", commits.len() - left)?;
+        } else {
+            writeln!(out, "{}", commits.len())?;
+        }
+        return Ok(());
+    }
+    for hash in &commits {
+        let mark = if walk.is_boundary(hash) {
+            "-"
+        } else if !args.left_right {
+            ""
+        } else if walk.is_left(hash) {
+            "<"
+        } else {
+            ">"
+        };
+        let mut line = format!("{mark}{}", hex::encode(hash));
+        if args.parents {
+            for parent in walk.parents(hash) {
+                line.push_str(&format!(" {}", hex::encode(parent)));
+            }
+        }
+        writeln!(out, "{line}")?;
+    }
+    if objects {
+        for (hash, path) in walk.objects(&commits, &revisions.tags)? {
+            writeln!(out, "{} {path}", hex::encode(hash))?;
+        }
+    }
+    Ok(())
+}
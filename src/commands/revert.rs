@@ -0,0 +1,14 @@
+use crate::{
+    commands::cherry_pick::{self, ReplayArgs},
+    sequencer::Action,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct RevertArgs {
+    #[clap(flatten)]
+    replay: ReplayArgs,
+}
+
+pub(crate) fn invoke(args: RevertArgs) -> anyhow::Result<()> {
+    cherry_pick::replay(Action::Revert, args.replay, false)
+}
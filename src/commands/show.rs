@@ -0,0 +1,265 @@
+use std::{collections::HashSet, io::Write};
+
+use crate::{
+    commands::{
+        diff::{CombinedArgs, DiffFormatArgs},
+        log::Decorate,
+    },
+    date::DateMode,
+    decorate::Decorations,
+    diff::{files, rename},
+    object::{
+        commit::{Commit, Tag},
+        read::{parse_tree, read_object},
+        ObjectKind, MODE_TREE,
+    },
+    pathspec::Pathspec,
+    pretty::{self, Format, PrettyOptions, ShownCommit},
+    revision,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ShowArgs {
+    /// Show each commit on one line with its abbreviated id and subject
+    #[clap(long = "oneline")]
+    oneline: bool,
+
+    /// Format of the commits: oneline, short, medium, full, fuller, raw or format:<template>
+    #[clap(long = "pretty", num_args = 0..=1, require_equals = true, default_missing_value = "medium")]
+    pretty: Option<String>,
+
+    /// Template for the commits, like --pretty=tformat:<template>
+    #[clap(long = "format")]
+    format: Option<String>,
+
+    /// Abbreviate commit ids
+    #[clap(long = "abbrev-commit")]
+    abbrev_commit: bool,
+
+    /// Format of dates: default, iso, iso-strict, rfc, short, raw, unix or relative
+    #[clap(long = "date")]
+    date: Option<String>,
+
+    /// Show the names of refs pointing at commits: short, full, auto or no
+    #[clap(long = "decorate", num_args = 0..=1, require_equals = true, default_missing_value = "short")]
+    decorate: Option<String>,
+
+    /// Don't show the names of refs pointing at commits
+    #[clap(long = "no-decorate")]
+    no_decorate: bool,
+
+    /// Show commits without their diff
+    #[clap(short = 's', long = "no-patch")]
+    no_patch: bool,
+
+    #[clap(flatten)]
+    diff: DiffFormatArgs,
+
+    #[clap(flatten)]
+    combined: CombinedArgs,
+
+    /// Objects to show, as revisions or `<rev>:<path>`; HEAD if none are given
+    objects: Vec<String>,
+
+    /// Paths to limit the diffs of commits to
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+/// Output state across the objects shown
+struct Shower<'a> {
+    args: &'a ShowArgs,
+    pretty: PrettyOptions,
+    decorations: Option<Decorations>,
+    pathspec: Pathspec,
+    /// Whether anything but a blob was shown, so the next object is set apart by a blank line
+    shown_one: bool,
+    /// Commits are shown once, however often they're named
+    shown_commits: HashSet<[u8; 20]>,
+}
+
+pub(crate) fn invoke(args: ShowArgs) -> anyhow::Result<()> {
+    let format = match (&args.format, &args.pretty) {
+        (Some(format), _) => Format::parse(format)?,
+        (None, Some(pretty)) => Format::parse(pretty)?,
+        (None, None) if args.oneline => Format::Oneline,
+        (None, None) => Format::Medium,
+    };
+    let decorate = Decorate::from_args(args.decorate.as_deref(), args.no_decorate)?;
+    // `%d` shows decorations even without `--decorate`
+    let decorations = if decorate != Decorate::No || matches!(format, Format::Template { .. }) {
+        Some(Decorations::load(decorate == Decorate::Full)?)
+    } else {
+        None
+    };
+    let pretty = PrettyOptions {
+        format,
+        abbrev_commit: args.abbrev_commit || args.oneline,
+        date_mode: match &args.date {
+            Some(mode) => DateMode::parse(mode)?,
+            None => DateMode::Default,
+        },
+        decorate: decorate != Decorate::No,
+    };
+
+    // Every name is resolved before anything is shown
+    let names = if args.objects.is_empty() {
+        vec![String::from("HEAD")]
+    } else {
+        args.objects.clone()
+    };
+    let mut objects = Vec::new();
+    for name in &names {
+        objects.push((name, revision::resolve(name)?));
+    }
+
+    let mut shower = Shower {
+        args: &args,
+        pretty,
+        decorations,
+        pathspec: Pathspec::new(&args.paths),
+        shown_one: false,
+        shown_commits: HashSet::new(),
+    };
+    let mut out = std::io::stdout().lock();
+    for (name, hash) in objects {
+        shower.show(&mut out, name, hash)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+impl Shower<'_> {
+    /// Shows an object: blobs as they are, trees as a list of names, and tags followed by the
+    /// object they point at
+    fn show(&mut self, out: &mut impl Write, name: &str, hash: [u8; 20]) -> anyhow::Result<()> {
+        let mut hash = hash;
+        loop {
+            let (kind, content) = read_object(&hex::encode(hash))?;
+            match kind {
+                ObjectKind::Blob => {
+                    out.write_all(&content)?;
+                    return Ok(());
+                }
+                ObjectKind::Tree => {
+                    if self.shown_one {
+                        writeln!(out)?;
+                    }
+                    write!(out, "tree {name}\n\n")?;
+                    for item in parse_tree(&content)? {
+                        let slash = if item.mode == MODE_TREE { "/" } else { "" };
+                        writeln!(out, "{}{slash}", item.name)?;
+                    }
+                    self.shown_one = true;
+                    return Ok(());
+                }
+                ObjectKind::Tag => {
+                    let tag = Tag::parse(&content)?;
+                    if self.shown_one {
+                        writeln!(out)?;
+                    }
+                    writeln!(out, "tag {}", tag.name)?;
+                    if let Some(tagger) = &tag.tagger {
+                        write!(out, "{}", pretty::format_tagger(tagger, &self.pretty))?;
+                    }
+                    write!(out, "\n{}", tag.message)?;
+                    self.shown_one = true;
+                    hash = tag.object;
+                }
+                ObjectKind::Commit => {
+                    let commit = Commit::parse(&content)?;
+                    return self.show_commit(out, &hash, &commit);
+                }
+            }
+        }
+    }
+
+    /// Whether paths were given and the commit doesn't change them relative to some parent,
+    /// so it isn't shown, as `log` would leave it out
+    fn is_treesame(&self, commit: &Commit) -> anyhow::Result<bool> {
+        if self.args.paths.is_empty() {
+            return Ok(false);
+        }
+        if commit.parents.is_empty() {
+            let pairs = files::tree_to_tree(None, Some(&commit.tree), &self.pathspec, false)?;
+            return Ok(pairs.is_empty());
+        }
+        for parent in &commit.parents {
+            let parent = Commit::read(parent)?.tree;
+            if files::tree_to_tree(Some(&parent), Some(&commit.tree), &self.pathspec, false)?
+                .is_empty()
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Shows a commit as `log` does, followed by its changes relative to its first parent, or
+    /// a combined diff against all parents for a merge
+    fn show_commit(
+        &mut self,
+        out: &mut impl Write,
+        hash: &[u8; 20],
+        commit: &Commit,
+    ) -> anyhow::Result<()> {
+        if !self.shown_commits.insert(*hash) || self.is_treesame(commit)? {
+            return Ok(());
+        }
+        let format = &self.pretty.format;
+        let mut entry = String::new();
+        if self.shown_one && !format.uses_terminator() {
+            entry.push('\n');
+        }
+        self.shown_one = true;
+        let shown = ShownCommit {
+            hash,
+            commit,
+            parents: &commit.parents,
+            decorations: &self
+                .decorations
+                .as_ref()
+                .map(|decorations| decorations.of(hash))
+                .unwrap_or_default(),
+        };
+        entry.push_str(&pretty::format_commit(&shown, &self.pretty)?);
+        if format.uses_terminator() && !format.is_empty() {
+            entry.push('\n');
+        }
+        write!(out, "{entry}")?;
+        if self.args.no_patch {
+            return Ok(());
+        }
+
+        let diff = &self.args.diff;
+        let first_parent = match commit.parents.first() {
+            Some(parent) => Some(Commit::read(parent)?.tree),
+            None => None,
+        };
+        let first_parent_pairs = || -> anyhow::Result<_> {
+            let unchanged = diff.find_copies_harder(true)?;
+            let pairs = files::tree_to_tree(
+                first_parent.as_ref(),
+                Some(&commit.tree),
+                &self.pathspec,
+                unchanged,
+            )?;
+            Ok(match diff.rename_options(true)? {
+                Some(opts) => rename::detect(pairs, &opts)?,
+                None => pairs,
+            })
+        };
+
+        if commit.parents.len() > 1 {
+            return diff.write_merge_after_message(
+                out,
+                format,
+                commit,
+                &self.pathspec,
+                self.args.combined.is_dense(),
+            );
+        }
+
+        diff.write_after_message(out, format, &first_parent_pairs()?)
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::Context;
+
+use crate::{
+    commands::{
+        checkout::{self, Switch},
+        diff::DiffFormatArgs,
+    },
+    diff::{files::tree_to_tree, rename},
+    object::commit::Commit,
+    pathspec::Pathspec,
+    stash::{self, Outcome, PushOptions},
+};
+
+#[derive(clap::Args, Debug)]
+#[clap(args_conflicts_with_subcommands = true)]
+pub struct StashArgs {
+    #[clap(subcommand)]
+    command: Option<StashCommand>,
+
+    /// Without a subcommand, the options of `stash push`
+    #[clap(flatten)]
+    push: PushArgs,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum StashCommand {
+    /// Save the local changes as a new stash entry and reset them away
+    Push(PushArgs),
+    /// List the stash entries, newest first
+    List,
+    /// Show the changes recorded in a stash entry, as a diffstat by default
+    Show {
+        #[clap(flatten)]
+        format: DiffFormatArgs,
+
+        stash: Option<String>,
+    },
+    /// Apply the changes of a stash entry, keeping the entry
+    Apply(ApplyArgs),
+    /// Apply the changes of a stash entry and drop it unless that fails
+    Pop(ApplyArgs),
+    /// Remove a stash entry
+    Drop {
+        #[clap(short = 'q', long = "quiet")]
+        quiet: bool,
+
+        stash: Option<String>,
+    },
+    /// Create a branch at the commit a stash entry was made on and apply the entry there
+    Branch { name: String, stash: Option<String> },
+}
+
+#[derive(clap::Args, Debug)]
+struct PushArgs {
+    /// Stash untracked files too, then remove them
+    #[clap(short = 'u', long = "include-untracked")]
+    include_untracked: bool,
+
+    /// Leave the changes that are staged in the index and working tree
+    #[clap(short = 'k', long = "keep-index")]
+    keep_index: bool,
+
+    /// Describe the entry with a message instead of the commit it was made on
+    #[clap(short = 'm', long = "message")]
+    message: Option<String>,
+
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Only stash the changes to these paths
+    paths: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ApplyArgs {
+    /// Restore the staged changes to the index as well
+    #[clap(long = "index")]
+    index: bool,
+
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    stash: Option<String>,
+}
+
+pub(crate) fn invoke(args: StashArgs) -> anyhow::Result<()> {
+    match args.command.unwrap_or(StashCommand::Push(args.push)) {
+        StashCommand::Push(args) => {
+            stash::push(&PushOptions {
+                paths: args.paths,
+                include_untracked: args.include_untracked,
+                keep_index: args.keep_index,
+                message: args.message,
+                quiet: args.quiet,
+            })?;
+        }
+        StashCommand::List => {
+            for (n, message) in stash::list()?.iter().enumerate() {
+                println!("stash@{{{n}}}: {message}");
+            }
+        }
+        StashCommand::Show { mut format, stash } => show(&mut format, stash.as_deref())?,
+        StashCommand::Apply(args) => {
+            let stash = stash::resolve(args.stash.as_deref())?;
+            if stash::apply(&stash, args.index, args.quiet)? == Outcome::Failed {
+                std::process::exit(1);
+            }
+        }
+        StashCommand::Pop(args) => {
+            let stash = stash::resolve(args.stash.as_deref())?;
+            anyhow::ensure!(
+                stash.entry.is_some(),
+                "'{}' is not a stash reference",
+                stash.name
+            );
+            if stash::apply(&stash, args.index, args.quiet)? == Outcome::Failed {
+                println!("The stash entry is kept in case you need it again.");
+                std::process::exit(1);
+            }
+            stash::drop(&stash, args.quiet)?;
+        }
+        StashCommand::Drop { quiet, stash } => {
+            let stash = stash::resolve(stash.as_deref())?;
+            stash::drop(&stash, quiet)?;
+        }
+        StashCommand::Branch { name, stash } => {
+            let stash = stash::resolve(stash.as_deref())?;
+            checkout::switch_to(Switch {
+                target: Some(hex::encode(stash.base)),
+                new_branch: Some((name, false)),
+                ..Switch::default()
+            })?;
+            if stash::apply(&stash, true, false)? == Outcome::Failed {
+                std::process::exit(1);
+            }
+            if stash.entry.is_some() {
+                stash::drop(&stash, false)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Shows the changes of a stash entry against the commit it was made on
+fn show(format: &mut DiffFormatArgs, stash: Option<&str>) -> anyhow::Result<()> {
+    format.use_stat_default();
+    let stash = stash::resolve(stash)?;
+    let old = Commit::read(&stash.base)?.tree;
+    let new = Commit::read(&stash.commit)?.tree;
+    let mut pairs = tree_to_tree(Some(&old), Some(&new), &Pathspec::new(&[]), false)?;
+    if let Some(renames) = format.rename_options(true)? {
+        pairs = rename::detect(pairs, &renames)?;
+    }
+    let mut out = std::io::stdout().lock();
+    format.write(&mut out, &pairs).context("Writing diff")
+}
@@ -0,0 +1,715 @@
+use std::{io::Write, path::Path};
+
+use anyhow::Context;
+
+use crate::{
+    config,
+    index::Index,
+    object::commit::Commit,
+    quote, rebase,
+    refs::{self, Head},
+    sequencer::{self, Action},
+    status::{self, StatusReport, UntrackedMode},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Long,
+    Short,
+    PorcelainV1,
+    PorcelainV2,
+}
+
+/// Branch state shown in the status header
+pub(crate) struct BranchInfo {
+    /// Branch name, `None` when HEAD is detached
+    pub(crate) name: Option<String>,
+    pub(crate) head: Option<[u8; 20]>,
+    pub(crate) upstream: Option<Upstream>,
+}
+
+/// The operations in progress that the long format describes ahead of the changes
+#[derive(Default)]
+pub(crate) struct InProgress {
+    /// `MERGE_HEAD` exists, as during a merge or a merge recreated by a rebase
+    pub(crate) merging: bool,
+    pub(crate) rebase: Option<rebase::Progress>,
+    pub(crate) replaying: Option<(Action, Option<[u8; 20]>)>,
+}
+
+pub(crate) struct Upstream {
+    name: String,
+    /// Ahead/behind counts, `None` if the upstream ref no longer exists
+    ahead_behind: Option<(usize, usize)>,
+}
+
+pub(crate) fn invoke(
+    short: bool,
+    branch: bool,
+    porcelain: Option<String>,
+    null_terminated: bool,
+    untracked_files: String,
+) -> anyhow::Result<()> {
+    let format = match porcelain.as_deref() {
+        Some("v1") | Some("1") => Format::PorcelainV1,
+        Some("v2") | Some("2") => Format::PorcelainV2,
+        Some(version) => anyhow::bail!("Unsupported porcelain version: {version}"),
+        None if short => Format::Short,
+        None if null_terminated => Format::PorcelainV1,
+        None => Format::Long,
+    };
+    let untracked = match untracked_files.as_str() {
+        "no" => UntrackedMode::No,
+        "normal" => UntrackedMode::Normal,
+        "all" => UntrackedMode::All,
+        other => anyhow::bail!("Invalid untracked files mode: {other}"),
+    };
+
+    let info = branch_info()?;
+    let head_tree = match info.head {
+        Some(head) => Some(Commit::read(&head)?.tree),
+        None => None,
+    };
+    let mut index = Index::read()?;
+    let mut report = status::compute(&mut index, head_tree.as_ref(), untracked)?;
+    report.detect_renames()?;
+    if report.index_refreshed {
+        // Refreshing cached stat info is an optimisation, another process may hold the lock
+        let _ = index.write();
+    }
+
+    let mut out = std::io::stdout().lock();
+    match format {
+        Format::Long => {
+            let state = in_progress()?;
+            let hints = config::read_repo_config()
+                .get_bool("advice.statusHints")
+                .unwrap_or(true);
+            print_long(&mut out, &info, &report, untracked, &state, hints)
+        }
+        Format::Short | Format::PorcelainV1 => {
+            print_short(&mut out, &info, &report, branch, null_terminated)
+        }
+        Format::PorcelainV2 => print_v2(&mut out, &info, &report, branch, null_terminated),
+    }
+    .context("Writing status")
+}
+
+pub(crate) fn in_progress() -> anyhow::Result<InProgress> {
+    Ok(InProgress {
+        merging: Path::new(".git/MERGE_HEAD").exists(),
+        rebase: rebase::progress()?,
+        replaying: sequencer::in_progress()?,
+    })
+}
+
+/// The long status as the template of a commit message shows it: without hints, compared with
+/// `tree` (the parent's when amending), and commented out line by line
+pub(crate) fn commit_template(tree: Option<&[u8; 20]>) -> anyhow::Result<String> {
+    let info = branch_info()?;
+    let mut index = Index::read()?;
+    let mut report = status::compute(&mut index, tree, UntrackedMode::Normal)?;
+    report.detect_renames()?;
+    let mut out = Vec::new();
+    print_long(
+        &mut out,
+        &info,
+        &report,
+        UntrackedMode::Normal,
+        &in_progress()?,
+        false,
+    )?;
+    let mut template = String::new();
+    for line in String::from_utf8_lossy(&out).lines() {
+        if line.is_empty() {
+            template.push_str("#\n");
+        } else if line.starts_with('\t') {
+            template.push_str(&format!("#{line}\n"));
+        } else {
+            template.push_str(&format!("# {line}\n"));
+        }
+    }
+    Ok(template)
+}
+
+pub(crate) fn branch_info() -> anyhow::Result<BranchInfo> {
+    let head = refs::head_commit()?;
+    let name = match refs::read_head()? {
+        Head::Symbolic(target) => Some(refs::shorten(&target).to_string()),
+        Head::Detached(_) => None,
+    };
+    let upstream = match (&name, head) {
+        (Some(name), Some(head)) => match refs::upstream_of(name) {
+            Some(upstream) => Some(Upstream {
+                name: refs::shorten(&upstream).to_string(),
+                ahead_behind: match refs::read_ref(&upstream)? {
+                    Some(theirs) => Some(crate::revwalk::ahead_behind(&head, &theirs)?),
+                    None => None,
+                },
+            }),
+            None => None,
+        },
+        _ => None,
+    };
+    Ok(BranchInfo {
+        name,
+        head,
+        upstream,
+    })
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        "commit"
+    } else {
+        "commits"
+    }
+}
+
+/// Describes how the current branch relates to its upstream, as status and checkout show it
+pub(crate) fn print_tracking(out: &mut impl Write, upstream: &Upstream) -> std::io::Result<()> {
+    let name = &upstream.name;
+    match upstream.ahead_behind {
+        None => {
+            writeln!(
+                out,
+                "Your branch is based on '{name}', but the upstream is gone."
+            )?;
+            writeln!(out, "  (use \"git branch --unset-upstream\" to fixup)")?;
+        }
+        Some((0, 0)) => writeln!(out, "Your branch is up to date with '{name}'.")?,
+        Some((ahead, 0)) => {
+            writeln!(
+                out,
+                "Your branch is ahead of '{name}' by {ahead} {}.",
+                plural(ahead)
+            )?;
+            writeln!(out, "  (use \"git push\" to publish your local commits)")?;
+        }
+        Some((0, behind)) => {
+            writeln!(
+                out,
+                "Your branch is behind '{name}' by {behind} {}, and can be fast-forwarded.",
+                plural(behind)
+            )?;
+            writeln!(out, "  (use \"git pull\" to update your local branch)")?;
+        }
+        Some((ahead, behind)) => {
+            writeln!(out, "Your branch and '{name}' have diverged,")?;
+            writeln!(
+                out,
+                "and have {ahead} and {behind} different commits each, respectively."
+            )?;
+            writeln!(
+                out,
+                "  (use \"git pull\" to merge the remote branch into yours)"
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn print_long(
+    out: &mut impl Write,
+    info: &BranchInfo,
+    report: &StatusReport,
+    untracked: UntrackedMode,
+    state: &InProgress,
+    hints: bool,
+) -> std::io::Result<()> {
+    match (&info.name, info.head, &state.rebase) {
+        (Some(name), _, _) => writeln!(out, "On branch {name}")?,
+        (None, Some(_), Some(rebase)) => {
+            writeln!(out, "interactive rebase in progress; onto {}", rebase.onto)?
+        }
+        (None, Some(head), None) => writeln!(out, "HEAD detached at {}", &hex::encode(head)[..7])?,
+        (None, None, _) => writeln!(out, "Not currently on any branch.")?,
+    }
+    if let Some(upstream) = &info.upstream {
+        print_tracking(out, upstream)?;
+        writeln!(out)?;
+    }
+    if state.merging {
+        if let Some(rebase) = &state.rebase {
+            print_rebase_progress(out, rebase, hints)?;
+            writeln!(out)?;
+        }
+        print_merge(out, report, hints)?;
+    } else if let Some(rebase) = &state.rebase {
+        print_rebase(out, rebase, report, hints)?;
+    } else if let Some((action, commit)) = state.replaying {
+        print_replay(out, action, commit, report, hints)?;
+    }
+    if info.head.is_none() {
+        writeln!(out, "\nNo commits yet\n")?;
+    }
+
+    // Unstaging is only suggested when the next commit is an ordinary one, not a merge or
+    // cherry-pick being concluded
+    let concluding =
+        Path::new(".git/MERGE_HEAD").exists() || Path::new(".git/CHERRY_PICK_HEAD").exists();
+    let unstage_hint = match info.head {
+        _ if concluding || !hints => None,
+        Some(_) => Some("  (use \"git restore --staged <file>...\" to unstage)"),
+        None => Some("  (use \"git rm --cached <file>...\" to unstage)"),
+    };
+    if report.has_staged() {
+        writeln!(out, "Changes to be committed:")?;
+        if let Some(hint) = unstage_hint {
+            writeln!(out, "{hint}")?;
+        }
+        for change in &report.changes {
+            if let Some(kind) = change.staged {
+                let path = quote::c_style(&change.path, false);
+                match &change.source {
+                    Some((source, _)) => writeln!(
+                        out,
+                        "\t{:<12}{} -> {path}",
+                        describe(kind),
+                        quote::c_style(source, false)
+                    )?,
+                    None => writeln!(out, "\t{:<12}{path}", describe(kind))?,
+                }
+            }
+        }
+        writeln!(out)?;
+    }
+
+    if !report.unmerged.is_empty() {
+        writeln!(out, "Unmerged paths:")?;
+        if let Some(hint) = unstage_hint {
+            writeln!(out, "{hint}")?;
+        }
+        let codes: Vec<&str> = report
+            .unmerged
+            .iter()
+            .map(|unmerged| unmerged.code())
+            .collect();
+        let deleted_modified = codes.iter().any(|code| matches!(*code, "UD" | "DU"));
+        if hints {
+            let hint = if !deleted_modified && !codes.contains(&"DD") {
+                "  (use \"git add <file>...\" to mark resolution)"
+            } else if codes.iter().all(|code| *code == "DD") {
+                "  (use \"git rm <file>...\" to mark resolution)"
+            } else {
+                "  (use \"git add/rm <file>...\" as appropriate to mark resolution)"
+            };
+            writeln!(out, "{hint}")?;
+        }
+        for unmerged in &report.unmerged {
+            writeln!(
+                out,
+                "\t{:<17}{}",
+                unmerged.description(),
+                quote::c_style(&unmerged.path, false)
+            )?;
+        }
+        writeln!(out)?;
+    }
+
+    if report.has_unstaged() {
+        let has_deletions = report
+            .changes
+            .iter()
+            .any(|change| change.unstaged == Some(status::ChangeKind::Deleted));
+        writeln!(out, "Changes not staged for commit:")?;
+        if hints {
+            let add = if has_deletions { "add/rm" } else { "add" };
+            writeln!(
+                out,
+                "  (use \"git {add} <file>...\" to update what will be committed)"
+            )?;
+            writeln!(
+                out,
+                "  (use \"git restore <file>...\" to discard changes in working directory)"
+            )?;
+        }
+        for change in &report.changes {
+            if let Some(kind) = change.unstaged {
+                writeln!(
+                    out,
+                    "\t{:<12}{}",
+                    describe(kind),
+                    quote::c_style(&change.path, false)
+                )?;
+            }
+        }
+        writeln!(out)?;
+    }
+
+    if !report.untracked.is_empty() {
+        writeln!(out, "Untracked files:")?;
+        if hints {
+            writeln!(
+                out,
+                "  (use \"git add <file>...\" to include in what will be committed)"
+            )?;
+        }
+        for path in &report.untracked {
+            writeln!(out, "\t{}", quote::c_style(path, false))?;
+        }
+        writeln!(out)?;
+    } else if untracked == UntrackedMode::No {
+        if hints {
+            writeln!(
+                out,
+                "Untracked files not listed (use -u option to show untracked files)"
+            )?;
+        } else {
+            writeln!(out, "Untracked files not listed")?;
+        }
+    }
+
+    if report.has_staged() {
+        Ok(())
+    } else if report.has_unstaged() || !report.unmerged.is_empty() {
+        match hints {
+            true => writeln!(
+                out,
+                "no changes added to commit (use \"git add\" and/or \"git commit -a\")"
+            ),
+            false => writeln!(out, "no changes added to commit"),
+        }
+    } else if !report.untracked.is_empty() {
+        match hints {
+            true => writeln!(
+                out,
+                "nothing added to commit but untracked files present (use \"git add\" to track)"
+            ),
+            false => writeln!(out, "nothing added to commit but untracked files present"),
+        }
+    } else if info.head.is_none() {
+        match hints {
+            true => writeln!(
+                out,
+                "nothing to commit (create/copy files and use \"git add\" to track)"
+            ),
+            false => writeln!(out, "nothing to commit"),
+        }
+    } else if untracked == UntrackedMode::No {
+        match hints {
+            true => writeln!(out, "nothing to commit (use -u to show untracked files)"),
+            false => writeln!(out, "nothing to commit"),
+        }
+    } else {
+        writeln!(out, "nothing to commit, working tree clean")
+    }
+}
+
+/// Says which cherry-pick or revert is in progress and how to go on from there
+fn print_replay(
+    out: &mut impl Write,
+    action: Action,
+    commit: Option<[u8; 20]>,
+    report: &StatusReport,
+    hints: bool,
+) -> std::io::Result<()> {
+    let (command, doing, name) = match action {
+        Action::Pick => ("cherry-pick", "cherry-picking", "Cherry-pick"),
+        Action::Revert => ("revert", "reverting", "Revert"),
+    };
+    match commit {
+        Some(commit) => writeln!(
+            out,
+            "You are currently {doing} commit {}.",
+            &hex::encode(commit)[..7]
+        )?,
+        None => writeln!(out, "{name} currently in progress.")?,
+    }
+    if !hints {
+        return writeln!(out);
+    }
+    if !report.unmerged.is_empty() {
+        writeln!(
+            out,
+            "  (fix conflicts and run \"git {command} --continue\")"
+        )?;
+    } else if commit.is_none() {
+        writeln!(out, "  (run \"git {command} --continue\" to continue)")?;
+    } else {
+        writeln!(
+            out,
+            "  (all conflicts fixed: run \"git {command} --continue\")"
+        )?;
+    }
+    writeln!(out, "  (use \"git {command} --skip\" to skip this patch)")?;
+    writeln!(
+        out,
+        "  (use \"git {command} --abort\" to cancel the {command} operation)"
+    )?;
+    writeln!(out)
+}
+
+/// Says whether the merge in progress still has conflicts to resolve
+fn print_merge(out: &mut impl Write, report: &StatusReport, hints: bool) -> std::io::Result<()> {
+    if !report.unmerged.is_empty() {
+        writeln!(out, "You have unmerged paths.")?;
+        if hints {
+            writeln!(out, "  (fix conflicts and run \"git commit\")")?;
+            writeln!(out, "  (use \"git merge --abort\" to abort the merge)")?;
+        }
+    } else {
+        writeln!(out, "All conflicts fixed but you are still merging.")?;
+        if hints {
+            writeln!(out, "  (use \"git commit\" to conclude merge)")?;
+        }
+    }
+    writeln!(out)
+}
+
+/// Shows the last commands a rebase carried out and the next ones it has to do
+fn print_rebase_progress(
+    out: &mut impl Write,
+    rebase: &rebase::Progress,
+    hints: bool,
+) -> std::io::Result<()> {
+    const SHOWN: usize = 2;
+    match rebase.done.len() {
+        0 => writeln!(out, "No commands done.")?,
+        1 => writeln!(out, "Last command done (1 command done):")?,
+        n => writeln!(out, "Last commands done ({n} commands done):")?,
+    }
+    for line in &rebase.done[rebase.done.len().saturating_sub(SHOWN)..] {
+        writeln!(out, "   {line}")?;
+    }
+    if rebase.done.len() > SHOWN && hints {
+        writeln!(out, "  (see more in file .git/rebase-merge/done)")?;
+    }
+    match rebase.todo.len() {
+        0 => writeln!(out, "No commands remaining.")?,
+        1 => writeln!(out, "Next command to do (1 remaining command):")?,
+        n => writeln!(out, "Next commands to do ({n} remaining commands):")?,
+    }
+    for line in rebase.todo.iter().take(SHOWN) {
+        writeln!(out, "   {line}")?;
+    }
+    if !rebase.todo.is_empty() && hints {
+        writeln!(out, "  (use \"git rebase --edit-todo\" to view and edit)")?;
+    }
+    Ok(())
+}
+
+/// Says what the rebase in progress stopped for and how to go on from there
+fn print_rebase(
+    out: &mut impl Write,
+    rebase: &rebase::Progress,
+    report: &StatusReport,
+    hints: bool,
+) -> std::io::Result<()> {
+    print_rebase_progress(out, rebase, hints)?;
+    let rebasing = match &rebase.branch {
+        Some(branch) => format!("rebasing branch '{branch}' on '{}'", rebase.onto),
+        None => String::from("rebasing"),
+    };
+    if !report.unmerged.is_empty() {
+        writeln!(out, "You are currently {rebasing}.")?;
+        if hints {
+            writeln!(
+                out,
+                "  (fix conflicts and then run \"git rebase --continue\")"
+            )?;
+            writeln!(out, "  (use \"git rebase --skip\" to skip this patch)")?;
+            writeln!(
+                out,
+                "  (use \"git rebase --abort\" to check out the original branch)"
+            )?;
+        }
+    } else if Path::new(".git/MERGE_MSG").exists() {
+        writeln!(out, "You are currently {rebasing}.")?;
+        if hints {
+            writeln!(
+                out,
+                "  (all conflicts fixed: run \"git rebase --continue\")"
+            )?;
+        }
+    } else {
+        match &rebase.branch {
+            Some(branch) => writeln!(
+                out,
+                "You are currently editing a commit while rebasing branch '{branch}' on '{}'.",
+                rebase.onto
+            )?,
+            None => writeln!(out, "You are currently editing a commit during a rebase.")?,
+        }
+        if hints {
+            writeln!(
+                out,
+                "  (use \"git commit --amend\" to amend the current commit)"
+            )?;
+            writeln!(
+                out,
+                "  (use \"git rebase --continue\" once you are satisfied with your changes)"
+            )?;
+        }
+    }
+    writeln!(out)
+}
+
+fn describe(kind: status::ChangeKind) -> &'static str {
+    match kind {
+        status::ChangeKind::Added => "new file:",
+        status::ChangeKind::Deleted => "deleted:",
+        status::ChangeKind::Modified => "modified:",
+        status::ChangeKind::TypeChanged => "typechange:",
+        status::ChangeKind::Renamed => "renamed:",
+    }
+}
+
+/// Formats a path for the short formats: quoted unless entries are NUL-terminated
+fn short_path(path: &str, null_terminated: bool) -> String {
+    if null_terminated {
+        path.to_string()
+    } else {
+        quote::c_style(path, true)
+    }
+}
+
+fn print_short(
+    out: &mut impl Write,
+    info: &BranchInfo,
+    report: &StatusReport,
+    branch: bool,
+    null_terminated: bool,
+) -> std::io::Result<()> {
+    let end = if null_terminated { '\0' } else { '\n' };
+    if branch {
+        write!(out, "## ")?;
+        match (&info.name, info.head) {
+            (Some(name), None) => write!(out, "No commits yet on {name}")?,
+            (Some(name), Some(_)) => write!(out, "{name}")?,
+            (None, _) => write!(out, "HEAD (no branch)")?,
+        }
+        if let Some(upstream) = &info.upstream {
+            write!(out, "...{}", upstream.name)?;
+            match upstream.ahead_behind {
+                None => write!(out, " [gone]")?,
+                Some((0, 0)) => {}
+                Some((ahead, 0)) => write!(out, " [ahead {ahead}]")?,
+                Some((0, behind)) => write!(out, " [behind {behind}]")?,
+                Some((ahead, behind)) => write!(out, " [ahead {ahead}, behind {behind}]")?,
+            }
+        }
+        write!(out, "{end}")?;
+    }
+
+    let mut lines: Vec<(&str, String, Option<&str>)> = Vec::new();
+    for change in &report.changes {
+        let x = change.staged.map_or(' ', |kind| kind.letter());
+        let y = change.unstaged.map_or(' ', |kind| kind.letter());
+        let source = change.source.as_ref().map(|(source, _)| source.as_str());
+        lines.push((&change.path, format!("{x}{y}"), source));
+    }
+    for unmerged in &report.unmerged {
+        lines.push((&unmerged.path, unmerged.code().to_string(), None));
+    }
+    lines.sort();
+    for (path, code, source) in lines {
+        let path = short_path(path, null_terminated);
+        match source {
+            // NUL-terminated entries put the source after the path instead of before an arrow
+            Some(source) if null_terminated => write!(out, "{code} {path}{end}{source}{end}")?,
+            Some(source) => write!(
+                out,
+                "{code} {} -> {path}{end}",
+                short_path(source, null_terminated)
+            )?,
+            None => write!(out, "{code} {path}{end}")?,
+        }
+    }
+    for path in &report.untracked {
+        write!(out, "?? {}{end}", short_path(path, null_terminated))?;
+    }
+    Ok(())
+}
+
+/// Porcelain v2 quotes like the short format, except that spaces are left alone
+fn v2_path(path: &str, null_terminated: bool) -> String {
+    if null_terminated {
+        path.to_string()
+    } else {
+        quote::c_style(path, false)
+    }
+}
+
+const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+fn mode_field(mode: Option<u32>) -> String {
+    format!("{:06o}", mode.unwrap_or(0))
+}
+
+fn hash_field(blob: Option<(u32, [u8; 20])>) -> String {
+    blob.map_or_else(|| NULL_HASH.to_string(), |(_, hash)| hex::encode(hash))
+}
+
+fn print_v2(
+    out: &mut impl Write,
+    info: &BranchInfo,
+    report: &StatusReport,
+    branch: bool,
+    null_terminated: bool,
+) -> std::io::Result<()> {
+    let end = if null_terminated { '\0' } else { '\n' };
+    if branch {
+        match info.head {
+            Some(head) => write!(out, "# branch.oid {}{end}", hex::encode(head))?,
+            None => write!(out, "# branch.oid (initial){end}")?,
+        }
+        write!(
+            out,
+            "# branch.head {}{end}",
+            info.name.as_deref().unwrap_or("(detached)")
+        )?;
+        if let Some(upstream) = &info.upstream {
+            write!(out, "# branch.upstream {}{end}", upstream.name)?;
+            if let Some((ahead, behind)) = upstream.ahead_behind {
+                write!(out, "# branch.ab +{ahead} -{behind}{end}")?;
+            }
+        }
+    }
+
+    // Ordinary changes and renames come first, then unmerged paths, like git
+    for change in &report.changes {
+        let x = change.staged.map_or('.', |kind| kind.letter());
+        let y = change.unstaged.map_or('.', |kind| kind.letter());
+        let fields = format!(
+            "{x}{y} N... {} {} {} {} {}",
+            mode_field(change.head.map(|(mode, _)| mode)),
+            mode_field(change.index.map(|(mode, _)| mode)),
+            mode_field(change.worktree),
+            hash_field(change.head),
+            hash_field(change.index),
+        );
+        let path = v2_path(&change.path, null_terminated);
+        match &change.source {
+            Some((source, similarity)) => {
+                let separator = if null_terminated { '\0' } else { '\t' };
+                write!(
+                    out,
+                    "2 {fields} R{similarity} {path}{separator}{}{end}",
+                    v2_path(source, null_terminated)
+                )?
+            }
+            None => write!(out, "1 {fields} {path}{end}")?,
+        }
+    }
+    for unmerged in &report.unmerged {
+        let [base, ours, theirs] = unmerged.stages;
+        write!(
+            out,
+            "u {} N... {} {} {} {} {} {} {} {}{end}",
+            unmerged.code(),
+            mode_field(base.map(|(mode, _)| mode)),
+            mode_field(ours.map(|(mode, _)| mode)),
+            mode_field(theirs.map(|(mode, _)| mode)),
+            mode_field(unmerged.worktree),
+            hash_field(base),
+            hash_field(ours),
+            hash_field(theirs),
+            v2_path(&unmerged.path, null_terminated),
+        )?;
+    }
+    for path in &report.untracked {
+        write!(out, "? {}{end}", v2_path(path, null_terminated))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use crate::commands::checkout::{switch_to, Switch};
+
+#[derive(clap::Args, Debug)]
+pub struct SwitchArgs {
+    /// Create a new branch at the start point and switch to it
+    #[clap(short = 'c', long = "create")]
+    create: Option<String>,
+
+    /// Create or reset a branch at the start point and switch to it
+    #[clap(short = 'C', long = "force-create")]
+    force_create: Option<String>,
+
+    /// Switch to a commit instead of a branch, detaching HEAD
+    #[clap(short = 'd', long = "detach")]
+    detach: bool,
+
+    /// Switch to a new branch with no commits, removing all tracked files
+    #[clap(long = "orphan")]
+    orphan: Option<String>,
+
+    /// Throw away local changes
+    #[clap(short = 'f', long = "discard-changes", visible_alias = "force")]
+    force: bool,
+
+    /// Suppress feedback messages
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Branch to switch to, or the start point of a new branch
+    target: Option<String>,
+}
+
+pub(crate) fn invoke(args: SwitchArgs) -> anyhow::Result<()> {
+    let new_branch = match (args.create, args.force_create) {
+        (Some(_), Some(_)) => anyhow::bail!("-c and -C are mutually exclusive"),
+        (Some(name), None) => Some((name, false)),
+        (None, Some(name)) => Some((name, true)),
+        (None, None) => None,
+    };
+    if args.orphan.is_some() {
+        anyhow::ensure!(
+            new_branch.is_none() && !args.detach,
+            "--orphan can't be combined with -c, -C or --detach"
+        );
+        anyhow::ensure!(
+            args.target.is_none(),
+            "'--orphan' cannot take <start-point>"
+        );
+    } else {
+        anyhow::ensure!(
+            args.target.is_some() || new_branch.is_some() || args.detach,
+            "missing branch or commit argument"
+        );
+    }
+    anyhow::ensure!(
+        !std::path::Path::new(".git/MERGE_HEAD").exists(),
+        "cannot switch branch while merging\nConsider \"git merge --quit\" or \"git worktree add\"."
+    );
+    switch_to(Switch {
+        target: args.target,
+        new_branch,
+        orphan: args.orphan,
+        orphan_empties: true,
+        detach: args.detach,
+        require_branch: true,
+        force: args.force,
+        quiet: args.quiet,
+    })
+}
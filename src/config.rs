@@ -9,41 +9,193 @@ pub(crate) struct Config {
 }
 impl Config {
     pub(crate) fn get(&self, query: &str) -> Option<&str> {
-        let mut queries = query.split('.');
-        match queries.next() {
-            None => None,
-            Some(query) => match queries.next() {
-                None => None,
-                Some(query2) => self
-                    .sections
-                    .get(query)
-                    .and_then(|x| x.get(query2))
-                    .map(|x| x.as_str()),
-            },
+        // Section and key names are case-insensitive, and `branch.main.remote` lives in the
+        // `[branch "main"]` section
+        let (section, rest) = query.split_once('.')?;
+        let (section, key) = match rest.rsplit_once('.') {
+            Some((subsection, key)) => {
+                (format!("{} \"{subsection}\"", section.to_lowercase()), key)
+            }
+            None => (section.to_lowercase(), rest),
+        };
+        self.sections
+            .get(&section)?
+            .get(&key.to_lowercase())
+            .map(|x| x.as_str())
+    }
+
+    /// Reads a boolean the way git spells them, `None` when unset or not a boolean
+    pub(crate) fn get_bool(&self, query: &str) -> Option<bool> {
+        match self.get(query)?.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(true),
+            "false" | "no" | "off" | "0" | "" => Some(false),
+            _ => None,
         }
     }
 }
 
 pub(crate) fn parse_config_from_file(f: File) -> Config {
+    parse_config(BufReader::new(f))
+}
+
+fn parse_config(reader: impl BufRead) -> Config {
     let mut config = Config {
         sections: HashMap::new(),
     };
-    let reader = BufReader::new(f);
+    let mut lines = reader.lines().map_while(Result::ok);
     let mut current_section = String::new();
-    for line in reader.lines() {
-        let Ok(line) = line else {
-            break;
-        };
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
         if line.starts_with('[') && line.ends_with(']') {
-            current_section = line[1..line.len() - 1].to_string();
-            config
-                .sections
-                .insert(current_section.clone(), HashMap::new());
-        } else if let Some((key, value)) = line.split_once('=') {
-            if let Some(section) = config.sections.get_mut(&current_section) {
-                section.insert(key.trim().to_string(), value.trim().to_string());
+            // Section and key names are case-insensitive, subsection names are not
+            let section = &line[1..line.len() - 1];
+            current_section = match section.split_once(' ') {
+                Some((name, subsection)) => format!("{} {subsection}", name.to_lowercase()),
+                None => section.to_lowercase(),
+            };
+            config.sections.entry(current_section.clone()).or_default();
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, raw)) => {
+                let mut value = Value::default();
+                let mut more = value.parse(raw);
+                while more {
+                    let Some(next) = lines.next() else {
+                        break;
+                    };
+                    more = value.parse(&next);
+                }
+                (key.trim(), value.text)
             }
+            // A key on its own is a boolean set to true
+            None => {
+                let key = line.split(['#', ';']).next().unwrap_or_default().trim();
+                (key, "true".to_string())
+            }
+        };
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            continue;
+        }
+        if let Some(section) = config.sections.get_mut(&current_section) {
+            section.insert(key.to_lowercase(), value);
         }
     }
     config
 }
+
+/// A value being read, which a backslash at the end of a line continues on the next
+#[derive(Default)]
+struct Value {
+    text: String,
+    /// Whitespace outside quotes, kept only if something follows it
+    spaces: usize,
+    quoted: bool,
+}
+
+impl Value {
+    /// Reads one line of the value: quotes removed, escapes resolved and comments outside
+    /// quotes dropped. Returns whether the value goes on to the next line.
+    fn parse(&mut self, line: &str) -> bool {
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if !self.quoted && c.is_whitespace() {
+                if !self.text.is_empty() {
+                    self.spaces += 1;
+                }
+                continue;
+            }
+            if !self.quoted && (c == '#' || c == ';') {
+                break;
+            }
+            self.text.extend(std::iter::repeat_n(' ', self.spaces));
+            self.spaces = 0;
+            match c {
+                '"' => self.quoted = !self.quoted,
+                '\\' => match chars.next() {
+                    None => return true,
+                    Some('n') => self.text.push('\n'),
+                    Some('t') => self.text.push('\t'),
+                    Some('b') => self.text.push('\u{8}'),
+                    Some(escaped) => self.text.push(escaped),
+                },
+                c => self.text.push(c),
+            }
+        }
+        false
+    }
+}
+
+/// Reads `.git/config`, treating a missing file as an empty config
+pub(crate) fn read_repo_config() -> Config {
+    match File::open(".git/config") {
+        Ok(f) => parse_config_from_file(f),
+        Err(_) => Config {
+            sections: HashMap::new(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(lines: &[&str]) -> String {
+        let mut value = Value::default();
+        for (i, line) in lines.iter().enumerate() {
+            let more = value.parse(line);
+            assert_eq!(more, i + 1 < lines.len(), "continuation after {line:?}");
+        }
+        value.text
+    }
+
+    #[test]
+    fn parses_plain_values() {
+        assert_eq!(value(&[" plain "]), "plain");
+        assert_eq!(value(&["two  words\t"]), "two  words");
+        assert_eq!(value(&[""]), "");
+    }
+
+    #[test]
+    fn drops_comments_outside_quotes() {
+        assert_eq!(value(&["value # comment"]), "value");
+        assert_eq!(value(&["value; comment"]), "value");
+        assert_eq!(value(&["\"a # b\" ; c"]), "a # b");
+    }
+
+    #[test]
+    fn keeps_whitespace_inside_quotes() {
+        assert_eq!(value(&["\"  padded  \""]), "  padded  ");
+        assert_eq!(value(&["a\" b \"c"]), "a b c");
+    }
+
+    #[test]
+    fn resolves_escapes() {
+        assert_eq!(value(&[r#"a\nb\tc\bd"#]), "a\nb\tc\u{8}d");
+        assert_eq!(value(&[r#"\"quoted\" \\ back"#]), "\"quoted\" \\ back");
+    }
+
+    #[test]
+    fn continues_after_a_trailing_backslash() {
+        // Whitespace around the line break is kept, as it is inside the value
+        assert_eq!(value(&["first \\", "  second"]), "first   second");
+        assert_eq!(value(&["\"in quotes \\", "goes on\""]), "in quotes goes on");
+    }
+
+    #[test]
+    fn reads_sections_keys_and_bare_booleans() {
+        let config = parse_config(
+            "[Core]\n\tBare = false\n\tfilemode\n[branch \"Main\"]\n\tremote = origin # where\n\tmerge = \\\n refs/heads/main\n; [ignored]\n[alias]\n\tst = \"status -s\"\n"
+                .as_bytes(),
+        );
+        assert_eq!(config.get("core.bare"), Some("false"));
+        assert_eq!(config.get_bool("core.fileMode"), Some(true));
+        assert_eq!(config.get("branch.Main.remote"), Some("origin"));
+        assert_eq!(config.get("branch.Main.merge"), Some("refs/heads/main"));
+        assert_eq!(config.get("branch.main.remote"), None);
+        assert_eq!(config.get("alias.st"), Some("status -s"));
+    }
+}
@@ -0,0 +1,281 @@
+//! Parsing and display of commit dates in git's formats.
+
+use anyhow::Context;
+use chrono::{Datelike, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+/// How dates are shown, as chosen with `--date`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum DateMode {
+    /// `Tue Nov 14 23:13:20 2023 +0100`
+    #[default]
+    Default,
+    /// `2023-11-14 23:13:20 +0100`
+    Iso,
+    /// `2023-11-14T23:13:20+01:00`
+    IsoStrict,
+    /// `Tue, 14 Nov 2023 23:13:20 +0100`
+    Rfc,
+    /// `2023-11-14`
+    Short,
+    /// `1700000000 +0100`
+    Raw,
+    /// `1700000000`
+    Unix,
+    /// `2 weeks ago`
+    Relative,
+}
+
+impl DateMode {
+    pub(crate) fn parse(name: &str) -> anyhow::Result<DateMode> {
+        Ok(match name {
+            "default" => DateMode::Default,
+            "iso" | "iso8601" => DateMode::Iso,
+            "iso-strict" | "iso8601-strict" => DateMode::IsoStrict,
+            "rfc" | "rfc2822" => DateMode::Rfc,
+            "short" => DateMode::Short,
+            "raw" => DateMode::Raw,
+            "unix" => DateMode::Unix,
+            "relative" => DateMode::Relative,
+            other => anyhow::bail!("unknown date format {other}"),
+        })
+    }
+}
+
+/// Formats a timestamp in the timezone it was recorded in (`tz` in minutes east of UTC)
+pub(crate) fn format(time: i64, tz: i32, mode: DateMode) -> String {
+    let offset = FixedOffset::east_opt(tz * 60).unwrap_or(FixedOffset::east_opt(0).unwrap());
+    let Some(date) = offset.timestamp_opt(time, 0).single() else {
+        return time.to_string();
+    };
+    let sign = if tz < 0 { '-' } else { '+' };
+    let (hours, minutes) = (tz.abs() / 60, tz.abs() % 60);
+    match mode {
+        DateMode::Default => format!(
+            "{} {sign}{hours:02}{minutes:02}",
+            date.format("%a %b %-d %H:%M:%S %Y")
+        ),
+        DateMode::Iso => format!(
+            "{} {sign}{hours:02}{minutes:02}",
+            date.format("%Y-%m-%d %H:%M:%S")
+        ),
+        DateMode::IsoStrict => format!(
+            "{}{sign}{hours:02}:{minutes:02}",
+            date.format("%Y-%m-%dT%H:%M:%S")
+        ),
+        DateMode::Rfc => format!(
+            "{} {sign}{hours:02}{minutes:02}",
+            date.format("%a, %-d %b %Y %H:%M:%S")
+        ),
+        DateMode::Short => date.format("%Y-%m-%d").to_string(),
+        DateMode::Raw => format!("{time} {sign}{hours:02}{minutes:02}"),
+        DateMode::Unix => time.to_string(),
+        DateMode::Relative => relative(time, Local::now().timestamp()),
+    }
+}
+
+fn plural(n: i64, unit: &str) -> String {
+    if n == 1 {
+        format!("{n} {unit}")
+    } else {
+        format!("{n} {unit}s")
+    }
+}
+
+/// Describes how long before `now` a timestamp is, rounding the way git does
+fn relative(time: i64, now: i64) -> String {
+    if time > now {
+        return String::from("in the future");
+    }
+    let mut diff = now - time;
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "second"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "minute"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 36 {
+        return format!("{} ago", plural(diff, "hour"));
+    }
+    diff = (diff + 12) / 24;
+    if diff < 14 {
+        return format!("{} ago", plural(diff, "day"));
+    }
+    if diff < 70 {
+        return format!("{} ago", plural((diff + 3) / 7, "week"));
+    }
+    if diff < 365 {
+        return format!("{} ago", plural((diff + 15) / 30, "month"));
+    }
+    if diff < 1825 {
+        let total_months = (diff * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        if months > 0 {
+            return format!("{}, {} ago", plural(years, "year"), plural(months, "month"));
+        }
+        return format!("{} ago", plural(years, "year"));
+    }
+    format!("{} ago", plural((diff + 183) / 365, "year"))
+}
+
+/// Parses a date as accepted in `GIT_*_DATE`: git's internal `<timestamp> <tz>` format
+/// (optionally prefixed with `@`), RFC 2822, as loosely as mail headers need, or ISO 8601
+pub(crate) fn parse_date(date: &str) -> anyhow::Result<(i64, i32)> {
+    let date = date.trim();
+    let internal = date.strip_prefix('@').unwrap_or(date);
+    let (time, tz) = internal.split_once(' ').unwrap_or((internal, "+0000"));
+    if let (Ok(time), Some(tz)) = (time.parse::<i64>(), parse_tz(tz)) {
+        return Ok((time, tz));
+    }
+    let parsed = chrono::DateTime::parse_from_rfc2822(date)
+        .or_else(|_| chrono::DateTime::parse_from_rfc3339(date))
+        .or_else(|_| chrono::DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z"));
+    if let Ok(parsed) = parsed {
+        return Ok((parsed.timestamp(), parsed.offset().local_minus_utc() / 60));
+    }
+    parse_loose(date).with_context(|| format!("Invalid date format: {date}"))
+}
+
+/// Parses a date the way git reads `Date:` headers, picking out the day, month, year, time
+/// and timezone in whatever order they come and ignoring the weekday, comments such as
+/// `(EST)` and words it doesn't know
+fn parse_loose(date: &str) -> Option<(i64, i32)> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let mut text = String::new();
+    let mut depth = 0;
+    for c in date.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = (depth - 1).max(0),
+            c if depth == 0 => text.push(c),
+            _ => {}
+        }
+    }
+    let (mut day, mut month, mut year) = (None, None, None);
+    let (mut time, mut tz) = (None, 0);
+    for token in text.split(|c: char| c.is_whitespace() || c == ',') {
+        if let Some(offset) = parse_tz(token) {
+            tz = offset;
+        } else if token.contains(':') {
+            let mut parts = token.split(':').map(|part| part.parse::<u32>().ok());
+            let (hour, minute) = (parts.next()??, parts.next()??);
+            let second = parts.next().flatten().unwrap_or(0);
+            time = Some(chrono::NaiveTime::from_hms_opt(hour, minute, second)?);
+        } else if let Ok(n) = token.parse::<i32>() {
+            match (day, token.len()) {
+                (None, ..=2) => day = Some(n as u32),
+                _ => year = Some(n),
+            }
+        } else if let Some(prefix) = token.get(..3) {
+            if let Some(i) = MONTHS
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(prefix))
+            {
+                month = Some(i as u32 + 1);
+            }
+        }
+    }
+    // Two-digit years are taken the way mailers of the last century meant them
+    let year = match year? {
+        year @ 0..70 => year + 2000,
+        year @ 70..100 => year + 1900,
+        year => year,
+    };
+    let naive = NaiveDate::from_ymd_opt(year, month?, day?)?.and_time(time.unwrap_or_default());
+    Some((naive.and_utc().timestamp() - i64::from(tz) * 60, tz))
+}
+
+/// Parses a `+hhmm` timezone into minutes
+pub(crate) fn parse_tz(tz: &str) -> Option<i32> {
+    let sign = match tz.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let digits = tz.get(1..).filter(|digits| digits.len() == 4)?;
+    let value: i32 = digits.parse().ok()?;
+    Some(sign * (value / 100 * 60 + value % 100))
+}
+
+/// Parses the dates `--since` and `--until` take: everything [`parse_date`] accepts, plain
+/// `YYYY-MM-DD[ HH:MM[:SS]]` in local time, `now`, `yesterday` and relative dates such as
+/// `2 weeks ago` or `1.year.3.months.ago`. As in git, a date without a time of day means that
+/// date at the current time.
+pub(crate) fn approxidate(text: &str) -> anyhow::Result<i64> {
+    let text = text.trim();
+    if let Ok((time, _)) = parse_date(text) {
+        return Ok(time);
+    }
+    let now = Local::now();
+    let local = |naive: NaiveDateTime| {
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|date| date.timestamp())
+    };
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"] {
+        if let Some(time) = NaiveDateTime::parse_from_str(text, format)
+            .ok()
+            .and_then(local)
+        {
+            return Ok(time);
+        }
+    }
+    for format in ["%Y-%m-%d", "%Y.%m.%d", "%m/%d/%Y"] {
+        if let Some(time) = NaiveDate::parse_from_str(text, format)
+            .ok()
+            .and_then(|date| local(date.and_time(now.time())))
+        {
+            return Ok(time);
+        }
+    }
+
+    let words: Vec<&str> = text
+        .split(|c: char| c.is_whitespace() || c == '.')
+        .filter(|word| !word.is_empty())
+        .collect();
+    let mut date = now.naive_local();
+    let mut understood = false;
+    let mut i = 0;
+    while i < words.len() {
+        let word = words[i].to_lowercase();
+        match word.as_str() {
+            "now" | "ago" => {}
+            "yesterday" => date -= Duration::days(1),
+            "today" => {}
+            _ => {
+                let n: i64 = word
+                    .parse()
+                    .ok()
+                    .with_context(|| format!("Invalid date: {text}"))?;
+                let unit = words
+                    .get(i + 1)
+                    .with_context(|| format!("Invalid date: {text}"))?
+                    .to_lowercase();
+                let unit = unit.strip_suffix('s').unwrap_or(&unit);
+                date = match unit {
+                    "second" | "sec" => date - Duration::seconds(n),
+                    "minute" | "min" => date - Duration::minutes(n),
+                    "hour" => date - Duration::hours(n),
+                    "day" => date - Duration::days(n),
+                    "week" => date - Duration::weeks(n),
+                    "month" => date
+                        .checked_sub_months(chrono::Months::new(n as u32))
+                        .context("Date out of range")?,
+                    "year" => date
+                        .with_year(date.year() - n as i32)
+                        .context("Date out of range")?,
+                    _ => anyhow::bail!("Invalid date: {text}"),
+                };
+                i += 1;
+            }
+        }
+        understood = true;
+        i += 1;
+    }
+    anyhow::ensure!(understood, "Invalid date: {text}");
+    local(date).with_context(|| format!("Invalid date: {text}"))
+}
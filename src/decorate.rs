@@ -0,0 +1,124 @@
+//! Ref names shown next to the commits they point at, as `log --decorate` and `%d` show them.
+
+use std::collections::HashMap;
+
+use crate::{
+    object::{commit::Tag, read::read_object, ObjectKind},
+    refs::{self, Head},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Head,
+    Branch,
+    Tag,
+    Other,
+}
+
+struct Decoration {
+    name: String,
+    kind: Kind,
+}
+
+/// The decorations of every object refs point at, in the order git lists them
+pub(crate) struct Decorations {
+    by_object: HashMap<[u8; 20], Vec<Decoration>>,
+    /// The branch HEAD points at, named as the decorations are
+    current_branch: Option<String>,
+}
+
+/// Which refs decorate: branches, remote-tracking branches, tags, the stash and HEAD
+fn decorates(name: &str) -> bool {
+    ["refs/heads/", "refs/remotes/", "refs/tags/"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+        || name == "refs/stash"
+}
+
+/// Strips the prefix of branches, remote-tracking branches and tags
+fn short_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+impl Decorations {
+    /// Loads the decorations from the refs, with full ref names or shortened ones
+    pub(crate) fn load(full: bool) -> anyhow::Result<Decorations> {
+        let mut decorations = Decorations {
+            by_object: HashMap::new(),
+            current_branch: None,
+        };
+        for (name, hash) in refs::list("refs/")? {
+            if !decorates(&name) {
+                continue;
+            }
+            let kind = if name.starts_with("refs/heads/") {
+                Kind::Branch
+            } else if name.starts_with("refs/tags/") {
+                Kind::Tag
+            } else {
+                Kind::Other
+            };
+            let name = if full { &name } else { short_name(&name) };
+            decorations.add(hash, name, kind);
+            // Annotated tags also decorate what they point at
+            let mut hash = hash;
+            while let Ok((ObjectKind::Tag, content)) = read_object(&hex::encode(hash)) {
+                hash = Tag::parse(&content)?.object;
+                decorations.add(hash, name, Kind::Tag);
+            }
+        }
+        let head = refs::read_head()?;
+        if let Some(hash) = refs::head_commit()? {
+            decorations.add(hash, "HEAD", Kind::Head);
+        }
+        if let Head::Symbolic(branch) = head {
+            let branch = if full { &branch } else { short_name(&branch) };
+            decorations.current_branch = Some(branch.to_string());
+        }
+        Ok(decorations)
+    }
+
+    /// Later refs go first, so HEAD, added last, leads the list
+    fn add(&mut self, hash: [u8; 20], name: &str, kind: Kind) {
+        let list = self.by_object.entry(hash).or_default();
+        list.insert(
+            0,
+            Decoration {
+                name: name.to_string(),
+                kind,
+            },
+        );
+    }
+
+    /// The objects that have decorations
+    pub(crate) fn objects(&self) -> impl Iterator<Item = &[u8; 20]> {
+        self.by_object.keys()
+    }
+
+    /// The decorations of an object, such as `HEAD -> main` and `tag: v1.0`
+    pub(crate) fn of(&self, hash: &[u8; 20]) -> Vec<String> {
+        let Some(list) = self.by_object.get(hash) else {
+            return Vec::new();
+        };
+        // HEAD is shown pointing at its branch when that branch is here too
+        let current = list.iter().any(|d| d.kind == Kind::Head).then(|| {
+            list.iter()
+                .find(|d| d.kind == Kind::Branch && Some(&d.name) == self.current_branch.as_ref())
+        });
+        let current = current.flatten();
+        list.iter()
+            .filter(|d| !current.is_some_and(|current| std::ptr::eq(*d, current)))
+            .map(|d| match d.kind {
+                Kind::Head => match current {
+                    Some(current) => format!("HEAD -> {}", current.name),
+                    None => d.name.clone(),
+                },
+                Kind::Tag => format!("tag: {}", d.name),
+                _ => d.name.clone(),
+            })
+            .collect()
+    }
+}
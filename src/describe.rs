@@ -0,0 +1,402 @@
+//! Describing a commit by the nearest tag it descends from, as `git describe` does:
+//! `v1.2-14-g2414721` is 14 commits on top of the tag `v1.2`, at commit `2414721`.
+//!
+//! The history is walked newest first from the commit. The first few tagged commits met become
+//! candidates, and each one's depth counts the commits walked that it doesn't reach. Once every
+//! remaining path is covered by the best candidates the walk stops, and the winner's depth is
+//! finished by walking on until everything left is reachable from it.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    index::Index,
+    object::commit::{Commit, Tag},
+    pathspec::wildmatch,
+    refs, revision,
+    status::{self, UntrackedMode},
+};
+
+/// The most candidates that can be tracked at once, one flag bit each
+pub(crate) const MAX_CANDIDATES: usize = 30;
+
+const SEEN: u32 = 1;
+
+#[derive(Debug)]
+pub(crate) struct DescribeOptions {
+    /// Use any ref, not just tags
+    pub(crate) all: bool,
+    /// Use lightweight tags too, not just annotated ones
+    pub(crate) tags: bool,
+    /// Always add the depth and abbreviated commit, even for an exact match
+    pub(crate) long: bool,
+    /// Hex digits of the commit to show, 0 for only the tag
+    pub(crate) abbrev: usize,
+    /// How many tagged commits to consider, 0 for only exact matches
+    pub(crate) candidates: usize,
+    /// Only follow first parents when looking for tags
+    pub(crate) first_parent: bool,
+    /// Fall back to the abbreviated commit when no tag can describe it
+    pub(crate) always: bool,
+    /// Only use tags matching one of these patterns
+    pub(crate) patterns: Vec<String>,
+    /// Don't use tags matching any of these patterns
+    pub(crate) excludes: Vec<String>,
+}
+
+impl Default for DescribeOptions {
+    fn default() -> DescribeOptions {
+        DescribeOptions {
+            all: false,
+            tags: false,
+            long: false,
+            abbrev: 7,
+            candidates: 10,
+            first_parent: false,
+            always: false,
+            patterns: Vec::new(),
+            excludes: Vec::new(),
+        }
+    }
+}
+
+/// The ref chosen to name a commit
+struct Name {
+    /// The tag name, or with `--all` the ref name without `refs/`
+    path: String,
+    /// 2 for annotated tags, 1 for lightweight ones and 0 for other refs
+    prio: u8,
+    /// What the ref points at
+    hash: [u8; 20],
+    /// The tag object of an annotated tag, once read
+    tag: Option<Tag>,
+    /// Whether the tag object calls itself something else than its ref, once checked
+    misnamed: Option<bool>,
+}
+
+/// A tagged commit met while walking
+struct Candidate {
+    /// The commit the name points at
+    commit: [u8; 20],
+    depth: usize,
+    flag: u32,
+    found_order: usize,
+}
+
+pub(crate) struct Describer {
+    opts: DescribeOptions,
+    /// Names by the commit (or other object) their ref peels to
+    names: HashMap<[u8; 20], Name>,
+    /// Commit date and parents of the commits seen so far
+    commits: HashMap<[u8; 20], (i64, Vec<[u8; 20]>)>,
+}
+
+impl Describer {
+    /// Collects the refs that may describe commits
+    pub(crate) fn new(opts: DescribeOptions) -> anyhow::Result<Describer> {
+        let mut describer = Describer {
+            opts,
+            names: HashMap::new(),
+            commits: HashMap::new(),
+        };
+        for (name, hash) in refs::list("refs/")? {
+            let tag_name = name.strip_prefix("refs/tags/");
+            if !describer.opts.all && tag_name.is_none() {
+                continue;
+            }
+            let matches = |patterns: &[String]| {
+                tag_name.is_some_and(|tag_name| {
+                    patterns
+                        .iter()
+                        .any(|pattern| wildmatch(pattern.as_bytes(), tag_name.as_bytes()))
+                })
+            };
+            if !describer.opts.excludes.is_empty()
+                && (tag_name.is_none() || matches(&describer.opts.excludes))
+            {
+                continue;
+            }
+            if !describer.opts.patterns.is_empty() && !matches(&describer.opts.patterns) {
+                continue;
+            }
+
+            let peeled = revision::peel(&hash, None)?;
+            let prio = if peeled != hash {
+                2
+            } else if tag_name.is_some() {
+                1
+            } else {
+                0
+            };
+            let path = match describer.opts.all {
+                true => &name["refs/".len()..],
+                false => tag_name.unwrap(),
+            };
+            describer.add_name(path, peeled, prio, hash);
+        }
+        anyhow::ensure!(
+            !describer.names.is_empty() || describer.opts.always,
+            "No names found, cannot describe anything."
+        );
+        Ok(describer)
+    }
+
+    /// Records `path` as naming `peeled`, unless a better name is known: refs with a higher
+    /// priority win, and of annotated tags the one tagged last
+    fn add_name(&mut self, path: &str, peeled: [u8; 20], prio: u8, hash: [u8; 20]) {
+        let mut tag = None;
+        let replace = match self.names.get_mut(&peeled) {
+            None => true,
+            Some(known) if known.prio < prio => true,
+            Some(known) if known.prio == 2 && prio == 2 => {
+                if known.tag.is_none() {
+                    known.tag = Tag::read(&known.hash).ok();
+                }
+                match (&known.tag, Tag::read(&hash)) {
+                    (None, _) => true,
+                    (Some(_), Err(_)) => false,
+                    (Some(known), Ok(new)) => {
+                        let newer = tag_date(known) < tag_date(&new);
+                        tag = Some(new);
+                        newer
+                    }
+                }
+            }
+            Some(_) => false,
+        };
+        if replace {
+            self.names.insert(
+                peeled,
+                Name {
+                    path: path.to_string(),
+                    prio,
+                    hash,
+                    tag,
+                    misnamed: None,
+                },
+            );
+        }
+    }
+
+    fn commit(&mut self, hash: &[u8; 20]) -> anyhow::Result<&(i64, Vec<[u8; 20]>)> {
+        if !self.commits.contains_key(hash) {
+            let commit = Commit::read(hash)?;
+            self.commits
+                .insert(*hash, (commit.committer.time, commit.parents));
+        }
+        Ok(&self.commits[hash])
+    }
+
+    /// Describes `commit` by the nearest usable name
+    pub(crate) fn describe(&mut self, commit: &[u8; 20]) -> anyhow::Result<String> {
+        let hex = hex::encode(commit);
+        if let Some(name) = self.names.get(commit) {
+            if self.opts.tags || self.opts.all || name.prio == 2 {
+                let mut described = self.append_name(commit)?;
+                let name = &self.names[commit];
+                if self.opts.long || name.misnamed == Some(true) {
+                    let target = name.tag.as_ref().map_or(name.hash, |tag| tag.object);
+                    described.push_str(&self.suffix(0, &target));
+                }
+                return Ok(described);
+            }
+        }
+        anyhow::ensure!(self.opts.candidates > 0, "no tag exactly matches '{hex}'");
+        self.commit(commit)?;
+
+        let mut flags: HashMap<[u8; 20], u32> = HashMap::from([(*commit, SEEN)]);
+        let mut list = VecDeque::from([*commit]);
+        let mut candidates: Vec<Candidate> = Vec::new();
+        let (mut seen_commits, mut annotated, mut unannotated) = (0, 0, 0);
+        let mut gave_up_on = None;
+        while let Some(current) = list.pop_front() {
+            seen_commits += 1;
+            if let Some(name) = self.names.get(&current) {
+                if !self.opts.tags && !self.opts.all && name.prio < 2 {
+                    unannotated += 1;
+                } else if candidates.len() < self.opts.candidates {
+                    let flag = 1 << (candidates.len() + 1);
+                    candidates.push(Candidate {
+                        commit: current,
+                        depth: seen_commits - 1,
+                        flag,
+                        found_order: candidates.len() + 1,
+                    });
+                    *flags.get_mut(&current).unwrap() |= flag;
+                    if name.prio == 2 {
+                        annotated += 1;
+                    }
+                } else {
+                    gave_up_on = Some(current);
+                    break;
+                }
+            }
+            let current_flags = flags[&current];
+            for candidate in &mut candidates {
+                if current_flags & candidate.flag == 0 {
+                    candidate.depth += 1;
+                }
+            }
+            // Stop if the last remaining path is already covered by the best candidates
+            if annotated > 0 && list.is_empty() {
+                let best_depth = candidates.iter().map(|c| c.depth).min().unwrap();
+                let best = candidates
+                    .iter()
+                    .filter(|c| c.depth == best_depth)
+                    .fold(0, |flags, c| flags | c.flag);
+                if current_flags & best == best {
+                    break;
+                }
+            }
+            let parents = self.commit(&current)?.1.clone();
+            for parent in parents {
+                self.queue(&mut list, &mut flags, parent, current_flags)?;
+                if self.opts.first_parent {
+                    break;
+                }
+            }
+        }
+
+        if candidates.is_empty() {
+            if self.opts.always {
+                return Ok(self.abbreviate(commit));
+            }
+            if unannotated > 0 {
+                anyhow::bail!(
+                    "No annotated tags can describe '{hex}'.\n\
+                     However, there were unannotated tags: try --tags."
+                );
+            }
+            anyhow::bail!("No tags can describe '{hex}'.\nTry --always, or create some tags.");
+        }
+        candidates.sort_by_key(|c| (c.depth, c.found_order));
+        let best = &mut candidates[0];
+        if let Some(gave_up_on) = gave_up_on {
+            let date = self.commit(&gave_up_on)?.0;
+            insert_by_date(&mut list, &self.commits, gave_up_on, date);
+        }
+        self.finish_depth(&mut list, &mut flags, best)?;
+
+        let (best_commit, depth) = (best.commit, best.depth);
+        let mut described = self.append_name(&best_commit)?;
+        if self.names[&best_commit].misnamed == Some(true) || self.opts.abbrev > 0 {
+            described.push_str(&self.suffix(depth, commit));
+        }
+        Ok(described)
+    }
+
+    /// Adds `parent` to the walk if it is new, passing on the flags of its child
+    fn queue(
+        &mut self,
+        list: &mut VecDeque<[u8; 20]>,
+        flags: &mut HashMap<[u8; 20], u32>,
+        parent: [u8; 20],
+        child_flags: u32,
+    ) -> anyhow::Result<()> {
+        let date = self.commit(&parent)?.0;
+        let parent_flags = flags.entry(parent).or_default();
+        if *parent_flags & SEEN == 0 {
+            insert_by_date(list, &self.commits, parent, date);
+        }
+        *parent_flags |= child_flags;
+        Ok(())
+    }
+
+    /// Walks on until every commit left is reachable from `best`, counting the ones that
+    /// aren't into its depth
+    fn finish_depth(
+        &mut self,
+        list: &mut VecDeque<[u8; 20]>,
+        flags: &mut HashMap<[u8; 20], u32>,
+        best: &mut Candidate,
+    ) -> anyhow::Result<()> {
+        while let Some(current) = list.pop_front() {
+            let current_flags = flags[&current];
+            if current_flags & best.flag != 0 {
+                if list.iter().all(|commit| flags[commit] & best.flag != 0) {
+                    break;
+                }
+            } else {
+                best.depth += 1;
+            }
+            let parents = self.commit(&current)?.1.clone();
+            for parent in parents {
+                self.queue(list, flags, parent, current_flags)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The name for `commit`, warning once if an annotated tag calls itself something else
+    fn append_name(&mut self, commit: &[u8; 20]) -> anyhow::Result<String> {
+        let all = self.opts.all;
+        let name = self.names.get_mut(commit).unwrap();
+        if name.prio == 2 && name.tag.is_none() {
+            name.tag = Some(
+                Tag::read(&name.hash)
+                    .map_err(|_| anyhow::anyhow!("annotated tag {} not available", name.path))?,
+            );
+        }
+        let Some(tag) = &name.tag else {
+            return Ok(name.path.clone());
+        };
+        if name.misnamed.is_none() {
+            let path = match all {
+                true => name.path.get("tags/".len()..).unwrap_or_default(),
+                false => &name.path,
+            };
+            let misnamed = tag.name != path;
+            if misnamed {
+                eprintln!(
+                    "warning: tag '{}' is externally known as '{}'",
+                    name.path, tag.name
+                );
+            }
+            name.misnamed = Some(misnamed);
+        }
+        Ok(match all {
+            true => format!("tags/{}", tag.name),
+            false => tag.name.clone(),
+        })
+    }
+
+    fn suffix(&self, depth: usize, commit: &[u8; 20]) -> String {
+        format!("-{depth}-g{}", self.abbreviate(commit))
+    }
+
+    fn abbreviate(&self, commit: &[u8; 20]) -> String {
+        let hex = hex::encode(commit);
+        match self.opts.abbrev {
+            0 => hex,
+            abbrev => hex[..abbrev.min(hex.len())].to_string(),
+        }
+    }
+}
+
+/// Inserts `commit` after the commits at least as recent
+fn insert_by_date(
+    list: &mut VecDeque<[u8; 20]>,
+    commits: &HashMap<[u8; 20], (i64, Vec<[u8; 20]>)>,
+    commit: [u8; 20],
+    date: i64,
+) {
+    let at = list
+        .iter()
+        .position(|other| commits[other].0 < date)
+        .unwrap_or(list.len());
+    list.insert(at, commit);
+}
+
+fn tag_date(tag: &Tag) -> i64 {
+    tag.tagger.as_ref().map_or(0, |tagger| tagger.time)
+}
+
+/// Whether tracked files in the index or working tree differ from HEAD
+pub(crate) fn is_dirty() -> anyhow::Result<bool> {
+    let head_tree = match refs::head_commit()? {
+        Some(head) => Some(Commit::read(&head)?.tree),
+        None => None,
+    };
+    let mut index = Index::read()?;
+    let report = status::compute(&mut index, head_tree.as_ref(), UntrackedMode::No)?;
+    Ok(report.has_staged() || report.has_unstaged() || !report.unmerged.is_empty())
+}
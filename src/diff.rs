@@ -0,0 +1,506 @@
+//! Line diffs between two buffers, producing the same edit scripts as git's xdiff.
+
+use std::{borrow::Cow, collections::HashMap};
+
+pub(crate) mod binary;
+pub(crate) mod color;
+pub(crate) mod combined;
+pub(crate) mod files;
+pub(crate) mod histogram;
+pub(crate) mod moved;
+pub(crate) mod myers;
+pub(crate) mod patch;
+pub(crate) mod patch_id;
+pub(crate) mod patience;
+pub(crate) mod pickaxe;
+pub(crate) mod rename;
+pub(crate) mod tree;
+pub(crate) mod words;
+
+/// Lines of one side of a diff, with per-line change marks
+pub(crate) struct Records<'a> {
+    pub(crate) lines: Vec<&'a [u8]>,
+    /// Equal lines (on either side) share an id
+    pub(crate) ids: Vec<usize>,
+    /// Change marks with a sentinel on each end, so index `i` is stored at `i + 1`
+    changed: Vec<bool>,
+}
+
+impl<'a> Records<'a> {
+    fn new(lines: Vec<&'a [u8]>, ids: Vec<usize>) -> Records<'a> {
+        let changed = vec![false; lines.len() + 2];
+        Records {
+            lines,
+            ids,
+            changed,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub(crate) fn is_changed(&self, i: i64) -> bool {
+        self.changed[(i + 1) as usize]
+    }
+
+    pub(crate) fn set_changed(&mut self, i: usize, changed: bool) {
+        self.changed[i + 1] = changed;
+    }
+
+    /// Number of occurrences of each line id
+    pub(crate) fn counts(&self) -> Vec<usize> {
+        let mut counts = Vec::new();
+        for &id in &self.ids {
+            if counts.len() <= id {
+                counts.resize(id + 1, 0);
+            }
+            counts[id] += 1;
+        }
+        counts
+    }
+}
+
+/// Splits a buffer into lines, each keeping its trailing newline
+pub(crate) fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Algorithm {
+    #[default]
+    Myers,
+    Patience,
+    Histogram,
+}
+
+impl Algorithm {
+    /// Parses a `--diff-algorithm` name. `minimal` is Myers with the minimal flag, so the flag
+    /// is returned alongside.
+    pub(crate) fn parse(name: &str) -> Option<(Algorithm, bool)> {
+        match name.to_ascii_lowercase().as_str() {
+            "default" | "myers" => Some((Algorithm::Myers, false)),
+            "minimal" => Some((Algorithm::Myers, true)),
+            "patience" => Some((Algorithm::Patience, false)),
+            "histogram" => Some((Algorithm::Histogram, false)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DiffOptions {
+    pub(crate) algorithm: Algorithm,
+    /// Spend extra time to find the smallest possible diff
+    pub(crate) minimal: bool,
+    /// Slide ambiguous hunks to where they look best for indented code
+    pub(crate) indent_heuristic: bool,
+    /// Treat lines differing only in the amount of whitespace as equal
+    pub(crate) ignore_space_change: bool,
+    /// Treat lines differing only in whitespace as equal
+    pub(crate) ignore_all_space: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> DiffOptions {
+        DiffOptions {
+            algorithm: Algorithm::Myers,
+            minimal: false,
+            indent_heuristic: true,
+            ignore_space_change: false,
+            ignore_all_space: false,
+        }
+    }
+}
+
+/// A run of changed lines: `old_len` lines at `old_start` replaced by `new_len` lines at
+/// `new_start` (0-based)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Change {
+    pub(crate) old_start: usize,
+    pub(crate) old_len: usize,
+    pub(crate) new_start: usize,
+    pub(crate) new_len: usize,
+}
+
+/// What a line is compared by: the line itself, with `ignore_space_change` the line with
+/// each run of whitespace shortened to one space and trailing whitespace dropped, and with
+/// `ignore_all_space` the line without any whitespace
+pub(crate) fn line_key<'a>(line: &'a [u8], opts: &DiffOptions) -> Cow<'a, [u8]> {
+    if opts.ignore_all_space {
+        let key = line.iter().filter(|c| !c.is_ascii_whitespace()).copied();
+        return Cow::Owned(key.collect());
+    }
+    if !opts.ignore_space_change {
+        return Cow::Borrowed(line);
+    }
+    let mut key = Vec::with_capacity(line.len());
+    let mut space = false;
+    for &c in line {
+        if c.is_ascii_whitespace() {
+            space = true;
+            continue;
+        }
+        if space {
+            key.push(b' ');
+        }
+        space = false;
+        key.push(c);
+    }
+    Cow::Owned(key)
+}
+
+/// Diffs two lists of lines, returning the changes in order
+pub(crate) fn diff_lines<'a>(
+    old: &[&'a [u8]],
+    new: &[&'a [u8]],
+    opts: &DiffOptions,
+) -> Vec<Change> {
+    let mut classes: HashMap<Cow<'a, [u8]>, usize> = HashMap::new();
+    let mut classify = |lines: &[&'a [u8]]| -> Vec<usize> {
+        lines
+            .iter()
+            .map(|line| {
+                let next = classes.len();
+                *classes.entry(line_key(line, opts)).or_insert(next)
+            })
+            .collect()
+    };
+    let old_ids = classify(old);
+    let new_ids = classify(new);
+    let mut old = Records::new(old.to_vec(), old_ids);
+    let mut new = Records::new(new.to_vec(), new_ids);
+
+    match opts.algorithm {
+        Algorithm::Myers => myers::diff(&mut old, &mut new, opts.minimal),
+        Algorithm::Patience => patience::diff(&mut old, &mut new, opts.minimal),
+        Algorithm::Histogram => histogram::diff(&mut old, &mut new, opts.minimal),
+    }
+
+    compact(&mut old, &mut new, opts.indent_heuristic);
+    compact(&mut new, &mut old, opts.indent_heuristic);
+    build_script(&old, &new)
+}
+
+fn build_script(old: &Records, new: &Records) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let (mut i1, mut i2) = (0i64, 0i64);
+    let (n1, n2) = (old.len() as i64, new.len() as i64);
+    while i1 < n1 || i2 < n2 {
+        if old.is_changed(i1) || new.is_changed(i2) {
+            let (start1, start2) = (i1, i2);
+            while old.is_changed(i1) {
+                i1 += 1;
+            }
+            while new.is_changed(i2) {
+                i2 += 1;
+            }
+            changes.push(Change {
+                old_start: start1 as usize,
+                old_len: (i1 - start1) as usize,
+                new_start: start2 as usize,
+                new_len: (i2 - start2) as usize,
+            });
+        } else {
+            i1 += 1;
+            i2 += 1;
+        }
+    }
+    changes
+}
+
+/// A group of consecutive changed lines `[start, end)`
+#[derive(Debug, Clone, Copy)]
+struct Group {
+    start: i64,
+    end: i64,
+}
+
+impl Group {
+    fn first(records: &Records) -> Group {
+        let mut end = 0;
+        while records.is_changed(end) {
+            end += 1;
+        }
+        Group { start: 0, end }
+    }
+
+    /// Moves to the next group, returning false at the end of the file
+    fn next(&mut self, records: &Records) -> bool {
+        if self.end == records.len() as i64 {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        while records.is_changed(self.end) {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn previous(&mut self, records: &Records) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while records.is_changed(self.start - 1) {
+            self.start -= 1;
+        }
+        true
+    }
+
+    fn slide_down(&mut self, records: &mut Records) -> bool {
+        if self.end < records.len() as i64
+            && records.ids[self.start as usize] == records.ids[self.end as usize]
+        {
+            records.set_changed(self.start as usize, false);
+            records.set_changed(self.end as usize, true);
+            self.start += 1;
+            self.end += 1;
+            while records.is_changed(self.end) {
+                self.end += 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn slide_up(&mut self, records: &mut Records) -> bool {
+        if self.start > 0
+            && records.ids[self.start as usize - 1] == records.ids[self.end as usize - 1]
+        {
+            self.start -= 1;
+            self.end -= 1;
+            records.set_changed(self.start as usize, true);
+            records.set_changed(self.end as usize, false);
+            while records.is_changed(self.start - 1) {
+                self.start -= 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+const INDENT_HEURISTIC_MAX_SLIDING: i64 = 100;
+
+/// Slides groups of changes in `records` so that they line up with changes in `other` where
+/// possible, or to the position the indent heuristic likes best
+fn compact(records: &mut Records, other: &mut Records, indent_heuristic: bool) {
+    let mut g = Group::first(records);
+    let mut go = Group::first(other);
+    loop {
+        if g.end != g.start {
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                let groupsize = g.end - g.start;
+                end_matching_other = -1;
+
+                while g.slide_up(records) {
+                    go.previous(other);
+                }
+                earliest_end = g.end;
+                if go.end > go.start {
+                    end_matching_other = g.end;
+                }
+                while g.slide_down(records) {
+                    go.next(other);
+                    if go.end > go.start {
+                        end_matching_other = g.end;
+                    }
+                }
+                if groupsize == g.end - g.start {
+                    break;
+                }
+            }
+
+            if g.end == earliest_end {
+                // no shifting was possible
+            } else if end_matching_other != -1 {
+                while go.end == go.start {
+                    g.slide_up(records);
+                    go.previous(other);
+                }
+            } else if indent_heuristic {
+                let groupsize = g.end - g.start;
+                let mut shift = earliest_end
+                    .max(g.end - groupsize - 1)
+                    .max(g.end - INDENT_HEURISTIC_MAX_SLIDING);
+                let mut best: Option<(i64, SplitScore)> = None;
+                while shift <= g.end {
+                    let mut score = SplitScore::default();
+                    score.add(&measure_split(records, shift));
+                    score.add(&measure_split(records, shift - groupsize));
+                    if best.is_none_or(|(_, best_score)| score.cmp(&best_score) <= 0) {
+                        best = Some((shift, score));
+                    }
+                    shift += 1;
+                }
+                let best_shift = best.map_or(g.end, |(shift, _)| shift);
+                while g.end > best_shift {
+                    g.slide_up(records);
+                    go.previous(other);
+                }
+            }
+        }
+        if !g.next(records) {
+            break;
+        }
+        go.next(other);
+    }
+}
+
+const MAX_INDENT: i32 = 200;
+const MAX_BLANKS: i32 = 20;
+
+/// Indentation width of a line, or -1 if it is blank
+fn get_indent(line: &[u8]) -> i32 {
+    let mut indent = 0;
+    for &c in line {
+        if !c.is_ascii_whitespace() && c != 0x0b {
+            return indent;
+        }
+        if c == b' ' {
+            indent += 1;
+        } else if c == b'\t' {
+            indent += 8 - indent % 8;
+        }
+        if indent >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+struct SplitMeasurement {
+    end_of_file: bool,
+    indent: i32,
+    pre_blank: i32,
+    pre_indent: i32,
+    post_blank: i32,
+    post_indent: i32,
+}
+
+fn measure_split(records: &Records, split: i64) -> SplitMeasurement {
+    let n = records.len() as i64;
+    let (end_of_file, indent) = if split >= n {
+        (true, -1)
+    } else {
+        (false, get_indent(records.lines[split as usize]))
+    };
+
+    let mut pre_blank = 0;
+    let mut pre_indent = -1;
+    let mut i = split - 1;
+    while i >= 0 {
+        pre_indent = get_indent(records.lines[i as usize]);
+        if pre_indent != -1 {
+            break;
+        }
+        pre_blank += 1;
+        if pre_blank == MAX_BLANKS {
+            pre_indent = 0;
+            break;
+        }
+        i -= 1;
+    }
+
+    let mut post_blank = 0;
+    let mut post_indent = -1;
+    let mut i = split + 1;
+    while i < n {
+        post_indent = get_indent(records.lines[i as usize]);
+        if post_indent != -1 {
+            break;
+        }
+        post_blank += 1;
+        if post_blank == MAX_BLANKS {
+            post_indent = 0;
+            break;
+        }
+        i += 1;
+    }
+
+    SplitMeasurement {
+        end_of_file,
+        indent,
+        pre_blank,
+        pre_indent,
+        post_blank,
+        post_indent,
+    }
+}
+
+const START_OF_FILE_PENALTY: i32 = 1;
+const END_OF_FILE_PENALTY: i32 = 21;
+const TOTAL_BLANK_WEIGHT: i32 = -30;
+const POST_BLANK_WEIGHT: i32 = 6;
+const RELATIVE_INDENT_PENALTY: i32 = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: i32 = 10;
+const RELATIVE_OUTDENT_PENALTY: i32 = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: i32 = 17;
+const RELATIVE_DEDENT_PENALTY: i32 = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: i32 = 17;
+const INDENT_WEIGHT: i32 = 60;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct SplitScore {
+    effective_indent: i32,
+    penalty: i32,
+}
+
+impl SplitScore {
+    fn add(&mut self, m: &SplitMeasurement) {
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            self.penalty += START_OF_FILE_PENALTY;
+        }
+        if m.end_of_file {
+            self.penalty += END_OF_FILE_PENALTY;
+        }
+        let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += TOTAL_BLANK_WEIGHT * total_blank;
+        self.penalty += POST_BLANK_WEIGHT * post_blank;
+
+        let indent = if m.indent != -1 {
+            m.indent
+        } else {
+            m.post_indent
+        };
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent;
+
+        if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+            // no adjustment
+        } else if indent > m.pre_indent {
+            self.penalty += if any_blanks {
+                RELATIVE_INDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_INDENT_PENALTY
+            };
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            self.penalty += if any_blanks {
+                RELATIVE_OUTDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_OUTDENT_PENALTY
+            };
+        } else {
+            self.penalty += if any_blanks {
+                RELATIVE_DEDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_DEDENT_PENALTY
+            };
+        }
+    }
+
+    /// Negative if `self` is the better split
+    fn cmp(&self, other: &SplitScore) -> i32 {
+        let cmp_indents = (self.effective_indent > other.effective_indent) as i32
+            - (self.effective_indent < other.effective_indent) as i32;
+        INDENT_WEIGHT * cmp_indents + (self.penalty - other.penalty)
+    }
+}
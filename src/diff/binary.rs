@@ -0,0 +1,54 @@
+//! Binary patches as `--binary` writes them: the new content, and for going back the old one,
+//! deflated and encoded in git's base85 in lines of up to 52 bytes.
+
+use std::io::Write;
+
+use flate2::{write::ZlibEncoder, Compression};
+
+pub(crate) const BASE85: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Writes the `GIT binary patch` body taking `old` to `new`, which `apply` can use either way
+pub(crate) fn write_patch(out: &mut Vec<u8>, old: &[u8], new: &[u8]) -> std::io::Result<()> {
+    writeln!(out, "GIT binary patch")?;
+    write_literal(out, new)?;
+    write_literal(out, old)
+}
+
+/// A `literal <size>` hunk holding the whole content, deflated for speed as git does, followed
+/// by a blank line
+fn write_literal(out: &mut Vec<u8>, content: &[u8]) -> std::io::Result<()> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(content)?;
+    let deflated = encoder.finish()?;
+    writeln!(out, "literal {}", content.len())?;
+    for chunk in deflated.chunks(52) {
+        // The length of the line's data: A-Z for 1 to 26 bytes, a-z for 27 to 52
+        let len = chunk.len() as u8;
+        out.push(if len <= 26 {
+            b'A' + len - 1
+        } else {
+            b'a' + len - 27
+        });
+        encode85(out, chunk);
+        out.push(b'\n');
+    }
+    out.push(b'\n');
+    Ok(())
+}
+
+/// Encodes data in groups of four bytes, zero-padding the last, as five base85 characters each
+fn encode85(out: &mut Vec<u8>, data: &[u8]) {
+    for group in data.chunks(4) {
+        let mut value = 0u32;
+        for i in 0..4 {
+            value = value << 8 | u32::from(group.get(i).copied().unwrap_or(0));
+        }
+        let mut encoded = [0; 5];
+        for digit in encoded.iter_mut().rev() {
+            *digit = BASE85[(value % 85) as usize];
+            value /= 85;
+        }
+        out.extend_from_slice(&encoded);
+    }
+}
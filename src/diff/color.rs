@@ -0,0 +1,228 @@
+//! Colored patch output: the escape sequences git uses for each kind of line, and the
+//! whitespace error highlighting applied to added lines.
+
+use std::io::{IsTerminal, Write};
+
+/// Escape sequences for each part of a patch. All of them are empty when color is off, so the
+/// same code writes both plain and colored output.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Palette {
+    pub(crate) meta: &'static str,
+    pub(crate) frag: &'static str,
+    pub(crate) func: &'static str,
+    pub(crate) context: &'static str,
+    pub(crate) old: &'static str,
+    pub(crate) new: &'static str,
+    pub(crate) whitespace: &'static str,
+    pub(crate) reset: &'static str,
+    pub(crate) old_moved: &'static str,
+    pub(crate) old_moved_alt: &'static str,
+    pub(crate) old_moved_dim: &'static str,
+    pub(crate) old_moved_alt_dim: &'static str,
+    pub(crate) new_moved: &'static str,
+    pub(crate) new_moved_alt: &'static str,
+    pub(crate) new_moved_dim: &'static str,
+    pub(crate) new_moved_alt_dim: &'static str,
+}
+
+impl Palette {
+    pub(crate) fn new(enabled: bool) -> Palette {
+        if !enabled {
+            return Palette {
+                meta: "",
+                frag: "",
+                func: "",
+                context: "",
+                old: "",
+                new: "",
+                whitespace: "",
+                reset: "",
+                old_moved: "",
+                old_moved_alt: "",
+                old_moved_dim: "",
+                old_moved_alt_dim: "",
+                new_moved: "",
+                new_moved_alt: "",
+                new_moved_dim: "",
+                new_moved_alt_dim: "",
+            };
+        }
+        Palette {
+            meta: "\x1b[1m",
+            frag: "\x1b[36m",
+            func: "",
+            context: "",
+            old: "\x1b[31m",
+            new: "\x1b[32m",
+            whitespace: "\x1b[41m",
+            reset: "\x1b[m",
+            old_moved: "\x1b[1;35m",
+            old_moved_alt: "\x1b[1;34m",
+            old_moved_dim: "\x1b[2m",
+            old_moved_alt_dim: "\x1b[2;3m",
+            new_moved: "\x1b[1;36m",
+            new_moved_alt: "\x1b[1;33m",
+            new_moved_dim: "\x1b[2m",
+            new_moved_alt_dim: "\x1b[2;3m",
+        }
+    }
+}
+
+/// Whether to color output for a `--color=<when>` or `color.diff` setting
+pub(crate) fn want_color(when: &str) -> anyhow::Result<bool> {
+    match when.to_ascii_lowercase().as_str() {
+        "always" => Ok(true),
+        "never" | "false" | "no" | "off" => Ok(false),
+        "auto" | "true" | "yes" | "on" => Ok(std::io::stdout().is_terminal()),
+        _ => anyhow::bail!("Invalid color setting '{when}'"),
+    }
+}
+
+/// Git's whitespace test: space, tab, newline and carriage return
+pub(crate) fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+/// Writes one line of output: `set_sign` colors the leading `first` character, `set` the rest.
+/// The trailing newline is kept outside the color.
+pub(crate) fn emit_line(
+    out: &mut impl Write,
+    set_sign: Option<&str>,
+    set: Option<&str>,
+    reset: &str,
+    first: Option<u8>,
+    line: &[u8],
+) -> std::io::Result<()> {
+    let mut line = line;
+    let newline = line.last() == Some(&b'\n');
+    if newline {
+        line = &line[..line.len() - 1];
+    }
+    let carriage_return = line.last() == Some(&b'\r');
+    if carriage_return {
+        line = &line[..line.len() - 1];
+    }
+
+    let mut needs_reset = false;
+    if !line.is_empty() || first.is_some() {
+        if let Some(set_sign) = set_sign {
+            out.write_all(set_sign.as_bytes())?;
+            needs_reset = true;
+        }
+        if let Some(first) = first {
+            out.write_all(&[first])?;
+        }
+        if !line.is_empty() {
+            if let Some(set) = set {
+                if set_sign.is_some_and(|set_sign| set_sign != set) {
+                    out.write_all(reset.as_bytes())?;
+                }
+                out.write_all(set.as_bytes())?;
+            }
+            out.write_all(line)?;
+            needs_reset = true;
+        }
+    }
+    if needs_reset {
+        out.write_all(reset.as_bytes())?;
+    }
+    if carriage_return {
+        out.write_all(b"\r")?;
+    }
+    if newline {
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Writes the content of an added line in `set`, painting trailing whitespace and spaces
+/// before a tab in the indent with `ws`, as git's default `core.whitespace` rules do
+pub(crate) fn ws_check_emit(
+    out: &mut impl Write,
+    line: &[u8],
+    set: &str,
+    reset: &str,
+    ws: &str,
+) -> std::io::Result<()> {
+    let mut line = line;
+    let newline = line.last() == Some(&b'\n');
+    if newline {
+        line = &line[..line.len() - 1];
+    }
+
+    let trailing = line.len() - line.iter().rev().take_while(|&&b| is_space(b)).count();
+
+    let mut written = 0;
+    for i in 0..trailing {
+        match line[i] {
+            b' ' => continue,
+            b'\t' => {}
+            _ => break,
+        }
+        if written < i {
+            out.write_all(ws.as_bytes())?;
+            out.write_all(&line[written..i])?;
+            out.write_all(reset.as_bytes())?;
+            out.write_all(&line[i..=i])?;
+        } else {
+            out.write_all(&line[written..=i])?;
+        }
+        written = i + 1;
+    }
+
+    if trailing > written {
+        out.write_all(set.as_bytes())?;
+        out.write_all(&line[written..trailing])?;
+        out.write_all(reset.as_bytes())?;
+    }
+    if trailing != line.len() {
+        out.write_all(ws.as_bytes())?;
+        out.write_all(&line[trailing..])?;
+        out.write_all(reset.as_bytes())?;
+    }
+    if newline {
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+pub(crate) fn is_blank_line(line: &[u8]) -> bool {
+    line.iter().all(|&b| is_space(b))
+}
+
+/// Number of blank lines at the end of a buffer, counted the way git does for `blank-at-eof`
+fn count_trailing_blank(content: &[u8]) -> usize {
+    if content.is_empty() {
+        return 0;
+    }
+    let mut ptr = content.len() as i64 - 1;
+    if content[ptr as usize] == b'\n' {
+        ptr -= 1;
+    }
+    let mut count = 0;
+    while ptr > 0 {
+        let mut prev_eol = ptr;
+        while prev_eol >= 0 && content[prev_eol as usize] != b'\n' {
+            prev_eol -= 1;
+        }
+        if !is_blank_line(&content[(prev_eol + 1) as usize..(ptr + 1) as usize]) {
+            break;
+        }
+        count += 1;
+        ptr = prev_eol - 1;
+    }
+    count
+}
+
+/// First line numbers (1-based) of the blank lines that the new side adds at the end of the
+/// file, or `None` if it does not add any
+pub(crate) fn blank_at_eof(old: &[u8], new: &[u8]) -> Option<(usize, usize)> {
+    let old_blank = count_trailing_blank(old);
+    let new_blank = count_trailing_blank(new);
+    if new_blank <= old_blank {
+        return None;
+    }
+    let old_lines = super::split_lines(old).len();
+    let new_lines = super::split_lines(new).len();
+    Some((old_lines - old_blank + 1, new_lines - new_blank + 1))
+}
@@ -0,0 +1,680 @@
+//! Combined diffs of a merge against all of its parents, as `diff -c` shows them: the result
+//! is diffed against each parent, and all hunks where it differs from them are shown. The dense
+//! form of `diff --cc` keeps only the hunks where it differs from the parents in more than one
+//! way.
+
+use std::{collections::HashMap, io::Write};
+
+use crate::{
+    diff::{
+        color::{is_space, Palette},
+        diff_lines, line_key,
+        patch::{abbrev, is_binary, FilePair, FileSide, PatchOptions, NULL_HASH},
+        rename::{self, RenameOptions},
+        split_lines,
+        tree::{diff_trees, TreeDiffOptions},
+        DiffOptions,
+    },
+    pathspec::Pathspec,
+    quote,
+};
+
+/// A path the result changes relative to every parent
+#[derive(Debug, Clone)]
+pub(crate) struct CombinedPath {
+    pub(crate) path: String,
+    /// The file in each parent, `None` where the parent doesn't have it
+    pub(crate) parents: Vec<Option<FileSide>>,
+    /// The file in the result, `None` if the merge deleted it
+    pub(crate) result: Option<FileSide>,
+    /// Status letter against each parent, as `--name-status` shows it
+    pub(crate) statuses: Vec<char>,
+}
+
+/// The paths of `result` that differ from every one of the `parents` trees, in tree order.
+/// With rename detection, a file renamed from a parent is compared with its source there.
+/// `unchanged` collects unmodified files as copy sources, as for `diff::files::tree_to_tree`.
+pub(crate) fn changed_paths(
+    parents: &[[u8; 20]],
+    result: &[u8; 20],
+    pathspec: &Pathspec,
+    renames: Option<&RenameOptions>,
+    unchanged: bool,
+) -> anyhow::Result<Vec<CombinedPath>> {
+    let opts = TreeDiffOptions {
+        recursive: true,
+        unchanged,
+        ..TreeDiffOptions::default()
+    };
+    let diff = |parent: &[u8; 20]| -> anyhow::Result<Vec<FilePair>> {
+        let pairs = diff_trees(Some(parent), Some(result), pathspec, opts)?;
+        match renames {
+            Some(renames) => rename::detect(pairs, renames),
+            None => Ok(pairs),
+        }
+    };
+    let Some((first, rest)) = parents.split_first() else {
+        return Ok(Vec::new());
+    };
+    let mut others = Vec::new();
+    for parent in rest {
+        let by_path: HashMap<String, FilePair> = diff(parent)?
+            .into_iter()
+            .map(|pair| (pair.path().to_string(), pair))
+            .collect();
+        others.push(by_path);
+    }
+
+    let mut paths = Vec::new();
+    'pairs: for pair in diff(first)? {
+        let mut pairs = vec![&pair];
+        for other in &others {
+            match other.get(pair.path()) {
+                Some(other) => pairs.push(other),
+                None => continue 'pairs,
+            }
+        }
+        paths.push(CombinedPath {
+            path: pair.path().to_string(),
+            parents: pairs.iter().map(|pair| pair.old.clone()).collect(),
+            result: pair.new.clone(),
+            statuses: pairs.iter().map(|pair| pair.status()).collect(),
+        });
+    }
+    Ok(paths)
+}
+
+/// A line some parents had that the result lost, shown before the result line it was lost at
+#[derive(Debug, Clone, Copy)]
+struct Lost<'a> {
+    text: &'a [u8],
+    /// Bit `n` is set if parent `n` had the line
+    parents: u64,
+}
+
+/// A line of the result, with the parent lines lost right before it
+#[derive(Debug, Clone)]
+struct Line<'a> {
+    /// Without its newline; `None` for the sentinel after the last line
+    text: Option<&'a [u8]>,
+    /// Bit `n` is set if the line was added relative to parent `n`; the bits above the parents
+    /// mark lines to show and lines whose lost lines are left out
+    flag: u64,
+    lost: Vec<Lost<'a>>,
+    /// For each parent, its line number (from 1) where a hunk starting at this line starts
+    parent_line: Vec<usize>,
+}
+
+fn without_newline(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n").unwrap_or(line)
+}
+
+/// Writes the combined patches of the paths, the `--cc` ones if `dense`
+pub(crate) fn write_patches(
+    out: &mut impl Write,
+    paths: &[CombinedPath],
+    opts: &PatchOptions,
+    dense: bool,
+) -> anyhow::Result<()> {
+    let palette = Palette::new(opts.color);
+    for path in paths {
+        write_path(out, path, opts, dense, &palette)?;
+    }
+    Ok(())
+}
+
+fn write_path(
+    out: &mut impl Write,
+    path: &CombinedPath,
+    opts: &PatchOptions,
+    dense: bool,
+    palette: &Palette,
+) -> anyhow::Result<()> {
+    let num_parents = path.parents.len();
+    let mode = |side: &Option<FileSide>| side.as_ref().map_or(0, |side| side.mode);
+    let mode_differs = path
+        .parents
+        .iter()
+        .any(|parent| mode(parent) != mode(&path.result));
+
+    let result = match &path.result {
+        Some(result) => result.content()?,
+        None => Vec::new(),
+    };
+    let mut parents = Vec::new();
+    for parent in &path.parents {
+        parents.push(match parent {
+            Some(parent) => parent.content()?,
+            None => Vec::new(),
+        });
+    }
+    if is_binary(&result) || parents.iter().any(|parent| is_binary(parent)) {
+        write_header(out, path, mode_differs, false, dense, palette)?;
+        writeln!(out, "Binary files differ")?;
+        return Ok(());
+    }
+
+    let result_lines = split_lines(&result);
+    let cnt = result_lines.len();
+    // One sentinel to hang lines lost at the end on, and one for the line numbers past it
+    let mut lines: Vec<Line> = result_lines
+        .iter()
+        .map(|line| Some(without_newline(line)))
+        .chain([None, None])
+        .map(|text| Line {
+            text,
+            flag: 0,
+            lost: Vec::new(),
+            parent_line: vec![0; num_parents],
+        })
+        .collect();
+    for (n, parent) in parents.iter().enumerate() {
+        let same = (0..n).find(|&m| parent_hash(path, m) == parent_hash(path, n));
+        match same {
+            Some(m) => reuse_parent(&mut lines, n, m),
+            None => diff_parent(&mut lines, &result_lines, parent, n, &opts.diff),
+        }
+    }
+
+    let show_hunks = make_hunks(&mut lines, cnt, num_parents, opts.context, dense);
+    if show_hunks || mode_differs {
+        write_header(out, path, mode_differs, true, dense, palette)?;
+        write_hunks(out, &lines, cnt, num_parents, opts.context, palette)?;
+    }
+    Ok(())
+}
+
+fn parent_hash(path: &CombinedPath, n: usize) -> [u8; 20] {
+    path.parents[n]
+        .as_ref()
+        .map_or(NULL_HASH, |parent| parent.hash)
+}
+
+/// Marks the result lines added relative to parent `n` and collects the lines it lost
+fn diff_parent<'a>(
+    lines: &mut [Line<'a>],
+    result_lines: &[&'a [u8]],
+    parent: &'a [u8],
+    n: usize,
+    opts: &DiffOptions,
+) {
+    let bit = 1u64 << n;
+    let parent_lines = split_lines(parent);
+    let changes = diff_lines(&parent_lines, result_lines, opts);
+
+    // Lost lines hang on the result line after them
+    let mut new_lost: Vec<Vec<Lost>> = vec![Vec::new(); lines.len()];
+    for change in &changes {
+        for line in &parent_lines[change.old_start..change.old_start + change.old_len] {
+            new_lost[change.new_start].push(Lost {
+                text: without_newline(line),
+                parents: bit,
+            });
+        }
+        for line in &mut lines[change.new_start..change.new_start + change.new_len] {
+            line.flag |= bit;
+        }
+    }
+
+    let cnt = result_lines.len();
+    let mut parent_line = 1;
+    for (lno, lost) in new_lost.into_iter().enumerate().take(cnt + 1) {
+        let line = &mut lines[lno];
+        line.parent_line[n] = parent_line;
+        coalesce(&mut line.lost, lost, n, opts);
+        // Lines shown as lost from the parent or unchanged in it advance its line number
+        parent_line += line
+            .lost
+            .iter()
+            .filter(|lost| lost.parents & bit != 0)
+            .count();
+        if lno < cnt && line.flag & bit == 0 {
+            parent_line += 1;
+        }
+    }
+    lines[cnt + 1].parent_line[n] = parent_line;
+}
+
+/// Treats parent `n` as having exactly the changes of the identical parent `m`
+fn reuse_parent(lines: &mut [Line], n: usize, m: usize) {
+    let (bit, same) = (1u64 << n, 1u64 << m);
+    for line in lines {
+        if line.flag & same != 0 {
+            line.flag |= bit;
+        }
+        for lost in &mut line.lost {
+            if lost.parents & same != 0 {
+                lost.parents |= bit;
+            }
+        }
+        line.parent_line[n] = line.parent_line[m];
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Match,
+    Base,
+    New,
+}
+
+/// Merges the lines lost from parent `n` into those lost from earlier parents, sharing the
+/// lines of a longest common subsequence so each is shown once
+fn coalesce<'a>(base: &mut Vec<Lost<'a>>, new: Vec<Lost<'a>>, n: usize, opts: &DiffOptions) {
+    if new.is_empty() {
+        return;
+    }
+    if base.is_empty() {
+        *base = new;
+        return;
+    }
+    let (base_len, new_len) = (base.len(), new.len());
+    let mut lcs = vec![vec![0usize; new_len + 1]; base_len + 1];
+    let mut directions = vec![vec![Direction::Base; new_len + 1]; base_len + 1];
+    for direction in &mut directions[0][1..] {
+        *direction = Direction::New;
+    }
+    for i in 1..=base_len {
+        for j in 1..=new_len {
+            if line_key(base[i - 1].text, opts) == line_key(new[j - 1].text, opts) {
+                lcs[i][j] = lcs[i - 1][j - 1] + 1;
+                directions[i][j] = Direction::Match;
+            } else if lcs[i][j - 1] >= lcs[i - 1][j] {
+                lcs[i][j] = lcs[i][j - 1];
+                directions[i][j] = Direction::New;
+            } else {
+                lcs[i][j] = lcs[i - 1][j];
+                directions[i][j] = Direction::Base;
+            }
+        }
+    }
+
+    let mut merged = Vec::with_capacity(base_len + new_len);
+    let (mut i, mut j) = (base_len, new_len);
+    while i != 0 || j != 0 {
+        match directions[i][j] {
+            Direction::Match => {
+                let mut lost = base[i - 1];
+                lost.parents |= 1 << n;
+                merged.push(lost);
+                i -= 1;
+                j -= 1;
+            }
+            Direction::New => {
+                merged.push(new[j - 1]);
+                j -= 1;
+            }
+            Direction::Base => {
+                merged.push(base[i - 1]);
+                i -= 1;
+            }
+        }
+    }
+    merged.reverse();
+    *base = merged;
+}
+
+fn is_interesting(line: &Line, all_mask: u64) -> bool {
+    line.flag & all_mask != 0 || !line.lost.is_empty()
+}
+
+/// `end` is the first line after a hunk. A last line that is only in the hunk for the lines
+/// lost before it already serves as context, so the hunk is taken to end before it.
+fn adjust_hunk_tail(lines: &[Line], all_mask: u64, begin: usize, end: usize) -> usize {
+    if begin < end && lines[end - 1].flag & all_mask == 0 {
+        end - 1
+    } else {
+        end
+    }
+}
+
+/// The first line from `i` whose mark is set, or with `unmarked` the first whose mark isn't
+fn find_next(lines: &[Line], mark: u64, mut i: usize, cnt: usize, unmarked: bool) -> usize {
+    while i <= cnt {
+        if (lines[i].flag & mark == 0) == unmarked {
+            return i;
+        }
+        i += 1;
+    }
+    i
+}
+
+/// Marks the lines to show: the interesting ones, if `dense` leaving out hunks where the result
+/// only takes one side, and their context. Returns whether any are left.
+fn make_hunks(
+    lines: &mut [Line],
+    cnt: usize,
+    num_parents: usize,
+    context: usize,
+    dense: bool,
+) -> bool {
+    let all_mask = (1u64 << num_parents) - 1;
+    let mark = 1u64 << num_parents;
+    for line in &mut lines[..=cnt] {
+        if is_interesting(line, all_mask) {
+            line.flag |= mark;
+        } else {
+            line.flag &= !mark;
+        }
+    }
+    if !dense {
+        return give_context(lines, cnt, num_parents, context);
+    }
+
+    let mut i = 0;
+    while i <= cnt {
+        while i <= cnt && lines[i].flag & mark == 0 {
+            i += 1;
+        }
+        if i > cnt {
+            break;
+        }
+        let begin = i;
+        let mut j = i + 1;
+        while j <= cnt {
+            if lines[j].flag & mark == 0 {
+                // Look for an interesting line within the context after the hunk
+                let mut ahead = adjust_hunk_tail(lines, all_mask, begin, j);
+                ahead = (ahead + context).min(cnt + 1);
+                let mut continues = false;
+                while ahead > 0 {
+                    ahead -= 1;
+                    if ahead < j {
+                        break;
+                    }
+                    if lines[ahead].flag & mark != 0 {
+                        continues = true;
+                        break;
+                    }
+                }
+                if !continues {
+                    break;
+                }
+                j = ahead;
+            }
+            j += 1;
+        }
+        let end = j;
+
+        // A hunk is only interesting if the result differs from the parents in different
+        // ways, or from all of them
+        let mut same_diff = 0;
+        let mut differs = false;
+        'lines: for line in &lines[begin..end] {
+            let this_diff = line.flag & all_mask;
+            let lost = line.lost.iter().map(|lost| lost.parents);
+            for this_diff in std::iter::once(this_diff)
+                .filter(|&diff| diff != 0)
+                .chain(lost)
+            {
+                if same_diff == 0 {
+                    same_diff = this_diff;
+                } else if same_diff != this_diff {
+                    differs = true;
+                    break 'lines;
+                }
+            }
+        }
+        if !differs && same_diff != all_mask {
+            for line in &mut lines[begin..end] {
+                line.flag &= !mark;
+            }
+        }
+        i = end;
+    }
+
+    give_context(lines, cnt, num_parents, context)
+}
+
+/// Marks the context lines around the marked ones, joining groups that are close together
+fn give_context(lines: &mut [Line], cnt: usize, num_parents: usize, context: usize) -> bool {
+    let all_mask = (1u64 << num_parents) - 1;
+    let mark = 1u64 << num_parents;
+    let no_pre_delete = 2u64 << num_parents;
+
+    let mut i = find_next(lines, mark, 0, cnt, false);
+    if i > cnt {
+        return false;
+    }
+    'hunks: while i <= cnt {
+        // Leading context, without the lines lost before it
+        for line in &mut lines[i.saturating_sub(context)..i] {
+            if line.flag & mark == 0 {
+                line.flag |= no_pre_delete;
+            }
+            line.flag |= mark;
+        }
+        loop {
+            let j = find_next(lines, mark, i, cnt, true);
+            if j > cnt {
+                break 'hunks;
+            }
+            let k = find_next(lines, mark, j, cnt, false);
+            let j = adjust_hunk_tail(lines, all_mask, i, j);
+            if k < j + context {
+                // The gap to the next marked line is small enough to show as context
+                for line in &mut lines[j..k] {
+                    line.flag |= mark;
+                }
+                i = k;
+                continue;
+            }
+            i = k;
+            for line in &mut lines[j..(j + context).min(cnt + 1)] {
+                line.flag |= mark;
+            }
+            break;
+        }
+    }
+    true
+}
+
+fn write_meta(out: &mut impl Write, palette: &Palette, line: &str) -> std::io::Result<()> {
+    writeln!(out, "{}{line}{}", palette.meta, palette.reset)
+}
+
+fn write_header(
+    out: &mut impl Write,
+    path: &CombinedPath,
+    mode_differs: bool,
+    file_header: bool,
+    dense: bool,
+    palette: &Palette,
+) -> std::io::Result<()> {
+    let command = if dense {
+        "diff --cc"
+    } else {
+        "diff --combined"
+    };
+    write_meta(
+        out,
+        palette,
+        &format!("{command} {}", quote::c_style(&path.path, false)),
+    )?;
+    let hashes: Vec<String> = (0..path.parents.len())
+        .map(|n| abbrev(&parent_hash(path, n)))
+        .collect();
+    let result_hash = path.result.as_ref().map_or(NULL_HASH, |result| result.hash);
+    write_meta(
+        out,
+        palette,
+        &format!("index {}..{}", hashes.join(","), abbrev(&result_hash)),
+    )?;
+
+    let deleted = path.result.is_none();
+    let mut added = false;
+    if mode_differs {
+        // It was added if no parent had it
+        added = !deleted && path.parents.iter().all(Option::is_none);
+        if let (true, Some(result)) = (added, &path.result) {
+            write_meta(out, palette, &format!("new file mode {:06o}", result.mode))?;
+        } else {
+            if deleted {
+                write!(out, "{}deleted file ", palette.meta)?;
+            }
+            let modes: Vec<String> = path
+                .parents
+                .iter()
+                .map(|parent| format!("{:06o}", parent.as_ref().map_or(0, |parent| parent.mode)))
+                .collect();
+            write!(out, "mode {}", modes.join(","))?;
+            if let Some(result) = &path.result {
+                write!(out, "..{:06o}", result.mode)?;
+            }
+            writeln!(out, "{}", palette.reset)?;
+        }
+    }
+    if !file_header {
+        return Ok(());
+    }
+    let old_name = if added {
+        String::from("/dev/null")
+    } else {
+        quote::c_style(&format!("a/{}", path.path), false)
+    };
+    let new_name = if deleted {
+        String::from("/dev/null")
+    } else {
+        quote::c_style(&format!("b/{}", path.path), false)
+    };
+    write_meta(out, palette, &format!("--- {old_name}"))?;
+    write_meta(out, palette, &format!("+++ {new_name}"))
+}
+
+/// Writes a line without its newline, keeping a carriage return outside the color
+fn write_line(out: &mut impl Write, text: &[u8], reset: &str) -> std::io::Result<()> {
+    match text.strip_suffix(b"\r") {
+        Some(text) => {
+            out.write_all(text)?;
+            writeln!(out, "{reset}\r")
+        }
+        None => {
+            out.write_all(text)?;
+            writeln!(out, "{reset}")
+        }
+    }
+}
+
+/// The start of a function, for the hunk header: git's default is any line starting with a
+/// letter, `_` or `$`
+fn is_function_line(text: &[u8]) -> bool {
+    text.first()
+        .is_some_and(|&c| c.is_ascii_alphabetic() || c == b'_' || c == b'$')
+}
+
+fn write_hunks(
+    out: &mut impl Write,
+    lines: &[Line],
+    cnt: usize,
+    num_parents: usize,
+    context: usize,
+    palette: &Palette,
+) -> std::io::Result<()> {
+    let all_mask = (1u64 << num_parents) - 1;
+    let mark = 1u64 << num_parents;
+    let no_pre_delete = 2u64 << num_parents;
+    let markers = "@".repeat(num_parents + 1);
+
+    let mut lno = 0;
+    loop {
+        let mut function = None;
+        while lno <= cnt && lines[lno].flag & mark == 0 {
+            if let Some(text) = lines[lno].text.filter(|text| is_function_line(text)) {
+                function = Some(text);
+            }
+            lno += 1;
+        }
+        if lno > cnt {
+            break;
+        }
+        let mut end = lno + 1;
+        while end <= cnt && lines[end].flag & mark != 0 {
+            end += 1;
+        }
+        let mut result_count = end - lno;
+        if end > cnt {
+            // The sentinel only carries the lines lost at the end
+            result_count -= 1;
+        }
+        let mut null_context = 0;
+        if context == 0 {
+            // Result lines only there to hang lost lines on aren't shown without context
+            null_context = lines[lno..end]
+                .iter()
+                .filter(|line| line.flag & all_mask == 0)
+                .count();
+            // With nothing but lost lines at the end this wraps around, as it does in git
+            result_count = result_count.wrapping_sub(null_context);
+        }
+
+        write!(out, "{}{markers}", palette.frag)?;
+        for n in 0..num_parents {
+            let start = lines[lno].parent_line[n];
+            let count = lines[end].parent_line[n] - start;
+            write!(out, " -{start},{}", count.wrapping_sub(null_context))?;
+        }
+        write!(out, " +{},{result_count} {markers}", lno + 1)?;
+        if let Some(function) = function {
+            // Like git, up to the last non-space of the first 40 bytes, that byte excluded
+            let mut comment_end = 0;
+            for (i, &c) in function.iter().take(40).enumerate() {
+                if c == 0 {
+                    break;
+                }
+                if !is_space(c) {
+                    comment_end = i;
+                }
+            }
+            if comment_end > 0 {
+                write!(
+                    out,
+                    "{} {}{}{}",
+                    palette.reset, palette.context, palette.reset, palette.func
+                )?;
+                out.write_all(&function[..comment_end])?;
+            }
+        }
+        writeln!(out, "{}", palette.reset)?;
+
+        while lno < end {
+            let line = &lines[lno];
+            lno += 1;
+            if line.flag & no_pre_delete == 0 {
+                for lost in &line.lost {
+                    out.write_all(palette.old.as_bytes())?;
+                    for n in 0..num_parents {
+                        let marker = if lost.parents & (1 << n) != 0 {
+                            b'-'
+                        } else {
+                            b' '
+                        };
+                        out.write_all(&[marker])?;
+                    }
+                    write_line(out, lost.text, palette.reset)?;
+                }
+            }
+            let Some(text) = line.text else {
+                break;
+            };
+            if line.flag & all_mask == 0 {
+                // Only here to hang the lost lines on
+                if context == 0 {
+                    continue;
+                }
+                out.write_all(palette.context.as_bytes())?;
+            } else {
+                out.write_all(palette.new.as_bytes())?;
+            }
+            for n in 0..num_parents {
+                let marker = if line.flag & (1 << n) != 0 {
+                    b'+'
+                } else {
+                    b' '
+                };
+                out.write_all(&[marker])?;
+            }
+            write_line(out, text, palette.reset)?;
+        }
+    }
+    Ok(())
+}
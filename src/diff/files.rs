@@ -0,0 +1,282 @@
+//! Collects the file pairs that differ between trees, the index and the working tree.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use crate::{
+    diff::{
+        patch::{FilePair, FileSide},
+        tree::{diff_trees, TreeDiffOptions},
+    },
+    index::{mode_from_metadata, Index},
+    object::MODE_GITLINK,
+    pathspec::Pathspec,
+    unpack::{tree_blobs, Blob},
+};
+
+/// Pairs for the paths that differ. With `unchanged`, paths present on both sides with the same
+/// content are paired too, for use as copy sources.
+fn pairs_between(
+    old: &BTreeMap<String, Blob>,
+    new: &BTreeMap<String, Blob>,
+    pathspec: &Pathspec,
+    unchanged: bool,
+) -> Vec<FilePair> {
+    let mut paths: Vec<&String> = old.keys().chain(new.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    paths
+        .into_iter()
+        .filter(|path| pathspec.matches(path))
+        .filter_map(|path| {
+            let old = old.get(path);
+            let new = new.get(path);
+            if old == new && !unchanged {
+                return None;
+            }
+            Some(FilePair::new(
+                old.map(|(mode, hash)| FileSide::from_object(path, *mode, *hash)),
+                new.map(|(mode, hash)| FileSide::from_object(path, *mode, *hash)),
+            ))
+        })
+        .collect()
+}
+
+fn index_blobs(index: &Index) -> BTreeMap<String, Blob> {
+    index
+        .entries()
+        .iter()
+        .filter(|entry| entry.stage == 0)
+        .map(|entry| (entry.path.clone(), (entry.mode, entry.hash)))
+        .collect()
+}
+
+/// Changes between two trees; `None` stands for the empty tree
+pub(crate) fn tree_to_tree(
+    old: Option<&[u8; 20]>,
+    new: Option<&[u8; 20]>,
+    pathspec: &Pathspec,
+    unchanged: bool,
+) -> anyhow::Result<Vec<FilePair>> {
+    let opts = TreeDiffOptions {
+        recursive: true,
+        unchanged,
+        ..TreeDiffOptions::default()
+    };
+    diff_trees(old, new, pathspec, opts)
+}
+
+/// Changes staged in the index relative to a tree. Unmerged paths are returned separately
+/// rather than as deleted.
+pub(crate) fn tree_to_index(
+    tree: Option<&[u8; 20]>,
+    index: &Index,
+    pathspec: &Pathspec,
+    unchanged: bool,
+) -> anyhow::Result<(Vec<FilePair>, Vec<String>)> {
+    let mut tree = tree_blobs(tree)?;
+    let unmerged = unmerged_paths(index, pathspec);
+    for path in &unmerged {
+        tree.remove(path);
+    }
+    let pairs = pairs_between(&tree, &index_blobs(index), pathspec, unchanged);
+    Ok((pairs, unmerged))
+}
+
+/// The paths with conflict stages, once each
+fn unmerged_paths(index: &Index, pathspec: &Pathspec) -> Vec<String> {
+    let mut unmerged: Vec<String> = Vec::new();
+    for entry in index.entries() {
+        if entry.stage != 0 && pathspec.matches(&entry.path) && unmerged.last() != Some(&entry.path)
+        {
+            unmerged.push(entry.path.clone());
+        }
+    }
+    unmerged
+}
+
+/// The working tree version of a tracked path, `None` if it was deleted. Files whose cached stat
+/// information matches the index are not read.
+fn worktree_side(index: &Index, path: &str) -> anyhow::Result<Option<FileSide>> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(None);
+    };
+    if metadata.is_dir() {
+        return Ok(None);
+    }
+    let mode = mode_from_metadata(&metadata);
+    if let Some(entry) = index.get(path, 0) {
+        if entry.mode == MODE_GITLINK {
+            return Ok(Some(FileSide::from_object(path, entry.mode, entry.hash)));
+        }
+        if entry.stat_matches(&metadata) && !index.is_racy(entry) {
+            return Ok(Some(FileSide::from_object(path, entry.mode, entry.hash)));
+        }
+    }
+    let hash = crate::worktree::hash_file(Path::new(path))?;
+    // Content that matches the index is the indexed object, whatever the stat data says
+    if let Some(entry) = index.get(path, 0) {
+        if entry.mode == mode && entry.hash == hash {
+            return Ok(Some(FileSide::from_object(path, mode, hash)));
+        }
+    }
+    Ok(Some(FileSide {
+        path: path.to_string(),
+        mode,
+        hash,
+        worktree: true,
+    }))
+}
+
+fn differs(old: &FileSide, new: &Option<FileSide>) -> bool {
+    new.as_ref()
+        .is_none_or(|new| new.mode != old.mode || new.hash != old.hash)
+}
+
+/// Unstaged changes: the index against the working tree. Unmerged paths are returned separately.
+pub(crate) fn index_to_worktree(
+    index: &Index,
+    pathspec: &Pathspec,
+    unchanged: bool,
+) -> anyhow::Result<(Vec<FilePair>, Vec<String>)> {
+    let mut pairs = Vec::new();
+    let mut unmerged = Vec::new();
+    for entry in index.entries() {
+        if !pathspec.matches(&entry.path) {
+            continue;
+        }
+        if entry.stage != 0 {
+            if unmerged.last() != Some(&entry.path) {
+                unmerged.push(entry.path.clone());
+            }
+            continue;
+        }
+        let old = FileSide::from_object(&entry.path, entry.mode, entry.hash);
+        let new = worktree_side(index, &entry.path)?;
+        if unchanged || differs(&old, &new) {
+            pairs.push(FilePair::new(Some(old), new));
+        }
+    }
+    Ok((pairs, unmerged))
+}
+
+/// Changes between a tree and the working tree, for the paths tracked in either the tree or
+/// the index, unmerged paths included: their working tree file is what is compared
+pub(crate) fn tree_to_worktree(
+    tree: Option<&[u8; 20]>,
+    index: &Index,
+    pathspec: &Pathspec,
+    unchanged: bool,
+) -> anyhow::Result<Vec<FilePair>> {
+    let tree = tree_blobs(tree)?;
+    let tracked: BTreeSet<&String> = index.entries().iter().map(|entry| &entry.path).collect();
+    let mut paths: Vec<&String> = tree.keys().chain(tracked.iter().copied()).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut pairs = Vec::new();
+    for path in paths {
+        if !pathspec.matches(path) {
+            continue;
+        }
+        let old = tree
+            .get(path)
+            .map(|(mode, hash)| FileSide::from_object(path, *mode, *hash));
+        let new = if tracked.contains(path) {
+            worktree_side(index, path)?
+        } else {
+            None
+        };
+        let changed = match &old {
+            Some(old) => unchanged || differs(old, &new),
+            None => new.is_some(),
+        };
+        if changed {
+            pairs.push(FilePair::new(old, new));
+        }
+    }
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        index::IndexEntry,
+        object::MODE_FILE,
+        testing::{self, blob, tree},
+    };
+
+    /// An index with `a` changed from `head` and `c` in conflict
+    fn conflicted_index() -> Index {
+        let mut index = Index::default();
+        index.add(IndexEntry::new("a".into(), MODE_FILE, blob("a2\n"), 0));
+        index.add(IndexEntry::new("b".into(), MODE_FILE, blob("b\n"), 0));
+        for (stage, content) in [(1, "base\n"), (2, "ours\n"), (3, "theirs\n")] {
+            index.add(IndexEntry::new("c".into(), MODE_FILE, blob(content), stage));
+        }
+        index
+    }
+
+    fn summary(pairs: &[FilePair]) -> Vec<(char, &str)> {
+        pairs
+            .iter()
+            .map(|pair| (pair.status(), pair.path()))
+            .collect()
+    }
+
+    #[test]
+    fn tree_to_index_reports_unmerged_paths_apart() {
+        testing::in_repo(|| {
+            let head = tree(&[("a", "a\n"), ("b", "b\n"), ("c", "base\n")]);
+            let index = conflicted_index();
+            let (pairs, unmerged) =
+                tree_to_index(Some(&head), &index, &Pathspec::default(), false)?;
+            assert_eq!(summary(&pairs), [('M', "a")]);
+            assert_eq!(unmerged, ["c"]);
+
+            let pathspec = Pathspec::new(&["a".to_string()]);
+            let (pairs, unmerged) = tree_to_index(Some(&head), &index, &pathspec, false)?;
+            assert_eq!(summary(&pairs), [('M', "a")]);
+            assert!(unmerged.is_empty());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn tree_to_index_reports_unmerged_paths_missing_from_the_tree() {
+        testing::in_repo(|| {
+            let head = tree(&[("a", "a2\n"), ("b", "b\n")]);
+            let (pairs, unmerged) = tree_to_index(
+                Some(&head),
+                &conflicted_index(),
+                &Pathspec::default(),
+                false,
+            )?;
+            assert!(pairs.is_empty());
+            assert_eq!(unmerged, ["c"]);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn tree_to_worktree_reads_unmerged_paths_from_the_working_tree() {
+        testing::in_repo(|| {
+            let head = tree(&[("a", "a\n"), ("b", "b\n"), ("c", "base\n")]);
+            let index = conflicted_index();
+            fs::write("a", "a\n")?;
+            fs::write("b", "b\n")?;
+            fs::write("c", "<<<<<<< ours\n")?;
+            let pairs = tree_to_worktree(Some(&head), &index, &Pathspec::default(), false)?;
+            assert_eq!(summary(&pairs), [('M', "c")]);
+
+            fs::remove_file("c")?;
+            let pairs = tree_to_worktree(Some(&head), &index, &Pathspec::default(), false)?;
+            assert_eq!(summary(&pairs), [('D', "c")]);
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,194 @@
+//! Histogram diff: split the regions around the longest run of common lines whose rarest line
+//! is as rare as possible, recursing on both sides. Mirrors git's xhistogram.c.
+
+use std::collections::HashMap;
+
+use super::{myers, Records};
+
+/// Lines occurring more often than this are never used to anchor a split
+const MAX_CHAIN_LENGTH: usize = 64;
+
+/// Inclusive line ranges of a common run
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    begin1: usize,
+    end1: usize,
+    begin2: usize,
+    end2: usize,
+}
+
+enum Lcs {
+    Found(Region),
+    /// No common lines at all
+    None,
+    /// Common lines exist but all of them are too frequent
+    TooFrequent,
+}
+
+/// Marks changed lines in `old` and `new`
+pub(crate) fn diff(old: &mut Records, new: &mut Records, minimal: bool) {
+    let (len1, len2) = (old.len(), new.len());
+    histogram(old, new, (0, len1), (0, len2), minimal);
+}
+
+fn mark_all(records: &mut Records, start: usize, count: usize) {
+    for i in start..start + count {
+        records.set_changed(i, true);
+    }
+}
+
+fn histogram(
+    old: &mut Records,
+    new: &mut Records,
+    (mut line1, mut count1): (usize, usize),
+    (mut line2, mut count2): (usize, usize),
+    minimal: bool,
+) {
+    loop {
+        if count1 == 0 {
+            mark_all(new, line2, count2);
+            return;
+        }
+        if count2 == 0 {
+            mark_all(old, line1, count1);
+            return;
+        }
+        match find_lcs(old, new, (line1, count1), (line2, count2)) {
+            Lcs::TooFrequent => {
+                myers::diff_range(old, new, (line1, count1), (line2, count2), minimal);
+                return;
+            }
+            Lcs::None => {
+                mark_all(old, line1, count1);
+                mark_all(new, line2, count2);
+                return;
+            }
+            Lcs::Found(lcs) => {
+                histogram(
+                    old,
+                    new,
+                    (line1, lcs.begin1 - line1),
+                    (line2, lcs.begin2 - line2),
+                    minimal,
+                );
+                count1 = line1 + count1 - (lcs.end1 + 1);
+                line1 = lcs.end1 + 1;
+                count2 = line2 + count2 - (lcs.end2 + 1);
+                line2 = lcs.end2 + 1;
+            }
+        }
+    }
+}
+
+/// Occurrences of one line in the old region: the first position and how many there are
+struct Occurrences {
+    first: usize,
+    count: usize,
+}
+
+fn find_lcs(
+    old: &Records,
+    new: &Records,
+    (line1, count1): (usize, usize),
+    (line2, count2): (usize, usize),
+) -> Lcs {
+    let last1 = line1 + count1 - 1;
+    let last2 = line2 + count2 - 1;
+
+    // Index the old region; `next[i]` is the next position of the line at `line1 + i`
+    let mut occurrences: HashMap<usize, Occurrences> = HashMap::new();
+    let mut next: Vec<Option<usize>> = vec![None; count1];
+    for ptr in (line1..=last1).rev() {
+        match occurrences.get_mut(&old.ids[ptr]) {
+            Some(occurrence) => {
+                next[ptr - line1] = Some(occurrence.first);
+                occurrence.first = ptr;
+                occurrence.count += 1;
+            }
+            None => {
+                occurrences.insert(
+                    old.ids[ptr],
+                    Occurrences {
+                        first: ptr,
+                        count: 1,
+                    },
+                );
+            }
+        }
+    }
+    let count_of = |ptr: usize| occurrences[&old.ids[ptr]].count;
+
+    let mut lcs: Option<Region> = None;
+    let mut min_count = MAX_CHAIN_LENGTH + 1;
+    let mut has_common = false;
+    let mut b_ptr = line2;
+    while b_ptr <= last2 {
+        let mut b_next = b_ptr + 1;
+        if let Some(occurrence) = occurrences.get(&new.ids[b_ptr]) {
+            has_common = true;
+            if occurrence.count <= min_count {
+                let mut a_ptr = occurrence.first;
+                loop {
+                    let np = next[a_ptr - line1];
+                    let (mut as_, mut bs) = (a_ptr, b_ptr);
+                    let (mut ae, mut be) = (a_ptr, b_ptr);
+                    let mut rc = occurrence.count;
+                    while line1 < as_ && line2 < bs && old.ids[as_ - 1] == new.ids[bs - 1] {
+                        as_ -= 1;
+                        bs -= 1;
+                        if rc > 1 {
+                            rc = rc.min(count_of(as_));
+                        }
+                    }
+                    while ae < last1 && be < last2 && old.ids[ae + 1] == new.ids[be + 1] {
+                        ae += 1;
+                        be += 1;
+                        if rc > 1 {
+                            rc = rc.min(count_of(ae));
+                        }
+                    }
+
+                    if b_next <= be {
+                        b_next = be + 1;
+                    }
+                    let longest = lcs.map_or(0, |lcs| lcs.end1 - lcs.begin1);
+                    if longest < ae - as_ || rc < min_count {
+                        lcs = Some(Region {
+                            begin1: as_,
+                            end1: ae,
+                            begin2: bs,
+                            end2: be,
+                        });
+                        min_count = rc;
+                    }
+
+                    // Continue with the next occurrence past the run just measured
+                    let Some(mut np) = np else {
+                        break;
+                    };
+                    let mut exhausted = false;
+                    while np <= ae {
+                        match next[np - line1] {
+                            Some(n) => np = n,
+                            None => {
+                                exhausted = true;
+                                break;
+                            }
+                        }
+                    }
+                    if exhausted {
+                        break;
+                    }
+                    a_ptr = np;
+                }
+            }
+        }
+        b_ptr = b_next;
+    }
+
+    if has_common && MAX_CHAIN_LENGTH < min_count {
+        Lcs::TooFrequent
+    } else {
+        lcs.map_or(Lcs::None, Lcs::Found)
+    }
+}
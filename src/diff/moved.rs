@@ -0,0 +1,235 @@
+//! `--color-moved`: find blocks of removed lines that were added back elsewhere in the same
+//! diff (or the reverse) and mark them for coloring, following git's block matching.
+
+use std::collections::HashMap;
+
+use super::patch::{Symbol, SymbolKind, MOVED, MOVED_ALT, MOVED_DIM};
+
+/// Blocks with fewer alphanumeric characters than this are not worth pointing out
+const MIN_ALNUM_COUNT: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ColorMoved {
+    #[default]
+    No,
+    /// Every moved line, however short the block
+    Plain,
+    /// Moved blocks of at least 20 alphanumeric characters
+    Blocks,
+    /// Blocks, with adjacent blocks told apart by an alternate color
+    Zebra,
+    /// Zebra, with the inside of blocks dimmed so their edges stand out
+    DimmedZebra,
+}
+
+impl ColorMoved {
+    pub(crate) fn parse(name: &str) -> Option<ColorMoved> {
+        match name.to_ascii_lowercase().as_str() {
+            "no" | "false" | "off" => Some(ColorMoved::No),
+            "plain" => Some(ColorMoved::Plain),
+            "blocks" => Some(ColorMoved::Blocks),
+            "zebra" | "default" | "true" | "yes" | "on" => Some(ColorMoved::Zebra),
+            "dimmed-zebra" | "dimmed_zebra" => Some(ColorMoved::DimmedZebra),
+            _ => None,
+        }
+    }
+}
+
+fn is_moved_candidate(symbol: &Symbol) -> bool {
+    matches!(symbol.kind, SymbolKind::Plus | SymbolKind::Minus)
+}
+
+/// Added and removed lines, indexed for matching
+struct Entries {
+    /// Symbol index of each entry
+    symbols: Vec<usize>,
+    /// The entry for the following line, if it is the same kind with nothing in between
+    next_line: Vec<Option<usize>>,
+    /// Line id of each symbol; equal lines share an id
+    ids: Vec<usize>,
+    /// Entries of added and removed lines for each id
+    added: Vec<Vec<usize>>,
+    removed: Vec<Vec<usize>>,
+}
+
+impl Entries {
+    fn new(symbols: &[Symbol]) -> Entries {
+        let mut entries = Entries {
+            symbols: Vec::new(),
+            next_line: Vec::new(),
+            ids: vec![0; symbols.len()],
+            added: Vec::new(),
+            removed: Vec::new(),
+        };
+        let mut interned: HashMap<&[u8], usize> = HashMap::new();
+        let mut previous: Option<usize> = None;
+        for (n, symbol) in symbols.iter().enumerate() {
+            if !is_moved_candidate(symbol) {
+                previous = None;
+                continue;
+            }
+            let next_id = interned.len();
+            let id = *interned.entry(&symbol.line).or_insert(next_id);
+            if id == entries.added.len() {
+                entries.added.push(Vec::new());
+                entries.removed.push(Vec::new());
+            }
+            entries.ids[n] = id;
+
+            let entry = entries.symbols.len();
+            entries.symbols.push(n);
+            entries.next_line.push(None);
+            if let Some(previous) = previous {
+                if symbols[entries.symbols[previous]].kind == symbol.kind {
+                    entries.next_line[previous] = Some(entry);
+                }
+            }
+            previous = Some(entry);
+            match symbol.kind {
+                SymbolKind::Plus => entries.added[id].push(entry),
+                _ => entries.removed[id].push(entry),
+            }
+        }
+        entries
+    }
+}
+
+/// Clears the marks of the block that ends before `n` if it is too small to count as moved.
+/// Returns whether the block stays marked.
+fn adjust_last_block(
+    symbols: &mut [Symbol],
+    mode: ColorMoved,
+    n: usize,
+    block_length: usize,
+) -> bool {
+    if mode == ColorMoved::Plain {
+        return block_length > 0;
+    }
+    let block = &mut symbols[n - block_length..n];
+    let alnum = block
+        .iter()
+        .flat_map(|symbol| symbol.line.iter())
+        .filter(|b| b.is_ascii_alphanumeric())
+        .take(MIN_ALNUM_COUNT)
+        .count();
+    if alnum >= MIN_ALNUM_COUNT {
+        return true;
+    }
+    for symbol in block {
+        symbol.flags &= !(MOVED | MOVED_ALT);
+    }
+    false
+}
+
+/// Marks moved lines in the symbols of a whole diff
+pub(crate) fn mark(symbols: &mut [Symbol], mode: ColorMoved) {
+    if mode == ColorMoved::No {
+        return;
+    }
+    let entries = Entries::new(symbols);
+
+    // Potential moved blocks: for each, the entry matched by the last line
+    let mut blocks: Vec<usize> = Vec::new();
+    let mut flipped = false;
+    let mut block_length = 0;
+    let mut moved_kind: Option<SymbolKind> = None;
+    let mut n = 0;
+    while n < symbols.len() {
+        let kind = symbols[n].kind;
+        let id = entries.ids[n];
+        let mut matches: &[usize] = match kind {
+            SymbolKind::Plus => &entries.removed[id],
+            SymbolKind::Minus => &entries.added[id],
+            _ => {
+                flipped = false;
+                &[]
+            }
+        };
+
+        if !blocks.is_empty() && (matches.is_empty() || Some(kind) != moved_kind) {
+            if !adjust_last_block(symbols, mode, n, block_length) && block_length > 1 {
+                // Start over from the second line of the block, which may begin another match
+                matches = &[];
+                n -= block_length;
+            }
+            blocks.clear();
+            block_length = 0;
+            flipped = false;
+        }
+        if matches.is_empty() {
+            moved_kind = None;
+            n += 1;
+            continue;
+        }
+
+        if mode == ColorMoved::Plain {
+            symbols[n].flags |= MOVED;
+            n += 1;
+            continue;
+        }
+
+        blocks.retain_mut(|entry| match entries.next_line[*entry] {
+            Some(next) if entries.ids[entries.symbols[next]] == id => {
+                *entry = next;
+                true
+            }
+            _ => false,
+        });
+
+        if blocks.is_empty() {
+            let contiguous = adjust_last_block(symbols, mode, n, block_length);
+            if !contiguous && block_length > 1 {
+                n -= block_length;
+            } else {
+                blocks.extend_from_slice(matches);
+            }
+            flipped = contiguous && !blocks.is_empty() && moved_kind == Some(kind) && !flipped;
+            moved_kind = if blocks.is_empty() { None } else { Some(kind) };
+            block_length = 0;
+        }
+
+        if !blocks.is_empty() {
+            block_length += 1;
+            symbols[n].flags |= MOVED;
+            if flipped && mode != ColorMoved::Blocks {
+                symbols[n].flags |= MOVED_ALT;
+            }
+        }
+        n += 1;
+    }
+    adjust_last_block(symbols, mode, n, block_length);
+
+    if mode == ColorMoved::DimmedZebra {
+        dim_moved_lines(symbols);
+    }
+}
+
+/// Dims moved lines that are not at the edge of a block
+fn dim_moved_lines(symbols: &mut [Symbol]) {
+    let zebra = |symbol: &Symbol| symbol.flags & (MOVED | MOVED_ALT);
+    for n in 0..symbols.len() {
+        let line = &symbols[n];
+        if !is_moved_candidate(line) || line.flags & MOVED == 0 {
+            continue;
+        }
+        let prev = n
+            .checked_sub(1)
+            .map(|p| &symbols[p])
+            .filter(|prev| is_moved_candidate(prev));
+        let next = symbols.get(n + 1).filter(|next| is_moved_candidate(next));
+
+        let inside = prev.is_some_and(|prev| zebra(prev) == zebra(line))
+            && next.is_some_and(|next| zebra(next) == zebra(line));
+        if !inside {
+            let boundary = |other: Option<&Symbol>| {
+                other.is_some_and(|other| {
+                    other.flags & MOVED != 0 && other.flags & MOVED_ALT != line.flags & MOVED_ALT
+                })
+            };
+            if boundary(prev) || boundary(next) {
+                continue;
+            }
+        }
+        symbols[n].flags |= MOVED_DIM;
+    }
+}
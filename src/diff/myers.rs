@@ -0,0 +1,556 @@
+//! Myers' O(ND) algorithm in linear space, following the structure of git's xdiff so that the
+//! same edit script (and therefore the same hunks) is produced.
+
+use super::Records;
+
+const MAX_EQLIMIT: usize = 1024;
+const SIMSCAN_WINDOW: usize = 100;
+const KPDIS_RUN: usize = 4;
+const MAX_COST_MIN: i64 = 256;
+const HEUR_MIN_COST: i64 = 256;
+const SNAKE_CNT: i64 = 20;
+const K_HEUR: i64 = 4;
+
+/// Marks changed lines in `old` and `new`. With `minimal`, the cost-limiting heuristics used for
+/// large inputs are disabled.
+pub(crate) fn diff(old: &mut Records, new: &mut Records, minimal: bool) {
+    // Common prefix and suffix never change
+    let lim = old.len().min(new.len());
+    let start = (0..lim).find(|&i| old.ids[i] != new.ids[i]).unwrap_or(lim);
+    let suffix = (0..lim - start)
+        .find(|&i| old.ids[old.len() - 1 - i] != new.ids[new.len() - 1 - i])
+        .unwrap_or(lim - start);
+    let old_end = old.len() - suffix;
+    let new_end = new.len() - suffix;
+
+    // Lines without any match on the other side are changed outright and kept out of the search
+    let (old_index, new_index) = cleanup_records(old, new, start, old_end, new_end);
+    let ha1: Vec<usize> = old_index.iter().map(|&i| old.ids[i]).collect();
+    let ha2: Vec<usize> = new_index.iter().map(|&i| new.ids[i]).collect();
+
+    let ndiags = (ha1.len() + ha2.len() + 3) as i64;
+    let mut ctx = Context {
+        ha1: &ha1,
+        ha2: &ha2,
+        kvdf: vec![0; ndiags as usize * 2],
+        kvdb: vec![0; ndiags as usize * 2],
+        offset: ha2.len() as i64 + 1,
+        mxcost: if minimal {
+            i64::MAX
+        } else {
+            (bogosqrt(ndiags as usize) as i64).max(MAX_COST_MIN)
+        },
+    };
+    let mut changed1 = vec![false; ha1.len()];
+    let mut changed2 = vec![false; ha2.len()];
+    ctx.compare(
+        0,
+        ha1.len() as i64,
+        0,
+        ha2.len() as i64,
+        minimal,
+        &mut changed1,
+        &mut changed2,
+    );
+    for (i, changed) in changed1.into_iter().enumerate() {
+        if changed {
+            old.set_changed(old_index[i], true);
+        }
+    }
+    for (i, changed) in changed2.into_iter().enumerate() {
+        if changed {
+            new.set_changed(new_index[i], true);
+        }
+    }
+}
+
+/// Diffs `count` lines from `start` on each side as if they were whole files of their own, the
+/// way patience and histogram diff fall back to Myers for regions they cannot split
+pub(crate) fn diff_range(
+    old: &mut Records,
+    new: &mut Records,
+    (start1, count1): (usize, usize),
+    (start2, count2): (usize, usize),
+    minimal: bool,
+) {
+    fn sub_records<'a>(records: &Records<'a>, start: usize, count: usize) -> Records<'a> {
+        Records::new(
+            records.lines[start..start + count].to_vec(),
+            records.ids[start..start + count].to_vec(),
+        )
+    }
+    let mut sub_old = sub_records(old, start1, count1);
+    let mut sub_new = sub_records(new, start2, count2);
+    diff(&mut sub_old, &mut sub_new, minimal);
+    for i in 0..count1 {
+        old.set_changed(start1 + i, sub_old.is_changed(i as i64));
+    }
+    for i in 0..count2 {
+        new.set_changed(start2 + i, sub_new.is_changed(i as i64));
+    }
+}
+
+fn bogosqrt(mut n: usize) -> usize {
+    let mut i = 1;
+    while n > 0 {
+        i <<= 1;
+        n >>= 2;
+    }
+    i
+}
+
+/// Returns the indices of the lines that take part in the search, marking the others changed
+fn cleanup_records(
+    old: &mut Records,
+    new: &mut Records,
+    start: usize,
+    old_end: usize,
+    new_end: usize,
+) -> (Vec<usize>, Vec<usize>) {
+    let classify = |records: &Records, other_counts: &[usize], end: usize| -> Vec<u8> {
+        let mlim = bogosqrt(records.len()).min(MAX_EQLIMIT);
+        let mut dis = vec![0u8; records.len() + 1];
+        for (dis, &id) in dis[start..end].iter_mut().zip(&records.ids[start..end]) {
+            let nm = other_counts.get(id).copied().unwrap_or(0);
+            *dis = if nm == 0 {
+                0
+            } else if nm >= mlim {
+                2
+            } else {
+                1
+            };
+        }
+        dis
+    };
+    let old_counts = old.counts();
+    let new_counts = new.counts();
+    let dis1 = classify(old, &new_counts, old_end);
+    let dis2 = classify(new, &old_counts, new_end);
+
+    let keep = |records: &mut Records, dis: &[u8], end: usize| -> Vec<usize> {
+        let mut index = Vec::new();
+        for i in start..end {
+            if dis[i] == 1 || (dis[i] == 2 && !clean_mmatch(dis, i, start, end - 1)) {
+                index.push(i);
+            } else {
+                records.set_changed(i, true);
+            }
+        }
+        index
+    };
+    let old_index = keep(old, &dis1, old_end);
+    let new_index = keep(new, &dis2, new_end);
+    (old_index, new_index)
+}
+
+/// Whether a line with many matches sits in a run of unmatched lines and can be discarded
+fn clean_mmatch(dis: &[u8], i: usize, s: usize, e: usize) -> bool {
+    if e < s {
+        return false;
+    }
+    let s = if i - s > SIMSCAN_WINDOW {
+        i - SIMSCAN_WINDOW
+    } else {
+        s
+    };
+    let e = if e - i > SIMSCAN_WINDOW {
+        i + SIMSCAN_WINDOW
+    } else {
+        e
+    };
+
+    let (mut rdis0, mut rpdis0) = (0, 1);
+    let mut r = 1;
+    while i >= s + r {
+        match dis[i - r] {
+            0 => rdis0 += 1,
+            2 => rpdis0 += 1,
+            _ => break,
+        }
+        r += 1;
+    }
+    if rdis0 == 0 {
+        return false;
+    }
+    let (mut rdis1, mut rpdis1) = (0, 1);
+    let mut r = 1;
+    while i + r <= e {
+        match dis[i + r] {
+            0 => rdis1 += 1,
+            2 => rpdis1 += 1,
+            _ => break,
+        }
+        r += 1;
+    }
+    if rdis1 == 0 {
+        return false;
+    }
+    rdis1 += rdis0;
+    rpdis1 += rpdis0;
+    rpdis1 * KPDIS_RUN < rpdis1 + rdis1
+}
+
+struct Context<'a> {
+    ha1: &'a [usize],
+    ha2: &'a [usize],
+    kvdf: Vec<i64>,
+    kvdb: Vec<i64>,
+    /// Diagonal `d` is stored at `d + offset`
+    offset: i64,
+    mxcost: i64,
+}
+
+struct Split {
+    i1: i64,
+    i2: i64,
+    min_lo: bool,
+    min_hi: bool,
+}
+
+impl Context<'_> {
+    fn kf(&self, d: i64) -> i64 {
+        self.kvdf[(d + self.offset) as usize]
+    }
+
+    fn kb(&self, d: i64) -> i64 {
+        self.kvdb[(d + self.offset) as usize]
+    }
+
+    fn set_kf(&mut self, d: i64, value: i64) {
+        self.kvdf[(d + self.offset) as usize] = value;
+    }
+
+    fn set_kb(&mut self, d: i64, value: i64) {
+        self.kvdb[(d + self.offset) as usize] = value;
+    }
+
+    fn eq(&self, i1: i64, i2: i64) -> bool {
+        self.ha1[i1 as usize] == self.ha2[i2 as usize]
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn compare(
+        &mut self,
+        mut off1: i64,
+        mut lim1: i64,
+        mut off2: i64,
+        mut lim2: i64,
+        need_min: bool,
+        changed1: &mut [bool],
+        changed2: &mut [bool],
+    ) {
+        while off1 < lim1 && off2 < lim2 && self.eq(off1, off2) {
+            off1 += 1;
+            off2 += 1;
+        }
+        while off1 < lim1 && off2 < lim2 && self.eq(lim1 - 1, lim2 - 1) {
+            lim1 -= 1;
+            lim2 -= 1;
+        }
+        if off1 == lim1 {
+            for i in off2..lim2 {
+                changed2[i as usize] = true;
+            }
+        } else if off2 == lim2 {
+            for i in off1..lim1 {
+                changed1[i as usize] = true;
+            }
+        } else {
+            let split = self.split(off1, lim1, off2, lim2, need_min);
+            self.compare(
+                off1,
+                split.i1,
+                off2,
+                split.i2,
+                split.min_lo,
+                changed1,
+                changed2,
+            );
+            self.compare(
+                split.i1,
+                lim1,
+                split.i2,
+                lim2,
+                split.min_hi,
+                changed1,
+                changed2,
+            );
+        }
+    }
+
+    /// Finds the middle snake of the box, or a good enough split point when the search gets
+    /// too expensive
+    fn split(&mut self, off1: i64, lim1: i64, off2: i64, lim2: i64, need_min: bool) -> Split {
+        let dmin = off1 - lim2;
+        let dmax = lim1 - off2;
+        let fmid = off1 - off2;
+        let bmid = lim1 - lim2;
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+
+        self.set_kf(fmid, off1);
+        self.set_kb(bmid, lim1);
+
+        let mut ec = 1;
+        loop {
+            let mut got_snake = false;
+
+            if fmin > dmin {
+                fmin -= 1;
+                self.set_kf(fmin - 1, -1);
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                self.set_kf(fmax + 1, -1);
+            } else {
+                fmax -= 1;
+            }
+
+            let mut d = fmax;
+            while d >= fmin {
+                let mut i1 = if self.kf(d - 1) >= self.kf(d + 1) {
+                    self.kf(d - 1) + 1
+                } else {
+                    self.kf(d + 1)
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && self.eq(i1, i2) {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - prev1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                self.set_kf(d, i1);
+                if odd && bmin <= d && d <= bmax && self.kb(d) <= i1 {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+                d -= 2;
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                self.set_kb(bmin - 1, i64::MAX);
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                self.set_kb(bmax + 1, i64::MAX);
+            } else {
+                bmax -= 1;
+            }
+
+            let mut d = bmax;
+            while d >= bmin {
+                let mut i1 = if self.kb(d - 1) < self.kb(d + 1) {
+                    self.kb(d - 1)
+                } else {
+                    self.kb(d + 1) - 1
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && self.eq(i1 - 1, i2 - 1) {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if prev1 - i1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                self.set_kb(d, i1);
+                if !odd && fmin <= d && d <= fmax && i1 <= self.kf(d) {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+                d -= 2;
+            }
+
+            if need_min {
+                ec += 1;
+                continue;
+            }
+
+            if got_snake && ec > HEUR_MIN_COST {
+                if let Some(split) =
+                    self.forward_heuristic(off1, lim1, off2, lim2, fmin, fmax, fmid, ec)
+                {
+                    return split;
+                }
+                if let Some(split) =
+                    self.backward_heuristic(off1, lim1, off2, lim2, bmin, bmax, bmid, ec)
+                {
+                    return split;
+                }
+            }
+
+            if ec >= self.mxcost {
+                return self.furthest_reaching(off1, lim1, off2, lim2, (fmin, fmax), (bmin, bmax));
+            }
+            ec += 1;
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn forward_heuristic(
+        &self,
+        off1: i64,
+        lim1: i64,
+        off2: i64,
+        lim2: i64,
+        fmin: i64,
+        fmax: i64,
+        fmid: i64,
+        ec: i64,
+    ) -> Option<Split> {
+        let mut best = 0;
+        let mut split = None;
+        let mut d = fmax;
+        while d >= fmin {
+            let dd = (d - fmid).abs();
+            let i1 = self.kf(d);
+            let i2 = i1 - d;
+            let v = (i1 - off1) + (i2 - off2) - dd;
+            if v > K_HEUR * ec
+                && v > best
+                && off1 + SNAKE_CNT <= i1
+                && i1 < lim1
+                && off2 + SNAKE_CNT <= i2
+                && i2 < lim2
+            {
+                let mut k = 1;
+                while self.eq(i1 - k, i2 - k) {
+                    if k == SNAKE_CNT {
+                        best = v;
+                        split = Some((i1, i2));
+                        break;
+                    }
+                    k += 1;
+                }
+            }
+            d -= 2;
+        }
+        split.map(|(i1, i2)| Split {
+            i1,
+            i2,
+            min_lo: true,
+            min_hi: false,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn backward_heuristic(
+        &self,
+        off1: i64,
+        lim1: i64,
+        off2: i64,
+        lim2: i64,
+        bmin: i64,
+        bmax: i64,
+        bmid: i64,
+        ec: i64,
+    ) -> Option<Split> {
+        let mut best = 0;
+        let mut split = None;
+        let mut d = bmax;
+        while d >= bmin {
+            let dd = (d - bmid).abs();
+            let i1 = self.kb(d);
+            let i2 = i1 - d;
+            let v = (lim1 - i1) + (lim2 - i2) - dd;
+            if v > K_HEUR * ec
+                && v > best
+                && off1 < i1
+                && i1 <= lim1 - SNAKE_CNT
+                && off2 < i2
+                && i2 <= lim2 - SNAKE_CNT
+            {
+                let mut k = 0;
+                while self.eq(i1 + k, i2 + k) {
+                    if k == SNAKE_CNT - 1 {
+                        best = v;
+                        split = Some((i1, i2));
+                        break;
+                    }
+                    k += 1;
+                }
+            }
+            d -= 2;
+        }
+        split.map(|(i1, i2)| Split {
+            i1,
+            i2,
+            min_lo: false,
+            min_hi: true,
+        })
+    }
+
+    /// Gives up on finding the optimal split and takes the furthest reaching path
+    fn furthest_reaching(
+        &self,
+        off1: i64,
+        lim1: i64,
+        off2: i64,
+        lim2: i64,
+        (fmin, fmax): (i64, i64),
+        (bmin, bmax): (i64, i64),
+    ) -> Split {
+        let (mut fbest, mut fbest1) = (-1, -1);
+        let mut d = fmax;
+        while d >= fmin {
+            let mut i1 = self.kf(d).min(lim1);
+            let mut i2 = i1 - d;
+            if lim2 < i2 {
+                i1 = lim2 + d;
+                i2 = lim2;
+            }
+            if fbest < i1 + i2 {
+                fbest = i1 + i2;
+                fbest1 = i1;
+            }
+            d -= 2;
+        }
+        let (mut bbest, mut bbest1) = (i64::MAX, i64::MAX);
+        let mut d = bmax;
+        while d >= bmin {
+            let mut i1 = off1.max(self.kb(d));
+            let mut i2 = i1 - d;
+            if i2 < off2 {
+                i1 = off2 + d;
+                i2 = off2;
+            }
+            if i1 + i2 < bbest {
+                bbest = i1 + i2;
+                bbest1 = i1;
+            }
+            d -= 2;
+        }
+        if (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+            Split {
+                i1: fbest1,
+                i2: fbest - fbest1,
+                min_lo: true,
+                min_hi: false,
+            }
+        } else {
+            Split {
+                i1: bbest1,
+                i2: bbest - bbest1,
+                min_lo: false,
+                min_hi: true,
+            }
+        }
+    }
+}
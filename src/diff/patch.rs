@@ -0,0 +1,962 @@
+//! Git-style patch output: `diff --git` headers, unified hunks and diffstats.
+
+use std::io::Write;
+
+use crate::{
+    diff::{
+        binary,
+        color::{self, emit_line, ws_check_emit, Palette},
+        diff_lines,
+        moved::{self, ColorMoved},
+        rename, split_lines,
+        words::{self, WordDiff, WordDiffMode},
+        Change, DiffOptions,
+    },
+    object::{read::read_object_of_kind, ObjectKind, MODE_GITLINK},
+    quote,
+};
+
+pub(crate) const NULL_HASH: [u8; 20] = [0; 20];
+
+/// One side of a file pair
+#[derive(Debug, Clone)]
+pub(crate) struct FileSide {
+    pub(crate) path: String,
+    pub(crate) mode: u32,
+    pub(crate) hash: [u8; 20],
+    /// Content is read from the working tree instead of the object database
+    pub(crate) worktree: bool,
+}
+
+impl FileSide {
+    pub(crate) fn from_object(path: &str, mode: u32, hash: [u8; 20]) -> FileSide {
+        FileSide {
+            path: path.to_string(),
+            mode,
+            hash,
+            worktree: false,
+        }
+    }
+
+    pub(crate) fn content(&self) -> anyhow::Result<Vec<u8>> {
+        if self.mode == MODE_GITLINK {
+            Ok(format!("Subproject commit {}\n", hex::encode(self.hash)).into_bytes())
+        } else if self.worktree {
+            crate::worktree::read_file(std::path::Path::new(&self.path))
+        } else {
+            read_object_of_kind(&hex::encode(self.hash), ObjectKind::Blob)
+        }
+    }
+}
+
+/// A changed path: `old` is missing for additions and `new` for deletions
+#[derive(Debug, Clone)]
+pub(crate) struct FilePair {
+    pub(crate) old: Option<FileSide>,
+    pub(crate) new: Option<FileSide>,
+    /// Set when `new` was detected as a rename or copy of `old`
+    pub(crate) rename: Option<Rename>,
+}
+
+/// How a renamed or copied file relates to its source
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rename {
+    /// Similarity out of [`rename::MAX_SCORE`]
+    pub(crate) score: u32,
+    /// The source still exists, so this is a copy
+    pub(crate) copy: bool,
+}
+
+impl FilePair {
+    pub(crate) fn new(old: Option<FileSide>, new: Option<FileSide>) -> FilePair {
+        FilePair {
+            old,
+            new,
+            rename: None,
+        }
+    }
+
+    pub(crate) fn status(&self) -> char {
+        match (&self.old, &self.new) {
+            _ if self.rename.is_some_and(|rename| rename.copy) => 'C',
+            _ if self.rename.is_some() => 'R',
+            (None, _) => 'A',
+            (_, None) => 'D',
+            (Some(old), Some(new)) if old.mode >> 12 != new.mode >> 12 => 'T',
+            _ => 'M',
+        }
+    }
+
+    /// Path shown for the pair, the new path if there is one
+    pub(crate) fn path(&self) -> &str {
+        match (&self.old, &self.new) {
+            (_, Some(new)) => &new.path,
+            (Some(old), None) => &old.path,
+            (None, None) => "",
+        }
+    }
+
+    /// Similarity percentage of a rename or copy
+    pub(crate) fn similarity(&self) -> Option<u32> {
+        self.rename
+            .map(|rename| rename.score * 100 / rename::MAX_SCORE)
+    }
+
+    /// Status letter, followed by the similarity for renames and copies as in `R087`
+    pub(crate) fn status_with_score(&self) -> String {
+        match self.similarity() {
+            Some(similarity) => format!("{}{similarity:03}", self.status()),
+            None => self.status().to_string(),
+        }
+    }
+
+    /// Name shown in diffstats: `old => new` for renames, with the common leading directories
+    /// and trailing path components factored out as in `src/{a.rs => b.rs}`
+    pub(crate) fn display_name(&self) -> String {
+        match (&self.old, &self.new, self.rename) {
+            (Some(old), Some(new), Some(_)) => rename_name(&old.path, &new.path),
+            _ => quote::c_style(self.path(), false),
+        }
+    }
+}
+
+fn rename_name(a: &str, b: &str) -> String {
+    let quoted_a = quote::c_style(a, false);
+    let quoted_b = quote::c_style(b, false);
+    if quoted_a != a || quoted_b != b {
+        return format!("{quoted_a} => {quoted_b}");
+    }
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+    // Walk back from the terminating NUL; with a common prefix the walk may reach its slash
+    let at = |s: &[u8], i: usize| s.get(i).copied().unwrap_or(0);
+    let floor = prefix.saturating_sub(1);
+    let (mut i, mut j) = (a.len(), b.len());
+    let mut suffix = 0;
+    loop {
+        if at(a, i) != at(b, j) {
+            break;
+        }
+        if at(a, i) == b'/' {
+            suffix = a.len() - i;
+        }
+        if i == floor || j == floor {
+            break;
+        }
+        i -= 1;
+        j -= 1;
+    }
+    let a_mid = a.len().saturating_sub(prefix + suffix);
+    let b_mid = b.len().saturating_sub(prefix + suffix);
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+    let mut name = String::new();
+    if prefix + suffix > 0 {
+        name.push_str(&text(&a[..prefix]));
+        name.push('{');
+    }
+    name.push_str(&text(&a[prefix..prefix + a_mid]));
+    name.push_str(" => ");
+    name.push_str(&text(&b[prefix..prefix + b_mid]));
+    if prefix + suffix > 0 {
+        name.push('}');
+        name.push_str(&text(&a[a.len() - suffix..]));
+    }
+    name
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PatchOptions {
+    /// Lines of context around each hunk
+    pub(crate) context: usize,
+    pub(crate) diff: DiffOptions,
+    pub(crate) color: bool,
+    pub(crate) word_diff: Option<WordDiff>,
+    pub(crate) color_moved: ColorMoved,
+    /// Write binary files as patches that can be applied, with full object names
+    pub(crate) binary: bool,
+    /// Columns a `--stat` diffstat is laid out for
+    pub(crate) stat_width: usize,
+}
+
+impl Default for PatchOptions {
+    fn default() -> PatchOptions {
+        PatchOptions {
+            context: 3,
+            diff: DiffOptions::default(),
+            color: false,
+            word_diff: None,
+            color_moved: ColorMoved::No,
+            binary: false,
+            stat_width: 80,
+        }
+    }
+}
+
+/// Abbreviated object name as shown on `index` lines
+pub(crate) fn abbrev(hash: &[u8; 20]) -> String {
+    hex::encode(hash)[..7].to_string()
+}
+
+/// Git treats content with a NUL byte in the first 8000 bytes as binary
+pub(crate) fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(8000)].contains(&0)
+}
+
+fn quoted(prefix: &str, path: &str) -> String {
+    quote::c_style(&format!("{prefix}{path}"), false)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SymbolKind {
+    /// Extended header lines such as `diff --git` and `index`
+    Meta,
+    /// `---` and `+++` lines
+    FilePair,
+    /// Uncolored lines such as `Binary files ... differ`
+    Header,
+    /// `@@ ... @@` hunk headers
+    Frag,
+    Context,
+    Minus,
+    Plus,
+    /// `\ No newline at end of file`, following the last line if it lacks one
+    NoNewline,
+    /// Rendered `--word-diff` output
+    Words,
+    /// A context line in `--word-diff` mode
+    WordsContext,
+}
+
+/// Line flags for moved-line and whitespace coloring
+pub(crate) const MOVED: u8 = 1;
+pub(crate) const MOVED_ALT: u8 = 2;
+pub(crate) const MOVED_DIM: u8 = 4;
+const BLANK_AT_EOF: u8 = 8;
+
+/// One line of patch output. Content lines keep their newline but not their `+`/`-`/` `.
+#[derive(Debug, Clone)]
+pub(crate) struct Symbol {
+    pub(crate) kind: SymbolKind,
+    pub(crate) line: Vec<u8>,
+    pub(crate) flags: u8,
+}
+
+/// Writes patches for file pairs. With `--color-moved` the output is held back until
+/// [`PatchWriter::finish`], as moved lines are matched across the whole diff.
+pub(crate) struct PatchWriter<'a, W: Write> {
+    out: &'a mut W,
+    opts: &'a PatchOptions,
+    palette: Palette,
+    pending: Option<Vec<Symbol>>,
+}
+
+impl<'a, W: Write> PatchWriter<'a, W> {
+    pub(crate) fn new(out: &'a mut W, opts: &'a PatchOptions) -> PatchWriter<'a, W> {
+        PatchWriter {
+            out,
+            opts,
+            palette: Palette::new(opts.color),
+            pending: (opts.color_moved != ColorMoved::No).then(Vec::new),
+        }
+    }
+
+    /// Writes the patch for one file pair. A pair whose type changed (file to symlink, say) is
+    /// written as a deletion followed by an addition, like git does.
+    pub(crate) fn write(&mut self, pair: &FilePair) -> anyhow::Result<()> {
+        if pair.status() == 'T' {
+            let deletion = FilePair::new(pair.old.clone(), None);
+            let addition = FilePair::new(None, pair.new.clone());
+            self.write(&deletion)?;
+            return self.write(&addition);
+        }
+
+        let old_path = pair
+            .old
+            .as_ref()
+            .map_or(pair.path(), |old| old.path.as_str());
+        let new_path = pair
+            .new
+            .as_ref()
+            .map_or(pair.path(), |new| new.path.as_str());
+        self.meta(format!(
+            "diff --git {} {}",
+            quoted("a/", old_path),
+            quoted("b/", new_path)
+        ))?;
+
+        let old_hash = pair.old.as_ref().map_or(NULL_HASH, |old| old.hash);
+        let new_hash = pair.new.as_ref().map_or(NULL_HASH, |new| new.hash);
+        match (&pair.old, &pair.new) {
+            (None, Some(new)) => self.meta(format!("new file mode {:06o}", new.mode))?,
+            (Some(old), None) => self.meta(format!("deleted file mode {:06o}", old.mode))?,
+            (Some(old), Some(new)) if old.mode != new.mode => {
+                self.meta(format!("old mode {:06o}", old.mode))?;
+                self.meta(format!("new mode {:06o}", new.mode))?;
+            }
+            _ => {}
+        }
+        if let (Some(old), Some(new), Some(similarity)) = (&pair.old, &pair.new, pair.similarity())
+        {
+            let kind = if pair.status() == 'C' {
+                "copy"
+            } else {
+                "rename"
+            };
+            self.meta(format!("similarity index {similarity}%"))?;
+            self.meta(format!("{kind} from {}", quote::c_style(&old.path, false)))?;
+            self.meta(format!("{kind} to {}", quote::c_style(&new.path, false)))?;
+        }
+        if old_hash == new_hash {
+            return Ok(());
+        }
+        let old_content = match &pair.old {
+            Some(old) => old.content()?,
+            None => Vec::new(),
+        };
+        let new_content = match &pair.new {
+            Some(new) => new.content()?,
+            None => Vec::new(),
+        };
+        let binary = is_binary(&old_content) || is_binary(&new_content);
+
+        // Binary patches name the objects in full, as applying them checks the old content
+        let mut index = if binary && self.opts.binary {
+            format!("index {}..{}", hex::encode(old_hash), hex::encode(new_hash))
+        } else {
+            format!("index {}..{}", abbrev(&old_hash), abbrev(&new_hash))
+        };
+        if let (Some(old), Some(new)) = (&pair.old, &pair.new) {
+            if old.mode == new.mode {
+                index.push_str(&format!(" {:06o}", old.mode));
+            }
+        }
+        self.meta(index)?;
+        let old_name = if pair.old.is_some() {
+            quoted("a/", old_path)
+        } else {
+            String::from("/dev/null")
+        };
+        let new_name = if pair.new.is_some() {
+            quoted("b/", new_path)
+        } else {
+            String::from("/dev/null")
+        };
+
+        if binary && self.opts.binary {
+            let mut body = Vec::new();
+            binary::write_patch(&mut body, &old_content, &new_content)?;
+            return Ok(self.emit(SymbolKind::Header, body, 0)?);
+        }
+        if binary {
+            self.emit(
+                SymbolKind::Header,
+                format!("Binary files {old_name} and {new_name} differ\n"),
+                0,
+            )?;
+            return Ok(());
+        }
+        if old_content.is_empty() && new_content.is_empty() {
+            return Ok(());
+        }
+        self.emit(SymbolKind::FilePair, format!("--- {old_name}"), 0)?;
+        self.emit(SymbolKind::FilePair, format!("+++ {new_name}"), 0)?;
+        self.write_hunks(&old_content, &new_content)?;
+        Ok(())
+    }
+
+    /// Writes anything held back for `--color-moved`
+    pub(crate) fn finish(mut self) -> std::io::Result<()> {
+        if let Some(mut symbols) = self.pending.take() {
+            moved::mark(&mut symbols, self.opts.color_moved);
+            for symbol in &symbols {
+                self.write_symbol(symbol)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn meta(&mut self, line: String) -> std::io::Result<()> {
+        self.emit(SymbolKind::Meta, line, 0)
+    }
+
+    fn emit(
+        &mut self,
+        kind: SymbolKind,
+        line: impl Into<Vec<u8>>,
+        flags: u8,
+    ) -> std::io::Result<()> {
+        let symbol = Symbol {
+            kind,
+            line: line.into(),
+            flags,
+        };
+        match &mut self.pending {
+            Some(pending) => {
+                pending.push(symbol);
+                Ok(())
+            }
+            None => self.write_symbol(&symbol),
+        }
+    }
+
+    fn write_symbol(&mut self, symbol: &Symbol) -> std::io::Result<()> {
+        let palette = self.palette;
+        let out = &mut *self.out;
+        let line = symbol.line.as_slice();
+        match symbol.kind {
+            SymbolKind::Meta => {
+                out.write_all(palette.meta.as_bytes())?;
+                out.write_all(line)?;
+                writeln!(out, "{}", palette.reset)?;
+            }
+            SymbolKind::FilePair => {
+                out.write_all(palette.meta.as_bytes())?;
+                out.write_all(line)?;
+                out.write_all(palette.reset.as_bytes())?;
+                // Names with spaces get a trailing tab so they can be told apart from a date
+                if line[4..].contains(&b' ') {
+                    out.write_all(b"\t")?;
+                }
+                out.write_all(b"\n")?;
+            }
+            SymbolKind::Header | SymbolKind::Words => out.write_all(line)?,
+            SymbolKind::Frag => {
+                // `@@ -a,b +c,d @@` in one color and the function name in another
+                let end = line[2..]
+                    .windows(2)
+                    .position(|w| w == b"@@")
+                    .map_or(line.len(), |at| at + 4);
+                out.write_all(palette.frag.as_bytes())?;
+                out.write_all(&line[..end])?;
+                out.write_all(palette.reset.as_bytes())?;
+                let rest = &line[end..];
+                let blanks = rest
+                    .iter()
+                    .take_while(|&&b| b == b' ' || b == b'\t')
+                    .count();
+                if blanks > 0 {
+                    out.write_all(palette.context.as_bytes())?;
+                    out.write_all(&rest[..blanks])?;
+                    out.write_all(palette.reset.as_bytes())?;
+                }
+                if blanks < rest.len() {
+                    out.write_all(palette.func.as_bytes())?;
+                    out.write_all(&rest[blanks..])?;
+                    out.write_all(palette.reset.as_bytes())?;
+                }
+                out.write_all(b"\n")?;
+            }
+            SymbolKind::Context => emit_line(
+                out,
+                Some(palette.context),
+                None,
+                palette.reset,
+                Some(b' '),
+                line,
+            )?,
+            SymbolKind::Minus => {
+                let set = match symbol.flags & (MOVED | MOVED_ALT | MOVED_DIM) {
+                    f if f == MOVED | MOVED_ALT | MOVED_DIM => palette.old_moved_alt_dim,
+                    f if f == MOVED | MOVED_ALT => palette.old_moved_alt,
+                    f if f == MOVED | MOVED_DIM => palette.old_moved_dim,
+                    MOVED => palette.old_moved,
+                    _ => palette.old,
+                };
+                emit_line(out, Some(set), None, palette.reset, Some(b'-'), line)?;
+            }
+            SymbolKind::Plus => {
+                let set = match symbol.flags & (MOVED | MOVED_ALT | MOVED_DIM) {
+                    f if f == MOVED | MOVED_ALT | MOVED_DIM => palette.new_moved_alt_dim,
+                    f if f == MOVED | MOVED_ALT => palette.new_moved_alt,
+                    f if f == MOVED | MOVED_DIM => palette.new_moved_dim,
+                    MOVED => palette.new_moved,
+                    _ => palette.new,
+                };
+                let ws = palette.whitespace;
+                if ws.is_empty() {
+                    emit_line(out, Some(set), None, palette.reset, Some(b'+'), line)?;
+                } else if symbol.flags & BLANK_AT_EOF != 0 {
+                    emit_line(out, Some(ws), None, palette.reset, Some(b'+'), line)?;
+                } else {
+                    emit_line(out, Some(set), None, palette.reset, Some(b'+'), b"")?;
+                    ws_check_emit(out, line, set, palette.reset, ws)?;
+                }
+            }
+            SymbolKind::NoNewline => {
+                emit_line(out, Some(palette.context), None, palette.reset, None, line)?
+            }
+            SymbolKind::WordsContext => {
+                let porcelain = self
+                    .opts
+                    .word_diff
+                    .as_ref()
+                    .is_some_and(|word_diff| word_diff.mode == WordDiffMode::Porcelain);
+                if porcelain {
+                    emit_line(out, Some(palette.context), None, palette.reset, None, line)?;
+                    out.write_all(b"~\n")?;
+                } else {
+                    let line = match line.first() {
+                        Some(b'\n') => line,
+                        _ => &line[1..],
+                    };
+                    emit_line(out, Some(palette.context), None, palette.reset, None, line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the unified diff hunks between two buffers
+    fn write_hunks(&mut self, old: &[u8], new: &[u8]) -> std::io::Result<()> {
+        let context = self.opts.context;
+        let old_lines = split_lines(old);
+        let new_lines = split_lines(new);
+        let changes = diff_lines(&old_lines, &new_lines, &self.opts.diff);
+        let blank_at_eof = color::blank_at_eof(old, new);
+
+        for hunk in group_hunks(&changes, context) {
+            let (s1, e1, s2, e2) = hunk_range(hunk, context, old_lines.len(), new_lines.len());
+            let mut header = format!(
+                "@@ -{} +{} @@",
+                range_header(s1, e1 - s1),
+                range_header(s2, e2 - s2)
+            )
+            .into_bytes();
+            if let Some(func) = function_line(&old_lines, s1) {
+                header.push(b' ');
+                header.extend_from_slice(func);
+            }
+            self.emit(SymbolKind::Frag, header, 0)?;
+
+            // Line numbers as git tracks them for blank-at-eof, one past the current line
+            let mut lno_old = if e1 > s1 { s1 + 1 } else { s1 };
+            let mut lno_new = if e2 > s2 { s2 + 1 } else { s2 };
+            let mut minus = Vec::new();
+            let mut plus = Vec::new();
+            for (sign, line) in hunk_lines(&old_lines, &new_lines, hunk, e2, s2) {
+                // A last line without a newline is shown with one, followed by a marker line
+                let incomplete = !line.ends_with(b"\n");
+                let line = [line, if incomplete { b"\n" } else { b"" }].concat();
+                if self.opts.word_diff.is_some() {
+                    match sign {
+                        b'-' => minus.extend_from_slice(&line),
+                        b'+' => plus.extend_from_slice(&line),
+                        _ => {
+                            self.flush_words(&mut minus, &mut plus)?;
+                            self.emit(SymbolKind::WordsContext, [b" ", &line[..]].concat(), 0)?;
+                        }
+                    }
+                    continue;
+                }
+                match sign {
+                    b'-' => {
+                        lno_old += 1;
+                        self.emit(SymbolKind::Minus, line, 0)?;
+                    }
+                    b'+' => {
+                        lno_new += 1;
+                        let at_eof = blank_at_eof.is_some_and(|(old_at, new_at)| {
+                            old_at <= lno_old && new_at <= lno_new && color::is_blank_line(&line)
+                        });
+                        self.emit(
+                            SymbolKind::Plus,
+                            line,
+                            if at_eof { BLANK_AT_EOF } else { 0 },
+                        )?;
+                    }
+                    _ => {
+                        lno_old += 1;
+                        lno_new += 1;
+                        self.emit(SymbolKind::Context, line, 0)?;
+                    }
+                }
+                if incomplete {
+                    lno_old += 1;
+                    self.emit(SymbolKind::NoNewline, "\\ No newline at end of file\n", 0)?;
+                }
+            }
+            self.flush_words(&mut minus, &mut plus)?;
+        }
+        Ok(())
+    }
+
+    fn flush_words(&mut self, minus: &mut Vec<u8>, plus: &mut Vec<u8>) -> std::io::Result<()> {
+        if let Some(word_diff) = &self.opts.word_diff {
+            if !minus.is_empty() || !plus.is_empty() {
+                let words = words::show(minus, plus, word_diff, &self.palette);
+                self.emit(SymbolKind::Words, words, 0)?;
+            }
+        }
+        minus.clear();
+        plus.clear();
+        Ok(())
+    }
+}
+
+/// The lines of a hunk in output order, each with its `-`, `+` or ` ` sign
+fn hunk_lines<'a>(
+    old: &[&'a [u8]],
+    new: &[&'a [u8]],
+    hunk: &[Change],
+    new_end: usize,
+    new_start: usize,
+) -> Vec<(u8, &'a [u8])> {
+    let mut lines = Vec::new();
+    let mut i2 = new_start;
+    for change in hunk {
+        lines.extend(new[i2..change.new_start].iter().map(|&line| (b' ', line)));
+        lines.extend(
+            old[change.old_start..change.old_start + change.old_len]
+                .iter()
+                .map(|&line| (b'-', line)),
+        );
+        lines.extend(
+            new[change.new_start..change.new_start + change.new_len]
+                .iter()
+                .map(|&line| (b'+', line)),
+        );
+        i2 = change.new_start + change.new_len;
+    }
+    lines.extend(new[i2..new_end].iter().map(|&line| (b' ', line)));
+    lines
+}
+
+/// Groups changes into hunks: changes separated by at most twice the context share a hunk
+pub(crate) fn group_hunks(changes: &[Change], context: usize) -> Vec<&[Change]> {
+    let mut hunks = Vec::new();
+    let mut start = 0;
+    for i in 1..=changes.len() {
+        let split = i == changes.len() || {
+            let prev = &changes[i - 1];
+            changes[i].old_start - (prev.old_start + prev.old_len) > 2 * context
+        };
+        if split {
+            if start < i {
+                hunks.push(&changes[start..i]);
+            }
+            start = i;
+        }
+    }
+    hunks
+}
+
+/// Line range of a hunk: `(old_start, old_end, new_start, new_end)`, 0-based and exclusive
+pub(crate) fn hunk_range(
+    hunk: &[Change],
+    context: usize,
+    old_len: usize,
+    new_len: usize,
+) -> (usize, usize, usize, usize) {
+    let first = hunk[0];
+    let last = hunk[hunk.len() - 1];
+    let s1 = first.old_start.saturating_sub(context);
+    let s2 = first.new_start.saturating_sub(context);
+    let lctx = context
+        .min(old_len - (last.old_start + last.old_len))
+        .min(new_len - (last.new_start + last.new_len));
+    let e1 = last.old_start + last.old_len + lctx;
+    let e2 = last.new_start + last.new_len + lctx;
+    (s1, e1, s2, e2)
+}
+
+/// Formats `-start,count` the way git does (a count of 1 is omitted)
+pub(crate) fn range_header(start: usize, count: usize) -> String {
+    let start = if count == 0 { start } else { start + 1 };
+    if count == 1 {
+        format!("{start}")
+    } else {
+        format!("{start},{count}")
+    }
+}
+
+/// The nearest line before the hunk that looks like the start of a function: git's default
+/// is any line starting with a letter, `_` or `$`
+pub(crate) fn function_line<'a>(old: &[&'a [u8]], hunk_start: usize) -> Option<&'a [u8]> {
+    old[..hunk_start.min(old.len())]
+        .iter()
+        .rev()
+        .find_map(|line| {
+            let first = *line.first()?;
+            if !(first.is_ascii_alphabetic() || first == b'_' || first == b'$') {
+                return None;
+            }
+            let mut line = &line[..line.len().min(80)];
+            while let Some((last, rest)) = line.split_last() {
+                if !last.is_ascii_whitespace() && *last != 0x0b {
+                    break;
+                }
+                line = rest;
+            }
+            Some(line)
+        })
+}
+
+/// Per-file line counts for stats
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum FileStat {
+    Lines {
+        added: usize,
+        deleted: usize,
+    },
+    /// Binary files are counted in bytes
+    Binary {
+        old_size: usize,
+        new_size: usize,
+    },
+}
+
+pub(crate) fn file_stat(pair: &FilePair, opts: &DiffOptions) -> anyhow::Result<FileStat> {
+    let old = match &pair.old {
+        Some(old) => old.content()?,
+        None => Vec::new(),
+    };
+    let new = match &pair.new {
+        Some(new) => new.content()?,
+        None => Vec::new(),
+    };
+    if is_binary(&old) || is_binary(&new) {
+        // A binary file renamed without changes has no size change worth showing
+        let same = matches!((&pair.old, &pair.new), (Some(a), Some(b)) if a.hash == b.hash);
+        return Ok(FileStat::Binary {
+            old_size: if same { 0 } else { old.len() },
+            new_size: if same { 0 } else { new.len() },
+        });
+    }
+    let changes = diff_lines(&split_lines(&old), &split_lines(&new), opts);
+    Ok(FileStat::Lines {
+        added: changes.iter().map(|change| change.new_len).sum(),
+        deleted: changes.iter().map(|change| change.old_len).sum(),
+    })
+}
+
+pub(crate) fn write_numstat(
+    out: &mut impl Write,
+    pairs: &[FilePair],
+    opts: &DiffOptions,
+) -> anyhow::Result<()> {
+    for pair in pairs {
+        match file_stat(pair, opts)? {
+            FileStat::Lines { added, deleted } => write!(out, "{added}\t{deleted}\t")?,
+            FileStat::Binary { .. } => write!(out, "-\t-\t")?,
+        }
+        writeln!(out, "{}", pair.display_name())?;
+    }
+    Ok(())
+}
+
+fn decimal_width(n: usize) -> usize {
+    n.to_string().len()
+}
+
+fn scale_linear(it: usize, width: usize, max_change: usize) -> usize {
+    if it == 0 {
+        0
+    } else {
+        1 + it * (width - 1) / max_change
+    }
+}
+
+/// Writes a `--stat` diffstat, laid out for [`PatchOptions::stat_width`] columns like git's
+pub(crate) fn write_stat(
+    out: &mut impl Write,
+    pairs: &[FilePair],
+    opts: &PatchOptions,
+) -> anyhow::Result<()> {
+    let palette = Palette::new(opts.color);
+    let (add_c, del_c, reset) = (palette.new, palette.old, palette.reset);
+    let stats = pairs
+        .iter()
+        .map(|pair| Ok((pair.display_name(), file_stat(pair, &opts.diff)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut max_len = 0;
+    let mut max_change = 0;
+    let mut number_width = 0;
+    let mut bin_width = 0;
+    for (name, stat) in &stats {
+        max_len = max_len.max(name.chars().count());
+        match stat {
+            FileStat::Lines { added, deleted } => max_change = max_change.max(added + deleted),
+            FileStat::Binary { old_size, new_size } => {
+                bin_width = bin_width.max(14 + decimal_width(*old_size) + decimal_width(*new_size));
+                number_width = 3;
+            }
+        }
+    }
+
+    let mut width = opts.stat_width;
+    number_width = number_width.max(decimal_width(max_change));
+    width = width.max(16 + 6 + number_width);
+    let mut graph_width = if max_change + 4 > bin_width {
+        max_change
+    } else {
+        bin_width - 4
+    };
+    let mut name_width = max_len;
+    if name_width + number_width + 6 + graph_width > width {
+        let limit = (width * 3 / 8).saturating_sub(number_width + 6);
+        if graph_width > limit {
+            graph_width = limit.max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    let (mut insertions, mut deletions) = (0, 0);
+    for (name, stat) in &stats {
+        let mut prefix = "";
+        let mut shown: &str = name;
+        let name_len = name.chars().count();
+        let mut len = name_width;
+        if name_width < name_len {
+            prefix = "...";
+            len = len.saturating_sub(3);
+            let skip = name_len - len;
+            let start = name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i);
+            shown = &name[start..];
+            if let Some(slash) = shown.find('/') {
+                shown = &shown[slash..];
+            }
+        }
+        let padding = len.saturating_sub(shown.chars().count());
+        write!(out, " {prefix}{shown}{:padding$} | ", "")?;
+        match *stat {
+            FileStat::Binary { old_size, new_size } => {
+                write!(out, "{:>number_width$}", "Bin")?;
+                if old_size == 0 && new_size == 0 {
+                    writeln!(out)?;
+                } else {
+                    writeln!(
+                        out,
+                        " {del_c}{old_size}{reset} -> {add_c}{new_size}{reset} bytes"
+                    )?;
+                }
+            }
+            FileStat::Lines { added, deleted } => {
+                insertions += added;
+                deletions += deleted;
+                let (mut add, mut del) = (added, deleted);
+                if graph_width <= max_change {
+                    let mut total = scale_linear(add + del, graph_width, max_change);
+                    if total < 2 && add > 0 && del > 0 {
+                        total = 2;
+                    }
+                    if add < del {
+                        add = scale_linear(add, graph_width, max_change);
+                        del = total - add;
+                    } else {
+                        del = scale_linear(del, graph_width, max_change);
+                        add = total - del;
+                    }
+                }
+                let space = if added + deleted > 0 { " " } else { "" };
+                let graph = |sign: &str, count: usize, color: &str| {
+                    if count == 0 {
+                        String::new()
+                    } else {
+                        format!("{color}{}{reset}", sign.repeat(count))
+                    }
+                };
+                writeln!(
+                    out,
+                    "{:>number_width$}{space}{}{}",
+                    added + deleted,
+                    graph("+", add, add_c),
+                    graph("-", del, del_c)
+                )?;
+            }
+        }
+    }
+    writeln!(out, "{}", stat_summary(stats.len(), insertions, deletions))?;
+    Ok(())
+}
+
+/// Writes the last line of a `--stat` on its own, as `--shortstat` does. Binary files count as
+/// changed without adding to the lines.
+pub(crate) fn write_shortstat(
+    out: &mut impl Write,
+    pairs: &[FilePair],
+    opts: &PatchOptions,
+) -> anyhow::Result<()> {
+    let (mut insertions, mut deletions) = (0, 0);
+    for pair in pairs {
+        if let FileStat::Lines { added, deleted } = file_stat(pair, &opts.diff)? {
+            insertions += added;
+            deletions += deleted;
+        }
+    }
+    writeln!(out, "{}", stat_summary(pairs.len(), insertions, deletions))?;
+    Ok(())
+}
+
+/// ` 2 files changed, 3 insertions(+), 1 deletion(-)`
+pub(crate) fn stat_summary(files: usize, insertions: usize, deletions: usize) -> String {
+    if files == 0 {
+        return String::from(" 0 files changed");
+    }
+    let mut summary = format!(
+        " {files} {} changed",
+        if files == 1 { "file" } else { "files" }
+    );
+    if insertions > 0 || deletions == 0 {
+        summary.push_str(&format!(
+            ", {insertions} {}(+)",
+            if insertions == 1 {
+                "insertion"
+            } else {
+                "insertions"
+            }
+        ));
+    }
+    if deletions > 0 || insertions == 0 {
+        summary.push_str(&format!(
+            ", {deletions} {}(-)",
+            if deletions == 1 {
+                "deletion"
+            } else {
+                "deletions"
+            }
+        ));
+    }
+    summary
+}
+
+/// Writes a `--summary`: created and deleted files with their modes, renames, copies and mode
+/// changes
+pub(crate) fn write_summary(out: &mut impl Write, pairs: &[FilePair]) -> anyhow::Result<()> {
+    for pair in pairs {
+        let name = quote::c_style(pair.path(), false);
+        match (&pair.old, &pair.new) {
+            (None, Some(new)) => writeln!(out, " create mode {:06o} {name}", new.mode)?,
+            (Some(old), None) => writeln!(out, " delete mode {:06o} {name}", old.mode)?,
+            (Some(old), Some(new)) => {
+                if let (Some(rename), Some(similarity)) = (pair.rename, pair.similarity()) {
+                    let kind = if rename.copy { "copy" } else { "rename" };
+                    writeln!(out, " {kind} {} ({similarity}%)", pair.display_name())?;
+                    if old.mode != new.mode {
+                        writeln!(out, " mode change {:06o} => {:06o}", old.mode, new.mode)?;
+                    }
+                } else if old.mode != new.mode {
+                    writeln!(
+                        out,
+                        " mode change {:06o} => {:06o} {name}",
+                        old.mode, new.mode
+                    )?;
+                }
+            }
+            (None, None) => {}
+        }
+    }
+    Ok(())
+}
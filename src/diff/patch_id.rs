@@ -0,0 +1,63 @@
+//! Patch ids: a hash of the changes a commit makes, ignoring whitespace and line numbers, so
+//! the same change applied elsewhere in history hashes the same.
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    diff::{
+        files::tree_to_tree,
+        patch::{PatchOptions, PatchWriter},
+    },
+    object::commit::Commit,
+    pathspec::Pathspec,
+};
+
+/// The patch id of a commit's changes against its parent, or `None` for merges, which have no
+/// single patch
+pub(crate) fn commit_patch_id(
+    commit: &Commit,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Option<[u8; 20]>> {
+    let parent = match commit.parents.as_slice() {
+        [] => None,
+        [parent] => Some(Commit::read(parent)?.tree),
+        _ => return Ok(None),
+    };
+    let pairs = tree_to_tree(parent.as_ref(), Some(&commit.tree), pathspec, false)?;
+    let opts = PatchOptions::default();
+    let mut patch = Vec::new();
+    let mut writer = PatchWriter::new(&mut patch, &opts);
+    for pair in &pairs {
+        writer.write(pair)?;
+    }
+    writer.finish()?;
+    Ok(Some(patch_id(&patch)))
+}
+
+/// Hashes a patch without its whitespace, `index` lines and hunk headers. Binary files have no
+/// lines to hash, so their blob ids from the `index` line stand in for the content.
+fn patch_id(patch: &[u8]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    let mut index: Option<&[u8]> = None;
+    for line in patch.split(|&b| b == b'\n') {
+        if let Some(hashes) = line.strip_prefix(b"index ") {
+            index = Some(hashes);
+            continue;
+        }
+        if line.starts_with(b"@@ ") {
+            continue;
+        }
+        if line.starts_with(b"Binary files ") {
+            if let Some(hashes) = index.take() {
+                hasher.update(hashes.split(|&b| b == b' ').next().unwrap_or_default());
+            }
+        }
+        let stripped: Vec<u8> = line
+            .iter()
+            .copied()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect();
+        hasher.update(&stripped);
+    }
+    hasher.finalize().into()
+}
@@ -0,0 +1,155 @@
+//! Patience diff: anchor the diff on lines that occur exactly once on each side, then recurse
+//! into the gaps. Mirrors git's xpatience.c so the same hunks come out.
+
+use std::collections::HashMap;
+
+use super::{myers, Records};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Occurrence {
+    /// Only seen in the old lines so far
+    Missing,
+    Unique(usize),
+    NotUnique,
+}
+
+struct Entry {
+    line1: usize,
+    line2: Occurrence,
+}
+
+/// Marks changed lines in `old` and `new`
+pub(crate) fn diff(old: &mut Records, new: &mut Records, minimal: bool) {
+    let (len1, len2) = (old.len(), new.len());
+    patience(old, new, (0, len1), (0, len2), minimal);
+}
+
+fn mark_all(records: &mut Records, start: usize, count: usize) {
+    for i in start..start + count {
+        records.set_changed(i, true);
+    }
+}
+
+fn patience(
+    old: &mut Records,
+    new: &mut Records,
+    (mut line1, count1): (usize, usize),
+    (mut line2, count2): (usize, usize),
+    minimal: bool,
+) {
+    if count1 == 0 {
+        mark_all(new, line2, count2);
+        return;
+    }
+    if count2 == 0 {
+        mark_all(old, line1, count1);
+        return;
+    }
+
+    // Entries in order of first occurrence in the old lines
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut by_id: HashMap<usize, usize> = HashMap::new();
+    for i in line1..line1 + count1 {
+        match by_id.get(&old.ids[i]) {
+            Some(&entry) => entries[entry].line2 = Occurrence::NotUnique,
+            None => {
+                by_id.insert(old.ids[i], entries.len());
+                entries.push(Entry {
+                    line1: i,
+                    line2: Occurrence::Missing,
+                });
+            }
+        }
+    }
+    let mut has_matches = false;
+    for i in line2..line2 + count2 {
+        if let Some(&entry) = by_id.get(&new.ids[i]) {
+            has_matches = true;
+            let entry = &mut entries[entry];
+            entry.line2 = match entry.line2 {
+                Occurrence::Missing => Occurrence::Unique(i),
+                _ => Occurrence::NotUnique,
+            };
+        }
+    }
+    if !has_matches {
+        mark_all(old, line1, count1);
+        mark_all(new, line2, count2);
+        return;
+    }
+
+    let common = longest_common_sequence(&entries);
+    if common.is_empty() {
+        myers::diff_range(old, new, (line1, count1), (line2, count2), minimal);
+        return;
+    }
+
+    // Walk the unique common lines, growing each match and recursing into the gaps between
+    let (end1, end2) = (line1 + count1, line2 + count2);
+    let mut k = 0;
+    loop {
+        let (mut next1, mut next2) = match common.get(k) {
+            Some(&(next1, next2)) => (next1, next2),
+            None => (end1, end2),
+        };
+        if k < common.len() {
+            while next1 > line1 && next2 > line2 && old.ids[next1 - 1] == new.ids[next2 - 1] {
+                next1 -= 1;
+                next2 -= 1;
+            }
+        }
+        while line1 < next1 && line2 < next2 && old.ids[line1] == new.ids[line2] {
+            line1 += 1;
+            line2 += 1;
+        }
+        if next1 > line1 || next2 > line2 {
+            patience(
+                old,
+                new,
+                (line1, next1 - line1),
+                (line2, next2 - line2),
+                minimal,
+            );
+        }
+        if k == common.len() {
+            return;
+        }
+        while k + 1 < common.len() && common[k + 1] == (common[k].0 + 1, common[k].1 + 1) {
+            k += 1;
+        }
+        line1 = common[k].0 + 1;
+        line2 = common[k].1 + 1;
+        k += 1;
+    }
+}
+
+/// Longest increasing run of new-side positions among the unique lines, by patience sorting
+fn longest_common_sequence(entries: &[Entry]) -> Vec<(usize, usize)> {
+    let mut sequence: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; entries.len()];
+    let line2 = |entry: usize| match entries[entry].line2 {
+        Occurrence::Unique(line) => line,
+        _ => unreachable!(),
+    };
+    for (i, entry) in entries.iter().enumerate() {
+        let Occurrence::Unique(line) = entry.line2 else {
+            continue;
+        };
+        let slot = sequence.partition_point(|&other| line2(other) <= line);
+        previous[i] = slot.checked_sub(1).map(|slot| sequence[slot]);
+        if slot == sequence.len() {
+            sequence.push(i);
+        } else {
+            sequence[slot] = i;
+        }
+    }
+
+    let mut common = Vec::new();
+    let mut entry = sequence.last().copied();
+    while let Some(i) = entry {
+        common.push((entries[i].line1, line2(i)));
+        entry = previous[i];
+    }
+    common.reverse();
+    common
+}
@@ -0,0 +1,111 @@
+//! Filtering file pairs by what their changes touch, after git's diffcore-pickaxe: `-S` keeps
+//! pairs where the number of occurrences of a string changes, `-G` pairs with an added or
+//! removed line matching a regex.
+
+use regex::bytes::Regex;
+
+use crate::{
+    diff::{
+        diff_lines,
+        patch::{is_binary, FilePair, FileSide},
+        split_lines, DiffOptions,
+    },
+    grep::{self, PatternType},
+};
+
+#[derive(Debug)]
+pub(crate) enum Needle {
+    /// `-S`: the string, or with `--pickaxe-regex` the extended regex, occurs a different
+    /// number of times on each side
+    Count(Regex),
+    /// `-G`: an added or removed line matches the extended regex
+    Grep(Regex),
+}
+
+#[derive(Debug)]
+pub(crate) struct Pickaxe {
+    needle: Needle,
+    /// Keep every pair of a change set when any of them matches (`--pickaxe-all`)
+    all: bool,
+}
+
+impl Pickaxe {
+    /// `-S<string>`, taken as an extended regex when `regex` is set
+    pub(crate) fn count(
+        needle: &str,
+        regex: bool,
+        ignore_case: bool,
+        all: bool,
+    ) -> anyhow::Result<Pickaxe> {
+        anyhow::ensure!(!needle.is_empty(), "-S requires a non-empty string");
+        let kind = if regex {
+            PatternType::Extended
+        } else {
+            PatternType::Fixed
+        };
+        Ok(Pickaxe {
+            needle: Needle::Count(grep::compile_bytes(needle, kind, ignore_case)?),
+            all,
+        })
+    }
+
+    /// `-G<regex>`
+    pub(crate) fn grep(regex: &str, ignore_case: bool, all: bool) -> anyhow::Result<Pickaxe> {
+        let regex = grep::compile_bytes(regex, PatternType::Extended, ignore_case)?;
+        Ok(Pickaxe {
+            needle: Needle::Grep(regex),
+            all,
+        })
+    }
+
+    /// The pairs that match, or all of them with `--pickaxe-all` when any does
+    pub(crate) fn filter(&self, pairs: Vec<FilePair>) -> anyhow::Result<Vec<FilePair>> {
+        let mut matching = Vec::new();
+        for pair in &pairs {
+            matching.push(self.matches(pair)?);
+        }
+        if self.all {
+            return Ok(if matching.contains(&true) {
+                pairs
+            } else {
+                Vec::new()
+            });
+        }
+        Ok(pairs
+            .into_iter()
+            .zip(matching)
+            .filter_map(|(pair, matches)| matches.then_some(pair))
+            .collect())
+    }
+
+    /// Pairs with the same content on both sides never match, nor do binary files
+    fn matches(&self, pair: &FilePair) -> anyhow::Result<bool> {
+        if let (Some(old), Some(new)) = (&pair.old, &pair.new) {
+            if old.hash == new.hash {
+                return Ok(false);
+            }
+        }
+        let content =
+            |side: &Option<FileSide>| side.as_ref().map_or(Ok(Vec::new()), FileSide::content);
+        let (old, new) = (content(&pair.old)?, content(&pair.new)?);
+        if is_binary(&old) || is_binary(&new) {
+            return Ok(false);
+        }
+        Ok(match &self.needle {
+            Needle::Count(regex) => regex.find_iter(&old).count() != regex.find_iter(&new).count(),
+            Needle::Grep(regex) => {
+                let (old_lines, new_lines) = (split_lines(&old), split_lines(&new));
+                let matches =
+                    |line: &&[u8]| regex.is_match(line.strip_suffix(b"\n").unwrap_or(line));
+                diff_lines(&old_lines, &new_lines, &DiffOptions::default())
+                    .iter()
+                    .any(|change| {
+                        old_lines[change.old_start..change.old_start + change.old_len]
+                            .iter()
+                            .chain(&new_lines[change.new_start..change.new_start + change.new_len])
+                            .any(matches)
+                    })
+            }
+        })
+    }
+}
@@ -0,0 +1,446 @@
+//! Rename and copy detection, after git's diffcore-rename. Added files are paired with deleted
+//! (or, for copies, any remaining) files first by identical content, then by matching basenames
+//! and finally by a similarity score computed from content fingerprints.
+
+use std::collections::HashMap;
+
+use crate::diff::patch::{is_binary, FilePair, FileSide, Rename};
+
+/// Similarity scores are fractions of this
+pub(crate) const MAX_SCORE: u32 = 60000;
+/// 50%, the default for `-M` and `-C`
+pub(crate) const DEFAULT_SCORE: u32 = 30000;
+/// Default for `diff.renameLimit` and `-l`
+pub(crate) const DEFAULT_LIMIT: usize = 1000;
+
+/// How many best-scoring sources to remember for each destination
+const CANDIDATES_PER_DST: usize = 4;
+/// Modulus for the chunk fingerprints
+const HASHBASE: u32 = 107927;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RenameOptions {
+    /// Also pair added files with modified (and, if unchanged pairs are given, unmodified)
+    /// sources that stay in place
+    pub(crate) copies: bool,
+    /// Minimum similarity, out of [`MAX_SCORE`]
+    pub(crate) min_score: u32,
+    /// Skip the similarity matrix when it would exceed this many entries squared
+    pub(crate) limit: usize,
+}
+
+/// Parses the `<n>` of `-M<n>`: digits read as a fraction (`5` is 50%, `05` is 5%), or a
+/// percentage when followed by `%`
+pub(crate) fn parse_score(value: &str) -> anyhow::Result<u32> {
+    let (mut num, mut scale) = (0u64, 1u64);
+    let mut dot = false;
+    let mut rest = value;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '.' if !dot => {
+                scale = 1;
+                dot = true;
+            }
+            '%' => {
+                scale = if dot { scale * 100 } else { 100 };
+                rest = &rest[1..];
+                break;
+            }
+            '0'..='9' => {
+                if scale < 100000 {
+                    scale *= 10;
+                    num = num * 10 + u64::from(c as u8 - b'0');
+                }
+            }
+            _ => break,
+        }
+        rest = &rest[1..];
+    }
+    anyhow::ensure!(rest.is_empty(), "Invalid similarity score '{value}'");
+    Ok(if num >= scale {
+        MAX_SCORE
+    } else {
+        (u64::from(MAX_SCORE) * num / scale) as u32
+    })
+}
+
+/// A file taking part in detection, with its content loaded on demand
+struct Candidate {
+    side: FileSide,
+    content: Option<Vec<u8>>,
+    fingerprint: Option<HashMap<u32, u64>>,
+}
+
+impl Candidate {
+    fn new(side: &FileSide) -> Candidate {
+        Candidate {
+            side: side.clone(),
+            content: None,
+            fingerprint: None,
+        }
+    }
+
+    fn content(&mut self) -> anyhow::Result<&[u8]> {
+        if self.content.is_none() {
+            self.content = Some(self.side.content()?);
+        }
+        Ok(self.content.as_deref().unwrap())
+    }
+
+    fn is_regular(&self) -> bool {
+        self.side.mode >> 12 == 0o10
+    }
+
+    /// Bytes per chunk hash, where chunks end at a newline or after 64 bytes
+    fn fingerprint(&mut self) -> anyhow::Result<&HashMap<u32, u64>> {
+        if self.fingerprint.is_none() {
+            let content = self.content()?;
+            let text = !is_binary(content);
+            let mut counts = HashMap::new();
+            let (mut accum1, mut accum2, mut n) = (0u32, 0u32, 0u64);
+            for (i, &c) in content.iter().enumerate() {
+                // CR of a CRLF pair is ignored in text
+                if text && c == b'\r' && content.get(i + 1) == Some(&b'\n') {
+                    continue;
+                }
+                let old1 = accum1;
+                accum1 = (accum1 << 7) ^ (accum2 >> 25);
+                accum2 = (accum2 << 7) ^ (old1 >> 25);
+                accum1 = accum1.wrapping_add(u32::from(c));
+                n += 1;
+                if n < 64 && c != b'\n' {
+                    continue;
+                }
+                let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE;
+                *counts.entry(hash).or_insert(0) += n;
+                (accum1, accum2, n) = (0, 0, 0);
+            }
+            if n > 0 {
+                let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE;
+                *counts.entry(hash).or_insert(0) += n;
+            }
+            self.fingerprint = Some(counts);
+        }
+        Ok(self.fingerprint.as_ref().unwrap())
+    }
+}
+
+/// How much of `dst` comes from `src`, out of [`MAX_SCORE`]. Only regular files are compared,
+/// and pairs whose sizes differ too much to reach `min_score` are not looked at closely.
+fn similarity(src: &mut Candidate, dst: &mut Candidate, min_score: u32) -> anyhow::Result<u32> {
+    if !src.is_regular() || !dst.is_regular() {
+        return Ok(0);
+    }
+    let src_size = src.content()?.len() as u64;
+    let dst_size = dst.content()?.len() as u64;
+    let max_size = src_size.max(dst_size);
+    let delta_size = max_size - src_size.min(dst_size);
+    if max_size * u64::from(MAX_SCORE - min_score) < delta_size * u64::from(MAX_SCORE) {
+        return Ok(0);
+    }
+    if dst_size == 0 {
+        return Ok(0);
+    }
+    let dst_counts = dst.fingerprint()?.clone();
+    let copied: u64 = src
+        .fingerprint()?
+        .iter()
+        .map(|(hash, &count)| count.min(dst_counts.get(hash).copied().unwrap_or(0)))
+        .sum();
+    Ok((copied * u64::from(MAX_SCORE) / max_size) as u32)
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Whether the final path components are equal
+fn basename_same(src: &str, dst: &str) -> bool {
+    basename(src) == basename(dst)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Score {
+    dst: usize,
+    src: usize,
+    score: u32,
+    name_score: bool,
+}
+
+/// Best matches first: higher scores, then same basenames
+fn score_order(a: &Option<Score>, b: &Option<Score>) -> std::cmp::Ordering {
+    match (a, b) {
+        (None, None) => std::cmp::Ordering::Equal,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (Some(a), Some(b)) => b.score.cmp(&a.score).then(b.name_score.cmp(&a.name_score)),
+    }
+}
+
+/// Keeps `score` among a destination's best candidates if it beats the worst of them
+fn record_if_better(slots: &mut [Option<Score>], score: Score) {
+    let mut worst = 0;
+    for i in 1..slots.len() {
+        if score_order(&slots[i], &slots[worst]).is_gt() {
+            worst = i;
+        }
+    }
+    if score_order(&slots[worst], &Some(score)).is_gt() {
+        slots[worst] = Some(score);
+    }
+}
+
+struct Detection {
+    sources: Vec<Candidate>,
+    dests: Vec<Candidate>,
+    /// Pair index of each source and destination
+    source_pairs: Vec<usize>,
+    dest_pairs: Vec<usize>,
+    /// How many pairs use each source: its renames and copies, plus one if it stays in place
+    used: Vec<usize>,
+    /// Source and score a destination was matched with
+    matched: Vec<Option<(usize, u32)>>,
+    /// Sources unchanged between both sides, only present when finding copies harder
+    unchanged: Vec<bool>,
+}
+
+impl Detection {
+    fn record(&mut self, dst: usize, src: usize, score: u32) {
+        self.used[src] += 1;
+        self.matched[dst] = Some((src, score));
+    }
+
+    /// Pairs added files with identical sources, preferring unused ones with the same basename
+    fn exact(&mut self, copies: bool) {
+        for dst in 0..self.dests.len() {
+            let target = &self.dests[dst].side;
+            let mut best: Option<(usize, usize)> = None;
+            for (src, candidate) in self.sources.iter().enumerate() {
+                let source = &candidate.side;
+                if source.hash != target.hash {
+                    continue;
+                }
+                let regular = candidate.is_regular() && self.dests[dst].is_regular();
+                if !regular && source.mode != target.mode {
+                    continue;
+                }
+                if self.used[src] > 0 && !copies {
+                    continue;
+                }
+                let score = usize::from(self.used[src] == 0)
+                    + usize::from(basename_same(&source.path, &target.path));
+                if best.is_none_or(|(_, best_score)| score > best_score) {
+                    best = Some((src, score));
+                    if score == 2 {
+                        break;
+                    }
+                }
+            }
+            if let Some((src, _)) = best {
+                self.record(dst, src, MAX_SCORE);
+            }
+        }
+    }
+
+    /// Pairs files whose basename is unique among the remaining sources and destinations
+    fn basenames(&mut self, active: &[usize], min_score: u32) -> anyhow::Result<()> {
+        let mut sources: HashMap<&str, Option<usize>> = HashMap::new();
+        for &src in active {
+            let name = basename(&self.sources[src].side.path);
+            sources
+                .entry(name)
+                .and_modify(|unique| *unique = None)
+                .or_insert(Some(src));
+        }
+        let mut dests: HashMap<&str, Option<usize>> = HashMap::new();
+        for (dst, candidate) in self.dests.iter().enumerate() {
+            if self.matched[dst].is_some() {
+                continue;
+            }
+            dests
+                .entry(basename(&candidate.side.path))
+                .and_modify(|unique| *unique = None)
+                .or_insert(Some(dst));
+        }
+        let mut found = Vec::new();
+        for &src in active {
+            let name = basename(&self.sources[src].side.path);
+            if let (Some(Some(src)), Some(Some(dst))) = (sources.get(name), dests.get(name)) {
+                found.push((*src, *dst));
+            }
+        }
+        for (src, dst) in found {
+            let (source, dest) = (&mut self.sources[src], &mut self.dests[dst]);
+            let score = similarity(source, dest, min_score)?;
+            if score >= min_score {
+                self.record(dst, src, score);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares every remaining destination with every active source and takes the best
+    /// matches first
+    fn inexact(
+        &mut self,
+        active: &[usize],
+        opts: &RenameOptions,
+        skip_unchanged: bool,
+    ) -> anyhow::Result<()> {
+        let mut matrix = Vec::new();
+        for dst in 0..self.dests.len() {
+            if self.matched[dst].is_some() {
+                continue;
+            }
+            let mut slots = [None; CANDIDATES_PER_DST];
+            for &src in active {
+                if skip_unchanged && self.unchanged[src] {
+                    continue;
+                }
+                let (source, dest) = (&mut self.sources[src], &mut self.dests[dst]);
+                let score = similarity(source, dest, opts.min_score)?;
+                let name_score = basename_same(&source.side.path, &dest.side.path);
+                record_if_better(
+                    &mut slots,
+                    Score {
+                        dst,
+                        src,
+                        score,
+                        name_score,
+                    },
+                );
+            }
+            matrix.extend(slots);
+        }
+        matrix.sort_by(score_order);
+
+        let rounds: &[bool] = if opts.copies {
+            &[false, true]
+        } else {
+            &[false]
+        };
+        for &copies in rounds {
+            for score in &matrix {
+                let Some(score) = score else { break };
+                if score.score < opts.min_score {
+                    break;
+                }
+                if self.matched[score.dst].is_some() || (!copies && self.used[score.src] > 0) {
+                    continue;
+                }
+                self.record(score.dst, score.src, score.score);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_unchanged(pair: &FilePair) -> bool {
+    matches!((&pair.old, &pair.new), (Some(old), Some(new)) if old.mode == new.mode && old.hash == new.hash)
+}
+
+/// Turns additions into renames or copies of matching sources. Deleted files that were renamed
+/// disappear from the result, and so do the unchanged pairs given as extra copy sources.
+pub(crate) fn detect(pairs: Vec<FilePair>, opts: &RenameOptions) -> anyhow::Result<Vec<FilePair>> {
+    let mut detection = Detection {
+        sources: Vec::new(),
+        dests: Vec::new(),
+        source_pairs: Vec::new(),
+        dest_pairs: Vec::new(),
+        used: Vec::new(),
+        matched: Vec::new(),
+        unchanged: Vec::new(),
+    };
+    for (i, pair) in pairs.iter().enumerate() {
+        match (&pair.old, &pair.new) {
+            (None, Some(new)) => {
+                detection.dests.push(Candidate::new(new));
+                detection.dest_pairs.push(i);
+                detection.matched.push(None);
+            }
+            (Some(old), new) if new.is_none() || opts.copies => {
+                detection.sources.push(Candidate::new(old));
+                detection.source_pairs.push(i);
+                detection.used.push(usize::from(new.is_some()));
+                detection.unchanged.push(is_unchanged(pair));
+            }
+            _ => {}
+        }
+    }
+
+    if !detection.sources.is_empty() && !detection.dests.is_empty() {
+        detection.exact(opts.copies);
+        if opts.min_score < MAX_SCORE {
+            let remaining = |detection: &Detection| -> Vec<usize> {
+                (0..detection.sources.len())
+                    .filter(|&src| opts.copies || detection.used[src] == 0)
+                    .collect()
+            };
+            if !opts.copies {
+                let min_basename_score = opts.min_score + (MAX_SCORE - opts.min_score) / 2;
+                detection.basenames(&remaining(&detection), min_basename_score)?;
+            }
+            let active = remaining(&detection);
+            let dests = detection.matched.iter().filter(|m| m.is_none()).count();
+            if dests > 0 && !active.is_empty() {
+                let limit = opts.limit * opts.limit;
+                let modified = active
+                    .iter()
+                    .filter(|&&src| !detection.unchanged[src])
+                    .count();
+                if opts.limit == 0 || dests * active.len() <= limit {
+                    detection.inexact(&active, opts, false)?;
+                } else if opts.copies && modified < active.len() && dests * modified <= limit {
+                    detection.inexact(&active, opts, true)?;
+                } else {
+                    eprintln!(
+                        "warning: exhaustive rename detection was skipped due to too many files."
+                    );
+                    eprintln!(
+                        "warning: you may want to set your diff.renameLimit variable to at least {} and retry the command.",
+                        dests.max(active.len())
+                    );
+                }
+            }
+        }
+    }
+
+    let mut dest_of_pair = HashMap::new();
+    for (dst, &pair) in detection.dest_pairs.iter().enumerate() {
+        dest_of_pair.insert(pair, dst);
+    }
+    let mut source_of_pair = HashMap::new();
+    for (src, &pair) in detection.source_pairs.iter().enumerate() {
+        source_of_pair.insert(pair, src);
+    }
+    let mut used = detection.used.clone();
+    let mut out = Vec::new();
+    for (i, pair) in pairs.into_iter().enumerate() {
+        if let Some(&dst) = dest_of_pair.get(&i) {
+            if let Some((src, score)) = detection.matched[dst] {
+                // The last pair using a deleted source is its rename, any others are copies
+                used[src] -= 1;
+                out.push(FilePair {
+                    old: Some(detection.sources[src].side.clone()),
+                    new: pair.new,
+                    rename: Some(Rename {
+                        score,
+                        copy: used[src] > 0,
+                    }),
+                });
+                continue;
+            }
+        } else if pair.new.is_none() {
+            if source_of_pair
+                .get(&i)
+                .is_some_and(|&src| detection.used[src] > 0)
+            {
+                continue;
+            }
+        } else if is_unchanged(&pair) {
+            continue;
+        }
+        out.push(pair);
+    }
+    Ok(out)
+}
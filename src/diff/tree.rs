@@ -0,0 +1,129 @@
+//! Tree-to-tree diffs that walk both trees in parallel, skipping subtrees whose hashes match.
+
+use std::cmp::Ordering;
+
+use crate::{
+    diff::patch::{FilePair, FileSide},
+    object::{read::read_tree_items, TreeItem, MODE_TREE},
+    pathspec::Pathspec,
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TreeDiffOptions {
+    /// Descend into changed subtrees instead of reporting them as single entries
+    pub(crate) recursive: bool,
+    /// Also report the changed subtrees themselves when recursing
+    pub(crate) show_trees: bool,
+    /// Report unchanged files too, as pairs with identical sides, so they can serve as copy
+    /// sources
+    pub(crate) unchanged: bool,
+}
+
+/// Changes between two trees in tree order; `None` stands for the empty tree
+pub(crate) fn diff_trees(
+    old: Option<&[u8; 20]>,
+    new: Option<&[u8; 20]>,
+    pathspec: &Pathspec,
+    opts: TreeDiffOptions,
+) -> anyhow::Result<Vec<FilePair>> {
+    let mut pairs = Vec::new();
+    walk(old, new, "", pathspec, opts, &mut pairs)?;
+    Ok(pairs)
+}
+
+fn items(tree: Option<&[u8; 20]>) -> anyhow::Result<Vec<TreeItem>> {
+    match tree {
+        Some(hash) => read_tree_items(hash),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Orders entries the way trees store them: subtrees sort as if their name ended in '/'
+fn entry_order(a: &TreeItem, b: &TreeItem) -> Ordering {
+    let key = |item: &TreeItem| {
+        let mut key = item.name.as_bytes().to_vec();
+        if item.mode == MODE_TREE {
+            key.push(b'/');
+        }
+        key
+    };
+    key(a).cmp(&key(b))
+}
+
+fn walk(
+    old: Option<&[u8; 20]>,
+    new: Option<&[u8; 20]>,
+    prefix: &str,
+    pathspec: &Pathspec,
+    opts: TreeDiffOptions,
+    pairs: &mut Vec<FilePair>,
+) -> anyhow::Result<()> {
+    let old_items = items(old)?;
+    let new_items = items(new)?;
+    let (mut i, mut j) = (0, 0);
+    while i < old_items.len() || j < new_items.len() {
+        let order = match (old_items.get(i), new_items.get(j)) {
+            (Some(a), Some(b)) => entry_order(a, b),
+            (Some(_), None) => Ordering::Less,
+            _ => Ordering::Greater,
+        };
+        let (a, b) = match order {
+            Ordering::Less => (Some(&old_items[i]), None),
+            Ordering::Greater => (None, Some(&new_items[j])),
+            Ordering::Equal => (Some(&old_items[i]), Some(&new_items[j])),
+        };
+        if order != Ordering::Greater {
+            i += 1;
+        }
+        if order != Ordering::Less {
+            j += 1;
+        }
+        let name = &a.or(b).unwrap().name;
+        let path = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{prefix}/{name}")
+        };
+        entry(a, b, &path, pathspec, opts, pairs)?;
+    }
+    Ok(())
+}
+
+fn entry(
+    old: Option<&TreeItem>,
+    new: Option<&TreeItem>,
+    path: &str,
+    pathspec: &Pathspec,
+    opts: TreeDiffOptions,
+    pairs: &mut Vec<FilePair>,
+) -> anyhow::Result<()> {
+    let unchanged =
+        matches!((old, new), (Some(a), Some(b)) if a.mode == b.mode && a.hash == b.hash);
+    if unchanged && !opts.unchanged {
+        return Ok(());
+    }
+    let side = |item: Option<&TreeItem>| {
+        item.map(|item| FileSide::from_object(path, item.mode, item.hash))
+    };
+    let is_tree = old.or(new).unwrap().mode == MODE_TREE;
+    if !is_tree || !opts.recursive {
+        if pathspec.matches(path) || (is_tree && pathspec.leads_to(path)) {
+            pairs.push(FilePair::new(side(old), side(new)));
+        }
+        return Ok(());
+    }
+    if !pathspec.matches(path) && !pathspec.leads_to(path) {
+        return Ok(());
+    }
+    if opts.show_trees && !unchanged {
+        pairs.push(FilePair::new(side(old), side(new)));
+    }
+    walk(
+        old.map(|item| &item.hash),
+        new.map(|item| &item.hash),
+        path,
+        pathspec,
+        opts,
+        pairs,
+    )
+}
@@ -0,0 +1,212 @@
+//! `--word-diff`: changed lines of a hunk are re-diffed word by word and shown inline.
+
+use regex::bytes::Regex;
+
+use super::{color::is_space, color::Palette, diff_lines, Algorithm, DiffOptions};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WordDiffMode {
+    /// `[-removed-]{+added+}`
+    Plain,
+    /// Removed and added words in color only
+    Color,
+    /// One run per line with a `-`, `+` or ` ` prefix and `~` for newlines
+    Porcelain,
+}
+
+impl WordDiffMode {
+    pub(crate) fn parse(name: &str) -> Option<WordDiffMode> {
+        match name {
+            "plain" => Some(WordDiffMode::Plain),
+            "color" => Some(WordDiffMode::Color),
+            "porcelain" => Some(WordDiffMode::Porcelain),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct WordDiff {
+    pub(crate) mode: WordDiffMode,
+    /// What makes a word; runs of non-whitespace without one
+    pub(crate) regex: Option<Regex>,
+}
+
+/// How one kind of run is shown: a color and the text around it
+struct Style<'a> {
+    color: &'a str,
+    prefix: &'a str,
+    suffix: &'a str,
+}
+
+/// Word boundaries `(start, end)` in `text`, following git's `find_word_boundaries`
+fn split_words(text: &[u8], regex: Option<&Regex>) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut begin = 0;
+    while begin < text.len() {
+        let Some(end) = next_word(text, regex, &mut begin) else {
+            break;
+        };
+        words.push((begin, end));
+        begin = end;
+    }
+    words
+}
+
+fn next_word(text: &[u8], regex: Option<&Regex>, begin: &mut usize) -> Option<usize> {
+    if let Some(regex) = regex {
+        while *begin < text.len() {
+            let found = regex.find(&text[*begin..])?;
+            let start = *begin + found.start();
+            // A word never spans lines
+            let end = text[start..*begin + found.end()]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(*begin + found.end(), |newline| start + newline);
+            *begin = start;
+            if *begin == end {
+                *begin += 1;
+            } else {
+                return Some(end);
+            }
+        }
+    }
+
+    while *begin < text.len() && is_space(text[*begin]) {
+        *begin += 1;
+    }
+    if *begin >= text.len() {
+        return None;
+    }
+    let mut end = *begin + 1;
+    while end < text.len() && !is_space(text[end]) {
+        end += 1;
+    }
+    Some(end)
+}
+
+/// Writes a run of text in a style, wrapping each line of it separately
+fn write_run(out: &mut Vec<u8>, style: &Style, newline: &str, text: &[u8], reset: &str) {
+    let mut rest = text;
+    while !rest.is_empty() {
+        let line_end = rest.iter().position(|&b| b == b'\n');
+        if line_end != Some(0) {
+            out.extend_from_slice(style.color.as_bytes());
+            out.extend_from_slice(style.prefix.as_bytes());
+            out.extend_from_slice(&rest[..line_end.unwrap_or(rest.len())]);
+            out.extend_from_slice(style.suffix.as_bytes());
+            if !style.color.is_empty() {
+                out.extend_from_slice(reset.as_bytes());
+            }
+        }
+        let Some(line_end) = line_end else {
+            break;
+        };
+        out.extend_from_slice(newline.as_bytes());
+        rest = &rest[line_end + 1..];
+    }
+}
+
+/// Renders the removed and added lines of one block of a hunk as a word diff
+pub(crate) fn show<'a>(
+    minus: &'a [u8],
+    plus: &'a [u8],
+    word_diff: &WordDiff,
+    palette: &Palette,
+) -> Vec<u8> {
+    let (old, new, ctx, newline) = match word_diff.mode {
+        WordDiffMode::Porcelain => (("-", "\n"), ("+", "\n"), (" ", "\n"), "~\n"),
+        WordDiffMode::Plain => (("[-", "-]"), ("{+", "+}"), ("", ""), "\n"),
+        WordDiffMode::Color => (("", ""), ("", ""), ("", ""), "\n"),
+    };
+    let old = Style {
+        color: palette.old,
+        prefix: old.0,
+        suffix: old.1,
+    };
+    let new = Style {
+        color: palette.new,
+        prefix: new.0,
+        suffix: new.1,
+    };
+    let ctx = Style {
+        color: palette.context,
+        prefix: ctx.0,
+        suffix: ctx.1,
+    };
+
+    let mut out = Vec::new();
+    if plus.is_empty() {
+        write_run(&mut out, &old, newline, minus, palette.reset);
+        return out;
+    }
+
+    let regex = word_diff.regex.as_ref();
+    let minus_words = split_words(minus, regex);
+    let plus_words = split_words(plus, regex);
+    let words = |text: &'a [u8], bounds: &[(usize, usize)]| -> Vec<&'a [u8]> {
+        bounds
+            .iter()
+            .map(|&(start, end)| &text[start..end])
+            .collect()
+    };
+    let minus_list = words(minus, &minus_words);
+    let plus_list = words(plus, &plus_words);
+    let opts = DiffOptions {
+        algorithm: Algorithm::Myers,
+        minimal: false,
+        indent_heuristic: false,
+        ignore_space_change: false,
+        ignore_all_space: false,
+    };
+    let changes = diff_lines(&minus_list, &plus_list, &opts);
+
+    // Byte range covered by `len` words from `start`; empty ranges sit after the previous word
+    let span = |bounds: &[(usize, usize)], start: usize, len: usize| {
+        if len > 0 {
+            (bounds[start].0, bounds[start + len - 1].1)
+        } else {
+            let end = start
+                .checked_sub(1)
+                .map_or(0, |previous| bounds[previous].1);
+            (end, end)
+        }
+    };
+    let mut current = 0;
+    for change in changes {
+        let (minus_begin, minus_end) = span(&minus_words, change.old_start, change.old_len);
+        let (plus_begin, plus_end) = span(&plus_words, change.new_start, change.new_len);
+        if current != plus_begin {
+            write_run(
+                &mut out,
+                &ctx,
+                newline,
+                &plus[current..plus_begin],
+                palette.reset,
+            );
+        }
+        if minus_begin != minus_end {
+            write_run(
+                &mut out,
+                &old,
+                newline,
+                &minus[minus_begin..minus_end],
+                palette.reset,
+            );
+        }
+        if plus_begin != plus_end {
+            write_run(
+                &mut out,
+                &new,
+                newline,
+                &plus[plus_begin..plus_end],
+                palette.reset,
+            );
+        }
+        current = plus_end;
+    }
+    if current != plus.len() {
+        write_run(&mut out, &ctx, newline, &plus[current..], palette.reset);
+    }
+    out
+}
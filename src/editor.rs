@@ -0,0 +1,66 @@
+//! Launching the user's editor on a file, as git does for commit messages and todo lists.
+
+use std::{path::Path, process::Command};
+
+use anyhow::Context;
+
+use crate::config;
+
+/// The editor for commit messages: `GIT_EDITOR`, `core.editor`, `VISUAL` on a capable terminal,
+/// then `EDITOR`, falling back to `vi`
+fn commit_editor() -> anyhow::Result<String> {
+    if let Ok(editor) = std::env::var("GIT_EDITOR") {
+        return Ok(editor);
+    }
+    if let Some(editor) = config::read_repo_config().get("core.editor") {
+        return Ok(editor.to_string());
+    }
+    let dumb = std::env::var("TERM").map_or(true, |term| term == "dumb");
+    if !dumb {
+        if let Ok(editor) = std::env::var("VISUAL") {
+            return Ok(editor);
+        }
+    }
+    if let Ok(editor) = std::env::var("EDITOR") {
+        return Ok(editor);
+    }
+    anyhow::ensure!(!dumb, "Terminal is dumb, but EDITOR unset");
+    Ok(String::from("vi"))
+}
+
+/// The editor for todo lists: `GIT_SEQUENCE_EDITOR` or `sequence.editor`, otherwise the one
+/// for commit messages
+fn sequence_editor() -> anyhow::Result<String> {
+    if let Ok(editor) = std::env::var("GIT_SEQUENCE_EDITOR") {
+        return Ok(editor);
+    }
+    if let Some(editor) = config::read_repo_config().get("sequence.editor") {
+        return Ok(editor.to_string());
+    }
+    commit_editor()
+}
+
+/// Opens `path` in the editor and waits for it. The editor is run by the shell with the path as
+/// its argument, so it may be a command with options of its own; `:` leaves the file as it is.
+pub(crate) fn edit(path: &Path, sequence: bool) -> anyhow::Result<()> {
+    let editor = if sequence {
+        sequence_editor()?
+    } else {
+        commit_editor()?
+    };
+    if editor == ":" {
+        return Ok(());
+    }
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(&editor)
+        .arg(path)
+        .status()
+        .with_context(|| format!("unable to start editor '{editor}'"))?;
+    anyhow::ensure!(
+        status.success(),
+        "There was a problem with the editor '{editor}'."
+    );
+    Ok(())
+}
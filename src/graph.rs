@@ -0,0 +1,569 @@
+//! The ASCII history graph drawn to the left of `log --graph` output. Each line of history
+//! occupies a column; commits are drawn as `*` in their column, merges fan out into the columns
+//! of their parents, and lines that lead to the same commit collapse into one.
+//!
+//! Drawing a commit goes through a series of states, each producing one line: lines expanding
+//! the graph around octopus merges, the commit line itself, the line fanning out a merge's
+//! parents, and lines collapsing branches into their targets.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Nothing left to draw for the commit, only lines continuing the columns
+    Padding,
+    /// The previous commit wasn't finished; an ellipsis marks the gap
+    Skip,
+    /// Making room around an octopus merge
+    PreCommit,
+    Commit,
+    PostMerge,
+    Collapsing,
+}
+
+/// Characters joining a merge to its parents, indexed from the merge layout
+const MERGE_CHARS: [char; 3] = ['/', '|', '\\'];
+
+pub(crate) struct Graph {
+    commit: Option<[u8; 20]>,
+    /// The parents of the commit that are shown, so lines lead to them
+    parents: Vec<[u8; 20]>,
+    /// Width of the graph on the lines of the current commit, to align the text beside it
+    width: usize,
+    expansion_row: usize,
+    state: State,
+    prev_state: State,
+    /// Column of the current commit
+    commit_index: usize,
+    prev_commit_index: usize,
+    /// For merges, whether the first parent is to the left of the merge (0) or below it (1)
+    merge_layout: i32,
+    /// Columns added by the current merge, or -1 when it joins the column next to it
+    edges_added: i32,
+    prev_edges_added: i32,
+    /// The commit each column leads to, before the current commit
+    columns: Vec<[u8; 20]>,
+    /// The commit each column leads to, after the current commit
+    new_columns: Vec<[u8; 20]>,
+    /// For each character position, the index in `new_columns` the line there is heading to,
+    /// or -1
+    mapping: Vec<i32>,
+    old_mapping: Vec<i32>,
+    mapping_size: usize,
+}
+
+impl Graph {
+    pub(crate) fn new() -> Graph {
+        Graph {
+            commit: None,
+            parents: Vec::new(),
+            width: 0,
+            expansion_row: 0,
+            state: State::Padding,
+            prev_state: State::Padding,
+            commit_index: 0,
+            prev_commit_index: 0,
+            merge_layout: 0,
+            edges_added: 0,
+            prev_edges_added: 0,
+            columns: Vec::new(),
+            new_columns: Vec::new(),
+            mapping: Vec::new(),
+            old_mapping: Vec::new(),
+            mapping_size: 0,
+        }
+    }
+
+    /// Moves the graph on to the next commit shown, with those of its parents that are shown
+    pub(crate) fn update(&mut self, commit: [u8; 20], parents: Vec<[u8; 20]>) {
+        self.commit = Some(commit);
+        self.parents = parents;
+        self.prev_commit_index = self.commit_index;
+        self.update_columns();
+        self.expansion_row = 0;
+
+        // The state is set directly, as no line was drawn in the one it replaces
+        self.state = if self.state != State::Padding {
+            State::Skip
+        } else if self.needs_pre_commit_line() {
+            State::PreCommit
+        } else {
+            State::Commit
+        };
+    }
+
+    fn update_state(&mut self, state: State) {
+        self.prev_state = self.state;
+        self.state = state;
+    }
+
+    fn num_parents(&self) -> i32 {
+        self.parents.len() as i32
+    }
+
+    /// Octopus merges draw a dash for each parent beyond the ones the merge line reaches
+    fn num_dashed_parents(&self) -> i32 {
+        self.num_parents() + self.merge_layout - 3
+    }
+
+    fn num_expansion_rows(&self) -> i32 {
+        self.num_dashed_parents() * 2
+    }
+
+    fn needs_pre_commit_line(&self) -> bool {
+        self.parents.len() >= 3
+            && self.commit_index + 1 < self.columns.len()
+            && (self.expansion_row as i32) < self.num_expansion_rows()
+    }
+
+    fn find_new_column(&self, commit: &[u8; 20]) -> Option<usize> {
+        self.new_columns.iter().position(|column| column == commit)
+    }
+
+    fn set_mapping(&mut self, index: i32, target: i32) {
+        let index = index as usize;
+        if index >= self.mapping.len() {
+            self.mapping.resize(index + 1, -1);
+        }
+        self.mapping[index] = target;
+    }
+
+    fn mapping_at(&self, index: i32) -> i32 {
+        if index < 0 {
+            return -1;
+        }
+        self.mapping.get(index as usize).copied().unwrap_or(-1)
+    }
+
+    /// Works out the columns after the current commit: its own column is replaced by its
+    /// parents, and `mapping` records where each line has to go
+    fn update_columns(&mut self) {
+        std::mem::swap(&mut self.columns, &mut self.new_columns);
+        self.new_columns.clear();
+
+        let max_new_columns = self.columns.len() + self.parents.len();
+        self.mapping_size = 2 * max_new_columns;
+        if self.mapping.len() < self.mapping_size {
+            self.mapping.resize(self.mapping_size, -1);
+            self.old_mapping.resize(self.mapping_size, -1);
+        }
+        for target in &mut self.mapping[..self.mapping_size] {
+            *target = -1;
+        }
+        self.width = 0;
+        self.prev_edges_added = self.edges_added;
+        self.edges_added = 0;
+
+        let commit = self.commit.expect("updated with a commit");
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column_commit = if i == self.columns.len() {
+                if seen_this {
+                    break;
+                }
+                commit
+            } else {
+                self.columns[i]
+            };
+            if column_commit == commit {
+                seen_this = true;
+                self.commit_index = i;
+                self.merge_layout = -1;
+                for parent in self.parents.clone() {
+                    self.insert_into_new_columns(parent, i as i32);
+                }
+                // The commit takes up at least two characters
+                if self.parents.is_empty() {
+                    self.width += 2;
+                }
+            } else {
+                self.insert_into_new_columns(column_commit, -1);
+            }
+        }
+
+        while self.mapping_size > 1 && self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+    }
+
+    fn insert_into_new_columns(&mut self, commit: [u8; 20], index: i32) {
+        let i = match self.find_new_column(&commit) {
+            Some(i) => i,
+            None => {
+                self.new_columns.push(commit);
+                self.new_columns.len() - 1
+            }
+        } as i32;
+
+        let width = self.width as i32;
+        let mapping_index;
+        if self.parents.len() > 1 && index > -1 && self.merge_layout == -1 {
+            // The first parent of a merge decides whether its line goes down or to the left
+            let distance = index - i;
+            let shift = if distance > 1 { 2 * distance - 3 } else { 1 };
+            self.merge_layout = if distance > 0 { 0 } else { 1 };
+            self.edges_added = self.num_parents() + self.merge_layout - 2;
+            mapping_index = width + (self.merge_layout - 1) * shift;
+            self.width += 2 * self.merge_layout as usize;
+        } else if self.edges_added > 0 && width >= 2 && i == self.mapping_at(width - 2) {
+            // A merge added columns, but this parent is in the last existing column, so the two
+            // edges join right away
+            mapping_index = width - 2;
+            self.edges_added = -1;
+        } else {
+            mapping_index = width;
+            self.width += 2;
+        }
+        self.set_mapping(mapping_index, i);
+    }
+
+    /// Whether every line is in its column, or one character to the right of it where a `/`
+    /// takes it there
+    fn is_mapping_correct(&self) -> bool {
+        self.mapping[..self.mapping_size]
+            .iter()
+            .enumerate()
+            .all(|(i, &target)| target < 0 || target == (i / 2) as i32)
+    }
+
+    fn pad(&self, line: &mut String) {
+        while line.len() < self.width {
+            line.push(' ');
+        }
+    }
+
+    /// The next line of graph for the current commit, and whether it was the commit line
+    fn next_line(&mut self) -> (String, bool) {
+        let mut line = String::new();
+        let mut commit_line = false;
+        match self.state {
+            State::Padding => self.output_padding_line(&mut line),
+            State::Skip => self.output_skip_line(&mut line),
+            State::PreCommit => self.output_pre_commit_line(&mut line),
+            State::Commit => {
+                self.output_commit_line(&mut line);
+                commit_line = true;
+            }
+            State::PostMerge => self.output_post_merge_line(&mut line),
+            State::Collapsing => self.output_collapsing_line(&mut line),
+        }
+        self.pad(&mut line);
+        (line, commit_line)
+    }
+
+    fn output_padding_line(&self, line: &mut String) {
+        for _ in &self.new_columns {
+            line.push_str("| ");
+        }
+    }
+
+    fn output_skip_line(&mut self, line: &mut String) {
+        line.push_str("...");
+        if self.needs_pre_commit_line() {
+            self.update_state(State::PreCommit);
+        } else {
+            self.update_state(State::Commit);
+        }
+    }
+
+    fn output_pre_commit_line(&mut self, line: &mut String) {
+        let commit = self.commit.expect("updated with a commit");
+        let mut seen_this = false;
+        for (i, column) in self.columns.iter().enumerate() {
+            if *column == commit {
+                seen_this = true;
+                line.push('|');
+                line.extend(std::iter::repeat_n(' ', self.expansion_row));
+            } else if seen_this && self.expansion_row == 0 {
+                // Lines right of a merge drawn just before keep leaning the same way
+                if self.prev_state == State::PostMerge && self.prev_commit_index < i {
+                    line.push('\\');
+                } else {
+                    line.push('|');
+                }
+            } else if seen_this {
+                line.push('\\');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+        self.expansion_row += 1;
+        if !self.needs_pre_commit_line() {
+            self.update_state(State::Commit);
+        }
+    }
+
+    fn output_octopus_dashes(&self, line: &mut String) {
+        let dashed = self.num_dashed_parents();
+        for i in 0..dashed {
+            line.push('-');
+            line.push(if i == dashed - 1 { '.' } else { '-' });
+        }
+    }
+
+    fn output_commit_line(&mut self, line: &mut String) {
+        let commit = self.commit.expect("updated with a commit");
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column_commit = if i == self.columns.len() {
+                if seen_this {
+                    break;
+                }
+                commit
+            } else {
+                self.columns[i]
+            };
+            if column_commit == commit {
+                seen_this = true;
+                line.push('*');
+                if self.parents.len() > 2 {
+                    self.output_octopus_dashes(line);
+                }
+            } else if seen_this && self.edges_added > 1 {
+                line.push('\\');
+            } else if seen_this && self.edges_added == 1 {
+                // A merge without room made before it: continue the lean of a merge just drawn
+                if self.prev_state == State::PostMerge
+                    && self.prev_edges_added > 0
+                    && self.prev_commit_index < i
+                {
+                    line.push('\\');
+                } else {
+                    line.push('|');
+                }
+            } else if self.prev_state == State::Collapsing
+                && self.old_mapping.get(2 * i + 1).copied() == Some(i as i32)
+                && self.mapping_at(2 * i as i32) < i as i32
+            {
+                line.push('/');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+
+        if self.parents.len() > 1 {
+            self.update_state(State::PostMerge);
+        } else if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        } else {
+            self.update_state(State::Collapsing);
+        }
+    }
+
+    fn output_post_merge_line(&mut self, line: &mut String) {
+        let commit = self.commit.expect("updated with a commit");
+        let first_parent = self.parents[0];
+        let mut parent_column_seen = false;
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column_commit = if i == self.columns.len() {
+                if seen_this {
+                    break;
+                }
+                commit
+            } else {
+                self.columns[i]
+            };
+            if column_commit == commit {
+                // Draw an edge towards the column of each parent
+                seen_this = true;
+                let mut layout = self.merge_layout as usize;
+                for j in 0..self.parents.len() {
+                    line.push(MERGE_CHARS[layout]);
+                    if layout == 2 {
+                        if self.edges_added > 0 || j + 1 < self.parents.len() {
+                            line.push(' ');
+                        }
+                    } else {
+                        layout += 1;
+                    }
+                }
+                if self.edges_added == 0 {
+                    line.push(' ');
+                }
+            } else if seen_this {
+                line.push(if self.edges_added > 0 { '\\' } else { '|' });
+                line.push(' ');
+            } else {
+                line.push('|');
+                if self.merge_layout != 0 || i + 1 != self.commit_index {
+                    line.push(if parent_column_seen { '_' } else { ' ' });
+                }
+            }
+            if column_commit == first_parent {
+                parent_column_seen = true;
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        } else {
+            self.update_state(State::Collapsing);
+        }
+    }
+
+    fn output_collapsing_line(&mut self, line: &mut String) {
+        let mut used_horizontal = false;
+        let mut horizontal_edge = -1;
+        let mut horizontal_edge_target = -1;
+
+        std::mem::swap(&mut self.mapping, &mut self.old_mapping);
+        for target in &mut self.mapping[..self.mapping_size] {
+            *target = -1;
+        }
+
+        for i in 0..self.mapping_size as i32 {
+            let target = self.old_mapping[i as usize];
+            if target < 0 {
+                continue;
+            }
+            // Lines only ever move left, so crossing lines are easy to follow
+            if target * 2 == i {
+                self.mapping[i as usize] = target;
+            } else if self.mapping[i as usize - 1] < 0 {
+                // Nothing to the left: move one position left
+                self.mapping[i as usize - 1] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge = i;
+                    horizontal_edge_target = target;
+                    let mut j = target * 2 + 3;
+                    while j < i - 2 {
+                        self.mapping[j as usize] = target;
+                        j += 2;
+                    }
+                }
+            } else if self.mapping[i as usize - 1] == target {
+                // Joining the line to the left, which heads for the same commit
+            } else {
+                // Crossing over the line to the left
+                self.mapping[i as usize - 2] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge_target = target;
+                    horizontal_edge = i - 1;
+                    let mut j = target * 2 + 3;
+                    while j < i - 2 {
+                        self.mapping[j as usize] = target;
+                        j += 2;
+                    }
+                }
+            }
+        }
+
+        self.old_mapping[..self.mapping_size].copy_from_slice(&self.mapping[..self.mapping_size]);
+        if self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+
+        for i in 0..self.mapping_size as i32 {
+            let target = self.mapping[i as usize];
+            if target < 0 {
+                line.push(' ');
+            } else if target * 2 == i {
+                line.push('|');
+            } else if target == horizontal_edge_target && i != horizontal_edge - 1 {
+                // Only the first segment of a horizontal edge continues on the next line
+                if i != target * 2 + 3 {
+                    self.mapping[i as usize] = -1;
+                }
+                used_horizontal = true;
+                line.push('_');
+            } else {
+                if used_horizontal && i < horizontal_edge {
+                    self.mapping[i as usize] = -1;
+                }
+                line.push('/');
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        }
+    }
+
+    fn is_commit_finished(&self) -> bool {
+        self.state == State::Padding
+    }
+
+    /// A line continuing every column, to put beside text between commits
+    fn padding_line(&mut self) -> String {
+        if self.state != State::Commit {
+            return self.next_line().0;
+        }
+        let commit = self.commit.expect("updated with a commit");
+        let mut line = String::new();
+        for column in &self.columns {
+            line.push('|');
+            if *column == commit && self.parents.len() > 2 {
+                let spaces = (self.parents.len() - 2) * self.expansion_row;
+                line.extend(std::iter::repeat_n(' ', spaces));
+            } else {
+                line.push(' ');
+            }
+        }
+        self.pad(&mut line);
+        self.prev_state = State::Padding;
+        line
+    }
+
+    /// Draws the lines leading up to the current commit and the commit line, which is left
+    /// unterminated for the commit's text
+    pub(crate) fn show_commit(&mut self, out: &mut String) {
+        if self.is_commit_finished() {
+            out.push_str(&self.padding_line());
+            return;
+        }
+        loop {
+            let (line, commit_line) = self.next_line();
+            out.push_str(&line);
+            if commit_line || self.is_commit_finished() {
+                break;
+            }
+            out.push('\n');
+        }
+    }
+
+    /// Draws the graph part of the next line
+    pub(crate) fn show_oneline(&mut self, out: &mut String) {
+        out.push_str(&self.next_line().0);
+    }
+
+    pub(crate) fn show_padding(&mut self, out: &mut String) {
+        out.push_str(&self.padding_line());
+    }
+
+    /// Draws whatever is left of the current commit
+    fn show_remainder(&mut self, out: &mut String) {
+        if self.is_commit_finished() {
+            return;
+        }
+        loop {
+            out.push_str(&self.next_line().0);
+            if self.is_commit_finished() {
+                break;
+            }
+            out.push('\n');
+        }
+    }
+
+    /// Shows the text of a commit beside the graph, continuing the graph on each line after
+    /// the first and finishing the commit's part of the graph after the text
+    pub(crate) fn show_commit_msg(&mut self, out: &mut String, text: &str) {
+        let mut lines = text.split_inclusive('\n').peekable();
+        while let Some(line) = lines.next() {
+            out.push_str(line);
+            if line.ends_with('\n') && lines.peek().is_some() {
+                self.show_oneline(out);
+            }
+        }
+        if !self.is_commit_finished() {
+            let newline_terminated = text.ends_with('\n');
+            if !newline_terminated {
+                out.push('\n');
+            }
+            self.show_remainder(out);
+            if newline_terminated {
+                out.push('\n');
+            }
+        }
+    }
+}
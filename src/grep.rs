@@ -0,0 +1,473 @@
+//! Patterns as git's grep machinery takes them: POSIX basic regular expressions by default,
+//! extended ones with `-E`, fixed strings with `-F` and Perl-style ones with `-P`, and the
+//! search of files for lines matching an expression of them.
+
+use anyhow::Context;
+use regex::{Regex, RegexBuilder};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum PatternType {
+    #[default]
+    Basic,
+    Extended,
+    Fixed,
+    /// Taken as the regex crate's syntax, which is close to Perl's short of look-around
+    /// and backreferences
+    Perl,
+}
+
+/// Compiles a pattern of the given type into a [`Regex`]
+pub(crate) fn compile(
+    pattern: &str,
+    kind: PatternType,
+    ignore_case: bool,
+) -> anyhow::Result<Regex> {
+    RegexBuilder::new(&to_regex_syntax(pattern, kind))
+        .case_insensitive(ignore_case)
+        .build()
+        .with_context(|| format!("invalid regex: {pattern}"))
+}
+
+/// Compiles a pattern of the given type into a regex matching file content, which needn't be
+/// utf-8
+pub(crate) fn compile_bytes(
+    pattern: &str,
+    kind: PatternType,
+    ignore_case: bool,
+) -> anyhow::Result<regex::bytes::Regex> {
+    regex::bytes::RegexBuilder::new(&to_regex_syntax(pattern, kind))
+        .case_insensitive(ignore_case)
+        .build()
+        .with_context(|| format!("invalid regex: {pattern}"))
+}
+
+fn to_regex_syntax(pattern: &str, kind: PatternType) -> String {
+    match kind {
+        PatternType::Basic => translate(pattern, false),
+        PatternType::Extended => translate(pattern, true),
+        PatternType::Fixed => regex::escape(pattern),
+        PatternType::Perl => pattern.to_string(),
+    }
+}
+
+/// Rewrites a POSIX regular expression in the syntax of the regex crate. In basic expressions
+/// `(`, `)`, `{`, `}`, `|`, `+` and `?` are literal unless escaped; in both kinds a backslash
+/// inside a bracket expression is literal.
+fn translate(pattern: &str, extended: bool) -> String {
+    let mut out = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('(' | ')' | '{' | '}' | '|' | '+' | '?')) if !extended => out.push(c),
+                Some('<' | '>') => out.push_str("\\b"),
+                Some(c) if c.is_ascii_alphanumeric() && !"wWsSbB".contains(c) => out.push(c),
+                Some(c) => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                None => out.push_str("\\\\"),
+            },
+            '(' | ')' | '{' | '}' | '|' | '+' | '?' if !extended => {
+                out.push('\\');
+                out.push(c);
+            }
+            '[' => {
+                out.push('[');
+                if chars.peek() == Some(&'^') {
+                    out.push('^');
+                    chars.next();
+                }
+                if chars.peek() == Some(&']') {
+                    out.push_str("\\]");
+                    chars.next();
+                }
+                while let Some(c) = chars.next() {
+                    match c {
+                        ']' => break,
+                        '[' if matches!(chars.peek(), Some(':' | '.' | '=')) => {
+                            // Character classes like `[:alpha:]` carry over as they are
+                            let delimiter = chars.next().unwrap();
+                            out.push('[');
+                            out.push(delimiter);
+                            for c in chars.by_ref() {
+                                out.push(c);
+                                if c == ']' {
+                                    break;
+                                }
+                            }
+                        }
+                        '\\' | '[' | '&' | '~' => {
+                            out.push('\\');
+                            out.push(c);
+                        }
+                        _ => out.push(c),
+                    }
+                }
+                out.push(']');
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// A piece of a pattern expression, in the order given on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Token {
+    Pattern(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Pattern(regex::bytes::Regex),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// Matches lines against patterns combined with `--and`, `--or`, `--not` and parentheses.
+/// `--not` binds tightest and `--or` loosest; patterns next to each other are or-ed.
+#[derive(Debug)]
+pub(crate) struct Matcher {
+    expr: Expr,
+    /// Only count matches that are whole words
+    word: bool,
+}
+
+impl Matcher {
+    pub(crate) fn new(
+        tokens: &[Token],
+        kind: PatternType,
+        ignore_case: bool,
+        word: bool,
+    ) -> anyhow::Result<Matcher> {
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            kind,
+            ignore_case,
+        };
+        let Some(expr) = parser.or()? else {
+            anyhow::bail!("no pattern given");
+        };
+        if let Some(token) = tokens.get(parser.pos) {
+            anyhow::bail!("incomplete pattern expression: {token:?}");
+        }
+        Ok(Matcher { expr, word })
+    }
+
+    pub(crate) fn is_match(&self, line: &[u8]) -> bool {
+        self.eval(&self.expr, line)
+    }
+
+    fn eval(&self, expr: &Expr, line: &[u8]) -> bool {
+        match expr {
+            Expr::Pattern(regex) => self.pattern_matches(regex, line),
+            Expr::Not(expr) => !self.eval(expr, line),
+            Expr::And(left, right) => self.eval(left, line) && self.eval(right, line),
+            Expr::Or(left, right) => self.eval(left, line) || self.eval(right, line),
+        }
+    }
+
+    /// With `-w`, a match only counts when it is bounded by non-word characters. When the
+    /// first match isn't, later ones are tried from the next word on.
+    fn pattern_matches(&self, regex: &regex::bytes::Regex, line: &[u8]) -> bool {
+        let mut start = 0;
+        while let Some(found) = regex.find_at(line, start) {
+            if !self.word {
+                return true;
+            }
+            let bounded = (found.start() == 0 || !is_word_char(line[found.start() - 1]))
+                && (found.end() == line.len() || !is_word_char(line[found.end()]));
+            if bounded && !found.is_empty() {
+                return true;
+            }
+            start = found.start() + 1;
+            while start < line.len() && is_word_char(line[start - 1]) {
+                start += 1;
+            }
+            if start >= line.len() {
+                break;
+            }
+        }
+        false
+    }
+
+    /// The expressions or-ed together at the top, which `--all-match` wants each to match
+    /// some line of a file
+    fn terms(&self) -> Vec<&Expr> {
+        let mut terms = Vec::new();
+        let mut expr = &self.expr;
+        while let Expr::Or(left, right) = expr {
+            terms.push(&**left);
+            expr = right;
+        }
+        terms.push(expr);
+        terms
+    }
+}
+
+fn is_word_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// Recursive descent over the tokens, building right-leaning trees as git does
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    kind: PatternType,
+    ignore_case: bool,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn or(&mut self) -> anyhow::Result<Option<Expr>> {
+        let Some(left) = self.and()? else {
+            return Ok(None);
+        };
+        let explicit = self.peek() == Some(&Token::Or);
+        if explicit {
+            self.pos += 1;
+        }
+        if self.peek().is_none_or(|token| *token == Token::Close) {
+            anyhow::ensure!(!explicit, "--or not followed by pattern expression");
+            return Ok(Some(left));
+        }
+        let Some(right) = self.or()? else {
+            anyhow::bail!("not a pattern expression: {:?}", self.peek());
+        };
+        Ok(Some(Expr::Or(Box::new(left), Box::new(right))))
+    }
+
+    fn and(&mut self) -> anyhow::Result<Option<Expr>> {
+        let Some(left) = self.not()? else {
+            return Ok(None);
+        };
+        if self.peek() != Some(&Token::And) {
+            return Ok(Some(left));
+        }
+        self.pos += 1;
+        let Some(right) = self.and()? else {
+            anyhow::bail!("--and not followed by pattern expression");
+        };
+        Ok(Some(Expr::And(Box::new(left), Box::new(right))))
+    }
+
+    fn not(&mut self) -> anyhow::Result<Option<Expr>> {
+        let tokens = self.tokens;
+        match tokens.get(self.pos) {
+            Some(Token::Not) => {
+                self.pos += 1;
+                let Some(expr) = self.not()? else {
+                    anyhow::bail!("--not not followed by pattern expression");
+                };
+                Ok(Some(Expr::Not(Box::new(expr))))
+            }
+            Some(Token::Pattern(pattern)) => {
+                self.pos += 1;
+                let regex = compile_bytes(pattern, self.kind, self.ignore_case)?;
+                Ok(Some(Expr::Pattern(regex)))
+            }
+            Some(Token::Open) => {
+                self.pos += 1;
+                let expr = self.or()?;
+                anyhow::ensure!(
+                    self.peek() == Some(&Token::Close),
+                    "unmatched ( for expression group"
+                );
+                self.pos += 1;
+                Ok(expr)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// What is shown of the files searched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Report {
+    /// The matching lines, with any context asked for
+    Lines,
+    /// The number of matching lines of each file with some
+    Count,
+    /// The names of files with matching lines
+    FilesWithMatches,
+    /// The names of files without matching lines
+    FilesWithoutMatch,
+    /// Nothing, only whether anything matched
+    Quiet,
+}
+
+/// How files with a NUL byte early on are treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryFiles {
+    /// Searched, but only said to match rather than having their lines shown
+    Matches,
+    /// Not searched, as with `-I`
+    Skip,
+    /// Searched as any other file, as with `-a`
+    Text,
+}
+
+/// Options of a search for lines, and how they are shown
+#[derive(Debug)]
+pub(crate) struct SearchOptions {
+    pub(crate) matcher: Matcher,
+    /// Select the lines that don't match
+    pub(crate) invert: bool,
+    /// Only search files where each top-level or-ed expression matches some line
+    pub(crate) all_match: bool,
+    pub(crate) report: Report,
+    pub(crate) binary: BinaryFiles,
+    pub(crate) line_number: bool,
+    /// Start lines with the name of their file
+    pub(crate) with_filename: bool,
+    /// Lines of context shown before and after each matching line
+    pub(crate) before: usize,
+    pub(crate) after: usize,
+}
+
+/// The outcome of searching one file
+#[derive(Debug, Default)]
+pub(crate) struct FileMatches {
+    pub(crate) output: Vec<u8>,
+    /// Whether the file counts as a hit for the exit status
+    pub(crate) hit: bool,
+    /// Whether lines were shown, so that with context the output has to be set apart from
+    /// the lines of files before it by `--`
+    pub(crate) shows_lines: bool,
+}
+
+impl SearchOptions {
+    /// Whether lines around the matching ones are shown, so that groups of lines are
+    /// separated by `--`
+    pub(crate) fn shows_context(&self) -> bool {
+        self.before > 0 || self.after > 0
+    }
+
+    /// Searches the content of a file, shown under `name`
+    pub(crate) fn search(&self, name: &str, content: &[u8]) -> FileMatches {
+        let binary = self.binary != BinaryFiles::Text && crate::diff::patch::is_binary(content);
+        if binary && self.binary == BinaryFiles::Skip {
+            return FileMatches::default();
+        }
+        let mut lines: Vec<&[u8]> = content.split(|&b| b == b'\n').collect();
+        if lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        if self.all_match && !self.all_terms_match(&lines) {
+            return FileMatches::default();
+        }
+
+        let mut search = FileSearch {
+            options: self,
+            name,
+            lines: &lines,
+            result: FileMatches::default(),
+            last_shown: 0,
+        };
+        let only_binary_match = binary && self.report == Report::Lines;
+        let mut count = 0;
+        let mut last_hit = None;
+        for (i, line) in lines.iter().enumerate() {
+            let lno = i + 1;
+            let hit = self.matcher.is_match(line) != self.invert;
+            if hit {
+                count += 1;
+                match self.report {
+                    Report::Quiet => {
+                        search.result.hit = true;
+                        return search.result;
+                    }
+                    Report::FilesWithMatches => {
+                        search.result.output = format!("{name}\n").into_bytes();
+                        search.result.hit = true;
+                        return search.result;
+                    }
+                    Report::FilesWithoutMatch => return FileMatches::default(),
+                    Report::Count => continue,
+                    Report::Lines if only_binary_match => {
+                        search.result.output = format!("Binary file {name} matches\n").into_bytes();
+                        search.result.hit = true;
+                        return search.result;
+                    }
+                    Report::Lines => {}
+                }
+                let from = lno.saturating_sub(self.before).max(search.last_shown + 1);
+                for context in from..lno {
+                    search.show_line(context, b'-');
+                }
+                search.show_line(lno, b':');
+                last_hit = Some(lno);
+            } else if last_hit.is_some_and(|last_hit| lno <= last_hit + self.after) {
+                search.show_line(lno, b'-');
+            }
+        }
+
+        let mut result = search.result;
+        match self.report {
+            Report::FilesWithoutMatch => {
+                result.output = format!("{name}\n").into_bytes();
+                result.hit = true;
+            }
+            Report::Count if count > 0 => {
+                let name = if self.with_filename {
+                    format!("{name}:")
+                } else {
+                    String::new()
+                };
+                result.output = format!("{name}{count}\n").into_bytes();
+                result.hit = true;
+            }
+            _ => result.hit = last_hit.is_some(),
+        }
+        result
+    }
+
+    fn all_terms_match(&self, lines: &[&[u8]]) -> bool {
+        self.matcher
+            .terms()
+            .into_iter()
+            .all(|term| lines.iter().any(|line| self.matcher.eval(term, line)))
+    }
+}
+
+/// The state of the search of one file for the lines it shows
+struct FileSearch<'a> {
+    options: &'a SearchOptions,
+    name: &'a str,
+    lines: &'a [&'a [u8]],
+    result: FileMatches,
+    /// The number of the last line shown, or 0 before any
+    last_shown: usize,
+}
+
+impl FileSearch<'_> {
+    fn show_line(&mut self, lno: usize, separator: u8) {
+        let out = &mut self.result.output;
+        if self.options.shows_context() && self.last_shown != 0 && lno > self.last_shown + 1 {
+            out.extend_from_slice(b"--\n");
+        }
+        self.last_shown = lno;
+        self.result.shows_lines = true;
+        if self.options.with_filename {
+            out.extend_from_slice(self.name.as_bytes());
+            out.push(separator);
+        }
+        if self.options.line_number {
+            out.extend_from_slice(lno.to_string().as_bytes());
+            out.push(separator);
+        }
+        out.extend_from_slice(self.lines[lno - 1]);
+        out.push(b'\n');
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::Path,
+};
+
+use anyhow::Context;
+use sha1::Digest;
+
+use crate::object::{MODE_EXECUTABLE, MODE_FILE, MODE_SYMLINK};
+
+const INDEX_PATH: &str = ".git/index";
+const INDEX_LOCK_PATH: &str = ".git/index.lock";
+const SIGNATURE: &[u8; 4] = b"DIRC";
+
+/// One entry of the staging area, mirroring git's on-disk index entry
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct IndexEntry {
+    pub(crate) ctime: (u32, u32),
+    pub(crate) mtime: (u32, u32),
+    pub(crate) dev: u32,
+    pub(crate) ino: u32,
+    pub(crate) mode: u32,
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    pub(crate) size: u32,
+    pub(crate) hash: [u8; 20],
+    /// 0 for merged entries, 1 (base), 2 (ours) and 3 (theirs) for conflicts
+    pub(crate) stage: u16,
+    pub(crate) path: String,
+}
+
+impl IndexEntry {
+    /// Creates an entry with no cached stat information, as `read-tree` does
+    pub(crate) fn new(path: String, mode: u32, hash: [u8; 20], stage: u16) -> IndexEntry {
+        IndexEntry {
+            mode,
+            hash,
+            stage,
+            path,
+            ..Default::default()
+        }
+    }
+
+    /// Records the stat information of the working tree file
+    pub(crate) fn update_stat(&mut self, metadata: &fs::Metadata) {
+        self.ctime = (metadata.ctime() as u32, metadata.ctime_nsec() as u32);
+        self.mtime = (metadata.mtime() as u32, metadata.mtime_nsec() as u32);
+        self.dev = metadata.dev() as u32;
+        self.ino = metadata.ino() as u32;
+        self.uid = metadata.uid();
+        self.gid = metadata.gid();
+        self.size = metadata.len() as u32;
+    }
+
+    /// Whether the cached stat information still describes the file
+    pub(crate) fn stat_matches(&self, metadata: &fs::Metadata) -> bool {
+        self.mtime == (metadata.mtime() as u32, metadata.mtime_nsec() as u32)
+            && self.ctime == (metadata.ctime() as u32, metadata.ctime_nsec() as u32)
+            && self.size == metadata.len() as u32
+            && self.ino == metadata.ino() as u32
+            && self.mode == mode_from_metadata(metadata)
+    }
+}
+
+/// The mode git records for a working tree file
+pub(crate) fn mode_from_metadata(metadata: &fs::Metadata) -> u32 {
+    if metadata.file_type().is_symlink() {
+        MODE_SYMLINK
+    } else if metadata.permissions().mode() & 0o111 != 0 {
+        MODE_EXECUTABLE
+    } else {
+        MODE_FILE
+    }
+}
+
+/// Whether a path may be in the index: relative, without empty, `.` or `..` components, and
+/// outside any `.git` directory however it is capitalised
+pub(crate) fn verify_path(path: &str) -> bool {
+    path.split('/').all(|component| {
+        !matches!(component, "" | "." | "..") && !component.eq_ignore_ascii_case(".git")
+    })
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Index {
+    /// Sorted by path, then stage
+    entries: Vec<IndexEntry>,
+    /// Modification time of the index file when it was read, used to detect racily clean entries
+    timestamp: Option<(u32, u32)>,
+}
+
+impl Index {
+    /// Reads `.git/index`, returning an empty index if there is none
+    pub(crate) fn read() -> anyhow::Result<Index> {
+        let Ok(data) = fs::read(INDEX_PATH) else {
+            return Ok(Index::default());
+        };
+        let mut index = Index::parse(&data).context("Parsing index file")?;
+        let metadata = fs::metadata(INDEX_PATH)?;
+        index.timestamp = Some((metadata.mtime() as u32, metadata.mtime_nsec() as u32));
+        Ok(index)
+    }
+
+    fn parse(data: &[u8]) -> anyhow::Result<Index> {
+        anyhow::ensure!(data.len() >= 32, "Index file is too short");
+        let (body, checksum) = data.split_at(data.len() - 20);
+        anyhow::ensure!(
+            sha1::Sha1::digest(body).as_slice() == checksum,
+            "Index checksum mismatch"
+        );
+        anyhow::ensure!(&body[..4] == SIGNATURE, "Not an index file");
+        let version = read_u32(body, 4);
+        anyhow::ensure!(
+            version == 2 || version == 3,
+            "Unsupported index version {version}"
+        );
+        let count = read_u32(body, 8) as usize;
+        let mut entries = Vec::with_capacity(count);
+        let mut pos = 12;
+        for _ in 0..count {
+            anyhow::ensure!(body.len() >= pos + 62, "Truncated index entry");
+            let field = |i: usize| read_u32(body, pos + i * 4);
+            let mut hash = [0; 20];
+            hash.copy_from_slice(&body[pos + 40..pos + 60]);
+            let flags = u16::from_be_bytes([body[pos + 60], body[pos + 61]]);
+            let mut name_start = pos + 62;
+            if flags & 0x4000 != 0 {
+                // extended flags (version 3), nothing we use
+                name_start += 2;
+            }
+            let name_len = body[name_start..]
+                .iter()
+                .position(|&b| b == 0)
+                .context("Unterminated index entry path")?;
+            let path = String::from_utf8(body[name_start..name_start + name_len].to_vec())
+                .context("Index entry path is not utf-8")?;
+            entries.push(IndexEntry {
+                ctime: (field(0), field(1)),
+                mtime: (field(2), field(3)),
+                dev: field(4),
+                ino: field(5),
+                mode: field(6),
+                uid: field(7),
+                gid: field(8),
+                size: field(9),
+                hash,
+                stage: (flags >> 12) & 0x3,
+                path,
+            });
+            let entry_len = name_start - pos + name_len;
+            pos += (entry_len + 8) & !7;
+        }
+        // Extensions (cached trees, resolve-undo, ...) are dropped and not written back
+        Ok(Index {
+            entries,
+            timestamp: None,
+        })
+    }
+
+    /// Writes the index through `.git/index.lock`
+    pub(crate) fn write(&mut self) -> anyhow::Result<()> {
+        let data = self.serialize();
+        let mut lock = File::create_new(INDEX_LOCK_PATH)
+            .context("Unable to create .git/index.lock, is another process running?")?;
+        let written = lock.write_all(&data).and_then(|_| lock.sync_all());
+        if let Err(err) = written {
+            let _ = fs::remove_file(INDEX_LOCK_PATH);
+            return Err(err).context("Writing index");
+        }
+        fs::rename(INDEX_LOCK_PATH, INDEX_PATH).context("Replacing index file")?;
+        Ok(())
+    }
+
+    /// The index file's content: version 2, without extensions
+    fn serialize(&mut self) -> Vec<u8> {
+        self.entries.sort_by(compare_entries);
+        let mut data = Vec::new();
+        data.extend(SIGNATURE);
+        data.extend(2u32.to_be_bytes());
+        data.extend((self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            let start = data.len();
+            for field in [
+                entry.ctime.0,
+                entry.ctime.1,
+                entry.mtime.0,
+                entry.mtime.1,
+                entry.dev,
+                entry.ino,
+                entry.mode,
+                entry.uid,
+                entry.gid,
+                entry.size,
+            ] {
+                data.extend(field.to_be_bytes());
+            }
+            data.extend(entry.hash);
+            let name_len = entry.path.len().min(0xfff) as u16;
+            data.extend(((entry.stage << 12) | name_len).to_be_bytes());
+            data.extend(entry.path.as_bytes());
+            let entry_len = data.len() - start;
+            data.resize(start + ((entry_len + 8) & !7), 0);
+        }
+        let checksum = sha1::Sha1::digest(&data);
+        data.extend(checksum);
+        data
+    }
+
+    pub(crate) fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    fn position(&self, path: &str, stage: u16) -> Result<usize, usize> {
+        self.entries.binary_search_by(|entry| {
+            compare_paths(&entry.path, path).then(entry.stage.cmp(&stage))
+        })
+    }
+
+    pub(crate) fn get(&self, path: &str, stage: u16) -> Option<&IndexEntry> {
+        self.position(path, stage).ok().map(|i| &self.entries[i])
+    }
+
+    pub(crate) fn get_mut(&mut self, path: &str, stage: u16) -> Option<&mut IndexEntry> {
+        self.position(path, stage)
+            .ok()
+            .map(|i| &mut self.entries[i])
+    }
+
+    /// All entries (any stage) for a path
+    pub(crate) fn stages(&self, path: &str) -> &[IndexEntry] {
+        let start = self.position(path, 0).unwrap_or_else(|i| i);
+        let end = self.entries[start..]
+            .iter()
+            .position(|entry| entry.path != path)
+            .map_or(self.entries.len(), |n| start + n);
+        &self.entries[start..end]
+    }
+
+    /// Adds or replaces an entry. Adding a merged entry drops any conflict stages for the path
+    /// and vice versa.
+    pub(crate) fn add(&mut self, entry: IndexEntry) {
+        if entry.stage == 0 {
+            self.remove_conflicts(&entry.path);
+        } else if let Ok(i) = self.position(&entry.path, 0) {
+            self.entries.remove(i);
+        }
+        match self.position(&entry.path, entry.stage) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    /// Removes every stage of a path, returning true if something was removed
+    pub(crate) fn remove(&mut self, path: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.path != path);
+        before != self.entries.len()
+    }
+
+    fn remove_conflicts(&mut self, path: &str) {
+        self.entries
+            .retain(|entry| entry.path != path || entry.stage == 0);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub(crate) fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|entry| entry.stage != 0)
+    }
+
+    /// An entry is racily clean when the file was modified in the same second the index was
+    /// written: its stat data can match even though the content changed afterwards.
+    pub(crate) fn is_racy(&self, entry: &IndexEntry) -> bool {
+        match self.timestamp {
+            Some(timestamp) => entry.mtime >= timestamp,
+            None => false,
+        }
+    }
+
+    /// Checks whether the working tree file at `entry.path` still has the indexed content,
+    /// hashing the file only when the cached stat information can't be trusted
+    pub(crate) fn matches_worktree(&self, entry: &IndexEntry) -> anyhow::Result<bool> {
+        let Ok(metadata) = fs::symlink_metadata(&entry.path) else {
+            return Ok(false);
+        };
+        if metadata.is_dir() {
+            return Ok(false);
+        }
+        if entry.stat_matches(&metadata) && !self.is_racy(entry) {
+            return Ok(true);
+        }
+        if entry.mode != mode_from_metadata(&metadata) {
+            return Ok(false);
+        }
+        Ok(crate::worktree::hash_file(Path::new(&entry.path))? == entry.hash)
+    }
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+/// Index entries are ordered by the raw bytes of their path
+fn compare_paths(a: &str, b: &str) -> std::cmp::Ordering {
+    a.as_bytes().cmp(b.as_bytes())
+}
+
+fn compare_entries(a: &IndexEntry, b: &IndexEntry) -> std::cmp::Ordering {
+    compare_paths(&a.path, &b.path).then(a.stage.cmp(&b.stage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn entry(path: &str, stage: u16, seed: u8) -> IndexEntry {
+        IndexEntry {
+            ctime: (1_700_000_000, 12),
+            mtime: (1_700_000_001, u32::from(seed)),
+            dev: 2049,
+            ino: 1000 + u32::from(seed),
+            mode: MODE_FILE,
+            uid: 1000,
+            gid: 100,
+            size: 6,
+            hash: [seed; 20],
+            stage,
+            path: path.to_string(),
+        }
+    }
+
+    fn sample() -> Index {
+        let mut index = Index::default();
+        // Paths of lengths 1 to 8, so that each amount of padding is written
+        for (i, path) in [
+            "a", "ab", "d/a", "d/ab", "x.txt", "d/e/ab", "sub/y.c", "deep/e/f",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            index.add(entry(path, 0, i as u8));
+        }
+        index.add(entry("conflict", 1, 20));
+        index.add(entry("conflict", 2, 21));
+        index.add(entry("conflict", 3, 22));
+        index
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let mut index = sample();
+        let parsed = Index::parse(&index.serialize()).unwrap();
+        assert_eq!(parsed.entries, index.entries);
+    }
+
+    #[test]
+    fn writes_entries_in_path_and_stage_order() {
+        let mut index = Index {
+            entries: vec![entry("b", 0, 1), entry("a", 3, 2), entry("a", 1, 3)],
+            timestamp: None,
+        };
+        let parsed = Index::parse(&index.serialize()).unwrap();
+        let order: Vec<(&str, u16)> = parsed
+            .entries()
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.stage))
+            .collect();
+        assert_eq!(order, [("a", 1), ("a", 3), ("b", 0)]);
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let mut data = sample().serialize();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(Index::parse(&data).is_err());
+    }
+
+    #[test]
+    fn round_trips_through_the_index_file() {
+        testing::in_repo(|| {
+            let mut index = sample();
+            index.write()?;
+            assert!(!Path::new(INDEX_LOCK_PATH).exists());
+            let read = Index::read()?;
+            assert_eq!(read.entries, index.entries);
+            assert!(read.timestamp.is_some());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn adding_a_stage_replaces_the_other_kind() {
+        let mut index = sample();
+        index.add(entry("conflict", 0, 30));
+        assert_eq!(index.stages("conflict"), [entry("conflict", 0, 30)]);
+        index.add(entry("a", 2, 31));
+        assert_eq!(index.stages("a"), [entry("a", 2, 31)]);
+    }
+
+    #[test]
+    fn verifies_paths() {
+        for path in ["a", "a/b", ".gitignore", "dir/.github/x", "a.git"] {
+            assert!(verify_path(path), "{path}");
+        }
+        for path in [
+            "",
+            "/a",
+            "a/",
+            "a//b",
+            "./a",
+            "a/../b",
+            ".git",
+            ".GIT/config",
+            "d/.Git/x",
+        ] {
+            assert!(!verify_path(path), "{path}");
+        }
+    }
+}
@@ -1,3 +1,37 @@
+pub(crate) mod am;
+pub(crate) mod apply;
+pub(crate) mod bisect;
+pub(crate) mod blame;
+pub(crate) mod diff;
 pub(crate) mod object;
+pub(crate) mod pathspec;
+pub(crate) mod quote;
 pub(crate) mod config;
+pub(crate) mod date;
+pub(crate) mod describe;
+pub(crate) mod editor;
+pub(crate) mod decorate;
+pub(crate) mod graph;
+pub(crate) mod grep;
+pub(crate) mod line_log;
+pub(crate) mod line_range;
+pub(crate) mod mail;
+pub(crate) mod merge;
+pub(crate) mod merge_base;
+pub(crate) mod name_rev;
+pub(crate) mod parallel;
+pub(crate) mod pretty;
+pub(crate) mod rebase;
+pub(crate) mod refs;
+pub(crate) mod revision;
+pub(crate) mod revwalk;
+pub(crate) mod sequencer;
+pub(crate) mod stash;
+pub(crate) mod status;
+pub(crate) mod index;
+pub(crate) mod worktree;
+pub(crate) mod unpack;
+pub(crate) mod checkout;
+#[cfg(test)]
+pub(crate) mod testing;
 pub mod commands;
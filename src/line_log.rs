@@ -0,0 +1,396 @@
+//! Following line ranges through history for `log -L`, after git's line-log. Each commit's diff
+//! against its parents moves the ranges to where their lines were before it, and commits whose
+//! diff touches a range are shown with the part of the diff inside it.
+
+use std::{collections::HashMap, io::Write};
+
+use anyhow::Context;
+
+use crate::{
+    diff::{
+        color::Palette,
+        diff_lines, files,
+        patch::FileSide,
+        rename::{self, RenameOptions},
+        split_lines, Change, DiffOptions,
+    },
+    line_range,
+    object::{commit::Commit, read::read_object_of_kind, ObjectKind},
+    pathspec::Pathspec,
+    revision,
+};
+
+/// 0-based, half-open line numbers
+type Range = (usize, usize);
+
+/// The ranges followed in one file, sorted and apart from each other
+#[derive(Debug, Clone)]
+struct FileRanges {
+    path: String,
+    ranges: Vec<Range>,
+}
+
+/// What a commit changed in the ranges of one file
+#[derive(Debug)]
+struct RangeDiff {
+    /// The path before the commit, or none if the commit added the file
+    old_path: Option<String>,
+    old: Vec<u8>,
+    new_path: String,
+    new: Vec<u8>,
+    /// The ranges in the commit's version of the file
+    ranges: Vec<Range>,
+    /// The hunks of the diff that touch the ranges, as lines of the parent and the commit
+    hunks: Vec<(Range, Range)>,
+}
+
+/// The ranges each commit still to be walked is followed in, and the diffs of the commits found
+/// to change them
+#[derive(Debug, Default)]
+pub(crate) struct LineLog {
+    ranges: HashMap<[u8; 20], Vec<FileRanges>>,
+    diffs: HashMap<[u8; 20], Vec<RangeDiff>>,
+    /// Follows files across renames when set
+    renames: Option<RenameOptions>,
+}
+
+impl LineLog {
+    /// Resolves `-L` arguments against the files of the commit the walk starts from
+    pub(crate) fn new(
+        commit: &[u8; 20],
+        args: &[String],
+        renames: Option<RenameOptions>,
+    ) -> anyhow::Result<LineLog> {
+        let tree = Commit::read(commit)?.tree;
+        let mut files: Vec<FileRanges> = Vec::new();
+        for arg in args {
+            let (spec, path) = line_range::split_path(arg)?;
+            let blob = revision::lookup_path(&tree, path)
+                .with_context(|| format!("There is no path {path} in the commit"))?;
+            let content = read_object_of_kind(&hex::encode(blob), ObjectKind::Blob)?;
+            let lines = split_lines(&content);
+            let file = match files.iter().position(|file| file.path == path) {
+                Some(i) => &mut files[i],
+                None => {
+                    files.push(FileRanges {
+                        path: path.to_string(),
+                        ranges: Vec::new(),
+                    });
+                    files.last_mut().unwrap()
+                }
+            };
+            let anchor = file.ranges.last().map_or(1, |range| range.1 + 1);
+            file.ranges
+                .push(line_range::resolve(spec, &lines, anchor, path)?);
+        }
+        let mut line_log = LineLog {
+            renames,
+            ..LineLog::default()
+        };
+        line_log.add(commit, files);
+        Ok(line_log)
+    }
+
+    /// Hands the ranges of a commit on to its parents, given with their trees. Returns whether
+    /// the commit changed the ranges and, for a merge, the first parent the ranges came from
+    /// unchanged, which is then the only one followed. Merges changing the ranges relative to
+    /// every parent are shown without a diff.
+    pub(crate) fn process(
+        &mut self,
+        hash: &[u8; 20],
+        tree: &[u8; 20],
+        parents: &[([u8; 20], [u8; 20])],
+        merge: bool,
+    ) -> anyhow::Result<(bool, Option<[u8; 20]>)> {
+        let Some(ranges) = self.ranges.remove(hash) else {
+            return Ok((false, None));
+        };
+        if !merge {
+            let parent_tree = parents.first().map(|(_, tree)| tree);
+            let (parent_ranges, diffs) = self.map_to_parent(&ranges, tree, parent_tree)?;
+            if let Some((parent, _)) = parents.first() {
+                self.add(parent, parent_ranges);
+            }
+            let changed = !diffs.is_empty();
+            if changed {
+                self.diffs.insert(*hash, diffs);
+            }
+            return Ok((changed, None));
+        }
+
+        let mut candidates = Vec::new();
+        for (parent, parent_tree) in parents {
+            let (parent_ranges, diffs) = self.map_to_parent(&ranges, tree, Some(parent_tree))?;
+            if diffs.is_empty() {
+                self.add(parent, parent_ranges);
+                return Ok((false, Some(*parent)));
+            }
+            candidates.push((parent, parent_ranges));
+        }
+        for (parent, parent_ranges) in candidates {
+            self.add(parent, parent_ranges);
+        }
+        Ok((true, None))
+    }
+
+    /// Adds ranges to those a commit is followed in
+    fn add(&mut self, commit: &[u8; 20], ranges: Vec<FileRanges>) {
+        let files = self.ranges.entry(*commit).or_default();
+        for file in ranges {
+            match files.iter_mut().find(|other| other.path == file.path) {
+                Some(other) => other.ranges.extend(file.ranges),
+                None => files.push(file),
+            }
+        }
+        for file in files.iter_mut() {
+            file.ranges = line_range::merge(std::mem::take(&mut file.ranges));
+        }
+        files.retain(|file| !file.ranges.is_empty());
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    /// Moves ranges across the diff from a parent's tree (or the empty tree) to a commit's,
+    /// returning where they were in the parent and the diffs of the files whose ranges changed
+    fn map_to_parent(
+        &self,
+        ranges: &[FileRanges],
+        tree: &[u8; 20],
+        parent_tree: Option<&[u8; 20]>,
+    ) -> anyhow::Result<(Vec<FileRanges>, Vec<RangeDiff>)> {
+        let paths: Vec<String> = ranges.iter().map(|file| file.path.clone()).collect();
+        let is_followed =
+            |side: &Option<FileSide>| side.as_ref().is_some_and(|side| paths.contains(&side.path));
+        let mut pairs =
+            files::tree_to_tree(parent_tree, Some(tree), &Pathspec::new(&paths), false)?;
+        if let Some(opts) = &self.renames {
+            if pairs.iter().any(|pair| pair.old.is_none()) {
+                // An added file may have been renamed, which takes the whole diff to find
+                let candidates =
+                    files::tree_to_tree(parent_tree, Some(tree), &Pathspec::default(), false)?
+                        .into_iter()
+                        .filter(|pair| pair.new.is_none() || is_followed(&pair.new))
+                        .collect();
+                pairs = rename::detect(candidates, opts)?;
+                pairs.retain(|pair| is_followed(&pair.new));
+            }
+        }
+
+        let mut parent_ranges = Vec::new();
+        let mut diffs = Vec::new();
+        for file in ranges {
+            let Some(pair) = pairs
+                .iter()
+                .find(|pair| pair.new.as_ref().is_some_and(|new| new.path == file.path))
+            else {
+                parent_ranges.push(file.clone());
+                continue;
+            };
+            let new = pair.new.as_ref().map(FileSide::content).transpose()?;
+            let new = new.unwrap_or_default();
+            let old = pair.old.as_ref().map(FileSide::content).transpose()?;
+            let old = old.unwrap_or_default();
+            let changes = diff_lines(
+                &split_lines(&old),
+                &split_lines(&new),
+                &DiffOptions::default(),
+            );
+            let hunks = touching(&changes, &file.ranges);
+
+            let untouched = difference(
+                &file.ranges,
+                &hunks.iter().map(|(_, target)| *target).collect::<Vec<_>>(),
+            );
+            let mut mapped = shift(&untouched, &changes);
+            mapped.extend(hunks.iter().map(|(parent, _)| *parent));
+            let old_path = pair.old.as_ref().map(|old| old.path.clone());
+            parent_ranges.push(FileRanges {
+                path: old_path.clone().unwrap_or_else(|| file.path.clone()),
+                ranges: line_range::merge(mapped),
+            });
+            if !hunks.is_empty() {
+                diffs.push(RangeDiff {
+                    old_path,
+                    old,
+                    new_path: file.path.clone(),
+                    new,
+                    ranges: file.ranges.clone(),
+                    hunks,
+                });
+            }
+        }
+        Ok((parent_ranges, diffs))
+    }
+
+    /// Writes the diffs a commit made to its ranges: for each range a hunk with all of its
+    /// lines, and the lines the commit removed from it
+    pub(crate) fn write_diff(
+        &self,
+        out: &mut impl Write,
+        hash: &[u8; 20],
+        color: bool,
+    ) -> std::io::Result<()> {
+        let palette = Palette::new(color);
+        for diff in self.diffs.get(hash).into_iter().flatten() {
+            diff.write(out, &palette)?;
+        }
+        Ok(())
+    }
+}
+
+impl RangeDiff {
+    fn write(&self, out: &mut impl Write, palette: &Palette) -> std::io::Result<()> {
+        let Palette {
+            meta, frag, reset, ..
+        } = palette;
+        let old_lines = split_lines(&self.old);
+        let new_lines = split_lines(&self.new);
+        let old_path = self.old_path.as_deref().unwrap_or(&self.new_path);
+        writeln!(
+            out,
+            "{meta}diff --git a/{old_path} b/{}{reset}",
+            self.new_path
+        )?;
+        match &self.old_path {
+            Some(path) => writeln!(out, "{meta}--- a/{path}{reset}")?,
+            None => writeln!(out, "{meta}--- /dev/null{reset}")?,
+        }
+        writeln!(out, "{meta}+++ b/{}{reset}", self.new_path)?;
+
+        let hunks = &self.hunks;
+        let mut j = 0;
+        for &(t_start, t_end) in &self.ranges {
+            while j < hunks.len() && hunks[j].1 .1 < t_start {
+                j += 1;
+            }
+            if j == hunks.len() || hunks[j].1 .0 > t_end {
+                continue;
+            }
+            // The last hunk that falls in this range
+            let mut last = j;
+            while last < hunks.len() && hunks[last].1 .0 < t_end {
+                last += 1;
+            }
+            if last > j {
+                last -= 1;
+            }
+
+            // The parent's lines are those of the first and last hunks, shifted out to the
+            // ends of the range
+            let (first_parent, first_target) = hunks[j];
+            let (last_parent, last_target) = hunks[last];
+            let mut p_start = first_parent.0 as i64 - first_target.0.saturating_sub(t_start) as i64;
+            let mut p_end = last_parent.1 as i64 + t_end.saturating_sub(last_target.1) as i64;
+            if p_start == 0 && p_end == 0 {
+                p_start = -1;
+                p_end = -1;
+            }
+            writeln!(
+                out,
+                "{frag}@@ -{},{} +{},{} @@{reset}",
+                p_start + 1,
+                p_end - p_start,
+                t_start + 1,
+                t_end - t_start
+            )?;
+
+            let mut t_cur = t_start;
+            while j < hunks.len() && hunks[j].1 .0 < t_end {
+                let (parent, target) = hunks[j];
+                while t_cur < target.0 {
+                    write_line(out, b' ', new_lines.get(t_cur), palette.context, reset)?;
+                    t_cur += 1;
+                }
+                for k in parent.0..parent.1 {
+                    write_line(out, b'-', old_lines.get(k), palette.old, reset)?;
+                }
+                while t_cur < target.1 && t_cur < t_end {
+                    write_line(out, b'+', new_lines.get(t_cur), palette.new, reset)?;
+                    t_cur += 1;
+                }
+                j += 1;
+            }
+            while t_cur < t_end {
+                write_line(out, b' ', new_lines.get(t_cur), palette.context, reset)?;
+                t_cur += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_line(
+    out: &mut impl Write,
+    sign: u8,
+    line: Option<&&[u8]>,
+    color: &str,
+    reset: &str,
+) -> std::io::Result<()> {
+    let line = line.copied().unwrap_or_default();
+    let text = line.strip_suffix(b"\n");
+    write!(out, "{color}")?;
+    out.write_all(&[sign])?;
+    out.write_all(text.unwrap_or(line))?;
+    writeln!(out, "{reset}")?;
+    if text.is_none() {
+        writeln!(out, "\\ No newline at end of file")?;
+    }
+    Ok(())
+}
+
+fn overlaps(a: Range, b: Range) -> bool {
+    !(a.1 <= b.0 || b.1 <= a.0)
+}
+
+/// The changes whose lines in the commit overlap a range, as lines of the parent and the commit
+fn touching(changes: &[Change], ranges: &[Range]) -> Vec<(Range, Range)> {
+    changes
+        .iter()
+        .map(|change| {
+            (
+                (change.old_start, change.old_start + change.old_len),
+                (change.new_start, change.new_start + change.new_len),
+            )
+        })
+        .filter(|(_, target)| ranges.iter().any(|range| overlaps(*target, *range)))
+        .collect()
+}
+
+/// The parts of `ranges` outside of `remove`, both sorted. Empty ranges of `remove` still split
+/// the ranges they fall inside, so each part moves by the lines removed before it.
+fn difference(ranges: &[Range], remove: &[Range]) -> Vec<Range> {
+    let mut out = Vec::new();
+    for &(mut start, end) in ranges {
+        for &(remove_start, remove_end) in remove {
+            if remove_end <= start {
+                continue;
+            }
+            if remove_start >= end {
+                break;
+            }
+            if start < remove_start {
+                out.push((start, remove_start));
+            }
+            start = start.max(remove_end);
+        }
+        if start < end {
+            out.push((start, end));
+        }
+    }
+    out
+}
+
+/// Moves ranges of a commit's lines to where they were in the parent, by the lines added and
+/// removed before them
+fn shift(ranges: &[Range], changes: &[Change]) -> Vec<Range> {
+    let mut offset = 0i64;
+    let mut changes = changes.iter().peekable();
+    let mut out = Vec::new();
+    for &(start, end) in ranges {
+        while let Some(change) = changes.next_if(|change| start >= change.new_start) {
+            offset += change.old_len as i64 - change.new_len as i64;
+        }
+        let moved = |line: usize| (line as i64 + offset).max(0) as usize;
+        out.push((moved(start), moved(end)));
+    }
+    out
+}
@@ -0,0 +1,253 @@
+//! Line ranges as `-L` takes them: `<start>,<end>` where either side is a line number or a
+//! `/regex/`, the end may also be `+<count>` or `-<count>` lines from the start, and
+//! `:<funcname>` selects the function whose first line matches the regex. `log -L` follows
+//! each range with the file it's in, as `<range>:<file>`.
+
+use crate::grep::{self, PatternType};
+
+/// Resolves one `-L` argument against the lines of `path`, returning the 0-based half-open range
+/// it selects. Regexes are searched for from line `anchor` (1-based, usually the line after the
+/// previous range) unless prefixed with `^`, which searches from the top.
+pub(crate) fn resolve(
+    spec: &str,
+    lines: &[&[u8]],
+    anchor: usize,
+    path: &str,
+) -> anyhow::Result<(usize, usize)> {
+    let (begin, end) = parse(spec, lines, anchor)?;
+    let count = lines.len();
+    if (count == 0 && (begin > 0 || end > 0)) || count < begin {
+        let noun = if count == 1 { "line" } else { "lines" };
+        anyhow::bail!("file {path} has only {count} {noun}");
+    }
+    let end = if end < 1 || count < end { count } else { end };
+    Ok((begin.max(1) - 1, end))
+}
+
+/// Splits a `log -L` argument, `<start>,<end>:<file>` or `:<funcname>:<file>`, into the range
+/// and the file. Regexes in the range may contain `:` themselves.
+pub(crate) fn split_path(arg: &str) -> anyhow::Result<(&str, &str)> {
+    let path =
+        skip_range(arg.as_bytes()).and_then(|end| Some((end, arg[end..].strip_prefix(':')?)));
+    match path {
+        Some((end, path)) if !path.is_empty() => Ok((&arg[..end], path)),
+        _ => anyhow::bail!("-L argument not 'start,end:file' or ':funcname:file': {arg}"),
+    }
+}
+
+/// The length of the range at the start of a `-L` argument
+fn skip_range(arg: &[u8]) -> Option<usize> {
+    if let Some(body) = arg.strip_prefix(b":").or_else(|| arg.strip_prefix(b"^:")) {
+        let start = arg.len() - body.len();
+        let mut i = start;
+        while i < arg.len() && arg[i] != b':' {
+            if arg[i] == b'\\' && i + 1 < arg.len() {
+                i += 1;
+            }
+            i += 1;
+        }
+        return (i > start).then_some(i);
+    }
+    let mut end = skip_location(arg, 0, true);
+    if arg.get(end) == Some(&b',') {
+        end = skip_location(arg, end + 1, false);
+    }
+    Some(end)
+}
+
+/// Skips a line number, `+<count>`/`-<count>` or `/regex/` starting at `from`
+fn skip_location(arg: &[u8], from: usize, start: bool) -> usize {
+    let mut i = from;
+    if matches!(arg.get(i), Some(b'+' | b'-')) {
+        i += 1;
+    }
+    let digits = arg[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 {
+        return i + digits;
+    }
+    i = from;
+    if start && arg.get(i) == Some(&b'^') {
+        i += 1;
+    }
+    if arg.get(i) != Some(&b'/') {
+        return i;
+    }
+    i += 1;
+    while i < arg.len() && arg[i] != b'/' {
+        if arg[i] == b'\\' {
+            i += 1;
+        }
+        i += 1;
+    }
+    if i >= arg.len() {
+        return from;
+    }
+    i + 1
+}
+
+/// Sorts ranges and merges the ones that overlap or touch
+pub(crate) fn merge(mut ranges: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ if start < end => merged.push((start, end)),
+            _ => {}
+        }
+    }
+    merged
+}
+
+/// The 1-based first and last lines named by `spec`, 0 where a side was left out
+fn parse(spec: &str, lines: &[&[u8]], anchor: usize) -> anyhow::Result<(usize, usize)> {
+    let anchor = anchor.clamp(1, lines.len() + 1);
+    if spec.starts_with(':') || spec.starts_with("^:") {
+        return funcname(spec, lines, anchor);
+    }
+    let invalid = || anyhow::anyhow!("invalid -L argument '{spec}'");
+    let (mut begin, mut end) = (0, 0);
+    let mut rest = location(spec, lines, Side::Start(anchor), &mut begin)?;
+    if let Some(after) = rest.strip_prefix(',') {
+        rest = location(after, lines, Side::End(begin + 1), &mut end)?;
+    }
+    if !rest.is_empty() {
+        return Err(invalid());
+    }
+    if begin > 0 && end > 0 && end < begin {
+        std::mem::swap(&mut begin, &mut end);
+    }
+    Ok((begin, end))
+}
+
+/// Which side of a range is being parsed, with the line searches start from
+#[derive(Clone, Copy)]
+enum Side {
+    Start(usize),
+    End(usize),
+}
+
+/// Parses a line number, `/regex/` or, for the end, `+<count>`/`-<count>` at the start of
+/// `spec` into `line`, returning what follows it
+fn location<'s>(
+    spec: &'s str,
+    lines: &[&[u8]],
+    side: Side,
+    line: &mut usize,
+) -> anyhow::Result<&'s str> {
+    let digits = |s: &str| s.bytes().take_while(u8::is_ascii_digit).count();
+    if let Side::End(begin) = side {
+        if let Some(sign @ ('+' | '-')) = spec.chars().next() {
+            let len = digits(&spec[1..]);
+            if len == 0 {
+                return Ok(spec);
+            }
+            let count: usize = spec[1..=len].parse()?;
+            anyhow::ensure!(count > 0, "-L invalid empty range");
+            *line = if sign == '+' {
+                begin + count - 2
+            } else {
+                begin.saturating_sub(count).max(1)
+            };
+            return Ok(&spec[1 + len..]);
+        }
+    }
+    let len = digits(spec);
+    if len > 0 {
+        let number: usize = spec[..len].parse()?;
+        anyhow::ensure!(number > 0, "-L invalid line number: {number}");
+        *line = number;
+        return Ok(&spec[len..]);
+    }
+
+    let (mut from, spec) = match side {
+        Side::End(begin) => (begin, spec),
+        Side::Start(anchor) => match spec.strip_prefix('^') {
+            Some(rest) => (1, rest),
+            None => (anchor, spec),
+        },
+    };
+    let Some(body) = spec.strip_prefix('/') else {
+        return Ok(spec);
+    };
+    let mut end = None;
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '/' => {
+                end = Some(i);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let Some(end) = end else {
+        return Ok(spec);
+    };
+    let pattern = &body[..end];
+    let regex = grep::compile(pattern, PatternType::Basic, false)?;
+    from -= 1;
+    let found = lines[from.min(lines.len())..]
+        .iter()
+        .position(|text| regex.is_match(&String::from_utf8_lossy(text)))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "-L parameter '{pattern}' starting at line {}: No match",
+                from + 1
+            )
+        })?;
+    *line = from + found + 1;
+    Ok(&body[end + 1..])
+}
+
+/// `:<funcname>`: from the first function line matching the regex up to the next function line
+fn funcname(spec: &str, lines: &[&[u8]], anchor: usize) -> anyhow::Result<(usize, usize)> {
+    let (anchor, spec) = match spec.strip_prefix('^') {
+        Some(rest) => (1, rest),
+        None => (anchor, spec),
+    };
+    let body = &spec[1..];
+    let mut end = body.len();
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            ':' => {
+                end = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+    anyhow::ensure!(end > 0 && end == body.len(), "invalid -L argument '{spec}'");
+    let pattern = &body[..end];
+    let regex = grep::compile(pattern, PatternType::Basic, false)?;
+    let from = anchor - 1;
+    let begin = lines[from.min(lines.len())..]
+        .iter()
+        .position(|text| is_function_line(text) && regex.is_match(&String::from_utf8_lossy(text)))
+        .map(|found| from + found)
+        .ok_or_else(|| {
+            anyhow::anyhow!("-L parameter '{pattern}' starting at line {anchor}: no match")
+        })?;
+    anyhow::ensure!(
+        begin < lines.len(),
+        "-L parameter '{pattern}' matches at EOF"
+    );
+    let end = lines[begin + 1..]
+        .iter()
+        .position(|text| is_function_line(text))
+        .map_or(lines.len(), |found| begin + 1 + found);
+    Ok((begin + 1, end))
+}
+
+/// git's default notion of where a function starts: a line beginning with a letter, `_` or `$`
+fn is_function_line(line: &[u8]) -> bool {
+    line.first()
+        .is_some_and(|&c| c.is_ascii_alphabetic() || c == b'_' || c == b'$')
+}
@@ -0,0 +1,540 @@
+//! Commits as emails and back, for `format-patch` and `am`: RFC 2822 headers with RFC 2047
+//! encoded words where they aren't plain ASCII, mailbox splitting, and taking the author,
+//! message and patch out of a mail the way `git mailinfo` does.
+
+use anyhow::Context;
+
+use crate::{
+    date::{self, DateMode},
+    object::commit::Signature,
+};
+
+/// The date on the `From ` line starting each mail in a mailbox, the same for all patches
+const MBOX_DATE: &str = "Mon Sep 17 00:00:00 2001";
+
+/// Columns a header line is kept within
+const MAX_LINE: usize = 78;
+
+/// Columns an encoded word line is kept within
+const MAX_ENCODED_LINE: usize = 76;
+
+/// Writes the headers of a mail, up to and including the blank line before the body.
+/// `subject_prefix` (such as `[PATCH 1/2]`) goes before the subject, if not empty, and
+/// `eight_bit` declares a body that isn't plain ASCII.
+pub(crate) fn write_headers(
+    out: &mut String,
+    hash: &[u8; 20],
+    author: &Signature,
+    subject_prefix: &str,
+    subject: &str,
+    eight_bit: bool,
+) {
+    out.push_str(&format!("From {} {MBOX_DATE}\n", hex::encode(hash)));
+    out.push_str(&format!(
+        "From: {} <{}>\n",
+        address_name(&author.name),
+        author.email
+    ));
+    out.push_str(&format!(
+        "Date: {}\n",
+        date::format(author.time, author.tz_offset, DateMode::Rfc)
+    ));
+    let mut header = String::from("Subject: ");
+    if !subject_prefix.is_empty() {
+        header.push_str(subject_prefix);
+        header.push(' ');
+    }
+    if needs_encoding(subject) {
+        encode_word(&mut header, subject, false);
+    } else {
+        wrap_header(&mut header, subject);
+    }
+    out.push_str(&header);
+    out.push('\n');
+    if eight_bit {
+        out.push_str("MIME-Version: 1.0\n");
+        out.push_str("Content-Type: text/plain; charset=UTF-8\n");
+        out.push_str("Content-Transfer-Encoding: 8bit\n");
+    }
+    out.push('\n');
+}
+
+/// The display name of an address: encoded if it isn't ASCII, quoted if it has characters
+/// with a meaning in addresses
+fn address_name(name: &str) -> String {
+    if needs_encoding(name) {
+        let mut out = String::from("From: ");
+        encode_word(&mut out, name, true);
+        return out["From: ".len()..].to_string();
+    }
+    if name.contains(|c| "()<>@,;:\\\".[]".contains(c)) {
+        let escaped = name.replace('\\', "\\\\").replace('"', "\\\"");
+        return format!("\"{escaped}\"");
+    }
+    name.to_string()
+}
+
+fn needs_encoding(text: &str) -> bool {
+    !text.is_ascii() || text.contains("=?")
+}
+
+/// Appends `text` to a header as `=?UTF-8?q?...?=` encoded words, starting new lines to stay
+/// within the line length. Phrases in addresses encode more characters than subjects do.
+fn encode_word(out: &mut String, text: &str, address: bool) {
+    let mut line_len = out.len() - out.rfind('\n').map_or(0, |at| at + 1);
+    out.push_str("=?UTF-8?q?");
+    line_len += "=?UTF-8?q?".len();
+    // Lines break between characters, never inside one
+    for c in text.chars() {
+        let special = !c.is_ascii_graphic()
+            || matches!(c, '=' | '?' | '_')
+            || (address && !(c.is_ascii_alphanumeric() || "!*+-/".contains(c)));
+        let width = if special { 3 * c.len_utf8() } else { 1 };
+        if line_len + 2 + width > MAX_ENCODED_LINE {
+            out.push_str("?=\n =?UTF-8?q?");
+            line_len = " =?UTF-8?q?".len();
+        }
+        if special {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                out.push_str(&format!("={byte:02X}"));
+            }
+        } else {
+            out.push(c);
+        }
+        line_len += width;
+    }
+    out.push_str("?=");
+}
+
+/// Appends `text` to a header, folding it at spaces onto lines indented by one space so that
+/// none is longer than [`MAX_LINE`] where that can be helped
+fn wrap_header(out: &mut String, text: &str) {
+    let mut column = out.len() - out.rfind('\n').map_or(0, |at| at + 1);
+    let mut first = true;
+    for word in text.split(' ') {
+        let width = word.chars().count();
+        if first {
+            if column + width > MAX_LINE && column > 0 {
+                out.push_str("\n ");
+                column = 1;
+            }
+        } else if column + 1 + width > MAX_LINE {
+            out.push_str("\n ");
+            column = 1;
+        } else {
+            out.push(' ');
+            column += 1;
+        }
+        out.push_str(word);
+        column += width;
+        first = false;
+    }
+}
+
+/// Splits a mailbox into its mails at the `From ` lines starting them. Content that doesn't
+/// start with one is taken as a single mail.
+pub(crate) fn split_mbox(content: &[u8]) -> Vec<&[u8]> {
+    let mut mails = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        if is_from_line(line) && offset > start {
+            mails.push(&content[start..offset]);
+            start = offset;
+        }
+        offset += line.len();
+    }
+    if offset > start {
+        mails.push(&content[start..]);
+    }
+    mails
+}
+
+/// Whether a line starts a mail in a mailbox: `From ` followed by anything ending in a date
+/// with a time of day and a year, as git's mailsplit checks
+fn is_from_line(line: &[u8]) -> bool {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    if line.len() < 20 || !line.starts_with(b"From ") {
+        return false;
+    }
+    let Some(colon) = line[5..].iter().rposition(|&b| b == b':').map(|at| at + 5) else {
+        return false;
+    };
+    let digit = |at: Option<usize>| {
+        at.and_then(|at| line.get(at))
+            .is_some_and(u8::is_ascii_digit)
+    };
+    if !(digit(colon.checked_sub(4))
+        && digit(colon.checked_sub(2))
+        && digit(colon.checked_sub(1))
+        && digit(Some(colon + 1))
+        && digit(Some(colon + 2)))
+    {
+        return false;
+    }
+    let year: String = line[colon + 3..]
+        .iter()
+        .skip_while(|b| b.is_ascii_whitespace())
+        .take_while(|b| b.is_ascii_digit())
+        .map(|&b| b as char)
+        .collect();
+    year.parse::<u32>().is_ok_and(|year| year > 90)
+}
+
+/// What a mail carries for a commit
+#[derive(Debug, Clone)]
+pub(crate) struct MailInfo {
+    pub(crate) author_name: String,
+    pub(crate) author_email: String,
+    pub(crate) date: Option<String>,
+    pub(crate) subject: String,
+    /// The message body after the subject, up to where the patch starts
+    pub(crate) body: String,
+    pub(crate) patch: Vec<u8>,
+}
+
+impl MailInfo {
+    /// The commit message: the subject, then the body set apart by a blank line
+    pub(crate) fn message(&self) -> String {
+        let body = stripspace(&self.body);
+        if body.is_empty() {
+            format!("{}\n", self.subject)
+        } else {
+            format!("{}\n\n{body}", self.subject)
+        }
+    }
+
+    /// The author named by the `From` and `Date` headers, dated now if the mail has no date
+    pub(crate) fn author(&self) -> anyhow::Result<Signature> {
+        let mut author = Signature::current("AUTHOR")?;
+        author.name = self.author_name.clone();
+        author.email = self.author_email.clone();
+        if let Some(date) = &self.date {
+            (author.time, author.tz_offset) = date::parse_date(date)?;
+        }
+        Ok(author)
+    }
+}
+
+/// Takes a mail apart: `From`, `Date` and `Subject` from its headers or, overriding them,
+/// from the start of the body; the message up to the line starting the patch; and the patch.
+/// Unless `keep_subject` is set, `Re:` and bracketed prefixes such as `[PATCH 1/2]` are
+/// dropped from the subject.
+pub(crate) fn parse(mail: &[u8], keep_subject: bool) -> anyhow::Result<MailInfo> {
+    let text = String::from_utf8_lossy(mail);
+    let mut lines = text.split_inclusive('\n').peekable();
+    if lines
+        .peek()
+        .is_some_and(|line| is_from_line(line.as_bytes()))
+    {
+        lines.next();
+    }
+
+    let mut headers = Vec::new();
+    let mut transfer_encoding = String::new();
+    for (name, value) in read_headers(&mut lines) {
+        if name.eq_ignore_ascii_case("content-transfer-encoding") {
+            transfer_encoding = value.trim().to_ascii_lowercase();
+        } else {
+            headers.push((name, value));
+        }
+    }
+    let rest: String = lines.collect();
+    let body = match transfer_encoding.as_str() {
+        "quoted-printable" => decode_quoted_printable(&rest),
+        "base64" => decode_base64(&rest).context("Invalid base64 body")?,
+        _ => rest,
+    };
+
+    // Headers repeated at the start of the body take precedence
+    let mut body_lines = body.split_inclusive('\n').peekable();
+    while body_lines.peek().is_some_and(|line| line.trim().is_empty()) {
+        body_lines.next();
+    }
+    let in_body = body_lines.peek().is_some_and(|line| {
+        ["From:", "Subject:", "Date:"]
+            .iter()
+            .any(|name| line.starts_with(name))
+    });
+    if in_body {
+        headers.extend(read_headers(&mut body_lines));
+    }
+
+    let mut info = MailInfo {
+        author_name: String::new(),
+        author_email: String::new(),
+        date: None,
+        subject: String::new(),
+        body: String::new(),
+        patch: Vec::new(),
+    };
+    for (name, value) in headers {
+        let value = decode_words(&value);
+        match name.to_ascii_lowercase().as_str() {
+            "from" => (info.author_name, info.author_email) = parse_address(&value),
+            "date" => info.date = Some(value.trim().to_string()),
+            "subject" => info.subject = value.trim().to_string(),
+            _ => {}
+        }
+    }
+    if !keep_subject {
+        info.subject = cleanup_subject(&info.subject);
+    }
+
+    let mut in_patch = false;
+    let mut patch = String::new();
+    for line in body_lines {
+        if !in_patch && is_patch_break(line) {
+            in_patch = true;
+        }
+        if in_patch {
+            patch.push_str(line);
+        } else {
+            info.body.push_str(line);
+        }
+    }
+    info.patch = patch.into_bytes();
+    Ok(info)
+}
+
+/// Reads `Name: value` header lines, unfolding continuation lines, up to a blank line or one
+/// that isn't a header
+fn read_headers<'a>(
+    lines: &mut std::iter::Peekable<impl Iterator<Item = &'a str>>,
+) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    while let Some(line) = lines.peek() {
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.is_empty() {
+            lines.next();
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push_str(line);
+                lines.next();
+                continue;
+            }
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            break;
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            break;
+        }
+        headers.push((name.to_string(), value.trim_start().to_string()));
+        lines.next();
+    }
+    headers
+}
+
+/// The name and email of an address given as `Name <email>`, `email (Name)` or just `email`
+fn parse_address(value: &str) -> (String, String) {
+    let value = value.trim();
+    let (name, email) = if let Some((name, rest)) = value.split_once('<') {
+        let email = rest.split_once('>').map_or(rest, |(email, _)| email);
+        (name.trim().to_string(), email.trim().to_string())
+    } else if let Some((email, rest)) = value.split_once('(') {
+        let name = rest.rsplit_once(')').map_or(rest, |(name, _)| name);
+        (name.trim().to_string(), email.trim().to_string())
+    } else {
+        (String::new(), value.to_string())
+    };
+    let name = match name
+        .strip_prefix('"')
+        .and_then(|name| name.strip_suffix('"'))
+    {
+        Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => name,
+    };
+    // Without a name, the part of the email before the `@` stands in for it
+    let name = if name.is_empty() {
+        email.split('@').next().unwrap_or_default().to_string()
+    } else {
+        name
+    };
+    (name, email)
+}
+
+/// Drops `Re:` and bracketed prefixes, along with whitespace and colons around them, from the
+/// start of a subject
+fn cleanup_subject(subject: &str) -> String {
+    let mut subject = subject;
+    loop {
+        let lower = subject.get(..3).map(str::to_ascii_lowercase);
+        if lower.as_deref() == Some("re:") && subject.len() > 3 {
+            subject = &subject[3..];
+        } else if subject.starts_with([' ', '\t', ':']) {
+            subject = &subject[1..];
+        } else if let (true, Some(end)) = (subject.starts_with('['), subject.find(']')) {
+            subject = &subject[end + 1..];
+        } else {
+            break;
+        }
+    }
+    subject.trim().to_string()
+}
+
+/// Whether a line starts the patch: a `---` line on its own or before a file name, or a diff
+fn is_patch_break(line: &str) -> bool {
+    if let Some(rest) = line.strip_prefix("---") {
+        if rest.starts_with(' ') && !rest[1..].starts_with(char::is_whitespace) {
+            return true;
+        }
+        return rest.trim().is_empty();
+    }
+    line.starts_with("diff -") || line.starts_with("Index: ")
+}
+
+/// Decodes RFC 2047 encoded words (`=?charset?q?...?=` and `=?charset?b?...?=`). Whitespace
+/// between two encoded words is dropped.
+fn decode_words(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let decoded = rest[start + 2..]
+            .split_once('?')
+            .and_then(|(charset, rest)| {
+                let (encoding, rest) = rest.split_once('?')?;
+                let (text, rest) = rest.split_once("?=")?;
+                let bytes = match encoding {
+                    "q" | "Q" => decode_q(text),
+                    "b" | "B" => decode_base64(text).ok()?.into_bytes(),
+                    _ => return None,
+                };
+                Some((to_utf8(&bytes, charset), rest))
+            });
+        let Some((decoded, after)) = decoded else {
+            out.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            after_word = false;
+            continue;
+        };
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            out.push_str(between);
+        }
+        out.push_str(&decoded);
+        rest = after;
+        after_word = true;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Decodes the `q` encoding of encoded words, where `_` stands for a space
+fn decode_q(text: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'_' => out.push(b' '),
+            b'=' => {
+                if let Some(byte) = text
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    out.push(byte);
+                    i += 3;
+                    continue;
+                }
+                out.push(b'=');
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    out
+}
+
+fn decode_quoted_printable(text: &str) -> String {
+    let mut out = Vec::new();
+    for line in text.split_inclusive('\n') {
+        let (line, newline) = match line.strip_suffix('\n') {
+            Some(line) => (line.trim_end_matches('\r'), true),
+            None => (line, false),
+        };
+        // A trailing `=` joins the line to the next
+        let (line, soft) = match line.trim_end().strip_suffix('=') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        let bytes = line.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'=' {
+                if let Some(byte) = line
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    out.push(byte);
+                    i += 3;
+                    continue;
+                }
+            }
+            out.push(bytes[i]);
+            i += 1;
+        }
+        if newline && !soft {
+            out.push(b'\n');
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn decode_base64(text: &str) -> anyhow::Result<String> {
+    let mut out = Vec::new();
+    let mut value = 0u32;
+    let mut bits = 0;
+    for byte in text.bytes() {
+        let digit = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ if byte.is_ascii_whitespace() => continue,
+            _ => anyhow::bail!("invalid base64 character '{}'", byte as char),
+        };
+        value = value << 6 | u32::from(digit);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((value >> bits) as u8);
+        }
+    }
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+/// Text in a mail's charset as UTF-8; Latin-1 is converted, anything else taken as UTF-8
+fn to_utf8(bytes: &[u8], charset: &str) -> String {
+    if charset.eq_ignore_ascii_case("iso-8859-1") || charset.eq_ignore_ascii_case("latin1") {
+        return bytes.iter().map(|&b| b as char).collect();
+    }
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Strips trailing whitespace from lines, squeezing runs of blank lines and dropping those at
+/// either end, as `git stripspace` does without touching comment lines
+fn stripspace(text: &str) -> String {
+    let mut out = String::new();
+    let mut blank = false;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
@@ -1,5 +1,5 @@
 use clap::Parser;
-use git_rust::commands::Command;
+use git_rust::commands::{self, Command};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -9,6 +9,18 @@ struct Cli {
 }
 
 fn main() -> anyhow::Result<()> {
-    let args = Cli::parse();
-    args.command.execute()
+    let args = Cli::parse_from(commands::normalize_args(std::env::args()));
+    match args.command.execute() {
+        // The reader went away early, as `| head` does, which is not the command failing
+        Err(error) if is_broken_pipe(&error) => Ok(()),
+        result => result,
+    }
+}
+
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|error| error.kind() == std::io::ErrorKind::BrokenPipe)
+    })
 }
@@ -0,0 +1,939 @@
+//! Three-way merges of trees, as `git merge` does them: paths only one side changed take that
+//! side's version, files both sides changed get their contents merged line by line, and what
+//! can't be merged is left as a conflict, written with markers into the merged tree and
+//! recorded as stages 1 to 3 in the index.
+//!
+//! Renames between the base and each side are detected first, so that changes follow a file
+//! to its new name, and a directory one side renamed as a whole takes along the files the other
+//! side added to it. Histories with several merge bases get them merged into a virtual
+//! ancestor first, as the ort strategy does.
+
+pub(crate) mod content;
+pub(crate) mod octopus;
+pub(crate) mod subtree;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    diff::{
+        patch::{abbrev, is_binary, FilePair, FileSide},
+        rename::{self, RenameOptions},
+    },
+    index::{Index, IndexEntry},
+    merge::content::{Favor, FileOptions, Labels},
+    merge_base::CommitGraph,
+    object::{
+        commit::Commit,
+        read::read_object_of_kind,
+        write::{hash_object, write_tree_from_paths},
+        ObjectKind, MODE_GITLINK, MODE_SYMLINK,
+    },
+    unpack::{self, tree_blobs, Blob, UnpackOptions},
+};
+
+/// `merge.renameLimit` defaults to more than `diff.renameLimit`, as a missed rename costs a merge
+/// more than a diff
+const RENAME_LIMIT: usize = 7000;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MergeOptions<'a> {
+    /// Names of the sides for conflict markers and messages
+    pub(crate) labels: Labels<'a>,
+    pub(crate) content: FileOptions,
+    /// Shift their tree (and the base) to line up with ours before merging, by this prefix or,
+    /// when it is empty, to wherever it matches best
+    pub(crate) subtree_shift: Option<&'a str>,
+}
+
+/// The outcome of merging two trees
+#[derive(Debug)]
+pub(crate) struct TreeMerge {
+    /// The merged tree, conflicted files included with their markers
+    pub(crate) tree: [u8; 20],
+    /// The base, our and their versions of each conflicted path
+    pub(crate) conflicts: BTreeMap<String, [Option<Blob>; 3]>,
+    /// What happened to the paths worth mentioning, in path order
+    pub(crate) messages: Vec<String>,
+}
+
+impl TreeMerge {
+    pub(crate) fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// A commit taking part in a recursive merge: a real one, or a virtual ancestor standing for
+/// the commits it was merged from
+struct MergeSide {
+    tree: Option<[u8; 20]>,
+    commits: Vec<[u8; 20]>,
+}
+
+impl MergeSide {
+    fn read(commit: &[u8; 20]) -> anyhow::Result<MergeSide> {
+        Ok(MergeSide {
+            tree: Some(Commit::read(commit)?.tree),
+            commits: vec![*commit],
+        })
+    }
+}
+
+/// Merges commits `ours` and `theirs` given their merge bases, labelling the base after them.
+/// Several bases are merged two at a time into a virtual ancestor first, oldest first and
+/// conflicts included, and the merges of those look for their own bases the same way.
+pub(crate) fn merge_commits(
+    ours: &[u8; 20],
+    theirs: &[u8; 20],
+    bases: &[[u8; 20]],
+    opts: MergeOptions,
+) -> anyhow::Result<TreeMerge> {
+    let mut graph = CommitGraph::new();
+    let ours = MergeSide::read(ours)?;
+    let theirs = MergeSide::read(theirs)?;
+    merge_recursive(&mut graph, &ours, &theirs, Some(bases), opts, 0)
+}
+
+/// Merges the changes `ours` and `theirs` made since the tree `base`, which needn't be an
+/// ancestor of either: a cherry-pick merges in the changes between a commit and its parent,
+/// and a revert those between the commit's parent and the commit
+pub(crate) fn merge_tree_changes(
+    base: Option<&[u8; 20]>,
+    ours: &[u8; 20],
+    theirs: Option<&[u8; 20]>,
+    opts: MergeOptions,
+) -> anyhow::Result<TreeMerge> {
+    merge_trees(base, Some(ours), theirs, opts, 0)
+}
+
+fn merge_recursive(
+    graph: &mut CommitGraph,
+    ours: &MergeSide,
+    theirs: &MergeSide,
+    bases: Option<&[[u8; 20]]>,
+    opts: MergeOptions,
+    depth: usize,
+) -> anyhow::Result<TreeMerge> {
+    // Only the first merge is given its bases; in the others theirs is a single real commit
+    let bases = match bases {
+        Some(bases) => bases.to_vec(),
+        None => graph.merge_bases(&theirs.commits[0], &ours.commits)?,
+    };
+    let base_label = match bases.as_slice() {
+        [] => String::from("empty tree"),
+        [base] => abbrev(base),
+        _ => String::from("merged common ancestors"),
+    };
+    let mut bases = bases.iter().rev();
+    let mut merged = match bases.next() {
+        Some(base) => MergeSide::read(base)?,
+        None => MergeSide {
+            tree: None,
+            commits: Vec::new(),
+        },
+    };
+    for base in bases {
+        let next = MergeSide::read(base)?;
+        let inner = MergeOptions {
+            labels: Labels {
+                ours: "Temporary merge branch 1",
+                base: "",
+                theirs: "Temporary merge branch 2",
+            },
+            content: FileOptions {
+                favor: Favor::None,
+                ..opts.content
+            },
+            ..opts
+        };
+        let result = merge_recursive(graph, &merged, &next, None, inner, depth + 1)?;
+        merged.tree = Some(result.tree);
+        merged.commits.extend(next.commits);
+    }
+
+    let opts = MergeOptions {
+        labels: Labels {
+            base: &base_label,
+            ..opts.labels
+        },
+        ..opts
+    };
+    let (mut base, mut theirs) = (merged.tree, theirs.tree);
+    if let (Some(prefix), Some(ours)) = (opts.subtree_shift, &ours.tree) {
+        if let Some(tree) = &base {
+            base = Some(subtree::shift_tree(ours, tree, prefix)?);
+        }
+        if let Some(tree) = &theirs {
+            theirs = Some(subtree::shift_tree(ours, tree, prefix)?);
+        }
+    }
+    merge_trees(base.as_ref(), ours.tree.as_ref(), theirs.as_ref(), opts, depth)
+}
+
+/// One path of the merge: what the base and each side have there, renames followed
+#[derive(Debug, Clone, Default)]
+struct Entry {
+    stages: [Option<Blob>; 3],
+    /// Where each version was found, when a rename brought it here
+    paths: [Option<String>; 3],
+    /// A rename conflict involves the path, so it stays unmerged however its content merges
+    path_conflict: bool,
+}
+
+/// Paths and their merged versions as the merge goes along
+struct Merger<'a> {
+    opts: MergeOptions<'a>,
+    /// Nesting of the merge, merges building a virtual ancestor being inner ones
+    depth: usize,
+    ours: BTreeMap<String, Blob>,
+    result: BTreeMap<String, Blob>,
+    conflicts: BTreeMap<String, [Option<Blob>; 3]>,
+    messages: BTreeMap<String, Vec<String>>,
+}
+
+/// Merges the changes `ours` and `theirs` made since `base`, storing the merged tree and any
+/// files merged along the way
+fn merge_trees(
+    base: Option<&[u8; 20]>,
+    ours: Option<&[u8; 20]>,
+    theirs: Option<&[u8; 20]>,
+    opts: MergeOptions,
+    depth: usize,
+) -> anyhow::Result<TreeMerge> {
+    let sides = [tree_blobs(base)?, tree_blobs(ours)?, tree_blobs(theirs)?];
+    let mut merger = Merger {
+        opts,
+        depth,
+        ours: sides[1].clone(),
+        result: BTreeMap::new(),
+        conflicts: BTreeMap::new(),
+        messages: BTreeMap::new(),
+    };
+
+    let mut entries: BTreeMap<String, Entry> = BTreeMap::new();
+    for (side, blobs) in sides.iter().enumerate() {
+        for (path, blob) in blobs {
+            entries.entry(path.clone()).or_default().stages[side] = Some(*blob);
+        }
+    }
+    let mut renames = [BTreeMap::new(), find_renames(&sides, 1)?, find_renames(&sides, 2)?];
+    let mut ours_dirs = merger.find_dir_renames(&sides, 1, &renames[1]);
+    let mut theirs_dirs = merger.find_dir_renames(&sides, 2, &renames[2]);
+    // A directory both sides renamed leaves nothing for either to follow
+    let both: Vec<String> = ours_dirs
+        .keys()
+        .filter(|dir| theirs_dirs.contains_key(*dir))
+        .cloned()
+        .collect();
+    for dir in both {
+        ours_dirs.remove(&dir);
+        theirs_dirs.remove(&dir);
+    }
+    merger.follow_dir_renames(&sides, &mut entries, &mut renames, 1, &ours_dirs);
+    merger.follow_dir_renames(&sides, &mut entries, &mut renames, 2, &theirs_dirs);
+    merger.follow_renames(&mut entries, &renames)?;
+
+    for (path, entry) in entries {
+        merger.merge_entry(path, entry)?;
+    }
+    merger.move_files_out_of_the_way();
+
+    let tree = write_tree_from_paths(&merger.result)?;
+    Ok(TreeMerge {
+        tree,
+        conflicts: merger.conflicts,
+        messages: merger.messages.into_values().flatten().collect(),
+    })
+}
+
+/// Kind of entry: regular file, symlink or submodule
+fn file_type(mode: u32) -> u32 {
+    mode >> 12
+}
+
+fn is_regular(mode: u32) -> bool {
+    mode != MODE_SYMLINK && mode != MODE_GITLINK
+}
+
+/// The directory a path is in, `""` at the top
+fn dirname(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit_once('/').map_or(path, |(_, name)| name)
+}
+
+/// The directories a path is in, outermost first
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(move |(i, _)| &path[..i])
+}
+
+/// Directories of the base that are gone from `side`
+fn removed_dirs<'a>(
+    base: &'a BTreeMap<String, Blob>,
+    side: &BTreeMap<String, Blob>,
+) -> BTreeSet<&'a str> {
+    let side_dirs: BTreeSet<&str> = side.keys().flat_map(|path| ancestors(path)).collect();
+    base.keys()
+        .flat_map(|path| ancestors(path))
+        .filter(|dir| !side_dirs.contains(dir))
+        .collect()
+}
+
+/// Directories gone from `side` that the other side added files to. Only these may have been
+/// renamed as a whole as far as the merge cares; files added deeper down do not count.
+fn dirs_added_to(sides: &[BTreeMap<String, Blob>; 3], side: usize) -> BTreeSet<&str> {
+    let (base, other) = (&sides[0], &sides[3 - side]);
+    let removed = removed_dirs(base, &sides[side]);
+    other
+        .keys()
+        .filter(|path| !base.contains_key(*path))
+        .map(|path| dirname(path))
+        .filter(|dir| removed.contains(dir))
+        .collect()
+}
+
+/// Renames from the base to side `side`, as a map from old to new path. Only the deleted files
+/// that matter to the merge are looked for among similar files: those the other side changed
+/// or deleted too, and those under a directory gone from this side that the other side added
+/// files to. When there are any, the rest are still paired with identical added files.
+fn find_renames(
+    sides: &[BTreeMap<String, Blob>; 3],
+    side: usize,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let (base, ours, other) = (&sides[0], &sides[side], &sides[3 - side]);
+    let added_to = dirs_added_to(sides, side);
+    let deleted: BTreeMap<&String, &Blob> = base
+        .iter()
+        .filter(|(path, _)| !ours.contains_key(*path))
+        .collect();
+    let relevant: BTreeSet<&String> = deleted
+        .iter()
+        .filter(|(path, blob)| {
+            other.get(**path) != Some(**blob) || ancestors(path).any(|dir| added_to.contains(dir))
+        })
+        .map(|(path, _)| *path)
+        .collect();
+    if relevant.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    let added: BTreeMap<&String, &Blob> = ours
+        .iter()
+        .filter(|(path, _)| !base.contains_key(*path))
+        .collect();
+
+    let mut renames: BTreeMap<String, String> =
+        detect_renames(deleted.clone(), added.clone(), rename::MAX_SCORE)?
+            .into_iter()
+            .collect();
+    let targets: BTreeSet<&String> = renames.values().collect();
+    let inexact = detect_renames(
+        deleted
+            .into_iter()
+            .filter(|(path, _)| relevant.contains(path) && !renames.contains_key(*path))
+            .collect(),
+        added
+            .into_iter()
+            .filter(|(path, _)| !targets.contains(path))
+            .collect(),
+        rename::DEFAULT_SCORE,
+    )?;
+    renames.extend(inexact);
+    Ok(renames)
+}
+
+fn detect_renames(
+    sources: BTreeMap<&String, &Blob>,
+    targets: BTreeMap<&String, &Blob>,
+    min_score: u32,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let sources = sources.into_iter().map(|(path, &(mode, hash))| {
+        FilePair::new(Some(FileSide::from_object(path, mode, hash)), None)
+    });
+    let targets = targets.into_iter().map(|(path, &(mode, hash))| {
+        FilePair::new(None, Some(FileSide::from_object(path, mode, hash)))
+    });
+    let opts = RenameOptions {
+        copies: false,
+        min_score,
+        limit: RENAME_LIMIT,
+    };
+    Ok(rename::detect(sources.chain(targets).collect(), &opts)?
+        .into_iter()
+        .filter(|pair| pair.rename.is_some())
+        .filter_map(|pair| Some((pair.old?.path, pair.new?.path)))
+        .collect())
+}
+
+impl<'a> Merger<'a> {
+    /// Records a message about `path`; inner merges keep quiet
+    fn say(&mut self, path: &str, message: String) {
+        if self.depth > 0 {
+            return;
+        }
+        self.messages
+            .entry(path.to_string())
+            .or_default()
+            .push(message);
+    }
+
+    fn label(&self, side: usize) -> &'a str {
+        match side {
+            0 => self.opts.labels.base,
+            1 => self.opts.labels.ours,
+            _ => self.opts.labels.theirs,
+        }
+    }
+
+    /// Directories `side` renamed as a whole: those gone from it, and added to by the other
+    /// side, that most of the files renamed out of went to one directory. Renaming `a/b/f` to
+    /// `x/b/f` renames `a` to `x` as well.
+    fn find_dir_renames(
+        &mut self,
+        sides: &[BTreeMap<String, Blob>; 3],
+        side: usize,
+        renames: &BTreeMap<String, String>,
+    ) -> BTreeMap<String, String> {
+        // Following a directory rename is only ever a suggestion, which a virtual ancestor
+        // can't leave open, so inner merges keep files where they are
+        if self.depth > 0 {
+            return BTreeMap::new();
+        }
+        let removed = removed_dirs(&sides[0], &sides[side]);
+        let mut counts: BTreeMap<&str, BTreeMap<&str, usize>> = BTreeMap::new();
+        for (old, new) in renames {
+            let (mut old_dir, mut new_dir) = (dirname(old), dirname(new));
+            while removed.contains(old_dir) && old_dir != new_dir {
+                *counts.entry(old_dir).or_default().entry(new_dir).or_default() += 1;
+                if old_dir.is_empty() || basename(old_dir) != basename(new_dir) {
+                    break;
+                }
+                (old_dir, new_dir) = (dirname(old_dir), dirname(new_dir));
+            }
+        }
+
+        let added_to = dirs_added_to(sides, side);
+        let mut dir_renames = BTreeMap::new();
+        for (old_dir, targets) in counts {
+            if !added_to.contains(old_dir) {
+                continue;
+            }
+            let best = targets.values().max().copied().unwrap_or(0);
+            let mut winners = targets.iter().filter(|(_, &count)| count == best);
+            match (winners.next(), winners.next()) {
+                (Some((new_dir, _)), None) => {
+                    dir_renames.insert(old_dir.to_string(), new_dir.to_string());
+                }
+                _ => self.say(
+                    old_dir,
+                    format!(
+                        "CONFLICT (directory rename split): Unclear where to rename {old_dir} to; it was renamed to multiple other directories, with no destination getting a majority of the files."
+                    ),
+                ),
+            }
+        }
+        dir_renames
+    }
+
+    /// Moves what the other side added to directories `side` renamed along with them. The
+    /// move is only a suggestion, so the paths are left unmerged.
+    fn follow_dir_renames(
+        &mut self,
+        sides: &[BTreeMap<String, Blob>; 3],
+        entries: &mut BTreeMap<String, Entry>,
+        renames: &mut [BTreeMap<String, String>; 3],
+        side: usize,
+        dir_renames: &BTreeMap<String, String>,
+    ) {
+        if dir_renames.is_empty() {
+            return;
+        }
+        let other = 3 - side;
+        let added = sides[other]
+            .keys()
+            .filter(|path| !sides[0].contains_key(*path));
+        for path in added {
+            let Some(dir) = ancestors(path)
+                .filter(|dir| dir_renames.contains_key(*dir))
+                .last()
+            else {
+                continue;
+            };
+            let new_dir = &dir_renames[dir];
+            let rest = &path[dir.len() + 1..];
+            let new_path = if new_dir.is_empty() {
+                rest.to_string()
+            } else {
+                format!("{new_dir}/{rest}")
+            };
+            if entries
+                .get(&new_path)
+                .is_some_and(|entry| entry.stages[other].is_some())
+            {
+                continue;
+            }
+            let blob = entries
+                .get_mut(path)
+                .and_then(|entry| entry.stages[other].take());
+            let target = entries.entry(new_path.clone()).or_default();
+            target.stages[other] = blob;
+            target.paths[other] = Some(path.clone());
+            target.path_conflict = true;
+
+            let (other_label, side_label) = (self.label(other), self.label(side));
+            let source = renames[other]
+                .iter_mut()
+                .find(|(_, new)| *new == path);
+            let message = match source {
+                Some((old, new)) => {
+                    let message = format!(
+                        "CONFLICT (file location): {old} renamed to {path} in {other_label}, inside a directory that was renamed in {side_label}, suggesting it should perhaps be moved to {new_path}."
+                    );
+                    *new = new_path.clone();
+                    message
+                }
+                None => format!(
+                    "CONFLICT (file location): {path} added in {other_label} inside a directory that was renamed in {side_label}, suggesting it should perhaps be moved to {new_path}."
+                ),
+            };
+            self.say(&new_path, message);
+        }
+    }
+
+    /// Brings the base version, and the other side's version if it still has one, to where
+    /// each renamed file went
+    fn follow_renames(
+        &mut self,
+        entries: &mut BTreeMap<String, Entry>,
+        renames: &[BTreeMap<String, String>; 3],
+    ) -> anyhow::Result<()> {
+        let sources: BTreeSet<&String> = renames[1].keys().chain(renames[2].keys()).collect();
+        for old in sources {
+            let base = entries[old].stages[0];
+            match (renames[1].get(old), renames[2].get(old)) {
+                (Some(ours), Some(theirs)) if ours == theirs => {
+                    entries.get_mut(old).expect("a source").stages[0] = None;
+                    let entry = entries.get_mut(ours).expect("a rename target");
+                    entry.stages[0] = base;
+                    entry.paths[0] = Some(old.clone());
+                }
+                (Some(ours), Some(theirs)) => self.rename_rename(entries, old, ours, theirs)?,
+                (Some(new), None) => self.rename(entries, old, new, 1)?,
+                (None, Some(new)) => self.rename(entries, old, new, 2)?,
+                (None, None) => unreachable!("sources come from the renames"),
+            }
+        }
+        Ok(())
+    }
+
+    /// `side` renamed `old` to `new`
+    fn rename(
+        &mut self,
+        entries: &mut BTreeMap<String, Entry>,
+        old: &str,
+        new: &str,
+        side: usize,
+    ) -> anyhow::Result<()> {
+        let other = 3 - side;
+        let source = entries.get_mut(old).expect("a source");
+        let base = source.stages[0].take();
+        let theirs = source.stages[other].take();
+        let ours = entries[new].stages[side];
+        let Some(theirs) = theirs else {
+            let (side_label, other_label) = (self.label(side), self.label(other));
+            self.say(
+                new,
+                format!(
+                    "CONFLICT (rename/delete): {old} renamed to {new} in {side_label}, but deleted in {other_label}."
+                ),
+            );
+            let entry = entries.get_mut(new).expect("a rename target");
+            entry.stages[0] = base;
+            entry.paths[0] = Some(old.to_string());
+            entry.path_conflict = true;
+            return Ok(());
+        };
+
+        let target = entries.get_mut(new).expect("a rename target");
+        if target.stages[other].is_none() {
+            target.stages[0] = base;
+            target.stages[other] = Some(theirs);
+            target.paths[0] = Some(old.to_string());
+            target.paths[other] = Some(old.to_string());
+            return Ok(());
+        }
+        // The other side has something else there, which this rename is merged with as if both
+        // had been added
+        let (Some(base), Some(ours)) = (base, ours) else {
+            return Ok(());
+        };
+        let mut stages = [Some(base), None, None];
+        stages[side] = Some(ours);
+        stages[other] = Some(theirs);
+        let mut paths = [old.to_string(), old.to_string(), old.to_string()];
+        paths[side] = new.to_string();
+        let extra_marker_size = 1 + 2 * self.depth;
+        let (merged, clean) = self.merge_file(old, stages, &paths, extra_marker_size)?;
+        if !clean {
+            self.say(
+                new,
+                format!(
+                    "CONFLICT (rename involved in collision): rename of {old} -> {new} has content conflicts AND collides with another path; this may result in nested conflict markers."
+                ),
+            );
+        }
+        entries.get_mut(new).expect("a rename target").stages[side] = Some(merged);
+        Ok(())
+    }
+
+    /// Both sides renamed `old`, to different paths. Its contents are merged and recorded at
+    /// both, leaving the three paths unmerged.
+    fn rename_rename(
+        &mut self,
+        entries: &mut BTreeMap<String, Entry>,
+        old: &str,
+        ours_path: &str,
+        theirs_path: &str,
+    ) -> anyhow::Result<()> {
+        let base = entries[old].stages[0];
+        let ours = entries[ours_path].stages[1];
+        let theirs = entries[theirs_path].stages[2];
+        let paths = [
+            old.to_string(),
+            ours_path.to_string(),
+            theirs_path.to_string(),
+        ];
+        let extra_marker_size = 1 + 2 * self.depth;
+        let (merged, clean) =
+            self.merge_file(old, [base, ours, theirs], &paths, extra_marker_size)?;
+        let binary = !clean && ours == Some(merged);
+        entries.get_mut(old).expect("a source").path_conflict = true;
+        let ours_entry = entries.get_mut(ours_path).expect("a rename target");
+        ours_entry.stages[1] = Some(merged);
+        ours_entry.path_conflict = true;
+        let theirs_entry = entries.get_mut(theirs_path).expect("a rename target");
+        if !binary {
+            theirs_entry.stages[2] = Some(merged);
+        }
+        theirs_entry.path_conflict = true;
+
+        let (ours_label, theirs_label) = (self.label(1), self.label(2));
+        self.say(
+            old,
+            format!(
+                "CONFLICT (rename/rename): {old} renamed to {ours_path} in {ours_label} and to {theirs_path} in {theirs_label}."
+            ),
+        );
+        Ok(())
+    }
+
+    /// Where a path goes when something else has to be recorded at its place: `<path>~<side>`,
+    /// made unique if need be
+    fn unique_path(&self, path: &str, side: &str) -> String {
+        let base = format!("{path}~{}", side.replace('/', "_"));
+        let taken = |candidate: &String| {
+            self.result.contains_key(candidate) || self.conflicts.contains_key(candidate)
+        };
+        let mut candidate = base.clone();
+        let mut suffix = 0;
+        while taken(&candidate) {
+            candidate = format!("{base}_{suffix}");
+            suffix += 1;
+        }
+        candidate
+    }
+
+    fn merge_entry(&mut self, path: String, entry: Entry) -> anyhow::Result<()> {
+        let [o, a, b] = entry.stages;
+        let merged = if entry.path_conflict && (a.is_none() || b.is_none()) {
+            // A file renamed on one side and deleted on the other may have been modified too,
+            // which a virtual ancestor settles the same way as other modify/delete conflicts
+            let kept = a.or(b);
+            if o.is_none() || kept.is_none() || kept == o {
+                kept
+            } else if self.depth > 0 {
+                o
+            } else {
+                self.say_modify_delete(&path, a.is_none());
+                kept
+            }
+        } else if a == b || o == b {
+            a
+        } else if o == a {
+            b
+        } else {
+            match (a, b) {
+                (Some(a), Some(b)) if file_type(a.0) != file_type(b.0) => {
+                    self.record_distinct_types(&path, o, a, b);
+                    return Ok(());
+                }
+                (Some(a), Some(_)) => {
+                    let paths = entry
+                        .paths
+                        .map(|source| source.unwrap_or_else(|| path.clone()));
+                    let (merged, clean) =
+                        self.merge_file(&path, entry.stages, &paths, 2 * self.depth)?;
+                    if !clean {
+                        let reason = if a.0 == MODE_GITLINK {
+                            "submodule"
+                        } else if o.is_none() {
+                            "add/add"
+                        } else {
+                            "content"
+                        };
+                        self.say(&path, format!("CONFLICT ({reason}): Merge conflict in {path}"));
+                        self.conflicts.insert(path.clone(), entry.stages);
+                    }
+                    Some(merged)
+                }
+                // An inner merge can't choose between modifying and deleting, so the virtual
+                // ancestor keeps the base version
+                _ if self.depth > 0 => o,
+                _ => {
+                    self.say_modify_delete(&path, a.is_none());
+                    self.conflicts.insert(path.clone(), entry.stages);
+                    a.or(b)
+                }
+            }
+        };
+        if entry.path_conflict {
+            self.conflicts.entry(path.clone()).or_insert(entry.stages);
+        }
+        if let Some(merged) = merged {
+            self.result.insert(path, merged);
+        }
+        Ok(())
+    }
+
+    fn say_modify_delete(&mut self, path: &str, deleted_in_ours: bool) {
+        let labels = self.opts.labels;
+        let (deleted_in, modified_in) = if deleted_in_ours {
+            (labels.ours, labels.theirs)
+        } else {
+            (labels.theirs, labels.ours)
+        };
+        self.say(
+            path,
+            format!(
+                "CONFLICT (modify/delete): {path} deleted in {deleted_in} and modified in {modified_in}.  Version {modified_in} of {path} left in tree."
+            ),
+        );
+    }
+
+    /// Merges two versions of a file of the same type found at `paths`, returning the result
+    /// and whether it is free of conflicts
+    fn merge_file(
+        &mut self,
+        path: &str,
+        [o, a, b]: [Option<Blob>; 3],
+        paths: &[String; 3],
+        extra_marker_size: usize,
+    ) -> anyhow::Result<(Blob, bool)> {
+        let (Some(a), Some(b)) = (a, b) else {
+            anyhow::bail!("Merging {path} needs both sides");
+        };
+        let o_mode = o.map_or(0, |o| o.0);
+        let mut clean = true;
+        let mode = if a.0 == b.0 || a.0 == o_mode {
+            b.0
+        } else {
+            clean = b.0 == o_mode;
+            a.0
+        };
+
+        let favor = self.opts.content.favor;
+        let o_hash = o.map(|o| o.1);
+        let hash = if a.1 == b.1 || Some(a.1) == o_hash {
+            b.1
+        } else if Some(b.1) == o_hash {
+            a.1
+        } else if is_regular(a.0) {
+            // A base of another type has nothing to contribute
+            let base = match o {
+                Some(o) if file_type(o.0) == file_type(a.0) => read_blob(&o.1)?,
+                _ => Vec::new(),
+            };
+            let ours = read_blob(&a.1)?;
+            let theirs = read_blob(&b.1)?;
+            let (ours_label, theirs_label) = if paths.iter().all(|source| *source == paths[0]) {
+                (self.label(1).to_string(), self.label(2).to_string())
+            } else {
+                (
+                    format!("{}:{}", self.label(1), paths[1]),
+                    format!("{}:{}", self.label(2), paths[2]),
+                )
+            };
+            if is_binary(&base) || is_binary(&ours) || is_binary(&theirs) {
+                let hash = if self.depth > 0 {
+                    hash_object(ObjectKind::Blob, &base, true)?
+                } else if favor == Favor::Theirs {
+                    b.1
+                } else {
+                    a.1
+                };
+                if favor == Favor::None || self.depth > 0 {
+                    clean = false;
+                    self.say(
+                        path,
+                        format!(
+                            "warning: Cannot merge binary files: {path} ({ours_label} vs. {theirs_label})"
+                        ),
+                    );
+                }
+                self.say(path, format!("Auto-merging {path}"));
+                hash
+            } else {
+                self.say(path, format!("Auto-merging {path}"));
+                let base_label = if paths.iter().all(|source| *source == paths[0]) {
+                    self.label(0).to_string()
+                } else {
+                    format!("{}:{}", self.label(0), paths[0])
+                };
+                let labels = Labels {
+                    ours: &ours_label,
+                    base: &base_label,
+                    theirs: &theirs_label,
+                };
+                let opts = FileOptions {
+                    extra_marker_size,
+                    ..self.opts.content
+                };
+                let (merged, conflicts) = content::merge(&base, &ours, &theirs, labels, &opts);
+                if conflicts > 0 {
+                    clean = false;
+                }
+                hash_object(ObjectKind::Blob, &merged, true)?
+            }
+        } else if self.depth > 0 {
+            // Symlinks and submodules can't be merged, an inner merge keeps the base
+            clean = false;
+            o.filter(|o| file_type(o.0) == file_type(a.0))
+                .map_or(a.1, |o| o.1)
+        } else if a.0 == MODE_SYMLINK && favor != Favor::None {
+            if favor == Favor::Theirs {
+                b.1
+            } else {
+                a.1
+            }
+        } else {
+            // Neither can be merged, ours stays
+            clean = false;
+            a.1
+        };
+        Ok(((mode, hash), clean))
+    }
+
+    /// Both sides have something different at `path`, say a file and a symlink. Regular files
+    /// move aside to `<path>~<side>` so each side is recorded somewhere: ours if it is one,
+    /// otherwise theirs, or both when neither is.
+    fn record_distinct_types(&mut self, path: &str, o: Option<Blob>, a: Blob, b: Blob) {
+        let (move_ours, move_theirs) = if is_regular(a.0) {
+            (true, false)
+        } else if is_regular(b.0) {
+            (false, true)
+        } else {
+            (true, true)
+        };
+        let how = if move_ours && move_theirs {
+            "both of them"
+        } else {
+            "one of them"
+        };
+        self.say(
+            path,
+            format!(
+                "CONFLICT (distinct types): {path} had different types on each side; renamed {how} so each can be recorded somewhere."
+            ),
+        );
+        let same_type = |side: Blob| o.filter(|o| file_type(o.0) == file_type(side.0));
+        let labels = self.opts.labels;
+        let ours_path = if move_ours {
+            self.unique_path(path, labels.ours)
+        } else {
+            path.to_string()
+        };
+        self.result.insert(ours_path.clone(), a);
+        self.conflicts
+            .insert(ours_path, [same_type(a), Some(a), None]);
+        let theirs_path = if move_theirs {
+            self.unique_path(path, labels.theirs)
+        } else {
+            path.to_string()
+        };
+        self.result.insert(theirs_path.clone(), b);
+        self.conflicts
+            .insert(theirs_path, [same_type(b), None, Some(b)]);
+    }
+
+    /// Files that ended up where the other side has a directory move to `<path>~<side>`
+    fn move_files_out_of_the_way(&mut self) {
+        let in_the_way: Vec<String> = self
+            .result
+            .keys()
+            .filter(|path| {
+                let dir = format!("{path}/");
+                self.result
+                    .range(dir.clone()..)
+                    .next()
+                    .is_some_and(|(next, _)| next.starts_with(&dir))
+            })
+            .cloned()
+            .collect();
+        for path in in_the_way {
+            let blob = self.result.remove(&path).expect("listed from the result");
+            let from_ours = self.ours.get(&path) == Some(&blob);
+            let side = if from_ours {
+                self.opts.labels.ours
+            } else {
+                self.opts.labels.theirs
+            };
+            let new_path = self.unique_path(&path, side);
+            self.say(
+                &path,
+                format!(
+                    "CONFLICT (file/directory): directory in the way of {path} from {side}; moving it to {new_path} instead."
+                ),
+            );
+            let stages = self.conflicts.remove(&path).unwrap_or(if from_ours {
+                [None, Some(blob), None]
+            } else {
+                [None, None, Some(blob)]
+            });
+            self.conflicts.insert(new_path.clone(), stages);
+            self.result.insert(new_path, blob);
+        }
+    }
+}
+
+fn read_blob(hash: &[u8; 20]) -> anyhow::Result<Vec<u8>> {
+    read_object_of_kind(&hex::encode(hash), ObjectKind::Blob)
+}
+
+/// Moves the index and working tree from `head` to the merged tree, refusing to overwrite
+/// local changes, then records the conflicts as stages 1 to 3
+pub(crate) fn checkout(
+    index: &mut Index,
+    head: Option<&[u8; 20]>,
+    merge: &TreeMerge,
+) -> anyhow::Result<()> {
+    let opts = UnpackOptions {
+        update: true,
+        merging: true,
+        ..UnpackOptions::default()
+    };
+    unpack::two_way(index, head, Some(&merge.tree), opts)?;
+    for (path, stages) in &merge.conflicts {
+        index.remove(path);
+        for (stage, blob) in stages.iter().enumerate() {
+            if let Some((mode, hash)) = blob {
+                index.add(IndexEntry::new(
+                    path.clone(),
+                    *mode,
+                    *hash,
+                    stage as u16 + 1,
+                ));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,492 @@
+//! Three-way merges of file contents, after xdiff's merge: both sides are diffed against the
+//! base, changes made by only one side are taken as they are, and changes that overlap become
+//! conflicts, narrowed down to the lines where the sides really differ.
+
+use anyhow::Context;
+
+use crate::diff::{diff_lines, line_key, split_lines, Algorithm, Change, DiffOptions};
+
+/// Length of the `<<<<<<<`, `|||||||`, `=======` and `>>>>>>>` markers
+const MARKER_SIZE: usize = 7;
+
+/// Unchanged lines between two conflicts that are folded into one conflict instead
+const SIMPLIFY_GAP: i64 = 3;
+
+/// How conflicts are written out, `merge.conflictStyle`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ConflictStyle {
+    /// Both sides of each conflict
+    #[default]
+    Merge,
+    /// Both sides and the base they changed
+    Diff3,
+    /// Like diff3, with the lines both sides agree on moved out of the conflict
+    ZealousDiff3,
+}
+
+impl ConflictStyle {
+    pub(crate) fn parse(name: &str) -> Option<ConflictStyle> {
+        match name {
+            "merge" => Some(ConflictStyle::Merge),
+            "diff3" => Some(ConflictStyle::Diff3),
+            "zdiff3" => Some(ConflictStyle::ZealousDiff3),
+            _ => None,
+        }
+    }
+
+    /// `merge.conflictStyle`, `merge` by default
+    pub(crate) fn configured() -> anyhow::Result<ConflictStyle> {
+        match crate::config::read_repo_config().get("merge.conflictStyle") {
+            Some(name) => ConflictStyle::parse(name)
+                .with_context(|| format!("unknown style '{name}' given for 'merge.conflictstyle'")),
+            None => Ok(ConflictStyle::default()),
+        }
+    }
+}
+
+/// The side conflicting lines resolve to instead of being written as a conflict, `-X ours`
+/// and `-X theirs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Favor {
+    #[default]
+    None,
+    Ours,
+    Theirs,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct FileOptions {
+    pub(crate) style: ConflictStyle,
+    pub(crate) favor: Favor,
+    /// Lines differing only in the amount of whitespace count as unchanged
+    pub(crate) ignore_space_change: bool,
+    /// Added to the length of the markers, so that conflicts merged into a virtual ancestor
+    /// can be told apart from the ones around them
+    pub(crate) extra_marker_size: usize,
+}
+
+/// Names written after the conflict markers
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Labels<'a> {
+    pub(crate) ours: &'a str,
+    pub(crate) base: &'a str,
+    pub(crate) theirs: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resolution {
+    Conflict,
+    Ours,
+    Theirs,
+    /// Both sides made the same change, so the lines around it are taken from ours
+    Identical,
+}
+
+/// A region where at least one side changed the base: `chg0` base lines at `i0` became `chg1`
+/// lines at `i1` in ours and `chg2` lines at `i2` in theirs
+#[derive(Debug, Clone, Copy)]
+struct Chunk {
+    resolution: Resolution,
+    i0: i64,
+    chg0: i64,
+    i1: i64,
+    chg1: i64,
+    i2: i64,
+    chg2: i64,
+}
+
+/// The lines of the three versions being merged
+struct Sides<'a> {
+    base: Vec<&'a [u8]>,
+    ours: Vec<&'a [u8]>,
+    theirs: Vec<&'a [u8]>,
+    /// How lines are compared
+    diff: DiffOptions,
+}
+
+/// Merges the changes `ours` and `theirs` made to `base`, returning the result (with conflict
+/// markers) and the number of conflicts left in it
+pub(crate) fn merge(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: Labels,
+    opts: &FileOptions,
+) -> (Vec<u8>, usize) {
+    // The ort strategy diffs with the histogram algorithm
+    let sides = Sides {
+        base: split_lines(base),
+        ours: split_lines(ours),
+        theirs: split_lines(theirs),
+        diff: DiffOptions {
+            algorithm: Algorithm::Histogram,
+            minimal: false,
+            indent_heuristic: false,
+            ignore_space_change: opts.ignore_space_change,
+            ignore_all_space: false,
+        },
+    };
+    let ours_changes = diff_lines(&sides.base, &sides.ours, &sides.diff);
+    let theirs_changes = diff_lines(&sides.base, &sides.theirs, &sides.diff);
+    if ours_changes.is_empty() {
+        return (theirs.to_vec(), 0);
+    }
+    if theirs_changes.is_empty() {
+        return (ours.to_vec(), 0);
+    }
+
+    let mut chunks = sides.chunks(&ours_changes, &theirs_changes);
+    if opts.style == ConflictStyle::ZealousDiff3 {
+        sides.trim_conflicts(&mut chunks);
+    } else if opts.style == ConflictStyle::Merge {
+        chunks = sides.refine_conflicts(chunks);
+        simplify_conflicts(&mut chunks);
+    }
+    let favored = match opts.favor {
+        Favor::None => Resolution::Conflict,
+        Favor::Ours => Resolution::Ours,
+        Favor::Theirs => Resolution::Theirs,
+    };
+    for chunk in &mut chunks {
+        if chunk.resolution == Resolution::Conflict {
+            chunk.resolution = favored;
+        }
+    }
+
+    let conflicts = chunks
+        .iter()
+        .filter(|chunk| chunk.resolution == Resolution::Conflict)
+        .count();
+    (sides.fill(&chunks, labels, opts), conflicts)
+}
+
+/// Adds a chunk, folding it into the previous one when they overlap on either side
+fn append(chunks: &mut Vec<Chunk>, chunk: Chunk) {
+    if let Some(last) = chunks.last_mut() {
+        if chunk.i1 <= last.i1 + last.chg1 || chunk.i2 <= last.i2 + last.chg2 {
+            if chunk.resolution != last.resolution {
+                last.resolution = Resolution::Conflict;
+            }
+            last.chg0 = chunk.i0 + chunk.chg0 - last.i0;
+            last.chg1 = chunk.i1 + chunk.chg1 - last.i1;
+            last.chg2 = chunk.i2 + chunk.chg2 - last.i2;
+            return;
+        }
+    }
+    chunks.push(chunk);
+}
+
+/// Joins conflicts separated by only a few unchanged lines, which reads more easily than
+/// several small conflicts
+fn simplify_conflicts(chunks: &mut Vec<Chunk>) {
+    let mut i = 0;
+    while i + 1 < chunks.len() {
+        let (chunk, next) = (chunks[i], chunks[i + 1]);
+        let gap = next.i1 - (chunk.i1 + chunk.chg1);
+        if chunk.resolution != Resolution::Conflict
+            || next.resolution != Resolution::Conflict
+            || gap > SIMPLIFY_GAP
+        {
+            i += 1;
+            continue;
+        }
+        chunks[i].chg1 = next.i1 + next.chg1 - chunk.i1;
+        chunks[i].chg2 = next.i2 + next.chg2 - chunk.i2;
+        chunks.remove(i + 1);
+    }
+}
+
+impl Sides<'_> {
+    fn same_lines(&self, ours: &[&[u8]], theirs: &[&[u8]]) -> bool {
+        ours.len() == theirs.len()
+            && ours
+                .iter()
+                .zip(theirs)
+                .all(|(a, b)| line_key(a, &self.diff) == line_key(b, &self.diff))
+    }
+
+    /// Walks the two diffs against the base side by side, pairing up their changes
+    fn chunks(&self, ours: &[Change], theirs: &[Change]) -> Vec<Chunk> {
+        let span = |change: &Change| {
+            (
+                change.old_start as i64,
+                change.old_len as i64,
+                change.new_start as i64,
+                change.new_len as i64,
+            )
+        };
+        let mut chunks = Vec::new();
+        let (mut a, mut b) = (0, 0);
+        while a < ours.len() && b < theirs.len() {
+            let (o1, ochg1, n1, nchg1) = span(&ours[a]);
+            let (o2, ochg2, n2, nchg2) = span(&theirs[b]);
+            if o1 + ochg1 < o2 {
+                let chunk = Chunk {
+                    resolution: Resolution::Ours,
+                    i0: o1,
+                    chg0: ochg1,
+                    i1: n1,
+                    chg1: nchg1,
+                    i2: n2 - o2 + o1,
+                    chg2: ochg1,
+                };
+                append(&mut chunks, chunk);
+                a += 1;
+                continue;
+            }
+            if o2 + ochg2 < o1 {
+                let chunk = Chunk {
+                    resolution: Resolution::Theirs,
+                    i0: o2,
+                    chg0: ochg2,
+                    i1: n1 - o1 + o2,
+                    chg1: ochg2,
+                    i2: n2,
+                    chg2: nchg2,
+                };
+                append(&mut chunks, chunk);
+                b += 1;
+                continue;
+            }
+            let same = o1 == o2
+                && ochg1 == ochg2
+                && self.same_lines(
+                    &self.ours[n1 as usize..(n1 + nchg1) as usize],
+                    &self.theirs[n2 as usize..(n2 + nchg2) as usize],
+                );
+            if !same {
+                // Widen both sides to cover the base lines either of them changed
+                let off = o1 - o2;
+                let ffo = off + ochg1 - ochg2;
+                let (mut i0, mut i1, mut i2) = (o1, n1, n2);
+                if off > 0 {
+                    i0 -= off;
+                    i1 -= off;
+                } else {
+                    i2 += off;
+                }
+                let mut chg0 = o1 + ochg1 - i0;
+                let mut chg1 = n1 + nchg1 - i1;
+                let mut chg2 = n2 + nchg2 - i2;
+                if ffo < 0 {
+                    chg0 -= ffo;
+                    chg1 -= ffo;
+                } else {
+                    chg2 += ffo;
+                }
+                let chunk = Chunk {
+                    resolution: Resolution::Conflict,
+                    i0,
+                    chg0,
+                    i1,
+                    chg1,
+                    i2,
+                    chg2,
+                };
+                append(&mut chunks, chunk);
+            }
+            let (end1, end2) = (o1 + ochg1, o2 + ochg2);
+            if end1 >= end2 {
+                b += 1;
+            }
+            if end2 >= end1 {
+                a += 1;
+            }
+        }
+        let (base_len, ours_len, theirs_len) = (
+            self.base.len() as i64,
+            self.ours.len() as i64,
+            self.theirs.len() as i64,
+        );
+        for change in &ours[a..] {
+            let (o, ochg, n, nchg) = span(change);
+            let chunk = Chunk {
+                resolution: Resolution::Ours,
+                i0: o,
+                chg0: ochg,
+                i1: n,
+                chg1: nchg,
+                i2: o + theirs_len - base_len,
+                chg2: ochg,
+            };
+            append(&mut chunks, chunk);
+        }
+        for change in &theirs[b..] {
+            let (o, ochg, n, nchg) = span(change);
+            let chunk = Chunk {
+                resolution: Resolution::Theirs,
+                i0: o,
+                chg0: ochg,
+                i1: o + ours_len - base_len,
+                chg1: ochg,
+                i2: n,
+                chg2: nchg,
+            };
+            append(&mut chunks, chunk);
+        }
+        chunks
+    }
+
+    /// Diffs the two sides of each conflict against each other, keeping only the lines that
+    /// differ as conflicts. Sides that turn out identical aren't a conflict at all.
+    fn refine_conflicts(&self, chunks: Vec<Chunk>) -> Vec<Chunk> {
+        let mut refined = Vec::with_capacity(chunks.len());
+        for mut chunk in chunks {
+            if chunk.resolution != Resolution::Conflict || chunk.chg1 == 0 || chunk.chg2 == 0 {
+                refined.push(chunk);
+                continue;
+            }
+            let ours = &self.ours[chunk.i1 as usize..(chunk.i1 + chunk.chg1) as usize];
+            let theirs = &self.theirs[chunk.i2 as usize..(chunk.i2 + chunk.chg2) as usize];
+            let changes = diff_lines(ours, theirs, &self.diff);
+            if changes.is_empty() {
+                chunk.resolution = Resolution::Identical;
+                refined.push(chunk);
+                continue;
+            }
+            for change in changes {
+                refined.push(Chunk {
+                    i1: chunk.i1 + change.old_start as i64,
+                    chg1: change.old_len as i64,
+                    i2: chunk.i2 + change.new_start as i64,
+                    chg2: change.new_len as i64,
+                    ..chunk
+                });
+            }
+        }
+        refined
+    }
+
+    /// Moves the lines both sides start or end a conflict with out of it
+    fn trim_conflicts(&self, chunks: &mut [Chunk]) {
+        for chunk in chunks {
+            if chunk.resolution != Resolution::Conflict {
+                continue;
+            }
+            while chunk.chg1 > 0
+                && chunk.chg2 > 0
+                && self.same_lines(
+                    &self.ours[chunk.i1 as usize..][..1],
+                    &self.theirs[chunk.i2 as usize..][..1],
+                )
+            {
+                chunk.chg1 -= 1;
+                chunk.chg2 -= 1;
+                chunk.i1 += 1;
+                chunk.i2 += 1;
+            }
+            while chunk.chg1 > 0
+                && chunk.chg2 > 0
+                && self.same_lines(
+                    &self.ours[(chunk.i1 + chunk.chg1 - 1) as usize..][..1],
+                    &self.theirs[(chunk.i2 + chunk.chg2 - 1) as usize..][..1],
+                )
+            {
+                chunk.chg1 -= 1;
+                chunk.chg2 -= 1;
+            }
+        }
+    }
+
+    /// Whether conflict markers should end in CRLF: when the lines before the conflict on both
+    /// sides and the first line of the base do
+    fn needs_cr(&self, chunk: &Chunk) -> bool {
+        let before = |i: i64| if i > 0 { i - 1 } else { 0 } as usize;
+        let mut needs_cr = is_eol_crlf(&self.ours, before(chunk.i1));
+        if needs_cr != Some(false) {
+            needs_cr = is_eol_crlf(&self.theirs, before(chunk.i2));
+        }
+        if needs_cr != Some(false) {
+            needs_cr = is_eol_crlf(&self.base, 0);
+        }
+        needs_cr.unwrap_or(false)
+    }
+
+    /// Writes the merged result: our lines outside the chunks, and inside each the side it was
+    /// resolved to or a conflict
+    fn fill(&self, chunks: &[Chunk], labels: Labels, opts: &FileOptions) -> Vec<u8> {
+        let marker_size = MARKER_SIZE + opts.extra_marker_size;
+        let mut out = Vec::new();
+        let mut i = 0;
+        for chunk in chunks {
+            match chunk.resolution {
+                Resolution::Identical => continue,
+                Resolution::Conflict => {
+                    copy_lines(&mut out, &self.ours[i..chunk.i1 as usize], false, false);
+                    let needs_cr = self.needs_cr(chunk);
+                    let marker = |out: &mut Vec<u8>, c: u8, label: Option<&str>| {
+                        out.extend(std::iter::repeat_n(c, marker_size));
+                        if let Some(label) = label {
+                            out.push(b' ');
+                            out.extend(label.as_bytes());
+                        }
+                        if needs_cr {
+                            out.push(b'\r');
+                        }
+                        out.push(b'\n');
+                    };
+                    marker(&mut out, b'<', Some(labels.ours));
+                    copy_lines(&mut out, self.ours_lines(chunk), needs_cr, true);
+                    if opts.style != ConflictStyle::Merge {
+                        marker(&mut out, b'|', Some(labels.base));
+                        let base = &self.base[chunk.i0 as usize..(chunk.i0 + chunk.chg0) as usize];
+                        copy_lines(&mut out, base, needs_cr, true);
+                    }
+                    marker(&mut out, b'=', None);
+                    copy_lines(&mut out, self.theirs_lines(chunk), needs_cr, true);
+                    marker(&mut out, b'>', Some(labels.theirs));
+                }
+                Resolution::Ours => {
+                    copy_lines(&mut out, &self.ours[i..chunk.i1 as usize], false, false);
+                    copy_lines(&mut out, self.ours_lines(chunk), false, false);
+                }
+                Resolution::Theirs => {
+                    copy_lines(&mut out, &self.ours[i..chunk.i1 as usize], false, false);
+                    copy_lines(&mut out, self.theirs_lines(chunk), false, false);
+                }
+            }
+            i = (chunk.i1 + chunk.chg1) as usize;
+        }
+        copy_lines(&mut out, &self.ours[i..], false, false);
+        out
+    }
+
+    fn ours_lines(&self, chunk: &Chunk) -> &[&[u8]] {
+        &self.ours[chunk.i1 as usize..(chunk.i1 + chunk.chg1) as usize]
+    }
+
+    fn theirs_lines(&self, chunk: &Chunk) -> &[&[u8]] {
+        &self.theirs[chunk.i2 as usize..(chunk.i2 + chunk.chg2) as usize]
+    }
+}
+
+/// Appends lines, with `add_nl` completing a last line that has no newline so that a marker
+/// can follow
+fn copy_lines(out: &mut Vec<u8>, lines: &[&[u8]], needs_cr: bool, add_nl: bool) {
+    for line in lines {
+        out.extend_from_slice(line);
+    }
+    if add_nl && lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+        if needs_cr {
+            out.push(b'\r');
+        }
+        out.push(b'\n');
+    }
+}
+
+/// Whether line `i` ends in CRLF; for a last line without newline, whether the line before it
+/// does. `None` when there is no line ending to go by.
+fn is_eol_crlf(lines: &[&[u8]], i: usize) -> Option<bool> {
+    let crlf = |line: &[u8]| line.ends_with(b"\r\n");
+    if i + 1 < lines.len() {
+        return Some(crlf(lines[i]));
+    }
+    let last = lines.get(i)?;
+    if last.ends_with(b"\n") {
+        return Some(crlf(last));
+    }
+    if i == 0 {
+        return None;
+    }
+    Some(crlf(lines[i - 1]))
+}
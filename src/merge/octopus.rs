@@ -0,0 +1,215 @@
+//! The octopus strategy: several heads merged into one commit, one after the other, each with a
+//! plain three-way merge in the index that doesn't look for renames. Files both sides changed
+//! get their contents merged, but only the last head may leave conflicts behind: the next head
+//! couldn't be merged on top of them, so the strategy gives up instead.
+
+use crate::{
+    index::{Index, IndexEntry},
+    merge::content::{self, FileOptions, Labels},
+    merge_base::CommitGraph,
+    object::{
+        commit::Commit,
+        read::read_object_of_kind,
+        write::{hash_object, write_tree_from_paths},
+        ObjectKind, MODE_GITLINK, MODE_SYMLINK,
+    },
+    unpack::{self, Blob, UnpackOptions},
+    worktree,
+};
+
+/// How the octopus went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// Every head merged cleanly into this tree
+    Merged([u8; 20]),
+    /// The last head left conflicts in the index and working tree
+    Conflicts,
+    /// A head couldn't be merged at all
+    Failed,
+}
+
+/// Merges `heads`, named for the messages, into the index and working tree, which match the
+/// commit `head`. Progress goes to stdout and the trouble with files to stderr, as the
+/// strategy reports them.
+pub(crate) fn merge_octopus(
+    index: &mut Index,
+    head: &[u8; 20],
+    heads: &[(&str, [u8; 20])],
+    file_opts: &FileOptions,
+) -> anyhow::Result<Outcome> {
+    let mut graph = CommitGraph::new();
+    // The commits merged so far and the tree they merged into
+    let mut merged = vec![*head];
+    let mut tree = Some(Commit::read(head)?.tree);
+    let mut fast_forward = true;
+    let mut failed = false;
+    for (name, commit) in heads {
+        if failed {
+            println!("Automated merge did not work.");
+            println!("Should not be doing an octopus.");
+            return Ok(Outcome::Failed);
+        }
+        let bases = graph.merge_bases(commit, &merged)?;
+        if bases.is_empty() {
+            eprintln!("Unable to find common commit with {name}");
+            return Ok(Outcome::Failed);
+        }
+        if bases.contains(commit) {
+            println!("Already up to date with {name}");
+            continue;
+        }
+        let their_tree = Commit::read(commit)?.tree;
+        let opts = UnpackOptions {
+            update: true,
+            merging: true,
+            aggressive: true,
+            ..UnpackOptions::default()
+        };
+        if fast_forward && bases == merged {
+            // The first head merged is a fast-forward: its tree is the merge so far, though
+            // HEAD still counts as a parent
+            println!("Fast-forwarding to: {name}");
+            if let Err(err) = unpack::two_way(index, tree.as_ref(), Some(&their_tree), opts) {
+                eprintln!("error: {err}");
+                return Ok(Outcome::Failed);
+            }
+            merged = vec![*commit];
+            tree = Some(their_tree);
+            continue;
+        }
+        fast_forward = false;
+
+        println!("Trying simple merge with {name}");
+        let base_tree = match bases.first() {
+            Some(base) => Some(Commit::read(base)?.tree),
+            None => None,
+        };
+        if let Err(err) = unpack::three_way(
+            index,
+            base_tree.as_ref(),
+            tree.as_ref(),
+            Some(&their_tree),
+            opts,
+        ) {
+            eprintln!("error: {err}");
+            return Ok(Outcome::Failed);
+        }
+        if index.has_conflicts() {
+            println!("Simple merge did not work, trying automatic merge.");
+            failed = !merge_unmerged(index, name, file_opts)?;
+        }
+        merged.push(*commit);
+        tree = index_tree(index)?;
+    }
+    Ok(match tree {
+        Some(tree) if !failed => Outcome::Merged(tree),
+        _ => Outcome::Conflicts,
+    })
+}
+
+/// The tree of the index, unless it has conflicts
+pub(crate) fn index_tree(index: &Index) -> anyhow::Result<Option<[u8; 20]>> {
+    if index.has_conflicts() {
+        return Ok(None);
+    }
+    let files = index
+        .entries()
+        .iter()
+        .map(|entry| (entry.path.clone(), (entry.mode, entry.hash)))
+        .collect();
+    Ok(Some(write_tree_from_paths(&files)?))
+}
+
+/// Merges the contents of the files left unmerged, as `git merge-one-file` does, returning
+/// whether all of them merged cleanly
+fn merge_unmerged(index: &mut Index, name: &str, opts: &FileOptions) -> anyhow::Result<bool> {
+    let mut unmerged: Vec<String> = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.stage != 0)
+        .map(|entry| entry.path.clone())
+        .collect();
+    unmerged.dedup();
+    let mut clean = true;
+    for path in unmerged {
+        let mut stages: [Option<Blob>; 3] = [None; 3];
+        for entry in index.stages(&path) {
+            stages[entry.stage as usize - 1] = Some((entry.mode, entry.hash));
+        }
+        if !merge_one_file(index, &path, stages, name, opts)? {
+            clean = false;
+        }
+    }
+    if !clean {
+        eprintln!("fatal: merge program failed");
+    }
+    Ok(clean)
+}
+
+fn merge_one_file(
+    index: &mut Index,
+    path: &str,
+    [o, a, b]: [Option<Blob>; 3],
+    name: &str,
+    opts: &FileOptions,
+) -> anyhow::Result<bool> {
+    let (Some(a), Some(b)) = (a, b) else {
+        let hex = |blob: Option<Blob>| blob.map(|(_, hash)| hex::encode(hash)).unwrap_or_default();
+        eprintln!(
+            "ERROR: {path}: Not handling case {} -> {} -> {}",
+            hex(o),
+            hex(a),
+            hex(b)
+        );
+        return Ok(false);
+    };
+    if a.0 == MODE_SYMLINK || b.0 == MODE_SYMLINK {
+        eprintln!("ERROR: {path}: Not merging symbolic link changes.");
+        return Ok(false);
+    }
+    if a.0 == MODE_GITLINK || b.0 == MODE_GITLINK {
+        eprintln!("ERROR: {path}: Not merging conflicting submodule changes.");
+        return Ok(false);
+    }
+    let base = match o {
+        Some((_, hash)) => {
+            println!("Auto-merging {path}");
+            read_object_of_kind(&hex::encode(hash), ObjectKind::Blob)?
+        }
+        None => {
+            println!("Added {path} in both, but differently.");
+            Vec::new()
+        }
+    };
+    let ours = read_object_of_kind(&hex::encode(a.1), ObjectKind::Blob)?;
+    let theirs = read_object_of_kind(&hex::encode(b.1), ObjectKind::Blob)?;
+    let labels = Labels {
+        ours: "HEAD",
+        base: "base",
+        theirs: name,
+    };
+    let (merged, conflicts) = content::merge(&base, &ours, &theirs, labels, opts);
+    let hash = hash_object(ObjectKind::Blob, &merged, true)?;
+    // The file is written in our mode whatever happens, like the result of a clean merge
+    worktree::write_file(path, a.0, &hash)?;
+
+    let mut problems = Vec::new();
+    if conflicts > 0 || o.is_none() {
+        problems.push(String::from("content conflict"));
+    }
+    if a.0 != b.0 {
+        let base_mode = o.map(|(mode, _)| format!("{mode:o}")).unwrap_or_default();
+        problems.push(format!(
+            "permissions conflict: {base_mode}->{:o},{:o}",
+            a.0, b.0
+        ));
+    }
+    if !problems.is_empty() {
+        eprintln!("ERROR: {} in {path}", problems.join(", "));
+        return Ok(false);
+    }
+    let mut entry = IndexEntry::new(path.to_string(), a.0, hash, 0);
+    entry.update_stat(&std::fs::symlink_metadata(path)?);
+    index.add(entry);
+    Ok(true)
+}
@@ -0,0 +1,193 @@
+//! Lining up trees that hold the same project at different depths, for subtree merges: the
+//! other side's tree is shifted down under the directory of ours it resembles most, or up out
+//! of the directory of its own that resembles ours.
+
+use anyhow::Context;
+
+use crate::{
+    object::{
+        read::read_tree_items, write::write_tree_from_paths, TreeItem, MODE_SYMLINK, MODE_TREE,
+    },
+    unpack::tree_blobs,
+};
+
+/// How deep into a tree directories are tried when looking for the best match
+const DEPTH_LIMIT: usize = 2;
+
+/// Shifts `two` to line up with `one`: under or out of `prefix` when one is given, wherever it
+/// matches best when it is empty. Returns `two` itself when no shifting helps.
+pub(crate) fn shift_tree(one: &[u8; 20], two: &[u8; 20], prefix: &str) -> anyhow::Result<[u8; 20]> {
+    if prefix.is_empty() {
+        shift_tree_to_match(one, two)
+    } else {
+        shift_tree_by(one, two, prefix)
+    }
+}
+
+fn shift_tree_to_match(one: &[u8; 20], two: &[u8; 20]) -> anyhow::Result<[u8; 20]> {
+    let score = score_trees(one, two)?;
+    // A directory of one that resembles two: two goes under it
+    let (mut add_score, mut add_prefix) = (score, String::new());
+    match_trees(one, two, &mut add_score, &mut add_prefix, "", DEPTH_LIMIT)?;
+    // A directory of two that resembles one: only that part of two is kept
+    let (mut del_score, mut del_prefix) = (score, String::new());
+    match_trees(two, one, &mut del_score, &mut del_prefix, "", DEPTH_LIMIT)?;
+
+    if add_score < del_score {
+        if del_prefix.is_empty() {
+            return Ok(*two);
+        }
+        return subtree_at(two, &del_prefix)?.with_context(|| {
+            format!("cannot find path {del_prefix} in tree {}", hex::encode(two))
+        });
+    }
+    if add_prefix.is_empty() {
+        return Ok(*two);
+    }
+    splice_tree(one, &add_prefix, two)
+}
+
+/// With the prefix given, the only question is which way to shift
+fn shift_tree_by(one: &[u8; 20], two: &[u8; 20], prefix: &str) -> anyhow::Result<[u8; 20]> {
+    let sub1 = subtree_at(one, prefix)?;
+    let sub2 = subtree_at(two, prefix)?;
+    let down = match (sub1, sub2) {
+        (Some(sub1), Some(sub2)) => {
+            // Both could be: shift whichever way matches better than not shifting at all
+            let mut best = score_trees(one, two)?;
+            let mut down = None;
+            let score = score_trees(&sub1, two)?;
+            if score > best {
+                down = Some(true);
+                best = score;
+            }
+            if score_trees(&sub2, one)? > best {
+                down = Some(false);
+            }
+            down
+        }
+        (Some(_), None) => Some(true),
+        (None, Some(_)) => Some(false),
+        (None, None) => None,
+    };
+    match (down, sub2) {
+        (Some(true), _) => splice_tree(one, prefix, two),
+        (Some(false), Some(sub2)) => Ok(sub2),
+        _ => Ok(*two),
+    }
+}
+
+/// Finds the directory of `one`, at most `limit` levels below the top, whose entries match the
+/// top of `two` best, if it beats `best_score`
+fn match_trees(
+    one: &[u8; 20],
+    two: &[u8; 20],
+    best_score: &mut i64,
+    best_match: &mut String,
+    base: &str,
+    limit: usize,
+) -> anyhow::Result<()> {
+    for item in read_tree_items(one)? {
+        if item.mode != MODE_TREE {
+            continue;
+        }
+        let score = score_trees(&item.hash, two)?;
+        if *best_score < score {
+            *best_match = format!("{base}{}", item.name);
+            *best_score = score;
+        }
+        if limit > 0 {
+            let base = format!("{base}{}/", item.name);
+            match_trees(&item.hash, two, best_score, best_match, &base, limit - 1)?;
+        }
+    }
+    Ok(())
+}
+
+/// How alike the top levels of two trees are: entries both have the same count for them,
+/// directories most, and entries only one has or that differ count against them
+fn score_trees(one: &[u8; 20], two: &[u8; 20]) -> anyhow::Result<i64> {
+    let one = read_tree_items(one)?;
+    let two = read_tree_items(two)?;
+    let (mut i, mut j) = (0, 0);
+    let mut score = 0;
+    while i < one.len() || j < two.len() {
+        let order = match (one.get(i), two.get(j)) {
+            (Some(a), Some(b)) => sort_key(a).cmp(&sort_key(b)),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            _ => std::cmp::Ordering::Greater,
+        };
+        match order {
+            std::cmp::Ordering::Less => {
+                score += score_missing(one[i].mode);
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                score += score_missing(two[j].mode);
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                let (a, b) = (one[i].mode, two[j].mode);
+                score += if (a == MODE_TREE) != (b == MODE_TREE) {
+                    -100
+                } else if (a == MODE_SYMLINK) != (b == MODE_SYMLINK) {
+                    -50
+                } else if one[i].hash != two[j].hash {
+                    -5
+                } else if a == MODE_TREE {
+                    1000
+                } else if a == MODE_SYMLINK {
+                    500
+                } else {
+                    250
+                };
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    Ok(score)
+}
+
+fn score_missing(mode: u32) -> i64 {
+    match mode {
+        MODE_TREE => -1000,
+        MODE_SYMLINK => -500,
+        _ => -50,
+    }
+}
+
+/// Trees sort as if their names ended in a slash
+fn sort_key(item: &TreeItem) -> Vec<u8> {
+    let mut key = item.name.as_bytes().to_vec();
+    if item.mode == MODE_TREE {
+        key.push(b'/');
+    }
+    key
+}
+
+/// The tree at `path` inside `tree`, if there is a directory there
+fn subtree_at(tree: &[u8; 20], path: &str) -> anyhow::Result<Option<[u8; 20]>> {
+    let mut hash = *tree;
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        let item = read_tree_items(&hash)?
+            .into_iter()
+            .find(|item| item.name == component);
+        match item {
+            Some(item) if item.mode == MODE_TREE => hash = item.hash,
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(hash))
+}
+
+/// `one` with what is at `prefix` replaced by `two`
+fn splice_tree(one: &[u8; 20], prefix: &str, two: &[u8; 20]) -> anyhow::Result<[u8; 20]> {
+    let under = format!("{prefix}/");
+    let mut files = tree_blobs(Some(one))?;
+    files.retain(|path, _| !path.starts_with(&under));
+    for (path, blob) in tree_blobs(Some(two))? {
+        files.insert(format!("{under}{path}"), blob);
+    }
+    write_tree_from_paths(&files)
+}
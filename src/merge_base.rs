@@ -0,0 +1,286 @@
+//! Common ancestors of commits, found by painting down from each side until the paint meets.
+//!
+//! Commits are visited highest generation number first, a commit's generation being one more
+//! than the highest of its parents'. Unlike commit dates, generations never put a parent before
+//! its child, so a query about recent commits stops at the generation it needs instead of
+//! walking on through history that can't matter.
+
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::object::commit::Commit;
+
+/// Reachable from the first side
+const PARENT1: u8 = 1 << 0;
+/// Reachable from the second side
+const PARENT2: u8 = 1 << 1;
+/// Reachable from a common ancestor, so not a best one
+const STALE: u8 = 1 << 2;
+/// Already collected as a common ancestor
+const RESULT: u8 = 1 << 3;
+
+struct Node {
+    parents: Vec<[u8; 20]>,
+    date: i64,
+    /// Filled in the first time it's needed
+    generation: Option<u32>,
+}
+
+/// A commit waiting to be painted, ordered highest generation first, then newest, then in the
+/// order it was queued
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Queued {
+    generation: u32,
+    date: i64,
+    order: std::cmp::Reverse<usize>,
+    hash: [u8; 20],
+}
+
+/// The commits read so far, with their generations, so that several queries can share them
+#[derive(Default)]
+pub(crate) struct CommitGraph {
+    nodes: HashMap<[u8; 20], Node>,
+    flags: HashMap<[u8; 20], u8>,
+}
+
+impl CommitGraph {
+    pub(crate) fn new() -> CommitGraph {
+        CommitGraph::default()
+    }
+
+    fn load(&mut self, hash: &[u8; 20]) -> anyhow::Result<&Node> {
+        if !self.nodes.contains_key(hash) {
+            let commit = Commit::read(hash)?;
+            let node = Node {
+                parents: commit.parents,
+                date: commit.committer.time,
+                generation: None,
+            };
+            self.nodes.insert(*hash, node);
+        }
+        Ok(&self.nodes[hash])
+    }
+
+    /// The generation of a commit: 1 for roots, otherwise one more than its highest parent's
+    fn generation(&mut self, hash: &[u8; 20]) -> anyhow::Result<u32> {
+        // Parents are worked out first, without recursing, as histories can be very deep
+        let mut stack = vec![*hash];
+        while let Some(top) = stack.last().copied() {
+            let node = self.load(&top)?;
+            if node.generation.is_some() {
+                stack.pop();
+                continue;
+            }
+            let parents = node.parents.clone();
+            let mut generation = 0;
+            let mut pending = false;
+            for parent in &parents {
+                match self.load(parent)?.generation {
+                    Some(parent_generation) => generation = generation.max(parent_generation),
+                    None => {
+                        stack.push(*parent);
+                        pending = true;
+                    }
+                }
+            }
+            if !pending {
+                stack.pop();
+                self.nodes.get_mut(&top).unwrap().generation = Some(generation + 1);
+            }
+        }
+        Ok(self.nodes[hash].generation.unwrap_or_default())
+    }
+
+    fn has_flag(&self, hash: &[u8; 20], flag: u8) -> bool {
+        self.flags.get(hash).is_some_and(|flags| flags & flag != 0)
+    }
+
+    fn queue(
+        &mut self,
+        queue: &mut BinaryHeap<Queued>,
+        hash: &[u8; 20],
+        order: &mut usize,
+    ) -> anyhow::Result<()> {
+        let generation = self.generation(hash)?;
+        *order += 1;
+        queue.push(Queued {
+            generation,
+            date: self.nodes[hash].date,
+            order: std::cmp::Reverse(*order),
+            hash: *hash,
+        });
+        Ok(())
+    }
+
+    /// Paints `one` and everything reachable from it with [`PARENT1`], the `twos` and theirs
+    /// with [`PARENT2`], and what's reachable from commits with both with [`STALE`]. Painting
+    /// stops once only stale commits are left, or at commits below `min_generation`. Returns the
+    /// commits found with both colors, newest first; some may have turned stale since.
+    fn paint_down_to_common(
+        &mut self,
+        one: &[u8; 20],
+        twos: &[[u8; 20]],
+        min_generation: u32,
+    ) -> anyhow::Result<Vec<[u8; 20]>> {
+        self.flags.clear();
+        self.flags.insert(*one, PARENT1);
+        if twos.is_empty() {
+            return Ok(vec![*one]);
+        }
+        let mut queue = BinaryHeap::new();
+        let mut order = 0;
+        self.queue(&mut queue, one, &mut order)?;
+        for two in twos {
+            *self.flags.entry(*two).or_default() |= PARENT2;
+            self.queue(&mut queue, two, &mut order)?;
+        }
+
+        let mut result = Vec::new();
+        while queue
+            .iter()
+            .any(|queued| !self.has_flag(&queued.hash, STALE))
+        {
+            let Some(queued) = queue.pop() else {
+                break;
+            };
+            if queued.generation < min_generation {
+                break;
+            }
+            let hash = queued.hash;
+            let mut flags = self.flags[&hash] & (PARENT1 | PARENT2 | STALE);
+            if flags == PARENT1 | PARENT2 {
+                if !self.has_flag(&hash, RESULT) {
+                    *self.flags.get_mut(&hash).unwrap() |= RESULT;
+                    self.insert_by_date(&mut result, hash);
+                }
+                // Everything below a common ancestor is a worse one
+                flags |= STALE;
+            }
+            for parent in self.nodes[&hash].parents.clone() {
+                let parent_flags = self.flags.entry(parent).or_default();
+                if *parent_flags & flags == flags {
+                    continue;
+                }
+                *parent_flags |= flags;
+                self.queue(&mut queue, &parent, &mut order)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Inserts a commit before the first one that is older, so commits of the same date stay in
+    /// the order they were inserted
+    fn insert_by_date(&self, list: &mut Vec<[u8; 20]>, hash: [u8; 20]) {
+        let date = self.nodes[&hash].date;
+        let at = list
+            .iter()
+            .position(|other| self.nodes[other].date < date)
+            .unwrap_or(list.len());
+        list.insert(at, hash);
+    }
+
+    /// The best common ancestors of `one` and all of `twos` together: the commits reachable from
+    /// `one` and from any of `twos` that aren't reachable from another such commit, newest
+    /// first
+    pub(crate) fn merge_bases(
+        &mut self,
+        one: &[u8; 20],
+        twos: &[[u8; 20]],
+    ) -> anyhow::Result<Vec<[u8; 20]>> {
+        if twos.contains(one) {
+            return Ok(vec![*one]);
+        }
+        let mut bases = Vec::new();
+        for hash in self.paint_down_to_common(one, twos, 0)? {
+            if !self.has_flag(&hash, STALE) {
+                self.insert_by_date(&mut bases, hash);
+            }
+        }
+        if bases.len() <= 1 {
+            return Ok(bases);
+        }
+        // A base found before the paint reached it from a better base is still redundant
+        let mut result = Vec::new();
+        for hash in self.remove_redundant(&bases)? {
+            self.insert_by_date(&mut result, hash);
+        }
+        Ok(result)
+    }
+
+    /// Leaves out the commits reachable from another one of `commits`, keeping the order
+    fn remove_redundant(&mut self, commits: &[[u8; 20]]) -> anyhow::Result<Vec<[u8; 20]>> {
+        let mut redundant = vec![false; commits.len()];
+        for i in 0..commits.len() {
+            if redundant[i] {
+                continue;
+            }
+            let others: Vec<usize> = (0..commits.len())
+                .filter(|&j| j != i && !redundant[j])
+                .collect();
+            let work: Vec<[u8; 20]> = others.iter().map(|&j| commits[j]).collect();
+            self.paint_down_to_common(&commits[i], &work, 0)?;
+            if self.has_flag(&commits[i], PARENT2) {
+                redundant[i] = true;
+            }
+            for (&j, hash) in others.iter().zip(&work) {
+                if self.has_flag(hash, PARENT1) {
+                    redundant[j] = true;
+                }
+            }
+        }
+        Ok(commits
+            .iter()
+            .zip(redundant)
+            .filter(|(_, redundant)| !redundant)
+            .map(|(hash, _)| *hash)
+            .collect())
+    }
+
+    /// Whether `commit` is reachable from `reference`, or is it. Only commits of at least
+    /// `commit`'s generation can lead to it, so painting stops below that.
+    pub(crate) fn is_ancestor(
+        &mut self,
+        commit: &[u8; 20],
+        reference: &[u8; 20],
+    ) -> anyhow::Result<bool> {
+        let generation = self.generation(commit)?;
+        if generation > self.generation(reference)? {
+            return Ok(false);
+        }
+        self.paint_down_to_common(commit, &[*reference], generation)?;
+        Ok(self.has_flag(commit, PARENT2))
+    }
+
+    /// The commits not reachable from any of the others, without duplicates, in the order
+    /// given
+    pub(crate) fn independent(&mut self, commits: &[[u8; 20]]) -> anyhow::Result<Vec<[u8; 20]>> {
+        let mut unique = Vec::new();
+        for hash in commits {
+            if !unique.contains(hash) {
+                unique.push(*hash);
+            }
+        }
+        self.remove_redundant(&unique)
+    }
+
+    /// The best common ancestors for merging all of `commits` at once: the merge bases of the
+    /// first two, then of each of those with the next commit, and so on
+    pub(crate) fn octopus_bases(&mut self, commits: &[[u8; 20]]) -> anyhow::Result<Vec<[u8; 20]>> {
+        let Some((first, rest)) = commits.split_first() else {
+            return Ok(Vec::new());
+        };
+        let mut bases = vec![*first];
+        for commit in rest {
+            let mut next = Vec::new();
+            for base in &bases {
+                next.extend(self.merge_bases(commit, &[*base])?);
+            }
+            bases = next;
+        }
+        self.independent(&bases)
+    }
+}
+
+/// The best common ancestors of two commits, newest first
+pub(crate) fn merge_bases(a: &[u8; 20], b: &[u8; 20]) -> anyhow::Result<Vec<[u8; 20]>> {
+    CommitGraph::new().merge_bases(a, &[*b])
+}
@@ -0,0 +1,389 @@
+//! Naming commits relative to the refs that reach them, as `git name-rev` does: `master~3^2` is
+//! the second parent of the commit three first-parent steps below `master`.
+//!
+//! Every ref is a tip that names the commit it points at; the names then spread down to the
+//! parents, each commit keeping the best one it is offered. Tags beat branches, older tags beat
+//! newer ones, and otherwise shorter names win: a hop to a second or later parent weighs
+//! [`MERGE_TRAVERSAL_WEIGHT`] first-parent ones, and so does a trailing `~<n>`. Tips are
+//! processed best first so that the worse names don't spread only to be replaced.
+
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    object::{
+        commit::{Commit, Tag},
+        read::read_object,
+        ObjectKind,
+    },
+    pathspec::wildmatch,
+    refs, revision,
+};
+
+/// How much a hop to a merge's second or later parent weighs against a first-parent one
+const MERGE_TRAVERSAL_WEIGHT: u64 = 65535;
+
+/// How much older than the commits being named a commit may be and still be worth naming
+pub(crate) const CUTOFF_DATE_SLOP: i64 = 86400;
+
+#[derive(Debug, Default)]
+pub(crate) struct NameRevOptions {
+    /// Only use tags
+    pub(crate) tags_only: bool,
+    /// Use the shortest unambiguous ref names, as when only tags are wanted and the caller only
+    /// prints the names
+    pub(crate) shorten: bool,
+    /// Only use refs matching one of these patterns, matched against the full name or any
+    /// `/`-separated tail of it; a tail match allows a short name
+    pub(crate) refs: Vec<String>,
+    /// Ignore refs matching any of these patterns
+    pub(crate) excludes: Vec<String>,
+    /// Don't spread names to commits older than this
+    pub(crate) cutoff: Option<i64>,
+}
+
+/// A ref names are spread from
+struct Tip {
+    /// What the ref points at, possibly a tag
+    hash: [u8; 20],
+    name: String,
+    /// The commit the ref peels to, if any
+    commit: Option<[u8; 20]>,
+    /// The date of the outermost tag, or of the commit for other refs
+    taggerdate: i64,
+    from_tag: bool,
+    /// Whether tags were peeled to get to the commit
+    deref: bool,
+}
+
+#[derive(Clone)]
+struct RevName {
+    /// The ref or merge parent path the name starts from, `tag^0` for peeled tags
+    tip_name: Rc<str>,
+    taggerdate: i64,
+    /// First-parent steps below `tip_name`
+    generation: u32,
+    distance: u64,
+    from_tag: bool,
+}
+
+impl RevName {
+    /// Whether `other` should replace this name
+    fn is_worse_than(&self, other: &RevName) -> bool {
+        let (old, new) = (
+            effective_distance(self.distance, self.generation),
+            effective_distance(other.distance, other.generation),
+        );
+        if self.from_tag && other.from_tag {
+            // Prefer names based on older tags, even if they are further away
+            return self.taggerdate > other.taggerdate
+                || (self.taggerdate == other.taggerdate && old > new);
+        }
+        if self.from_tag != other.from_tag {
+            return other.from_tag;
+        }
+        if old != new {
+            return old > new;
+        }
+        self.taggerdate > other.taggerdate
+    }
+}
+
+/// The distance names are compared by: `~<n>` costs as much as following a merge, so that
+/// `tip^2` is as short as `tip~1`
+fn effective_distance(distance: u64, generation: u32) -> u64 {
+    match generation {
+        0 => distance,
+        _ => distance + MERGE_TRAVERSAL_WEIGHT,
+    }
+}
+
+/// The names of every commit reachable from the chosen refs
+pub(crate) struct Names {
+    tips: Vec<Tip>,
+    names: HashMap<[u8; 20], RevName>,
+    /// Commit date and parents of the commits seen so far
+    commits: HashMap<[u8; 20], (i64, Vec<[u8; 20]>)>,
+}
+
+impl Names {
+    /// Collects the refs chosen by `opts` and names the commits they reach
+    pub(crate) fn collect(opts: &NameRevOptions) -> anyhow::Result<Names> {
+        let mut names = Names {
+            tips: Vec::new(),
+            names: HashMap::new(),
+            commits: HashMap::new(),
+        };
+        for (name, hash) in refs::list("refs/")? {
+            if let Some(tip) = names.tip(opts, name, hash)? {
+                names.tips.push(tip);
+            }
+        }
+
+        let mut order: Vec<usize> = (0..names.tips.len()).collect();
+        order.sort_by(|&a, &b| {
+            let (a, b) = (&names.tips[a], &names.tips[b]);
+            b.from_tag
+                .cmp(&a.from_tag)
+                .then(a.taggerdate.cmp(&b.taggerdate))
+        });
+        for i in order {
+            let tip = &names.tips[i];
+            if let Some(commit) = tip.commit {
+                let tip_name = if tip.deref {
+                    format!("{}^0", tip.name)
+                } else {
+                    tip.name.clone()
+                };
+                let (taggerdate, from_tag) = (tip.taggerdate, tip.from_tag);
+                names.name_from(commit, tip_name.into(), taggerdate, from_tag, opts.cutoff)?;
+            }
+        }
+        Ok(names)
+    }
+
+    /// The tip for ref `name`, unless the options rule it out
+    fn tip(
+        &mut self,
+        opts: &NameRevOptions,
+        name: String,
+        hash: [u8; 20],
+    ) -> anyhow::Result<Option<Tip>> {
+        if opts.tags_only && !name.starts_with("refs/tags/") {
+            return Ok(None);
+        }
+        if opts
+            .excludes
+            .iter()
+            .any(|pattern| subpath_matches(&name, pattern).is_some())
+        {
+            return Ok(None);
+        }
+        let mut shorten = opts.shorten;
+        if !opts.refs.is_empty() {
+            let mut matched = false;
+            for pattern in &opts.refs {
+                // Check every pattern: a later one may match a subpath, allowing a short name
+                match subpath_matches(&name, pattern) {
+                    None => {}
+                    Some(0) => matched = true,
+                    Some(_) => {
+                        matched = true;
+                        shorten = true;
+                    }
+                }
+            }
+            if !matched {
+                return Ok(None);
+            }
+        }
+
+        let mut object = hash;
+        let mut taggerdate = None;
+        let mut deref = false;
+        let mut commit = None;
+        loop {
+            let (kind, content) = read_object(&hex::encode(object))?;
+            match kind {
+                ObjectKind::Tag => {
+                    let tag = Tag::parse(&content)?;
+                    taggerdate.get_or_insert(tag.tagger.map_or(0, |tagger| tagger.time));
+                    object = tag.object;
+                    deref = true;
+                }
+                ObjectKind::Commit => {
+                    let parsed = Commit::parse(&content)?;
+                    taggerdate.get_or_insert(parsed.committer.time);
+                    self.commits
+                        .insert(object, (parsed.committer.time, parsed.parents));
+                    commit = Some(object);
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        let from_tag = commit.is_some() && name.starts_with("refs/tags/");
+        let name = if shorten {
+            refs::shorten_unambiguous(&name)?
+        } else {
+            name.strip_prefix("refs/heads/")
+                .or_else(|| name.strip_prefix("refs/"))
+                .unwrap_or(&name)
+                .to_string()
+        };
+        Ok(Some(Tip {
+            hash,
+            name,
+            commit,
+            taggerdate: taggerdate.unwrap_or(i64::MAX),
+            from_tag,
+            deref,
+        }))
+    }
+
+    fn commit(&mut self, hash: &[u8; 20]) -> anyhow::Result<&(i64, Vec<[u8; 20]>)> {
+        if !self.commits.contains_key(hash) {
+            let commit = Commit::read(hash)?;
+            self.commits
+                .insert(*hash, (commit.committer.time, commit.parents));
+        }
+        Ok(&self.commits[hash])
+    }
+
+    /// Offers `tip_name` to `start` and spreads it down through the history, depth first with
+    /// first parents before the others
+    fn name_from(
+        &mut self,
+        start: [u8; 20],
+        tip_name: Rc<str>,
+        taggerdate: i64,
+        from_tag: bool,
+        cutoff: Option<i64>,
+    ) -> anyhow::Result<()> {
+        let too_old = |date: i64| cutoff.is_some_and(|cutoff| date < cutoff);
+        let start_name = RevName {
+            tip_name,
+            taggerdate,
+            generation: 0,
+            distance: 0,
+            from_tag,
+        };
+        if too_old(self.commit(&start)?.0) || !self.offer(start, start_name) {
+            return Ok(());
+        }
+
+        let mut stack = vec![start];
+        while let Some(commit) = stack.pop() {
+            let name = self.names[&commit].clone();
+            let parents = self.commit(&commit)?.1.clone();
+            let mut to_queue = Vec::new();
+            for (i, parent) in parents.into_iter().enumerate() {
+                if too_old(self.commit(&parent)?.0) {
+                    continue;
+                }
+                let parent_name = if i == 0 {
+                    RevName {
+                        generation: name.generation + 1,
+                        distance: name.distance + 1,
+                        ..name.clone()
+                    }
+                } else {
+                    let base = name.tip_name.strip_suffix("^0").unwrap_or(&name.tip_name);
+                    let tip_name = match name.generation {
+                        0 => format!("{base}^{}", i + 1),
+                        generation => format!("{base}~{generation}^{}", i + 1),
+                    };
+                    RevName {
+                        tip_name: tip_name.into(),
+                        generation: 0,
+                        distance: name.distance + MERGE_TRAVERSAL_WEIGHT,
+                        ..name.clone()
+                    }
+                };
+                if self.offer(parent, parent_name) {
+                    to_queue.push(parent);
+                }
+            }
+            // The first parent must come off the stack first
+            stack.extend(to_queue.into_iter().rev());
+        }
+        Ok(())
+    }
+
+    /// Gives `commit` the name unless it has a better one, returning whether it did
+    fn offer(&mut self, commit: [u8; 20], name: RevName) -> bool {
+        if let Some(known) = self.names.get(&commit) {
+            if !known.is_worse_than(&name) {
+                return false;
+            }
+        }
+        self.names.insert(commit, name);
+        true
+    }
+
+    /// The name of a commit, or for other objects the ref pointing straight at them
+    pub(crate) fn name(&self, hash: &[u8; 20]) -> anyhow::Result<Option<String>> {
+        if let Some(name) = self.names.get(hash) {
+            return Ok(Some(match name.generation {
+                0 => name.tip_name.to_string(),
+                generation => {
+                    let base = name.tip_name.strip_suffix("^0").unwrap_or(&name.tip_name);
+                    format!("{base}~{generation}")
+                }
+            }));
+        }
+        if self.commits.contains_key(hash) || revision::kind_of(hash)? == ObjectKind::Commit {
+            return Ok(None);
+        }
+        Ok(self
+            .tips
+            .iter()
+            .find(|tip| tip.hash == *hash)
+            .map(|tip| tip.name.clone()))
+    }
+
+    /// Every named commit
+    pub(crate) fn commits(&self) -> impl Iterator<Item = &[u8; 20]> {
+        self.names.keys()
+    }
+
+    /// Follows each full object name in `line` with the name of the object in parentheses, or
+    /// replaces it by the name with `name_only`; objects without a name are left alone
+    pub(crate) fn annotate(&self, line: &str, name_only: bool) -> String {
+        let is_hex = |b: u8| b.is_ascii_digit() || (b'a'..=b'f').contains(&b);
+        let bytes = line.as_bytes();
+        let mut out = String::new();
+        let mut start = 0;
+        let mut run = 0;
+        for (i, &b) in bytes.iter().enumerate() {
+            if !is_hex(b) {
+                run = 0;
+                continue;
+            }
+            run += 1;
+            if run != 40 || bytes.get(i + 1).is_some_and(|&next| is_hex(next)) {
+                continue;
+            }
+            run = 0;
+            let hex = &line[i + 1 - 40..=i];
+            let Some(name) = hex::decode(hex)
+                .ok()
+                .and_then(|hash| <[u8; 20]>::try_from(hash).ok())
+                .and_then(|hash| self.known_name(&hash))
+            else {
+                continue;
+            };
+            if name_only {
+                out.push_str(&line[start..i + 1 - 40]);
+                out.push_str(&name);
+            } else {
+                out.push_str(&line[start..=i]);
+                out.push_str(&format!(" ({name})"));
+            }
+            start = i + 1;
+        }
+        out.push_str(&line[start..]);
+        out
+    }
+
+    /// Like `name`, but only for objects already seen while naming, without reading others
+    fn known_name(&self, hash: &[u8; 20]) -> Option<String> {
+        if self.names.contains_key(hash) {
+            return self.name(hash).ok().flatten();
+        }
+        if self.commits.contains_key(hash) {
+            return None;
+        }
+        self.tips
+            .iter()
+            .find(|tip| tip.hash == *hash)
+            .map(|tip| tip.name.clone())
+    }
+}
+
+/// Where `pattern` matches `name` or one of its `/`-separated tails: the offset of the tail
+fn subpath_matches(name: &str, pattern: &str) -> Option<usize> {
+    std::iter::once(0)
+        .chain(name.match_indices('/').map(|(i, _)| i + 1))
+        .find(|&offset| wildmatch(pattern.as_bytes(), &name.as_bytes()[offset..]))
+}
@@ -1,13 +1,26 @@
 use core::ffi;
-use std::{fmt::Display, fs};
+use std::{fmt::Display, fs, path::Path};
 
 use anyhow::Context;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum ObjectKind {
     Blob,
     Tree,
     Commit,
+    Tag,
+}
+
+impl Display for ObjectKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Commit => "commit",
+            ObjectKind::Tag => "tag",
+        };
+        write!(f, "{name}")
+    }
 }
 
 #[derive(Debug)]
@@ -28,19 +41,36 @@ impl std::str::FromStr for ObjectKind {
             "blob" => Ok(ObjectKind::Blob),
             "tree" => Ok(ObjectKind::Tree),
             "commit" => Ok(ObjectKind::Commit),
+            "tag" => Ok(ObjectKind::Tag),
             _ => Err(FileTypeParseError),
         }
     }
 }
 
+pub(crate) mod commit;
 pub(crate) mod read;
 pub(crate) mod write;
 
 pub(crate) fn open(object_hash: &str) -> anyhow::Result<std::fs::File> {
+    let object_hash = resolve_hash(object_hash)?;
+    fs::File::open(format!(
+        ".git/objects/{}/{}",
+        &object_hash[..2],
+        &object_hash[2..]
+    ))
+    .context("Opening object file")
+}
+
+/// Expands an (abbreviated) hex object name to the full 40 character hash
+pub(crate) fn resolve_hash(object_hash: &str) -> anyhow::Result<String> {
     if object_hash.len() < 4 || object_hash.len() > 40 {
-        anyhow::bail!("");
+        anyhow::bail!("Invalid object name length: {object_hash}");
     }
-    hex::decode(object_hash).context("Non-hex object hash")?;
+    if !object_hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        anyhow::bail!("Non-hex object hash: {object_hash}");
+    }
+    let object_hash = object_hash.to_ascii_lowercase();
+    let object_hash = object_hash.as_str();
     let dir_name = format!(".git/objects/{}", &object_hash[..2]);
     let object_name_pref = &object_hash[2..];
     if !fs::exists(&dir_name)? {
@@ -57,7 +87,7 @@ pub(crate) fn open(object_hash: &str) -> anyhow::Result<std::fs::File> {
         if file_name.starts_with(object_name_pref) {
             count += 1;
             if count > 1 {
-                anyhow::bail!("");
+                anyhow::bail!("Ambiguous object name: {object_hash}");
             }
             buf.push(file_name);
         }
@@ -65,9 +95,29 @@ pub(crate) fn open(object_hash: &str) -> anyhow::Result<std::fs::File> {
     let Some(file_name) = buf.first() else {
         anyhow::bail!("Not found: {object_hash}");
     };
-    fs::File::open(format!("{dir_name}/{}", file_name)).context("Opening object file")
+    Ok(format!("{}{}", &object_hash[..2], file_name))
+}
+
+/// Returns true if the object is stored in the object database
+pub(crate) fn exists(hash: &[u8; 20]) -> bool {
+    let hash = hex::encode(hash);
+    Path::new(&format!(".git/objects/{}/{}", &hash[..2], &hash[2..])).exists()
 }
 
+/// Owned tree entry, as produced by `read::parse_tree`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TreeItem {
+    pub(crate) mode: u32,
+    pub(crate) name: String,
+    pub(crate) hash: [u8; 20],
+}
+
+pub(crate) const MODE_TREE: u32 = 0o40000;
+pub(crate) const MODE_FILE: u32 = 0o100644;
+pub(crate) const MODE_EXECUTABLE: u32 = 0o100755;
+pub(crate) const MODE_SYMLINK: u32 = 0o120000;
+pub(crate) const MODE_GITLINK: u32 = 0o160000;
+
 pub(crate) struct TreeEntry<'a> {
     mode: &'a str,
     kind: String,
@@ -76,7 +126,7 @@ pub(crate) struct TreeEntry<'a> {
 }
 
 impl<'a> TreeEntry<'a> {
-    pub(crate) fn parse(mode_name: &'a Vec<u8>, hash: &[u8; 20]) -> anyhow::Result<TreeEntry<'a>> {
+    pub(crate) fn parse(mode_name: &'a [u8], hash: &[u8; 20]) -> anyhow::Result<TreeEntry<'a>> {
         let str = ffi::CStr::from_bytes_with_nul(mode_name)?;
         let str = str.to_str().context("Converting Ctr to str")?;
         let Some((mode, name)) = str.split_once(' ') else {
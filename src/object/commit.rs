@@ -0,0 +1,206 @@
+use anyhow::Context;
+
+use crate::{
+    date::{parse_date, parse_tz},
+    object::ObjectKind,
+};
+
+/// Identity line of a commit, tag or reflog entry: `<name> <<email>> <timestamp> <tz>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Signature {
+    pub(crate) name: String,
+    pub(crate) email: String,
+    pub(crate) time: i64,
+    /// Offset from UTC in minutes
+    pub(crate) tz_offset: i32,
+}
+
+impl Signature {
+    /// The identity for `role` (`AUTHOR` or `COMMITTER`) at the current time, taken from the
+    /// `GIT_<role>_NAME`, `GIT_<role>_EMAIL` and `GIT_<role>_DATE` environment variables, falling
+    /// back to `user.name` and `user.email`
+    pub(crate) fn current(role: &str) -> anyhow::Result<Signature> {
+        let config = crate::config::read_repo_config();
+        let name = std::env::var(format!("GIT_{role}_NAME"))
+            .ok()
+            .or_else(|| config.get("user.name").map(str::to_string))
+            .or_else(|| std::env::var("USER").ok())
+            .context("Unable to determine identity, set user.name")?;
+        let email = std::env::var(format!("GIT_{role}_EMAIL"))
+            .ok()
+            .or_else(|| config.get("user.email").map(str::to_string))
+            .or_else(|| std::env::var("EMAIL").ok())
+            .context("Unable to determine identity, set user.email")?;
+        let (time, tz_offset) = match std::env::var(format!("GIT_{role}_DATE")) {
+            Ok(date) => parse_date(&date)?,
+            Err(_) => {
+                let now = chrono::Local::now();
+                (now.timestamp(), now.offset().local_minus_utc() / 60)
+            }
+        };
+        Ok(Signature {
+            name,
+            email,
+            time,
+            tz_offset,
+        })
+    }
+
+    /// Parses the value of an `author`, `committer` or `tagger` header
+    pub(crate) fn parse(value: &str) -> anyhow::Result<Signature> {
+        let (name, rest) = value
+            .split_once('<')
+            .with_context(|| format!("Invalid identity: {value}"))?;
+        let (email, date) = rest
+            .rsplit_once('>')
+            .with_context(|| format!("Invalid identity: {value}"))?;
+        let mut date = date.split_whitespace();
+        let time = date.next().and_then(|time| time.parse().ok()).unwrap_or(0);
+        let tz_offset = date.next().and_then(parse_tz).unwrap_or(0);
+        Ok(Signature {
+            name: name.trim().to_string(),
+            email: email.to_string(),
+            time,
+            tz_offset,
+        })
+    }
+
+    /// `Name <email>` without the date
+    pub(crate) fn identity(&self) -> String {
+        format!("{} <{}>", self.name, self.email)
+    }
+}
+
+impl std::fmt::Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.tz_offset < 0 { '-' } else { '+' };
+        let offset = self.tz_offset.abs();
+        write!(
+            f,
+            "{} <{}> {} {sign}{:02}{:02}",
+            self.name,
+            self.email,
+            self.time,
+            offset / 60,
+            offset % 60
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Commit {
+    pub(crate) tree: [u8; 20],
+    pub(crate) parents: Vec<[u8; 20]>,
+    pub(crate) author: Signature,
+    pub(crate) committer: Signature,
+    pub(crate) message: String,
+}
+
+impl Commit {
+    pub(crate) fn parse(content: &[u8]) -> anyhow::Result<Commit> {
+        let content = String::from_utf8_lossy(content);
+        let (headers, message) = split_headers(&content);
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        for (key, value) in headers {
+            match key.as_str() {
+                "tree" => tree = Some(parse_hash(&value)?),
+                "parent" => parents.push(parse_hash(&value)?),
+                "author" => author = Some(Signature::parse(&value)?),
+                "committer" => committer = Some(Signature::parse(&value)?),
+                _ => {}
+            }
+        }
+        let author = author.context("Commit has no author")?;
+        Ok(Commit {
+            tree: tree.context("Commit has no tree")?,
+            parents,
+            committer: committer.unwrap_or_else(|| author.clone()),
+            author,
+            message,
+        })
+    }
+
+    /// First line of the message
+    pub(crate) fn summary(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
+
+    pub(crate) fn read(hash: &[u8; 20]) -> anyhow::Result<Commit> {
+        let content =
+            crate::object::read::read_object_of_kind(&hex::encode(hash), ObjectKind::Commit)?;
+        Commit::parse(&content)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Tag {
+    pub(crate) object: [u8; 20],
+    /// The name the tag was created with
+    pub(crate) name: String,
+    /// Missing from some very old tags
+    pub(crate) tagger: Option<Signature>,
+    pub(crate) message: String,
+}
+
+impl Tag {
+    pub(crate) fn parse(content: &[u8]) -> anyhow::Result<Tag> {
+        let content = String::from_utf8_lossy(content);
+        let (headers, message) = split_headers(&content);
+        let object = headers
+            .iter()
+            .find(|(key, _)| key == "object")
+            .context("Tag has no object")?;
+        let name = headers
+            .iter()
+            .find(|(key, _)| key == "tag")
+            .map(|(_, name)| name.clone())
+            .unwrap_or_default();
+        let tagger = headers
+            .iter()
+            .find(|(key, _)| key == "tagger")
+            .and_then(|(_, tagger)| Signature::parse(tagger).ok());
+        Ok(Tag {
+            object: parse_hash(&object.1)?,
+            name,
+            tagger,
+            message,
+        })
+    }
+
+    pub(crate) fn read(hash: &[u8; 20]) -> anyhow::Result<Tag> {
+        let content =
+            crate::object::read::read_object_of_kind(&hex::encode(hash), ObjectKind::Tag)?;
+        Tag::parse(&content)
+    }
+}
+
+/// Splits `key value` header lines (with space-indented continuation lines) from the message
+fn split_headers(content: &str) -> (Vec<(String, String)>, String) {
+    let (head, message) = match content.split_once("\n\n") {
+        Some((head, message)) => (head, message.to_string()),
+        None => (content.trim_end_matches('\n'), String::new()),
+    };
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in head.lines() {
+        if let Some(continuation) = line.strip_prefix(' ') {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push('\n');
+                value.push_str(continuation);
+            }
+            continue;
+        }
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        headers.push((key.to_string(), value.to_string()));
+    }
+    (headers, message)
+}
+
+pub(crate) fn parse_hash(value: &str) -> anyhow::Result<[u8; 20]> {
+    let mut hash = [0; 20];
+    hex::decode_to_slice(value.trim(), &mut hash)
+        .with_context(|| format!("Invalid object id: {value}"))?;
+    Ok(hash)
+}
@@ -4,9 +4,11 @@ use std::{
     io::{BufRead, BufReader, Read, Take},
 };
 
+use flate2::read::ZlibDecoder;
+
 use crate::object::ObjectKind;
 
-use super::TreeEntry;
+use super::{TreeEntry, TreeItem};
 
 use anyhow::Context;
 
@@ -102,7 +104,7 @@ pub(crate) fn parse_header<R: Read>(
         .context("Unknown object type: {file_type}")?;
     let size = size.parse::<u64>().context("Parsing content size")?;
 
-    return Ok((object_kind, size));
+    Ok((object_kind, size))
 }
 
 impl<'a, R: Read> Read for GitObjectReader<'a, R> {
@@ -110,7 +112,101 @@ impl<'a, R: Read> Read for GitObjectReader<'a, R> {
         match self.kind {
             ObjectKind::Blob => self.source.read(buf),
             ObjectKind::Commit => self.source.read(buf),
+            ObjectKind::Tag => self.source.read(buf),
             ObjectKind::Tree => read_tree(&mut self.source, buf, self.opt),
         }
     }
 }
+
+/// Reads a whole object into memory, returning its kind and raw content
+pub(crate) fn read_object(object_hash: &str) -> anyhow::Result<(ObjectKind, Vec<u8>)> {
+    let file = crate::object::open(object_hash)
+        .with_context(|| format!("Not a valid object name: {object_hash}"))?;
+    let mut reader = BufReader::new(ZlibDecoder::new(file));
+    let (kind, size) = parse_header(&mut reader).context("Parsing object header")?;
+    let mut content = Vec::with_capacity(size as usize);
+    reader
+        .take(size)
+        .read_to_end(&mut content)
+        .context("Reading object content")?;
+    anyhow::ensure!(
+        content.len() as u64 == size,
+        "Object {object_hash} is truncated"
+    );
+    Ok((kind, content))
+}
+
+/// Reads an object and checks that it has the expected kind
+pub(crate) fn read_object_of_kind(
+    object_hash: &str,
+    expected: ObjectKind,
+) -> anyhow::Result<Vec<u8>> {
+    let (kind, content) = read_object(object_hash)?;
+    anyhow::ensure!(
+        kind == expected,
+        "Object {object_hash} is a {kind}, not a {expected}"
+    );
+    Ok(content)
+}
+
+/// Parses the content of a tree object into its entries
+pub(crate) fn parse_tree(mut content: &[u8]) -> anyhow::Result<Vec<TreeItem>> {
+    let mut items = Vec::new();
+    while !content.is_empty() {
+        let Some(nul) = content.iter().position(|&b| b == 0) else {
+            anyhow::bail!("Invalid tree entry data");
+        };
+        anyhow::ensure!(content.len() >= nul + 21, "Truncated tree entry");
+        let header = std::str::from_utf8(&content[..nul]).context("Tree entry is not utf-8")?;
+        let Some((mode, name)) = header.split_once(' ') else {
+            anyhow::bail!("Unknown tree entry header: {header}");
+        };
+        let mode =
+            u32::from_str_radix(mode, 8).with_context(|| format!("Unknown file mode: {mode}"))?;
+        let mut hash = [0; 20];
+        hash.copy_from_slice(&content[nul + 1..nul + 21]);
+        items.push(TreeItem {
+            mode,
+            name: name.to_string(),
+            hash,
+        });
+        content = &content[nul + 21..];
+    }
+    Ok(items)
+}
+
+/// Reads and parses a tree object
+pub(crate) fn read_tree_items(tree_hash: &[u8; 20]) -> anyhow::Result<Vec<TreeItem>> {
+    let content = read_object_of_kind(&hex::encode(tree_hash), ObjectKind::Tree)?;
+    parse_tree(&content)
+}
+
+/// Recursively lists every non-tree entry of a tree, with paths joined by '/'
+pub(crate) fn flatten_tree(tree_hash: &[u8; 20], prefix: &str) -> anyhow::Result<Vec<TreeItem>> {
+    let mut out = Vec::new();
+    flatten_tree_into(tree_hash, prefix, &mut out)?;
+    Ok(out)
+}
+
+fn flatten_tree_into(
+    tree_hash: &[u8; 20],
+    prefix: &str,
+    out: &mut Vec<TreeItem>,
+) -> anyhow::Result<()> {
+    for item in read_tree_items(tree_hash)? {
+        let path = if prefix.is_empty() {
+            item.name.clone()
+        } else {
+            format!("{prefix}/{}", item.name)
+        };
+        if item.mode == crate::object::MODE_TREE && !item.name.contains('/') {
+            flatten_tree_into(&item.hash, &path, out)?;
+        } else if crate::index::verify_path(&path) && !item.name.contains('/') {
+            out.push(TreeItem { name: path, ..item });
+        } else {
+            // A name like `..` or `.git` would have the entry written outside the working tree
+            anyhow::bail!("invalid path '{path}'");
+        }
+    }
+    Ok(())
+}
@@ -4,6 +4,7 @@ use ignore::WalkBuilder;
 use sha1::Digest;
 use std::{
     cmp::Ordering,
+    collections::BTreeMap,
     fs::{self, File},
     fmt::Write,
     io::Write as IOWrite,
@@ -11,7 +12,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::config::parse_config_from_file;
+use crate::object::{commit::Signature, ObjectKind, MODE_TREE};
 
 // TODO: reorganize tree and commit writing code, isolate file writing functionality
 // to use in all object writing code (write to tmp file and copy to real file + chmod to read-only)
@@ -145,76 +146,121 @@ pub(crate) fn write_tree(path: &Path) -> anyhow::Result<Option<[u8; 20]>> {
     Ok(Some(hash_bytes.into()))
 }
 
-fn write_blob(file_path: &Path) -> anyhow::Result<[u8; 20]> {
-    calc_hash_object(file_path, true)
-}
-
-pub(crate) fn write_commit(tree_hash: String, parent: Option<String>, message: String) -> anyhow::Result<[u8; 20]> {
-    let git_config = parse_config_from_file(File::open(".git/config").context("Open git config file")?);
-    let Some(author) = git_config.get("user.name") else {
-        anyhow::bail!("No author name found in config");
-        };
-    let Some(email) = git_config.get("user.email") else {
-        anyhow::bail!("No author email found in config");
-        };
-    let mut commit = String::new();
-    writeln!(commit, "tree {tree_hash}")?;
-    if let Some(parent) = parent {
-        writeln!(commit, "parent {parent}")?;
-    };
-    let now = chrono::Local::now();
-    let timezone = now.offset().local_minus_utc() / 3600;
-    let timezone = if timezone >= 0 {
-        format!("+{:0>2}", timezone)
-    } else {
-        format!("-{:0>2}", -timezone)
-    };
-    writeln!(
-        commit,
-        "author {} <{}> {} {}00",
-        author,
-        email,
-        now.timestamp(),
-        timezone
-    )?;
-    writeln!(
-        commit,
-        "committer {} <{}> {} {}00\n",
-        author,
-        email,
-        now.timestamp(),
-        timezone
-    )?;
-    writeln!(commit, "{}", message)?;
-    let size = commit.len();
-    let header = format!("commit {size}\0");
+/// Computes the id of an in-memory object, storing it when `save` is set
+pub(crate) fn hash_object(
+    kind: ObjectKind,
+    content: &[u8],
+    save: bool,
+) -> anyhow::Result<[u8; 20]> {
+    let header = format!("{kind} {}\0", content.len());
     let mut hasher = sha1::Sha1::new();
     hasher.update(header.as_bytes());
-    hasher.update(commit.as_bytes());
-    let hash_bytes = hasher.finalize();
+    hasher.update(content);
+    let hash_bytes: [u8; 20] = hasher.finalize().into();
+    if !save || crate::object::exists(&hash_bytes) {
+        return Ok(hash_bytes);
+    }
     let hash = hex::encode(hash_bytes);
 
-    let tmp_file_path = Path::new(".git/objects/.tmp").join("commit_tmp");
-    let tmp_dir_path = tmp_file_path.parent().unwrap();
+    let tmp_dir_path = Path::new(".git/objects/.tmp");
     fs::create_dir_all(tmp_dir_path).context("Create temp path")?;
+    let tmp_file_path = tmp_dir_path.join(format!("{kind}_{hash}"));
     let write_file = File::create(&tmp_file_path).context("Create tmp file")?;
     let mut zlib_encoder = ZlibEncoder::new(write_file, Compression::default());
+    zlib_encoder.write_all(header.as_bytes())?;
+    zlib_encoder.write_all(content)?;
+    zlib_encoder.finish().context("Compressing object")?;
 
-    write!(zlib_encoder, "commit {size}\0")?;
-    zlib_encoder.write_all(commit.as_bytes())?;
-    fs::create_dir_all(format!(".git/objects/{}", &hash[..2]))
-        .context("Creating object dir")?;
+    fs::create_dir_all(format!(".git/objects/{}", &hash[..2])).context("Creating object dir")?;
     fs::rename(
         &tmp_file_path,
         format!(".git/objects/{}/{}", &hash[..2], &hash[2..]),
     )
     .context("Move temp file to actual file")?;
+    Ok(hash_bytes)
+}
+
+fn write_blob(file_path: &Path) -> anyhow::Result<[u8; 20]> {
+    calc_hash_object(file_path, true)
+}
+
+/// Writes the tree holding the given files, keyed by their full paths, along with the subtrees
+/// it needs
+pub(crate) fn write_tree_from_paths(
+    files: &BTreeMap<String, (u32, [u8; 20])>,
+) -> anyhow::Result<[u8; 20]> {
+    write_subtree(
+        files
+            .iter()
+            .map(|(path, blob)| (path.as_str(), *blob))
+            .collect(),
+    )
+}
+
+/// Mode and hash of a tree entry
+type Entry = (u32, [u8; 20]);
+
+fn write_subtree(files: Vec<(&str, Entry)>) -> anyhow::Result<[u8; 20]> {
+    let mut entries = Vec::new();
+    let mut subtrees: BTreeMap<&str, Vec<(&str, Entry)>> = BTreeMap::new();
+    for (path, blob) in files {
+        match path.split_once('/') {
+            Some((dir, rest)) => subtrees.entry(dir).or_default().push((rest, blob)),
+            None => entries.push((path.to_string(), blob)),
+        }
+    }
+    for (dir, files) in subtrees {
+        entries.push((dir.to_string(), (MODE_TREE, write_subtree(files)?)));
+    }
+    // Trees sort as if their names ended in a slash
+    let sort_key = |(name, (mode, _)): &(String, Entry)| {
+        let mut key = name.as_bytes().to_vec();
+        if *mode == MODE_TREE {
+            key.push(b'/');
+        }
+        key
+    };
+    entries.sort_by_cached_key(sort_key);
+    let mut content = Vec::new();
+    for (name, (mode, hash)) in entries {
+        content.extend(format!("{mode:o} {name}\0").as_bytes());
+        content.extend(hash);
+    }
+    hash_object(ObjectKind::Tree, &content, true)
+}
 
-    Ok(hash_bytes.into())
+/// Writes a commit of `tree` with the given parents, by the current author and committer
+pub(crate) fn write_commit(
+    tree: &[u8; 20],
+    parents: &[[u8; 20]],
+    message: &str,
+) -> anyhow::Result<[u8; 20]> {
+    write_commit_by(tree, parents, &Signature::current("AUTHOR")?, message)
+}
+
+/// Writes a commit of `tree` keeping the author of the change, as cherry-picks do, committed by
+/// the current committer
+pub(crate) fn write_commit_by(
+    tree: &[u8; 20],
+    parents: &[[u8; 20]],
+    author: &Signature,
+    message: &str,
+) -> anyhow::Result<[u8; 20]> {
+    let committer = Signature::current("COMMITTER")?;
+    let mut commit = String::new();
+    writeln!(commit, "tree {}", hex::encode(tree))?;
+    for parent in parents {
+        writeln!(commit, "parent {}", hex::encode(parent))?;
+    }
+    writeln!(commit, "author {author}")?;
+    writeln!(commit, "committer {committer}")?;
+    writeln!(commit)?;
+    commit.push_str(message);
+    hash_object(ObjectKind::Commit, commit.as_bytes(), true)
 }
 
 pub(crate) fn calc_hash_object(file_path: &Path, save_file: bool) -> anyhow::Result<[u8; 20]> {
-    let metadata = fs::metadata(&file_path).context("Stating the file")?;
+    let metadata = fs::metadata(file_path).context("Stating the file")?;
     let size = metadata.len();
     let mut file = File::open(file_path).context("Opening file")?;
 
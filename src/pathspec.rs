@@ -0,0 +1,100 @@
+/// Limits commands to a set of paths. A pattern matches a path equal to it, any path below it,
+/// or, when it contains `*`, `?` or `[`, paths matching it as a glob.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Pathspec {
+    patterns: Vec<String>,
+}
+
+impl Pathspec {
+    pub(crate) fn new(patterns: &[String]) -> Pathspec {
+        Pathspec {
+            patterns: patterns
+                .iter()
+                .map(|pattern| {
+                    let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+                    pattern.trim_end_matches('/').to_string()
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        self.patterns.is_empty()
+            || self
+                .patterns
+                .iter()
+                .any(|pattern| pattern_matches(pattern, path))
+    }
+
+    /// Patterns that match none of `paths`
+    pub(crate) fn unmatched<'a>(&self, paths: impl IntoIterator<Item = &'a str>) -> Vec<&str> {
+        let mut unmatched: Vec<&str> = self.patterns.iter().map(String::as_str).collect();
+        for path in paths {
+            unmatched.retain(|pattern| !pattern_matches(pattern, path));
+        }
+        unmatched
+    }
+
+    /// Whether paths below the directory `dir` may match, so a tree walk has to descend into it
+    pub(crate) fn leads_to(&self, dir: &str) -> bool {
+        self.patterns.iter().any(|pattern| {
+            pattern.contains(['*', '?', '['])
+                || pattern
+                    .strip_prefix(dir)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+}
+
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    pattern.is_empty()
+        || pattern == "."
+        || path == pattern
+        || path
+            .strip_prefix(pattern)
+            .is_some_and(|rest| rest.starts_with('/'))
+        || (pattern.contains(['*', '?', '[']) && wildmatch(pattern.as_bytes(), path.as_bytes()))
+}
+
+/// Shell-style glob matching where `*` also matches `/`, as git pathspecs do by default
+pub(crate) fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') => (0..=text.len()).any(|i| wildmatch(&pattern[1..], &text[i..])),
+        Some(b'?') => !text.is_empty() && wildmatch(&pattern[1..], &text[1..]),
+        Some(b'[') => {
+            let Some(end) = pattern
+                .iter()
+                .skip(2)
+                .position(|&c| c == b']')
+                .map(|i| i + 2)
+            else {
+                return text.first() == Some(&b'[') && wildmatch(&pattern[1..], &text[1..]);
+            };
+            let Some(&c) = text.first() else {
+                return false;
+            };
+            let mut class = &pattern[1..end];
+            let negated = matches!(class.first(), Some(b'!') | Some(b'^'));
+            if negated {
+                class = &class[1..];
+            }
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    matched |= class[i] <= c && c <= class[i + 2];
+                    i += 3;
+                } else {
+                    matched |= class[i] == c;
+                    i += 1;
+                }
+            }
+            matched != negated && wildmatch(&pattern[end + 1..], &text[1..])
+        }
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && wildmatch(&pattern[2..], &text[1..])
+        }
+        Some(&p) => text.first() == Some(&p) && wildmatch(&pattern[1..], &text[1..]),
+    }
+}
@@ -0,0 +1,402 @@
+//! Showing commits the way `git log` does: the built-in `--pretty` formats and `--format`
+//! templates with `%` placeholders.
+
+use crate::{
+    date::{self, DateMode},
+    diff::patch::abbrev,
+    object::{
+        commit::{Commit, Signature},
+        read::read_object_of_kind,
+        ObjectKind,
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Format {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+    Fuller,
+    Raw,
+    /// A `--format` template; with `terminator`, every commit ends with a newline, otherwise
+    /// commits are only separated by one
+    Template {
+        template: String,
+        terminator: bool,
+    },
+}
+
+impl Format {
+    /// Parses the value of `--pretty` or `--format`: a built-in format name (or a prefix of one),
+    /// `format:<template>`, `tformat:<template>`, or a bare template containing `%`
+    pub(crate) fn parse(value: &str) -> anyhow::Result<Format> {
+        if let Some(template) = value.strip_prefix("format:") {
+            return Ok(Format::Template {
+                template: template.to_string(),
+                terminator: false,
+            });
+        }
+        if let Some(template) = value.strip_prefix("tformat:") {
+            return Ok(Format::Template {
+                template: template.to_string(),
+                terminator: true,
+            });
+        }
+        if value.is_empty() || value.contains('%') {
+            return Ok(Format::Template {
+                template: value.to_string(),
+                terminator: true,
+            });
+        }
+        let formats = [
+            ("raw", Format::Raw),
+            ("medium", Format::Medium),
+            ("short", Format::Short),
+            ("fuller", Format::Fuller),
+            ("full", Format::Full),
+            ("oneline", Format::Oneline),
+        ];
+        // Of the names the value abbreviates, the shortest wins, as with `full` and `fuller`
+        formats
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(value))
+            .min_by_key(|(name, _)| name.len())
+            .map(|(_, format)| format)
+            .ok_or_else(|| anyhow::anyhow!("invalid --pretty format: {value}"))
+    }
+
+    /// Whether each commit is followed by a newline, rather than commits being separated by one
+    pub(crate) fn uses_terminator(&self) -> bool {
+        match self {
+            Format::Oneline => true,
+            Format::Template { terminator, .. } => *terminator,
+            _ => false,
+        }
+    }
+
+    /// Whether commits print as nothing at all, not even a terminating newline
+    pub(crate) fn is_empty(&self) -> bool {
+        matches!(self, Format::Template { template, .. } if template.is_empty())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PrettyOptions {
+    pub(crate) format: Format,
+    /// Abbreviate the commit id on the `commit` line and in `oneline`
+    pub(crate) abbrev_commit: bool,
+    pub(crate) date_mode: DateMode,
+    /// Show the ref names pointing at commits next to their ids
+    pub(crate) decorate: bool,
+}
+
+/// A commit to show, with the parents that history simplification left it
+pub(crate) struct ShownCommit<'a> {
+    pub(crate) hash: &'a [u8; 20],
+    pub(crate) commit: &'a Commit,
+    pub(crate) parents: &'a [[u8; 20]],
+    /// The ref names pointing at the commit, for `--decorate` and `%d`
+    pub(crate) decorations: &'a [String],
+}
+
+/// The subject of a message: the lines of its first paragraph joined by spaces
+pub(crate) fn subject(message: &str) -> String {
+    let lines: Vec<&str> = message
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim_end)
+        .collect();
+    lines.join(" ")
+}
+
+/// Strips comment lines and trailing whitespace from a message, squeezing runs of blank lines
+/// and dropping those at either end
+pub(crate) fn cleanup_message(message: &str) -> String {
+    let mut out = String::new();
+    let mut blank = false;
+    for line in message.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Everything after the subject paragraph and the blank lines following it
+pub(crate) fn body(message: &str) -> &str {
+    // Leading blank lines, the subject paragraph, then the blank lines after it
+    let mut state = 0;
+    let mut offset = 0;
+    for line in message.split_inclusive('\n') {
+        let blank = line.trim().is_empty();
+        match (state, blank) {
+            (0, false) => state = 1,
+            (1, true) => state = 2,
+            (2, false) => break,
+            _ => {}
+        }
+        offset += line.len();
+    }
+    &message[offset..]
+}
+
+/// The first line of the subject reduced to characters safe in file names, as `format-patch`
+/// names patches
+pub(crate) fn sanitized_subject(message: &str) -> String {
+    let mut out = String::new();
+    let mut space = 2;
+    let line = message
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            if space == 1 {
+                out.push('-');
+            }
+            space = 0;
+            out.push(c);
+            if c == '.' {
+                while chars.peek() == Some(&'.') {
+                    chars.next();
+                }
+            }
+        } else {
+            space |= 1;
+        }
+    }
+    out.trim_end_matches(['.', '-']).to_string()
+}
+
+/// Expands tabs to the next multiple of eight columns from the start of the line
+fn expand_tabs(line: &str) -> String {
+    let mut out = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = 8 - width % 8;
+            out.extend(std::iter::repeat_n(' ', spaces));
+            width += spaces;
+        } else {
+            out.push(c);
+            width += 1;
+        }
+    }
+    out
+}
+
+/// Decorations as they follow a commit id: ` (HEAD -> main, tag: v1.0)`, or nothing
+fn decoration_list(decorations: &[String]) -> String {
+    if decorations.is_empty() {
+        return String::new();
+    }
+    format!(" ({})", decorations.join(", "))
+}
+
+/// Formats one commit. The built-in formats other than `oneline` end in a newline; `oneline`
+/// and templates leave terminating the entry to the caller.
+pub(crate) fn format_commit(shown: &ShownCommit, opts: &PrettyOptions) -> anyhow::Result<String> {
+    let commit = shown.commit;
+    let hash = if opts.abbrev_commit {
+        abbrev(shown.hash)
+    } else {
+        hex::encode(shown.hash)
+    };
+    let decorations = if opts.decorate {
+        decoration_list(shown.decorations)
+    } else {
+        String::new()
+    };
+    let mut out = String::new();
+    match &opts.format {
+        Format::Template { template, .. } => return Ok(expand(template, shown, opts)),
+        Format::Oneline => {
+            out.push_str(&format!("{hash}{decorations} {}", subject(&commit.message)));
+            return Ok(out);
+        }
+        _ => out.push_str(&format!("commit {hash}{decorations}\n")),
+    }
+
+    if opts.format == Format::Raw {
+        let content = read_object_of_kind(&hex::encode(shown.hash), ObjectKind::Commit)?;
+        let content = String::from_utf8_lossy(&content);
+        let headers = content
+            .split_once("\n\n")
+            .map_or(&*content, |(headers, _)| headers);
+        out.push_str(headers);
+        out.push('\n');
+    } else {
+        if shown.parents.len() > 1 {
+            let parents: Vec<String> = shown.parents.iter().map(abbrev).collect();
+            out.push_str(&format!("Merge: {}\n", parents.join(" ")));
+        }
+        let (author, committer) = (&commit.author, &commit.committer);
+        let author_date = date::format(author.time, author.tz_offset, opts.date_mode);
+        let commit_date = date::format(committer.time, committer.tz_offset, opts.date_mode);
+        match opts.format {
+            Format::Medium => {
+                out.push_str(&format!("Author: {}\n", author.identity()));
+                out.push_str(&format!("Date:   {author_date}\n"));
+            }
+            Format::Short => out.push_str(&format!("Author: {}\n", author.identity())),
+            Format::Full => {
+                out.push_str(&format!("Author: {}\n", author.identity()));
+                out.push_str(&format!("Commit: {}\n", committer.identity()));
+            }
+            _ => {
+                out.push_str(&format!("Author:     {}\n", author.identity()));
+                out.push_str(&format!("AuthorDate: {author_date}\n"));
+                out.push_str(&format!("Commit:     {}\n", committer.identity()));
+                out.push_str(&format!("CommitDate: {commit_date}\n"));
+            }
+        }
+    }
+
+    // The message is indented, without leading blank lines and, for `short`, only its first
+    // paragraph
+    out.push('\n');
+    let expand = matches!(opts.format, Format::Medium | Format::Full | Format::Fuller);
+    let mut first = true;
+    for line in commit.message.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            if first {
+                continue;
+            }
+            if opts.format == Format::Short {
+                break;
+            }
+        }
+        first = false;
+        out.push_str("    ");
+        if expand {
+            out.push_str(&expand_tabs(line));
+        } else {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    Ok(out)
+}
+
+/// The lines naming the tagger of an annotated tag, like the author lines of the format:
+/// nothing for `oneline`, and a date only for `medium` and `fuller`
+pub(crate) fn format_tagger(tagger: &Signature, opts: &PrettyOptions) -> String {
+    let date = date::format(tagger.time, tagger.tz_offset, opts.date_mode);
+    match opts.format {
+        Format::Oneline => String::new(),
+        Format::Medium => format!("Tagger: {}\nDate:   {date}\n", tagger.identity()),
+        Format::Fuller => format!("Tagger:     {}\nTaggerDate: {date}\n", tagger.identity()),
+        _ => format!("Tagger: {}\n", tagger.identity()),
+    }
+}
+
+/// Expands the `%` placeholders of a `--format` template. Unknown placeholders are kept as
+/// they are.
+fn expand(template: &str, shown: &ShownCommit, opts: &PrettyOptions) -> String {
+    let commit = shown.commit;
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('%') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let mut chars = rest.chars();
+        let Some(c) = chars.next() else {
+            out.push('%');
+            break;
+        };
+        let mut consumed = c.len_utf8();
+        let expansion = match c {
+            '%' => Some(String::from("%")),
+            'n' => Some(String::from("\n")),
+            'H' => Some(hex::encode(shown.hash)),
+            'h' => Some(abbrev(shown.hash)),
+            'T' => Some(hex::encode(commit.tree)),
+            't' => Some(abbrev(&commit.tree)),
+            'P' => Some(
+                shown
+                    .parents
+                    .iter()
+                    .map(hex::encode)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            'p' => Some(
+                shown
+                    .parents
+                    .iter()
+                    .map(abbrev)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            'd' => Some(decoration_list(shown.decorations)),
+            'D' => Some(shown.decorations.join(", ")),
+            's' => Some(subject(&commit.message)),
+            'f' => Some(sanitized_subject(&commit.message)),
+            'b' => Some(body(&commit.message).to_string()),
+            'B' => Some(commit.message.clone()),
+            'a' | 'c' => {
+                let signature = if c == 'a' {
+                    &commit.author
+                } else {
+                    &commit.committer
+                };
+                let (time, tz) = (signature.time, signature.tz_offset);
+                let field = match chars.next() {
+                    Some('n' | 'N') => Some(signature.name.clone()),
+                    Some('e' | 'E') => Some(signature.email.clone()),
+                    Some('l' | 'L') => Some(
+                        signature
+                            .email
+                            .split('@')
+                            .next()
+                            .unwrap_or_default()
+                            .to_string(),
+                    ),
+                    Some('d') => Some(date::format(time, tz, opts.date_mode)),
+                    Some('D') => Some(date::format(time, tz, DateMode::Rfc)),
+                    Some('r') => Some(date::format(time, tz, DateMode::Relative)),
+                    Some('t') => Some(date::format(time, tz, DateMode::Unix)),
+                    Some('i') => Some(date::format(time, tz, DateMode::Iso)),
+                    Some('I') => Some(date::format(time, tz, DateMode::IsoStrict)),
+                    Some('s') => Some(date::format(time, tz, DateMode::Short)),
+                    _ => None,
+                };
+                consumed += 1;
+                field
+            }
+            'x' => {
+                let hex = rest.get(1..3).filter(|hex| hex.len() == 2);
+                consumed += 2;
+                hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .map(|byte| char::from(byte).to_string())
+            }
+            _ => None,
+        };
+        match expansion {
+            Some(expansion) => {
+                out.push_str(&expansion);
+                rest = &rest[consumed..];
+            }
+            None => out.push('%'),
+        }
+    }
+    out.push_str(rest);
+    out
+}
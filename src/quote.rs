@@ -0,0 +1,125 @@
+/// Quotes a path the way git does in its output: paths containing `"`, `\`, control or
+/// non-ASCII characters (and spaces, when `quote_space` is set) are wrapped in double quotes
+/// with C-style escapes.
+pub(crate) fn c_style(path: &str, quote_space: bool) -> String {
+    let needs_quoting = path.bytes().any(|b| {
+        b == b'"' || b == b'\\' || !(0x20..0x7f).contains(&b) || (quote_space && b == b' ')
+    });
+    if !needs_quoting {
+        return path.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for b in path.bytes() {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            b if !(0x20..0x7f).contains(&b) => quoted.push_str(&format!("\\{b:03o}")),
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quotes an argument for the shell the way git records command lines: wrapped in single
+/// quotes, with `'` and `!` escaped outside of them
+pub(crate) fn shell(arg: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in arg.chars() {
+        match c {
+            '\'' | '!' => {
+                quoted.push_str("'\\");
+                quoted.push(c);
+                quoted.push('\'');
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Splits a line of arguments quoted by [`shell`] back into the arguments, `None` if any of
+/// them isn't quoted that way
+pub(crate) fn split_shell(line: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    let mut chars = line.trim_start().chars().peekable();
+    while chars.peek().is_some() {
+        if chars.next() != Some('\'') {
+            return None;
+        }
+        let mut arg = String::new();
+        loop {
+            match chars.next()? {
+                '\'' => {}
+                c => {
+                    arg.push(c);
+                    continue;
+                }
+            }
+            // Outside the quotes, only an escaped `'` or `!` may join the next quoted part
+            let mut lookahead = chars.clone();
+            match (lookahead.next(), lookahead.next(), lookahead.next()) {
+                (Some('\\'), Some(c @ ('\'' | '!')), Some('\'')) => {
+                    arg.push(c);
+                    chars = lookahead;
+                }
+                (None, _, _) => break,
+                (Some(c), _, _) if c.is_whitespace() => break,
+                _ => return None,
+            }
+        }
+        args.push(arg);
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    }
+    Some(args)
+}
+
+/// Reads a path quoted by [`c_style`] from the start of `text`, returning it and what follows
+/// the closing quote, or `None` if `text` doesn't start with a well-formed quoted path
+pub(crate) fn unquote_c_style(text: &str) -> Option<(String, &str)> {
+    let bytes = text.as_bytes();
+    if bytes.first() != Some(&b'"') {
+        return None;
+    }
+    let mut path = Vec::new();
+    let mut i = 1;
+    loop {
+        match *bytes.get(i)? {
+            b'"' => break,
+            b'\\' => {
+                i += 1;
+                let byte = match *bytes.get(i)? {
+                    b'a' => 0x07,
+                    b'b' => 0x08,
+                    b't' => b'\t',
+                    b'n' => b'\n',
+                    b'v' => 0x0b,
+                    b'f' => 0x0c,
+                    b'r' => b'\r',
+                    b'"' => b'"',
+                    b'\\' => b'\\',
+                    b'0'..=b'3' => {
+                        let octal = std::str::from_utf8(bytes.get(i..i + 3)?).ok()?;
+                        i += 2;
+                        u8::from_str_radix(octal, 8).ok()?
+                    }
+                    _ => return None,
+                };
+                path.push(byte);
+            }
+            byte => path.push(byte),
+        }
+        i += 1;
+    }
+    Some((String::from_utf8_lossy(&path).into_owned(), &text[i + 1..]))
+}
@@ -0,0 +1,2300 @@
+//! Rebasing: replaying the commits of a branch onto a new base by working through a todo list,
+//! as `git rebase` does with its merge backend.
+//!
+//! The todo list starts as a `pick` for each commit to replay; an interactive rebase lets the
+//! user edit it first, to reword, edit, squash, drop or reorder commits and run commands in
+//! between. `--rebase-merges` adds `label`, `reset` and `merge` commands recreating the shape of
+//! the history, and `--update-refs` an `update-ref` for each branch pointing into it. Everything
+//! lives in `.git/rebase-merge`, laid out as git lays it out, so that a rebase stopped for the
+//! user can be continued, skipped past or aborted by either.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::Context;
+
+use crate::{
+    checkout,
+    commands::status,
+    date::{self, DateMode},
+    diff::{
+        files::tree_to_tree,
+        patch::{abbrev, PatchOptions, PatchWriter},
+    },
+    editor,
+    index::Index,
+    merge::{
+        self,
+        content::{ConflictStyle, FileOptions, Labels},
+        octopus::index_tree,
+        MergeOptions,
+    },
+    merge_base,
+    object::{
+        commit::{parse_hash, Commit, Signature},
+        write::{write_commit_by, write_tree_from_paths},
+    },
+    pathspec::Pathspec,
+    pretty, refs, revision,
+    revwalk::{RevWalk, Revisions, Sort, WalkOptions},
+    sequencer::{self, Action},
+    status::UntrackedMode,
+    unpack::{self, UnpackOptions},
+    worktree,
+};
+
+const STATE: &str = ".git/rebase-merge";
+
+const NULL_ID: [u8; 20] = [0; 20];
+
+/// Files describing the command the rebase stopped at, removed before the next one runs
+const STOP_FILES: &[&str] = &["message", "author-script", "stopped-sha", "amend", "patch"];
+
+/// Files carrying a chain of `fixup` and `squash` commands from one to the next
+const CHAIN_FILES: &[&str] = &["current-fixups", "message-squash", "message-fixup"];
+
+const CONFLICT_HINT: &str = "Resolve all conflicts manually, mark them as resolved with
+\"git add/rm <conflicted_files>\", then run \"git rebase --continue\".
+You can instead skip this commit: run \"git rebase --skip\".
+To abort and get back to the state before \"git rebase\", run \"git rebase --abort\".";
+
+const RESCHEDULED_HINT: &str =
+    "It has been rescheduled; To edit the command before continuing, please
+edit the todo list first:
+
+    git rebase --edit-todo
+    git rebase --continue";
+
+const STAGED_CHANGES: &str = "error: you have staged changes in your working tree
+If these changes are meant to be squashed into the previous commit, run:
+
+  git commit --amend 
+
+If they are meant to go into a new commit, run:
+
+  git commit 
+
+In both cases, once you're done, continue with:
+
+  git rebase --continue
+
+";
+
+const EDIT_TODO_ADVICE: &str =
+    "You can fix this with 'git rebase --edit-todo' and then run 'git rebase --continue'.
+Or you can abort the rebase with 'git rebase --abort'.";
+
+const COMMANDS_HELP: &str = "
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup [-C | -c] <commit> = like \"squash\" but keep only the previous
+#                    commit's log message, unless -C is used, in which case
+#                    keep only this commit's message; -c is same as -C but
+#                    opens the editor
+# x, exec <command> = run command (the rest of the line) using shell
+# b, break = stop here (continue rebase later with 'git rebase --continue')
+# d, drop <commit> = remove commit
+# l, label <label> = label current HEAD with a name
+# t, reset <label> = reset HEAD to a label
+# m, merge [-C <commit> | -c <commit>] <label> [# <oneline>]
+#         create a merge commit using the original merge commit's
+#         message (or the oneline, if no original merge commit was
+#         specified); use -c <commit> to reword the commit message
+# u, update-ref <ref> = track a placeholder for the <ref> to be updated
+#                       to this position in the new commits. The <ref> is
+#                       updated at the end of the rebase
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+";
+
+/// How a run of the todo list ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// The rebase is finished
+    Done,
+    /// The rebase stopped as asked, by `edit` or `break`
+    Stopped,
+    /// A command failed or conflicted and waits for the user
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Pick,
+    Reword,
+    Edit,
+    Squash,
+    Fixup,
+    Exec,
+    Break,
+    Drop,
+    Label,
+    Reset,
+    Merge,
+    UpdateRef,
+    Noop,
+}
+
+/// Each command with its name and the letter it may be abbreviated to
+const COMMANDS: &[(Command, &str, Option<char>)] = &[
+    (Command::Pick, "pick", Some('p')),
+    (Command::Reword, "reword", Some('r')),
+    (Command::Edit, "edit", Some('e')),
+    (Command::Squash, "squash", Some('s')),
+    (Command::Fixup, "fixup", Some('f')),
+    (Command::Exec, "exec", Some('x')),
+    (Command::Break, "break", Some('b')),
+    (Command::Drop, "drop", Some('d')),
+    (Command::Label, "label", Some('l')),
+    (Command::Reset, "reset", Some('t')),
+    (Command::Merge, "merge", Some('m')),
+    (Command::UpdateRef, "update-ref", Some('u')),
+    (Command::Noop, "noop", None),
+];
+
+impl Command {
+    fn name(self) -> &'static str {
+        COMMANDS
+            .iter()
+            .find(|(command, _, _)| *command == self)
+            .map_or("", |(_, name, _)| name)
+    }
+
+    fn parse(word: &str) -> Option<Command> {
+        COMMANDS
+            .iter()
+            .find(|(_, name, letter)| {
+                *name == word || letter.is_some_and(|letter| word == letter.to_string())
+            })
+            .map(|(command, _, _)| *command)
+    }
+
+    /// Whether the command melds its commit into the one before
+    fn is_fixup(self) -> bool {
+        matches!(self, Command::Squash | Command::Fixup)
+    }
+}
+
+/// A line of the todo list
+#[derive(Debug, Clone)]
+struct Item {
+    /// `None` for comments and blank lines, whose text is kept in `arg`
+    command: Option<Command>,
+    /// The `-C` or `-c` of `fixup` and `merge`
+    flag: Option<char>,
+    commit: Option<[u8; 20]>,
+    /// The rest of the line: a subject, a label, a ref or a shell command
+    arg: String,
+}
+
+impl Item {
+    fn comment(text: &str) -> Item {
+        Item {
+            command: None,
+            flag: None,
+            commit: None,
+            arg: text.to_string(),
+        }
+    }
+
+    fn new(command: Command, commit: Option<[u8; 20]>, arg: &str) -> Item {
+        Item {
+            command: Some(command),
+            flag: None,
+            commit,
+            arg: arg.to_string(),
+        }
+    }
+
+    /// Parses a line, `None` if it isn't a valid command
+    fn parse(line: &str) -> Option<Item> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Some(Item::comment(line));
+        }
+        let (word, rest) = split_word(line);
+        let command = Command::parse(word)?;
+        let mut item = Item::new(command, None, "");
+        let mut rest = rest;
+        if matches!(command, Command::Fixup | Command::Merge) {
+            if let Some(after) = rest
+                .strip_prefix("-C ")
+                .or_else(|| rest.strip_prefix("-c "))
+            {
+                item.flag = rest[1..].chars().next();
+                rest = after.trim_start();
+            }
+        }
+        match command {
+            Command::Break | Command::Noop => {
+                if !rest.is_empty() {
+                    return None;
+                }
+            }
+            Command::Exec | Command::Label | Command::Reset | Command::UpdateRef => {
+                if rest.is_empty() {
+                    return None;
+                }
+                item.arg = rest.to_string();
+            }
+            Command::Merge if item.flag.is_none() => {
+                if rest.is_empty() {
+                    return None;
+                }
+                item.arg = rest.to_string();
+            }
+            _ => {
+                let (name, arg) = split_word(rest);
+                let commit = revision::resolve(name)
+                    .and_then(|hash| revision::peel_to_commit(&hash))
+                    .ok()?;
+                item.commit = Some(commit);
+                item.arg = arg.to_string();
+                if command == Command::Merge && item.arg.is_empty() {
+                    return None;
+                }
+            }
+        }
+        Some(item)
+    }
+
+    /// The line for the todo list, with the commit abbreviated when `short`
+    fn format(&self, short: bool) -> String {
+        let Some(command) = self.command else {
+            return self.arg.clone();
+        };
+        let mut line = command.name().to_string();
+        if let Some(flag) = self.flag {
+            line.push_str(&format!(" -{flag}"));
+        }
+        if let Some(commit) = &self.commit {
+            let name = match short {
+                true => abbrev(commit),
+                false => hex::encode(commit),
+            };
+            line.push_str(&format!(" {name}"));
+        }
+        if !self.arg.is_empty() {
+            line.push_str(&format!(" {}", self.arg));
+        }
+        line
+    }
+}
+
+/// Splits off the first word of `text` and the rest after the whitespace following it
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// Parses a todo list, `None` after reporting each line that isn't a valid command
+fn parse_todo(text: &str) -> Option<Vec<Item>> {
+    let mut items = Vec::new();
+    let mut valid = true;
+    for (i, line) in text.lines().enumerate() {
+        match Item::parse(line) {
+            Some(item) => items.push(item),
+            None => {
+                eprintln!("error: invalid line {}: {line}", i + 1);
+                valid = false;
+            }
+        }
+    }
+    valid.then_some(items)
+}
+
+/// Drops comments from an edited todo list, squeezing runs of blank lines and dropping those at
+/// either end, as git strips the lists it reads back from the editor
+fn strip_comments(items: Vec<Item>) -> Vec<Item> {
+    let mut stripped: Vec<Item> = Vec::new();
+    let mut blank = false;
+    for item in items {
+        if item.command.is_none() {
+            blank |= item.arg.is_empty() && !stripped.is_empty();
+            continue;
+        }
+        if blank {
+            stripped.push(Item::comment(""));
+            blank = false;
+        }
+        stripped.push(item);
+    }
+    stripped
+}
+
+fn count_commands(items: &[Item]) -> usize {
+    items.iter().filter(|item| item.command.is_some()).count()
+}
+
+fn format_items(items: &[Item], short: bool) -> String {
+    items
+        .iter()
+        .map(|item| format!("{}\n", item.format(short)))
+        .collect()
+}
+
+fn state_path(name: &str) -> PathBuf {
+    Path::new(STATE).join(name)
+}
+
+fn read_state(name: &str) -> Option<String> {
+    fs::read_to_string(state_path(name)).ok()
+}
+
+fn write_state(name: &str, content: &str) -> anyhow::Result<()> {
+    fs::write(state_path(name), content).with_context(|| format!("Writing {STATE}/{name}"))
+}
+
+fn remove_state(name: &str) -> anyhow::Result<()> {
+    let path = state_path(name);
+    if path.exists() {
+        fs::remove_file(&path).with_context(|| format!("Removing {STATE}/{name}"))?;
+    }
+    Ok(())
+}
+
+fn read_todo() -> anyhow::Result<Vec<Item>> {
+    let todo = read_state("git-rebase-todo").context("Reading the rebase todo list")?;
+    parse_todo(&todo).context("please fix this using 'git rebase --edit-todo'.")
+}
+
+fn write_todo(items: &[Item]) -> anyhow::Result<()> {
+    write_state("git-rebase-todo", &format_items(items, false))
+}
+
+fn read_number(name: &str) -> usize {
+    read_state(name)
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Whether a rebase is in progress
+pub(crate) fn in_progress() -> bool {
+    Path::new(STATE).is_dir()
+}
+
+/// What `start` needs to know about the rebase to do
+pub(crate) struct Options {
+    /// Commits reachable from it aren't replayed
+    pub(crate) upstream: [u8; 20],
+    /// Where the commits are replayed, and how it was named on the command line
+    pub(crate) onto: [u8; 20],
+    pub(crate) onto_name: String,
+    /// The branch being rebased as a full ref name, `None` for a detached HEAD
+    pub(crate) head_name: Option<String>,
+    pub(crate) orig_head: [u8; 20],
+    pub(crate) interactive: bool,
+    pub(crate) autosquash: bool,
+    pub(crate) rebase_merges: bool,
+    pub(crate) update_refs: bool,
+}
+
+/// Starts a rebase: writes the todo list, lets the user edit it when interactive, checks out
+/// the new base and works through the list
+pub(crate) fn start(opts: &Options) -> anyhow::Result<Outcome> {
+    let mut todo = make_todo(opts)?;
+
+    fs::create_dir_all(STATE).context("Creating the rebase state directory")?;
+    let head_name = opts.head_name.as_deref().unwrap_or("detached HEAD");
+    write_state("head-name", &format!("{head_name}\n"))?;
+    write_state("onto", &format!("{}\n", hex::encode(opts.onto)))?;
+    write_state("orig-head", &format!("{}\n", hex::encode(opts.orig_head)))?;
+    write_state("interactive", "")?;
+    write_state("no-reschedule-failed-exec", "")?;
+    if !opts.interactive {
+        write_state("drop_redundant_commits", "")?;
+    }
+
+    let header = format!(
+        "Rebase {}..{} onto {} ({} command{})",
+        abbrev(&opts.upstream),
+        abbrev(&opts.orig_head),
+        abbrev(&opts.onto),
+        count_commands(&todo),
+        if count_commands(&todo) == 1 { "" } else { "s" }
+    );
+    let help = todo_help(Some(&header));
+    let full = format!("{}{help}", format_items(&todo, false));
+    write_state("git-rebase-todo.backup", &full)?;
+    if opts.interactive {
+        todo = match edit_todo_list(&format_items(&todo, true), &help) {
+            Ok(Some(todo)) => todo,
+            Ok(None) => {
+                eprintln!("{EDIT_TODO_ADVICE}");
+                checkout_onto(&opts.onto, &opts.onto_name, &opts.orig_head)?;
+                return Ok(Outcome::Failed);
+            }
+            Err(err) => {
+                fs::remove_dir_all(STATE).context("Removing the rebase state")?;
+                eprintln!("error: {err:#}");
+                return Ok(Outcome::Failed);
+            }
+        };
+        if count_commands(&todo) == 0 {
+            fs::remove_dir_all(STATE).context("Removing the rebase state")?;
+            eprintln!("error: nothing to do");
+            return Ok(Outcome::Failed);
+        }
+    } else {
+        write_state("git-rebase-todo", &full)?;
+        todo = strip_comments(todo);
+    }
+    let total = todo.len();
+
+    // Picks of commits already sitting on the new base needn't be replayed
+    let mut onto = opts.onto;
+    let mut skipped = 0;
+    for item in &todo {
+        if item.command.is_none() {
+            skipped += 1;
+            continue;
+        }
+        let Some(commit) = item.commit.filter(|_| item.command == Some(Command::Pick)) else {
+            break;
+        };
+        if Commit::read(&commit)?.parents != [onto] {
+            break;
+        }
+        onto = commit;
+        skipped += 1;
+    }
+    let done: Vec<Item> = todo.drain(..skipped).collect();
+
+    let mut updates: Vec<RefUpdate> = todo
+        .iter()
+        .chain(&done)
+        .filter(|item| item.command == Some(Command::UpdateRef))
+        .map(|item| -> anyhow::Result<RefUpdate> {
+            Ok(RefUpdate {
+                name: item.arg.clone(),
+                old: refs::read_ref(&item.arg)?.unwrap_or(NULL_ID),
+                new: NULL_ID,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+    updates.sort_by(|a, b| a.name.cmp(&b.name));
+    if !updates.is_empty() {
+        write_update_refs(&updates)?;
+    }
+
+    checkout_onto(&onto, &opts.onto_name, &opts.orig_head)?;
+    write_state("done", &format_items(&done, false))?;
+    write_state("msgnum", &format!("{}\n", count_commands(&done)))?;
+    write_state("end", &format!("{total}\n"))?;
+    write_todo(&todo)?;
+    run()
+}
+
+/// Counts the commands done and left to do afresh for the progress shown as the rebase goes on
+/// after stopping, which unlike the count at the start leaves out blank lines
+fn recount() -> anyhow::Result<()> {
+    let done = read_state("done")
+        .and_then(|done| parse_todo(&done))
+        .unwrap_or_default();
+    let total = count_commands(&done) + count_commands(&read_todo()?);
+    write_state("end", &format!("{total}\n"))
+}
+
+/// Detaches HEAD at the commit the rebase starts from, noting the original one in `ORIG_HEAD`
+fn checkout_onto(onto: &[u8; 20], onto_name: &str, orig_head: &[u8; 20]) -> anyhow::Result<()> {
+    refs::update_ref("ORIG_HEAD", orig_head, "rebase: updating ORIG_HEAD")?;
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let mut index = Index::read()?;
+    let head_tree = Commit::read(&head)?.tree;
+    let onto_tree = Commit::read(onto)?.tree;
+    if let Err(err) = checkout::switch_trees(&mut index, Some(&head_tree), Some(&onto_tree), false)
+    {
+        fs::remove_dir_all(STATE).context("Removing the rebase state")?;
+        return Err(err.context("could not detach HEAD"));
+    }
+    index.write()?;
+    refs::detach_head(onto, &format!("rebase (start): checkout {onto_name}"))
+}
+
+/// The commits to replay, oldest first: those reachable from the branch but not from upstream,
+/// leaving out merges and commits whose changes upstream already has
+fn walk_commits(opts: &Options, no_merges: bool) -> anyhow::Result<Vec<[u8; 20]>> {
+    let range = format!(
+        "{}...{}",
+        hex::encode(opts.upstream),
+        hex::encode(opts.orig_head)
+    );
+    let revisions = Revisions::parse(&[range], &[], false)?;
+    let mut walk = RevWalk::new(
+        &revisions,
+        WalkOptions {
+            sort: Sort::Topo,
+            no_merges,
+            cherry_pick: true,
+            ..WalkOptions::default()
+        },
+    )?;
+    let mut commits = Vec::new();
+    while let Some(commit) = walk.next()? {
+        if !walk.is_left(&commit) {
+            commits.push(commit);
+        }
+    }
+    commits.reverse();
+    Ok(commits)
+}
+
+/// Whether a commit changes nothing compared with its first parent
+fn is_empty_commit(commit: &Commit) -> anyhow::Result<bool> {
+    Ok(match commit.parents.first() {
+        Some(parent) => Commit::read(parent)?.tree == commit.tree,
+        None => commit.tree == empty_tree()?,
+    })
+}
+
+/// A `pick` for each commit, with commits that were empty from the start marked as such
+fn pick_line(hash: &[u8; 20], commit: &Commit) -> anyhow::Result<Item> {
+    let mut subject = pretty::subject(&commit.message);
+    if is_empty_commit(commit)? {
+        subject.push_str(" # empty");
+    }
+    Ok(Item::new(Command::Pick, Some(*hash), &subject))
+}
+
+/// The todo list the rebase starts with, before the user edits it
+fn make_todo(opts: &Options) -> anyhow::Result<Vec<Item>> {
+    let mut todo = if opts.rebase_merges {
+        make_script_with_merges(opts)?
+    } else {
+        make_script(opts)?
+    };
+    // The refs go in first, so that those at a commit with fixups follow its whole chain
+    if opts.update_refs {
+        todo = add_update_refs(todo, opts.head_name.as_deref())?;
+    }
+    if opts.autosquash {
+        todo = rearrange_squash(todo)?;
+    }
+    if count_commands(&todo) == 0 {
+        todo.push(Item::new(Command::Noop, None, ""));
+    }
+    Ok(todo)
+}
+
+fn make_script(opts: &Options) -> anyhow::Result<Vec<Item>> {
+    walk_commits(opts, true)?
+        .iter()
+        .map(|hash| pick_line(hash, &Commit::read(hash)?))
+        .collect()
+}
+
+/// Names given to commits in a todo list recreating merges
+#[derive(Default)]
+struct LabelNames {
+    by_commit: HashMap<[u8; 20], String>,
+    taken: HashSet<String>,
+}
+
+impl LabelNames {
+    /// The label of a commit, giving it one based on `name` (or its abbreviated id) if it has none
+    /// yet. Names are reduced to characters that are safe in ref names and made unique.
+    fn label(&mut self, commit: &[u8; 20], name: Option<&str>) -> String {
+        if let Some(label) = self.by_commit.get(commit) {
+            return label.clone();
+        }
+        let mut label = match name {
+            None => abbrev(commit),
+            Some(name) => {
+                let mut label = String::new();
+                for c in name.chars() {
+                    if !c.is_ascii() || c.is_ascii_alphanumeric() {
+                        label.push(c);
+                    } else if !label.is_empty() && !label.ends_with('-') {
+                        label.push('-');
+                    }
+                }
+                if label.is_empty() {
+                    label = format!("rev-{}", abbrev(commit));
+                }
+                label
+            }
+        };
+        let is_hex = label.len() == 40 && label.bytes().all(|b| b.is_ascii_hexdigit());
+        if is_hex || label == "#" || self.taken.contains(&label) {
+            let base = label.clone();
+            let mut n = 2;
+            while self.taken.contains(&label) || n == 2 {
+                label = format!("{base}-{n}");
+                n += 1;
+            }
+        }
+        self.taken.insert(label.clone());
+        self.by_commit.insert(*commit, label.clone());
+        label
+    }
+}
+
+/// The todo list of `--rebase-merges`: each line of history is replayed after a `reset` to
+/// where it branched off, labelled where others merge it or branch from it, and merges are
+/// recreated with `merge` commands naming the labels
+fn make_script_with_merges(opts: &Options) -> anyhow::Result<Vec<Item>> {
+    let commits = walk_commits(opts, false)?;
+    let interesting: HashSet<[u8; 20]> = commits.iter().copied().collect();
+    let mut labels = LabelNames::default();
+    let bases = merge_base::merge_bases(&opts.upstream, &opts.orig_head)?;
+    if let Some(base) = bases.first() {
+        labels.label(base, Some("onto"));
+    }
+
+    // Gather the lines for the commits and the tips of the branches merged
+    let mut todo_lines: HashMap<[u8; 20], Vec<Item>> = HashMap::new();
+    let mut read = HashMap::new();
+    let mut tips = Vec::new();
+    for hash in &commits {
+        let commit = Commit::read(hash)?;
+        let oneline = pretty::subject(&commit.message);
+        let [_, merged @ ..] = commit.parents.as_slice() else {
+            todo_lines.insert(*hash, vec![pick_line(hash, &commit)?]);
+            read.insert(*hash, commit);
+            continue;
+        };
+        if merged.is_empty() {
+            todo_lines.insert(*hash, vec![pick_line(hash, &commit)?]);
+            read.insert(*hash, commit);
+            continue;
+        }
+        let name = merge_label(&oneline);
+        let mut arg = Vec::new();
+        for parent in merged {
+            if interesting.contains(parent) {
+                tips.push(*parent);
+                arg.push(labels.label(parent, Some(&name)));
+            } else {
+                arg.push(labels.label(parent, None));
+            }
+        }
+        let mut item = Item::new(
+            Command::Merge,
+            Some(*hash),
+            &format!("{} # {oneline}", arg.join(" ")),
+        );
+        item.flag = Some('C');
+        todo_lines.insert(*hash, vec![item]);
+        read.insert(*hash, commit);
+    }
+
+    // Label the commits more than one line of history grows from, and end with HEAD
+    let mut child_seen = HashSet::new();
+    for hash in &commits {
+        for parent in &read[hash].parents {
+            if interesting.contains(parent) && !child_seen.insert(*parent) {
+                labels.label(parent, Some("branch-point"));
+            }
+        }
+    }
+    tips.extend(commits.last());
+
+    let mut todo = vec![Item::new(Command::Label, None, "onto")];
+    let mut shown = HashSet::new();
+    for tip in tips {
+        if shown.contains(&tip) {
+            continue;
+        }
+        match labels.by_commit.get(&tip) {
+            Some(label) => {
+                todo.push(Item::comment(""));
+                todo.push(Item::comment(&format!("# Branch {label}")));
+            }
+            None => todo.push(Item::comment("")),
+        }
+        let mut line = Vec::new();
+        let mut commit = Some(tip);
+        while let Some(hash) =
+            commit.filter(|hash| interesting.contains(hash) && !shown.contains(hash))
+        {
+            line.push(hash);
+            commit = read[&hash].parents.first().copied();
+        }
+        match commit {
+            None => todo.push(Item::new(Command::Reset, None, "[new root]")),
+            Some(base) => {
+                let to = labels.label(&base, None);
+                if to == "onto" {
+                    todo.push(Item::new(Command::Reset, None, "onto"));
+                } else {
+                    let oneline = pretty::subject(&Commit::read(&base)?.message);
+                    todo.push(Item::new(
+                        Command::Reset,
+                        None,
+                        &format!("{to} # {oneline}"),
+                    ));
+                }
+            }
+        }
+        for hash in line.iter().rev() {
+            todo.extend(todo_lines.remove(hash).unwrap_or_default());
+            if let Some(label) = labels.by_commit.get(hash) {
+                todo.push(Item::new(Command::Label, None, label));
+            }
+            shown.insert(*hash);
+        }
+    }
+    Ok(todo)
+}
+
+/// The label for the branch a merge merged: the name quoted in `Merge branch 'x'`, the source of
+/// a pull request, or the whole subject
+fn merge_label(oneline: &str) -> String {
+    if let Some(rest) = oneline.strip_prefix("Merge ") {
+        if let Some((_, quoted)) = rest.split_once('\'') {
+            if let Some((name, _)) = quoted.split_once('\'') {
+                return name.to_string();
+            }
+        }
+    }
+    if let Some(rest) = oneline.strip_prefix("Merge pull request ") {
+        if let Some((_, from)) = rest.split_once(" from ") {
+            return from.to_string();
+        }
+    }
+    oneline.to_string()
+}
+
+/// Strips `fixup! `, `amend! ` and `squash! ` prefixes, `None` if there are none
+fn strip_fixupish(subject: &str) -> Option<&str> {
+    let mut rest = ["fixup! ", "amend! ", "squash! "]
+        .iter()
+        .find_map(|prefix| subject.strip_prefix(prefix))?;
+    loop {
+        rest = rest.trim_start();
+        match ["fixup! ", "amend! ", "squash! "]
+            .iter()
+            .find_map(|prefix| rest.strip_prefix(prefix))
+        {
+            Some(next) => rest = next,
+            None => return Some(rest),
+        }
+    }
+}
+
+/// `--autosquash`: moves each `fixup!`, `amend!` and `squash!` commit right after the commit it
+/// names, by subject, commit or subject prefix, turning it into the matching command
+fn rearrange_squash(items: Vec<Item>) -> anyhow::Result<Vec<Item>> {
+    let n = items.len();
+    let mut subjects: Vec<Option<String>> = vec![None; n];
+    let mut by_subject: HashMap<String, usize> = HashMap::new();
+    let mut by_commit: HashMap<[u8; 20], usize> = HashMap::new();
+    let mut next: Vec<Option<usize>> = vec![None; n];
+    let mut tail: Vec<Option<usize>> = vec![None; n];
+    let mut items = items;
+    let mut rearranged = false;
+    for i in 0..n {
+        let Some(commit) = items[i]
+            .commit
+            .filter(|_| items[i].command != Some(Command::Drop))
+        else {
+            continue;
+        };
+        let subject = pretty::subject(&Commit::read(&commit)?.message);
+        let mut target = None;
+        if let Some(rest) = strip_fixupish(&subject) {
+            target = match by_subject.get(rest) {
+                Some(&found) => Some(found),
+                None => {
+                    let named = (!rest.contains(' '))
+                        .then(|| {
+                            revision::resolve(rest).and_then(|hash| revision::peel_to_commit(&hash))
+                        })
+                        .and_then(Result::ok)
+                        .and_then(|hash| by_commit.get(&hash).copied());
+                    named.or_else(|| {
+                        (0..i).find(|&j| {
+                            subjects[j]
+                                .as_deref()
+                                .is_some_and(|earlier| earlier.starts_with(rest))
+                        })
+                    })
+                }
+            };
+        }
+        match target {
+            Some(target) => {
+                rearranged = true;
+                if subject.starts_with("fixup!") {
+                    items[i].command = Some(Command::Fixup);
+                } else if subject.starts_with("amend!") {
+                    items[i].command = Some(Command::Fixup);
+                    items[i].flag = Some('C');
+                } else {
+                    items[i].command = Some(Command::Squash);
+                }
+                let last = tail[target].unwrap_or(target);
+                next[i] = next[last];
+                next[last] = Some(i);
+                tail[target] = Some(i);
+            }
+            None => {
+                by_subject.entry(subject.clone()).or_insert(i);
+            }
+        }
+        subjects[i] = Some(subject);
+        by_commit.insert(commit, i);
+    }
+    if !rearranged {
+        return Ok(items);
+    }
+    let mut order = Vec::with_capacity(n);
+    for (i, item) in items.iter().enumerate() {
+        if item.command.is_some_and(Command::is_fixup) {
+            continue;
+        }
+        let mut current = Some(i);
+        while let Some(cur) = current {
+            order.push(cur);
+            current = next[cur];
+        }
+    }
+    Ok(order.into_iter().map(|i| items[i].clone()).collect())
+}
+
+/// `--update-refs`: after each commit other local branches point at, an `update-ref` moving
+/// them along with it
+fn add_update_refs(items: Vec<Item>, head_name: Option<&str>) -> anyhow::Result<Vec<Item>> {
+    let mut branches: HashMap<[u8; 20], Vec<String>> = HashMap::new();
+    for (name, hash) in refs::list("refs/heads/")? {
+        if Some(name.as_str()) != head_name {
+            branches.entry(hash).or_default().push(name);
+        }
+    }
+    let mut todo = Vec::new();
+    for item in items {
+        let names = item
+            .commit
+            .filter(|_| item.command.is_some())
+            .and_then(|commit| branches.get(&commit));
+        let names = names.cloned().unwrap_or_default();
+        todo.push(item);
+        if names.is_empty() {
+            continue;
+        }
+        for name in names {
+            todo.push(Item::new(Command::UpdateRef, None, &name));
+        }
+        todo.push(Item::comment(""));
+    }
+    Ok(todo)
+}
+
+/// The help following the todo list: for a new list, a header saying what it rebases;
+/// otherwise how to go on after editing it
+fn todo_help(header: Option<&str>) -> String {
+    match header {
+        Some(header) => format!("\n# {header}\n#{COMMANDS_HELP}# However, if you remove everything, the rebase will be aborted.\n#\n"),
+        None => format!("#{COMMANDS_HELP}# You are editing the todo file of an ongoing interactive rebase.\n# To continue rebase after editing, run:\n#     git rebase --continue\n#\n"),
+    }
+}
+
+/// Writes the todo list with help, opens it in the sequence editor and reads it back, `None` if
+/// the user left invalid lines in it
+fn edit_todo_list(todo: &str, help: &str) -> anyhow::Result<Option<Vec<Item>>> {
+    write_state("git-rebase-todo", &format!("{todo}{help}"))?;
+    editor::edit(&state_path("git-rebase-todo"), true)?;
+    let edited = read_state("git-rebase-todo").context("Reading the rebase todo list")?;
+    Ok(parse_todo(&edited).map(strip_comments))
+}
+
+/// A ref an `update-ref` command moves once the rebase is done, with where it pointed before and
+/// where it will point, the null id until its command has run
+struct RefUpdate {
+    name: String,
+    old: [u8; 20],
+    new: [u8; 20],
+}
+
+fn read_update_refs() -> anyhow::Result<Vec<RefUpdate>> {
+    let Some(content) = read_state("update-refs") else {
+        return Ok(Vec::new());
+    };
+    let lines: Vec<&str> = content.lines().collect();
+    lines
+        .chunks(3)
+        .map(|chunk| match chunk {
+            [name, old, new] => Ok(RefUpdate {
+                name: name.to_string(),
+                old: parse_hash(old)?,
+                new: parse_hash(new)?,
+            }),
+            _ => anyhow::bail!("Invalid {STATE}/update-refs"),
+        })
+        .collect()
+}
+
+fn write_update_refs(updates: &[RefUpdate]) -> anyhow::Result<()> {
+    let content: String = updates
+        .iter()
+        .map(|update| {
+            format!(
+                "{}\n{}\n{}\n",
+                update.name,
+                hex::encode(update.old),
+                hex::encode(update.new)
+            )
+        })
+        .collect();
+    write_state("update-refs", &content)
+}
+
+/// Erases the progress line, so that what follows starts on a clean one
+fn clear_line() {
+    let dumb = std::env::var("TERM").map_or(true, |term| term == "dumb");
+    if dumb {
+        let columns = std::env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse().ok())
+            .unwrap_or(80);
+        eprint!("\r{}\r", " ".repeat(columns));
+    } else {
+        eprint!("\r\x1b[K");
+    }
+}
+
+/// Works through the todo list until it is done or a command stops
+fn run() -> anyhow::Result<Outcome> {
+    loop {
+        let mut todo = read_todo()?;
+        let Some(position) = todo.iter().position(|item| item.command.is_some()) else {
+            return finish();
+        };
+        let rest = todo.split_off(position + 1);
+        let item = todo[position].clone();
+        append_done(&todo)?;
+        write_todo(&rest)?;
+        for name in STOP_FILES {
+            remove_state(name)?;
+        }
+        for file in [".git/MERGE_HEAD", ".git/AUTO_MERGE", ".git/REBASE_HEAD"] {
+            worktree::remove_if_exists(file)?;
+        }
+        let done = read_number("msgnum") + 1;
+        write_state("msgnum", &format!("{done}\n"))?;
+        let total = read_number("end");
+        eprint!("Rebasing ({done}/{total})\r");
+
+        let command = item.command.unwrap_or(Command::Noop);
+        if !command.is_fixup() {
+            for name in CHAIN_FILES {
+                remove_state(name)?;
+            }
+        }
+        let outcome = match command {
+            Command::Pick | Command::Reword | Command::Edit | Command::Squash | Command::Fixup => {
+                pick(&item)?
+            }
+            Command::Drop | Command::Noop => None,
+            Command::Exec => exec(&item.arg)?,
+            Command::Break => {
+                let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+                clear_line();
+                eprintln!(
+                    "Stopped at {} ({})",
+                    abbrev(&head),
+                    pretty::subject(&Commit::read(&head)?.message)
+                );
+                Some(Outcome::Stopped)
+            }
+            Command::Label => label(&item)?,
+            Command::Reset => reset(&item)?,
+            Command::Merge => merge_item(&item)?,
+            Command::UpdateRef => {
+                let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+                let mut updates = read_update_refs()?;
+                match updates.iter_mut().find(|update| update.name == item.arg) {
+                    Some(update) => update.new = head,
+                    None => updates.push(RefUpdate {
+                        name: item.arg.clone(),
+                        old: refs::read_ref(&item.arg)?.unwrap_or(NULL_ID),
+                        new: head,
+                    }),
+                }
+                write_update_refs(&updates)?;
+                None
+            }
+        };
+        if let Some(outcome) = outcome {
+            return Ok(outcome);
+        }
+        if let (
+            Some(
+                Command::Pick | Command::Reword | Command::Squash | Command::Fixup | Command::Merge,
+            ),
+            Some(commit),
+        ) = (item.command, item.commit)
+        {
+            record_rewritten(&commit)?;
+        }
+    }
+}
+
+/// Notes which commit HEAD now stands for in `rewritten-list`, once a chain of fixups has
+/// ended: until then the commits melded in wait in `rewritten-pending`
+fn record_rewritten(original: &[u8; 20]) -> anyhow::Result<()> {
+    let mut pending = read_state("rewritten-pending").unwrap_or_default();
+    pending.push_str(&format!("{}\n", hex::encode(original)));
+    if next_is_fixup()? {
+        return write_state("rewritten-pending", &pending);
+    }
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let mut list = read_state("rewritten-list").unwrap_or_default();
+    for line in pending.lines() {
+        list.push_str(&format!("{line} {}\n", hex::encode(head)));
+    }
+    write_state("rewritten-list", &list)?;
+    remove_state("rewritten-pending")
+}
+
+fn append_done(items: &[Item]) -> anyhow::Result<()> {
+    let mut done = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(state_path("done"))
+        .context("Opening the list of done rebase commands")?;
+    done.write_all(format_items(items, false).as_bytes())
+        .context("Writing the list of done rebase commands")
+}
+
+/// Puts a command that couldn't run back at the top of the todo list, so that it runs again
+/// once the user has dealt with what stopped it. Like git, it stays in the list of those done.
+fn reschedule(item: &Item) -> anyhow::Result<Outcome> {
+    let mut todo = read_todo()?;
+    todo.insert(0, item.clone());
+    write_todo(&todo)?;
+    eprintln!("hint: Could not execute the todo command");
+    eprintln!("hint: ");
+    eprintln!("hint:     {}", item.format(false));
+    eprintln!("hint: ");
+    for line in RESCHEDULED_HINT.lines() {
+        eprintln!("hint: {line}");
+    }
+    Ok(Outcome::Failed)
+}
+
+/// The tree of the first parent, the empty tree for a root commit
+fn parent_tree(commit: &Commit) -> anyhow::Result<[u8; 20]> {
+    match commit.parents.first() {
+        Some(parent) => Ok(Commit::read(parent)?.tree),
+        None => empty_tree(),
+    }
+}
+
+fn empty_tree() -> anyhow::Result<[u8; 20]> {
+    write_tree_from_paths(&BTreeMap::new())
+}
+
+fn first_line(message: &str) -> &str {
+    message.lines().next().unwrap_or_default()
+}
+
+/// Replays the commit of a `pick`, `reword`, `edit`, `squash` or `fixup`, fast-forwarding when
+/// it already sits on HEAD
+fn pick(item: &Item) -> anyhow::Result<Option<Outcome>> {
+    let command = item.command.unwrap_or(Command::Pick);
+    let hash = item.commit.context("The command names no commit")?;
+    let commit = Commit::read(&hash)?;
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let head_commit = Commit::read(&head)?;
+    let mut index = Index::read()?;
+    if index_tree(&index)? != Some(head_commit.tree) {
+        eprintln!("error: your local changes would be overwritten by rebase.");
+        eprintln!("hint: commit your changes or stash them to proceed.");
+        return reschedule(item).map(Some);
+    }
+    let parent = match commit.parents.as_slice() {
+        [] => None,
+        [parent] => Some(*parent),
+        _ => {
+            eprintln!(
+                "error: commit {} is a merge but no -m option was given.",
+                hex::encode(hash)
+            );
+            return reschedule(item).map(Some);
+        }
+    };
+
+    if !command.is_fixup() && parent == Some(head) {
+        if let Err(err) = checkout::switch_trees(
+            &mut index,
+            Some(&head_commit.tree),
+            Some(&commit.tree),
+            false,
+        ) {
+            eprintln!("error: {err:#}");
+            return reschedule(item).map(Some);
+        }
+        index.write()?;
+        refs::update_ref("HEAD", &hash, "rebase: fast-forward")?;
+        return after_pick(item, &hash);
+    }
+
+    if command.is_fixup() {
+        update_squash_messages(command, item.flag, &hash, &commit)?;
+    }
+    let result = sequencer::merge_changes(
+        Action::Pick,
+        &hash,
+        &commit,
+        parent.as_ref(),
+        &head_commit.tree,
+    )?;
+    if let Err(err) = merge::checkout(&mut index, Some(&head_commit.tree), &result) {
+        eprintln!("error: {err:#}");
+        return reschedule(item).map(Some);
+    }
+    index.write()?;
+    fs::write(".git/AUTO_MERGE", format!("{}\n", hex::encode(result.tree)))
+        .context("Writing AUTO_MERGE")?;
+    let message = match command.is_fixup() {
+        true => read_state("message-squash").unwrap_or_else(|| commit.message.clone()),
+        false => commit.message.clone(),
+    };
+    if !result.is_clean() {
+        let mut out = std::io::stdout().lock();
+        for line in &result.messages {
+            writeln!(out, "{line}")?;
+        }
+        drop(out);
+        let conflicts: String = result
+            .conflicts
+            .keys()
+            .map(|path| format!("#\t{path}\n"))
+            .collect();
+        if command.is_fixup() {
+            // The melded message is kept as it is, to be amended into HEAD on `--continue`
+            fs::write(".git/MERGE_MSG", &message).context("Writing MERGE_MSG")?;
+            write_stop_state(
+                &hash,
+                &commit,
+                message.strip_suffix('\n').unwrap_or(&message),
+            )?;
+            write_state("amend", &format!("{}\n", hex::encode(head)))?;
+        } else {
+            fs::write(
+                ".git/MERGE_MSG",
+                format!("{message}\n# Conflicts:\n{conflicts}"),
+            )
+            .context("Writing MERGE_MSG")?;
+            write_stop_state(&hash, &commit, &message)?;
+        }
+        let name = format!("{}... {}", abbrev(&hash), first_line(&commit.message));
+        eprintln!("error: could not apply {name}");
+        for line in CONFLICT_HINT.lines() {
+            eprintln!("hint: {line}");
+        }
+        eprintln!("Could not apply {name}");
+        return Ok(Some(Outcome::Failed));
+    }
+
+    if command.is_fixup() {
+        let final_fixup = !next_is_fixup()?;
+        squash_commit(item.flag, &hash, &result.tree, final_fixup)?;
+        return Ok(None);
+    }
+    if result.tree == head_commit.tree && !is_empty_commit(&commit)? {
+        if state_path("drop_redundant_commits").exists() {
+            eprintln!(
+                "dropping {} {} -- patch contents already upstream",
+                hex::encode(hash),
+                first_line(&commit.message)
+            );
+            return Ok(None);
+        }
+        fs::write(".git/MERGE_MSG", &message).context("Writing MERGE_MSG")?;
+        fs::write(".git/CHERRY_PICK_HEAD", format!("{}\n", hex::encode(hash)))
+            .context("Writing CHERRY_PICK_HEAD")?;
+        write_stop_state(&hash, &commit, &message)?;
+        eprint!(
+            "The previous cherry-pick is now empty, possibly due to conflict resolution.\nIf you wish to commit it anyway, use:\n\n    git commit --allow-empty\n\nOtherwise, please use 'git rebase --skip'\n"
+        );
+        status::invoke(false, false, None, false, String::from("normal"))?;
+        eprintln!(
+            "Could not apply {}... {}",
+            abbrev(&hash),
+            first_line(&commit.message)
+        );
+        return Ok(Some(Outcome::Failed));
+    }
+
+    let new = write_commit_by(&result.tree, &[head], &commit.author, &message)?;
+    refs::update_ref(
+        "HEAD",
+        &new,
+        &format!("rebase ({}): {}", command.name(), first_line(&message)),
+    )?;
+    after_pick(item, &hash)
+}
+
+/// What follows a replayed commit: a `reword` edits its message and an `edit` stops
+fn after_pick(item: &Item, original: &[u8; 20]) -> anyhow::Result<Option<Outcome>> {
+    match item.command {
+        Some(Command::Reword) => {
+            let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+            let head_commit = Commit::read(&head)?;
+            let base = parent_tree(&head_commit)?;
+            let message = match edit_message(&head_commit.message, &base, &head_commit.author, true)
+            {
+                Ok(message) => message,
+                Err(err) => {
+                    eprintln!("error: {err:#}");
+                    eprintln!(
+                        "Could not apply {}... {}",
+                        abbrev(original),
+                        first_line(&head_commit.message)
+                    );
+                    return Ok(Some(Outcome::Failed));
+                }
+            };
+            let new = write_commit_by(
+                &head_commit.tree,
+                &head_commit.parents,
+                &head_commit.author,
+                &message,
+            )?;
+            refs::update_ref(
+                "HEAD",
+                &new,
+                &format!("rebase (reword): {}", first_line(&message)),
+            )?;
+            sequencer::print_summary(&new, &base, true)?;
+            Ok(None)
+        }
+        Some(Command::Edit) => {
+            let commit = Commit::read(original)?;
+            let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+            write_stop_state(original, &commit, &commit.message)?;
+            write_state("amend", &format!("{}\n", hex::encode(head)))?;
+            clear_line();
+            eprint!(
+                "Stopped at {}...  {}\nYou can amend the commit now, with\n\n  git commit --amend \n\nOnce you are satisfied with your changes, run\n\n  git rebase --continue\n",
+                abbrev(original),
+                first_line(&commit.message)
+            );
+            Ok(Some(Outcome::Stopped))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Records the commit a command stopped at, with the message and author to commit it with and
+/// the patch it makes
+fn write_stop_state(hash: &[u8; 20], commit: &Commit, message: &str) -> anyhow::Result<()> {
+    let pairs = tree_to_tree(
+        Some(&parent_tree(commit)?),
+        Some(&commit.tree),
+        &Pathspec::new(&[]),
+        false,
+    )?;
+    let opts = PatchOptions::default();
+    let mut patch = Vec::new();
+    let mut writer = PatchWriter::new(&mut patch, &opts);
+    for pair in &pairs {
+        writer.write(pair)?;
+    }
+    writer.finish()?;
+    fs::write(state_path("patch"), patch).with_context(|| format!("Writing {STATE}/patch"))?;
+    write_state("message", &format!("{message}\n"))?;
+    write_author_script(&commit.author)?;
+    write_state("stopped-sha", &format!("{}\n", hex::encode(hash)))?;
+    fs::write(".git/REBASE_HEAD", format!("{}\n", hex::encode(hash))).context("Writing REBASE_HEAD")
+}
+
+fn quote_shell(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn write_author_script(author: &Signature) -> anyhow::Result<()> {
+    let sign = if author.tz_offset < 0 { '-' } else { '+' };
+    let offset = author.tz_offset.abs();
+    let date = format!(
+        "@{} {sign}{:02}{:02}",
+        author.time,
+        offset / 60,
+        offset % 60
+    );
+    write_state(
+        "author-script",
+        &format!(
+            "GIT_AUTHOR_NAME={}\nGIT_AUTHOR_EMAIL={}\nGIT_AUTHOR_DATE={}\n",
+            quote_shell(&author.name),
+            quote_shell(&author.email),
+            quote_shell(&date)
+        ),
+    )
+}
+
+/// The author recorded when a command stopped, `None` if there is no record
+fn read_author_script() -> anyhow::Result<Option<Signature>> {
+    let Some(script) = read_state("author-script") else {
+        return Ok(None);
+    };
+    let mut values = HashMap::new();
+    for line in script.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value
+            .strip_prefix('\'')
+            .and_then(|value| value.strip_suffix('\''))
+            .unwrap_or(value)
+            .replace("'\\''", "'");
+        values.insert(key, value);
+    }
+    let (Some(name), Some(email), Some(date)) = (
+        values.get("GIT_AUTHOR_NAME"),
+        values.get("GIT_AUTHOR_EMAIL"),
+        values.get("GIT_AUTHOR_DATE"),
+    ) else {
+        anyhow::bail!("Invalid {STATE}/author-script");
+    };
+    let date = date.strip_prefix('@').unwrap_or(date);
+    Ok(Some(Signature::parse(&format!("{name} <{email}> {date}"))?))
+}
+
+/// Lets the user edit a commit message in the commit editor, below it the usual instructions,
+/// the author when it isn't the committer, the date when amending and the status compared with
+/// `base`, all commented out
+pub(crate) fn edit_message(
+    message: &str,
+    base: &[u8; 20],
+    author: &Signature,
+    amend: bool,
+) -> anyhow::Result<String> {
+    let mut text = message.to_string();
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str("\n# Please enter the commit message for your changes. Lines starting\n# with '#' will be ignored, and an empty message aborts the commit.\n#\n");
+    let committer = Signature::current("COMMITTER")?;
+    let show_author = author.identity() != committer.identity();
+    if show_author {
+        text.push_str(&format!("# Author:    {}\n", author.identity()));
+    }
+    if amend {
+        let date = date::format(author.time, author.tz_offset, DateMode::Default);
+        text.push_str(&format!("# Date:      {date}\n"));
+    }
+    if show_author || amend {
+        text.push_str("#\n");
+    }
+    text.push_str(&status::commit_template(Some(base))?);
+    let path = Path::new(".git/COMMIT_EDITMSG");
+    fs::write(path, &text).context("Writing COMMIT_EDITMSG")?;
+    editor::edit(path, false)?;
+    let edited = fs::read_to_string(path).context("Reading COMMIT_EDITMSG")?;
+    let message = pretty::cleanup_message(&edited);
+    anyhow::ensure!(
+        !message.is_empty(),
+        "Aborting commit due to empty commit message."
+    );
+    Ok(message)
+}
+
+/// Whether the next command melds into the commit too, so that a chain of them goes on
+fn next_is_fixup() -> anyhow::Result<bool> {
+    Ok(read_todo()?
+        .iter()
+        .find_map(|item| item.command)
+        .is_some_and(Command::is_fixup))
+}
+
+/// Comments out each line of `text`
+fn commented(text: &str) -> String {
+    text.lines()
+        .map(|line| match line {
+            "" => String::from("#\n"),
+            line => format!("# {line}\n"),
+        })
+        .collect()
+}
+
+/// The length of the subject paragraph of a message, with its line endings
+fn subject_length(message: &str) -> usize {
+    message
+        .split_inclusive('\n')
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::len)
+        .sum()
+}
+
+/// Adds a `fixup` or `squash` to the chain of them being melded into HEAD: the combined message
+/// the chain builds up in `message-squash`, the message a chain of plain fixups keeps in
+/// `message-fixup`, and the commands so far in `current-fixups`
+fn update_squash_messages(
+    command: Command,
+    flag: Option<char>,
+    hash: &[u8; 20],
+    commit: &Commit,
+) -> anyhow::Result<()> {
+    let fixups = read_state("current-fixups").unwrap_or_default();
+    let count = fixups.lines().count();
+    let seen_squash = fixups.lines().any(|line| line.starts_with("squash"));
+    let replaces = command == Command::Fixup && flag.is_some();
+    let mut message = if count > 0 {
+        let previous = read_state("message-squash").context("could not read message-squash")?;
+        let rest = match previous.starts_with('#') {
+            true => previous.find('\n').map_or("", |end| &previous[end..]),
+            false => previous.as_str(),
+        };
+        let message = format!("# This is a combination of {} commits.{rest}", count + 2);
+        match replaces && !seen_squash {
+            true => comment_previous_messages(&message),
+            false => message,
+        }
+    } else {
+        let head = refs::head_commit()?.context("need a HEAD to fixup")?;
+        let head_message = Commit::read(&head)?.message;
+        if command == Command::Fixup && flag.is_none() {
+            write_state("message-fixup", &head_message)?;
+        }
+        let first = match replaces {
+            true => "The 1st commit message will be skipped:",
+            false => "This is the 1st commit message:",
+        };
+        let body = match replaces {
+            true => commented(&head_message),
+            false => head_message,
+        };
+        format!("# This is a combination of 2 commits.\n# {first}\n\n{body}")
+    };
+
+    let body = &commit.message;
+    if command == Command::Squash || replaces {
+        let comment_subject = body.starts_with("amend!")
+            || ((command == Command::Squash || seen_squash)
+                && (body.starts_with("squash!") || body.starts_with("fixup!")));
+        let commented_length = match comment_subject {
+            true => subject_length(body),
+            false => 0,
+        };
+        message.push_str(&format!(
+            "\n# This is the commit message #{}:\n\n",
+            count + 2
+        ));
+        message.push_str(&commented(&body[..commented_length]));
+        let kept = &body[commented_length..];
+        message.push_str(kept);
+        if replaces && !seen_squash && (state_path("message-fixup").exists() || count == 0) {
+            write_state("message-fixup", kept.trim_start_matches('\n'))?;
+        } else {
+            remove_state("message-fixup")?;
+        }
+    } else {
+        message.push_str(&format!(
+            "\n# The commit message #{} will be skipped:\n\n",
+            count + 2
+        ));
+        message.push_str(&commented(body));
+    }
+    write_state("message-squash", &message)?;
+    let line = format!("{} {}", command.name(), hex::encode(hash));
+    let fixups = match fixups.is_empty() {
+        true => line,
+        false => format!("{}\n{line}", fixups.trim_end()),
+    };
+    write_state("current-fixups", &fixups)
+}
+
+/// For a `fixup -C` following plain fixups, comments out the messages kept so far, since only
+/// the new one is kept
+fn comment_previous_messages(message: &str) -> String {
+    let mut out = String::new();
+    let mut n = 1;
+    let mut keeping = true;
+    let mut lines = message.split_inclusive('\n').peekable();
+    while let Some(line) = lines.next() {
+        let kept_header = match n {
+            1 => String::from("# This is the 1st commit message:\n"),
+            n => format!("# This is the commit message #{n}:\n"),
+        };
+        let skipped_header = match n {
+            1 => String::from("# The 1st commit message will be skipped:\n"),
+            n => format!("# The commit message #{n} will be skipped:\n"),
+        };
+        if line == kept_header {
+            out.push_str(&skipped_header);
+            if lines.peek() == Some(&"\n") {
+                out.push('\n');
+                lines.next();
+            }
+            keeping = false;
+            n += 1;
+        } else if line == skipped_header {
+            out.push_str(line);
+            keeping = true;
+            n += 1;
+        } else if keeping {
+            out.push_str(line);
+        } else {
+            out.push_str(&commented(line));
+        }
+    }
+    out
+}
+
+/// Melds `tree` into HEAD for a `fixup` or `squash`. Until the chain ends the combined message
+/// is kept as it is; the last one takes the message to keep, or lets the user edit the
+/// combination, and clears the chain.
+fn squash_commit(
+    flag: Option<char>,
+    picked: &[u8; 20],
+    tree: &[u8; 20],
+    final_fixup: bool,
+) -> anyhow::Result<()> {
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let head_commit = Commit::read(&head)?;
+    let squashed = read_state("message-squash").unwrap_or_default();
+    let fixup_message = read_state("message-fixup").filter(|_| flag != Some('c'));
+    let (message, show) = if !final_fixup {
+        (squashed, false)
+    } else if let Some(message) = fixup_message {
+        (message, false)
+    } else {
+        // As with a commit made in the editor, the squashed commit is left in REBASE_HEAD
+        let base = parent_tree(&head_commit)?;
+        fs::write(".git/MERGE_MSG", &squashed).context("Writing MERGE_MSG")?;
+        let message = edit_message(&squashed, &base, &head_commit.author, true)?;
+        fs::write(".git/REBASE_HEAD", format!("{}\n", hex::encode(picked)))
+            .context("Writing REBASE_HEAD")?;
+        refs::clear_merge_state()?;
+        (message, true)
+    };
+    let new = write_commit_by(tree, &head_commit.parents, &head_commit.author, &message)?;
+    let command = match read_state("current-fixups")
+        .unwrap_or_default()
+        .lines()
+        .last()
+        .and_then(|line| line.split_whitespace().next())
+    {
+        Some("fixup") => "fixup",
+        _ => "squash",
+    };
+    refs::update_ref(
+        "HEAD",
+        &new,
+        &format!("rebase ({command}): {}", first_line(&message)),
+    )?;
+    if show {
+        sequencer::print_summary(&new, &parent_tree(&head_commit)?, true)?;
+    }
+    if final_fixup {
+        for name in CHAIN_FILES {
+            remove_state(name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the index or working tree differ from HEAD
+fn has_local_changes() -> anyhow::Result<bool> {
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let tree = Commit::read(&head)?.tree;
+    let mut index = Index::read()?;
+    let report = crate::status::compute(&mut index, Some(&tree), UntrackedMode::No)?;
+    Ok(report.has_staged() || report.has_unstaged() || !report.unmerged.is_empty())
+}
+
+/// Reports changes to tracked files that rebasing would clobber, returning whether there are any
+pub(crate) fn report_local_changes() -> anyhow::Result<bool> {
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let tree = Commit::read(&head)?.tree;
+    let mut index = Index::read()?;
+    let report = crate::status::compute(&mut index, Some(&tree), UntrackedMode::No)?;
+    let unstaged = report.has_unstaged() || !report.unmerged.is_empty();
+    let staged = report.has_staged();
+    if unstaged {
+        eprintln!("error: cannot rebase: You have unstaged changes.");
+    }
+    if staged {
+        match unstaged {
+            true => eprintln!("error: additionally, your index contains uncommitted changes."),
+            false => eprintln!("error: cannot rebase: Your index contains uncommitted changes."),
+        }
+    }
+    Ok(unstaged || staged)
+}
+
+/// Runs the shell command of an `exec`, stopping when it fails or leaves changes behind
+fn exec(command: &str) -> anyhow::Result<Option<Outcome>> {
+    clear_line();
+    eprintln!("Executing: {command}");
+    let status = process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .status()
+        .with_context(|| format!("Running {command}"))?;
+    let dirty = report_local_changes()?;
+    if !status.success() {
+        let changes = match dirty {
+            true => "and made changes to the index and/or the working tree\n",
+            false => "",
+        };
+        eprint!(
+            "warning: execution failed: {command}\n{changes}You can fix the problem, and then run\n\n  git rebase --continue\n\n\n"
+        );
+        return Ok(Some(Outcome::Failed));
+    }
+    if dirty {
+        eprint!(
+            "warning: execution succeeded: {command}\nbut left changes to the index and/or the working tree\nCommit or stash your changes, and then run\n\n  git rebase --continue\n\n\n"
+        );
+        return Ok(Some(Outcome::Failed));
+    }
+    Ok(None)
+}
+
+/// Resolves the label of a `reset` or `merge`: a `label` made earlier, or any commit
+fn resolve_label(label: &str) -> Option<[u8; 20]> {
+    if let Ok(Some(hash)) = refs::read_ref(&format!("refs/rewritten/{label}")) {
+        return Some(hash);
+    }
+    revision::resolve(label)
+        .and_then(|hash| revision::peel_to_commit(&hash))
+        .ok()
+}
+
+fn label(item: &Item) -> anyhow::Result<Option<Outcome>> {
+    if refs::check_branch_name(&item.arg).is_err() {
+        eprintln!(
+            "error: refusing to update ref with bad name 'refs/rewritten/{}'",
+            item.arg
+        );
+        return reschedule(item).map(Some);
+    }
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let name = format!("refs/rewritten/{}", item.arg);
+    refs::update_ref(&name, &head, &format!("rebase (label) '{}'", item.arg))?;
+    let mut labels = read_state("refs-to-delete").unwrap_or_default();
+    labels.push_str(&format!("{name}\n"));
+    write_state("refs-to-delete", &labels)?;
+    Ok(None)
+}
+
+/// Moves HEAD, the index and the working tree to a label, for the next line of history to grow
+/// from there
+fn reset(item: &Item) -> anyhow::Result<Option<Outcome>> {
+    let (name, _) = split_word(&item.arg);
+    let target = match name {
+        "[new" => None,
+        name => match resolve_label(name) {
+            Some(target) => Some(target),
+            None => {
+                eprintln!("error: could not resolve '{name}'");
+                return reschedule(item).map(Some);
+            }
+        },
+    };
+    if has_local_changes()? {
+        eprintln!("error: your local changes would be overwritten by rebase.");
+        eprintln!("hint: commit your changes or stash them to proceed.");
+        return reschedule(item).map(Some);
+    }
+    let Some(target) = target else {
+        eprintln!("error: resetting to a new root is not supported");
+        return reschedule(item).map(Some);
+    };
+    let tree = Commit::read(&target)?.tree;
+    let mut index = Index::read()?;
+    let opts = UnpackOptions {
+        update: true,
+        reset: true,
+        ..UnpackOptions::default()
+    };
+    unpack::one_way(&mut index, Some(&tree), opts)?;
+    index.write()?;
+    refs::update_ref("HEAD", &target, &format!("rebase (reset): '{name}'"))?;
+    Ok(None)
+}
+
+/// Recreates a merge of a label into HEAD, reusing the original merge when its parents haven't
+/// changed
+fn merge_item(item: &Item) -> anyhow::Result<Option<Outcome>> {
+    let (labels, oneline) = match item.arg.split_once('#') {
+        Some((labels, oneline)) => (labels, Some(oneline.trim())),
+        None => (item.arg.as_str(), None),
+    };
+    let labels: Vec<&str> = labels.split_whitespace().collect();
+    let [name] = labels.as_slice() else {
+        eprintln!("error: octopus merges are not supported");
+        return reschedule(item).map(Some);
+    };
+    let Some(to_merge) = resolve_label(name) else {
+        eprintln!("error: could not resolve '{name}'");
+        eprintln!("error: unable to parse '{name}'");
+        return reschedule(item).map(Some);
+    };
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let head_commit = Commit::read(&head)?;
+    let mut index = Index::read()?;
+    if index_tree(&index)? != Some(head_commit.tree) {
+        eprintln!("error: your local changes would be overwritten by rebase.");
+        eprintln!("hint: commit your changes or stash them to proceed.");
+        return reschedule(item).map(Some);
+    }
+    let original = match item.commit {
+        Some(hash) => Some((hash, Commit::read(&hash)?)),
+        None => None,
+    };
+
+    if let Some((hash, commit)) = &original {
+        if item.flag != Some('c') && commit.parents == [head, to_merge] {
+            if let Err(err) = checkout::switch_trees(
+                &mut index,
+                Some(&head_commit.tree),
+                Some(&commit.tree),
+                false,
+            ) {
+                eprintln!("error: {err:#}");
+                return reschedule(item).map(Some);
+            }
+            index.write()?;
+            refs::update_ref("HEAD", hash, "rebase: fast-forward")?;
+            return Ok(None);
+        }
+    }
+    let bases = merge_base::merge_bases(&head, &to_merge)?;
+    if bases.first() == Some(&to_merge) {
+        // Already merged
+        return Ok(None);
+    }
+
+    let (message, author) = match &original {
+        Some((_, commit)) => (commit.message.clone(), commit.author.clone()),
+        None => {
+            let message = match oneline {
+                Some(oneline) => format!("{oneline}\n"),
+                None => format!("Merge branch '{name}'\n"),
+            };
+            (message, Signature::current("AUTHOR")?)
+        }
+    };
+    let theirs = format!("refs/rewritten/{name}");
+    let opts = MergeOptions {
+        labels: Labels {
+            ours: "HEAD",
+            base: "",
+            theirs: &theirs,
+        },
+        content: FileOptions {
+            style: ConflictStyle::configured()?,
+            ..FileOptions::default()
+        },
+        subtree_shift: None,
+    };
+    let result = merge::merge_commits(&head, &to_merge, &bases, opts)?;
+    if let Err(err) = merge::checkout(&mut index, Some(&head_commit.tree), &result) {
+        eprintln!("error: {err:#}");
+        return reschedule(item).map(Some);
+    }
+    index.write()?;
+    fs::write(".git/AUTO_MERGE", format!("{}\n", hex::encode(result.tree)))
+        .context("Writing AUTO_MERGE")?;
+    let mut out = std::io::stdout().lock();
+    for line in &result.messages {
+        writeln!(out, "{line}")?;
+    }
+    drop(out);
+
+    if !result.is_clean() {
+        fs::write(".git/MERGE_HEAD", format!("{}\n", hex::encode(to_merge)))
+            .context("Writing MERGE_HEAD")?;
+        fs::write(".git/MERGE_MSG", &message).context("Writing MERGE_MSG")?;
+        write_state("message", &format!("{message}\n"))?;
+        write_state("patch", "")?;
+        write_author_script(&author)?;
+        match &original {
+            Some((hash, _)) => {
+                write_state("stopped-sha", &format!("{}\n", hex::encode(hash)))?;
+                fs::write(".git/REBASE_HEAD", format!("{}\n", hex::encode(hash)))
+                    .context("Writing REBASE_HEAD")?;
+                eprintln!("Could not apply {}... {}", abbrev(hash), item.arg);
+            }
+            None => eprintln!("Could not merge {}", item.arg),
+        }
+        return Ok(Some(Outcome::Failed));
+    }
+    let message = match item.flag {
+        Some('c') => edit_message(&message, &head_commit.tree, &author, false)?,
+        _ => message,
+    };
+    let new = write_commit_by(&result.tree, &[head, to_merge], &author, &message)?;
+    refs::update_ref(
+        "HEAD",
+        &new,
+        &format!("rebase (pick): {}", first_line(&message)),
+    )?;
+    worktree::remove_if_exists(".git/AUTO_MERGE")?;
+    if item.flag != Some('C') {
+        sequencer::print_summary(&new, &head_commit.tree, true)?;
+    }
+    Ok(None)
+}
+
+/// Moves the rebased branch to where HEAD ended up and checks it out again, along with the refs
+/// of `update-ref` commands
+fn finish() -> anyhow::Result<Outcome> {
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let head_name = read_state("head-name").unwrap_or_default();
+    let head_name = head_name.trim();
+    let onto = read_state("onto").unwrap_or_default();
+    if head_name.starts_with("refs/") {
+        refs::update_ref(
+            head_name,
+            &head,
+            &format!("rebase (finish): {head_name} onto {}", onto.trim()),
+        )?;
+        refs::set_head_branch(
+            head_name,
+            &format!("rebase (finish): returning to {head_name}"),
+        )?;
+    }
+    let mut updated = Vec::new();
+    for update in read_update_refs()? {
+        if update.new != NULL_ID {
+            refs::update_ref(&update.name, &update.new, "rewritten during rebase")?;
+            updated.push(update.name);
+        }
+    }
+    remove_rebase_state()?;
+    clear_line();
+    eprintln!("Successfully rebased and updated {head_name}.");
+    if !updated.is_empty() {
+        eprintln!("Updated the following refs with --update-refs:");
+        for name in updated {
+            eprintln!("\t{name}");
+        }
+    }
+    Ok(Outcome::Done)
+}
+
+/// Removes the rebase state along with the labels it made
+fn remove_rebase_state() -> anyhow::Result<()> {
+    for name in read_state("refs-to-delete").unwrap_or_default().lines() {
+        worktree::remove_if_exists(&format!(".git/{name}"))?;
+        worktree::remove_if_exists(&format!(".git/logs/{name}"))?;
+    }
+    for dir in [".git/refs/rewritten", ".git/logs/refs/rewritten"] {
+        if fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_none()) {
+            fs::remove_dir(dir).with_context(|| format!("Removing {dir}"))?;
+        }
+    }
+    fs::remove_dir_all(STATE).context("Removing the rebase state")
+}
+
+fn ensure_in_progress() -> anyhow::Result<()> {
+    anyhow::ensure!(in_progress(), "No rebase in progress?");
+    Ok(())
+}
+
+/// `--continue`: commits what the user resolved or staged for the command the rebase stopped
+/// at, then goes on with the todo list
+pub(crate) fn resume() -> anyhow::Result<Outcome> {
+    ensure_in_progress()?;
+    let todo = read_state("git-rebase-todo").context("Reading the rebase todo list")?;
+    if parse_todo(&todo).is_none() {
+        eprintln!("error: please fix this using 'git rebase --edit-todo'.");
+        return Ok(Outcome::Failed);
+    }
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let head_commit = Commit::read(&head)?;
+    let mut index = Index::read()?;
+    let report = crate::status::compute(&mut index, Some(&head_commit.tree), UntrackedMode::No)?;
+    if !report.unmerged.is_empty() || report.has_unstaged() {
+        for unmerged in &report.unmerged {
+            println!("{}: needs merge", unmerged.path);
+        }
+        println!("You must edit all merge conflicts and then\nmark them as resolved using git add");
+        return Ok(Outcome::Failed);
+    }
+    if !commit_staged(&head, &head_commit)? {
+        return Ok(Outcome::Failed);
+    }
+    if let Some(stopped) = read_state("stopped-sha") {
+        record_rewritten(&parse_hash(stopped.trim())?)?;
+    }
+    recount()?;
+    run()
+}
+
+/// The last command the rebase carried out
+fn last_done() -> Option<Item> {
+    let done = read_state("done")?;
+    done.lines()
+        .rev()
+        .filter_map(Item::parse)
+        .find(|item| item.command.is_some())
+}
+
+/// Commits the changes staged where the rebase stopped: amending HEAD after an `edit`, melding
+/// them in for a `fixup` or `squash`, and otherwise committing them as the commit that stopped.
+/// Returns false when they can't be committed.
+fn commit_staged(head: &[u8; 20], head_commit: &Commit) -> anyhow::Result<bool> {
+    let index = Index::read()?;
+    let tree = index_tree(&index)?.context("Your index file is unmerged.")?;
+    let staged = tree != head_commit.tree;
+    let merge_head = match fs::read_to_string(".git/MERGE_HEAD") {
+        Ok(content) => Some(parse_hash(content.trim())?),
+        Err(_) => None,
+    };
+    let last = last_done();
+    let last_command = last.as_ref().and_then(|item| item.command);
+
+    if last_command.is_some_and(Command::is_fixup) && state_path("message-squash").exists() {
+        // The melded commit is amended by hand, and any fixups after it start a chain of their own
+        let message = read_state("message").unwrap_or_default();
+        let base = parent_tree(head_commit)?;
+        let message = edit_message(&message, &base, &head_commit.author, true)?;
+        let new = write_commit_by(&tree, &head_commit.parents, &head_commit.author, &message)?;
+        refs::update_ref(
+            "HEAD",
+            &new,
+            &format!("rebase (continue): {}", first_line(&message)),
+        )?;
+        refs::clear_merge_state()?;
+        for name in CHAIN_FILES {
+            remove_state(name)?;
+        }
+        sequencer::print_summary(&new, &base, true)?;
+        return Ok(true);
+    }
+    if let Some(amend) = read_state("amend") {
+        if !staged {
+            return Ok(true);
+        }
+        if parse_hash(amend.trim())? != *head {
+            eprintln!("error: \nYou have uncommitted changes in your working tree. Please, commit them\nfirst and then run 'git rebase --continue' again.");
+            return Ok(false);
+        }
+        let message = read_state("message").unwrap_or_else(|| head_commit.message.clone());
+        let message = pretty::cleanup_message(&message);
+        let new = write_commit_by(&tree, &head_commit.parents, &head_commit.author, &message)?;
+        refs::update_ref(
+            "HEAD",
+            &new,
+            &format!("rebase (continue): {}", first_line(&message)),
+        )?;
+        sequencer::print_summary(&new, &parent_tree(head_commit)?, true)?;
+        return Ok(true);
+    }
+    if !staged && merge_head.is_none() {
+        // The commit turned out empty, or the user resolved its conflicts to nothing
+        refs::clear_merge_state()?;
+        return Ok(true);
+    }
+
+    let message = fs::read_to_string(".git/MERGE_MSG")
+        .ok()
+        .or_else(|| read_state("message"))
+        .unwrap_or_default();
+    let Some(author) = read_author_script()? else {
+        eprintln!(
+            "error: could not open '{STATE}/author-script' for reading: No such file or directory"
+        );
+        eprint!("{STAGED_CHANGES}");
+        eprintln!("error: could not commit staged changes.");
+        return Ok(false);
+    };
+    let reword = matches!(last_command, Some(Command::Reword))
+        || (last_command == Some(Command::Merge)
+            && last.as_ref().and_then(|item| item.flag) == Some('c'));
+    let message = match reword {
+        true => edit_message(&message, &head_commit.tree, &author, false)?,
+        false => pretty::cleanup_message(&message),
+    };
+    anyhow::ensure!(
+        !message.is_empty(),
+        "Aborting commit due to empty commit message."
+    );
+    let mut parents = vec![*head];
+    parents.extend(merge_head);
+    let new = write_commit_by(&tree, &parents, &author, &message)?;
+    refs::update_ref(
+        "HEAD",
+        &new,
+        &format!("rebase (continue): {}", first_line(&message)),
+    )?;
+    refs::clear_merge_state()?;
+    sequencer::print_summary(&new, &head_commit.tree, false)?;
+    Ok(true)
+}
+
+/// `--skip`: throws away the changes of the command the rebase stopped at and goes on
+pub(crate) fn skip() -> anyhow::Result<Outcome> {
+    ensure_in_progress()?;
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    hard_reset(&head)?;
+    refs::clear_merge_state()?;
+    for name in STOP_FILES {
+        remove_state(name)?;
+    }
+    recount()?;
+    run()
+}
+
+/// Resets the index and working tree to a commit, discarding local changes
+fn hard_reset(commit: &[u8; 20]) -> anyhow::Result<()> {
+    let tree = Commit::read(commit)?.tree;
+    let mut index = Index::read()?;
+    let opts = UnpackOptions {
+        update: true,
+        reset: true,
+        ..UnpackOptions::default()
+    };
+    unpack::one_way(&mut index, Some(&tree), opts)?;
+    index.write()
+}
+
+/// `--abort`: goes back to the branch and commit the rebase started from
+pub(crate) fn abort() -> anyhow::Result<()> {
+    ensure_in_progress()?;
+    let orig_head = read_state("orig-head").context("Reading the original HEAD")?;
+    let orig_head = parse_hash(orig_head.trim())?;
+    hard_reset(&orig_head)?;
+    refs::clear_merge_state()?;
+    let head_name = read_state("head-name").unwrap_or_default();
+    let head_name = head_name.trim();
+    if head_name.starts_with("refs/") {
+        refs::set_head_branch(
+            head_name,
+            &format!("rebase (abort): returning to {head_name}"),
+        )?;
+    } else {
+        refs::detach_head(
+            &orig_head,
+            &format!("rebase (abort): returning to {}", hex::encode(orig_head)),
+        )?;
+    }
+    worktree::remove_if_exists(".git/REBASE_HEAD")?;
+    remove_rebase_state()
+}
+
+/// `--quit`: forgets the rebase, leaving HEAD, the index and the working tree as they are
+pub(crate) fn quit() -> anyhow::Result<()> {
+    ensure_in_progress()?;
+    remove_rebase_state()
+}
+
+/// `--edit-todo`: lets the user edit what is left of the todo list
+pub(crate) fn edit_todo() -> anyhow::Result<Outcome> {
+    ensure_in_progress()?;
+    let todo = read_state("git-rebase-todo").context("Reading the rebase todo list")?;
+    parse_todo(&todo);
+    let todo: String = todo
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .map(|line| match Item::parse(line) {
+            Some(item) => format!("{}\n", item.format(true)),
+            None => format!("{line}\n"),
+        })
+        .collect();
+    let help = todo_help(None);
+    let Some(todo) = edit_todo_list(&todo, &help)? else {
+        eprintln!("{EDIT_TODO_ADVICE}");
+        return Ok(Outcome::Failed);
+    };
+    write_state(
+        "git-rebase-todo",
+        &format!("{}{help}", format_items(&todo, false)),
+    )?;
+    Ok(Outcome::Done)
+}
+
+/// What a rebase in progress has done and has left to do, for `status`
+pub(crate) struct Progress {
+    /// The branch being rebased, `None` for a detached HEAD
+    pub(crate) branch: Option<String>,
+    /// The abbreviated commit the branch is rebased onto
+    pub(crate) onto: String,
+    /// The commands done and left to do, with commits abbreviated
+    pub(crate) done: Vec<String>,
+    pub(crate) todo: Vec<String>,
+}
+
+/// The rebase in progress, `None` when there is none
+pub(crate) fn progress() -> anyhow::Result<Option<Progress>> {
+    if !in_progress() {
+        return Ok(None);
+    }
+    let head_name = read_state("head-name").unwrap_or_default();
+    let branch = head_name
+        .trim()
+        .strip_prefix("refs/heads/")
+        .map(str::to_string);
+    let onto = read_state("onto").unwrap_or_default();
+    let onto = onto.trim().get(..7).unwrap_or_default().to_string();
+    let lines = |name: &str| -> Vec<String> {
+        read_state(name)
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(abbreviate_line)
+            .collect()
+    };
+    Ok(Some(Progress {
+        branch,
+        onto,
+        done: lines("done"),
+        todo: lines("git-rebase-todo"),
+    }))
+}
+
+/// Abbreviates the object named second on a todo line, as `status` shows them
+fn abbreviate_line(line: &str) -> String {
+    let (word, rest) = split_word(line);
+    if matches!(word, "exec" | "x" | "label" | "l") {
+        return line.to_string();
+    }
+    let (name, rest) = split_word(rest);
+    match revision::resolve(name) {
+        Ok(hash) if !name.is_empty() => format!("{word} {} {rest}", abbrev(&hash)),
+        _ => line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, commit, tree};
+
+    /// `main` is `base` followed by commits with the given subjects, each changing `f`
+    fn history(subjects: &[&str]) -> ([u8; 20], Vec<[u8; 20]>) {
+        let base = commit(&tree(&[("f", "base\n")]), &[], "base");
+        let mut commits = Vec::new();
+        let mut parent = base;
+        for subject in subjects {
+            parent = commit(&tree(&[("f", &format!("{subject}\n"))]), &[parent], subject);
+            commits.push(parent);
+        }
+        (base, commits)
+    }
+
+    fn set_branch(name: &str, commit: &[u8; 20]) -> anyhow::Result<()> {
+        fs::write(
+            format!(".git/refs/heads/{name}"),
+            format!("{}\n", hex::encode(commit)),
+        )?;
+        Ok(())
+    }
+
+    fn options(base: &[u8; 20], head: &[u8; 20]) -> Options {
+        Options {
+            upstream: *base,
+            onto: *base,
+            onto_name: hex::encode(base),
+            head_name: Some(String::from("refs/heads/main")),
+            orig_head: *head,
+            interactive: true,
+            autosquash: false,
+            rebase_merges: false,
+            update_refs: false,
+        }
+    }
+
+    /// The todo list as lines, with commits named by their index in `commits`
+    fn lines(todo: &[Item], commits: &[[u8; 20]]) -> Vec<String> {
+        let mut text = format_items(todo, false);
+        for (i, commit) in commits.iter().enumerate() {
+            text = text.replace(&hex::encode(commit), &i.to_string());
+        }
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn autosquash_moves_fixups_after_their_targets() {
+        testing::in_repo(|| {
+            let (base, commits) = history(&["a", "b", "fixup! a", "squash! b", "amend! a"]);
+            let opts = Options {
+                autosquash: true,
+                ..options(&base, &commits[4])
+            };
+            assert_eq!(
+                lines(&make_todo(&opts)?, &commits),
+                [
+                    "pick 0 a",
+                    "fixup 2 fixup! a",
+                    "fixup -C 4 amend! a",
+                    "pick 1 b",
+                    "squash 3 squash! b",
+                ]
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn update_refs_follow_the_commits_of_other_branches() {
+        testing::in_repo(|| {
+            let (base, commits) = history(&["a", "b", "c"]);
+            set_branch("main", &commits[2])?;
+            set_branch("side", &commits[0])?;
+            set_branch("topic", &commits[1])?;
+            let opts = Options {
+                update_refs: true,
+                ..options(&base, &commits[2])
+            };
+            assert_eq!(
+                lines(&make_todo(&opts)?, &commits),
+                [
+                    "pick 0 a",
+                    "update-ref refs/heads/side",
+                    "",
+                    "pick 1 b",
+                    "update-ref refs/heads/topic",
+                    "",
+                    "pick 2 c",
+                ]
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn update_refs_come_after_the_fixups_of_their_commit() {
+        testing::in_repo(|| {
+            let (base, commits) = history(&["a", "b", "fixup! a"]);
+            set_branch("main", &commits[2])?;
+            set_branch("side", &commits[0])?;
+            let opts = Options {
+                autosquash: true,
+                update_refs: true,
+                ..options(&base, &commits[2])
+            };
+            assert_eq!(
+                lines(&make_todo(&opts)?, &commits),
+                [
+                    "pick 0 a",
+                    "fixup 2 fixup! a",
+                    "update-ref refs/heads/side",
+                    "",
+                    "pick 1 b",
+                ]
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn an_empty_list_gets_a_noop() {
+        testing::in_repo(|| {
+            let (base, _) = history(&[]);
+            assert_eq!(lines(&make_todo(&options(&base, &base))?, &[]), ["noop"]);
+            Ok(())
+        });
+    }
+}
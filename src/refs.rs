@@ -0,0 +1,436 @@
+use std::{collections::BTreeMap, fs, io::Write, path::Path};
+
+use anyhow::Context;
+
+use crate::object::commit::{parse_hash, Signature};
+
+/// What `.git/HEAD` points at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Head {
+    /// `ref: refs/heads/<branch>`, the branch may not exist yet
+    Symbolic(String),
+    Detached([u8; 20]),
+}
+
+pub(crate) fn read_head() -> anyhow::Result<Head> {
+    let content = fs::read_to_string(".git/HEAD").context("Reading HEAD")?;
+    let content = content.trim();
+    match content.strip_prefix("ref: ") {
+        Some(target) => Ok(Head::Symbolic(target.trim().to_string())),
+        None => Ok(Head::Detached(parse_hash(content)?)),
+    }
+}
+
+/// Resolves HEAD to a commit, `None` on an unborn branch
+pub(crate) fn head_commit() -> anyhow::Result<Option<[u8; 20]>> {
+    match read_head()? {
+        Head::Detached(hash) => Ok(Some(hash)),
+        Head::Symbolic(target) => read_ref(&target),
+    }
+}
+
+/// The upstream of a local branch from `branch.<name>.remote` and `branch.<name>.merge`,
+/// as a full ref name such as `refs/remotes/origin/main`
+pub(crate) fn upstream_of(branch: &str) -> Option<String> {
+    let config = crate::config::read_repo_config();
+    let remote = config.get(&format!("branch.{branch}.remote"))?;
+    let merge = config.get(&format!("branch.{branch}.merge"))?;
+    if remote == "." {
+        return Some(merge.to_string());
+    }
+    // Map the remote branch through the remote's fetch refspec, e.g.
+    // `+refs/heads/*:refs/remotes/origin/*`
+    let fetch = config.get(&format!("remote.{remote}.fetch"))?;
+    let (src, dst) = fetch.trim_start_matches('+').split_once(':')?;
+    match (src.strip_suffix('*'), dst.strip_suffix('*')) {
+        (Some(src), Some(dst)) => Some(format!("{dst}{}", merge.strip_prefix(src)?)),
+        _ if src == merge => Some(dst.to_string()),
+        _ => None,
+    }
+}
+
+/// Strips `refs/heads/`, `refs/tags/` or `refs/remotes/` for display
+pub(crate) fn shorten(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// The shortest name that `expand_ref` turns back into `name`, or `name` itself if every shorter
+/// one is taken by another ref
+pub(crate) fn shorten_unambiguous(name: &str) -> anyhow::Result<String> {
+    const RULES: [(&str, &str); 6] = [
+        ("", ""),
+        ("refs/", ""),
+        ("refs/tags/", ""),
+        ("refs/heads/", ""),
+        ("refs/remotes/", ""),
+        ("refs/remotes/", "/HEAD"),
+    ];
+    for (i, (prefix, suffix)) in RULES.iter().enumerate().skip(1).rev() {
+        let Some(short) = name
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_suffix(suffix))
+            .filter(|short| !short.is_empty())
+        else {
+            continue;
+        };
+        let mut ambiguous = false;
+        for (prefix, suffix) in &RULES[..i] {
+            if read_ref(&format!("{prefix}{short}{suffix}"))?.is_some() {
+                ambiguous = true;
+                break;
+            }
+        }
+        if !ambiguous {
+            return Ok(short.to_string());
+        }
+    }
+    Ok(name.to_string())
+}
+
+/// Reads a fully qualified ref (`HEAD`, `refs/heads/main`, ...), following symbolic refs
+pub(crate) fn read_ref(name: &str) -> anyhow::Result<Option<[u8; 20]>> {
+    let mut name = name.to_string();
+    for _ in 0..5 {
+        let path = Path::new(".git").join(&name);
+        if path.is_file() {
+            let content =
+                fs::read_to_string(&path).with_context(|| format!("Reading ref {name}"))?;
+            let content = content.trim();
+            match content.strip_prefix("ref: ") {
+                Some(target) => {
+                    name = target.trim().to_string();
+                    continue;
+                }
+                None => return Ok(Some(parse_hash(content)?)),
+            }
+        }
+        return Ok(packed_refs()?.get(&name).copied());
+    }
+    anyhow::bail!("Symbolic ref loop at {name}")
+}
+
+/// Expands a short ref name using git's lookup rules, returning the full name
+pub(crate) fn expand_ref(name: &str) -> anyhow::Result<Option<String>> {
+    if name == "@" {
+        return Ok(Some(String::from("HEAD")));
+    }
+    let candidates = [
+        name.to_string(),
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ];
+    for candidate in candidates {
+        if candidate != "HEAD" && !candidate.starts_with("refs/") && !is_pseudo_ref(&candidate) {
+            continue;
+        }
+        if read_ref(&candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+fn is_pseudo_ref(name: &str) -> bool {
+    name.ends_with("HEAD") && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_')
+}
+
+/// Resolves a short or full ref name to an object id
+pub(crate) fn resolve(name: &str) -> anyhow::Result<Option<[u8; 20]>> {
+    match expand_ref(name)? {
+        Some(full) => read_ref(&full),
+        None => Ok(None),
+    }
+}
+
+/// Returns the entries of `.git/packed-refs`, keyed by ref name
+pub(crate) fn packed_refs() -> anyhow::Result<BTreeMap<String, [u8; 20]>> {
+    let mut refs = BTreeMap::new();
+    let Ok(content) = fs::read_to_string(".git/packed-refs") else {
+        return Ok(refs);
+    };
+    for line in content.lines() {
+        if line.starts_with('#') || line.starts_with('^') {
+            continue;
+        }
+        if let Some((hash, name)) = line.split_once(' ') {
+            refs.insert(name.to_string(), parse_hash(hash)?);
+        }
+    }
+    Ok(refs)
+}
+
+/// Every ref under `prefix` (such as `refs/heads/`), loose refs taking precedence over packed
+pub(crate) fn list(prefix: &str) -> anyhow::Result<BTreeMap<String, [u8; 20]>> {
+    let mut refs: BTreeMap<String, [u8; 20]> = packed_refs()?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(prefix))
+        .collect();
+    let mut stack = vec![Path::new(".git/refs").to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            let Some(name) = path
+                .strip_prefix(".git")
+                .ok()
+                .and_then(|name| name.to_str())
+            else {
+                continue;
+            };
+            if !name.starts_with(prefix) || name.ends_with(".lock") {
+                continue;
+            }
+            if let Some(hash) = read_ref(name)? {
+                refs.insert(name.to_string(), hash);
+            }
+        }
+    }
+    Ok(refs)
+}
+
+/// Checks a branch name against git's ref name rules
+pub(crate) fn check_branch_name(name: &str) -> anyhow::Result<()> {
+    let valid = !name.is_empty()
+        && name != "HEAD"
+        && name != "@"
+        && !name.starts_with('-')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.contains("//")
+        && !name
+            .bytes()
+            .any(|b| b < 0x20 || b == 0x7f || b" ~^:?*[\\".contains(&b))
+        && name
+            .split('/')
+            .all(|component| !component.starts_with('.') && !component.ends_with(".lock"));
+    anyhow::ensure!(valid, "'{name}' is not a valid branch name");
+    Ok(())
+}
+
+/// Refs whose updates are recorded in a reflog, as with `core.logAllRefUpdates=true`, along with
+/// `refs/stash`, whose reflog is the stack of stash entries
+fn logs_updates(name: &str) -> bool {
+    name == "HEAD"
+        || name == "refs/stash"
+        || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        || Path::new(".git/logs").join(name).exists()
+}
+
+/// Appends `<old> <new> <identity>\t<message>` to the reflog of `name`
+fn append_reflog(
+    name: &str,
+    old: Option<[u8; 20]>,
+    new: &[u8; 20],
+    message: &str,
+) -> anyhow::Result<()> {
+    if !logs_updates(name) {
+        return Ok(());
+    }
+    let path = Path::new(".git/logs").join(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Creating {}", parent.display()))?;
+    }
+    let identity = Signature::current("COMMITTER")?;
+    let message = message.lines().next().unwrap_or("");
+    let line = format!(
+        "{} {} {identity}\t{message}\n",
+        hex::encode(old.unwrap_or_default()),
+        hex::encode(new)
+    );
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Opening reflog {}", path.display()))?;
+    log.write_all(line.as_bytes())
+        .with_context(|| format!("Writing reflog {}", path.display()))
+}
+
+/// Replaces a ref file through `<name>.lock`
+fn write_ref_file(name: &str, content: &str) -> anyhow::Result<()> {
+    let path = Path::new(".git").join(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Creating {}", parent.display()))?;
+    }
+    let lock = path.with_file_name(format!(
+        "{}.lock",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let mut file = fs::File::create_new(&lock)
+        .with_context(|| format!("Unable to lock {name}, is another process running?"))?;
+    let written = file.write_all(content.as_bytes());
+    if let Err(err) = written {
+        let _ = fs::remove_file(&lock);
+        return Err(err).with_context(|| format!("Writing {name}"));
+    }
+    fs::rename(&lock, &path).with_context(|| format!("Updating {name}"))
+}
+
+/// Points a ref at `new`, logging the change. `HEAD` is followed to its branch unless detached,
+/// and updates of the checked-out branch are logged in HEAD's reflog as well.
+pub(crate) fn update_ref(name: &str, new: &[u8; 20], message: &str) -> anyhow::Result<()> {
+    let head = read_head()?;
+    let via_head = name == "HEAD";
+    let name = match (&head, name) {
+        (Head::Symbolic(target), "HEAD") => target.as_str(),
+        _ => name,
+    };
+    let old = read_ref(name)?;
+    if old.as_ref() == Some(new) {
+        // Updates made through a symbolic HEAD are recorded in its reflog even when nothing
+        // moves
+        if via_head && name != "HEAD" {
+            append_reflog("HEAD", old, new, message)?;
+        }
+        return Ok(());
+    }
+    write_ref_file(name, &format!("{}\n", hex::encode(new)))?;
+    append_reflog(name, old, new, message)?;
+    if name != "HEAD" && head == Head::Symbolic(name.to_string()) {
+        append_reflog("HEAD", old, new, message)?;
+    }
+    Ok(())
+}
+
+/// A line of a reflog
+#[derive(Debug, Clone)]
+pub(crate) struct ReflogEntry {
+    pub(crate) old: [u8; 20],
+    pub(crate) new: [u8; 20],
+    /// Who made the change and when, as in commit headers
+    pub(crate) identity: String,
+    pub(crate) message: String,
+}
+
+/// The entries of a ref's reflog, oldest first. Refs without a reflog have no entries.
+pub(crate) fn read_reflog(name: &str) -> anyhow::Result<Vec<ReflogEntry>> {
+    let Ok(log) = fs::read_to_string(Path::new(".git/logs").join(name)) else {
+        return Ok(Vec::new());
+    };
+    let mut entries = Vec::new();
+    for line in log.lines() {
+        let (line, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut fields = line.splitn(3, ' ');
+        let (Some(old), Some(new)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        entries.push(ReflogEntry {
+            old: parse_hash(old)?,
+            new: parse_hash(new)?,
+            identity: fields.next().unwrap_or_default().to_string(),
+            message: message.to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Removes the `n`th newest entry of a ref's reflog, as `reflog delete --rewrite --updateref`
+/// does: the entry after it now starts where it ended, and the ref is moved to the newest
+/// entry left, or deleted with its reflog when none is.
+pub(crate) fn drop_reflog_entry(name: &str, n: usize) -> anyhow::Result<()> {
+    let mut entries = read_reflog(name)?;
+    let index = entries
+        .len()
+        .checked_sub(n + 1)
+        .with_context(|| format!("log for '{name}' only has {} entries", entries.len()))?;
+    let removed = entries.remove(index);
+    if let Some(next) = entries.get_mut(index) {
+        next.old = removed.old;
+    }
+    let Some(newest) = entries.last() else {
+        let path = Path::new(".git").join(name);
+        fs::remove_file(&path).with_context(|| format!("Deleting {name}"))?;
+        let log = Path::new(".git/logs").join(name);
+        return fs::remove_file(&log).with_context(|| format!("Deleting {}", log.display()));
+    };
+    write_ref_file(name, &format!("{}\n", hex::encode(newest.new)))?;
+    let log: String = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "{} {} {}\t{}\n",
+                hex::encode(entry.old),
+                hex::encode(entry.new),
+                entry.identity,
+                entry.message
+            )
+        })
+        .collect();
+    fs::write(Path::new(".git/logs").join(name), log)
+        .with_context(|| format!("Writing reflog of {name}"))
+}
+
+/// Detaches HEAD at `commit`. Nothing is logged when HEAD is already detached there.
+pub(crate) fn detach_head(commit: &[u8; 20], message: &str) -> anyhow::Result<()> {
+    if read_head()? == Head::Detached(*commit) {
+        return Ok(());
+    }
+    let old = head_commit()?;
+    write_ref_file("HEAD", &format!("{}\n", hex::encode(commit)))?;
+    append_reflog("HEAD", old, commit, message)
+}
+
+/// Points HEAD at a branch (a full ref name), which need not exist yet
+pub(crate) fn set_head_branch(target: &str, message: &str) -> anyhow::Result<()> {
+    let old = head_commit()?;
+    write_ref_file("HEAD", &format!("ref: {target}\n"))?;
+    match read_ref(target)? {
+        Some(new) => append_reflog("HEAD", old, &new, message),
+        None => Ok(()),
+    }
+}
+
+/// What was checked out before the `n`th most recent switch recorded in HEAD's reflog, as
+/// `@{-n}` means: a branch name or a full commit id
+pub(crate) fn previous_checkout(n: usize) -> anyhow::Result<Option<String>> {
+    let (Ok(log), Some(skip)) = (fs::read_to_string(".git/logs/HEAD"), n.checked_sub(1)) else {
+        return Ok(None);
+    };
+    Ok(log
+        .lines()
+        .rev()
+        .filter_map(|line| {
+            let (_, message) = line.split_once('\t')?;
+            let moves = message.strip_prefix("checkout: moving from ")?;
+            let (from, _) = moves.split_once(" to ")?;
+            Some(from.to_string())
+        })
+        .nth(skip))
+}
+
+/// Forgets an interrupted merge, cherry-pick or revert by removing `MERGE_HEAD` and its
+/// companions
+pub(crate) fn clear_merge_state() -> anyhow::Result<()> {
+    for name in [
+        "MERGE_HEAD",
+        "MERGE_MSG",
+        "MERGE_MODE",
+        "MERGE_RR",
+        "AUTO_MERGE",
+        "SQUASH_MSG",
+        "CHERRY_PICK_HEAD",
+        "REVERT_HEAD",
+    ] {
+        let path = Path::new(".git").join(name);
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("Removing {name}"))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use anyhow::Context;
+
+use crate::object::{
+    commit::{Commit, Tag},
+    read::{read_object, read_tree_items},
+    ObjectKind,
+};
+
+/// Resolves a revision (`HEAD~2`, `main^{tree}`, `v1.0:src/lib.rs`, abbreviated hashes, ...)
+/// to an object id
+pub(crate) fn resolve(spec: &str) -> anyhow::Result<[u8; 20]> {
+    if let Some((rev, path)) = split_path(spec) {
+        if rev.is_empty() {
+            return resolve_index_path(path);
+        }
+        let tree = peel_to_tree(&resolve(rev)?)?;
+        return lookup_path(&tree, path)
+            .with_context(|| format!("Path '{path}' does not exist in '{rev}'"));
+    }
+
+    let base_end = spec.find(['^', '~']).unwrap_or(spec.len());
+    let (base, mut suffix) = spec.split_at(base_end);
+    let mut hash = resolve_base(base)?;
+    while !suffix.is_empty() {
+        if let Some(rest) = suffix.strip_prefix("^{") {
+            let end = rest
+                .find('}')
+                .with_context(|| format!("Invalid revision: {spec}"))?;
+            hash = match &rest[..end] {
+                "" => peel(&hash, None)?,
+                "commit" => peel_to_commit(&hash)?,
+                "tree" => peel_to_tree(&hash)?,
+                "blob" => peel(&hash, Some(ObjectKind::Blob))?,
+                "tag" => {
+                    anyhow::ensure!(kind_of(&hash)? == ObjectKind::Tag, "{spec} is not a tag");
+                    hash
+                }
+                "object" => hash,
+                other => anyhow::bail!("Unsupported peel target: {other}"),
+            };
+            suffix = &rest[end + 1..];
+        } else if let Some(rest) = suffix.strip_prefix('^') {
+            let (n, rest) = split_number(rest);
+            let n = n.unwrap_or(1);
+            let commit = peel_to_commit(&hash)?;
+            if n == 0 {
+                hash = commit;
+            } else {
+                let parents = Commit::read(&commit)?.parents;
+                hash = *parents
+                    .get(n - 1)
+                    .with_context(|| format!("Revision {spec} has no parent {n}"))?;
+            }
+            suffix = rest;
+        } else if let Some(rest) = suffix.strip_prefix('~') {
+            let (n, rest) = split_number(rest);
+            for _ in 0..n.unwrap_or(1) {
+                let commit = peel_to_commit(&hash)?;
+                hash = *Commit::read(&commit)?
+                    .parents
+                    .first()
+                    .with_context(|| format!("Revision {spec} goes past a root commit"))?;
+            }
+            suffix = rest;
+        } else {
+            anyhow::bail!("Invalid revision: {spec}");
+        }
+    }
+    Ok(hash)
+}
+
+/// Splits `<rev>:<path>`, ignoring colons inside `^{...}`
+fn split_path(spec: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ':' if depth == 0 => return Some((&spec[..i], &spec[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn split_number(s: &str) -> (Option<usize>, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    (s[..end].parse().ok(), &s[end..])
+}
+
+fn resolve_base(base: &str) -> anyhow::Result<[u8; 20]> {
+    if let Some(hash) = resolve_reflog_entry(base)? {
+        return Ok(hash);
+    }
+    let base = if base.is_empty() { "HEAD" } else { base };
+    if base.len() != 40 {
+        if let Some(hash) = crate::refs::resolve(base)? {
+            return Ok(hash);
+        }
+    }
+    let hash =
+        crate::object::resolve_hash(base).with_context(|| format!("Unknown revision: {base}"))?;
+    crate::object::commit::parse_hash(&hash)
+}
+
+/// Resolves `<ref>@{<n>}`, the value the ref had `n` changes ago according to its reflog, where
+/// a missing ref stands for the current branch
+fn resolve_reflog_entry(base: &str) -> anyhow::Result<Option<[u8; 20]>> {
+    let Some((name, n)) = base
+        .strip_suffix('}')
+        .and_then(|rest| rest.rsplit_once("@{"))
+        .and_then(|(name, n)| Some((name, n.parse::<usize>().ok()?)))
+    else {
+        return Ok(None);
+    };
+    let full = match name {
+        "" => match crate::refs::read_head()? {
+            crate::refs::Head::Symbolic(target) => target,
+            crate::refs::Head::Detached(_) => String::from("HEAD"),
+        },
+        name => match crate::refs::expand_ref(name)? {
+            Some(full) => full,
+            None => return Ok(None),
+        },
+    };
+    let log = crate::refs::read_reflog(&full)?;
+    if log.is_empty() {
+        return Ok(None);
+    }
+    let entry = log
+        .len()
+        .checked_sub(n + 1)
+        .with_context(|| format!("log for '{name}' only has {} entries", log.len()))?;
+    Ok(Some(log[entry].new))
+}
+
+fn resolve_index_path(path: &str) -> anyhow::Result<[u8; 20]> {
+    let (stage, path) = match path.split_once(':') {
+        Some((stage, path)) if stage.len() == 1 => {
+            (stage.parse::<u16>().context("Invalid stage")?, path)
+        }
+        _ => (0, path),
+    };
+    let index = crate::index::Index::read()?;
+    index
+        .get(path, stage)
+        .map(|entry| entry.hash)
+        .with_context(|| format!("Path '{path}' is not in the index at stage {stage}"))
+}
+
+/// Finds the object at `path` inside a tree
+pub(crate) fn lookup_path(tree: &[u8; 20], path: &str) -> anyhow::Result<[u8; 20]> {
+    let mut hash = *tree;
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        let item = read_tree_items(&hash)?
+            .into_iter()
+            .find(|item| item.name == component)
+            .with_context(|| format!("No such path: {path}"))?;
+        hash = item.hash;
+    }
+    Ok(hash)
+}
+
+pub(crate) fn kind_of(hash: &[u8; 20]) -> anyhow::Result<ObjectKind> {
+    Ok(read_object(&hex::encode(hash))?.0)
+}
+
+/// Follows tags (and commits, when a tree is wanted) until an object of the wanted kind is found.
+/// With `None`, only tags are peeled.
+pub(crate) fn peel(hash: &[u8; 20], want: Option<ObjectKind>) -> anyhow::Result<[u8; 20]> {
+    let mut hash = *hash;
+    loop {
+        let (kind, content) = read_object(&hex::encode(hash))?;
+        if Some(&kind) == want.as_ref() {
+            return Ok(hash);
+        }
+        match kind {
+            ObjectKind::Tag => hash = Tag::parse(&content)?.object,
+            ObjectKind::Commit if want == Some(ObjectKind::Tree) => {
+                hash = Commit::parse(&content)?.tree
+            }
+            _ if want.is_none() => return Ok(hash),
+            _ => anyhow::bail!("{} is a {kind}, not a {}", hex::encode(hash), want.unwrap()),
+        }
+    }
+}
+
+pub(crate) fn peel_to_commit(hash: &[u8; 20]) -> anyhow::Result<[u8; 20]> {
+    peel(hash, Some(ObjectKind::Commit))
+}
+
+pub(crate) fn peel_to_tree(hash: &[u8; 20]) -> anyhow::Result<[u8; 20]> {
+    peel(hash, Some(ObjectKind::Tree))
+}
@@ -0,0 +1,1199 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+};
+
+use regex::Regex;
+
+use crate::{
+    diff::{
+        patch_id::commit_patch_id,
+        tree::{diff_trees, TreeDiffOptions},
+    },
+    line_log::LineLog,
+    merge_base,
+    object::{
+        commit::{Commit, Tag},
+        read::{read_object, read_object_of_kind, read_tree_items},
+        ObjectKind, MODE_GITLINK, MODE_TREE,
+    },
+    pathspec::Pathspec,
+    refs::{self, Head},
+    revision,
+};
+
+/// Every commit reachable from `start`, including itself
+pub(crate) fn ancestors(start: &[u8; 20]) -> anyhow::Result<HashSet<[u8; 20]>> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([*start]);
+    while let Some(hash) = queue.pop_front() {
+        if !seen.insert(hash) {
+            continue;
+        }
+        queue.extend(Commit::read(&hash)?.parents);
+    }
+    Ok(seen)
+}
+
+/// Marks a tree and everything in it as seen
+fn mark_tree_seen(tree: &[u8; 20], seen: &mut HashSet<[u8; 20]>) -> anyhow::Result<()> {
+    if !seen.insert(*tree) {
+        return Ok(());
+    }
+    for item in read_tree_items(tree)? {
+        match item.mode {
+            MODE_TREE => mark_tree_seen(&item.hash, seen)?,
+            MODE_GITLINK => {}
+            _ => {
+                seen.insert(item.hash);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lists a tree and everything in it not seen yet, each with its path. Submodule commits
+/// aren't objects of this repository, so they're left out.
+fn tree_objects(
+    tree: [u8; 20],
+    path: String,
+    seen: &mut HashSet<[u8; 20]>,
+    objects: &mut Vec<([u8; 20], String)>,
+) -> anyhow::Result<()> {
+    if !seen.insert(tree) {
+        return Ok(());
+    }
+    objects.push((tree, path.clone()));
+    for item in read_tree_items(&tree)? {
+        let item_path = if path.is_empty() {
+            item.name
+        } else {
+            format!("{path}/{}", item.name)
+        };
+        match item.mode {
+            MODE_TREE => tree_objects(item.hash, item_path, seen, objects)?,
+            MODE_GITLINK => {}
+            _ => {
+                if seen.insert(item.hash) {
+                    objects.push((item.hash, item_path));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Number of commits reachable only from `ours` and only from `theirs`
+pub(crate) fn ahead_behind(ours: &[u8; 20], theirs: &[u8; 20]) -> anyhow::Result<(usize, usize)> {
+    let ours = ancestors(ours)?;
+    let theirs = ancestors(theirs)?;
+    Ok((
+        ours.difference(&theirs).count(),
+        theirs.difference(&ours).count(),
+    ))
+}
+
+/// Order in which [`RevWalk`] returns commits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Sort {
+    /// Newest committer date first, as commits are found
+    #[default]
+    Default,
+    /// No parent before all of its children, otherwise by committer date
+    Date,
+    /// No parent before all of its children, otherwise by author date
+    AuthorDate,
+    /// No parent before all of its children, keeping lines of history together
+    Topo,
+}
+
+/// Patterns a commit has to match to be shown
+#[derive(Debug, Default)]
+pub(crate) struct CommitFilter {
+    /// Matched against `Name <email>` of the author, any of them may match
+    pub(crate) authors: Vec<Regex>,
+    /// Matched against `Name <email>` of the committer, any of them may match
+    pub(crate) committers: Vec<Regex>,
+    /// Matched against each line of the message
+    pub(crate) messages: Vec<Regex>,
+    /// Require every message pattern to match instead of any of them
+    pub(crate) all_match: bool,
+}
+
+impl CommitFilter {
+    fn matches(&self, commit: &Commit) -> bool {
+        let identity_matches = |patterns: &[Regex], identity: String| {
+            patterns.is_empty() || patterns.iter().any(|pattern| pattern.is_match(&identity))
+        };
+        let line_matches =
+            |pattern: &Regex| commit.message.lines().any(|line| pattern.is_match(line));
+        let message_matches = self.messages.is_empty()
+            || if self.all_match {
+                self.messages.iter().all(line_matches)
+            } else {
+                self.messages.iter().any(line_matches)
+            };
+        identity_matches(&self.authors, commit.author.identity())
+            && identity_matches(&self.committers, commit.committer.identity())
+            && message_matches
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct WalkOptions {
+    pub(crate) sort: Sort,
+    /// Follow only the first parent of merges
+    pub(crate) first_parent: bool,
+    /// Skip commits with more than one parent
+    pub(crate) no_merges: bool,
+    /// Stop after this many commits have been shown
+    pub(crate) max_count: Option<usize>,
+    /// Skip commits committed before this time, and stop walking past them
+    pub(crate) since: Option<i64>,
+    /// Skip commits committed after this time
+    pub(crate) until: Option<i64>,
+    /// Only show commits changing these paths, simplifying history to the parents they came from
+    pub(crate) paths: Option<Pathspec>,
+    /// Only show commits in this set (and those changing [`WalkOptions::paths`]), as
+    /// `--simplify-by-decoration` does with the commits refs point at
+    pub(crate) decorated: Option<HashSet<[u8; 20]>>,
+    /// Replace parents that aren't shown by their nearest shown ancestors, keeping the history
+    /// connected for `--graph` and `--parents`
+    pub(crate) rewrite_parents: bool,
+    /// Keep merges through the full history, then drop those that end up with a single
+    /// relevant parent once parents are rewritten, as `--simplify-merges` does
+    pub(crate) simplify_merges: bool,
+    /// Leave out this many commits before showing any
+    pub(crate) skip: usize,
+    /// After the shown commits, return the commits the walk stopped at: parents of shown
+    /// commits that aren't shown themselves
+    pub(crate) boundary: bool,
+    /// Leave out commits on either side of `a...b` whose patch matches a commit on the other
+    pub(crate) cherry_pick: bool,
+    /// Only show commits changing these line ranges, following them to where they came from
+    pub(crate) line_log: Option<LineLog>,
+    pub(crate) filter: CommitFilter,
+}
+
+const SEEN: u16 = 1 << 0;
+const ADDED: u16 = 1 << 1;
+const UNINTERESTING: u16 = 1 << 2;
+const TREESAME: u16 = 1 << 3;
+const SHOWN: u16 = 1 << 4;
+/// Reachable from the left side of `a...b`
+const LEFT: u16 = 1 << 5;
+/// A parent of a shown commit, so possibly a boundary commit
+const CHILD_SHOWN: u16 = 1 << 6;
+const BOUNDARY: u16 = 1 << 7;
+/// An excluded tip, which still counts as a relevant parent when simplifying history
+const BOTTOM: u16 = 1 << 8;
+
+/// How many uninteresting commits a limited walk looks at after the last interesting one,
+/// in case clock skew put an interesting commit behind them
+const SLOP: usize = 5;
+
+struct Node {
+    commit: Commit,
+    /// Parents left after history simplification
+    parents: Vec<[u8; 20]>,
+}
+
+/// Walks the commits reachable from some tips but not from others, the way `git log` and
+/// `git rev-list` do. Without excluded tips or a topological order, commits are produced while
+/// walking; otherwise the interesting part of the history is found first.
+pub(crate) struct RevWalk {
+    opts: WalkOptions,
+    nodes: HashMap<[u8; 20], Node>,
+    flags: HashMap<[u8; 20], u16>,
+    /// Commits still to be walked ordered by date, or everything left to show once limited
+    list: VecDeque<[u8; 20]>,
+    limited: bool,
+    /// For merges kept whole while simplifying merges, whether each parent's tree matches in the
+    /// limited paths
+    treesame: HashMap<[u8; 20], Vec<bool>>,
+    /// Uninteresting parents of the interesting commits, found when limiting
+    edges: Vec<[u8; 20]>,
+    /// Parents of shown commits, in the order they were found, to pick boundary commits from
+    boundary_candidates: Vec<[u8; 20]>,
+    /// Whether the shown commits are done and `list` holds the boundary commits
+    returning_boundary: bool,
+}
+
+impl RevWalk {
+    /// Starts a walk from the included revisions, leaving out everything reachable from the
+    /// excluded ones
+    pub(crate) fn new(revisions: &Revisions, opts: WalkOptions) -> anyhow::Result<RevWalk> {
+        let limited = !revisions.exclude.is_empty()
+            || opts.sort != Sort::Default
+            || opts.simplify_merges
+            || opts.cherry_pick;
+        let mut walk = RevWalk {
+            opts,
+            nodes: HashMap::new(),
+            flags: HashMap::new(),
+            list: VecDeque::new(),
+            limited,
+            treesame: HashMap::new(),
+            edges: Vec::new(),
+            boundary_candidates: Vec::new(),
+            returning_boundary: false,
+        };
+        let mut tips = Vec::new();
+        for hash in &revisions.exclude {
+            walk.load(hash)?;
+            *walk.flags.entry(*hash).or_default() |= UNINTERESTING | BOTTOM;
+            walk.mark_parents_uninteresting(hash);
+            tips.push(*hash);
+        }
+        for hash in &revisions.include {
+            walk.load(hash)?;
+            tips.push(*hash);
+        }
+        for hash in &revisions.left {
+            walk.set_flag(hash, LEFT);
+        }
+        for hash in tips {
+            if walk.set_flag(&hash, SEEN) {
+                continue;
+            }
+            walk.list.push_back(hash);
+        }
+        walk.list
+            .make_contiguous()
+            .sort_by_key(|hash| Reverse(walk.nodes[hash].commit.committer.time));
+
+        if walk.limited {
+            walk.limit()?;
+            if walk.opts.sort != Sort::Default {
+                walk.sort_topologically(walk.opts.sort);
+            }
+        }
+        if walk.opts.simplify_merges && walk.prunes() {
+            walk.simplify_merges()?;
+        }
+        for hash in &walk.list {
+            for parent in &walk.nodes[hash].parents {
+                if walk.has_flag(parent, UNINTERESTING) && !walk.edges.contains(parent) {
+                    walk.edges.push(*parent);
+                }
+            }
+        }
+        Ok(walk)
+    }
+
+    /// The next commit to show, then with [`WalkOptions::boundary`] the boundary commits
+    pub(crate) fn next(&mut self) -> anyhow::Result<Option<[u8; 20]>> {
+        if self.returning_boundary {
+            let hash = self.list.pop_front();
+            if let Some(hash) = &hash {
+                self.set_flag(hash, SHOWN);
+            }
+            return Ok(hash);
+        }
+
+        let mut next = None;
+        if self.opts.max_count != Some(0) {
+            next = self.next_shown()?;
+            while next.is_some() && self.opts.skip > 0 {
+                self.opts.skip -= 1;
+                next = self.next_shown()?;
+            }
+            if let Some(count) = &mut self.opts.max_count {
+                *count -= 1;
+            }
+        }
+        let Some(hash) = next else {
+            if !self.opts.boundary {
+                return Ok(None);
+            }
+            self.collect_boundary()?;
+            self.returning_boundary = true;
+            return self.next();
+        };
+        self.set_flag(&hash, SHOWN);
+        if self.opts.boundary {
+            for parent in self.nodes[&hash].parents.clone() {
+                if !self.has_flag(&parent, CHILD_SHOWN | SHOWN) {
+                    self.set_flag(&parent, CHILD_SHOWN);
+                    self.boundary_candidates.push(parent);
+                }
+            }
+        }
+        Ok(Some(hash))
+    }
+
+    /// Moves the parents of shown commits that weren't shown themselves to the list, ordered so
+    /// no parent comes before its children
+    fn collect_boundary(&mut self) -> anyhow::Result<()> {
+        self.list.clear();
+        for hash in std::mem::take(&mut self.boundary_candidates) {
+            if self.has_flag(&hash, SHOWN | BOUNDARY) {
+                continue;
+            }
+            self.set_flag(&hash, BOUNDARY);
+            self.load(&hash)?;
+            self.list.push_front(hash);
+        }
+        let sort = match self.opts.sort {
+            Sort::Default => Sort::Topo,
+            sort => sort,
+        };
+        self.sort_topologically(sort);
+        Ok(())
+    }
+
+    /// The next commit the walk shows, before `--max-count` and `--skip`
+    fn next_shown(&mut self) -> anyhow::Result<Option<[u8; 20]>> {
+        while let Some(hash) = self.list.pop_front() {
+            if !self.limited {
+                if self.older_than_since(&hash) {
+                    continue;
+                }
+                self.add_parents(&hash)?;
+            }
+            if self.shows(&hash) {
+                if self.prunes() && self.opts.rewrite_parents {
+                    self.rewrite_parents(&hash)?;
+                }
+                return Ok(Some(hash));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether a commit returned by [`RevWalk::next`] is reachable from the left side of `a...b`
+    pub(crate) fn is_left(&self, hash: &[u8; 20]) -> bool {
+        self.has_flag(hash, LEFT)
+    }
+
+    /// Whether a commit returned by [`RevWalk::next`] is a boundary commit
+    pub(crate) fn is_boundary(&self, hash: &[u8; 20]) -> bool {
+        self.has_flag(hash, BOUNDARY)
+    }
+
+    /// The uninteresting parents of the commits to show, where the walk stops. They count as
+    /// shown from then on, so they aren't returned as boundary commits as well.
+    pub(crate) fn edges(&mut self) -> Vec<[u8; 20]> {
+        let edges = self.edges.clone();
+        for edge in &edges {
+            self.set_flag(edge, SHOWN);
+        }
+        edges
+    }
+
+    /// The objects `commits` need beyond what the edges have: `tags` with their names, then the
+    /// trees and blobs of each commit, each the first time it's found, with its path
+    pub(crate) fn objects(
+        &self,
+        commits: &[[u8; 20]],
+        tags: &[[u8; 20]],
+    ) -> anyhow::Result<Vec<([u8; 20], String)>> {
+        let mut seen = HashSet::new();
+        for edge in &self.edges {
+            mark_tree_seen(&self.nodes[edge].commit.tree, &mut seen)?;
+        }
+        let mut objects = Vec::new();
+        for tag in tags {
+            if seen.insert(*tag) {
+                let content = read_object_of_kind(&hex::encode(tag), ObjectKind::Tag)?;
+                objects.push((*tag, Tag::parse(&content)?.name));
+            }
+        }
+        for commit in commits {
+            let tree = self.nodes[commit].commit.tree;
+            tree_objects(tree, String::new(), &mut seen, &mut objects)?;
+        }
+        Ok(objects)
+    }
+
+    /// A commit returned by [`RevWalk::next`]
+    pub(crate) fn commit(&self, hash: &[u8; 20]) -> &Commit {
+        &self.nodes[hash].commit
+    }
+
+    /// The parents of a commit returned by [`RevWalk::next`], after history simplification
+    pub(crate) fn parents(&self, hash: &[u8; 20]) -> &[[u8; 20]] {
+        &self.nodes[hash].parents
+    }
+
+    /// Everything a limited walk found to show, newest first, before any is shown. Commits left
+    /// out for not changing the limited paths are included; [`RevWalk::is_treesame`] tells them
+    /// apart.
+    pub(crate) fn limited_list(&self) -> Vec<[u8; 20]> {
+        self.list
+            .iter()
+            .filter(|hash| !self.has_flag(hash, UNINTERESTING))
+            .copied()
+            .collect()
+    }
+
+    /// The line ranges followed, with the diffs of the commits changing them
+    pub(crate) fn line_log(&self) -> Option<&LineLog> {
+        self.opts.line_log.as_ref()
+    }
+
+    /// Whether a commit found by the walk doesn't change the limited paths
+    pub(crate) fn is_treesame(&self, hash: &[u8; 20]) -> bool {
+        self.prunes() && self.has_flag(hash, TREESAME)
+    }
+
+    fn load(&mut self, hash: &[u8; 20]) -> anyhow::Result<()> {
+        if !self.nodes.contains_key(hash) {
+            let commit = Commit::read(hash)?;
+            let parents = commit.parents.clone();
+            self.nodes.insert(*hash, Node { commit, parents });
+        }
+        Ok(())
+    }
+
+    fn has_flag(&self, hash: &[u8; 20], flag: u16) -> bool {
+        self.flags.get(hash).is_some_and(|flags| flags & flag != 0)
+    }
+
+    /// Sets a flag, returning whether it was already set
+    fn set_flag(&mut self, hash: &[u8; 20], flag: u16) -> bool {
+        let flags = self.flags.entry(*hash).or_default();
+        let was_set = *flags & flag != 0;
+        *flags |= flag;
+        was_set
+    }
+
+    fn date(&self, hash: &[u8; 20]) -> i64 {
+        self.nodes[hash].commit.committer.time
+    }
+
+    fn older_than_since(&self, hash: &[u8; 20]) -> bool {
+        self.opts.since.is_some_and(|since| self.date(hash) < since)
+    }
+
+    /// Inserts a loaded commit into the walk list after all commits at least as new
+    fn insert_by_date(&mut self, hash: [u8; 20]) {
+        let date = self.date(&hash);
+        let position = self
+            .list
+            .iter()
+            .position(|other| self.date(other) < date)
+            .unwrap_or(self.list.len());
+        self.list.insert(position, hash);
+    }
+
+    /// Marks everything reachable from the already loaded parents of a commit uninteresting
+    fn mark_parents_uninteresting(&mut self, hash: &[u8; 20]) {
+        let mut pending: Vec<[u8; 20]> = match self.nodes.get(hash) {
+            Some(node) => node.commit.parents.clone(),
+            None => return,
+        };
+        while let Some(parent) = pending.pop() {
+            if self.set_flag(&parent, UNINTERESTING) {
+                continue;
+            }
+            if let Some(node) = self.nodes.get(&parent) {
+                pending.extend(&node.commit.parents);
+            }
+        }
+    }
+
+    /// Queues the parents of a commit for walking, after simplifying them for path limiting
+    fn add_parents(&mut self, hash: &[u8; 20]) -> anyhow::Result<()> {
+        if self.set_flag(hash, ADDED) {
+            return Ok(());
+        }
+        if self.has_flag(hash, UNINTERESTING) {
+            // Uninteresting commits spread to all parents, to find everything to exclude
+            for parent in self.nodes[hash].commit.parents.clone() {
+                self.set_flag(&parent, UNINTERESTING);
+                self.load(&parent)?;
+                self.mark_parents_uninteresting(&parent);
+                if !self.set_flag(&parent, SEEN) {
+                    self.insert_by_date(parent);
+                }
+            }
+            return Ok(());
+        }
+
+        self.simplify(hash)?;
+        let left = self.has_flag(hash, LEFT);
+        for parent in self.nodes[hash].parents.clone() {
+            self.load(&parent)?;
+            if left {
+                self.set_flag(&parent, LEFT);
+            }
+            if !self.set_flag(&parent, SEEN) {
+                self.insert_by_date(parent);
+            }
+            if self.opts.first_parent {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether commits can be left out for not changing anything of interest
+    fn prunes(&self) -> bool {
+        self.opts.paths.is_some() || self.opts.decorated.is_some() || self.opts.line_log.is_some()
+    }
+
+    /// Whether a commit's tree differs from `parent`'s (or the empty tree) in the limited paths.
+    /// When simplifying by decoration, decorated commits always count as changed and, without
+    /// paths, other commits never do.
+    fn changes_paths(&self, hash: &[u8; 20], parent: Option<&[u8; 20]>) -> anyhow::Result<bool> {
+        if let (Some(decorated), Some(_)) = (&self.opts.decorated, parent) {
+            if decorated.contains(hash) {
+                return Ok(true);
+            }
+            if self.opts.paths.is_none() {
+                return Ok(false);
+            }
+        }
+        let everything = Pathspec::default();
+        let paths = self.opts.paths.as_ref().unwrap_or(&everything);
+        let opts = TreeDiffOptions {
+            recursive: true,
+            ..TreeDiffOptions::default()
+        };
+        let old = parent.map(|parent| self.nodes[parent].commit.tree);
+        let new = self.nodes[hash].commit.tree;
+        Ok(!diff_trees(old.as_ref(), Some(&new), paths, opts)?.is_empty())
+    }
+
+    /// Marks a commit TREESAME when it doesn't change the limited paths. A commit that
+    /// matches one of its interesting parents keeps only that parent, following the history of
+    /// the paths down that side, except when simplifying by decoration, which keeps the full
+    /// history.
+    fn simplify(&mut self, hash: &[u8; 20]) -> anyhow::Result<()> {
+        if !self.prunes() {
+            return Ok(());
+        }
+        if self.opts.line_log.is_some() {
+            return self.simplify_line_log(hash);
+        }
+        let simplify_history = !self.opts.simplify_merges;
+        let parents = self.nodes[hash].parents.clone();
+        if parents.is_empty() {
+            if !self.changes_paths(hash, None)? {
+                self.set_flag(hash, TREESAME);
+            }
+            return Ok(());
+        }
+
+        let mut relevant_parents = 0;
+        let mut relevant_change = false;
+        let mut irrelevant_change = false;
+        let mut same = Vec::new();
+        for (nth, parent) in parents.iter().enumerate() {
+            let relevant = !self.has_flag(parent, UNINTERESTING) || self.has_flag(parent, BOTTOM);
+            if relevant {
+                relevant_parents += 1;
+            }
+            if nth == 1 && self.opts.first_parent {
+                break;
+            }
+            self.load(parent)?;
+            let changed = self.changes_paths(hash, Some(parent))?;
+            same.push(!changed);
+            if !changed {
+                if !simplify_history || !relevant {
+                    continue;
+                }
+                self.nodes.get_mut(hash).unwrap().parents = vec![*parent];
+                self.set_flag(hash, TREESAME);
+                return Ok(());
+            }
+            if relevant {
+                relevant_change = true;
+            } else {
+                irrelevant_change = true;
+            }
+        }
+        if !simplify_history && same.len() > 1 && !self.has_flag(hash, UNINTERESTING) {
+            self.treesame.insert(*hash, same);
+        }
+        let changed = if relevant_parents > 0 {
+            relevant_change
+        } else {
+            irrelevant_change
+        };
+        if changed {
+            if let Some(flags) = self.flags.get_mut(hash) {
+                *flags &= !TREESAME;
+            }
+        } else {
+            self.set_flag(hash, TREESAME);
+        }
+        Ok(())
+    }
+
+    /// Marks a commit TREESAME when it doesn't change the followed line ranges, handing them on
+    /// to its parents. A merge whose ranges came unchanged from one parent keeps only that one.
+    fn simplify_line_log(&mut self, hash: &[u8; 20]) -> anyhow::Result<()> {
+        let mut parents = self.nodes[hash].parents.clone();
+        let merge = parents.len() > 1;
+        if self.opts.first_parent {
+            parents.truncate(1);
+        }
+        let mut trees = Vec::new();
+        for parent in parents {
+            self.load(&parent)?;
+            trees.push((parent, self.nodes[&parent].commit.tree));
+        }
+        let tree = self.nodes[hash].commit.tree;
+        let line_log = self.opts.line_log.as_mut().unwrap();
+        let (changed, follow) = line_log.process(hash, &tree, &trees, merge)?;
+        if let Some(parent) = follow {
+            self.nodes.get_mut(hash).unwrap().parents = vec![parent];
+        }
+        if !changed {
+            self.set_flag(hash, TREESAME);
+        }
+        Ok(())
+    }
+
+    /// Walks the history up front until only uninteresting commits are left, keeping the
+    /// interesting ones in date order
+    fn limit(&mut self) -> anyhow::Result<()> {
+        let mut limited = VecDeque::new();
+        let mut date = i64::MAX;
+        let mut slop = SLOP;
+        while let Some(hash) = self.list.pop_front() {
+            if self.older_than_since(&hash) {
+                self.set_flag(&hash, UNINTERESTING);
+            }
+            self.add_parents(&hash)?;
+            if self.has_flag(&hash, UNINTERESTING) {
+                self.mark_parents_uninteresting(&hash);
+                slop = self.still_interesting(date, slop);
+                if slop > 0 {
+                    continue;
+                }
+                break;
+            }
+            if self
+                .opts
+                .until
+                .is_some_and(|until| self.date(&hash) > until)
+            {
+                continue;
+            }
+            date = self.date(&hash);
+            limited.push_back(hash);
+        }
+        self.list = limited;
+        if self.opts.cherry_pick {
+            self.cherry_pick()?;
+        }
+        Ok(())
+    }
+
+    /// Marks the commits on each side of `a...b` whose patch is also on the other side as
+    /// shown, so neither is. Patch ids are worked out for the smaller side first, then looked
+    /// up for the other.
+    fn cherry_pick(&mut self) -> anyhow::Result<()> {
+        let (left, right): (Vec<[u8; 20]>, Vec<[u8; 20]>) =
+            self.list.iter().partition(|hash| self.has_flag(hash, LEFT));
+        if left.is_empty() || right.is_empty() {
+            return Ok(());
+        }
+        let (smaller, larger) = if left.len() < right.len() {
+            (left, right)
+        } else {
+            (right, left)
+        };
+        let everything = Pathspec::default();
+        let pathspec = self.opts.paths.as_ref().unwrap_or(&everything);
+        let mut ids: HashMap<[u8; 20], Vec<[u8; 20]>> = HashMap::new();
+        for hash in smaller {
+            if let Some(id) = commit_patch_id(&self.nodes[&hash].commit, pathspec)? {
+                ids.entry(id).or_default().push(hash);
+            }
+        }
+        let mut same = Vec::new();
+        for hash in larger {
+            let Some(id) = commit_patch_id(&self.nodes[&hash].commit, pathspec)? else {
+                continue;
+            };
+            if let Some(matches) = ids.get(&id) {
+                same.push(hash);
+                same.extend(matches);
+            }
+        }
+        for hash in same {
+            self.set_flag(&hash, SHOWN);
+        }
+        Ok(())
+    }
+
+    fn still_interesting(&self, date: i64, slop: usize) -> usize {
+        let Some(next) = self.list.front() else {
+            return 0;
+        };
+        if date <= self.date(next) {
+            return SLOP;
+        }
+        if self
+            .list
+            .iter()
+            .any(|hash| !self.has_flag(hash, UNINTERESTING))
+        {
+            return SLOP;
+        }
+        slop - 1
+    }
+
+    /// Reorders the list so that no parent comes before its children
+    fn sort_topologically(&mut self, sort: Sort) {
+        let mut indegree: HashMap<[u8; 20], usize> =
+            self.list.iter().map(|hash| (*hash, 1)).collect();
+        for hash in &self.list {
+            for parent in &self.nodes[hash].parents {
+                if let Some(count) = indegree.get_mut(parent) {
+                    *count += 1;
+                }
+            }
+        }
+
+        // Topological order works through a stack, so each line of history is finished before
+        // the next; the date orders use a queue by date, ties going to the earliest queued
+        let mut queue = TopoQueue {
+            sort,
+            stack: Vec::new(),
+            heap: BinaryHeap::new(),
+            queued: 0,
+        };
+        for hash in &self.list {
+            if indegree[hash] == 1 {
+                queue.put(self, *hash);
+            }
+        }
+        // The tips come out in the order the walk found them
+        queue.stack.reverse();
+
+        let mut sorted = VecDeque::with_capacity(self.list.len());
+        while let Some(hash) = queue.get() {
+            for parent in &self.nodes[&hash].parents {
+                let Some(count) = indegree.get_mut(parent) else {
+                    continue;
+                };
+                if *count == 0 {
+                    continue;
+                }
+                *count -= 1;
+                if *count == 1 {
+                    queue.put(self, *parent);
+                }
+            }
+            indegree.insert(hash, 0);
+            sorted.push_back(hash);
+        }
+        self.list = sorted;
+    }
+
+    /// Whether a commit taken from the list is shown
+    fn shows(&self, hash: &[u8; 20]) -> bool {
+        if self.has_flag(hash, SHOWN) || self.has_flag(hash, UNINTERESTING) {
+            return false;
+        }
+        if self.opts.until.is_some_and(|until| self.date(hash) > until) {
+            return false;
+        }
+        let node = &self.nodes[hash];
+        if self.opts.no_merges && node.parents.len() > 1 {
+            return false;
+        }
+        if !self.opts.filter.matches(&node.commit) {
+            return false;
+        }
+        if self.prunes() && self.has_flag(hash, TREESAME) {
+            // Merges between shown lines of history still tie them together
+            return self.opts.rewrite_parents
+                && node
+                    .parents
+                    .iter()
+                    .filter(|parent| !self.has_flag(parent, UNINTERESTING))
+                    .count()
+                    >= 2;
+        }
+        true
+    }
+
+    /// Whether a commit would be shown if it came up in the walk, as [`RevWalk::parents`] of
+    /// shown commits may not be when they are filtered out
+    pub(crate) fn is_interesting(&self, hash: &[u8; 20]) -> bool {
+        self.nodes.contains_key(hash) && self.shows(hash)
+    }
+
+    /// Replaces each parent that the walk leaves out for not changing anything of interest with
+    /// its nearest ancestor that isn't left out, dropping parents without one
+    fn rewrite_parents(&mut self, hash: &[u8; 20]) -> anyhow::Result<()> {
+        let mut parents = Vec::new();
+        for parent in self.nodes[hash].parents.clone() {
+            if let Some(parent) = self.rewrite_one(parent)? {
+                if !parents.contains(&parent) {
+                    parents.push(parent);
+                }
+            }
+        }
+        self.nodes.get_mut(hash).unwrap().parents = parents;
+        Ok(())
+    }
+
+    fn rewrite_one(&mut self, mut hash: [u8; 20]) -> anyhow::Result<Option<[u8; 20]>> {
+        loop {
+            if !self.limited {
+                self.load(&hash)?;
+                self.add_parents(&hash)?;
+            }
+            if self.has_flag(&hash, UNINTERESTING) || !self.has_flag(&hash, TREESAME) {
+                return Ok(Some(hash));
+            }
+            if self.nodes[&hash].parents.is_empty() {
+                return Ok(None);
+            }
+            match self.one_relevant_parent(&hash) {
+                Some(parent) => hash = parent,
+                None => return Ok(Some(hash)),
+            }
+        }
+    }
+
+    /// The parent a commit's TREESAME-ness is decided by: the first with `--first-parent` or a
+    /// single parent, otherwise the only interesting parent, if there's exactly one
+    fn one_relevant_parent(&self, hash: &[u8; 20]) -> Option<[u8; 20]> {
+        let parents = &self.nodes[hash].parents;
+        if self.opts.first_parent || parents.len() == 1 {
+            return parents.first().copied();
+        }
+        let mut relevant = parents
+            .iter()
+            .filter(|parent| !self.has_flag(parent, UNINTERESTING));
+        match (relevant.next(), relevant.next()) {
+            (Some(parent), None) => Some(*parent),
+            _ => None,
+        }
+    }
+
+    /// Rewrites the parents of every commit in the list to what they simplify to, and drops the
+    /// commits that simplify to one of their parents: TREESAME commits, and merges left with
+    /// one relevant parent once redundant parents are removed
+    fn simplify_merges(&mut self) -> anyhow::Result<()> {
+        let mut simplified = HashMap::new();
+        // Oldest first, so parents tend to be done before their children
+        let mut todo: VecDeque<[u8; 20]> = self.list.iter().rev().copied().collect();
+        while !todo.is_empty() {
+            for hash in std::mem::take(&mut todo) {
+                self.simplify_one(hash, &mut simplified, &mut todo)?;
+            }
+        }
+        self.list.retain(|hash| simplified.get(hash) == Some(hash));
+        Ok(())
+    }
+
+    /// Works out what a commit simplifies to, or queues it again after the parents it waits on
+    fn simplify_one(
+        &mut self,
+        hash: [u8; 20],
+        simplified: &mut HashMap<[u8; 20], [u8; 20]>,
+        todo: &mut VecDeque<[u8; 20]>,
+    ) -> anyhow::Result<()> {
+        if simplified.contains_key(&hash) {
+            return Ok(());
+        }
+        // Uninteresting commits and roots simplify to themselves
+        let Some(node) = self.nodes.get(&hash) else {
+            simplified.insert(hash, hash);
+            return Ok(());
+        };
+        if self.has_flag(&hash, UNINTERESTING) || node.parents.is_empty() {
+            simplified.insert(hash, hash);
+            return Ok(());
+        }
+
+        let count = if self.opts.first_parent {
+            1
+        } else {
+            node.parents.len()
+        };
+        let waiting: Vec<[u8; 20]> = node.parents[..count]
+            .iter()
+            .filter(|parent| !simplified.contains_key(*parent))
+            .copied()
+            .collect();
+        if !waiting.is_empty() {
+            todo.extend(waiting);
+            todo.push_back(hash);
+            return Ok(());
+        }
+
+        let node = self.nodes.get_mut(&hash).unwrap();
+        for parent in &mut node.parents[..count] {
+            *parent = simplified[parent];
+        }
+        let mut seen = HashSet::new();
+        let duplicates: Vec<bool> = node.parents.iter().map(|p| !seen.insert(*p)).collect();
+        self.remove_parents(&hash, &duplicates);
+
+        let parents = self.nodes[&hash].parents.clone();
+        if !self.opts.first_parent && parents.len() > 1 {
+            // Parents reachable from other parents add nothing, and neither do roots that don't
+            // change the paths
+            let mut redundant = Vec::new();
+            for parent in &parents {
+                let mut marked = self.has_flag(parent, TREESAME)
+                    && self.nodes.get(parent).is_some_and(|p| p.parents.is_empty());
+                for other in parents.iter().filter(|other| *other != parent) {
+                    if !marked && ancestors(other)?.contains(parent) {
+                        marked = true;
+                    }
+                }
+                redundant.push(marked);
+            }
+            // Keep the first parent the commit is TREESAME to, if that's all it would lose
+            if let Some(same) = self.treesame.get(&hash) {
+                let mut first_marked = None;
+                let mut unmarked = false;
+                for (n, marked) in redundant.iter().enumerate() {
+                    if !same[n] {
+                        continue;
+                    }
+                    if !marked {
+                        unmarked = true;
+                        break;
+                    }
+                    first_marked.get_or_insert(n);
+                }
+                if let (false, Some(n)) = (unmarked, first_marked) {
+                    redundant[n] = false;
+                }
+            }
+            if redundant.contains(&true) {
+                self.remove_parents(&hash, &redundant);
+                if !self.has_flag(&hash, TREESAME) {
+                    self.update_treesame(&hash);
+                }
+            }
+        }
+
+        let target = match self.one_relevant_parent(&hash) {
+            Some(parent) if self.has_flag(&hash, TREESAME) => simplified[&parent],
+            _ => hash,
+        };
+        simplified.insert(hash, target);
+        Ok(())
+    }
+
+    /// Drops the marked parents of a commit, keeping its per-parent TREESAME record in step. A
+    /// merge left with one parent is TREESAME exactly when it matches that parent.
+    fn remove_parents(&mut self, hash: &[u8; 20], remove: &[bool]) {
+        let node = self.nodes.get_mut(hash).unwrap();
+        let mut marks = remove.iter();
+        node.parents.retain(|_| !marks.next().unwrap());
+        let remaining = node.parents.len();
+        let Some(same) = self.treesame.get_mut(hash) else {
+            return;
+        };
+        let mut marks = remove.iter();
+        same.retain(|_| !marks.next().unwrap());
+        if remaining > 1 {
+            return;
+        }
+        let treesame = same.first().copied().unwrap_or_default();
+        self.treesame.remove(hash);
+        if treesame {
+            self.set_flag(hash, TREESAME);
+        } else if let Some(flags) = self.flags.get_mut(hash) {
+            *flags &= !TREESAME;
+        }
+    }
+
+    /// Decides again whether a merge is TREESAME from its remaining parents: to all of its
+    /// relevant parents, or to all parents if none is relevant
+    fn update_treesame(&mut self, hash: &[u8; 20]) {
+        let Some(same) = self.treesame.get(hash) else {
+            return;
+        };
+        let mut relevant_parents = 0;
+        let mut relevant_change = false;
+        let mut irrelevant_change = false;
+        for (parent, same) in self.nodes[hash].parents.iter().zip(same) {
+            if self.has_flag(parent, UNINTERESTING) {
+                irrelevant_change |= !same;
+            } else {
+                relevant_change |= !same;
+                relevant_parents += 1;
+            }
+        }
+        let changed = if relevant_parents > 0 {
+            relevant_change
+        } else {
+            irrelevant_change
+        };
+        if changed {
+            if let Some(flags) = self.flags.get_mut(hash) {
+                *flags &= !TREESAME;
+            }
+        } else {
+            self.set_flag(hash, TREESAME);
+        }
+    }
+}
+
+/// Commits ready to be emitted by [`RevWalk::sort_topologically`]
+struct TopoQueue {
+    sort: Sort,
+    stack: Vec<[u8; 20]>,
+    heap: BinaryHeap<(i64, Reverse<usize>, [u8; 20])>,
+    queued: usize,
+}
+
+impl TopoQueue {
+    fn put(&mut self, walk: &RevWalk, hash: [u8; 20]) {
+        let date = match self.sort {
+            Sort::Topo => {
+                self.stack.push(hash);
+                return;
+            }
+            Sort::AuthorDate => walk.nodes[&hash].commit.author.time,
+            _ => walk.date(&hash),
+        };
+        self.heap.push((date, Reverse(self.queued), hash));
+        self.queued += 1;
+    }
+
+    fn get(&mut self) -> Option<[u8; 20]> {
+        match self.sort {
+            Sort::Topo => self.stack.pop(),
+            _ => self.heap.pop().map(|(_, _, hash)| hash),
+        }
+    }
+}
+
+/// The commits and paths named on the command line of `log` and similar commands
+#[derive(Debug, Default)]
+pub(crate) struct Revisions {
+    pub(crate) include: Vec<[u8; 20]>,
+    pub(crate) exclude: Vec<[u8; 20]>,
+    pub(crate) paths: Vec<String>,
+    /// The included tips on the left of `a...b`
+    pub(crate) left: Vec<[u8; 20]>,
+    /// Annotated tags the included commits were named through, which `--objects` lists
+    pub(crate) tags: Vec<[u8; 20]>,
+}
+
+impl Revisions {
+    /// Sorts out revisions (`rev`, `^rev`, `a..b`, `a...b`) from paths. Without `--`, the first
+    /// argument that isn't a revision has to be an existing path and starts the paths. `--not`
+    /// flips whether the revisions after it are included or excluded, and `--all` stands for
+    /// every ref and HEAD. With `default_head`, HEAD is used when no revision is given.
+    pub(crate) fn parse(
+        args: &[String],
+        paths: &[String],
+        default_head: bool,
+    ) -> anyhow::Result<Revisions> {
+        let mut revisions = Revisions::default();
+        let mut negated = false;
+        let mut named = false;
+        let mut args = args.iter();
+        for arg in args.by_ref() {
+            match arg.as_str() {
+                "--not" => {
+                    negated = !negated;
+                    continue;
+                }
+                "--all" => {
+                    revisions.add_all(negated)?;
+                    named = true;
+                    continue;
+                }
+                _ => {}
+            }
+            if revisions.add(arg, negated).is_ok() {
+                named = true;
+                continue;
+            }
+            if !paths.is_empty() {
+                anyhow::bail!("bad revision '{arg}'");
+            }
+            anyhow::ensure!(
+                std::fs::symlink_metadata(arg).is_ok(),
+                "ambiguous argument '{arg}': unknown revision or path not in the working tree.\nUse '--' to separate paths from revisions, like this:\n'git <command> [<revision>...] -- [<file>...]'"
+            );
+            revisions.paths.push(arg.clone());
+            break;
+        }
+        revisions.paths.extend(args.cloned());
+        revisions.paths.extend(paths.iter().cloned());
+
+        if !named && default_head {
+            let Some(head) = refs::head_commit()? else {
+                let branch = match refs::read_head()? {
+                    Head::Symbolic(target) => refs::shorten(&target).to_string(),
+                    Head::Detached(_) => String::from("HEAD"),
+                };
+                anyhow::bail!("your current branch '{branch}' does not have any commits yet");
+            };
+            revisions.include.push(head);
+        }
+        Ok(revisions)
+    }
+
+    /// Adds the commits of every ref and HEAD. Refs to other kinds of objects are skipped.
+    fn add_all(&mut self, exclude: bool) -> anyhow::Result<()> {
+        let refs = refs::list("refs/")?;
+        let head = refs::head_commit()?;
+        for hash in refs.values().chain(head.iter()) {
+            if revision::peel_to_commit(hash).is_ok() {
+                self.add_object(*hash, exclude)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a commit, or a tag that leads to one, to the included or excluded commits
+    fn add_object(&mut self, hash: [u8; 20], exclude: bool) -> anyhow::Result<[u8; 20]> {
+        let mut tags = Vec::new();
+        let mut target = hash;
+        while let Ok((ObjectKind::Tag, content)) = read_object(&hex::encode(target)) {
+            tags.push(target);
+            target = Tag::parse(&content)?.object;
+        }
+        let commit = revision::peel_to_commit(&target)?;
+        if exclude {
+            self.exclude.push(commit);
+        } else {
+            self.include.push(commit);
+            self.tags.extend(tags);
+        }
+        Ok(commit)
+    }
+
+    fn add(&mut self, arg: &str, negated: bool) -> anyhow::Result<()> {
+        let resolve = |rev: &str| {
+            let rev = if rev.is_empty() { "HEAD" } else { rev };
+            revision::resolve(rev)
+        };
+        if let Some((left, right)) = arg.split_once("...") {
+            let (left, right) = (resolve(left)?, resolve(right)?);
+            let (left_commit, right_commit) = (
+                revision::peel_to_commit(&left)?,
+                revision::peel_to_commit(&right)?,
+            );
+            // Everything reachable from both sides is left out
+            for base in merge_base::merge_bases(&left_commit, &right_commit)? {
+                self.add_object(base, !negated)?;
+            }
+            self.add_object(left, negated)?;
+            if !negated {
+                self.left.push(left_commit);
+            }
+            self.add_object(right, negated)?;
+        } else if let Some((from, to)) = arg.split_once("..") {
+            let (from, to) = (resolve(from)?, resolve(to)?);
+            self.add_object(from, !negated)?;
+            self.add_object(to, negated)?;
+        } else if let Some(rev) = arg.strip_prefix('^') {
+            self.add_object(resolve(rev)?, !negated)?;
+        } else {
+            self.add_object(resolve(arg)?, negated)?;
+        }
+        Ok(())
+    }
+}
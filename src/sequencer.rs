@@ -0,0 +1,813 @@
+//! Replaying commits onto HEAD one at a time, as `cherry-pick` and `revert` do: the changes a
+//! commit made to its parent, or for a revert the opposite, are merged into HEAD and committed.
+//!
+//! A commit that conflicts stops the replay, leaving `CHERRY_PICK_HEAD` or `REVERT_HEAD` naming
+//! it and its message in `MERGE_MSG`. When more than one commit is replayed, what is left to do
+//! is kept in `.git/sequencer` (the todo list, the options, HEAD from before the first commit and
+//! HEAD after the last one replayed), so that `--continue`, `--skip` and `--abort` can pick up
+//! from there.
+
+use std::{fs, io::Write, path::Path};
+
+use anyhow::Context;
+
+use crate::{
+    date::{self, DateMode},
+    diff::{
+        files::tree_to_tree,
+        patch::{abbrev, write_shortstat, write_summary, PatchOptions},
+        rename::{self, RenameOptions},
+    },
+    index::Index,
+    merge::{
+        self,
+        content::{ConflictStyle, FileOptions, Labels},
+        octopus::index_tree,
+        MergeOptions, TreeMerge,
+    },
+    object::{
+        commit::{Commit, Signature},
+        write::write_commit_by,
+    },
+    pathspec::Pathspec,
+    pretty, rebase,
+    refs::{self, Head},
+    revision,
+    unpack::{self, UnpackOptions},
+};
+
+const SEQUENCER: &str = ".git/sequencer";
+
+const RESOLVE_HINT: &[&str] = &[
+    "Fix them up in the work tree, and then use 'git add/rm <file>'",
+    "as appropriate to mark resolution and make a commit.",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Action {
+    Pick,
+    Revert,
+}
+
+impl Action {
+    /// The command replaying commits this way
+    pub(crate) fn command(self) -> &'static str {
+        match self {
+            Action::Pick => "cherry-pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    /// How the todo list names it
+    fn todo_word(self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    /// The pseudo-ref naming the commit a replay stopped at
+    fn head_file(self) -> &'static str {
+        match self {
+            Action::Pick => ".git/CHERRY_PICK_HEAD",
+            Action::Revert => ".git/REVERT_HEAD",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ReplayOptions {
+    /// Which parent of a merge to take the changes against, counting from 1
+    pub(crate) mainline: Option<usize>,
+    /// Append a line naming the picked commit to the message, as `-x` does
+    pub(crate) record_origin: bool,
+    /// Only apply the changes to the index and working tree
+    pub(crate) no_commit: bool,
+    /// Let the user edit the message before each commit
+    pub(crate) edit: bool,
+}
+
+/// How a replay ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// Every commit was replayed
+    Done,
+    /// A commit conflicted and waits for the conflicts to be resolved
+    Conflicts,
+    /// A commit's changes are already in HEAD, leaving nothing to commit
+    Empty,
+}
+
+/// A commit to replay, and whether to pick or revert it
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    action: Action,
+    commit: [u8; 20],
+}
+
+/// Replays `commits` onto HEAD in order. A `single` commit named on its own is replayed without
+/// recording a sequence, so only `CHERRY_PICK_HEAD` or `REVERT_HEAD` is left when it stops.
+pub(crate) fn start(
+    action: Action,
+    commits: &[[u8; 20]],
+    single: bool,
+    opts: ReplayOptions,
+) -> anyhow::Result<Outcome> {
+    let steps: Vec<Step> = commits
+        .iter()
+        .map(|&commit| Step { action, commit })
+        .collect();
+    if let ([step], true) = (steps.as_slice(), single) {
+        check_unmerged(action)?;
+        return replay(step, &opts);
+    }
+
+    if let Some(running) = last_command()? {
+        let skip = if Path::new(Action::Pick.head_file()).exists()
+            || Path::new(Action::Revert.head_file()).exists()
+        {
+            "--skip | "
+        } else {
+            ""
+        };
+        return Err(fail(
+            action,
+            &format!("{} is already in progress", running.command()),
+            &[&format!(
+                "try \"git {} (--continue | {skip}--abort | --quit)\"",
+                running.command()
+            )],
+        ));
+    }
+    check_unmerged(action)?;
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    fs::create_dir_all(SEQUENCER).context("Creating the sequencer directory")?;
+    fs::write(
+        Path::new(SEQUENCER).join("head"),
+        format!("{}\n", hex::encode(head)),
+    )
+    .context("Writing the sequencer head")?;
+    write_abort_safety()?;
+    write_options(&opts)?;
+    run(&steps, &opts)
+}
+
+/// `--continue`: commits the resolved commit the replay stopped at, then replays the rest
+pub(crate) fn resume(action: Action) -> anyhow::Result<Outcome> {
+    let stopped = Path::new(Action::Pick.head_file()).exists()
+        || Path::new(Action::Revert.head_file()).exists();
+    let todo = Path::new(SEQUENCER).join("todo");
+    if !todo.exists() {
+        if !stopped {
+            return Err(fail(action, "no cherry-pick or revert in progress", &[]));
+        }
+        return commit_resolved();
+    }
+    let opts = read_options()?;
+    let steps = read_todo()?;
+    if stopped {
+        let outcome = commit_resolved()?;
+        if outcome != Outcome::Done {
+            return Ok(outcome);
+        }
+    }
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    if index_tree(&Index::read()?)? != Some(Commit::read(&head)?.tree) {
+        return Err(dirty_index(action));
+    }
+    run(steps.get(1..).unwrap_or_default(), &opts)
+}
+
+/// `--skip`: drops the commit the replay stopped at, along with its changes, and replays the
+/// rest
+pub(crate) fn skip(action: Action) -> anyhow::Result<Outcome> {
+    if !Path::new(action.head_file()).exists() {
+        if last_command()? != Some(action) {
+            return Err(fail(
+                action,
+                &format!("no {} in progress", action.command()),
+                &[],
+            ));
+        }
+        if !rollback_is_safe()? {
+            return Err(fail(
+                action,
+                "there is nothing to skip",
+                &[
+                    "have you committed already?",
+                    &format!("try \"git {} --continue\"", action.command()),
+                ],
+            ));
+        }
+    }
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    reset_merge(&head)?;
+    if !Path::new(SEQUENCER).exists() {
+        return Ok(Outcome::Done);
+    }
+    resume(action)
+}
+
+/// `--abort`: goes back to where the replay started, or for a single commit to HEAD, undoing
+/// the changes of the commit it stopped at
+pub(crate) fn abort(action: Action) -> anyhow::Result<()> {
+    let Ok(head) = fs::read_to_string(Path::new(SEQUENCER).join("head")) else {
+        if !Path::new(Action::Pick.head_file()).exists()
+            && !Path::new(Action::Revert.head_file()).exists()
+        {
+            return Err(fail(action, "no cherry-pick or revert in progress", &[]));
+        }
+        let head = refs::head_commit()?.context("cannot abort from a branch yet to be born")?;
+        return reset_merge(&head);
+    };
+    let head = revision::resolve(head.trim()).context("could not parse the sequencer head")?;
+    if rollback_is_safe()? {
+        reset_merge(&head)?;
+    } else {
+        eprintln!("warning: You seem to have moved HEAD. Not rewinding, check your HEAD!");
+    }
+    quit()
+}
+
+/// `--quit`: forgets the replay in progress, keeping HEAD, the index and the working tree as
+/// they are
+pub(crate) fn quit() -> anyhow::Result<()> {
+    if Path::new(SEQUENCER).exists() {
+        fs::remove_dir_all(SEQUENCER).context("Removing the sequencer directory")?;
+    }
+    refs::clear_merge_state()
+}
+
+/// The cherry-pick or revert in progress, with the commit it stopped at unless it is going
+/// through a sequence of them
+pub(crate) fn in_progress() -> anyhow::Result<Option<(Action, Option<[u8; 20]>)>> {
+    let picking = read_head_file(Action::Pick)?;
+    match last_command()? {
+        Some(Action::Pick) => return Ok(Some((Action::Pick, None))),
+        Some(Action::Revert) if picking.is_none() => return Ok(Some((Action::Revert, None))),
+        _ => {}
+    }
+    if let Some(commit) = picking {
+        return Ok(Some((Action::Pick, Some(commit))));
+    }
+    Ok(read_head_file(Action::Revert)?.map(|commit| (Action::Revert, Some(commit))))
+}
+
+/// Replays the steps of a sequence, keeping the todo list up to date, and removes the sequence
+/// once all of them are done
+fn run(steps: &[Step], opts: &ReplayOptions) -> anyhow::Result<Outcome> {
+    for (i, step) in steps.iter().enumerate() {
+        write_todo(&steps[i..])?;
+        let outcome = replay(step, opts)?;
+        if outcome != Outcome::Done {
+            return Ok(outcome);
+        }
+        write_abort_safety()?;
+    }
+    fs::remove_dir_all(SEQUENCER).context("Removing the sequencer directory")?;
+    Ok(Outcome::Done)
+}
+
+/// Merges the changes of one commit into HEAD, or with `no_commit` into the index, and commits
+/// them unless they conflict or are already there
+fn replay(step: &Step, opts: &ReplayOptions) -> anyhow::Result<Outcome> {
+    let action = step.action;
+    let mut index = Index::read()?;
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let head_tree = Commit::read(&head)?.tree;
+    let ours = if opts.no_commit {
+        index_tree(&index)?.context("Your index file is unmerged.")?
+    } else {
+        if index_tree(&index)? != Some(head_tree) {
+            return Err(dirty_index(action));
+        }
+        head_tree
+    };
+
+    let commit = Commit::read(&step.commit)?;
+    let hex = hex::encode(step.commit);
+    let parent = match (commit.parents.as_slice(), opts.mainline) {
+        ([], _) => None,
+        ([parent], None) => Some(*parent),
+        (_, None) => {
+            let message = format!("commit {hex} is a merge but no -m option was given.");
+            return Err(fail(action, &message, &[]));
+        }
+        (parents, Some(mainline)) => match parents.get(mainline - 1) {
+            Some(parent) => Some(*parent),
+            None => {
+                let message = format!("commit {hex} does not have parent {mainline}");
+                return Err(fail(action, &message, &[]));
+            }
+        },
+    };
+    let message = replay_message(step, &commit, parent.as_ref(), opts);
+
+    let result = merge_changes(action, &step.commit, &commit, parent.as_ref(), &ours)?;
+    if let Err(err) = merge::checkout(&mut index, Some(&ours), &result) {
+        return Err(fail(action, &err.to_string(), &[]));
+    }
+    index.write()?;
+    fs::write(".git/AUTO_MERGE", format!("{}\n", hex::encode(result.tree)))
+        .context("Writing AUTO_MERGE")?;
+    let mut out = std::io::stdout().lock();
+    for line in &result.messages {
+        writeln!(out, "{line}")?;
+    }
+    drop(out);
+
+    let head_file = match action {
+        Action::Pick if !opts.no_commit => Some(action.head_file()),
+        Action::Revert if opts.no_commit || !result.is_clean() => Some(action.head_file()),
+        _ => None,
+    };
+    if !result.is_clean() {
+        let conflicts: String = result
+            .conflicts
+            .keys()
+            .map(|path| format!("#\t{path}\n"))
+            .collect();
+        fs::write(
+            ".git/MERGE_MSG",
+            format!("{message}\n# Conflicts:\n{conflicts}"),
+        )
+        .context("Writing MERGE_MSG")?;
+        if let Some(file) = head_file {
+            fs::write(file, format!("{hex}\n")).with_context(|| format!("Writing {file}"))?;
+        }
+        let doing = match action {
+            Action::Pick => "apply",
+            Action::Revert => "revert",
+        };
+        eprintln!(
+            "error: could not {doing} {}... {}",
+            abbrev(&step.commit),
+            commit.summary()
+        );
+        let command = action.command();
+        let hint = if opts.no_commit {
+            String::from(
+                "after resolving the conflicts, mark the corrected paths\nwith 'git add <paths>' or 'git rm <paths>'",
+            )
+        } else {
+            format!(
+                "After resolving the conflicts, mark them with\n\"git add/rm <pathspec>\", then run\n\"git {command} --continue\".\nYou can instead skip this commit with \"git {command} --skip\".\nTo abort and get back to the state before \"git {command}\",\nrun \"git {command} --abort\"."
+            )
+        };
+        for line in hint.lines() {
+            eprintln!("hint: {line}");
+        }
+        return Ok(Outcome::Conflicts);
+    }
+    if opts.no_commit || result.tree == head_tree {
+        fs::write(".git/MERGE_MSG", &message).context("Writing MERGE_MSG")?;
+        if let Some(file) = head_file {
+            fs::write(file, format!("{hex}\n")).with_context(|| format!("Writing {file}"))?;
+        }
+        if opts.no_commit {
+            return Ok(Outcome::Done);
+        }
+        return Ok(empty(action));
+    }
+
+    let author = match action {
+        Action::Pick => commit.author.clone(),
+        Action::Revert => Signature::current("AUTHOR")?,
+    };
+    let message = match opts.edit {
+        true => rebase::edit_message(&message, &head_tree, &author, false)?,
+        false => message,
+    };
+    let new = write_commit_by(&result.tree, &[head], &author, &message)?;
+    let first_line = message.lines().next().unwrap_or_default();
+    refs::update_ref("HEAD", &new, &format!("{}: {first_line}", action.command()))?;
+    print_summary(&new, &head_tree, true)?;
+    Ok(Outcome::Done)
+}
+
+/// Merges the changes `commit` made to `parent`, or for a revert the opposite, into the tree
+/// `ours`, labelling the sides after the commit
+pub(crate) fn merge_changes(
+    action: Action,
+    hash: &[u8; 20],
+    commit: &Commit,
+    parent: Option<&[u8; 20]>,
+    ours: &[u8; 20],
+) -> anyhow::Result<TreeMerge> {
+    let parent_tree = match parent {
+        Some(parent) => Some(Commit::read(parent)?.tree),
+        None => None,
+    };
+    let name = format!("{} ({})", abbrev(hash), commit.summary());
+    let parent_name = match parent {
+        Some(_) => format!("parent of {name}"),
+        None => String::from("(empty tree)"),
+    };
+    let (base, theirs, labels) = match action {
+        Action::Pick => (
+            parent_tree,
+            Some(commit.tree),
+            Labels {
+                ours: "HEAD",
+                base: &parent_name,
+                theirs: &name,
+            },
+        ),
+        Action::Revert => (
+            Some(commit.tree),
+            parent_tree,
+            Labels {
+                ours: "HEAD",
+                base: &name,
+                theirs: &parent_name,
+            },
+        ),
+    };
+    let opts = MergeOptions {
+        labels,
+        content: FileOptions {
+            style: ConflictStyle::configured()?,
+            ..FileOptions::default()
+        },
+        subtree_shift: None,
+    };
+    merge::merge_tree_changes(base.as_ref(), ours, theirs.as_ref(), opts)
+}
+
+/// The message of the commit replaying `commit`: its own for a pick, with `-x` noting where it
+/// was picked from, and for a revert one saying which commit it reverts
+fn replay_message(
+    step: &Step,
+    commit: &Commit,
+    parent: Option<&[u8; 20]>,
+    opts: &ReplayOptions,
+) -> String {
+    let hex = hex::encode(step.commit);
+    match step.action {
+        Action::Pick => {
+            let mut message = commit.message.clone();
+            if opts.record_origin {
+                if !message.ends_with('\n') {
+                    message.push('\n');
+                }
+                if !ends_with_trailers(&message) {
+                    message.push('\n');
+                }
+                message.push_str(&format!("(cherry picked from commit {hex})\n"));
+            }
+            message
+        }
+        Action::Revert => {
+            let mut message = format!(
+                "Revert \"{}\"\n\nThis reverts commit {hex}",
+                commit.summary()
+            );
+            if let (Some(parent), [_, _, ..]) = (parent, commit.parents.as_slice()) {
+                message.push_str(&format!(
+                    ", reversing\nchanges made to {}",
+                    hex::encode(parent)
+                ));
+            }
+            message.push_str(".\n");
+            message
+        }
+    }
+}
+
+/// Whether the last paragraph of a message, other than its subject, is a block of trailers
+/// (`Signed-off-by: ...` and the like), which a `(cherry picked from ...)` line joins rather
+/// than starting a paragraph of its own. Like git, a block that is mostly trailers counts when
+/// git itself added one of them.
+fn ends_with_trailers(message: &str) -> bool {
+    let paragraphs: Vec<Vec<&str>> = message
+        .split("\n\n")
+        .map(|paragraph| {
+            paragraph
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .collect::<Vec<_>>()
+        })
+        .filter(|lines| !lines.is_empty())
+        .collect();
+    let [_, .., last] = paragraphs.as_slice() else {
+        return false;
+    };
+    let mut trailers = 0;
+    let mut generated = false;
+    for (i, line) in last.iter().enumerate() {
+        if i > 0 && line.starts_with(char::is_whitespace) {
+            // A trailer's value continued
+            trailers += 1;
+            continue;
+        }
+        if line.starts_with("(cherry picked from commit ") || line.starts_with("Signed-off-by: ") {
+            generated = true;
+            trailers += 1;
+            continue;
+        }
+        let is_trailer = line.split_once(':').is_some_and(|(token, _)| {
+            !token.is_empty()
+                && token
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        });
+        if is_trailer {
+            trailers += 1;
+        }
+    }
+    trailers == last.len() || (generated && trailers * 4 >= last.len())
+}
+
+/// Commits the index as the resolution of the commit a replay stopped at, with the message
+/// prepared in `MERGE_MSG`, as `git commit` does while a cherry-pick or revert is in progress
+fn commit_resolved() -> anyhow::Result<Outcome> {
+    let index = Index::read()?;
+    let Some(tree) = index_tree(&index)? else {
+        let mut unmerged: Vec<&str> = index
+            .entries()
+            .iter()
+            .filter(|entry| entry.stage != 0)
+            .map(|entry| entry.path.as_str())
+            .collect();
+        unmerged.dedup();
+        for path in unmerged {
+            println!("U\t{path}");
+        }
+        let hints: String = RESOLVE_HINT
+            .iter()
+            .map(|line| format!("hint: {line}\n"))
+            .collect();
+        anyhow::bail!(
+            "Committing is not possible because you have unmerged files.\n{hints}fatal: Exiting because of an unresolved conflict."
+        );
+    };
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    let head_tree = Commit::read(&head)?.tree;
+    let picked = read_head_file(Action::Pick)?;
+    if tree == head_tree {
+        return Ok(empty(if picked.is_some() {
+            Action::Pick
+        } else {
+            Action::Revert
+        }));
+    }
+    let message = fs::read_to_string(".git/MERGE_MSG").unwrap_or_default();
+    let author = match &picked {
+        Some(picked) => Commit::read(picked)?.author,
+        None => Signature::current("AUTHOR")?,
+    };
+    let message = match read_options()?.edit {
+        true => rebase::edit_message(&message, &head_tree, &author, false)?,
+        false => pretty::cleanup_message(&message),
+    };
+    anyhow::ensure!(
+        !message.is_empty(),
+        "Aborting commit due to empty commit message."
+    );
+    let commit = write_commit_by(&tree, &[head], &author, &message)?;
+    let first_line = message.lines().next().unwrap_or_default();
+    let reflog = match picked {
+        Some(_) => format!("commit (cherry-pick): {first_line}"),
+        None => format!("commit: {first_line}"),
+    };
+    refs::update_ref("HEAD", &commit, &reflog)?;
+    refs::clear_merge_state()?;
+    print_summary(&commit, &head_tree, picked.is_some())?;
+    Ok(Outcome::Done)
+}
+
+/// Stops for a replayed commit that changes nothing, leaving it to the user to commit it
+/// anyway or skip it. A revert says nothing more than that there is nothing to commit.
+fn empty(action: Action) -> Outcome {
+    if action == Action::Pick {
+        eprint!(
+            "The previous cherry-pick is now empty, possibly due to conflict resolution.\nIf you wish to commit it anyway, use:\n\n    git commit --allow-empty\n\nOtherwise, please use 'git cherry-pick --skip'\n"
+        );
+    }
+    Outcome::Empty
+}
+
+/// Shows the commit just made: its subject, its author and date when asked or taken from
+/// someone else, and what it changed
+pub(crate) fn print_summary(
+    commit: &[u8; 20],
+    old_tree: &[u8; 20],
+    show_date: bool,
+) -> anyhow::Result<()> {
+    let branch = match refs::read_head()? {
+        Head::Symbolic(target) => refs::shorten(&target).to_string(),
+        Head::Detached(_) => String::from("detached HEAD"),
+    };
+    let made = Commit::read(commit)?;
+    let mut out = std::io::stdout().lock();
+    writeln!(
+        out,
+        "[{branch} {}] {}",
+        abbrev(commit),
+        pretty::subject(&made.message)
+    )?;
+    let author = &made.author;
+    if author.identity() != made.committer.identity() {
+        writeln!(out, " Author: {}", author.identity())?;
+    }
+    if show_date {
+        let date = date::format(author.time, author.tz_offset, DateMode::Default);
+        writeln!(out, " Date: {date}")?;
+    }
+    if made.parents.len() > 1 {
+        return Ok(());
+    }
+    let pairs = tree_to_tree(Some(old_tree), Some(&made.tree), &Pathspec::new(&[]), false)?;
+    let opts = RenameOptions {
+        copies: false,
+        min_score: rename::DEFAULT_SCORE,
+        limit: rename::DEFAULT_LIMIT,
+    };
+    let pairs = rename::detect(pairs, &opts)?;
+    write_shortstat(&mut out, &pairs, &PatchOptions::default())?;
+    write_summary(&mut out, &pairs)?;
+    Ok(())
+}
+
+/// Resets the index and the files the replay touched to `commit`, keeping other local changes,
+/// as `reset --merge` does
+fn reset_merge(commit: &[u8; 20]) -> anyhow::Result<()> {
+    let tree = Commit::read(commit)?.tree;
+    let mut index = Index::read()?;
+    let opts = UnpackOptions {
+        update: true,
+        overwrite_unmerged: true,
+        ..UnpackOptions::default()
+    };
+    unpack::one_way(&mut index, Some(&tree), opts)?;
+    index.write()?;
+    if let Some(old) = refs::head_commit()? {
+        refs::update_ref("ORIG_HEAD", &old, "updating ORIG_HEAD")?;
+    }
+    let hex = hex::encode(commit);
+    refs::update_ref("HEAD", commit, &format!("reset: moving to {hex}"))?;
+    refs::clear_merge_state()
+}
+
+/// Whether HEAD is still where the sequence left it, so that going back won't throw away
+/// commits made since
+fn rollback_is_safe() -> anyhow::Result<bool> {
+    let expected = match fs::read_to_string(Path::new(SEQUENCER).join("abort-safety")) {
+        Ok(content) => Some(revision::resolve(content.trim())?),
+        Err(_) => None,
+    };
+    Ok(refs::head_commit()? == expected)
+}
+
+fn write_abort_safety() -> anyhow::Result<()> {
+    let head = refs::head_commit()?.context("You do not have a valid HEAD.")?;
+    fs::write(
+        Path::new(SEQUENCER).join("abort-safety"),
+        format!("{}\n", hex::encode(head)),
+    )
+    .context("Writing the sequencer abort safety file")
+}
+
+/// The action of the sequence in progress, told by the first step of its todo list
+fn last_command() -> anyhow::Result<Option<Action>> {
+    let Ok(todo) = fs::read_to_string(Path::new(SEQUENCER).join("todo")) else {
+        return Ok(None);
+    };
+    Ok(match todo.split_whitespace().next() {
+        Some("pick") => Some(Action::Pick),
+        Some("revert") => Some(Action::Revert),
+        _ => None,
+    })
+}
+
+fn read_head_file(action: Action) -> anyhow::Result<Option<[u8; 20]>> {
+    match fs::read_to_string(action.head_file()) {
+        Ok(content) => Ok(Some(revision::resolve(content.trim())?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Writes the steps left to do, one `pick <commit> <subject>` line each
+fn write_todo(steps: &[Step]) -> anyhow::Result<()> {
+    let mut todo = String::new();
+    for step in steps {
+        let commit = Commit::read(&step.commit)?;
+        todo.push_str(&format!(
+            "{} {} {}\n",
+            step.action.todo_word(),
+            abbrev(&step.commit),
+            commit.summary()
+        ));
+    }
+    fs::write(Path::new(SEQUENCER).join("todo"), todo).context("Writing the sequencer todo list")
+}
+
+fn read_todo() -> anyhow::Result<Vec<Step>> {
+    let todo = fs::read_to_string(Path::new(SEQUENCER).join("todo"))
+        .context("Reading the sequencer todo list")?;
+    let mut steps = Vec::new();
+    for line in todo.lines() {
+        let mut words = line.split_whitespace();
+        let (Some(word), Some(commit)) = (words.next(), words.next()) else {
+            continue;
+        };
+        let action = match word {
+            "pick" | "p" => Action::Pick,
+            "revert" => Action::Revert,
+            _ => anyhow::bail!("invalid line in the sequencer todo list: {line}"),
+        };
+        let commit =
+            revision::resolve(commit).with_context(|| format!("could not parse '{commit}'"))?;
+        steps.push(Step { action, commit });
+    }
+    Ok(steps)
+}
+
+/// Records the options differing from the defaults, in git's config format
+fn write_options(opts: &ReplayOptions) -> anyhow::Result<()> {
+    let mut options = String::new();
+    if opts.no_commit {
+        options.push_str("\tno-commit = true\n");
+    }
+    if opts.record_origin {
+        options.push_str("\trecord-origin = true\n");
+    }
+    if opts.edit {
+        options.push_str("\tedit = true\n");
+    }
+    if let Some(mainline) = opts.mainline {
+        options.push_str(&format!("\tmainline = {mainline}\n"));
+    }
+    if options.is_empty() {
+        return Ok(());
+    }
+    fs::write(
+        Path::new(SEQUENCER).join("opts"),
+        format!("[options]\n{options}"),
+    )
+    .context("Writing the sequencer options")
+}
+
+fn read_options() -> anyhow::Result<ReplayOptions> {
+    let mut opts = ReplayOptions::default();
+    let Ok(content) = fs::read_to_string(Path::new(SEQUENCER).join("opts")) else {
+        return Ok(opts);
+    };
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "no-commit" => opts.no_commit = value == "true",
+            "record-origin" => opts.record_origin = value == "true",
+            "edit" => opts.edit = value == "true",
+            "mainline" => {
+                opts.mainline = Some(
+                    value
+                        .parse()
+                        .context("Invalid mainline in sequencer options")?,
+                )
+            }
+            _ => {}
+        }
+    }
+    Ok(opts)
+}
+
+/// Refuses to replay while conflicts from before are unresolved
+fn check_unmerged(action: Action) -> anyhow::Result<()> {
+    if !Index::read()?.has_conflicts() {
+        return Ok(());
+    }
+    let doing = match action {
+        Action::Pick => "Cherry-picking",
+        Action::Revert => "Reverting",
+    };
+    Err(fail(
+        action,
+        &format!("{doing} is not possible because you have unmerged files."),
+        RESOLVE_HINT,
+    ))
+}
+
+/// Refuses to replay onto an index with changes of its own, which the commit would take along
+fn dirty_index(action: Action) -> anyhow::Error {
+    fail(
+        action,
+        &format!(
+            "your local changes would be overwritten by {}.",
+            action.command()
+        ),
+        &["commit your changes or stash them to proceed."],
+    )
+}
+
+/// Reports why the replay can't go on, with advice, leaving the command to fail
+fn fail(action: Action, message: &str, hints: &[&str]) -> anyhow::Error {
+    eprintln!("error: {message}");
+    for hint in hints {
+        eprintln!("hint: {hint}");
+    }
+    anyhow::anyhow!("{} failed", action.command())
+}
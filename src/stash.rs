@@ -0,0 +1,442 @@
+//! Putting local changes aside and bringing them back, as `git stash` does.
+//!
+//! A stash entry is a merge commit whose tree is the working tree (W). Its first parent is the
+//! commit HEAD was at, its second a commit of the index (I) and, when untracked files were
+//! stashed too, its third a parentless commit of just those files (U). Entries are kept in the
+//! reflog of `refs/stash`, newest first, so that `stash@{<n>}` names the `n`th newest one.
+
+use std::{collections::BTreeMap, fs, io::Write, path::Path};
+
+use anyhow::Context;
+
+use crate::{
+    checkout::{self, PathOptions},
+    diff::patch::abbrev,
+    index::{mode_from_metadata, Index, IndexEntry},
+    merge::{
+        self,
+        content::{ConflictStyle, FileOptions, Labels},
+        octopus::index_tree,
+        MergeOptions,
+    },
+    object::{
+        commit::Commit,
+        write::{hash_object, write_commit, write_tree_from_paths},
+        ObjectKind,
+    },
+    pathspec::Pathspec,
+    refs::{self, Head},
+    revision,
+    status::{self, UntrackedMode},
+    unpack::{self, tree_blobs, UnpackOptions},
+    worktree,
+};
+
+pub(crate) const STASH_REF: &str = "refs/stash";
+
+/// How [`push`] saves local changes
+#[derive(Debug, Default)]
+pub(crate) struct PushOptions {
+    /// Only stash changes to these paths, leaving the others in place
+    pub(crate) paths: Vec<String>,
+    pub(crate) include_untracked: bool,
+    /// Leave the staged changes in the index and working tree after stashing
+    pub(crate) keep_index: bool,
+    pub(crate) message: Option<String>,
+    pub(crate) quiet: bool,
+}
+
+/// A stash entry found by [`resolve`]
+#[derive(Debug)]
+pub(crate) struct Stash {
+    /// The revision as given, `refs/stash@{0}` by default
+    pub(crate) name: String,
+    pub(crate) commit: [u8; 20],
+    /// The commit HEAD was at when stashing
+    pub(crate) base: [u8; 20],
+    pub(crate) index: [u8; 20],
+    pub(crate) untracked: Option<[u8; 20]>,
+    /// Position in the reflog of `refs/stash`, if the revision names one
+    pub(crate) entry: Option<usize>,
+}
+
+/// Whether [`apply`] went through; failures have been reported already
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Applied,
+    Failed,
+}
+
+/// Saves the local changes as a new stash entry and resets them away. Returns `false` when
+/// there was nothing to stash.
+pub(crate) fn push(opts: &PushOptions) -> anyhow::Result<bool> {
+    let head = refs::head_commit()?.context("You do not have the initial commit yet")?;
+    let head_commit = Commit::read(&head)?;
+    let mut index = Index::read()?;
+    anyhow::ensure!(
+        !index.has_conflicts(),
+        "Cannot save the current index state"
+    );
+    let pathspec = Pathspec::new(&opts.paths);
+    let untracked_mode = if opts.include_untracked {
+        UntrackedMode::All
+    } else {
+        UntrackedMode::No
+    };
+    let report = status::compute(&mut index, Some(&head_commit.tree), untracked_mode)?;
+    if report.index_refreshed {
+        index.write()?;
+    }
+
+    let untracked: Vec<&String> = report
+        .untracked
+        .iter()
+        .filter(|path| pathspec.matches(path))
+        .collect();
+    let unmatched = pathspec.unmatched(
+        index
+            .entries()
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .chain(report.untracked.iter().map(String::as_str)),
+    );
+    if !unmatched.is_empty() {
+        let errors: Vec<String> = unmatched
+            .iter()
+            .map(|pattern| format!("pathspec '{pattern}' did not match any file(s) known to git"))
+            .collect();
+        anyhow::bail!("{}\nDid you forget to 'git add'?", errors.join("\n"));
+    }
+    let changes: Vec<&status::PathStatus> = report
+        .changes
+        .iter()
+        .filter(|change| pathspec.matches(&change.path))
+        .collect();
+    if changes.is_empty() && untracked.is_empty() {
+        if !opts.quiet {
+            println!("No local changes to save");
+        }
+        return Ok(false);
+    }
+
+    let branch = match refs::read_head()? {
+        Head::Symbolic(target) => refs::shorten(&target).to_string(),
+        Head::Detached(_) => String::from("(no branch)"),
+    };
+    let on = format!("{branch}: {} {}", abbrev(&head), head_commit.summary());
+    let index_tree = index_tree(&index)?.context("Cannot save the current index state")?;
+    let index_commit = write_commit(&index_tree, &[head], &format!("index on {on}\n"))?;
+
+    let mut parents = vec![head, index_commit];
+    if !untracked.is_empty() {
+        let mut files = BTreeMap::new();
+        for path in &untracked {
+            files.insert(
+                path.to_string(),
+                worktree_blob(path)?.with_context(|| {
+                    format!("Cannot save the untracked files: {path} is missing")
+                })?,
+            );
+        }
+        let tree = write_tree_from_paths(&files)?;
+        parents.push(write_commit(
+            &tree,
+            &[],
+            &format!("untracked files on {on}\n"),
+        )?);
+    }
+
+    let mut files: BTreeMap<String, (u32, [u8; 20])> = index
+        .entries()
+        .iter()
+        .map(|entry| (entry.path.clone(), (entry.mode, entry.hash)))
+        .collect();
+    for change in changes.iter().filter(|change| change.unstaged.is_some()) {
+        match worktree_blob(&change.path)? {
+            Some(blob) => files.insert(change.path.clone(), blob),
+            None => files.remove(&change.path),
+        };
+    }
+    let worktree_tree = write_tree_from_paths(&files)?;
+    let message = match &opts.message {
+        Some(message) => format!("On {branch}: {message}"),
+        None => format!("WIP on {on}"),
+    };
+    let stash = write_commit(&worktree_tree, &parents, &message)?;
+    refs::update_ref(STASH_REF, &stash, &message)?;
+    if !opts.quiet {
+        println!("Saved working directory and index state {message}");
+    }
+
+    if opts.paths.is_empty() {
+        let reset = UnpackOptions {
+            update: true,
+            reset: true,
+            ..UnpackOptions::default()
+        };
+        unpack::one_way(&mut index, Some(&head_commit.tree), reset)?;
+        index.write()?;
+        refs::update_ref("ORIG_HEAD", &head, "updating ORIG_HEAD")?;
+        refs::update_ref("HEAD", &head, "reset: moving to HEAD")?;
+    } else {
+        checkout::checkout_paths(&mut index, Some(&head_commit.tree), &pathspec, RESET_PATHS)?;
+        index.write()?;
+    }
+    for path in untracked {
+        worktree::remove_file(path)?;
+    }
+    if opts.keep_index {
+        checkout::checkout_paths(&mut index, Some(&index_tree), &pathspec, RESET_PATHS)?;
+        index.write()?;
+    }
+    Ok(true)
+}
+
+/// Checking out paths the way `checkout --no-overlay <tree> -- <paths>` does
+const RESET_PATHS: PathOptions = PathOptions {
+    staged: true,
+    worktree: true,
+    overlay: false,
+};
+
+/// Stores a working tree file as a blob, `None` if it is missing
+fn worktree_blob(path: &str) -> anyhow::Result<Option<(u32, [u8; 20])>> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(None);
+    };
+    if metadata.is_dir() {
+        return Ok(None);
+    }
+    let content = worktree::read_file(Path::new(path))?;
+    let hash = hash_object(ObjectKind::Blob, &content, true)?;
+    Ok(Some((mode_from_metadata(&metadata), hash)))
+}
+
+/// Finds the stash entry a revision names: the newest one without a revision, `stash@{<n>}` for
+/// a number, and otherwise any commit that looks like a stash
+pub(crate) fn resolve(revision: Option<&str>) -> anyhow::Result<Stash> {
+    let name = match revision {
+        None => {
+            anyhow::ensure!(
+                refs::read_ref(STASH_REF)?.is_some(),
+                "No stash entries found."
+            );
+            format!("{STASH_REF}@{{0}}")
+        }
+        Some(n) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => {
+            format!("{STASH_REF}@{{{n}}}")
+        }
+        Some(name) => name.to_string(),
+    };
+    let commit = match revision::resolve(&name) {
+        Ok(hash) => hash,
+        Err(err) if err.to_string().starts_with("log for ") => return Err(err),
+        Err(_) => anyhow::bail!("{name} is not a valid reference"),
+    };
+    let stash = Commit::read(&commit)
+        .ok()
+        .filter(|stash| stash.parents.len() >= 2)
+        .with_context(|| format!("'{name}' is not a stash-like commit"))?;
+
+    let entry = match name
+        .strip_suffix('}')
+        .and_then(|rest| rest.rsplit_once("@{"))
+    {
+        Some((ref_name, n)) => {
+            let is_stash = match ref_name {
+                "" => false,
+                ref_name => refs::expand_ref(ref_name)?.as_deref() == Some(STASH_REF),
+            };
+            n.parse().ok().filter(|_| is_stash)
+        }
+        None => None,
+    };
+    Ok(Stash {
+        name,
+        commit,
+        base: stash.parents[0],
+        index: stash.parents[1],
+        untracked: stash.parents.get(2).copied(),
+        entry,
+    })
+}
+
+/// Merges the changes of a stash entry into the working tree, and with `restore_index` its
+/// staged changes into the index, then brings its untracked files back. The status is shown
+/// afterwards unless `quiet`.
+pub(crate) fn apply(stash: &Stash, restore_index: bool, quiet: bool) -> anyhow::Result<Outcome> {
+    let mut index = Index::read()?;
+    let head_tree = match refs::head_commit()? {
+        Some(head) => Some(Commit::read(&head)?.tree),
+        None => None,
+    };
+    let report = status::compute(&mut index, head_tree.as_ref(), UntrackedMode::No)?;
+    if report.index_refreshed {
+        index.write()?;
+    }
+    let current = match index_tree(&index)? {
+        Some(tree) => tree,
+        None => {
+            eprintln!("error: cannot apply a stash in the middle of a merge");
+            return Ok(Outcome::Failed);
+        }
+    };
+    let base_tree = Commit::read(&stash.base)?.tree;
+    let index_tree_of_stash = Commit::read(&stash.index)?.tree;
+    let stash_tree = Commit::read(&stash.commit)?.tree;
+    let style = ConflictStyle::configured()?;
+
+    let staged =
+        if restore_index && index_tree_of_stash != base_tree && index_tree_of_stash != current {
+            let opts = MergeOptions {
+                labels: Labels {
+                    ours: "Updated upstream",
+                    base: "Stash base",
+                    theirs: "Stashed changes",
+                },
+                content: FileOptions {
+                    style,
+                    ..FileOptions::default()
+                },
+                subtree_shift: None,
+            };
+            let result = merge::merge_tree_changes(
+                Some(&base_tree),
+                &current,
+                Some(&index_tree_of_stash),
+                opts,
+            )?;
+            if !result.is_clean() {
+                eprintln!("error: conflicts in index. Try without --index.");
+                return Ok(Outcome::Failed);
+            }
+            Some(result.tree)
+        } else {
+            None
+        };
+
+    let opts = MergeOptions {
+        labels: Labels {
+            ours: if base_tree == current {
+                "Version stash was based on"
+            } else {
+                "Updated upstream"
+            },
+            base: "Stash base",
+            theirs: "Stashed changes",
+        },
+        content: FileOptions {
+            style,
+            ..FileOptions::default()
+        },
+        subtree_shift: None,
+    };
+    let result = merge::merge_tree_changes(Some(&base_tree), &current, Some(&stash_tree), opts)?;
+    let mut outcome = Outcome::Applied;
+    match merge::checkout(&mut index, Some(&current), &result) {
+        Err(err) => {
+            eprintln!("error: {err}");
+            outcome = Outcome::Failed;
+        }
+        Ok(()) => {
+            fs::write(".git/AUTO_MERGE", format!("{}\n", hex::encode(result.tree)))
+                .context("Writing AUTO_MERGE")?;
+            if !quiet {
+                let mut out = std::io::stdout().lock();
+                for line in &result.messages {
+                    writeln!(out, "{line}")?;
+                }
+            }
+            if !result.is_clean() {
+                index.write()?;
+                if restore_index {
+                    eprintln!("Index was not unstashed.");
+                }
+                outcome = Outcome::Failed;
+            } else {
+                match staged {
+                    Some(tree) => unpack::one_way(
+                        &mut index,
+                        Some(&tree),
+                        UnpackOptions {
+                            reset: true,
+                            ..UnpackOptions::default()
+                        },
+                    )?,
+                    None => unstage_changes_unless_new(&mut index, &current)?,
+                }
+                index.write()?;
+            }
+        }
+    }
+
+    if let Some(untracked) = stash.untracked {
+        if !restore_untracked(&untracked)? {
+            eprintln!("error: could not restore untracked files from stash");
+            outcome = Outcome::Failed;
+        }
+    }
+    if !quiet {
+        std::io::stdout().flush()?;
+        crate::commands::status::invoke(false, false, None, false, String::from("normal"))?;
+    }
+    Ok(outcome)
+}
+
+/// Resets the index to `tree` after a merge, except that files the merge added stay added so
+/// that they don't turn into untracked files
+fn unstage_changes_unless_new(index: &mut Index, tree: &[u8; 20]) -> anyhow::Result<()> {
+    let blobs = tree_blobs(Some(tree))?;
+    let added: Vec<IndexEntry> = index
+        .entries()
+        .iter()
+        .filter(|entry| !blobs.contains_key(&entry.path))
+        .cloned()
+        .collect();
+    let opts = UnpackOptions {
+        reset: true,
+        ..UnpackOptions::default()
+    };
+    unpack::one_way(index, Some(tree), opts)?;
+    for entry in added {
+        index.add(entry);
+    }
+    Ok(())
+}
+
+/// Writes the files of an untracked files commit to the working tree, leaving files that
+/// exist already alone. Returns whether all of them could be written.
+fn restore_untracked(commit: &[u8; 20]) -> anyhow::Result<bool> {
+    let tree = Commit::read(commit)?.tree;
+    let mut restored = true;
+    for (path, (mode, hash)) in tree_blobs(Some(&tree))? {
+        if fs::symlink_metadata(&path).is_ok() {
+            eprintln!("{path} already exists, no checkout");
+            restored = false;
+            continue;
+        }
+        worktree::write_file(&path, mode, &hash)?;
+    }
+    Ok(restored)
+}
+
+/// Removes a stash entry from the stack, printing which one unless `quiet`
+pub(crate) fn drop(stash: &Stash, quiet: bool) -> anyhow::Result<()> {
+    let Some(entry) = stash.entry else {
+        anyhow::bail!("'{}' is not a stash reference", stash.name);
+    };
+    refs::drop_reflog_entry(STASH_REF, entry)?;
+    if !quiet {
+        println!("Dropped {} ({})", stash.name, hex::encode(stash.commit));
+    }
+    Ok(())
+}
+
+/// The messages of the stash entries, newest first
+pub(crate) fn list() -> anyhow::Result<Vec<String>> {
+    Ok(refs::read_reflog(STASH_REF)?
+        .into_iter()
+        .rev()
+        .map(|entry| entry.message)
+        .collect())
+}
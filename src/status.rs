@@ -0,0 +1,344 @@
+//! Comparison of HEAD, the index and the working tree.
+
+use std::{collections::BTreeMap, fs};
+
+use ignore::WalkBuilder;
+
+use crate::{
+    config,
+    diff::{
+        patch::{FilePair, FileSide},
+        rename::{self, RenameOptions},
+    },
+    index::{mode_from_metadata, Index},
+    object::MODE_GITLINK,
+    unpack::{tree_blobs, Blob},
+};
+
+/// How a path differs between two of HEAD, index and working tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChangeKind {
+    Added,
+    Deleted,
+    Modified,
+    TypeChanged,
+    /// Added in place of a path deleted from HEAD, see [`StatusReport::detect_renames`]
+    Renamed,
+}
+
+impl ChangeKind {
+    pub(crate) fn letter(self) -> char {
+        match self {
+            ChangeKind::Added => 'A',
+            ChangeKind::Deleted => 'D',
+            ChangeKind::Modified => 'M',
+            ChangeKind::TypeChanged => 'T',
+            ChangeKind::Renamed => 'R',
+        }
+    }
+
+    fn between(old: Option<Blob>, new: Option<Blob>) -> Option<ChangeKind> {
+        match (old, new) {
+            (None, None) => None,
+            (None, Some(_)) => Some(ChangeKind::Added),
+            (Some(_), None) => Some(ChangeKind::Deleted),
+            (Some(old), Some(new)) if old == new => None,
+            (Some((old_mode, _)), Some((new_mode, _))) if old_mode >> 12 != new_mode >> 12 => {
+                Some(ChangeKind::TypeChanged)
+            }
+            _ => Some(ChangeKind::Modified),
+        }
+    }
+}
+
+/// A tracked path that differs between HEAD and the index, or the index and the working tree
+#[derive(Debug, Clone)]
+pub(crate) struct PathStatus {
+    pub(crate) path: String,
+    pub(crate) head: Option<Blob>,
+    pub(crate) index: Option<Blob>,
+    /// Mode of the working tree file, `None` if it is missing
+    pub(crate) worktree: Option<u32>,
+    pub(crate) staged: Option<ChangeKind>,
+    pub(crate) unstaged: Option<ChangeKind>,
+    /// Path in HEAD a staged rename came from, with the similarity percentage
+    pub(crate) source: Option<(String, u32)>,
+}
+
+/// A path with conflict stages in the index
+#[derive(Debug, Clone)]
+pub(crate) struct Unmerged {
+    pub(crate) path: String,
+    /// Stages 1 (base), 2 (ours) and 3 (theirs)
+    pub(crate) stages: [Option<Blob>; 3],
+    pub(crate) worktree: Option<u32>,
+}
+
+impl Unmerged {
+    /// Two-letter short status code, e.g. `UU` or `AA`
+    pub(crate) fn code(&self) -> &'static str {
+        match self.stages.map(|stage| stage.is_some()) {
+            [true, false, false] => "DD",
+            [false, true, false] => "AU",
+            [true, true, false] => "UD",
+            [false, false, true] => "UA",
+            [true, false, true] => "DU",
+            [false, true, true] => "AA",
+            _ => "UU",
+        }
+    }
+
+    pub(crate) fn description(&self) -> &'static str {
+        match self.code() {
+            "DD" => "both deleted:",
+            "AU" => "added by us:",
+            "UD" => "deleted by them:",
+            "UA" => "added by them:",
+            "DU" => "deleted by us:",
+            "AA" => "both added:",
+            _ => "both modified:",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UntrackedMode {
+    No,
+    /// Directories with no tracked files are listed as `dir/`
+    Normal,
+    All,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct StatusReport {
+    pub(crate) changes: Vec<PathStatus>,
+    pub(crate) unmerged: Vec<Unmerged>,
+    pub(crate) untracked: Vec<String>,
+    /// Whether stat information of clean entries was refreshed in the index
+    pub(crate) index_refreshed: bool,
+}
+
+impl StatusReport {
+    pub(crate) fn has_staged(&self) -> bool {
+        self.changes.iter().any(|change| change.staged.is_some())
+    }
+
+    pub(crate) fn has_unstaged(&self) -> bool {
+        self.changes.iter().any(|change| change.unstaged.is_some())
+    }
+
+    /// Pairs paths added to the index with ones deleted from HEAD, as `status.renames` (by
+    /// default `diff.renames`) asks. A renamed path takes over the HEAD side of its source, which
+    /// is no longer listed.
+    pub(crate) fn detect_renames(&mut self) -> anyhow::Result<()> {
+        let config = config::read_repo_config();
+        let setting = config
+            .get("status.renames")
+            .or_else(|| config.get("diff.renames"));
+        if setting.is_some_and(|value| {
+            matches!(
+                value.to_ascii_lowercase().as_str(),
+                "false" | "no" | "off" | "0"
+            )
+        }) {
+            return Ok(());
+        }
+        let limit = config
+            .get("status.renameLimit")
+            .or_else(|| config.get("diff.renameLimit"))
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(rename::DEFAULT_LIMIT);
+
+        let side = |path: &str, blob: Option<Blob>| {
+            blob.map(|(mode, hash)| FileSide::from_object(path, mode, hash))
+        };
+        let pairs: Vec<FilePair> = self
+            .changes
+            .iter()
+            .filter(|change| matches!(change.staged, Some(ChangeKind::Added | ChangeKind::Deleted)))
+            .map(|change| {
+                FilePair::new(
+                    side(&change.path, change.head),
+                    side(&change.path, change.index),
+                )
+            })
+            .collect();
+        let opts = RenameOptions {
+            copies: false,
+            min_score: rename::DEFAULT_SCORE,
+            limit,
+        };
+        for pair in rename::detect(pairs, &opts)? {
+            let (Some(old), Some(new), Some(similarity)) =
+                (&pair.old, &pair.new, pair.similarity())
+            else {
+                continue;
+            };
+            self.changes.retain(|change| change.path != old.path);
+            if let Some(change) = self
+                .changes
+                .iter_mut()
+                .find(|change| change.path == new.path)
+            {
+                change.head = Some((old.mode, old.hash));
+                change.staged = Some(ChangeKind::Renamed);
+                change.source = Some((old.path.clone(), similarity));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Computes the status of the repository against `head` (a tree, `None` before the first commit).
+/// Entries whose stat info was refreshed are updated in `index` so the caller can write it back.
+pub(crate) fn compute(
+    index: &mut Index,
+    head: Option<&[u8; 20]>,
+    untracked: UntrackedMode,
+) -> anyhow::Result<StatusReport> {
+    let head = tree_blobs(head)?;
+    let mut paths: BTreeMap<String, PathStatus> = BTreeMap::new();
+    let mut report = StatusReport::default();
+
+    for (path, blob) in &head {
+        paths.insert(
+            path.clone(),
+            PathStatus {
+                path: path.clone(),
+                head: Some(*blob),
+                index: None,
+                worktree: None,
+                staged: None,
+                unstaged: None,
+                source: None,
+            },
+        );
+    }
+
+    let mut refreshed = Vec::new();
+    let mut unmerged: BTreeMap<String, [Option<Blob>; 3]> = BTreeMap::new();
+    for entry in index.entries() {
+        if entry.stage != 0 {
+            unmerged.entry(entry.path.clone()).or_default()[entry.stage as usize - 1] =
+                Some((entry.mode, entry.hash));
+            continue;
+        }
+        let metadata = fs::symlink_metadata(&entry.path)
+            .ok()
+            .filter(|m| !m.is_dir());
+        let worktree_mode = metadata.as_ref().map(mode_from_metadata);
+        let unstaged = match &metadata {
+            _ if entry.mode == MODE_GITLINK => None,
+            None => Some(ChangeKind::Deleted),
+            Some(metadata) => {
+                let mode = mode_from_metadata(metadata);
+                if mode >> 12 != entry.mode >> 12 {
+                    Some(ChangeKind::TypeChanged)
+                } else if entry.stat_matches(metadata) && !index.is_racy(entry) {
+                    None
+                } else if mode == entry.mode && index.matches_worktree(entry)? {
+                    refreshed.push((entry.path.clone(), metadata.clone()));
+                    None
+                } else {
+                    Some(ChangeKind::Modified)
+                }
+            }
+        };
+        let status = paths.entry(entry.path.clone()).or_insert(PathStatus {
+            path: entry.path.clone(),
+            head: None,
+            index: None,
+            worktree: None,
+            staged: None,
+            unstaged: None,
+            source: None,
+        });
+        status.index = Some((entry.mode, entry.hash));
+        status.worktree = worktree_mode;
+        status.unstaged = unstaged;
+    }
+
+    report.index_refreshed = !refreshed.is_empty();
+    for (path, metadata) in refreshed {
+        if let Some(entry) = index.get_mut(&path, 0) {
+            entry.update_stat(&metadata);
+        }
+    }
+
+    for (path, stages) in unmerged {
+        paths.remove(&path);
+        let worktree = fs::symlink_metadata(&path)
+            .ok()
+            .map(|metadata| mode_from_metadata(&metadata));
+        report.unmerged.push(Unmerged {
+            path,
+            stages,
+            worktree,
+        });
+    }
+
+    for (_, mut status) in paths {
+        status.staged = ChangeKind::between(status.head, status.index);
+        if status.index.is_none() {
+            // Deleted from the index: the working tree copy, if any, is untracked
+            status.worktree = None;
+        }
+        if status.staged.is_some() || status.unstaged.is_some() {
+            report.changes.push(status);
+        }
+    }
+
+    if untracked != UntrackedMode::No {
+        report.untracked = untracked_files(index, untracked)?;
+    }
+    Ok(report)
+}
+
+/// Lists files that are neither tracked nor ignored, with `.gitignore` rules applied the same
+/// way as `write-tree`
+fn untracked_files(index: &Index, mode: UntrackedMode) -> anyhow::Result<Vec<String>> {
+    let tracked_dirs: std::collections::HashSet<&str> = index
+        .entries()
+        .iter()
+        .flat_map(|entry| entry.path.match_indices('/').map(|(i, _)| &entry.path[..i]))
+        .collect();
+
+    let mut untracked = Vec::new();
+    for entry in WalkBuilder::new("./")
+        .standard_filters(false)
+        .hidden(false)
+        .parents(false)
+        .ignore(false)
+        .git_exclude(true)
+        .git_ignore(true)
+        .git_global(true)
+        .filter_entry(|path| path.file_name() != ".git")
+        .build()
+    {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if entry.file_type().is_none_or(|file_type| file_type.is_dir()) {
+            continue;
+        }
+        let path = entry.path().strip_prefix("./").unwrap_or(entry.path());
+        let Some(path) = path.to_str() else {
+            continue;
+        };
+        if !index.stages(path).is_empty() {
+            continue;
+        }
+        let shown = match mode {
+            UntrackedMode::Normal => path
+                .match_indices('/')
+                .map(|(i, _)| &path[..i])
+                .find(|dir| !tracked_dirs.contains(dir))
+                .map_or_else(|| path.to_string(), |dir| format!("{dir}/")),
+            _ => path.to_string(),
+        };
+        untracked.push(shown);
+    }
+    untracked.sort();
+    untracked.dedup();
+    Ok(untracked)
+}
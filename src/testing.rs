@@ -0,0 +1,72 @@
+//! Scratch repositories for the unit tests of code that works on the repository in the current
+//! directory.
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+use crate::object::{
+    write::{hash_object, write_tree_from_paths},
+    ObjectKind, MODE_FILE,
+};
+
+/// The working directory is shared by the whole process, so tests that change it take turns
+static CWD: Mutex<()> = Mutex::new(());
+static NEXT_REPO: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs `test` in a new empty repository, which is removed afterwards
+pub(crate) fn in_repo(test: impl FnOnce() -> anyhow::Result<()>) {
+    let _turn = CWD.lock().unwrap_or_else(PoisonError::into_inner);
+    let dir: PathBuf = env::temp_dir().join(format!(
+        "git-rust-test-{}-{}",
+        std::process::id(),
+        NEXT_REPO.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(dir.join(".git/objects")).expect("creating the test repository");
+    fs::create_dir_all(dir.join(".git/refs/heads")).expect("creating the test repository");
+    fs::write(dir.join(".git/HEAD"), "ref: refs/heads/main\n").expect("writing HEAD");
+
+    let previous = env::current_dir().expect("reading the working directory");
+    env::set_current_dir(&dir).expect("entering the test repository");
+    let result = panic::catch_unwind(AssertUnwindSafe(test));
+    env::set_current_dir(previous).expect("leaving the test repository");
+    let _ = fs::remove_dir_all(&dir);
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => panic!("{err:?}"),
+        Err(panic) => panic::resume_unwind(panic),
+    }
+}
+
+/// Stores `content` as a blob
+pub(crate) fn blob(content: &str) -> [u8; 20] {
+    hash_object(ObjectKind::Blob, content.as_bytes(), true).expect("writing a blob")
+}
+
+/// Stores a tree of regular files, given as paths and contents
+pub(crate) fn tree(files: &[(&str, &str)]) -> [u8; 20] {
+    let files: BTreeMap<String, (u32, [u8; 20])> = files
+        .iter()
+        .map(|(path, content)| (path.to_string(), (MODE_FILE, blob(content))))
+        .collect();
+    write_tree_from_paths(&files).expect("writing a tree")
+}
+
+/// Stores a commit of `tree`, with a fixed author and committer so that ids don't change
+pub(crate) fn commit(tree: &[u8; 20], parents: &[[u8; 20]], message: &str) -> [u8; 20] {
+    let mut content = format!("tree {}\n", hex::encode(tree));
+    for parent in parents {
+        content.push_str(&format!("parent {}\n", hex::encode(parent)));
+    }
+    content.push_str("author A U Thor <author@example.com> 1112912053 -0700\n");
+    content.push_str("committer C O Mitter <committer@example.com> 1112912053 -0700\n");
+    content.push_str(&format!("\n{message}\n"));
+    hash_object(ObjectKind::Commit, content.as_bytes(), true).expect("writing a commit")
+}
@@ -0,0 +1,513 @@
+//! Loading trees into the index: the one-, two- and three-way merges behind `read-tree -m`,
+//! which checkout and merge build on.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use anyhow::Context;
+
+use crate::{
+    index::{Index, IndexEntry},
+    object::read::flatten_tree,
+};
+
+/// Mode and object id of a path in a tree or index
+pub(crate) type Blob = (u32, [u8; 20]);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct UnpackOptions {
+    /// Update the working tree to match the new index
+    pub(crate) update: bool,
+    /// Discard unmerged entries and local changes instead of failing
+    pub(crate) reset: bool,
+    /// Phrase errors for switching branches rather than for merging
+    pub(crate) switching: bool,
+    /// Phrase errors for `git merge`, which tells how to get out of the way
+    pub(crate) merging: bool,
+    /// Replace unmerged paths with the result instead of refusing to start, while still
+    /// protecting local changes elsewhere
+    pub(crate) overwrite_unmerged: bool,
+    /// Also resolve paths deleted on both sides, or deleted on one and unchanged on the
+    /// other, in a three-way merge, as `read-tree --aggressive` does
+    pub(crate) aggressive: bool,
+}
+
+/// What happens to a single path when unpacking trees
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    /// Leave the index entry (if any) as it is
+    Keep,
+    /// Replace the index entry with a merged entry
+    Use(Blob),
+    Remove,
+    /// Record conflict stages 1, 2 and 3
+    Conflict([Option<Blob>; 3]),
+}
+
+/// Flattens a tree into a map from path to blob
+pub(crate) fn tree_blobs(tree: Option<&[u8; 20]>) -> anyhow::Result<BTreeMap<String, Blob>> {
+    let Some(tree) = tree else {
+        return Ok(BTreeMap::new());
+    };
+    Ok(flatten_tree(tree, "")?
+        .into_iter()
+        .map(|item| (item.name, (item.mode, item.hash)))
+        .collect())
+}
+
+fn index_blob(index: &Index, path: &str) -> Option<Blob> {
+    index.get(path, 0).map(|entry| (entry.mode, entry.hash))
+}
+
+/// One-way merge: the index becomes `tree`, keeping cached stat information for unchanged entries
+pub(crate) fn one_way(
+    index: &mut Index,
+    tree: Option<&[u8; 20]>,
+    opts: UnpackOptions,
+) -> anyhow::Result<()> {
+    let target = tree_blobs(tree)?;
+    let paths = all_paths(index, [&target]);
+    let mut outcomes = BTreeMap::new();
+    for path in paths {
+        let outcome = match target.get(&path) {
+            Some(blob) => Outcome::Use(*blob),
+            None => Outcome::Remove,
+        };
+        outcomes.insert(path, outcome);
+    }
+    apply(index, outcomes, opts)
+}
+
+/// Two-way merge moving the index from `head` to `merge`, carrying local changes over when they
+/// don't touch paths that differ between the two trees
+pub(crate) fn two_way(
+    index: &mut Index,
+    head: Option<&[u8; 20]>,
+    merge: Option<&[u8; 20]>,
+    opts: UnpackOptions,
+) -> anyhow::Result<()> {
+    if opts.reset {
+        return one_way(index, merge, opts);
+    }
+    let head = tree_blobs(head)?;
+    let merge = tree_blobs(merge)?;
+    let paths = all_paths(index, [&head, &merge]);
+    let mut outcomes = BTreeMap::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        let i = index_blob(index, &path);
+        let h = head.get(&path).copied();
+        let m = merge.get(&path).copied();
+        let outcome = match (i, h, m) {
+            (None, None, Some(m)) => Some(Outcome::Use(m)),
+            (None, Some(_), None) => Some(Outcome::Remove),
+            (None, Some(h), Some(m)) if h == m => Some(Outcome::Keep),
+            (None, Some(_), Some(_)) => None,
+            (Some(_), None, None) => Some(Outcome::Keep),
+            (Some(i), None, Some(m)) if i == m => Some(Outcome::Keep),
+            (Some(_), None, Some(_)) => None,
+            (Some(i), Some(h), None) if i == h => Some(Outcome::Remove),
+            (Some(_), Some(_), None) => None,
+            (Some(_), Some(h), Some(m)) if h == m => Some(Outcome::Keep),
+            (Some(i), Some(_), Some(m)) if i == m => Some(Outcome::Keep),
+            (Some(i), Some(h), Some(m)) if i == h => Some(Outcome::Use(m)),
+            (Some(_), Some(_), Some(_)) => None,
+            (None, None, None) => Some(Outcome::Keep),
+        };
+        match outcome {
+            Some(outcome) => {
+                outcomes.insert(path, outcome);
+            }
+            None => errors.push(path),
+        }
+    }
+    ensure_no_errors(errors, opts)?;
+    apply(index, outcomes, opts)
+}
+
+/// Three-way merge of `ours` and `theirs` against `base`, resolving trivial cases and leaving
+/// the other paths, deletions included unless `aggressive`, as stages 1/2/3
+pub(crate) fn three_way(
+    index: &mut Index,
+    base: Option<&[u8; 20]>,
+    ours: Option<&[u8; 20]>,
+    theirs: Option<&[u8; 20]>,
+    opts: UnpackOptions,
+) -> anyhow::Result<()> {
+    let base = tree_blobs(base)?;
+    let ours = tree_blobs(ours)?;
+    let theirs = tree_blobs(theirs)?;
+    let paths = all_paths(index, [&base, &ours, &theirs]);
+    let mut outcomes = BTreeMap::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        let o = base.get(&path).copied();
+        let a = ours.get(&path).copied();
+        let b = theirs.get(&path).copied();
+        // Deletions are only trivial when aggressive, unless the path was never in a tree
+        let resolves = |side: Option<Blob>| side.is_some() || o.is_none() || opts.aggressive;
+        let result = if a == b && resolves(a) {
+            a
+        } else if o == a && resolves(b) {
+            b
+        } else if o == b && resolves(a) {
+            a
+        } else {
+            outcomes.insert(path.clone(), Outcome::Conflict([o, a, b]));
+            None
+        };
+        let i = index_blob(index, &path);
+        let conflicted = outcomes.contains_key(&path);
+        if !opts.reset && i != a && (conflicted || i != result) {
+            // The index has changes of its own the merge would lose
+            errors.push(path);
+            continue;
+        }
+        if !conflicted {
+            let outcome = match result {
+                Some(blob) => Outcome::Use(blob),
+                // Deleted on both sides: a file at the path was never ours to remove
+                None if i.is_none() => Outcome::Keep,
+                None => Outcome::Remove,
+            };
+            outcomes.insert(path, outcome);
+        }
+    }
+    ensure_no_errors(errors, opts)?;
+    apply(index, outcomes, opts)
+}
+
+fn all_paths<const N: usize>(
+    index: &Index,
+    trees: [&BTreeMap<String, Blob>; N],
+) -> BTreeSet<String> {
+    let mut paths: BTreeSet<String> = index
+        .entries()
+        .iter()
+        .map(|entry| entry.path.clone())
+        .collect();
+    for tree in trees {
+        paths.extend(tree.keys().cloned());
+    }
+    paths
+}
+
+fn ensure_no_errors(errors: Vec<String>, opts: UnpackOptions) -> anyhow::Result<()> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let list: String = errors.iter().map(|path| format!("\n\t{path}")).collect();
+    if opts.switching {
+        anyhow::bail!(
+            "Your local changes to the following files would be overwritten by checkout:{list}\nPlease commit your changes or stash them before you switch branches.\nAborting"
+        );
+    }
+    if opts.merging {
+        anyhow::bail!(
+            "Your local changes to the following files would be overwritten by merge:{list}\nPlease commit your changes or stash them before you merge.\nAborting"
+        );
+    }
+    anyhow::bail!("Your local changes to the following files would be overwritten by merge:{list}")
+}
+
+/// Applies per-path outcomes to the index and, with `update`, to the working tree
+fn apply(
+    index: &mut Index,
+    outcomes: BTreeMap<String, Outcome>,
+    opts: UnpackOptions,
+) -> anyhow::Result<()> {
+    if !opts.reset && !opts.overwrite_unmerged && index.has_conflicts() {
+        anyhow::bail!("You need to resolve your current index first");
+    }
+
+    if opts.update && !opts.reset {
+        check_worktree(index, &outcomes, opts)?;
+    }
+
+    let mut checkouts = Vec::new();
+    let mut removals = Vec::new();
+    for (path, outcome) in outcomes {
+        let old = index.get(&path, 0).cloned();
+        let had_conflicts = index.stages(&path).iter().any(|entry| entry.stage != 0);
+        match outcome {
+            Outcome::Keep => {
+                if opts.reset && had_conflicts {
+                    index.remove(&path);
+                    if old.is_none() {
+                        removals.push(path);
+                    }
+                }
+            }
+            Outcome::Use((mode, hash)) => {
+                let unchanged = old
+                    .as_ref()
+                    .is_some_and(|old| old.mode == mode && old.hash == hash);
+                if unchanged {
+                    index.add(old.unwrap());
+                    if opts.reset
+                        && opts.update
+                        && !index.matches_worktree(index.get(&path, 0).unwrap())?
+                    {
+                        checkouts.push(path);
+                    }
+                } else {
+                    index.add(IndexEntry::new(path.clone(), mode, hash, 0));
+                    checkouts.push(path);
+                }
+            }
+            Outcome::Remove => {
+                if index.remove(&path) {
+                    removals.push(path);
+                }
+            }
+            Outcome::Conflict(stages) => {
+                index.remove(&path);
+                for (stage, blob) in stages.into_iter().enumerate() {
+                    if let Some((mode, hash)) = blob {
+                        index.add(IndexEntry::new(path.clone(), mode, hash, stage as u16 + 1));
+                    }
+                }
+            }
+        }
+    }
+
+    if opts.update {
+        for path in &removals {
+            crate::worktree::remove_file(path)?;
+        }
+        for path in &checkouts {
+            let entry = index
+                .get_mut(path, 0)
+                .expect("checked out entries are merged");
+            // A reset isn't checked beforehand and takes the place of whatever is in the way
+            if opts.reset {
+                crate::worktree::clear_path(path)?;
+            }
+            crate::worktree::checkout_entry(entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// Refuses to clobber local modifications or untracked files with the result of an unpack
+fn check_worktree(
+    index: &Index,
+    outcomes: &BTreeMap<String, Outcome>,
+    opts: UnpackOptions,
+) -> anyhow::Result<()> {
+    let mut dirty = Vec::new();
+    let mut untracked = Vec::new();
+    let mut untracked_removed = Vec::new();
+    let mut lost_dirs = Vec::new();
+    for (path, outcome) in outcomes {
+        let old = index.get(path, 0);
+        let changes = match outcome {
+            Outcome::Keep | Outcome::Conflict(_) => false,
+            Outcome::Use((mode, hash)) => {
+                old.is_none_or(|old| old.mode != *mode || old.hash != *hash)
+            }
+            Outcome::Remove => true,
+        };
+        if !changes || index.stages(path).iter().any(|entry| entry.stage != 0) {
+            continue;
+        }
+        if matches!(outcome, Outcome::Use(_)) {
+            // An untracked file where the path needs a directory
+            for dir in Path::new(path).ancestors().skip(1) {
+                let dir = dir.to_string_lossy();
+                if !dir.is_empty()
+                    && index.get(&dir, 0).is_none()
+                    && std::fs::symlink_metadata(&*dir).is_ok_and(|m| !m.is_dir())
+                    && !untracked.contains(&dir.to_string())
+                {
+                    untracked.push(dir.to_string());
+                }
+            }
+        }
+        let on_disk = std::fs::symlink_metadata(path);
+        match old {
+            Some(old) if !index.matches_worktree(old)? => dirty.push(path.clone()),
+            Some(_) => {}
+            // A file the index doesn't know is in the way of the path being written or removed
+            None if on_disk.as_ref().is_ok_and(|m| !m.is_dir()) => {
+                if *outcome == Outcome::Remove {
+                    untracked_removed.push(path.clone());
+                } else {
+                    untracked.push(path.clone());
+                }
+            }
+            // A directory in the way goes only if the index knows everything in it
+            None if on_disk.is_ok_and(|m| m.is_dir())
+                && matches!(outcome, Outcome::Use(_))
+                && has_untracked(index, Path::new(path))? =>
+            {
+                lost_dirs.push(path.clone())
+            }
+            None => {}
+        }
+    }
+    if !lost_dirs.is_empty() {
+        let list: String = lost_dirs.iter().map(|path| format!("\n\t{path}")).collect();
+        anyhow::bail!(
+            "Updating the following directories would lose untracked files in them:{list}\n\nAborting"
+        );
+    }
+    if !dirty.is_empty() {
+        let list: String = dirty.iter().map(|path| format!("\n\t{path}")).collect();
+        if opts.switching {
+            anyhow::bail!(
+                "Your local changes to the following files would be overwritten by checkout:{list}\nPlease commit your changes or stash them before you switch branches.\nAborting"
+            );
+        }
+        if opts.merging {
+            anyhow::bail!(
+                "Your local changes to the following files would be overwritten by merge:{list}\nPlease commit your changes or stash them before you merge.\nAborting"
+            );
+        }
+        anyhow::bail!(
+            "Your local changes to the following files would be overwritten:{list}\nPlease commit your changes or stash them."
+        );
+    }
+    ensure_no_untracked(&untracked, "overwritten", opts)?;
+    ensure_no_untracked(&untracked_removed, "removed", opts)
+}
+
+/// Whether a directory holds files the index doesn't track
+fn has_untracked(index: &Index, dir: &Path) -> anyhow::Result<bool> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("Reading directory {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        let untracked = if path.symlink_metadata()?.is_dir() {
+            has_untracked(index, &path)?
+        } else {
+            index.get(&path.to_string_lossy(), 0).is_none()
+        };
+        if untracked {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Refuses to overwrite or remove untracked files, `what` saying which
+fn ensure_no_untracked(paths: &[String], what: &str, opts: UnpackOptions) -> anyhow::Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    let list: String = paths.iter().map(|path| format!("\n\t{path}")).collect();
+    if opts.switching {
+        anyhow::bail!(
+            "The following untracked working tree files would be {what} by checkout:{list}\nPlease move or remove them before you switch branches.\nAborting"
+        );
+    }
+    if opts.merging {
+        anyhow::bail!(
+            "The following untracked working tree files would be {what} by merge:{list}\nPlease move or remove them before you merge.\nAborting"
+        );
+    }
+    anyhow::bail!(
+        "The following untracked working tree files would be {what}:{list}\nPlease move or remove them."
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::{self, tree};
+
+    const UPDATE: UnpackOptions = UnpackOptions {
+        update: true,
+        reset: false,
+        switching: true,
+        merging: false,
+        overwrite_unmerged: false,
+        aggressive: false,
+    };
+
+    /// Checks out `head` into an empty index and working tree
+    fn check_out(head: &[u8; 20]) -> anyhow::Result<Index> {
+        let mut index = Index::default();
+        one_way(&mut index, Some(head), UPDATE)?;
+        Ok(index)
+    }
+
+    #[test]
+    fn refuses_to_replace_a_directory_with_untracked_files() {
+        testing::in_repo(|| {
+            let head = tree(&[("a", "a\n")]);
+            let target = tree(&[("a", "a\n"), ("d", "file\n")]);
+            let mut index = check_out(&head)?;
+            fs::create_dir_all("d/sub")?;
+            fs::write("d/sub/untracked", "precious\n")?;
+
+            let err = two_way(&mut index, Some(&head), Some(&target), UPDATE).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("would lose untracked files in them:\n\td\n"),
+                "{err}"
+            );
+            assert_eq!(fs::read_to_string("d/sub/untracked")?, "precious\n");
+            assert!(index.get("d", 0).is_none());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn refuses_to_replace_an_untracked_file_in_a_leading_path() {
+        testing::in_repo(|| {
+            let head = tree(&[("a", "a\n")]);
+            let target = tree(&[("a", "a\n"), ("d/f", "file\n")]);
+            let mut index = check_out(&head)?;
+            fs::write("d", "precious\n")?;
+
+            let err = two_way(&mut index, Some(&head), Some(&target), UPDATE).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("would be overwritten by checkout:\n\td\n"),
+                "{err}"
+            );
+            assert_eq!(fs::read_to_string("d")?, "precious\n");
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn replaces_a_directory_of_tracked_files() {
+        testing::in_repo(|| {
+            let head = tree(&[("d/f", "file\n"), ("d/sub/g", "g\n")]);
+            let target = tree(&[("d", "now a file\n")]);
+            let mut index = check_out(&head)?;
+
+            two_way(&mut index, Some(&head), Some(&target), UPDATE)?;
+            assert_eq!(fs::read_to_string("d")?, "now a file\n");
+            let paths: Vec<&str> = index
+                .entries()
+                .iter()
+                .map(|entry| entry.path.as_str())
+                .collect();
+            assert_eq!(paths, ["d"]);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn a_reset_clears_whatever_is_in_the_way() {
+        testing::in_repo(|| {
+            let target = tree(&[("d/f", "file\n")]);
+            fs::write("d", "in the way\n")?;
+            let mut index = Index::default();
+            let opts = UnpackOptions {
+                reset: true,
+                ..UPDATE
+            };
+            two_way(&mut index, None, Some(&target), opts)?;
+            assert_eq!(fs::read_to_string("d/f")?, "file\n");
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,172 @@
+use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+use anyhow::Context;
+
+use crate::{
+    index::IndexEntry,
+    object::{
+        read::read_object_of_kind, write::hash_object, ObjectKind, MODE_EXECUTABLE, MODE_GITLINK,
+        MODE_SYMLINK,
+    },
+};
+
+/// Hashes a working tree file as a blob; symlinks are hashed by their target
+pub(crate) fn hash_file(path: &Path) -> anyhow::Result<[u8; 20]> {
+    hash_object(ObjectKind::Blob, &read_file(path)?, false)
+}
+
+/// Reads a working tree file the way it is stored in a blob
+pub(crate) fn read_file(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let metadata =
+        fs::symlink_metadata(path).with_context(|| format!("Stating {}", path.display()))?;
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path)?;
+        Ok(target.into_os_string().into_encoded_bytes())
+    } else {
+        fs::read(path).with_context(|| format!("Reading {}", path.display()))
+    }
+}
+
+/// Writes a blob to the working tree with the permission bits (or symlink) of `mode`,
+/// creating leading directories and replacing a file or empty directory at `path`
+pub(crate) fn write_file(path: &str, mode: u32, hash: &[u8; 20]) -> anyhow::Result<()> {
+    if mode == MODE_GITLINK {
+        // A submodule that is checked out stays as it is
+        if fs::symlink_metadata(path).is_ok_and(|m| m.is_dir()) {
+            return Ok(());
+        }
+        make_room(path)?;
+        fs::create_dir_all(path)?;
+        return Ok(());
+    }
+    let content = read_object_of_kind(&hex::encode(hash), ObjectKind::Blob)?;
+    write_content(path, mode, content)
+}
+
+/// Writes content to the working tree the way [`write_file`] writes a blob
+pub(crate) fn write_content(path: &str, mode: u32, content: Vec<u8>) -> anyhow::Result<()> {
+    make_room(path)?;
+    let target = Path::new(path);
+    if mode == MODE_SYMLINK {
+        let link = String::from_utf8(content).context("Symlink target is not utf-8")?;
+        std::os::unix::fs::symlink(link, target)
+            .with_context(|| format!("Creating symlink {path}"))?;
+    } else {
+        fs::write(target, content).with_context(|| format!("Writing {path}"))?;
+        let permissions = if mode == MODE_EXECUTABLE {
+            0o755
+        } else {
+            0o644
+        };
+        fs::set_permissions(target, fs::Permissions::from_mode(permissions))?;
+    }
+    Ok(())
+}
+
+/// Creates the directories leading to `path` and removes the file or empty directory at it.
+/// Anything else in the way is an error: callers check first that nothing would be lost, or
+/// [`clear_path`] when overwriting is what they are asked to do.
+fn make_room(path: &str) -> anyhow::Result<()> {
+    let target = Path::new(path);
+    if let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) {
+        make_dirs(parent)?;
+    }
+    if let Ok(metadata) = fs::symlink_metadata(target) {
+        if metadata.is_dir() {
+            remove_empty_dirs(target).with_context(|| {
+                format!("Unable to replace directory {path}, which isn't empty")
+            })?;
+        } else {
+            fs::remove_file(target).with_context(|| format!("Removing {path}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates the directories leading to a file, failing where a file is in the way
+fn make_dirs(dir: &Path) -> anyhow::Result<()> {
+    let mut current = std::path::PathBuf::new();
+    for component in dir.components() {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.is_dir() => continue,
+            Ok(_) => anyhow::bail!(
+                "Unable to create directory {}: a file is in the way",
+                current.display()
+            ),
+            Err(_) => {}
+        }
+        fs::create_dir(&current)
+            .with_context(|| format!("Creating directory {}", current.display()))?;
+    }
+    Ok(())
+}
+
+/// Removes a directory holding nothing but directories
+fn remove_empty_dirs(dir: &Path) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_empty_dirs(&entry.path())?;
+        }
+    }
+    fs::remove_dir(dir)
+}
+
+/// Removes whatever is at `path` or in the way of the directories leading to it, untracked
+/// files and whole directories included, for checkouts that are forced
+pub(crate) fn clear_path(path: &str) -> anyhow::Result<()> {
+    let mut current = std::path::PathBuf::new();
+    for component in Path::new(path).components() {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.is_dir() => {
+                if current == Path::new(path) {
+                    fs::remove_dir_all(&current)
+                        .with_context(|| format!("Removing directory {path}"))?;
+                }
+            }
+            Ok(_) => {
+                fs::remove_file(&current)
+                    .with_context(|| format!("Removing {}", current.display()))?;
+                return Ok(());
+            }
+            Err(_) => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+/// Checks out an index entry and refreshes its stat information
+pub(crate) fn checkout_entry(entry: &mut IndexEntry) -> anyhow::Result<()> {
+    write_file(&entry.path, entry.mode, &entry.hash)?;
+    let metadata = fs::symlink_metadata(&entry.path)?;
+    entry.update_stat(&metadata);
+    Ok(())
+}
+
+/// Removes a file if there is one, leaving its directory even when that empties it
+pub(crate) fn remove_if_exists(path: &str) -> anyhow::Result<()> {
+    if Path::new(path).exists() {
+        fs::remove_file(path).with_context(|| format!("Removing {path}"))?;
+    }
+    Ok(())
+}
+
+/// Removes a file and any directories left empty by its removal
+pub(crate) fn remove_file(path: &str) -> anyhow::Result<()> {
+    let target = Path::new(path);
+    match fs::symlink_metadata(target) {
+        Ok(metadata) if metadata.is_dir() => {}
+        Ok(_) => fs::remove_file(target).with_context(|| format!("Removing {path}"))?,
+        Err(_) => return Ok(()),
+    }
+    let mut parent = target.parent();
+    while let Some(dir) = parent.filter(|p| !p.as_os_str().is_empty()) {
+        if fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+    Ok(())
+}